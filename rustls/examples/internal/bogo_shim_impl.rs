@@ -576,7 +576,8 @@ fn make_server_cfg(opts: &Options) -> Arc<ServerConfig> {
     cfg.session_storage = ServerCacheWithResumptionDelay::new(opts.resumption_delay);
     cfg.max_fragment_size = opts.max_fragment;
     cfg.send_tls13_tickets = 1;
-    cfg.require_ems = opts.require_ems;
+    cfg.dangerous()
+        .set_require_ems(opts.require_ems);
 
     if opts.use_signing_scheme > 0 {
         let scheme = lookup_scheme(opts.use_signing_scheme);
@@ -765,7 +766,8 @@ fn make_client_cfg(opts: &Options) -> Arc<ClientConfig> {
     cfg.resumption = Resumption::store(ClientCacheWithoutKxHints::new(opts.resumption_delay));
     cfg.enable_sni = opts.use_sni;
     cfg.max_fragment_size = opts.max_fragment;
-    cfg.require_ems = opts.require_ems;
+    cfg.dangerous()
+        .set_require_ems(opts.require_ems);
 
     if !opts.protocols.is_empty() {
         cfg.alpn_protocols = opts
@@ -0,0 +1,145 @@
+//! Application-Layer Protocol Negotiation (ALPN) support
+//!
+//! This module has identifiers for commonly-used protocols, to put in
+//! [`ClientConfig::alpn_protocols`][cc_ap] or [`ServerConfig::alpn_protocols`][sc_ap],
+//! and [`validate()`] to check a list of protocols before use.
+//!
+//! After the handshake, [`CommonState::application_protocol()`][cs_ap] returns the
+//! agreed protocol as an [`ApplicationProtocol`].
+//!
+//! [cc_ap]: crate::ClientConfig::alpn_protocols
+//! [sc_ap]: crate::ServerConfig::alpn_protocols
+//! [cs_ap]: crate::CommonState::application_protocol
+
+use alloc::vec::Vec;
+
+use crate::error::{AlpnProtocolError, Error};
+
+/// HTTP/1.1, from [RFC 7230](https://www.rfc-editor.org/rfc/rfc7230).
+pub const HTTP_1_1: &[u8] = b"http/1.1";
+
+/// HTTP/2 over TLS, from [RFC 9113](https://www.rfc-editor.org/rfc/rfc9113).
+pub const H2: &[u8] = b"h2";
+
+/// HTTP/3, from [RFC 9114](https://www.rfc-editor.org/rfc/rfc9114).
+pub const H3: &[u8] = b"h3";
+
+/// The ACME TLS-ALPN-01 challenge protocol, from [RFC 8737](https://www.rfc-editor.org/rfc/rfc8737).
+pub const ACME_TLS_1: &[u8] = b"acme-tls/1";
+
+/// An ALPN protocol, recognising the ones with constants in this module.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApplicationProtocol<'a> {
+    /// [`HTTP_1_1`].
+    Http11,
+    /// [`H2`].
+    Http2,
+    /// [`H3`].
+    Http3,
+    /// [`ACME_TLS_1`].
+    AcmeTls1,
+    /// Any other protocol.
+    Other(&'a [u8]),
+}
+
+impl<'a> ApplicationProtocol<'a> {
+    /// Recognise a protocol identifier.
+    pub fn from_bytes(protocol: &'a [u8]) -> Self {
+        match protocol {
+            HTTP_1_1 => Self::Http11,
+            H2 => Self::Http2,
+            H3 => Self::Http3,
+            ACME_TLS_1 => Self::AcmeTls1,
+            other => Self::Other(other),
+        }
+    }
+
+    /// The protocol identifier, as sent in the ALPN extension.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        match self {
+            Self::Http11 => HTTP_1_1,
+            Self::Http2 => H2,
+            Self::Http3 => H3,
+            Self::AcmeTls1 => ACME_TLS_1,
+            Self::Other(other) => other,
+        }
+    }
+}
+
+/// Check that `protocols` can be sent in an ALPN extension.
+///
+/// Each protocol must be between 1 and 255 bytes long, none may appear twice,
+/// and the encoded list must fit in 65535 bytes.
+pub fn validate(protocols: &[Vec<u8>]) -> Result<(), Error> {
+    let mut encoded_len = 0usize;
+    for (i, protocol) in protocols.iter().enumerate() {
+        match protocol.len() {
+            0 => return Err(AlpnProtocolError::Empty.into()),
+            1..=MAX_PROTOCOL_LEN => {}
+            _ => return Err(AlpnProtocolError::TooLong.into()),
+        }
+        if protocols[..i].contains(protocol) {
+            return Err(AlpnProtocolError::Duplicate.into());
+        }
+        encoded_len += 1 + protocol.len();
+    }
+
+    match encoded_len > MAX_LIST_LEN {
+        true => Err(AlpnProtocolError::ListTooLong.into()),
+        false => Ok(()),
+    }
+}
+
+const MAX_PROTOCOL_LEN: usize = 0xff;
+const MAX_LIST_LEN: usize = 0xffff;
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn recognises_known_protocols() {
+        for protocol in [HTTP_1_1, H2, H3, ACME_TLS_1, b"spdy/3"] {
+            assert_eq!(
+                ApplicationProtocol::from_bytes(protocol).as_bytes(),
+                protocol
+            );
+        }
+        assert_eq!(
+            ApplicationProtocol::from_bytes(H2),
+            ApplicationProtocol::Http2
+        );
+        assert_eq!(
+            ApplicationProtocol::from_bytes(b"h2c"),
+            ApplicationProtocol::Other(b"h2c")
+        );
+    }
+
+    #[test]
+    fn validates_protocol_lists() {
+        assert_eq!(validate(&[]), Ok(()));
+        assert_eq!(validate(&[H2.to_vec(), HTTP_1_1.to_vec()]), Ok(()));
+        assert_eq!(validate(&[vec![b'x'; 255]]), Ok(()));
+
+        assert_eq!(
+            validate(&[H2.to_vec(), Vec::new()]),
+            Err(AlpnProtocolError::Empty.into())
+        );
+        assert_eq!(
+            validate(&[vec![b'x'; 256]]),
+            Err(AlpnProtocolError::TooLong.into())
+        );
+        assert_eq!(
+            validate(&[H2.to_vec(), HTTP_1_1.to_vec(), H2.to_vec()]),
+            Err(AlpnProtocolError::Duplicate.into())
+        );
+
+        let many = (0..=u8::MAX)
+            .map(|i| vec![i; 255])
+            .collect::<Vec<_>>();
+        assert_eq!(validate(&many), Err(AlpnProtocolError::ListTooLong.into()));
+    }
+}
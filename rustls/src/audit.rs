@@ -0,0 +1,94 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use crate::common_state::{HandshakeKind, Side};
+use crate::crypto::hash::HashAlgorithm;
+use crate::enums::{AlertDescription, CipherSuite, ProtocolVersion};
+use crate::msgs::enums::NamedGroup;
+
+/// Receives a [`ConnectionAudit`] for each connection, when it closes.
+///
+/// A connection is audited once, at the first of the events listed by [`CloseReason`].
+/// A connection that is dropped before any of them is not audited; to audit it,
+/// close it with [`CommonState::send_close_notify()`] first.
+///
+/// Install one with [`ClientConfig::audit_sink`] or [`ServerConfig::audit_sink`].
+/// This is called synchronously from within rustls, so should return promptly: for
+/// example, by queueing the record to be written elsewhere.
+///
+/// [`CommonState::send_close_notify()`]: crate::CommonState::send_close_notify
+///
+/// [`ClientConfig::audit_sink`]: crate::ClientConfig::audit_sink
+/// [`ServerConfig::audit_sink`]: crate::ServerConfig::audit_sink
+pub trait ReceivesConnectionAudits: Debug + Send + Sync {
+    /// Called once for each connection, with a summary of it.
+    fn connection_audit(&self, audit: &ConnectionAudit);
+}
+
+/// A summary of one connection, for compliance logging.
+///
+/// This is delivered to a [`ReceivesConnectionAudits`].  It contains no secret
+/// key material.  Every field is plain data (integers, enums of protocol code
+/// points, byte strings and a string) so that it can be serialized as-is.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionAudit {
+    /// Which side of the connection this is.
+    pub side: Side,
+    /// The negotiated protocol version, if any.
+    pub protocol_version: Option<ProtocolVersion>,
+    /// The negotiated cipher suite, if any.
+    pub cipher_suite: Option<CipherSuite>,
+    /// The negotiated key exchange group, if any.
+    ///
+    /// This is `None` for TLS1.2 connections that were resumed.
+    pub key_exchange_group: Option<NamedGroup>,
+    /// The kind of handshake done, if it completed far enough to tell.
+    pub handshake_kind: Option<HandshakeKind>,
+    /// The negotiated ALPN protocol, if any.
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// The hash of the peer's DER-encoded end-entity certificate, if it presented one.
+    ///
+    /// This is computed with the hash function of the negotiated cipher suite,
+    /// named by [`ConnectionAudit::peer_certificate_digest_algorithm`].
+    pub peer_certificate_digest: Option<Vec<u8>>,
+    /// The hash function used for [`ConnectionAudit::peer_certificate_digest`].
+    pub peer_certificate_digest_algorithm: Option<HashAlgorithm>,
+    /// The number of bytes of TLS records sent when the connection closed, including
+    /// record headers.
+    pub bytes_sent: u64,
+    /// The number of bytes of TLS records received when the connection closed, including
+    /// record headers.
+    pub bytes_received: u64,
+    /// How the connection ended.
+    pub close_reason: CloseReason,
+    /// A description of the error that ended the connection, if it failed.
+    ///
+    /// This is the `Display` form of the [`Error`], for people to read: its text is not stable.
+    ///
+    /// [`Error`]: crate::Error
+    pub error: Option<String>,
+}
+
+/// How a connection ended, as reported in a [`ConnectionAudit`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The peer sent a `close_notify` alert.
+    CloseNotifyReceived,
+    /// We sent a `close_notify` alert.
+    CloseNotifySent,
+    /// The transport reached EOF before the peer sent a `close_notify` alert.
+    UnexpectedEof,
+    /// We sent this fatal alert because the connection failed.
+    FatalAlertSent(AlertDescription),
+    /// The peer sent this fatal alert.
+    FatalAlertReceived(AlertDescription),
+    /// The connection failed, and no alert was sent because the server rejects
+    /// clients silently.
+    RejectedSilently,
+    /// The connection's secrets were extracted with `dangerous_extract_secrets()`,
+    /// so rustls is no longer part of it.
+    SecretsExtracted,
+}
@@ -7,10 +7,12 @@ use pki_types::{CertificateDer, PrivateKeyDer};
 use super::client_conn::Resumption;
 use crate::builder::{ConfigBuilder, WantsVerifier};
 use crate::client::{handy, ClientConfig, EchMode, ResolvesClientCert};
+use crate::common_state::PostHandshakeLimits;
 use crate::crypto::CryptoProvider;
 use crate::error::Error;
 use crate::key_log::NoKeyLog;
 use crate::msgs::handshake::CertificateChain;
+use crate::record_layer::{KeyLimits, RecordStrictness};
 use crate::time_provider::TimeProvider;
 use crate::versions::TLS13;
 use crate::webpki::{self, WebPkiServerVerifier};
@@ -54,13 +56,9 @@ impl ConfigBuilder<ClientConfig, WantsVerifier> {
         self,
         root_store: impl Into<Arc<webpki::RootCertStore>>,
     ) -> ConfigBuilder<ClientConfig, WantsClientCert> {
-        let algorithms = self
-            .state
-            .provider
-            .signature_verification_algorithms;
-        self.with_webpki_verifier(
-            WebPkiServerVerifier::new_without_revocation(root_store, algorithms).into(),
-        )
+        let verifier =
+            WebPkiServerVerifier::new_without_revocation(root_store, &self.state.provider);
+        self.with_webpki_verifier(verifier.into())
     }
 
     /// Choose how to verify server certificates using a webpki verifier.
@@ -176,21 +174,41 @@ impl ConfigBuilder<ClientConfig, WantsClientCert> {
         ClientConfig {
             provider: self.state.provider,
             alpn_protocols: Vec::new(),
+            trust_anchor_ids: Vec::new(),
             resumption: Resumption::default(),
             max_fragment_size: None,
+            max_handshake_size: None,
+            max_decompressed_cert_size: None,
+            record_strictness: RecordStrictness::default(),
+            key_limits: KeyLimits::default(),
+            post_handshake_limits: PostHandshakeLimits::default(),
+            max_fragment_length: None,
             client_auth_cert_resolver,
             versions: self.state.versions,
             enable_sni: true,
+            enable_grease: false,
+            hello_profile: None,
             verifier: self.state.verifier,
+            handshake_mutator: None,
             key_log: Arc::new(NoKeyLog {}),
+            record_observer: None,
+            alert_policy: None,
+            diagnostics: None,
+            audit_sink: None,
             enable_secret_extraction: false,
             enable_early_data: false,
             #[cfg(feature = "tls12")]
-            require_ems: cfg!(feature = "fips"),
+            require_ems: true,
+            #[cfg(feature = "tls12")]
+            strict_downgrade_protection: false,
             time_provider: self.state.time_provider,
+            handshake_timeout: None,
+            respond_to_close_notify: false,
             cert_compressors: compress::default_cert_compressors().to_vec(),
             cert_compression_cache: Arc::new(compress::CompressionCache::default()),
+            cert_decompression_cache: Arc::new(compress::DecompressionCache::default()),
             cert_decompressors: compress::default_cert_decompressors().to_vec(),
+            ech_resolver: None,
             ech_mode: self.state.client_ech_mode,
         }
     }
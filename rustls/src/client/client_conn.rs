@@ -1,33 +1,49 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::any::Any;
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
+use core::time::Duration;
 use core::{fmt, mem};
+#[cfg(feature = "std")]
+use std::collections::HashSet;
 
 use pki_types::{ServerName, UnixTime};
 
 use super::handy::NoClientSessionStorage;
 use super::hs;
+use crate::audit::ReceivesConnectionAudits;
 use crate::builder::ConfigBuilder;
-use crate::client::{EchMode, EchStatus};
-use crate::common_state::{CommonState, Protocol, Side};
+use crate::client::{EchMode, EchStatus, ResolvesEchConfig, ShapesClientHello};
+use crate::common_state::{
+    ChoosesAlerts, CommonState, MutatesHandshakeMessages, PostHandshakeLimits, Protocol, Side,
+};
 use crate::conn::{ConnectionCore, UnbufferedConnectionCommon};
 use crate::crypto::{CryptoProvider, SupportedKxGroup};
-use crate::enums::{CipherSuite, ProtocolVersion, SignatureScheme};
+use crate::diagnostics::ReceivesDiagnostics;
+use crate::enums::{
+    CertificateCompressionAlgorithm, CipherSuite, MaxFragmentLength, ProtocolVersion,
+    SignatureScheme,
+};
 use crate::error::Error;
+#[cfg(feature = "std")]
+use crate::lock::Mutex;
 #[cfg(feature = "logging")]
 use crate::log::trace;
+use crate::msgs::codec::Codec;
+use crate::msgs::deframer::MessageDeframer;
 use crate::msgs::enums::NamedGroup;
-use crate::msgs::handshake::ClientExtension;
+use crate::msgs::handshake::{CertReqExtension, ClientExtension};
 use crate::msgs::persist;
-use crate::suites::SupportedCipherSuite;
+use crate::record_layer::{KeyLimits, RecordStrictness};
+use crate::suites::{ExtractedSecrets, SupportedCipherSuite};
 #[cfg(feature = "std")]
 use crate::time_provider::DefaultTimeProvider;
 use crate::time_provider::TimeProvider;
 use crate::unbuffered::{EncryptError, TransmitTlsData};
 #[cfg(feature = "std")]
 use crate::WantsVerifier;
-use crate::{compress, sign, verify, versions, KeyLog, WantsVersions};
+use crate::{alpn, compress, sign, verify, versions, KeyLog, ObservesRecords, WantsVersions};
 #[cfg(doc)]
 use crate::{crypto, DistinguishedName};
 
@@ -121,10 +137,107 @@ pub trait ResolvesClientCert: fmt::Debug + Send + Sync {
         sigschemes: &[SignatureScheme],
     ) -> Option<Arc<sign::CertifiedKey>>;
 
+    /// Resolve a client certificate chain/private key, given the whole of the
+    /// server's `CertificateRequest`.
+    ///
+    /// This gives access to details not passed to [`ResolvesClientCert::resolve()`],
+    /// such as the TLS1.3 `certificate_request_context` and extensions.  The default
+    /// implementation calls [`ResolvesClientCert::resolve()`].
+    fn resolve_request(&self, request: &CertificateRequest<'_>) -> Option<Arc<sign::CertifiedKey>> {
+        self.resolve(request.root_hint_subjects(), request.signature_schemes())
+    }
+
     /// Return true if any certificates at all are available.
     fn has_certs(&self) -> bool;
 }
 
+/// A `CertificateRequest` received from the server.
+pub struct CertificateRequest<'a> {
+    version: ProtocolVersion,
+    context: Option<&'a [u8]>,
+    root_hint_subjects: &'a [&'a [u8]],
+    signature_schemes: &'a [SignatureScheme],
+    extensions: &'a [CertReqExtension],
+    user_data: Option<&'a (dyn Any + Send)>,
+}
+
+impl<'a> CertificateRequest<'a> {
+    pub(super) fn new(
+        version: ProtocolVersion,
+        context: Option<&'a [u8]>,
+        root_hint_subjects: &'a [&'a [u8]],
+        signature_schemes: &'a [SignatureScheme],
+        extensions: &'a [CertReqExtension],
+        user_data: Option<&'a (dyn Any + Send)>,
+    ) -> Self {
+        Self {
+            version,
+            context,
+            root_hint_subjects,
+            signature_schemes,
+            extensions,
+            user_data,
+        }
+    }
+
+    /// The version of TLS in use.
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// The TLS1.3 `certificate_request_context`.
+    ///
+    /// This is echoed back to the server in the client's `Certificate` message.
+    /// It is always empty for requests made during the handshake, and is `None`
+    /// for TLS1.2.
+    pub fn context(&self) -> Option<&'a [u8]> {
+        self.context
+    }
+
+    /// The certificate authority subject distinguished names sent by the server.
+    ///
+    /// See [`ResolvesClientCert::resolve()`] for how to use these.
+    pub fn root_hint_subjects(&self) -> &'a [&'a [u8]] {
+        self.root_hint_subjects
+    }
+
+    /// The signature schemes that the server supports, and that can be used
+    /// in the TLS version in use.
+    pub fn signature_schemes(&self) -> &'a [SignatureScheme] {
+        self.signature_schemes
+    }
+
+    /// The TLS1.3 extensions in the request, as their type and encoded body.
+    ///
+    /// This includes the extensions that are also available from other methods.
+    /// It is empty for TLS1.2, which has no `CertificateRequest` extensions.
+    pub fn extensions(&self) -> impl Iterator<Item = (u16, Vec<u8>)> + 'a {
+        self.extensions.iter().map(|ext| {
+            let mut encoded = ext.get_encoding();
+            // skip the type and length
+            (u16::from(ext.ext_type()), encoded.split_off(4))
+        })
+    }
+
+    /// The application data attached to the connection with [`CommonState::set_user_data()`].
+    ///
+    /// [`CommonState::set_user_data()`]: crate::CommonState::set_user_data
+    pub fn user_data(&self) -> Option<&'a (dyn Any + Send)> {
+        self.user_data
+    }
+}
+
+impl fmt::Debug for CertificateRequest<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertificateRequest")
+            .field("version", &self.version)
+            .field("context", &self.context)
+            .field("signature_schemes", &self.signature_schemes)
+            .field("extensions", &self.extensions)
+            .finish_non_exhaustive()
+    }
+}
+
 /// Common configuration for (typically) all connections made by a program.
 ///
 /// Making one of these is cheap, though one of the inputs may be expensive: gathering trust roots
@@ -144,13 +257,31 @@ pub trait ResolvesClientCert: fmt::Debug + Send + Sync {
 /// # Defaults
 ///
 /// * [`ClientConfig::max_fragment_size`]: the default is `None` (meaning 16kB).
+/// * [`ClientConfig::max_handshake_size`]: the default is `None` (meaning 64kB).
+/// * [`ClientConfig::max_decompressed_cert_size`]: the default is `None` (meaning the
+///   same as `max_handshake_size`).
+/// * [`ClientConfig::record_strictness`]: the default follows the TLS specifications.
+/// * [`ClientConfig::key_limits`]: the default is the cipher suite's limit, and updating keys at it.
+/// * [`ClientConfig::post_handshake_limits`]: 32 key updates, any number of tickets and one
+///   renegotiation request.
+/// * [`ClientConfig::max_fragment_length`]: the default is `None` -- the server is not asked
+///   to limit its record size.
 /// * [`ClientConfig::resumption`]: supports resumption with up to 256 server names, using session
 ///    ids or tickets, with a max of eight tickets per server.
 /// * [`ClientConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
+/// * [`ClientConfig::trust_anchor_ids`]: the default is empty -- no trust anchors are advertised.
 /// * [`ClientConfig::key_log`]: key material is not logged.
+/// * [`ClientConfig::record_observer`]: the default is `None` -- records are not observed.
+/// * [`ClientConfig::alert_policy`]: the default is `None` -- rustls chooses which alert to send.
+/// * [`ClientConfig::diagnostics`]: the default is `None` -- diagnostics are only logged.
+/// * [`ClientConfig::audit_sink`]: the default is `None` -- connections are not audited.
 /// * [`ClientConfig::cert_decompressors`]: depends on the crate features, see [`compress::default_cert_decompressors()`].
 /// * [`ClientConfig::cert_compressors`]: depends on the crate features, see [`compress::default_cert_compressors()`].
 /// * [`ClientConfig::cert_compression_cache`]: caches the most recently used 4 compressions
+/// * [`ClientConfig::cert_decompression_cache`]: caches the most recently used 4 decompressions
+/// * [`ClientConfig::handshake_timeout`]: the default is `None` -- handshakes are not timed out.
+/// * [`ClientConfig::enable_grease`]: the default is false -- GREASE values are not sent.
+/// * [`ClientConfig::hello_profile`]: the default is `None` -- the `ClientHello` is not shaped.
 ///
 /// [`RootCertStore`]: crate::RootCertStore
 #[derive(Clone, Debug)]
@@ -159,6 +290,20 @@ pub struct ClientConfig {
     /// If empty, no ALPN extension is sent.
     pub alpn_protocols: Vec<Vec<u8>>,
 
+    /// Identifiers of the trust anchors we accept, in order of preference.
+    ///
+    /// These are sent in the `trust_anchors` extension described by
+    /// [draft-ietf-tls-trust-anchor-ids], so that a server holding several
+    /// certificate chains (for example, one issued under a classical PKI and one
+    /// under a post-quantum PKI) can choose one we will accept.  Each identifier
+    /// is the binary form of a relative OID, as assigned by the trust anchor's
+    /// operator.
+    ///
+    /// If empty, no extension is sent.
+    ///
+    /// [draft-ietf-tls-trust-anchor-ids]: https://datatracker.ietf.org/doc/draft-ietf-tls-trust-anchor-ids/
+    pub trust_anchor_ids: Vec<Vec<u8>>,
+
     /// How and when the client can resume a previous session.
     pub resumption: Resumption,
 
@@ -175,6 +320,60 @@ pub struct ClientConfig {
     /// [ClientConnection::new]: crate::client::ClientConnection::new
     pub max_fragment_size: Option<usize>,
 
+    /// The maximum size of a handshake message we will accept from the peer.
+    /// A value of None is equivalent to 64 kB.
+    ///
+    /// TLS allows handshake messages of up to 16 MB, which must be buffered
+    /// whole, so this limits the memory a peer can make us use.  It may need
+    /// raising if peers send large certificate chains, for example with
+    /// post-quantum signatures.
+    ///
+    /// rustls enforces a minimum of 16 kB for this field.
+    /// Out of range values are reported as errors from [ClientConnection::new].
+    ///
+    /// [ClientConnection::new]: crate::client::ClientConnection::new
+    pub max_handshake_size: Option<usize>,
+
+    /// The largest decompressed size we will accept for the server's certificate
+    /// chain, as declared in its `CompressedCertificate` message.
+    /// A value of None is equivalent to [`ClientConfig::max_handshake_size`].
+    ///
+    /// Larger declared sizes are rejected before anything is decompressed.
+    /// This only applies to TLS1.3 connections using certificate compression.
+    pub max_decompressed_cert_size: Option<usize>,
+
+    /// How strictly the framing of records received from the server is checked.
+    ///
+    /// The default follows the TLS specifications.  See [`RecordStrictness`].
+    pub record_strictness: RecordStrictness,
+
+    /// Limits on how many records are encrypted with one key.
+    ///
+    /// The default is the cipher suite's limit, after which TLS1.3 keys are updated.
+    /// See [`KeyLimits`].
+    pub key_limits: KeyLimits,
+
+    /// Limits on the messages the server may send after the handshake.
+    ///
+    /// See [`PostHandshakeLimits`] for the defaults.
+    pub post_handshake_limits: PostHandshakeLimits,
+
+    /// Whether to ask the server to limit the size of the records it sends,
+    /// using the [RFC6066] `max_fragment_length` extension.
+    ///
+    /// This is intended for constrained clients that cannot buffer a full 16kB
+    /// record.  If the server agrees, both peers limit the plaintext of each record
+    /// they send to the requested length.  Servers are free to ignore the request.
+    ///
+    /// This is never sent for QUIC connections.
+    ///
+    /// The default is `None`: the extension is not sent.
+    /// Unknown values are reported as errors from [ClientConnection::new].
+    ///
+    /// [RFC6066]: https://www.rfc-editor.org/rfc/rfc6066.html#section-4
+    /// [ClientConnection::new]: crate::client::ClientConnection::new
+    pub max_fragment_length: Option<MaxFragmentLength>,
+
     /// How to decide what client auth certificate/keys to use.
     pub client_auth_cert_resolver: Arc<dyn ResolvesClientCert>,
 
@@ -184,10 +383,63 @@ pub struct ClientConfig {
     /// The default is true.
     pub enable_sni: bool,
 
+    /// Whether to send [GREASE] values in the `ClientHello`.
+    ///
+    /// This adds a reserved cipher suite, protocol version, key exchange group and two
+    /// extensions, chosen randomly for each connection.  Well-behaved servers must ignore
+    /// these; sending them helps keep servers tolerant of values they don't understand, and
+    /// makes rustls clients harder to tell apart from other implementations that do the same.
+    ///
+    /// The order of `ClientHello` extensions is randomized for each connection whether
+    /// or not this is enabled.
+    ///
+    /// The default is false.
+    ///
+    /// [GREASE]: https://www.rfc-editor.org/rfc/rfc8701
+    pub enable_grease: bool,
+
+    /// Shapes the `ClientHello`, for example to resemble that of another client.
+    /// See [`ShapesClientHello`].
+    ///
+    /// The default is `None`: the `ClientHello` is not shaped.
+    pub hello_profile: Option<Arc<dyn ShapesClientHello>>,
+
     /// How to output key material for debugging.  The default
     /// does nothing.
     pub key_log: Arc<dyn KeyLog>,
 
+    /// Receives metadata about each TLS record this connection sends or receives,
+    /// for debugging and accounting.  See [`ObservesRecords`].
+    ///
+    /// The default is `None`: records are not observed.
+    ///
+    /// [`ObservesRecords`]: crate::ObservesRecords
+    pub record_observer: Option<Arc<dyn ObservesRecords>>,
+
+    /// Chooses which alert is sent when a connection fails.  See [`ChoosesAlerts`].
+    ///
+    /// The default is `None`: rustls chooses.
+    ///
+    /// [`ChoosesAlerts`]: crate::ChoosesAlerts
+    pub alert_policy: Option<Arc<dyn ChoosesAlerts>>,
+
+    /// Receives diagnostics about connections.  See [`ReceivesDiagnostics`].
+    ///
+    /// This works whether or not the `logging` feature is enabled.
+    ///
+    /// The default is `None`: diagnostics are only logged.
+    ///
+    /// [`ReceivesDiagnostics`]: crate::ReceivesDiagnostics
+    pub diagnostics: Option<Arc<dyn ReceivesDiagnostics>>,
+
+    /// Receives a summary of each connection when it closes.
+    /// See [`ReceivesConnectionAudits`].
+    ///
+    /// The default is `None`: connections are not audited.
+    ///
+    /// [`ReceivesConnectionAudits`]: crate::ReceivesConnectionAudits
+    pub audit_sink: Option<Arc<dyn ReceivesConnectionAudits>>,
+
     /// Allows traffic secrets to be extracted after the handshake,
     /// e.g. for kTLS setup.
     pub enable_secret_extraction: bool,
@@ -198,24 +450,66 @@ pub struct ClientConfig {
     /// The default is false.
     pub enable_early_data: bool,
 
-    /// If set to `true`, requires the server to support the extended
-    /// master secret extraction method defined in [RFC 7627].
+    /// Whether to require the server to support the extended master secret
+    /// extraction method defined in RFC 7627.
+    ///
+    /// See [`DangerousClientConfig::set_require_ems`].
+    ///
+    /// [`DangerousClientConfig::set_require_ems`]: danger::DangerousClientConfig::set_require_ems
+    #[cfg(feature = "tls12")]
+    pub(super) require_ems: bool,
+
+    /// If set to `true`, treat any TLS1.3 downgrade protection sentinel in a TLS1.2
+    /// `ServerHello.random` as fatal, even when this client did not offer TLS1.3.
     ///
-    /// The default is `true` if the `fips` crate feature is enabled,
-    /// `false` otherwise.
+    /// [RFC 8446 section 4.1.3] requires clients that offered TLS1.3 to abort the
+    /// handshake if a server that negotiated TLS1.2 includes a sentinel; rustls
+    /// always does this.  A client that is configured for TLS1.2 only cannot tell
+    /// whether the sentinel indicates an attack or just a server that supports
+    /// TLS1.3, so by default it carries on and reports the sentinel via
+    /// [`ClientConnection::downgrade_sentinel()`].  Strict clients may prefer to
+    /// refuse such servers.
     ///
-    /// It must be set to `true` to meet FIPS requirement mentioned in section
-    /// **D.Q Transition of the TLS 1.2 KDF to Support the Extended Master
-    /// Secret** from [FIPS 140-3 IG.pdf].
+    /// The default is `false`.
     ///
-    /// [RFC 7627]: https://datatracker.ietf.org/doc/html/rfc7627
-    /// [FIPS 140-3 IG.pdf]: https://csrc.nist.gov/csrc/media/Projects/cryptographic-module-validation-program/documents/fips%20140-3/FIPS%20140-3%20IG.pdf
+    /// [RFC 8446 section 4.1.3]: https://www.rfc-editor.org/rfc/rfc8446#section-4.1.3
+    /// [`ClientConnection::downgrade_sentinel()`]: crate::client::ClientConnection::downgrade_sentinel
     #[cfg(feature = "tls12")]
-    pub require_ems: bool,
+    pub strict_downgrade_protection: bool,
 
     /// Provides the current system time
     pub time_provider: Arc<dyn TimeProvider>,
 
+    /// How long the handshake may take before it is abandoned.
+    ///
+    /// This is measured with [`ClientConfig::time_provider`] from when the connection is
+    /// created.  Once exceeded, processing of received data fails with
+    /// [`Error::HandshakeTimedOut`]; this is checked only when received data is
+    /// processed, so a peer that sends nothing must be handled by the application.
+    ///
+    /// The default is `None`: handshakes may take any amount of time.
+    ///
+    /// [`Error::HandshakeTimedOut`]: crate::Error::HandshakeTimedOut
+    pub handshake_timeout: Option<Duration>,
+
+    /// Whether to reply to a close_notify alert from the peer with our own.
+    ///
+    /// If `true`, receiving close_notify shuts down both directions of the
+    /// connection: a close_notify is queued in response, and further writes fail.
+    /// TLS1.2 ([RFC 5246 section 7.2.1]) requires this behaviour.
+    ///
+    /// If `false`, the connection is half-closed: the application may continue
+    /// to write data, and is responsible for eventually calling
+    /// [`CommonState::send_close_notify()`].  This is permitted by TLS1.3
+    /// ([RFC 8446 section 6.1]) and is needed by proxies.
+    ///
+    /// The default is `false`.
+    ///
+    /// [RFC 5246 section 7.2.1]: https://www.rfc-editor.org/rfc/rfc5246#section-7.2.1
+    /// [RFC 8446 section 6.1]: https://www.rfc-editor.org/rfc/rfc8446#section-6.1
+    /// [`CommonState::send_close_notify()`]: crate::CommonState::send_close_notify
+    pub respond_to_close_notify: bool,
+
     /// Source of randomness and other crypto.
     pub(super) provider: Arc<CryptoProvider>,
 
@@ -226,6 +520,11 @@ pub struct ClientConfig {
     /// How to verify the server certificate chain.
     pub(super) verifier: Arc<dyn verify::ServerCertVerifier>,
 
+    /// Alters outgoing handshake messages; see [`DangerousClientConfig::set_handshake_mutator`].
+    ///
+    /// [`DangerousClientConfig::set_handshake_mutator`]: danger::DangerousClientConfig::set_handshake_mutator
+    pub(crate) handshake_mutator: Option<Arc<dyn MutatesHandshakeMessages>>,
+
     /// How to decompress the server's certificate chain.
     ///
     /// If this is non-empty, the [RFC8779] certificate compression
@@ -256,6 +555,18 @@ pub struct ClientConfig {
     /// a cache that does no caching.
     pub cert_compression_cache: Arc<compress::CompressionCache>,
 
+    /// Caching for decompressed server certificates.
+    ///
+    /// This is optional: [`compress::DecompressionCache::Disabled`] gives
+    /// a cache that does no caching.
+    pub cert_decompression_cache: Arc<compress::DecompressionCache>,
+
+    /// Supplies Encrypted Client Hello (ECH) configurations for each connection.
+    ///
+    /// This is ignored if an [`EchMode`] was given with
+    /// [`ConfigBuilder<ClientConfig, WantsVersions>::with_ech()`].  The default is `None`.
+    pub ech_resolver: Option<Arc<dyn ResolvesEchConfig>>,
+
     /// How to offer Encrypted Client Hello (ECH). The default is to not offer ECH.
     pub(super) ech_mode: Option<EchMode>,
 }
@@ -349,7 +660,8 @@ impl ClientConfig {
     ///
     /// This is different from [`CryptoProvider::fips()`]: [`CryptoProvider::fips()`]
     /// is concerned only with cryptography, whereas this _also_ covers TLS-level
-    /// configuration that NIST recommends, as well as ECH HPKE suites if applicable.
+    /// configuration that NIST recommends, as well as ECH HPKE suites if applicable
+    /// (including those from [`ClientConfig::ech_resolver`]).
     pub fn fips(&self) -> bool {
         let mut is_fips = self.provider.fips();
 
@@ -358,13 +670,53 @@ impl ClientConfig {
             is_fips = is_fips && self.require_ems
         }
 
-        if let Some(ech_mode) = &self.ech_mode {
-            is_fips = is_fips && ech_mode.fips();
+        match (&self.ech_mode, &self.ech_resolver) {
+            (Some(ech_mode), _) => is_fips = is_fips && ech_mode.fips(),
+            (None, Some(ech_resolver)) => is_fips = is_fips && ech_resolver.fips(),
+            (None, None) => {}
         }
 
         is_fips
     }
 
+    /// Set [`ClientConfig::alpn_protocols`], after checking them with [`alpn::validate()`].
+    ///
+    /// ```
+    /// # fn example(config: rustls::ClientConfig) -> Result<rustls::ClientConfig, rustls::Error> {
+    /// use rustls::alpn;
+    /// let config = config.with_alpn_protocols([alpn::H2, alpn::HTTP_1_1])?;
+    /// # Ok(config)
+    /// # }
+    /// ```
+    pub fn with_alpn_protocols(
+        mut self,
+        protocols: impl IntoIterator<Item = impl Into<Vec<u8>>>,
+    ) -> Result<Self, Error> {
+        let protocols = protocols
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<_>>();
+        alpn::validate(&protocols)?;
+        self.alpn_protocols = protocols;
+        Ok(self)
+    }
+
+    /// Prefer the certificate compression algorithms in `preference`, in that order.
+    ///
+    /// This reorders [`ClientConfig::cert_compressors`] and
+    /// [`ClientConfig::cert_decompressors`] so that those for the algorithms in
+    /// `preference` come first; the rest keep their order after them.  The
+    /// client's certificate is compressed with the first of its compressors that
+    /// the server supports, and its decompressors are offered in order.
+    pub fn with_cert_compression_preference(
+        mut self,
+        preference: &[CertificateCompressionAlgorithm],
+    ) -> Self {
+        compress::prefer_algorithms(&mut self.cert_compressors, preference, |c| c.algorithm());
+        compress::prefer_algorithms(&mut self.cert_decompressors, preference, |d| d.algorithm());
+        self
+    }
+
     /// Return the crypto provider used to construct this client configuration.
     pub fn crypto_provider(&self) -> &Arc<CryptoProvider> {
         &self.provider
@@ -404,14 +756,6 @@ impl ClientConfig {
             .find(|&scs| scs.suite() == suite)
     }
 
-    pub(super) fn find_kx_group(&self, group: NamedGroup) -> Option<&'static dyn SupportedKxGroup> {
-        self.provider
-            .kx_groups
-            .iter()
-            .copied()
-            .find(|skxg| skxg.name() == group)
-    }
-
     pub(super) fn current_time(&self) -> Result<UnixTime, Error> {
         self.time_provider
             .current_time()
@@ -428,6 +772,25 @@ pub struct Resumption {
 
     /// What mechanism is used for resuming a TLS 1.2 session.
     pub(super) tls12_resumption: Tls12Resumption,
+
+    /// How long before expiry a ticket is considered due for refresh.
+    pub(super) ticket_refresh_window: Option<Duration>,
+
+    /// Whether TLS 1.2 sessions are removed from the store once offered.
+    pub(super) single_use_sessions: bool,
+
+    /// Whether the server's key exchange group is remembered in the store.
+    pub(super) remember_kx_groups: bool,
+
+    /// Whether resumed sessions must keep the cipher suite and key exchange group.
+    pub(super) require_same_parameters: bool,
+
+    /// If set, decides which tickets are stored.
+    pub(super) ticket_filter: Option<Arc<dyn FiltersTickets>>,
+
+    /// If set, the servers already connected to with this `Resumption`.
+    #[cfg(feature = "std")]
+    pub(super) connected_servers: Option<Arc<Mutex<HashSet<ServerName<'static>>>>>,
 }
 
 impl Resumption {
@@ -440,6 +803,13 @@ impl Resumption {
         Self {
             store: Arc::new(super::handy::ClientSessionMemoryCache::new(num)),
             tls12_resumption: Tls12Resumption::SessionIdOrTickets,
+            ticket_refresh_window: None,
+            single_use_sessions: false,
+            remember_kx_groups: true,
+            require_same_parameters: false,
+            ticket_filter: None,
+            #[cfg(feature = "std")]
+            connected_servers: None,
         }
     }
 
@@ -450,6 +820,13 @@ impl Resumption {
         Self {
             store,
             tls12_resumption: Tls12Resumption::SessionIdOrTickets,
+            ticket_refresh_window: None,
+            single_use_sessions: false,
+            remember_kx_groups: true,
+            require_same_parameters: false,
+            ticket_filter: None,
+            #[cfg(feature = "std")]
+            connected_servers: None,
         }
     }
 
@@ -458,6 +835,25 @@ impl Resumption {
         Self {
             store: Arc::new(NoClientSessionStorage),
             tls12_resumption: Tls12Resumption::Disabled,
+            ticket_refresh_window: None,
+            single_use_sessions: false,
+            remember_kx_groups: true,
+            require_same_parameters: false,
+            ticket_filter: None,
+            #[cfg(feature = "std")]
+            connected_servers: None,
+        }
+    }
+
+    /// Disable session resumption, but remember which key exchange group each
+    /// server chose, for up to `num` servers in memory.
+    ///
+    /// See [`Resumption::remember_kx_groups()`].
+    #[cfg(feature = "std")]
+    pub fn kx_groups_only(num: usize) -> Self {
+        Self {
+            store: Arc::new(super::handy::KxHintsOnly::new(num)),
+            ..Self::disabled()
         }
     }
 
@@ -469,6 +865,174 @@ impl Resumption {
         self.tls12_resumption = tls12;
         self
     }
+
+    /// Track whether tickets are close to expiry.
+    ///
+    /// When a connection offers a stored ticket that expires within `window`,
+    /// it reports [`ClientConnection::ticket_refresh_due()`] until the server
+    /// issues a replacement.  Applications can use this to make a connection
+    /// ahead of expiry, rather than falling back to full handshakes once
+    /// tickets run out.
+    ///
+    /// The default is not to track this.
+    ///
+    /// [`ClientConnection::ticket_refresh_due()`]: crate::client::ClientConnection::ticket_refresh_due
+    pub fn ticket_refresh_window(mut self, window: Duration) -> Self {
+        self.ticket_refresh_window = Some(window);
+        self
+    }
+
+    /// Offer each stored session at most once.
+    ///
+    /// TLS 1.3 tickets are always single-use; this additionally removes a TLS 1.2
+    /// session from the store when it is offered.  A TLS 1.2 session can otherwise
+    /// be resumed many times, letting a network observer link those connections.
+    ///
+    /// The default is to reuse TLS 1.2 sessions until they expire.
+    pub fn single_use_sessions(mut self) -> Self {
+        self.single_use_sessions = true;
+        self
+    }
+
+    /// Configure whether to remember which key exchange group each server chose.
+    ///
+    /// If enabled, the group is recorded with [`ClientSessionStore::set_kx_hint()`],
+    /// and the next connection to that server sends a key share for it first.
+    /// This avoids a `HelloRetryRequest` round trip on every connection to a server
+    /// that prefers a group other than the first in [`CryptoProvider::kx_groups`].
+    /// Disabling this means every connection starts with the same key share,
+    /// which is less distinctive to a network observer.
+    ///
+    /// The default is `true`.
+    ///
+    /// [`CryptoProvider::kx_groups`]: crate::crypto::CryptoProvider::kx_groups
+    pub fn remember_kx_groups(mut self, remember: bool) -> Self {
+        self.remember_kx_groups = remember;
+        self
+    }
+
+    /// Abort TLS 1.3 resumptions that change the session's security parameters.
+    ///
+    /// When resuming, a server may choose a different cipher suite (with the same
+    /// hash function) or key exchange group than the connection that issued the
+    /// ticket.  With this set, the client aborts such handshakes with
+    /// [`PeerIncompatible::ResumedWithDifferentParameters`], so that, for example,
+    /// a session established with a post-quantum hybrid group is never continued
+    /// with a classical one.
+    ///
+    /// The default is to accept any parameters that the client offered.
+    ///
+    /// [`PeerIncompatible::ResumedWithDifferentParameters`]: crate::PeerIncompatible::ResumedWithDifferentParameters
+    pub fn require_same_parameters(mut self) -> Self {
+        self.require_same_parameters = true;
+        self
+    }
+
+    /// Decide whether to store each ticket a server issues.
+    ///
+    /// `filter` sees every non-empty ticket before it is stored, and can refuse it,
+    /// for example to avoid tickets whose lifetime would let a server link
+    /// connections over longer than the application allows.
+    ///
+    /// A refused TLS 1.3 ticket is discarded.  Refusing a TLS 1.2 ticket means it
+    /// is not stored or offered; the session may still be resumed by session id,
+    /// depending on [`Resumption::tls12_resumption()`].
+    ///
+    /// The default is to store every ticket.
+    pub fn ticket_filter(mut self, filter: Arc<dyn FiltersTickets>) -> Self {
+        self.ticket_filter = Some(filter);
+        self
+    }
+
+    /// Whether `ticket`, received from `server_name`, should be stored.
+    pub(super) fn accepts_ticket(
+        &self,
+        server_name: &ServerName<'_>,
+        ticket: &ReceivedTicket<'_>,
+    ) -> bool {
+        let accepted = match &self.ticket_filter {
+            Some(filter) => filter.accept_ticket(server_name, ticket),
+            None => true,
+        };
+        if !accepted {
+            trace!("Ticket refused by filter: {ticket:?}");
+        }
+        accepted
+    }
+
+    /// Always make a full handshake for the first connection to each server.
+    ///
+    /// Sessions are only offered to a server once this `Resumption` (or a clone
+    /// of it) has been used to connect to that server.  This is useful with a
+    /// persistent [`ClientSessionStore`], so that each new process authenticates
+    /// every server afresh before relying on sessions stored by earlier ones.
+    ///
+    /// The default is to offer any stored session.
+    #[cfg(feature = "std")]
+    pub fn fresh_first_connection(mut self) -> Self {
+        self.connected_servers = Some(Arc::new(Mutex::new(HashSet::new())));
+        self
+    }
+
+    /// Record a connection to `server_name`, returning true if it is the first one.
+    #[cfg(feature = "std")]
+    pub(super) fn first_connection_to(&self, server_name: &ServerName<'static>) -> bool {
+        match &self.connected_servers {
+            Some(servers) => servers
+                .lock()
+                .map(|mut servers| servers.insert(server_name.clone()))
+                .unwrap_or(true),
+            None => false,
+        }
+    }
+}
+
+/// Why a client connection did, or did not, offer to resume a previous session.
+///
+/// Whether the server accepted an offered session is reported by
+/// [`CommonState::handshake_kind()`].
+///
+/// [`CommonState::handshake_kind()`]: crate::CommonState::handshake_kind
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResumptionDecision {
+    /// A stored session was offered to the server.
+    Offered,
+    /// No unexpired session was stored for the server.
+    NoSession,
+    /// This was the first connection to the server, and [`Resumption::fresh_first_connection()`]
+    /// is configured.
+    FirstConnection,
+}
+
+/// Decides which tickets a client stores, for [`Resumption::ticket_filter()`].
+pub trait FiltersTickets: fmt::Debug + Send + Sync {
+    /// Return true if `ticket`, received from `server_name`, should be stored.
+    fn accept_ticket(&self, server_name: &ServerName<'_>, ticket: &ReceivedTicket<'_>) -> bool;
+}
+
+/// The parameters of a ticket received from a server, given to [`FiltersTickets`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug)]
+pub struct ReceivedTicket<'a> {
+    /// The protocol version of the session the ticket resumes.
+    pub version: ProtocolVersion,
+
+    /// How long the server says the ticket may be used for.
+    ///
+    /// For TLS 1.2 this is the server's hint, and zero means unspecified.
+    pub lifetime: Duration,
+
+    /// The largest amount of early data the server will accept with the ticket.
+    ///
+    /// This is `None` for TLS 1.2, and for TLS 1.3 tickets that do not allow early data.
+    pub max_early_data_size: Option<u32>,
+
+    /// The ticket nonce.  This is `None` for TLS 1.2.
+    pub nonce: Option<&'a [u8]>,
+
+    /// The length of the ticket.
+    pub ticket_len: usize,
 }
 
 impl Default for Resumption {
@@ -501,12 +1065,29 @@ pub enum Tls12Resumption {
     SessionIdOrTickets,
 }
 
+/// A TLS1.3 downgrade protection sentinel, found in the `ServerHello.random`
+/// of a TLS1.2 handshake.
+///
+/// See [RFC 8446 section 4.1.3](https://www.rfc-editor.org/rfc/rfc8446#section-4.1.3).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DowngradeSentinel {
+    /// The server supports TLS1.3, but negotiated TLS1.2.
+    Tls12,
+    /// The server claims to have negotiated TLS1.1 or below.
+    ///
+    /// A correct server does not send this in a TLS1.2 handshake, but like
+    /// [`DowngradeSentinel::Tls12`] it only aborts the handshake if TLS1.3 was
+    /// offered, or [`ClientConfig::strict_downgrade_protection`] is enabled.
+    Tls11OrBelow,
+}
+
 /// Container for unsafe APIs
 pub(super) mod danger {
     use alloc::sync::Arc;
 
     use super::verify::ServerCertVerifier;
     use super::ClientConfig;
+    use crate::common_state::MutatesHandshakeMessages;
 
     /// Accessor for dangerous configuration options.
     #[derive(Debug)]
@@ -520,6 +1101,50 @@ pub(super) mod danger {
         pub fn set_certificate_verifier(&mut self, verifier: Arc<dyn ServerCertVerifier>) {
             self.cfg.verifier = verifier;
         }
+
+        /// Alters each handshake message sent by connections using this config.
+        ///
+        /// See [`MutatesHandshakeMessages`].  This is intended for negative testing
+        /// and protocol experiments: connections using it are unlikely to interoperate.
+        pub fn set_handshake_mutator(&mut self, mutator: Arc<dyn MutatesHandshakeMessages>) {
+            self.cfg.handshake_mutator = Some(mutator);
+        }
+
+        /// Sets whether the server must support the extended master secret extraction
+        /// method defined in [RFC 7627].  TLS1.2 handshakes with peers that do not
+        /// support it are aborted.
+        ///
+        /// The default is `true`.
+        ///
+        /// Setting this to `false` is dangerous: it permits legacy TLS1.2 peers that do
+        /// not support extended master secret, exposing those connections to the triple
+        /// handshake attack.  Use [`CommonState::extended_master_secret()`] to find out
+        /// whether a given connection negotiated it.
+        ///
+        /// It must be set to `true` to meet FIPS requirement mentioned in section
+        /// **D.Q Transition of the TLS 1.2 KDF to Support the Extended Master
+        /// Secret** from [FIPS 140-3 IG.pdf]: see [`ClientConfig::fips()`].
+        ///
+        /// [RFC 7627]: https://datatracker.ietf.org/doc/html/rfc7627
+        /// [FIPS 140-3 IG.pdf]: https://csrc.nist.gov/csrc/media/Projects/cryptographic-module-validation-program/documents/fips%20140-3/FIPS%20140-3%20IG.pdf
+        /// [`CommonState::extended_master_secret()`]: crate::CommonState::extended_master_secret
+        #[cfg(feature = "tls12")]
+        pub fn set_require_ems(&mut self, require_ems: bool) {
+            self.cfg.require_ems = require_ems;
+        }
+
+        /// Accept any number of TLS1.3 `KeyUpdate` messages from the peer, rather
+        /// than the limit set by [`PostHandshakeLimits::max_key_updates()`].
+        ///
+        /// This is dangerous: each `KeyUpdate` costs a key derivation, so a peer may
+        /// send a stream of them to use up resources.
+        ///
+        /// [`PostHandshakeLimits::max_key_updates()`]: crate::PostHandshakeLimits::max_key_updates
+        pub fn set_unlimited_key_updates(&mut self) {
+            self.cfg
+                .post_handshake_limits
+                .remove_key_update_limit();
+        }
     }
 }
 
@@ -550,7 +1175,6 @@ impl EarlyData {
         matches!(self.state, EarlyDataState::Ready | EarlyDataState::Accepted)
     }
 
-    #[cfg(feature = "std")]
     fn is_accepted(&self) -> bool {
         matches!(
             self.state,
@@ -611,14 +1235,89 @@ mod connection {
 
     use pki_types::ServerName;
 
-    use super::ClientConnectionData;
-    use crate::client::EchStatus;
+    use super::{ClientConnectionData, DowngradeSentinel, ResumptionDecision};
+    use crate::client::{EchStatus, RevocationStatus};
     use crate::common_state::Protocol;
     use crate::conn::{ConnectionCommon, ConnectionCore};
-    use crate::error::Error;
+    use crate::error::{ApiMisuse, Error};
+    use crate::msgs::enums::NamedGroup;
     use crate::suites::ExtractedSecrets;
+    use crate::verify::ServerCertVerifier;
     use crate::ClientConfig;
 
+    /// Settings for one [`ClientConnection`] that differ from its [`ClientConfig`].
+    ///
+    /// Pass these to [`ClientConnection::new_with_options()`].  The default options
+    /// change nothing.
+    #[derive(Clone, Debug, Default)]
+    pub struct ClientConnectionOptions {
+        verifier: Option<Arc<dyn ServerCertVerifier>>,
+        without_resumption: bool,
+        kx_groups: Option<Vec<NamedGroup>>,
+    }
+
+    impl ClientConnectionOptions {
+        /// Options which change nothing.
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Neither resume a previous session, nor store this session for later resumption.
+        ///
+        /// This is equivalent to a `config` whose [`ClientConfig::resumption`] is
+        /// [`Resumption::disabled()`]: in particular, no key exchange hints are used or
+        /// stored either.  It is useful for individual connections which must not be
+        /// linkable to others.
+        ///
+        /// [`Resumption::disabled()`]: crate::client::Resumption::disabled
+        pub fn without_resumption(mut self) -> Self {
+            self.without_resumption = true;
+            self
+        }
+
+        /// Only offer the key exchange groups of the config that are named in `groups`.
+        ///
+        /// This is for narrowing the groups for one connection, for example to insist on a
+        /// post-quantum hybrid group, without making another [`ClientConfig`].  The groups
+        /// keep the order of preference from the config.
+        ///
+        /// Note that TLS1.2 cannot be negotiated if `groups` includes no group usable with
+        /// TLS1.2.  [`ClientConnection::new_with_options()`] fails with
+        /// [`ApiMisuse::NoKxGroupsSelected`] if none of `groups` are supported by the config.
+        pub fn with_kx_groups(mut self, groups: &[NamedGroup]) -> Self {
+            self.kx_groups = Some(groups.to_vec());
+            self
+        }
+
+        /// Access options whose use is dangerous and requires extra care.
+        pub fn dangerous(&mut self) -> DangerousClientConnectionOptions<'_> {
+            DangerousClientConnectionOptions { options: self }
+        }
+    }
+
+    /// Accessor for dangerous per-connection options.
+    #[derive(Debug)]
+    pub struct DangerousClientConnectionOptions<'a> {
+        /// The underlying options
+        pub options: &'a mut ClientConnectionOptions,
+    }
+
+    impl DangerousClientConnectionOptions<'_> {
+        /// Verifies the server with `verifier`, rather than the verifier in the
+        /// connection's [`ClientConfig`].
+        ///
+        /// This is for one-off exceptions, such as accepting a specific self-signed
+        /// certificate while pairing with a device, without weakening the config for
+        /// its other users.
+        ///
+        /// Sessions authenticated by `verifier` must not be resumed by connections that
+        /// use the config's verifier, so the connection neither resumes nor stores sessions,
+        /// as for [`ClientConnectionOptions::without_resumption()`].
+        pub fn set_certificate_verifier(&mut self, verifier: Arc<dyn ServerCertVerifier>) {
+            self.options.verifier = Some(verifier);
+        }
+    }
+
     /// Stub that implements io::Write and dispatches to `write_early_data`.
     pub struct WriteEarlyData<'a> {
         sess: &'a mut ClientConnection,
@@ -679,11 +1378,47 @@ mod connection {
         /// we behave in the TLS protocol, `name` is the
         /// name of the server we want to talk to.
         pub fn new(config: Arc<ClientConfig>, name: ServerName<'static>) -> Result<Self, Error> {
+            Self::new_with_data(config, name, ClientConnectionData::new())
+        }
+
+        fn new_with_data(
+            config: Arc<ClientConfig>,
+            name: ServerName<'static>,
+            data: ClientConnectionData,
+        ) -> Result<Self, Error> {
             Ok(Self {
-                inner: ConnectionCore::for_client(config, name, Vec::new(), Protocol::Tcp)?.into(),
+                inner: ConnectionCore::for_client(config, name, Vec::new(), Protocol::Tcp, data)?
+                    .into(),
             })
         }
 
+        /// Make a new ClientConnection, with `options` overriding parts of `config`
+        /// for this connection only.
+        ///
+        /// See [`ClientConnectionOptions`].
+        pub fn new_with_options(
+            config: Arc<ClientConfig>,
+            name: ServerName<'static>,
+            options: ClientConnectionOptions,
+        ) -> Result<Self, Error> {
+            let mut data = ClientConnectionData::new();
+            if let Some(groups) = options.kx_groups {
+                if !config
+                    .provider
+                    .kx_groups
+                    .iter()
+                    .any(|skxg| groups.contains(&skxg.name()))
+                {
+                    return Err(ApiMisuse::NoKxGroupsSelected.into());
+                }
+                data.kx_groups = Some(groups);
+            }
+
+            data.without_resumption = options.without_resumption || options.verifier.is_some();
+            data.verifier = options.verifier;
+            Self::new_with_data(config, name, data)
+        }
+
         /// Returns an `io::Write` implementer you can write bytes to
         /// to send TLS1.3 early data (a.k.a. "0-RTT data") to the server.
         ///
@@ -736,6 +1471,51 @@ mod connection {
             self.inner.core.data.ech_status
         }
 
+        /// Return the TLS1.3 downgrade protection sentinel sent by the server, if any.
+        ///
+        /// This is only ever `Some` for TLS1.2 connections made without offering
+        /// TLS1.3, and with [`ClientConfig::strict_downgrade_protection`] disabled;
+        /// in all other cases a sentinel aborts the handshake.
+        pub fn downgrade_sentinel(&self) -> Option<DowngradeSentinel> {
+            self.inner.core.data.downgrade_sentinel
+        }
+
+        /// Return true if this connection offered a ticket that is close to expiry,
+        /// and the server has not yet issued a replacement.
+        ///
+        /// This is only tracked if [`Resumption::ticket_refresh_window()`] is
+        /// configured.  TLS1.3 servers send tickets after the handshake, so
+        /// this may become false later in the connection.
+        ///
+        /// [`Resumption::ticket_refresh_window()`]: crate::client::Resumption::ticket_refresh_window
+        pub fn ticket_refresh_due(&self) -> bool {
+            self.inner.core.data.ticket_refresh_due
+        }
+
+        /// Return whether this connection offered to resume a previous session, and why.
+        pub fn resumption_decision(&self) -> ResumptionDecision {
+            self.inner.core.data.resumption_decision
+        }
+
+        /// Return the revocation status of each certificate in the server's chain.
+        ///
+        /// These are in the same order as [`CommonState::peer_certificates()`], and are
+        /// determined by [`ServerCertVerifier::check_stapled_ocsp()`] from the OCSP
+        /// responses the server stapled.
+        ///
+        /// This is empty until the server's certificate chain has been verified, and for
+        /// resumed sessions.
+        ///
+        /// [`CommonState::peer_certificates()`]: crate::CommonState::peer_certificates
+        /// [`ServerCertVerifier::check_stapled_ocsp()`]: crate::client::danger::ServerCertVerifier::check_stapled_ocsp
+        pub fn peer_revocation_status(&self) -> &[RevocationStatus] {
+            &self
+                .inner
+                .core
+                .data
+                .peer_revocation_status
+        }
+
         /// Return true if the connection was made with a `ClientConfig` that is FIPS compatible.
         ///
         /// This is different from [`crate::crypto::CryptoProvider::fips()`]:
@@ -792,7 +1572,9 @@ mod connection {
     }
 }
 #[cfg(feature = "std")]
-pub use connection::{ClientConnection, WriteEarlyData};
+pub use connection::{
+    ClientConnection, ClientConnectionOptions, DangerousClientConnectionOptions, WriteEarlyData,
+};
 
 impl ConnectionCore<ClientConnectionData> {
     pub(crate) fn for_client(
@@ -800,12 +1582,22 @@ impl ConnectionCore<ClientConnectionData> {
         name: ServerName<'static>,
         extra_exts: Vec<ClientExtension>,
         proto: Protocol,
+        mut data: ClientConnectionData,
     ) -> Result<Self, Error> {
         let mut common_state = CommonState::new(Side::Client);
-        common_state.set_max_fragment_size(config.max_fragment_size)?;
+        common_state.apply_client_config(&config)?;
+        let mut message_deframer = MessageDeframer::default();
+        message_deframer.set_max_handshake_size(config.max_handshake_size)?;
+        message_deframer.set_record_strictness(config.record_strictness);
+        if let Some(max_fragment_length) = config.max_fragment_length {
+            if max_fragment_length
+                .fragment_len()
+                .is_none()
+            {
+                return Err(Error::BadMaxFragmentSize);
+            }
+        }
         common_state.protocol = proto;
-        common_state.enable_secret_extraction = config.enable_secret_extraction;
-        let mut data = ClientConnectionData::new();
         data.fips = config.fips();
 
         let mut cx = hs::ClientContext {
@@ -816,10 +1608,11 @@ impl ConnectionCore<ClientConnectionData> {
         };
 
         let state = hs::start_handshake(name, extra_exts, config, &mut cx)?;
-        Ok(Self::new(state, data, common_state))
+        let mut core = Self::new(state, data, common_state);
+        core.message_deframer = message_deframer;
+        Ok(core)
     }
 
-    #[cfg(feature = "std")]
     pub(crate) fn is_early_data_accepted(&self) -> bool {
         self.data.early_data.is_accepted()
     }
@@ -837,9 +1630,68 @@ impl UnbufferedClientConnection {
     /// the name of the server we want to talk to.
     pub fn new(config: Arc<ClientConfig>, name: ServerName<'static>) -> Result<Self, Error> {
         Ok(Self {
-            inner: ConnectionCore::for_client(config, name, Vec::new(), Protocol::Tcp)?.into(),
+            inner: ConnectionCore::for_client(
+                config,
+                name,
+                Vec::new(),
+                Protocol::Tcp,
+                ClientConnectionData::new(),
+            )?
+            .into(),
         })
     }
+
+    /// Return the TLS1.3 downgrade protection sentinel sent by the server, if any.
+    ///
+    /// See [`ClientConnection::downgrade_sentinel()`] for details.
+    ///
+    /// [`ClientConnection::downgrade_sentinel()`]: crate::client::ClientConnection::downgrade_sentinel
+    pub fn downgrade_sentinel(&self) -> Option<DowngradeSentinel> {
+        self.inner.core.data.downgrade_sentinel
+    }
+
+    /// Return true if this connection offered a ticket that is close to expiry,
+    /// and the server has not yet issued a replacement.
+    ///
+    /// See [`ClientConnection::ticket_refresh_due()`] for details.
+    ///
+    /// [`ClientConnection::ticket_refresh_due()`]: crate::client::ClientConnection::ticket_refresh_due
+    pub fn ticket_refresh_due(&self) -> bool {
+        self.inner.core.data.ticket_refresh_due
+    }
+
+    /// Return whether this connection offered to resume a previous session, and why.
+    pub fn resumption_decision(&self) -> ResumptionDecision {
+        self.inner.core.data.resumption_decision
+    }
+
+    /// Return the revocation status of each certificate in the server's chain.
+    ///
+    /// See [`ClientConnection::peer_revocation_status()`] for details.
+    ///
+    /// [`ClientConnection::peer_revocation_status()`]: crate::client::ClientConnection::peer_revocation_status
+    pub fn peer_revocation_status(&self) -> &[verify::RevocationStatus] {
+        &self
+            .inner
+            .core
+            .data
+            .peer_revocation_status
+    }
+
+    /// Returns True if the server signalled it will process early data.
+    ///
+    /// If you sent early data and this returns false at the end of the
+    /// handshake then the server will not process the data.  This
+    /// is not an error, but you may wish to resend the data.
+    pub fn is_early_data_accepted(&self) -> bool {
+        self.inner.core.is_early_data_accepted()
+    }
+
+    /// Extract secrets, so they can be used when configuring kTLS, for example.
+    /// Should be used with care as it exposes secret key material.
+    pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
+        self.inner.dangerous_extract_secrets()
+    }
 }
 
 impl Deref for UnbufferedClientConnection {
@@ -943,18 +1795,87 @@ pub struct ClientConnectionData {
     pub(super) early_data: EarlyData,
     pub(super) resumption_ciphersuite: Option<SupportedCipherSuite>,
     pub(super) ech_status: EchStatus,
+    pub(super) downgrade_sentinel: Option<DowngradeSentinel>,
+    pub(super) ticket_refresh_due: bool,
+    pub(super) resumption_decision: ResumptionDecision,
+    pub(super) peer_revocation_status: Vec<verify::RevocationStatus>,
     pub(super) fips: bool,
+    /// The key exchange groups this connection is narrowed to, if any.
+    pub(super) kx_groups: Option<Vec<NamedGroup>>,
+    /// Set by [`ClientConnectionOptions::without_resumption()`].
+    pub(super) without_resumption: bool,
+    /// Set by [`DangerousClientConnectionOptions::set_certificate_verifier()`].
+    pub(super) verifier: Option<Arc<dyn verify::ServerCertVerifier>>,
 }
 
 impl ClientConnectionData {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             early_data: EarlyData::new(),
             resumption_ciphersuite: None,
             ech_status: EchStatus::NotOffered,
+            downgrade_sentinel: None,
+            ticket_refresh_due: false,
+            resumption_decision: ResumptionDecision::NoSession,
+            peer_revocation_status: Vec::new(),
             fips: false,
+            kx_groups: None,
+            without_resumption: false,
+            verifier: None,
         }
     }
+
+    /// The verifier this connection uses to authenticate the server.
+    pub(super) fn verifier<'a>(
+        &'a self,
+        config: &'a ClientConfig,
+    ) -> &'a dyn verify::ServerCertVerifier {
+        match &self.verifier {
+            Some(verifier) => verifier.as_ref(),
+            None => config.verifier.as_ref(),
+        }
+    }
+
+    /// The session store used by this connection.
+    pub(super) fn session_store<'a>(&self, config: &'a ClientConfig) -> &'a dyn ClientSessionStore {
+        match self.without_resumption {
+            true => &NoClientSessionStorage,
+            false => config.resumption.store.as_ref(),
+        }
+    }
+
+    /// How this connection may resume a TLS1.2 session.
+    pub(super) fn tls12_resumption(&self, config: &ClientConfig) -> Tls12Resumption {
+        match self.without_resumption {
+            true => Tls12Resumption::Disabled,
+            false => config.resumption.tls12_resumption,
+        }
+    }
+
+    /// The key exchange groups of `config` this connection may use, in order of preference.
+    pub(super) fn kx_groups<'a>(
+        &'a self,
+        config: &'a ClientConfig,
+    ) -> impl Iterator<Item = &'static dyn SupportedKxGroup> + 'a {
+        config
+            .provider
+            .kx_groups
+            .iter()
+            .copied()
+            .filter(move |skxg| match &self.kx_groups {
+                Some(groups) => groups.contains(&skxg.name()),
+                None => true,
+            })
+    }
+
+    pub(super) fn find_kx_group(
+        &self,
+        config: &ClientConfig,
+        group: NamedGroup,
+    ) -> Option<&'static dyn SupportedKxGroup> {
+        self.kx_groups(config)
+            .find(|skxg| skxg.name() == group)
+    }
 }
 
 impl crate::conn::SideData for ClientConnectionData {}
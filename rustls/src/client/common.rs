@@ -1,18 +1,28 @@
 use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::any::Any;
+use core::iter;
 
-use super::ResolvesClientCert;
+use super::{CertificateRequest, ResolvesClientCert};
+use crate::crypto::SecureRandom;
+use crate::enums::{CipherSuite, ProtocolVersion};
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
-use crate::msgs::enums::ExtensionType;
-use crate::msgs::handshake::{CertificateChain, DistinguishedName, ServerExtension};
+use crate::msgs::base::Payload;
+use crate::msgs::enums::{ExtensionType, NamedGroup};
+use crate::msgs::handshake::{
+    CertReqExtension, CertificateChain, ClientExtension, DistinguishedName, ServerExtension,
+    UnknownExtension,
+};
+use crate::rand::GetRandomFailed;
 use crate::{compress, sign, SignatureScheme};
 
 #[derive(Debug)]
 pub(super) struct ServerCertDetails<'a> {
     pub(super) cert_chain: CertificateChain<'a>,
     pub(super) ocsp_response: Vec<u8>,
+    pub(super) intermediate_ocsp: Vec<Vec<u8>>,
 }
 
 impl<'a> ServerCertDetails<'a> {
@@ -20,17 +30,40 @@ impl<'a> ServerCertDetails<'a> {
         Self {
             cert_chain,
             ocsp_response,
+            intermediate_ocsp: Vec::new(),
         }
     }
 
+    pub(super) fn with_intermediate_ocsp(mut self, intermediate_ocsp: Vec<Vec<u8>>) -> Self {
+        self.intermediate_ocsp = intermediate_ocsp;
+        self
+    }
+
+    /// The OCSP response stapled to each certificate, end-entity first.
+    ///
+    /// Items are empty for certificates without a stapled response.
+    pub(super) fn ocsp_responses(&self) -> Vec<&[u8]> {
+        let intermediates = self
+            .intermediate_ocsp
+            .iter()
+            .map(|resp| resp.as_slice())
+            .chain(iter::repeat(&[][..]));
+        iter::once(self.ocsp_response.as_slice())
+            .chain(intermediates)
+            .take(self.cert_chain.len())
+            .collect()
+    }
+
     pub(super) fn into_owned(self) -> ServerCertDetails<'static> {
         let Self {
             cert_chain,
             ocsp_response,
+            intermediate_ocsp,
         } = self;
         ServerCertDetails {
             cert_chain: cert_chain.into_owned(),
             ocsp_response,
+            intermediate_ocsp,
         }
     }
 }
@@ -38,15 +71,20 @@ impl<'a> ServerCertDetails<'a> {
 pub(super) struct ClientHelloDetails {
     pub(super) sent_extensions: Vec<ExtensionType>,
     pub(super) extension_order_seed: u16,
+    pub(super) grease: Option<Grease>,
     pub(super) offered_cert_compression: bool,
+    /// The group of the hybrid key share's classical component, if that was also offered.
+    pub(super) offered_hybrid_component: Option<NamedGroup>,
 }
 
 impl ClientHelloDetails {
-    pub(super) fn new(extension_order_seed: u16) -> Self {
+    pub(super) fn new(extension_order_seed: u16, grease: Option<Grease>) -> Self {
         Self {
             sent_extensions: Vec::new(),
             extension_order_seed,
+            grease,
             offered_cert_compression: false,
+            offered_hybrid_component: None,
         }
     }
 
@@ -68,6 +106,64 @@ impl ClientHelloDetails {
     }
 }
 
+/// The [GREASE] values sent in a connection's `ClientHello`s.
+///
+/// These are chosen randomly for each connection, and reused after a `HelloRetryRequest`.
+///
+/// [GREASE]: https://www.rfc-editor.org/rfc/rfc8701
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Grease([u8; 4]);
+
+impl Grease {
+    pub(super) fn new(secure_random: &dyn SecureRandom) -> Result<Self, GetRandomFailed> {
+        let mut random = [0u8; 4];
+        secure_random.fill(&mut random)?;
+
+        // The two extensions must have different types.
+        if random[2] >> 4 == random[3] >> 4 {
+            random[3] ^= 0x10;
+        }
+        Ok(Self(random))
+    }
+
+    pub(super) fn cipher_suite(&self) -> CipherSuite {
+        CipherSuite::Unknown(grease_value(self.0[0]))
+    }
+
+    pub(super) fn version_and_group(&self) -> (ProtocolVersion, NamedGroup) {
+        let value = grease_value(self.0[1]);
+        (ProtocolVersion::Unknown(value), NamedGroup::Unknown(value))
+    }
+
+    /// Two extensions: one empty, and one containing a single zero byte.
+    pub(super) fn extensions(&self) -> [ClientExtension; 2] {
+        [
+            ClientExtension::Unknown(UnknownExtension {
+                typ: ExtensionType::Unknown(grease_value(self.0[2])),
+                payload: Payload::Borrowed(&[]),
+            }),
+            ClientExtension::Unknown(UnknownExtension {
+                typ: ExtensionType::Unknown(grease_value(self.0[3])),
+                payload: Payload::Borrowed(&[0]),
+            }),
+        ]
+    }
+}
+
+/// Map the high nibble of `random` to one of the values 0x0a0a, 0x1a1a, ..., 0xfafa.
+fn grease_value(random: u8) -> u16 {
+    let byte = (random & 0xf0) | 0x0a;
+    u16::from_be_bytes([byte, byte])
+}
+
+/// Whether `value` is one of the GREASE values.
+///
+/// A server must never send these, so they are not recorded as extensions we sent.
+pub(super) fn is_grease(value: u16) -> bool {
+    let [hi, lo] = value.to_be_bytes();
+    hi == lo && lo & 0x0f == 0x0a
+}
+
 pub(super) enum ClientAuthDetails {
     /// Send an empty `Certificate` and no `CertificateVerify`.
     Empty { auth_context_tls13: Option<Vec<u8>> },
@@ -83,10 +179,13 @@ pub(super) enum ClientAuthDetails {
 impl ClientAuthDetails {
     pub(super) fn resolve(
         resolver: &dyn ResolvesClientCert,
+        version: ProtocolVersion,
         canames: Option<&[DistinguishedName]>,
         sigschemes: &[SignatureScheme],
+        extensions: &[CertReqExtension],
         auth_context_tls13: Option<Vec<u8>>,
         compressor: Option<&'static dyn compress::CertCompressor>,
+        user_data: Option<&(dyn Any + Send)>,
     ) -> Self {
         let acceptable_issuers = canames
             .unwrap_or_default()
@@ -94,7 +193,15 @@ impl ClientAuthDetails {
             .map(|p| p.as_ref())
             .collect::<Vec<&[u8]>>();
 
-        if let Some(certkey) = resolver.resolve(&acceptable_issuers, sigschemes) {
+        let request = CertificateRequest::new(
+            version,
+            auth_context_tls13.as_deref(),
+            &acceptable_issuers,
+            sigschemes,
+            extensions,
+            user_data,
+        );
+        if let Some(certkey) = resolver.resolve_request(&request) {
             if let Some(signer) = certkey.key.choose_scheme(sigschemes) {
                 debug!("Attempting client auth");
                 return Self::Verify {
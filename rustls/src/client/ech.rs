@@ -1,10 +1,12 @@
 use alloc::boxed::Box;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::fmt;
 
 use pki_types::{DnsName, EchConfigListBytes, ServerName};
 use subtle::ConstantTimeEq;
 
+use crate::client::common::is_grease;
 use crate::client::tls13;
 use crate::crypto::hash::Hash;
 use crate::crypto::hpke::{EncapsulatedSecret, Hpke, HpkePublicKey, HpkeSealer, HpkeSuite};
@@ -29,7 +31,7 @@ use crate::tls13::key_schedule::{
 };
 use crate::CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV;
 use crate::{
-    AlertDescription, CommonState, EncryptedClientHelloError, Error, HandshakeType,
+    AlertDescription, CommonState, EncryptedClientHelloError, Error, HandshakeType, KeyLog,
     PeerIncompatible, PeerMisbehaved, ProtocolVersion, Tls13CipherSuite,
 };
 
@@ -69,6 +71,54 @@ impl From<EchGreaseConfig> for EchMode {
     }
 }
 
+/// Supplies ECH configurations for servers as connections are made.
+///
+/// This allows one [`ClientConfig`] to be shared by connections to many servers, each
+/// with its own ECH configuration -- typically sourced from the `ech` parameter of the
+/// server's DNS `HTTPS` resource record.
+///
+/// Install one of these in [`ClientConfig::ech_resolver`].  It is consulted only if
+/// [`ClientConfig`] was not built with an explicit [`EchMode`], and only for
+/// configurations that support TLS1.3.
+///
+/// [`ClientConfig`]: crate::client::ClientConfig
+/// [`ClientConfig::ech_resolver`]: crate::client::ClientConfig::ech_resolver
+pub trait ResolvesEchConfig: fmt::Debug + Send + Sync {
+    /// Return the ECH configuration to use for a new connection to `server_name`.
+    ///
+    /// Return `None` to connect without ECH.  This is called synchronously when the
+    /// connection is created, so should return promptly: lookups should be done
+    /// ahead of time, or in the background.
+    fn ech_config(&self, server_name: &ServerName<'_>) -> Option<EchConfig>;
+
+    /// Called when the server for `server_name` rejected our ECH offer.
+    ///
+    /// `retry_configs` carries any replacement configurations the server sent, as
+    /// an encoded `ECHConfigList` suitable for [`EchConfig::new()`].  These have been
+    /// authenticated by the server's certificate for its public name.  The handshake
+    /// itself fails with [`PeerIncompatible::ServerRejectedEncryptedClientHello`]; a
+    /// new connection should be made to use the retry configs.
+    ///
+    /// The default implementation does nothing.
+    fn ech_rejected(
+        &self,
+        server_name: &ServerName<'_>,
+        retry_configs: Option<EchConfigListBytes<'static>>,
+    ) {
+        let _ = (server_name, retry_configs);
+    }
+
+    /// Return `true` if every [`EchConfig`] this returns uses a FIPS-approved HPKE suite.
+    ///
+    /// This contributes to [`ClientConfig::fips()`].  The default implementation
+    /// returns `false`.
+    ///
+    /// [`ClientConfig::fips()`]: crate::client::ClientConfig::fips
+    fn fips(&self) -> bool {
+        false
+    }
+}
+
 /// Configuration for performing encrypted client hello.
 ///
 /// Note: differs from the protocol-encoded EchConfig (`EchConfigMsg`).
@@ -312,6 +362,8 @@ pub(crate) struct EchState {
     enable_sni: bool,
     // The extensions sent in the inner hello.
     sent_extensions: Vec<ExtensionType>,
+    // The encoding of the ECH configuration we've chosen, for key logging.
+    config_encoding: Vec<u8>,
 }
 
 impl EchState {
@@ -358,9 +410,23 @@ impl EchState {
             early_data_key_schedule: None,
             enable_sni,
             sent_extensions: Vec::new(),
+            config_encoding: config.config.get_encoding(),
         })
     }
 
+    /// Log the ECH configuration and HPKE shared secret used for the outer hello
+    /// with random `client_random`, as `ECH_CONFIG` and `ECH_SECRET`.
+    pub(crate) fn log_secrets(&self, key_log: &dyn KeyLog, client_random: &[u8; 32]) {
+        if let Some(secret) = self.sender.kem_shared_secret() {
+            if key_log.will_log("ECH_SECRET") {
+                key_log.log("ECH_SECRET", client_random, secret);
+            }
+        }
+        if key_log.will_log("ECH_CONFIG") {
+            key_log.log("ECH_CONFIG", client_random, &self.config_encoding);
+        }
+    }
+
     /// Construct a ClientHelloPayload offering ECH.
     ///
     /// An outer hello, with a protected inner hello for the `inner_name` will be returned, and the
@@ -661,6 +727,7 @@ impl EchState {
             .extensions
             .iter()
             .map(|ext| ext.ext_type())
+            .filter(|ext_type| !is_grease(u16::from(*ext_type)))
             .collect();
 
         // If we're resuming, we need to update the PSK binder in the inner hello.
@@ -815,6 +882,13 @@ pub(crate) struct EchAccepted {
     pub(crate) sent_extensions: Vec<ExtensionType>,
 }
 
+/// Encode `configs` as an `ECHConfigList`.
+pub(crate) fn config_list_bytes(configs: &[EchConfigPayload]) -> EchConfigListBytes<'static> {
+    let mut bytes = Vec::new();
+    configs.to_vec().encode(&mut bytes);
+    EchConfigListBytes::from(bytes)
+}
+
 pub(crate) fn fatal_alert_required(
     retry_configs: Option<Vec<EchConfigPayload>>,
     common: &mut CommonState,
@@ -1,11 +1,14 @@
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Formatter};
 
 use pki_types::ServerName;
 
 use crate::enums::SignatureScheme;
 use crate::error::Error;
-use crate::msgs::handshake::CertificateChain;
+use crate::msgs::handshake::{CertificateChain, DistinguishedName};
 use crate::msgs::persist;
+use crate::webpki::ParsedCertificate;
 use crate::{client, sign, NamedGroup};
 
 /// An implementer of `ClientSessionStore` which does nothing.
@@ -34,15 +37,76 @@ impl client::ClientSessionStore for NoClientSessionStorage {
     }
 }
 
+/// An implementer of `ClientSessionStore` which only stores key exchange hints,
+/// in memory.
+#[cfg(feature = "std")]
+pub(super) struct KxHintsOnly {
+    servers: crate::lock::Mutex<
+        crate::limited_cache::LimitedCache<ServerName<'static>, Option<NamedGroup>>,
+    >,
+}
+
+#[cfg(feature = "std")]
+impl KxHintsOnly {
+    /// Make a new store, remembering hints for up to `size` servers.
+    pub(super) fn new(size: usize) -> Self {
+        Self {
+            servers: crate::lock::Mutex::new(crate::limited_cache::LimitedCache::new(size)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Debug for KxHintsOnly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KxHintsOnly")
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "std")]
+impl client::ClientSessionStore for KxHintsOnly {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+        self.servers
+            .lock()
+            .unwrap()
+            .insert(server_name, Some(group));
+    }
+
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+        self.servers
+            .lock()
+            .unwrap()
+            .get(server_name)
+            .and_then(|group| *group)
+    }
+
+    fn set_tls12_session(&self, _: ServerName<'static>, _: persist::Tls12ClientSessionValue) {}
+
+    fn tls12_session(&self, _: &ServerName<'_>) -> Option<persist::Tls12ClientSessionValue> {
+        None
+    }
+
+    fn remove_tls12_session(&self, _: &ServerName<'_>) {}
+
+    fn insert_tls13_ticket(&self, _: ServerName<'static>, _: persist::Tls13ClientSessionValue) {}
+
+    fn take_tls13_ticket(&self, _: &ServerName<'_>) -> Option<persist::Tls13ClientSessionValue> {
+        None
+    }
+}
+
 #[cfg(any(feature = "std", feature = "hashbrown"))]
 mod cache {
     use alloc::collections::VecDeque;
+    use alloc::sync::Arc;
     use core::fmt;
 
     use pki_types::ServerName;
 
     use crate::lock::Mutex;
     use crate::msgs::persist;
+    use crate::server::ProducesTickets;
     use crate::{limited_cache, NamedGroup};
 
     const MAX_TLS13_TICKETS_PER_SERVER: usize = 8;
@@ -73,8 +137,12 @@ mod cache {
     /// in memory.
     ///
     /// It enforces a limit on the number of entries to bound memory usage.
+    ///
+    /// Use [`ClientSessionMemoryCache::with_encryption()`] to keep the stored
+    /// sessions' tickets and secrets encrypted while they are not in use.
     pub struct ClientSessionMemoryCache {
         servers: Mutex<limited_cache::LimitedCache<ServerName<'static>, ServerData>>,
+        sealer: Option<Arc<dyn ProducesTickets>>,
     }
 
     impl ClientSessionMemoryCache {
@@ -86,6 +154,7 @@ mod cache {
                 / MAX_TLS13_TICKETS_PER_SERVER;
             Self {
                 servers: Mutex::new(limited_cache::LimitedCache::new(max_servers)),
+                sealer: None,
             }
         }
 
@@ -97,6 +166,33 @@ mod cache {
                 / MAX_TLS13_TICKETS_PER_SERVER;
             Self {
                 servers: Mutex::new::<M>(limited_cache::LimitedCache::new(max_servers)),
+                sealer: None,
+            }
+        }
+
+        /// Store each session's ticket and secret encrypted by `sealer`,
+        /// decrypting them only when the session is used.
+        ///
+        /// This limits the exposure of resumption secrets in memory dumps.
+        /// `sealer` should hold an ephemeral key, such as the `Ticketer` of a
+        /// crypto provider.  Sessions that `sealer` can no longer decrypt,
+        /// for example after a key rotation, are treated as absent.
+        pub fn with_encryption(mut self, sealer: Arc<dyn ProducesTickets>) -> Self {
+            self.sealer = Some(sealer);
+            self
+        }
+
+        fn seal(&self, common: &mut persist::ClientSessionCommon) -> Option<()> {
+            match &self.sealer {
+                Some(sealer) => common.seal(sealer.as_ref()),
+                None => Some(()),
+            }
+        }
+
+        fn open(&self, common: &mut persist::ClientSessionCommon) -> Option<()> {
+            match &self.sealer {
+                Some(sealer) => common.unseal(sealer.as_ref()),
+                None => Some(()),
             }
         }
     }
@@ -123,12 +219,19 @@ mod cache {
             _value: persist::Tls12ClientSessionValue,
         ) {
             #[cfg(feature = "tls12")]
-            self.servers
-                .lock()
-                .unwrap()
-                .get_or_insert_default_and_edit(_server_name.clone(), |data| {
-                    data.tls12 = Some(_value)
-                });
+            {
+                let mut value = _value;
+                if self.seal(&mut value.common).is_none() {
+                    return;
+                }
+
+                self.servers
+                    .lock()
+                    .unwrap()
+                    .get_or_insert_default_and_edit(_server_name.clone(), |data| {
+                        data.tls12 = Some(value)
+                    });
+            }
         }
 
         fn tls12_session(
@@ -139,11 +242,16 @@ mod cache {
             return None;
 
             #[cfg(feature = "tls12")]
-            self.servers
-                .lock()
-                .unwrap()
-                .get(_server_name)
-                .and_then(|sd| sd.tls12.as_ref().cloned())
+            {
+                let mut value = self
+                    .servers
+                    .lock()
+                    .unwrap()
+                    .get(_server_name)
+                    .and_then(|sd| sd.tls12.as_ref().cloned())?;
+                self.open(&mut value.common)?;
+                Some(value)
+            }
         }
 
         fn remove_tls12_session(&self, _server_name: &ServerName<'static>) {
@@ -158,8 +266,12 @@ mod cache {
         fn insert_tls13_ticket(
             &self,
             server_name: ServerName<'static>,
-            value: persist::Tls13ClientSessionValue,
+            mut value: persist::Tls13ClientSessionValue,
         ) {
+            if self.seal(&mut value.common).is_none() {
+                return;
+            }
+
             self.servers
                 .lock()
                 .unwrap()
@@ -175,11 +287,14 @@ mod cache {
             &self,
             server_name: &ServerName<'static>,
         ) -> Option<persist::Tls13ClientSessionValue> {
-            self.servers
+            let mut value = self
+                .servers
                 .lock()
                 .unwrap()
                 .get_mut(server_name)
-                .and_then(|data| data.tls13.pop_back())
+                .and_then(|data| data.tls13.pop_back())?;
+            self.open(&mut value.common)?;
+            Some(value)
         }
     }
 
@@ -241,6 +356,142 @@ impl client::ResolvesClientCert for AlwaysResolvesClientCert {
     }
 }
 
+/// Something that resolves to one of several client certificate chains/keys,
+/// based on the certificate authorities and signature schemes the server accepts.
+///
+/// Chains are considered in the order they were added, and only those whose key
+/// supports one of the server's signature schemes are eligible.  The first chain
+/// issued by one of the server's certificate authority hints wins: a hint matches
+/// the issuer of any certificate in the chain, so may name an intermediate or root CA.
+/// If the server sent no hints, the first eligible chain is used.  Otherwise, if
+/// none match, the default chain (if any, and if eligible) is used.
+#[derive(Debug, Default)]
+pub struct ResolvesClientCertUsingIssuers {
+    identities: Vec<(Vec<DistinguishedName>, Arc<sign::CertifiedKey>)>,
+    default: Option<Arc<sign::CertifiedKey>>,
+}
+
+impl ResolvesClientCertUsingIssuers {
+    /// Create a new and empty (i.e., knows no certificates) resolver.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a new `sign::CertifiedKey`, to be used when the server accepts one of its issuers.
+    ///
+    /// This function fails if the certificate chain is empty or syntactically faulty.
+    pub fn add(&mut self, ck: sign::CertifiedKey) -> Result<(), Error> {
+        if ck.cert.is_empty() {
+            return Err(Error::NoCertificatesPresented);
+        }
+
+        let mut issuers = Vec::with_capacity(ck.cert.len());
+        for cert in &ck.cert {
+            let parsed = ParsedCertificate::try_from(cert)?;
+            issuers.push(DistinguishedName::in_sequence(parsed.0.issuer()));
+        }
+
+        self.identities
+            .push((issuers, Arc::new(ck)));
+        Ok(())
+    }
+
+    /// Set the `sign::CertifiedKey` to be used when no added chain matches the server's hints.
+    pub fn set_default(&mut self, ck: sign::CertifiedKey) {
+        self.default = Some(Arc::new(ck));
+    }
+}
+
+impl client::ResolvesClientCert for ResolvesClientCertUsingIssuers {
+    fn resolve(
+        &self,
+        root_hint_subjects: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<sign::CertifiedKey>> {
+        let mut eligible = self
+            .identities
+            .iter()
+            .filter(|(_, ck)| {
+                ck.key
+                    .choose_scheme(sigschemes)
+                    .is_some()
+            });
+
+        let chosen = match root_hint_subjects.is_empty() {
+            true => eligible.next(),
+            false => eligible.find(|(issuers, _)| {
+                issuers
+                    .iter()
+                    .any(|issuer| root_hint_subjects.contains(&issuer.as_ref()))
+            }),
+        };
+
+        match chosen {
+            Some((_, ck)) => Some(Arc::clone(ck)),
+            None => self
+                .default
+                .iter()
+                .find(|ck| {
+                    ck.key
+                        .choose_scheme(sigschemes)
+                        .is_some()
+                })
+                .cloned(),
+        }
+    }
+
+    fn has_certs(&self) -> bool {
+        !self.identities.is_empty() || self.default.is_some()
+    }
+}
+
+/// Something that resolves client certificates by calling a closure.
+///
+/// The closure is given the server's certificate authority hints and signature
+/// schemes, as described in [`client::ResolvesClientCert::resolve()`].  This
+/// avoids defining a type and implementing [`client::ResolvesClientCert`] for
+/// simple cases.
+pub struct ResolvesClientCertUsingFn<F> {
+    resolve: F,
+}
+
+impl<F> ResolvesClientCertUsingFn<F>
+where
+    F: Fn(&[&[u8]], &[SignatureScheme]) -> Option<Arc<sign::CertifiedKey>> + Send + Sync,
+{
+    /// Make a resolver that calls `resolve` when the server asks for a certificate.
+    ///
+    /// Return `None` from `resolve` to continue without client authentication.
+    pub fn new(resolve: F) -> Self {
+        Self { resolve }
+    }
+}
+
+impl<F> client::ResolvesClientCert for ResolvesClientCertUsingFn<F>
+where
+    F: Fn(&[&[u8]], &[SignatureScheme]) -> Option<Arc<sign::CertifiedKey>> + Send + Sync,
+{
+    fn resolve(
+        &self,
+        root_hint_subjects: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<sign::CertifiedKey>> {
+        (self.resolve)(root_hint_subjects, sigschemes)
+    }
+
+    fn has_certs(&self) -> bool {
+        // There is no way to know what the closure will return.
+        true
+    }
+}
+
+impl<F> Debug for ResolvesClientCertUsingFn<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResolvesClientCertUsingFn")
+            .finish_non_exhaustive()
+    }
+}
+
 test_for_each_provider! {
     use std::prelude::v1::*;
     use super::NoClientSessionStorage;
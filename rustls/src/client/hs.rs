@@ -9,28 +9,29 @@ use pki_types::ServerName;
 
 #[cfg(feature = "tls12")]
 use super::tls12;
-use super::Tls12Resumption;
-#[cfg(feature = "logging")]
+use super::{ResumptionDecision, Tls12Resumption};
 use crate::bs_debug;
 use crate::check::inappropriate_handshake_message;
 use crate::client::client_conn::ClientConnectionData;
-use crate::client::common::ClientHelloDetails;
+use crate::client::common::{is_grease, ClientHelloDetails, Grease};
 use crate::client::ech::EchState;
 use crate::client::{tls13, ClientConfig, EchMode, EchStatus};
 use crate::common_state::{CommonState, HandshakeKind, KxState, State};
 use crate::conn::ConnectionRandoms;
 use crate::crypto::{ActiveKeyExchange, KeyExchangeAlgorithm};
-use crate::enums::{AlertDescription, CipherSuite, ContentType, HandshakeType, ProtocolVersion};
+use crate::enums::{
+    AlertDescription, CipherSuite, ContentType, HandshakeType, MaxFragmentLength, ProtocolVersion,
+};
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHashBuffer;
 #[cfg(feature = "logging")]
-use crate::log::{debug, trace};
+use crate::log::trace;
 use crate::msgs::base::Payload;
 use crate::msgs::enums::{Compression, ECPointFormat, ExtensionType, PSKKeyExchangeMode};
 use crate::msgs::handshake::{
     CertificateStatusRequest, ClientExtension, ClientHelloPayload, ClientSessionTicket,
     ConvertProtocolNameList, HandshakeMessagePayload, HandshakePayload, HasServerExtensions,
-    HelloRetryRequest, KeyShareEntry, Random, SessionId,
+    HelloRetryRequest, KeyShareEntry, Random, SessionId, TrustAnchorId, UnknownExtension,
 };
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
@@ -46,6 +47,25 @@ fn find_session(
     config: &ClientConfig,
     cx: &mut ClientContext<'_>,
 ) -> Option<persist::Retrieved<ClientSessionValue>> {
+    if cx.data.without_resumption {
+        return None;
+    }
+
+    #[cfg(feature = "std")]
+    if config
+        .resumption
+        .first_connection_to(server_name)
+    {
+        diagnose!(
+            cx.common,
+            debug,
+            "Not resuming first connection to {:?}",
+            server_name
+        );
+        cx.data.resumption_decision = ResumptionDecision::FirstConnection;
+        return None;
+    }
+
     let found = config
         .resumption
         .store
@@ -54,11 +74,17 @@ fn find_session(
         .or_else(|| {
             #[cfg(feature = "tls12")]
             {
-                config
+                let session = config
                     .resumption
                     .store
-                    .tls12_session(server_name)
-                    .map(ClientSessionValue::Tls12)
+                    .tls12_session(server_name);
+                if session.is_some() && config.resumption.single_use_sessions {
+                    config
+                        .resumption
+                        .store
+                        .remove_tls12_session(server_name);
+                }
+                session.map(ClientSessionValue::Tls12)
             }
 
             #[cfg(not(feature = "tls12"))]
@@ -67,7 +93,7 @@ fn find_session(
         .and_then(|resuming| {
             let now = config
                 .current_time()
-                .map_err(|_err| debug!("Could not get current time: {_err}"))
+                .map_err(|_err| diagnose!(cx.common, debug, "Could not get current time: {_err}"))
                 .ok()?;
 
             let retrieved = persist::Retrieved::new(resuming, now);
@@ -77,11 +103,24 @@ fn find_session(
             }
         })
         .or_else(|| {
-            debug!("No cached session for {:?}", server_name);
+            diagnose!(cx.common, debug, "No cached session for {:?}", server_name);
             None
         });
 
     if let Some(resuming) = &found {
+        cx.data.resumption_decision = ResumptionDecision::Offered;
+        if let Some(window) = config.resumption.ticket_refresh_window {
+            if resuming.expires_within(window.as_secs()) {
+                diagnose!(
+                    cx.common,
+                    debug,
+                    "Cached session for {:?} is due for refresh",
+                    server_name
+                );
+                cx.data.ticket_refresh_due = true;
+            }
+        }
+
         if cx.common.is_quic() {
             cx.common.quic.params = resuming
                 .tls13()
@@ -111,6 +150,7 @@ pub(super) fn start_handshake(
     let key_share = if config.supports_version(ProtocolVersion::TLSv1_3) {
         Some(tls13::initial_key_share(
             &config,
+            cx.data,
             &server_name,
             &mut cx.common.kx_state,
         )?)
@@ -119,7 +159,7 @@ pub(super) fn start_handshake(
     };
 
     let session_id = if let Some(_resuming) = &mut resuming {
-        debug!("Resuming session");
+        diagnose!(cx.common, debug, "Resuming session");
 
         match &mut _resuming.value {
             #[cfg(feature = "tls12")]
@@ -135,7 +175,7 @@ pub(super) fn start_handshake(
             _ => None,
         }
     } else {
-        debug!("Not resuming any session");
+        diagnose!(cx.common, debug, "Not resuming any session");
         None
     };
 
@@ -150,9 +190,30 @@ pub(super) fn start_handshake(
 
     let random = Random::new(config.provider.secure_random)?;
     let extension_order_seed = crate::rand::random_u16(config.provider.secure_random)?;
+    let send_grease =
+        config.enable_grease || matches!(&config.hello_profile, Some(profile) if profile.grease());
+    let grease = match send_grease {
+        true => Some(Grease::new(config.provider.secure_random)?),
+        false => None,
+    };
 
-    let ech_state = match config.ech_mode.as_ref() {
-        Some(EchMode::Enable(ech_config)) => Some(EchState::new(
+    let resolved_ech_config = match (&config.ech_mode, &config.ech_resolver) {
+        (None, Some(resolver)) if config.supports_version(ProtocolVersion::TLSv1_3) => {
+            resolver.ech_config(&server_name)
+        }
+        _ => None,
+    };
+    if let Some(ech_config) = &resolved_ech_config {
+        cx.data.fips = cx.data.fips && ech_config.suite.fips();
+    }
+
+    let ech_config = match (config.ech_mode.as_ref(), resolved_ech_config.as_ref()) {
+        (Some(EchMode::Enable(ech_config)), _) | (None, Some(ech_config)) => Some(ech_config),
+        _ => None,
+    };
+
+    let ech_state = match ech_config {
+        Some(ech_config) => Some(EchState::new(
             ech_config,
             server_name.clone(),
             config
@@ -177,7 +238,7 @@ pub(super) fn start_handshake(
             #[cfg(feature = "tls12")]
             using_ems: false,
             sent_tls13_fake_ccs: false,
-            hello: ClientHelloDetails::new(extension_order_seed),
+            hello: ClientHelloDetails::new(extension_order_seed, grease),
             session_id,
             server_name,
             prev_ech_ext: None,
@@ -231,7 +292,17 @@ fn emit_client_hello_for_retry(
     let support_tls12 = config.supports_version(ProtocolVersion::TLSv1_2) && !forbids_tls12;
     let support_tls13 = config.supports_version(ProtocolVersion::TLSv1_3);
 
+    let grease = input.hello.grease;
     let mut supported_versions = Vec::new();
+    let mut named_groups = Vec::new();
+    if let Some(grease) = &grease {
+        let (version, group) = grease.version_and_group();
+        if support_tls13 {
+            supported_versions.push(version);
+        }
+        named_groups.push(group);
+    }
+
     if support_tls13 {
         supported_versions.push(ProtocolVersion::TLSv1_3);
     }
@@ -243,30 +314,59 @@ fn emit_client_hello_for_retry(
     // should be unreachable thanks to config builder
     assert!(!supported_versions.is_empty());
 
+    named_groups.extend(
+        cx.data
+            .kx_groups(config)
+            .map(|skxg| skxg.name()),
+    );
+
+    let profile = config.hello_profile.as_deref();
+    let mut signature_schemes = cx
+        .data
+        .verifier(config)
+        .supported_verify_schemes();
+    if let Some(profile) = profile {
+        profile.named_groups(&mut named_groups);
+        profile.signature_schemes(&mut signature_schemes);
+    }
+
+    // the group of the key share is always advertised, even if the profile removed it
+    if let Some(key_share) = &key_share {
+        if !named_groups.contains(&key_share.group()) {
+            named_groups.push(key_share.group());
+        }
+    }
+
+    // Also offer the classical component of a hybrid key share on its own, if that
+    // group is supported, so a server without the hybrid group need not retry.
+    // After a retry request, only the requested group is offered.
+    input.hello.offered_hybrid_component = match (&key_share, retryreq) {
+        (Some(key_share), None) => key_share
+            .hybrid_component()
+            .map(|(group, _)| group)
+            .filter(|group| {
+                *group != key_share.group()
+                    && cx
+                        .data
+                        .find_kx_group(config, *group)
+                        .is_some()
+                    && named_groups.contains(group)
+            }),
+        _ => None,
+    };
+
     let mut exts = vec![
         ClientExtension::SupportedVersions(supported_versions),
-        ClientExtension::NamedGroups(
-            config
-                .provider
-                .kx_groups
-                .iter()
-                .map(|skxg| skxg.name())
-                .collect(),
-        ),
-        ClientExtension::SignatureAlgorithms(
-            config
-                .verifier
-                .supported_verify_schemes(),
-        ),
+        ClientExtension::NamedGroups(named_groups),
+        ClientExtension::SignatureAlgorithms(signature_schemes),
         ClientExtension::ExtendedMasterSecretRequest,
         ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
     ];
 
     // Send the ECPointFormat extension only if we are proposing ECDHE
-    if config
-        .provider
-        .kx_groups
-        .iter()
+    if cx
+        .data
+        .kx_groups(config)
         .any(|skxg| skxg.name().key_exchange_algorithm() == KeyExchangeAlgorithm::ECDHE)
     {
         exts.push(ClientExtension::EcPointFormats(
@@ -274,6 +374,12 @@ fn emit_client_hello_for_retry(
         ));
     }
 
+    if let Some(max_fragment_length) = config.max_fragment_length {
+        if !cx.common.is_quic() {
+            exts.push(ClientExtension::MaxFragmentLength(max_fragment_length));
+        }
+    }
+
     match (ech_state.as_ref(), config.enable_sni) {
         // If we have ECH state we have a "cover name" to send in the outer hello
         // as the SNI domain name. This happens unconditionally so we ignore the
@@ -295,8 +401,14 @@ fn emit_client_hello_for_retry(
 
     if let Some(key_share) = &key_share {
         debug_assert!(support_tls13);
-        let key_share = KeyShareEntry::new(key_share.group(), key_share.pub_key());
-        exts.push(ClientExtension::KeyShare(vec![key_share]));
+        let mut shares = vec![KeyShareEntry::new(key_share.group(), key_share.pub_key())];
+        if let (Some(_), Some((group, share))) = (
+            input.hello.offered_hybrid_component,
+            key_share.hybrid_component(),
+        ) {
+            shares.push(KeyShareEntry::new(group, share));
+        }
+        exts.push(ClientExtension::KeyShare(shares));
     }
 
     if let Some(cookie) = retryreq.and_then(HelloRetryRequest::cookie) {
@@ -320,6 +432,16 @@ fn emit_client_hello_for_retry(
         )));
     }
 
+    if !config.trust_anchor_ids.is_empty() {
+        exts.push(ClientExtension::TrustAnchors(
+            config
+                .trust_anchor_ids
+                .iter()
+                .map(|id| TrustAnchorId::from(id.clone()))
+                .collect(),
+        ));
+    }
+
     input.hello.offered_cert_compression = if support_tls13 && !config.cert_decompressors.is_empty()
     {
         exts.push(ClientExtension::CertificateCompressionAlgorithms(
@@ -334,6 +456,26 @@ fn emit_client_hello_for_retry(
         false
     };
 
+    if let Some(grease) = &grease {
+        exts.extend(grease.extensions());
+    }
+
+    if let Some(profile) = profile {
+        for (typ, body) in profile.extra_extensions() {
+            let typ = ExtensionType::from(typ);
+            if exts
+                .iter()
+                .any(|ext| ext.ext_type() == typ)
+            {
+                continue;
+            }
+            exts.push(ClientExtension::Unknown(UnknownExtension {
+                typ,
+                payload: Payload::new(body),
+            }));
+        }
+    }
+
     // Extra extensions must be placed before the PSK extension
     exts.extend(extra_exts.iter().cloned());
 
@@ -351,36 +493,50 @@ fn emit_client_hello_for_retry(
 
     // Extensions MAY be randomized
     // but they also need to keep the same order as the previous ClientHello
+    let extension_order = profile.map_or(&[][..], |profile| profile.extension_order());
     exts.sort_by_cached_key(|new_ext| {
         match (&cx.data.ech_status, new_ext) {
             // When not offering ECH/GREASE, the PSK extension is always last.
-            (EchStatus::NotOffered, ClientExtension::PresharedKey(..)) => return u32::MAX,
+            (EchStatus::NotOffered, ClientExtension::PresharedKey(..)) => return (3, 0),
             // When ECH or GREASE are in-play, the ECH extension is always last.
-            (_, ClientExtension::EncryptedClientHello(_)) => return u32::MAX,
+            (_, ClientExtension::EncryptedClientHello(_)) => return (3, 0),
             // ... and the PSK extension should be second-to-last.
-            (_, ClientExtension::PresharedKey(..)) => return u32::MAX - 1,
+            (_, ClientExtension::PresharedKey(..)) => return (2, 0),
             _ => {}
         };
 
-        let seed = (input.hello.extension_order_seed as u32) << 16
-            | (u16::from(new_ext.ext_type()) as u32);
-        match low_quality_integer_hash(seed) {
-            u32::MAX => 0,
-            key => key,
+        // Extensions ordered by the profile come first.
+        let typ = u16::from(new_ext.ext_type());
+        if let Some(position) = extension_order
+            .iter()
+            .position(|ordered| *ordered == typ)
+        {
+            return (0, position as u32);
         }
+
+        let seed = (input.hello.extension_order_seed as u32) << 16 | (typ as u32);
+        (1, low_quality_integer_hash(seed))
     });
 
-    let mut cipher_suites: Vec<_> = config
-        .provider
-        .cipher_suites
+    let mut cipher_suites: Vec<_> = grease
         .iter()
-        .filter_map(|cs| match cs.usable_for_protocol(cx.common.protocol) {
-            true => Some(cs.suite()),
-            false => None,
-        })
+        .map(Grease::cipher_suite)
+        .chain(
+            config
+                .provider
+                .cipher_suites
+                .iter()
+                .filter_map(|cs| match cs.usable_for_protocol(cx.common.protocol) {
+                    true => Some(cs.suite()),
+                    false => None,
+                }),
+        )
         .collect();
     // We don't do renegotiation at all, in fact.
     cipher_suites.push(CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV);
+    if let Some(profile) = profile {
+        profile.cipher_suites(&mut cipher_suites);
+    }
 
     let mut chp_payload = ClientHelloPayload {
         client_version: ProtocolVersion::TLSv1_2,
@@ -409,6 +565,9 @@ fn emit_client_hello_for_retry(
         (EchStatus::NotOffered | EchStatus::Offered, Some(ech_state)) => {
             // Replace the client hello payload with an ECH client hello payload.
             chp_payload = ech_state.ech_hello(chp_payload, retryreq, &tls13_session)?;
+            if retryreq.is_none() {
+                ech_state.log_secrets(&*config.key_log, &chp_payload.random.0);
+            }
             cx.data.ech_status = EchStatus::Offered;
             // Store the ECH extension in case we need to carry it forward in a subsequent hello.
             input.prev_ech_ext = chp_payload.extensions.last().cloned();
@@ -436,6 +595,7 @@ fn emit_client_hello_for_retry(
         .extensions
         .iter()
         .map(ClientExtension::ext_type)
+        .filter(|ext_type| !is_grease(u16::from(*ext_type)))
         .collect();
 
     let mut chp = HandshakeMessagePayload {
@@ -488,6 +648,11 @@ fn emit_client_hello_for_retry(
 
     transcript_buffer.add_message(&ch);
     cx.common.send_msg(ch, false);
+    cx.common
+        .note_client_random(match &ech_state {
+            Some(ech_state) => &ech_state.inner_hello_random.0,
+            None => &input.random.0,
+        });
 
     // Calculate the hash of ClientHello and use it to derive EarlyTrafficSecret
     let early_key_schedule = early_key_schedule.map(|(resuming_suite, schedule)| {
@@ -558,7 +723,7 @@ fn prepare_resumption<'a>(
         Some(resuming) if !resuming.ticket().is_empty() => resuming,
         _ => {
             if config.supports_version(ProtocolVersion::TLSv1_3)
-                || config.resumption.tls12_resumption == Tls12Resumption::SessionIdOrTickets
+                || cx.data.tls12_resumption(config) == Tls12Resumption::SessionIdOrTickets
             {
                 // If we don't have a ticket, request one.
                 exts.push(ClientExtension::SessionTicket(ClientSessionTicket::Request));
@@ -572,7 +737,7 @@ fn prepare_resumption<'a>(
         None => {
             // TLS 1.2; send the ticket if we have support this protocol version
             if config.supports_version(ProtocolVersion::TLSv1_2)
-                && config.resumption.tls12_resumption == Tls12Resumption::SessionIdOrTickets
+                && cx.data.tls12_resumption(config) == Tls12Resumption::SessionIdOrTickets
             {
                 exts.push(ClientExtension::SessionTicket(ClientSessionTicket::Offer(
                     Payload::new(resuming.ticket()),
@@ -603,6 +768,31 @@ fn prepare_resumption<'a>(
     Some(tls13)
 }
 
+pub(super) fn process_max_fragment_length(
+    common: &mut CommonState,
+    config: &ClientConfig,
+    max_fragment_length: Option<MaxFragmentLength>,
+) -> Result<(), Error> {
+    if let Some(max_fragment_length) = max_fragment_length {
+        if Some(max_fragment_length) != config.max_fragment_length {
+            return Err(common.send_fatal_alert(
+                AlertDescription::IllegalParameter,
+                PeerMisbehaved::SelectedDifferentMaxFragmentLength,
+            ));
+        }
+
+        diagnose!(
+            common,
+            debug,
+            "Max fragment length is {:?}",
+            max_fragment_length
+        );
+        common.limit_fragment_len(max_fragment_length);
+    }
+
+    Ok(())
+}
+
 pub(super) fn process_alpn_protocol(
     common: &mut CommonState,
     config: &ClientConfig,
@@ -635,7 +825,9 @@ pub(super) fn process_alpn_protocol(
         ));
     }
 
-    debug!(
+    diagnose!(
+        common,
+        debug,
         "ALPN protocol is {:?}",
         common
             .alpn_protocol
@@ -737,6 +929,11 @@ impl State<ClientConnectionData> for ExpectServerHello {
         // Extract ALPN protocol
         if !cx.common.is_tls13() {
             process_alpn_protocol(cx.common, config, server_hello.alpn_protocol())?;
+            process_max_fragment_length(
+                cx.common,
+                config,
+                server_hello.max_fragment_length_extension(),
+            )?;
         }
 
         // If ECPointFormats extension is supplied by the server, it must contain
@@ -778,7 +975,7 @@ impl State<ClientConnectionData> for ExpectServerHello {
                 });
             }
             _ => {
-                debug!("Using ciphersuite {:?}", suite);
+                diagnose!(cx.common, debug, "Using ciphersuite {:?}", suite);
                 self.suite = Some(suite);
                 cx.common.suite = Some(suite);
             }
@@ -791,6 +988,8 @@ impl State<ClientConnectionData> for ExpectServerHello {
         transcript.add_message(&m);
 
         let randoms = ConnectionRandoms::new(self.input.random, server_hello.random);
+        cx.common
+            .note_client_random(&randoms.client);
         // For TLS1.3, start message encryption using
         // handshake_traffic_secret.
         match suite {
@@ -878,7 +1077,16 @@ impl ExpectServerHelloOrHelloRetryRequest {
 
         // A retry request is illegal if it contains no cookie and asks for
         // retry of a group we already sent.
-        if cookie.is_none() && req_group == Some(offered_key_share.group()) {
+        if cookie.is_none()
+            && req_group.is_some()
+            && (req_group == Some(offered_key_share.group())
+                || req_group
+                    == self
+                        .next
+                        .input
+                        .hello
+                        .offered_hybrid_component)
+        {
             return Err({
                 cx.common.send_fatal_alert(
                     AlertDescription::IllegalParameter,
@@ -1029,7 +1237,7 @@ impl ExpectServerHelloOrHelloRetryRequest {
 
         let key_share = match req_group {
             Some(group) if group != offered_key_share.group() => {
-                let skxg = match config.find_kx_group(group) {
+                let skxg = match cx.data.find_kx_group(config, group) {
                     Some(skxg) => skxg,
                     None => {
                         return Err(cx.common.send_fatal_alert(
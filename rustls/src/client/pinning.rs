@@ -0,0 +1,232 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt::{self, Debug, Formatter};
+
+use pki_types::{CertificateDer, ServerName, UnixTime};
+
+use crate::crypto::hash;
+use crate::enums::SignatureScheme;
+use crate::error::{CertificateError, Error};
+#[cfg(feature = "logging")]
+use crate::log::{debug, warn};
+use crate::verify::{
+    DigitallySignedStruct, HandshakeSignatureValid, RevocationStatus, ServerCertVerified,
+    ServerCertVerifier,
+};
+use crate::webpki::ParsedCertificate;
+
+/// Receives reports of server certificate chains that failed to match their pins.
+///
+/// Install one of these with [`SpkiPinningVerifier::with_reporter()`].
+pub trait ReportsPinFailures: Debug + Send + Sync {
+    /// Called when the certificate chain presented by a server did not match
+    /// any of the expected pins.
+    ///
+    /// This is called synchronously during the handshake, so should return promptly.
+    fn pin_failure(&self, report: &PinFailureReport);
+}
+
+/// A report of a server certificate chain that did not match its pins.
+///
+/// This is delivered to a [`ReportsPinFailures`].
+#[non_exhaustive]
+#[derive(Clone, Debug)]
+pub struct PinFailureReport {
+    /// The name of the server being verified.
+    pub server_name: ServerName<'static>,
+    /// The expected pins: hashes of a DER-encoded SubjectPublicKeyInfo.
+    pub expected_pins: Vec<Vec<u8>>,
+    /// The certificates presented by the server, end-entity first.
+    pub presented_chain: Vec<CertificateDer<'static>>,
+    /// The time of verification.
+    pub time: UnixTime,
+    /// Whether the handshake was aborted as a result.
+    ///
+    /// This is false if the verifier is in report-only mode.
+    pub enforced: bool,
+}
+
+/// A [`ServerCertVerifier`] that additionally requires the server's chain to match
+/// a set of public key pins.
+///
+/// Each pin is the hash of a DER-encoded SubjectPublicKeyInfo, in the style of
+/// HPKP's `pin-sha256`.  A chain matches if any certificate it contains has a pinned
+/// public key.  Pins are only checked once the inner verifier has accepted the chain.
+///
+/// Failures can be delivered to a [`ReportsPinFailures`], whether or not they are
+/// enforced.
+pub struct SpkiPinningVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    hash: &'static dyn hash::Hash,
+    pins: Vec<Vec<u8>>,
+    enforce: bool,
+    reporter: Option<Arc<dyn ReportsPinFailures>>,
+}
+
+impl SpkiPinningVerifier {
+    /// Verify chains with `inner`, and then require them to match one of `pins`.
+    ///
+    /// `pins` are computed with `hash`.
+    pub fn new(
+        inner: Arc<dyn ServerCertVerifier>,
+        hash: &'static dyn hash::Hash,
+        pins: Vec<Vec<u8>>,
+    ) -> Self {
+        Self {
+            inner,
+            hash,
+            pins,
+            enforce: true,
+            reporter: None,
+        }
+    }
+
+    /// Deliver pin failures to `reporter`.
+    pub fn with_reporter(mut self, reporter: Arc<dyn ReportsPinFailures>) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    /// Report pin failures, but accept the chain anyway.
+    pub fn report_only(mut self) -> Self {
+        self.enforce = false;
+        self
+    }
+
+    fn matches_pin(&self, cert: &CertificateDer<'_>) -> bool {
+        let spki = match ParsedCertificate::try_from(cert) {
+            Ok(parsed) => parsed.subject_public_key_info(),
+            Err(_) => return false,
+        };
+
+        let hashed = self.hash.hash(spki.as_ref());
+        self.pins
+            .iter()
+            .any(|pin| pin.as_slice() == hashed.as_ref())
+    }
+}
+
+impl Debug for SpkiPinningVerifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpkiPinningVerifier")
+            .field("inner", &self.inner)
+            .field("hash", &self.hash.algorithm())
+            .field("pins", &self.pins)
+            .field("enforce", &self.enforce)
+            .field("reporter", &self.reporter)
+            .finish()
+    }
+}
+
+impl ServerCertVerifier for SpkiPinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.verify_server_cert_with_user_data(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+            None,
+        )
+    }
+
+    fn verify_server_cert_with_user_data(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+        user_data: Option<&(dyn Any + Send)>,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self
+            .inner
+            .verify_server_cert_with_user_data(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+                user_data,
+            )?;
+
+        if self.matches_pin(end_entity)
+            || intermediates
+                .iter()
+                .any(|cert| self.matches_pin(cert))
+        {
+            return Ok(verified);
+        }
+
+        if let Some(reporter) = &self.reporter {
+            reporter.pin_failure(&PinFailureReport {
+                server_name: server_name.to_owned(),
+                expected_pins: self.pins.clone(),
+                presented_chain: core::iter::once(end_entity)
+                    .chain(intermediates)
+                    .map(|cert| cert.clone().into_owned())
+                    .collect(),
+                time: now,
+                enforced: self.enforce,
+            });
+        }
+
+        match self.enforce {
+            true => {
+                warn!("Server certificate chain for {server_name:?} matched no pins");
+                Err(Error::InvalidCertificate(
+                    CertificateError::ApplicationVerificationFailure,
+                ))
+            }
+            false => {
+                debug!(
+                    "Server certificate chain for {server_name:?} matched no pins; not enforced"
+                );
+                Ok(verified)
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn check_stapled_ocsp(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        ocsp_responses: &[&[u8]],
+        now: UnixTime,
+    ) -> Result<Vec<RevocationStatus>, Error> {
+        self.inner
+            .check_stapled_ocsp(end_entity, intermediates, ocsp_responses, now)
+    }
+}
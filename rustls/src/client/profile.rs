@@ -0,0 +1,71 @@
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use crate::enums::{CipherSuite, SignatureScheme};
+use crate::msgs::enums::NamedGroup;
+
+/// Shapes the `ClientHello` messages sent by a client.
+///
+/// A profile can make the `ClientHello` sent by rustls resemble that of another
+/// well-known client, so that both have the same fingerprint (for example, under
+/// JA3 or JA4).  This is useful for measurement tools, and for applications that
+/// must avoid being singled out by censors.
+///
+/// A profile only changes what is advertised: rustls still only negotiates the
+/// protocol versions, cipher suites, key exchange groups and signature schemes it
+/// was configured with, and aborts the handshake if the server chooses anything else.
+///
+/// Install one with [`ClientConfig::hello_profile`].  Every method has a default
+/// that leaves the `ClientHello` unchanged.  Profiles should be deterministic, so
+/// that a `ClientHello` sent in response to a `HelloRetryRequest` is consistent
+/// with the first.
+///
+/// [`ClientConfig::hello_profile`]: crate::ClientConfig::hello_profile
+pub trait ShapesClientHello: Debug + Send + Sync {
+    /// Whether to send GREASE values.
+    ///
+    /// These are sent if this returns true or [`ClientConfig::enable_grease`] is set.
+    ///
+    /// [`ClientConfig::enable_grease`]: crate::ClientConfig::enable_grease
+    fn grease(&self) -> bool {
+        false
+    }
+
+    /// The order in which to send extensions, as a list of extension type code points.
+    ///
+    /// Extensions with a listed type are sent first, in the order listed; other
+    /// extensions follow in a random order.  Regardless of this, the `pre_shared_key`
+    /// and `encrypted_client_hello` extensions are always sent last, as the protocol
+    /// requires.
+    fn extension_order(&self) -> &[u16] {
+        &[]
+    }
+
+    /// Adjust the cipher suites sent in the `ClientHello`.
+    ///
+    /// `suites` initially contains the usable cipher suites of the crypto provider,
+    /// in preference order, followed by `TLS_EMPTY_RENEGOTIATION_INFO_SCSV`.
+    fn cipher_suites(&self, _suites: &mut Vec<CipherSuite>) {}
+
+    /// Adjust the key exchange groups sent in the `supported_groups` extension.
+    ///
+    /// `groups` initially contains the groups of the crypto provider, in preference
+    /// order.  rustls sends its first key share for the first of the crypto provider's
+    /// groups that remains after this.  If none remain, or a `HelloRetryRequest` asks
+    /// for a removed group, the group of the key share is added back at the end.
+    fn named_groups(&self, _groups: &mut Vec<NamedGroup>) {}
+
+    /// Adjust the signature schemes sent in the `signature_algorithms` extension.
+    ///
+    /// `schemes` initially contains the schemes supported by the server certificate
+    /// verifier.
+    fn signature_schemes(&self, _schemes: &mut Vec<SignatureScheme>) {}
+
+    /// Additional extensions to send, as pairs of an extension type and its encoded body.
+    ///
+    /// These are sent as-is, and anything the server sends in response to them is
+    /// ignored.  Extensions with a type that rustls already sends are skipped.
+    fn extra_extensions(&self) -> Vec<(u16, Vec<u8>)> {
+        Vec::new()
+    }
+}
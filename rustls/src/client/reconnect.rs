@@ -0,0 +1,116 @@
+use alloc::sync::Arc;
+
+use super::ClientConfig;
+use crate::crypto::CryptoProvider;
+use crate::enums::AlertDescription;
+use crate::error::Error;
+
+/// Decides whether, and how, to retry a client handshake that failed.
+///
+/// Some servers and middleboxes fail handshakes for reasons unrelated to
+/// security: for example, by rejecting a `ClientHello` that carries early data,
+/// or a large post-quantum key share. This helper codifies retrying those
+/// handshakes with adjusted parameters, in this order:
+///
+/// 1. if [`ClientConfig::enable_early_data`] is set, it is cleared;
+/// 2. otherwise, the next of the provider's key exchange groups is used for
+///    the initial key share (all groups remain offered).
+///
+/// It never retries in a way that would weaken the connection: the protocol
+/// versions, cipher suites, certificate verifier and ECH configuration are
+/// never changed. Errors that may indicate an attack -- such as certificate
+/// errors and any [`PeerMisbehaved`] error, which includes detected downgrades --
+/// are never retried.
+///
+/// Each attempt must use a fresh transport connection.
+///
+/// ```no_run
+/// # fn connect(config: &std::sync::Arc<rustls::ClientConfig>) -> Result<(), rustls::Error> { Ok(()) }
+/// # fn example(config: std::sync::Arc<rustls::ClientConfig>) -> Result<(), rustls::Error> {
+/// let mut policy = rustls::client::ReconnectPolicy::new(config);
+/// loop {
+///     match connect(policy.config()) {
+///         Ok(()) => break Ok(()),
+///         Err(err) => {
+///             if policy.failed(&err).is_none() {
+///                 break Err(err);
+///             }
+///         }
+///     }
+/// }
+/// # }
+/// ```
+///
+/// [`PeerMisbehaved`]: crate::PeerMisbehaved
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    config: Arc<ClientConfig>,
+    attempts: usize,
+    max_attempts: usize,
+    key_share_rotations: usize,
+}
+
+impl ReconnectPolicy {
+    /// Start with `config`, allowing three attempts in total.
+    pub fn new(config: Arc<ClientConfig>) -> Self {
+        Self {
+            config,
+            attempts: 1,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            key_share_rotations: 0,
+        }
+    }
+
+    /// Set the maximum number of attempts, including the first.
+    pub fn with_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// The configuration to use for the next attempt.
+    pub fn config(&self) -> &Arc<ClientConfig> {
+        &self.config
+    }
+
+    /// Report that the attempt using [`ReconnectPolicy::config()`] failed with `error`.
+    ///
+    /// Returns the configuration to retry with, or `None` if the handshake should
+    /// not be retried.
+    pub fn failed(&mut self, error: &Error) -> Option<Arc<ClientConfig>> {
+        if self.attempts >= self.max_attempts || !is_retryable(error) {
+            return None;
+        }
+
+        let mut config = (*self.config).clone();
+        if config.enable_early_data {
+            config.enable_early_data = false;
+        } else if self.key_share_rotations + 1 < config.provider.kx_groups.len() {
+            let mut provider = CryptoProvider::clone(&config.provider);
+            provider.kx_groups.rotate_left(1);
+            config.provider = Arc::new(provider);
+            self.key_share_rotations += 1;
+        } else {
+            return None;
+        }
+
+        self.attempts += 1;
+        self.config = Arc::new(config);
+        Some(Arc::clone(&self.config))
+    }
+}
+
+/// Whether `error` is one a well-behaved but intolerant peer might cause.
+fn is_retryable(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::AlertReceived(
+            AlertDescription::HandshakeFailure
+                | AlertDescription::IllegalParameter
+                | AlertDescription::DecodeError
+                | AlertDescription::UnexpectedMessage
+                | AlertDescription::UnsupportedExtension
+        )
+    )
+}
+
+const DEFAULT_MAX_ATTEMPTS: usize = 3;
@@ -0,0 +1,384 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use pki_types::EchConfigListBytes;
+
+use super::{ClientConfig, EchConfig, EchMode};
+use crate::crypto::hpke::Hpke;
+use crate::enums::ProtocolVersion;
+use crate::error::Error;
+use crate::msgs::codec::{Codec, Reader};
+
+/// Service binding information for a server, from a DNS `HTTPS` or `SVCB` resource record.
+///
+/// See [RFC9460] for the meaning of these records.  Use [`ServiceBinding::apply_to()`]
+/// to make a [`ClientConfig`] that is consistent with the record: offering only the
+/// ALPN protocols the service supports, and using ECH if the service offers it.
+///
+/// Resolving the record, following `AliasMode` records, and connecting to the
+/// [`ServiceBinding::port()`] are left to the caller.
+///
+/// [RFC9460]: https://www.rfc-editor.org/rfc/rfc9460
+#[derive(Clone, Debug)]
+pub struct ServiceBinding {
+    priority: u16,
+    target_name: String,
+    alpn: Vec<Vec<u8>>,
+    no_default_alpn: bool,
+    port: Option<u16>,
+    ech_config_list: Option<EchConfigListBytes<'static>>,
+}
+
+impl ServiceBinding {
+    /// Parse the RDATA of a `HTTPS` or `SVCB` resource record.
+    ///
+    /// The target name must not use DNS name compression, as required by RFC9460.
+    pub fn from_rdata(rdata: &[u8]) -> Result<Self, ServiceBindingError> {
+        let mut r = Reader::init(rdata);
+        let priority = u16::read(&mut r).map_err(|_| ServiceBindingError::Truncated)?;
+        let target_name = read_target_name(&mut r)?;
+
+        let mut binding = Self {
+            priority,
+            target_name,
+            alpn: Vec::new(),
+            no_default_alpn: false,
+            port: None,
+            ech_config_list: None,
+        };
+
+        // https://www.rfc-editor.org/rfc/rfc9460#section-2.4.2
+        if binding.is_alias_mode() {
+            // recipients MUST ignore any SvcParams present
+            return Ok(binding);
+        }
+
+        let mut mandatory = Vec::new();
+        let mut last_key = None;
+        while r.any_left() {
+            let key = u16::read(&mut r).map_err(|_| ServiceBindingError::Truncated)?;
+            let len = u16::read(&mut r).map_err(|_| ServiceBindingError::Truncated)?;
+            let value = r
+                .take(usize::from(len))
+                .ok_or(ServiceBindingError::Truncated)?;
+
+            if matches!(last_key, Some(last) if last >= key) {
+                return Err(ServiceBindingError::UnsortedKeys);
+            }
+            last_key = Some(key);
+
+            match key {
+                KEY_MANDATORY => {
+                    let mut keys = Reader::init(value);
+                    while keys.any_left() {
+                        mandatory.push(
+                            u16::read(&mut keys).map_err(|_| ServiceBindingError::Truncated)?,
+                        );
+                    }
+                }
+                KEY_ALPN => {
+                    let mut ids = Reader::init(value);
+                    while ids.any_left() {
+                        let id = match ids.take(1) {
+                            Some(&[len]) if len > 0 => ids.take(usize::from(len)),
+                            _ => None,
+                        };
+                        binding.alpn.push(
+                            id.ok_or(ServiceBindingError::InvalidAlpn)?
+                                .to_vec(),
+                        );
+                    }
+                    if binding.alpn.is_empty() {
+                        return Err(ServiceBindingError::InvalidAlpn);
+                    }
+                }
+                KEY_NO_DEFAULT_ALPN if value.is_empty() => binding.no_default_alpn = true,
+                KEY_PORT => match value {
+                    &[hi, lo] => binding.port = Some(u16::from_be_bytes([hi, lo])),
+                    _ => return Err(ServiceBindingError::InvalidPort),
+                },
+                KEY_ECH => binding.ech_config_list = Some(EchConfigListBytes::from(value.to_vec())),
+                KEY_NO_DEFAULT_ALPN => return Err(ServiceBindingError::Truncated),
+                _ => {}
+            }
+        }
+
+        // https://www.rfc-editor.org/rfc/rfc9460#section-8
+        if let Some(&key) = mandatory
+            .iter()
+            .find(|key| !UNDERSTOOD_KEYS.contains(key))
+        {
+            return Err(ServiceBindingError::UnsupportedMandatoryKey(key));
+        }
+
+        Ok(binding)
+    }
+
+    /// The record's `SvcPriority`.  Zero means this is an `AliasMode` record.
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    /// Whether this is an `AliasMode` record.
+    ///
+    /// These only name another domain to resolve, in [`ServiceBinding::target_name()`],
+    /// so have no service parameters and cannot be used with [`ServiceBinding::apply_to()`].
+    pub fn is_alias_mode(&self) -> bool {
+        self.priority == 0
+    }
+
+    /// The record's `TargetName`, without a trailing dot.
+    ///
+    /// This is empty if the target is the owner name of the record.
+    pub fn target_name(&self) -> &str {
+        &self.target_name
+    }
+
+    /// The ALPN protocol identifiers the service supports, in addition to the default
+    /// protocol (unless [`ServiceBinding::no_default_alpn()`]).
+    pub fn alpn(&self) -> &[Vec<u8>] {
+        &self.alpn
+    }
+
+    /// Whether the service does not support the default protocol.
+    pub fn no_default_alpn(&self) -> bool {
+        self.no_default_alpn
+    }
+
+    /// The port to connect to, if different from the default.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// The service's encoded `ECHConfigList`, if it supports ECH.
+    pub fn ech_config_list(&self) -> Option<&EchConfigListBytes<'static>> {
+        self.ech_config_list.as_ref()
+    }
+
+    /// Make a copy of `config` that is consistent with this service binding.
+    ///
+    /// [`ClientConfig::alpn_protocols`] is reduced to the protocols this service supports;
+    /// `default_alpn` names the protocol the service supports unless
+    /// [`ServiceBinding::no_default_alpn()`] is set (for example, `http/1.1` for `HTTPS` records).
+    /// If `config` had ALPN protocols but none are supported, [`Error::NoApplicationProtocol`]
+    /// is returned and this service binding should be skipped.
+    ///
+    /// If the service offers ECH, the copy uses it with [`EchMode::Enable`], choosing an
+    /// ECH configuration compatible with `hpke_suites`.
+    ///
+    /// This fails with [`ServiceBindingError::AliasMode`] for `AliasMode` records, which
+    /// must be followed to a `ServiceMode` record first, and with
+    /// [`ServiceBindingError::EchRequiresTls13`] if the service offers ECH but `config`
+    /// does not support TLS1.3.
+    pub fn apply_to(
+        &self,
+        config: &ClientConfig,
+        default_alpn: &[u8],
+        hpke_suites: &[&'static dyn Hpke],
+    ) -> Result<ClientConfig, Error> {
+        if self.is_alias_mode() {
+            return Err(ServiceBindingError::AliasMode.into());
+        }
+
+        let mut config = config.clone();
+
+        if !config.alpn_protocols.is_empty() {
+            config.alpn_protocols.retain(|proto| {
+                self.alpn.contains(proto) || (!self.no_default_alpn && proto == default_alpn)
+            });
+            if config.alpn_protocols.is_empty() {
+                return Err(Error::NoApplicationProtocol);
+            }
+        }
+
+        if let Some(ech_config_list) = &self.ech_config_list {
+            if !config.supports_version(ProtocolVersion::TLSv1_3) {
+                return Err(ServiceBindingError::EchRequiresTls13.into());
+            }
+            let ech_config = EchConfig::new(ech_config_list.clone(), hpke_suites)?;
+            config.ech_mode = Some(EchMode::Enable(ech_config));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Read an uncompressed DNS name in wire format.
+fn read_target_name(r: &mut Reader<'_>) -> Result<String, ServiceBindingError> {
+    let mut name = String::new();
+    loop {
+        let len = match r.take(1) {
+            Some(&[len]) => usize::from(len),
+            _ => return Err(ServiceBindingError::Truncated),
+        };
+        if len == 0 {
+            return Ok(name);
+        }
+        if len > 63 {
+            return Err(ServiceBindingError::InvalidTargetName);
+        }
+
+        let label = r
+            .take(len)
+            .ok_or(ServiceBindingError::Truncated)?;
+        if !label.is_ascii() {
+            return Err(ServiceBindingError::InvalidTargetName);
+        }
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.extend(label.iter().map(|&b| char::from(b)));
+    }
+}
+
+/// Errors that may arise when parsing a [`ServiceBinding`].
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceBindingError {
+    /// The record is truncated or a value has the wrong length.
+    Truncated,
+    /// The target name is not a valid uncompressed DNS name.
+    InvalidTargetName,
+    /// The `SvcParamKeys` are not in strictly increasing order.
+    UnsortedKeys,
+    /// The `alpn` value is malformed.
+    InvalidAlpn,
+    /// The `port` value is malformed.
+    InvalidPort,
+    /// The record requires support for a `SvcParamKey` that we don't understand.
+    UnsupportedMandatoryKey(u16),
+    /// The record is in `AliasMode`, where a `ServiceMode` record is required.
+    AliasMode,
+    /// The service offers ECH, but the [`ClientConfig`] does not support TLS1.3.
+    EchRequiresTls13,
+}
+
+impl fmt::Display for ServiceBindingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => f.write_str("service binding record is truncated"),
+            Self::InvalidTargetName => f.write_str("service binding has invalid target name"),
+            Self::UnsortedKeys => f.write_str("service binding parameters are not sorted"),
+            Self::InvalidAlpn => f.write_str("service binding has invalid alpn parameter"),
+            Self::InvalidPort => f.write_str("service binding has invalid port parameter"),
+            Self::UnsupportedMandatoryKey(key) => {
+                write!(
+                    f,
+                    "service binding requires unsupported parameter key {key}"
+                )
+            }
+            Self::AliasMode => f.write_str("service binding is in alias mode"),
+            Self::EchRequiresTls13 => {
+                f.write_str("service binding offers ECH, which requires TLS1.3")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ServiceBindingError {}
+
+impl From<ServiceBindingError> for Error {
+    #[inline]
+    fn from(e: ServiceBindingError) -> Self {
+        Self::InvalidServiceBinding(e)
+    }
+}
+
+const KEY_MANDATORY: u16 = 0;
+const KEY_ALPN: u16 = 1;
+const KEY_NO_DEFAULT_ALPN: u16 = 2;
+const KEY_PORT: u16 = 3;
+const KEY_IPV4_HINT: u16 = 4;
+const KEY_ECH: u16 = 5;
+const KEY_IPV6_HINT: u16 = 6;
+
+/// Keys we can honour if they are mandatory.  Address hints are only hints, so
+/// ignoring them is compliant.
+const UNDERSTOOD_KEYS: &[u16] = &[
+    KEY_ALPN,
+    KEY_NO_DEFAULT_ALPN,
+    KEY_PORT,
+    KEY_IPV4_HINT,
+    KEY_ECH,
+    KEY_IPV6_HINT,
+];
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn parses_rfc9460_example() {
+        // https://www.rfc-editor.org/rfc/rfc9460#appendix-D.2 figure 6
+        // "16 foo.example.org. (alpn=h2,h3-19 mandatory=ipv4hint,alpn ipv4hint=192.0.2.1)"
+        let rdata = b"\x00\x10\x03foo\x07example\x03org\x00\
+                      \x00\x00\x00\x04\x00\x01\x00\x04\
+                      \x00\x01\x00\x09\x02h2\x05h3-19\
+                      \x00\x04\x00\x04\xc0\x00\x02\x01";
+        let binding = ServiceBinding::from_rdata(rdata).unwrap();
+        assert_eq!(binding.priority(), 16);
+        assert_eq!(binding.target_name(), "foo.example.org");
+        assert_eq!(binding.alpn(), &[b"h2".to_vec(), b"h3-19".to_vec()]);
+        assert!(!binding.no_default_alpn());
+        assert_eq!(binding.port(), None);
+        assert!(binding.ech_config_list().is_none());
+    }
+
+    #[test]
+    fn parses_port_and_ech() {
+        let rdata = b"\x00\x01\x00\
+                      \x00\x02\x00\x00\
+                      \x00\x03\x00\x02\x01\xbb\
+                      \x00\x05\x00\x03\x01\x02\x03";
+        let binding = ServiceBinding::from_rdata(rdata).unwrap();
+        assert_eq!(binding.target_name(), "");
+        assert!(binding.no_default_alpn());
+        assert_eq!(binding.port(), Some(443));
+        assert_eq!(
+            binding
+                .ech_config_list()
+                .map(|ech| ech.to_vec()),
+            Some(vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn alias_mode_ignores_parameters() {
+        // "0 svc.example.net. (port=q)", with a malformed port
+        let rdata = b"\x00\x00\x03svc\x07example\x03net\x00\
+                      \x00\x03\x00\x01q";
+        let binding = ServiceBinding::from_rdata(rdata).unwrap();
+        assert!(binding.is_alias_mode());
+        assert_eq!(binding.target_name(), "svc.example.net");
+        assert_eq!(binding.port(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_records() {
+        for (rdata, err) in [
+            (&b"\x00"[..], ServiceBindingError::Truncated),
+            (
+                b"\x00\x01\x00\x00\x03\x00\x01\x00",
+                ServiceBindingError::InvalidPort,
+            ),
+            (
+                b"\x00\x01\x00\x00\x03\x00\x02\x01\xbb\x00\x01\x00\x03\x02h2",
+                ServiceBindingError::UnsortedKeys,
+            ),
+            (
+                b"\x00\x01\x00\x00\x01\x00\x01\x00",
+                ServiceBindingError::InvalidAlpn,
+            ),
+            (
+                b"\x00\x01\x00\x00\x00\x00\x02\x00\x07\x00\x07\x00\x00",
+                ServiceBindingError::UnsupportedMandatoryKey(7),
+            ),
+        ] {
+            assert_eq!(ServiceBinding::from_rdata(rdata).unwrap_err(), err);
+        }
+    }
+}
@@ -3,24 +3,25 @@ use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::time::Duration;
 
 use pki_types::ServerName;
 pub(super) use server_hello::CompleteServerHelloHandling;
 use subtle::ConstantTimeEq;
 
-use super::client_conn::ClientConnectionData;
+use super::client_conn::{ClientConnectionData, DowngradeSentinel};
 use super::hs::ClientContext;
 use crate::check::{inappropriate_handshake_message, inappropriate_message};
 use crate::client::common::{ClientAuthDetails, ServerCertDetails};
-use crate::client::{hs, ClientConfig};
-use crate::common_state::{CommonState, HandshakeKind, KxState, Side, State};
+use crate::client::{hs, ClientConfig, ReceivedTicket};
+use crate::common_state::{CommonState, HandshakeKind, KxState, ResumptionKind, Side, State};
 use crate::conn::ConnectionRandoms;
 use crate::crypto::KeyExchangeAlgorithm;
 use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
 use crate::error::{Error, InvalidMessage, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHash;
 #[cfg(feature = "logging")]
-use crate::log::{debug, trace, warn};
+use crate::log::trace;
 use crate::msgs::base::{Payload, PayloadU16, PayloadU8};
 use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::msgs::handshake::{
@@ -64,18 +65,46 @@ mod server_hello {
             // Look for TLS1.3 downgrade signal in server random
             // both the server random and TLS12_DOWNGRADE_SENTINEL are
             // public values and don't require constant time comparison
-            let has_downgrade_marker = self.randoms.server[24..] == tls12::DOWNGRADE_SENTINEL;
-            if tls13_supported && has_downgrade_marker {
-                return Err({
-                    cx.common.send_fatal_alert(
-                        AlertDescription::IllegalParameter,
-                        PeerMisbehaved::AttemptedDowngradeToTls12WhenTls13IsSupported,
-                    )
-                });
+            let downgrade_sentinel = match &self.randoms.server[24..] {
+                marker if marker == tls12::DOWNGRADE_SENTINEL => Some(DowngradeSentinel::Tls12),
+                marker if marker == tls12::DOWNGRADE_SENTINEL_TLS11 => {
+                    Some(DowngradeSentinel::Tls11OrBelow)
+                }
+                _ => None,
+            };
+            if let Some(sentinel) = downgrade_sentinel {
+                if tls13_supported {
+                    return Err({
+                        cx.common.send_fatal_alert(
+                            AlertDescription::IllegalParameter,
+                            PeerMisbehaved::AttemptedDowngradeToTls12WhenTls13IsSupported,
+                        )
+                    });
+                }
+
+                // We didn't offer TLS1.3, so cannot distinguish an attack from
+                // a server that merely supports it.
+                if self.config.strict_downgrade_protection {
+                    return Err({
+                        cx.common.send_fatal_alert(
+                            AlertDescription::IllegalParameter,
+                            PeerMisbehaved::UnexpectedDowngradeSentinel,
+                        )
+                    });
+                }
+
+                diagnose!(
+                    cx.common,
+                    debug,
+                    "Server sent downgrade sentinel {:?}",
+                    sentinel
+                );
+                cx.data.downgrade_sentinel = Some(sentinel);
             }
 
             // Doing EMS?
             self.using_ems = server_hello.ems_support_acked();
+            cx.common.using_ems = self.using_ems;
             if self.config.require_ems && !self.using_ems {
                 return Err({
                     cx.common.send_fatal_alert(
@@ -90,7 +119,7 @@ mod server_hello {
                 .find_extension(ExtensionType::SessionTicket)
                 .is_some()
             {
-                debug!("Server supports tickets");
+                diagnose!(cx.common, debug, "Server supports tickets");
                 true
             } else {
                 false
@@ -102,13 +131,13 @@ mod server_hello {
                 .find_extension(ExtensionType::StatusRequest)
                 .is_some();
             if may_send_cert_status {
-                debug!("Server may staple OCSP response");
+                diagnose!(cx.common, debug, "Server may staple OCSP response");
             }
 
             // See if we're successfully resuming.
             if let Some(resuming) = self.resuming_session {
                 if resuming.session_id == server_hello.session_id {
-                    debug!("Server agreed to resume");
+                    diagnose!(cx.common, debug, "Server agreed to resume");
 
                     // Is the server telling lies about the ciphersuite?
                     if resuming.suite() != suite {
@@ -139,6 +168,11 @@ mod server_hello {
                             .into_owned(),
                     );
                     cx.common.handshake_kind = Some(HandshakeKind::Resumed);
+                    cx.common.resumption_kind = Some(if resuming.ticket().is_empty() {
+                        ResumptionKind::SessionId
+                    } else {
+                        ResumptionKind::Ticket
+                    });
                     let cert_verified = verify::ServerCertVerified::assertion();
                     let sig_verified = verify::HandshakeSignatureValid::assertion();
 
@@ -455,15 +489,18 @@ impl State<ClientConnectionData> for ExpectServerKx<'_> {
         kx.params.encode(&mut kx_params);
         let server_kx = ServerKxDetails::new(kx_params, kx.dss);
 
-        #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
-        {
-            match &kx.params {
-                ServerKeyExchangeParams::Ecdh(ecdhe) => {
-                    debug!("ECDHE curve is {:?}", ecdhe.curve_params)
-                }
-                ServerKeyExchangeParams::Dh(dhe) => {
-                    debug!("DHE params are p = {:?}, g = {:?}", dhe.dh_p, dhe.dh_g)
-                }
+        match &kx.params {
+            ServerKeyExchangeParams::Ecdh(ecdhe) => {
+                diagnose!(cx.common, debug, "ECDHE curve is {:?}", ecdhe.curve_params)
+            }
+            ServerKeyExchangeParams::Dh(dhe) => {
+                diagnose!(
+                    cx.common,
+                    debug,
+                    "DHE params are p = {:?}, g = {:?}",
+                    dhe.dh_p,
+                    dhe.dh_g
+                )
             }
         }
 
@@ -720,7 +757,7 @@ struct ExpectCertificateRequest<'a> {
 impl State<ClientConnectionData> for ExpectCertificateRequest<'_> {
     fn handle<'m>(
         mut self: Box<Self>,
-        _cx: &mut ClientContext<'_>,
+        cx: &mut ClientContext<'_>,
         m: Message<'m>,
     ) -> hs::NextStateOrError<'m>
     where
@@ -732,7 +769,7 @@ impl State<ClientConnectionData> for ExpectCertificateRequest<'_> {
             HandshakePayload::CertificateRequest
         )?;
         self.transcript.add_message(&m);
-        debug!("Got CertificateRequest {:?}", certreq);
+        diagnose!(cx.common, debug, "Got CertificateRequest {:?}", certreq);
 
         // The RFC jovially describes the design here as 'somewhat complicated'
         // and 'somewhat underspecified'.  So thanks for that.
@@ -742,15 +779,20 @@ impl State<ClientConnectionData> for ExpectCertificateRequest<'_> {
 
         const NO_CONTEXT: Option<Vec<u8>> = None; // TLS 1.2 doesn't use a context.
         let no_compression = None; // or compression
-        let client_auth = ClientAuthDetails::resolve(
-            self.config
-                .client_auth_cert_resolver
-                .as_ref(),
-            Some(&certreq.canames),
-            &certreq.sigschemes,
-            NO_CONTEXT,
-            no_compression,
-        );
+        let client_auth = cx.common.with_user_data(|data| {
+            ClientAuthDetails::resolve(
+                self.config
+                    .client_auth_cert_resolver
+                    .as_ref(),
+                ProtocolVersion::TLSv1_2,
+                Some(&certreq.canames),
+                &certreq.sigschemes,
+                &[], // TLS 1.2 doesn't have extensions here.
+                NO_CONTEXT,
+                no_compression,
+                data,
+            )
+        });
 
         Ok(Box::new(ExpectServerDone {
             config: self.config,
@@ -833,7 +875,7 @@ impl State<ClientConnectionData> for ExpectServerDone<'_> {
         cx.common.check_aligned_handshake()?;
 
         trace!("Server cert is {:?}", st.server_cert.cert_chain);
-        debug!("Server DNS name is {:?}", st.server_name);
+        diagnose!(cx.common, debug, "Server DNS name is {:?}", st.server_name);
 
         let suite = st.suite;
 
@@ -858,14 +900,32 @@ impl State<ClientConnectionData> for ExpectServerDone<'_> {
 
         let now = st.config.current_time()?;
 
-        let cert_verified = st
-            .config
-            .verifier
-            .verify_server_cert(
+        let cert_verified = cx
+            .common
+            .with_user_data(|data| {
+                cx.data
+                    .verifier(&st.config)
+                    .verify_server_cert_with_user_data(
+                        end_entity,
+                        intermediates,
+                        &st.server_name,
+                        &st.server_cert.ocsp_response,
+                        now,
+                        data,
+                    )
+            })
+            .map_err(|err| {
+                cx.common
+                    .send_cert_verify_error_alert(err)
+            })?;
+
+        cx.data.peer_revocation_status = cx
+            .data
+            .verifier(&st.config)
+            .check_stapled_ocsp(
                 end_entity,
                 intermediates,
-                &st.server_name,
-                &st.server_cert.ocsp_response,
+                &st.server_cert.ocsp_responses(),
                 now,
             )
             .map_err(|err| {
@@ -887,7 +947,9 @@ impl State<ClientConnectionData> for ExpectServerDone<'_> {
             if !SupportedCipherSuite::from(suite)
                 .usable_for_signature_algorithm(sig.scheme.algorithm())
             {
-                warn!(
+                diagnose!(
+                    cx.common,
+                    warn,
                     "peer signed kx with wrong algorithm (got {:?} expect {:?})",
                     sig.scheme.algorithm(),
                     suite.sign
@@ -895,8 +957,8 @@ impl State<ClientConnectionData> for ExpectServerDone<'_> {
                 return Err(PeerMisbehaved::SignedKxWithWrongAlgorithm.into());
             }
 
-            st.config
-                .verifier
+            cx.data
+                .verifier(&st.config)
                 .verify_tls12_signature(&message, end_entity, sig)
                 .map_err(|err| {
                     cx.common
@@ -923,7 +985,10 @@ impl State<ClientConnectionData> for ExpectServerDone<'_> {
         let named_group = kx_params
             .named_group()
             .ok_or(PeerMisbehaved::SelectedUnofferedKxGroup)?;
-        let skxg = match st.config.find_kx_group(named_group) {
+        let skxg = match cx
+            .data
+            .find_kx_group(&st.config, named_group)
+        {
             Some(skxg) => skxg,
             None => {
                 return Err(PeerMisbehaved::SelectedUnofferedKxGroup.into());
@@ -1050,15 +1115,33 @@ impl State<ClientConnectionData> for ExpectNewTicket {
             HandshakePayload::NewSessionTicket
         )?;
 
+        let received = ReceivedTicket {
+            version: ProtocolVersion::TLSv1_2,
+            lifetime: Duration::from_secs(nst.lifetime_hint.into()),
+            max_early_data_size: None,
+            nonce: None,
+            ticket_len: nst.ticket.0.len(),
+        };
+        let (ticket, resuming_session) = match nst.ticket.0.is_empty()
+            || self
+                .config
+                .resumption
+                .accepts_ticket(&self.server_name, &received)
+        {
+            true => (Some(nst), self.resuming_session),
+            // neither store the new ticket, nor store again the one it replaces
+            false => (None, None),
+        };
+
         Ok(Box::new(ExpectCcs {
             config: self.config,
             secrets: self.secrets,
-            resuming_session: self.resuming_session,
+            resuming_session,
             session_id: self.session_id,
             server_name: self.server_name,
             using_ems: self.using_ems,
             transcript: self.transcript,
-            ticket: Some(nst),
+            ticket,
             resuming: self.resuming,
             cert_verified: self.cert_verified,
             sig_verified: self.sig_verified,
@@ -1148,13 +1231,14 @@ struct ExpectFinished {
 
 impl ExpectFinished {
     // -- Waiting for their finished --
-    fn save_session(&mut self, cx: &ClientContext<'_>) {
+    fn save_session(&mut self, cx: &mut ClientContext<'_>) {
         // Save a ticket.  If we got a new ticket, save that.  Otherwise, save the
         // original ticket again.
         let (mut ticket, lifetime) = match self.ticket.take() {
             Some(nst) => (nst.ticket.0, nst.lifetime_hint),
             None => (Vec::new(), 0),
         };
+        let new_ticket = !ticket.is_empty();
 
         if ticket.is_empty() {
             if let Some(resuming_session) = &mut self.resuming_session {
@@ -1163,14 +1247,18 @@ impl ExpectFinished {
         }
 
         if self.session_id.is_empty() && ticket.is_empty() {
-            debug!("Session not saved: server didn't allocate id or ticket");
+            diagnose!(
+                cx.common,
+                debug,
+                "Session not saved: server didn't allocate id or ticket"
+            );
             return;
         }
 
         let now = match self.config.current_time() {
             Ok(now) => now,
             Err(_) => {
-                debug!("Could not get current time");
+                diagnose!(cx.common, debug, "Could not get current time");
                 return;
             }
         };
@@ -1189,10 +1277,12 @@ impl ExpectFinished {
             self.using_ems,
         );
 
-        self.config
-            .resumption
-            .store
+        cx.data
+            .session_store(&self.config)
             .set_tls12_session(self.server_name.clone(), session_value);
+        if new_ticket {
+            cx.common.note_ticket_received();
+        }
     }
 }
 
@@ -1230,6 +1320,9 @@ impl State<ClientConnectionData> for ExpectFinished {
         // Hash this message too.
         st.transcript.add_message(&m);
 
+        if st.ticket.is_some() {
+            cx.data.ticket_refresh_due = false;
+        }
         st.save_session(cx);
 
         if st.resuming {
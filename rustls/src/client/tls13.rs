@@ -2,6 +2,7 @@ use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::time::Duration;
 
 use pki_types::ServerName;
 use subtle::ConstantTimeEq;
@@ -11,8 +12,10 @@ use super::hs::ClientContext;
 use crate::check::inappropriate_handshake_message;
 use crate::client::common::{ClientAuthDetails, ClientHelloDetails, ServerCertDetails};
 use crate::client::ech::{self, EchState, EchStatus};
-use crate::client::{hs, ClientConfig, ClientSessionStore};
-use crate::common_state::{CommonState, HandshakeKind, KxState, Protocol, Side, State};
+use crate::client::{hs, ClientConfig, ReceivedTicket};
+use crate::common_state::{
+    CommonState, HandshakeKind, KxState, Protocol, ResumptionKind, Side, State,
+};
 use crate::conn::ConnectionRandoms;
 use crate::crypto::ActiveKeyExchange;
 use crate::enums::{
@@ -21,10 +24,11 @@ use crate::enums::{
 use crate::error::{Error, InvalidMessage, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
 #[cfg(feature = "logging")]
-use crate::log::{debug, trace, warn};
+use crate::log::trace;
 use crate::msgs::base::{Payload, PayloadU8};
 use crate::msgs::ccs::ChangeCipherSpecPayload;
-use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::codec::Reader;
+use crate::msgs::deframer::DEFAULT_MAX_HANDSHAKE_SIZE;
 use crate::msgs::enums::{ExtensionType, KeyUpdateRequest};
 use crate::msgs::handshake::{
     CertificatePayloadTls13, ClientExtension, EchConfigPayload, HandshakeMessagePayload,
@@ -87,7 +91,16 @@ pub(super) fn handle_server_hello(
             )
         })?;
 
-    if our_key_share.group() != their_key_share.group {
+    // The server may choose the classical component of a hybrid key share, if that
+    // was offered too.
+    let hybrid_component = match their_key_share.group {
+        group if group == our_key_share.group() => None,
+        group if Some(group) == hello.offered_hybrid_component => {
+            cx.data.find_kx_group(&config, group)
+        }
+        _ => None,
+    };
+    if our_key_share.group() != their_key_share.group && hybrid_component.is_none() {
         return Err({
             cx.common.send_fatal_alert(
                 AlertDescription::IllegalParameter,
@@ -132,14 +145,27 @@ pub(super) fn handle_server_hello(
                 });
             }
 
-            debug!("Resuming using PSK");
+            if config
+                .resumption
+                .require_same_parameters
+                && (resuming.suite() != suite || resuming.kx_group() != Some(their_key_share.group))
+            {
+                return Err({
+                    cx.common.send_fatal_alert(
+                        AlertDescription::HandshakeFailure,
+                        PeerIncompatible::ResumedWithDifferentParameters,
+                    )
+                });
+            }
+
+            diagnose!(cx.common, debug, "Resuming using PSK");
             // The key schedule has been initialized and set in fill_in_psk_binder()
         } else {
             return Err(PeerMisbehaved::SelectedUnofferedPsk.into());
         }
         KeySchedulePreHandshake::from(early_key_schedule)
     } else {
-        debug!("Not resuming");
+        diagnose!(cx.common, debug, "Not resuming");
         // Discard the early data key schedule.
         cx.data.early_data.rejected();
         cx.common.early_traffic = false;
@@ -147,8 +173,16 @@ pub(super) fn handle_server_hello(
         KeySchedulePreHandshake::new(suite)
     };
 
-    cx.common.kx_state.complete();
-    let shared_secret = our_key_share.complete(&their_key_share.payload.0)?;
+    let shared_secret = match hybrid_component {
+        Some(component) => {
+            cx.common.kx_state = KxState::Complete(component);
+            our_key_share.complete_hybrid_component(&their_key_share.payload.0)?
+        }
+        None => {
+            cx.common.kx_state.complete();
+            our_key_share.complete(&their_key_share.payload.0)?
+        }
+    };
 
     let mut key_schedule = key_schedule_pre_handshake.into_handshake(shared_secret);
 
@@ -175,12 +209,15 @@ pub(super) fn handle_server_hello(
             None => EchStatus::Rejected,
         };
     }
+    cx.common
+        .note_client_random(&randoms.client);
 
     // Remember what KX group the server liked for next time.
-    config
-        .resumption
-        .store
-        .set_kx_hint(server_name.clone(), their_key_share.group);
+    if config.resumption.remember_kx_groups {
+        cx.data
+            .session_store(&config)
+            .set_kx_hint(server_name.clone(), their_key_share.group);
+    }
 
     // If we change keying when a subsequent handshake message is being joined,
     // the two halves will have different record layer protections.  Disallow this.
@@ -228,20 +265,34 @@ fn validate_server_hello(
 
 pub(super) fn initial_key_share(
     config: &ClientConfig,
+    data: &ClientConnectionData,
     server_name: &ServerName<'_>,
     kx_state: &mut KxState,
 ) -> Result<Box<dyn ActiveKeyExchange>, Error> {
-    let group = config
-        .resumption
-        .store
-        .kx_hint(server_name)
-        .and_then(|group_name| config.find_kx_group(group_name))
+    let hint = match config.resumption.remember_kx_groups {
+        true => data
+            .session_store(config)
+            .kx_hint(server_name),
+        false => None,
+    };
+    // only send a key share for a group the hello profile leaves advertised
+    let mut advertised = data
+        .kx_groups(config)
+        .map(|skxg| skxg.name())
+        .collect::<Vec<_>>();
+    if let Some(profile) = &config.hello_profile {
+        profile.named_groups(&mut advertised);
+    }
+
+    let group = hint
+        .filter(|group_name| advertised.contains(group_name))
+        .and_then(|group_name| data.find_kx_group(config, group_name))
+        .or_else(|| {
+            data.kx_groups(config)
+                .find(|skxg| advertised.contains(&skxg.name()))
+        })
         .unwrap_or_else(|| {
-            config
-                .provider
-                .kx_groups
-                .iter()
-                .copied()
+            data.kx_groups(config)
                 .next()
                 .expect("No kx groups configured")
         });
@@ -416,11 +467,16 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
             HandshakeType::EncryptedExtensions,
             HandshakePayload::EncryptedExtensions
         )?;
-        debug!("TLS1.3 encrypted extensions: {:?}", exts);
+        diagnose!(cx.common, debug, "TLS1.3 encrypted extensions: {:?}", exts);
         self.transcript.add_message(&m);
 
         validate_encrypted_extensions(cx.common, &self.hello, exts)?;
         hs::process_alpn_protocol(cx.common, &self.config, exts.alpn_protocol())?;
+        hs::process_max_fragment_length(
+            cx.common,
+            &self.config,
+            exts.max_fragment_length_extension(),
+        )?;
 
         let ech_retry_configs = match (cx.data.ech_status, exts.server_ech_extension()) {
             // If we didn't offer ECH, or ECH was accepted, but the server sent an ECH encrypted
@@ -473,6 +529,7 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
                     .clone(),
             );
             cx.common.handshake_kind = Some(HandshakeKind::Resumed);
+            cx.common.resumption_kind = Some(ResumptionKind::Ticket);
 
             // We *don't* reverify the certificate chain here: resumption is a
             // continuation of the previous session in terms of security policy.
@@ -795,14 +852,14 @@ impl State<ClientConnectionData> for ExpectCertificateRequest {
             HandshakePayload::CertificateRequestTls13
         )?;
         self.transcript.add_message(&m);
-        debug!("Got CertificateRequest {:?}", certreq);
+        diagnose!(cx.common, debug, "Got CertificateRequest {:?}", certreq);
 
         // Fortunately the problems here in TLS1.2 and prior are corrected in
         // TLS1.3.
 
         // Must be empty during handshake.
         if !certreq.context.0.is_empty() {
-            warn!("Server sent non-empty certreq context");
+            diagnose!(cx.common, warn, "Server sent non-empty certreq context");
             return Err(cx.common.send_fatal_alert(
                 AlertDescription::DecodeError,
                 InvalidMessage::InvalidCertRequest,
@@ -835,15 +892,20 @@ impl State<ClientConnectionData> for ExpectCertificateRequest {
             })
             .cloned();
 
-        let client_auth = ClientAuthDetails::resolve(
-            self.config
-                .client_auth_cert_resolver
-                .as_ref(),
-            certreq.authorities_extension(),
-            &compat_sigschemes,
-            Some(certreq.context.0.clone()),
-            compat_compressor,
-        );
+        let client_auth = cx.common.with_user_data(|data| {
+            ClientAuthDetails::resolve(
+                self.config
+                    .client_auth_cert_resolver
+                    .as_ref(),
+                ProtocolVersion::TLSv1_3,
+                certreq.authorities_extension(),
+                &compat_sigschemes,
+                &certreq.extensions,
+                Some(certreq.context.0.clone()),
+                compat_compressor,
+                data,
+            )
+        });
 
         Ok(if self.offered_cert_compression {
             Box::new(ExpectCertificateOrCompressedCertificate {
@@ -918,32 +980,48 @@ impl State<ClientConnectionData> for ExpectCompressedCertificate {
             }
         };
 
-        if compressed_cert.uncompressed_len as usize > CERTIFICATE_MAX_SIZE_LIMIT {
+        let max_size = self
+            .config
+            .max_decompressed_cert_size
+            .or(self.config.max_handshake_size)
+            .unwrap_or(DEFAULT_MAX_HANDSHAKE_SIZE as usize);
+        if compressed_cert.uncompressed_len as usize > max_size {
             return Err(cx.common.send_fatal_alert(
                 AlertDescription::BadCertificate,
                 InvalidMessage::MessageTooLarge,
             ));
         }
 
-        let mut decompress_buffer = vec![0u8; compressed_cert.uncompressed_len as usize];
-        if let Err(compress::DecompressionFailed) =
-            decompressor.decompress(compressed_cert.compressed.0.bytes(), &mut decompress_buffer)
-        {
-            return Err(cx.common.send_fatal_alert(
-                AlertDescription::BadCertificate,
-                PeerMisbehaved::InvalidCertCompression,
-            ));
-        }
+        let (decompressed, elapsed) = compress::timed(|| {
+            self.config
+                .cert_decompression_cache
+                .decompression_for(
+                    *decompressor,
+                    self.suite.common.hash_provider,
+                    &compressed_cert,
+                )
+        });
+        let decompressed = match decompressed {
+            Ok(decompressed) => decompressed,
+            Err(compress::DecompressionFailed) => {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::BadCertificate,
+                    PeerMisbehaved::InvalidCertCompression,
+                ));
+            }
+        };
 
-        let cert_payload =
-            match CertificatePayloadTls13::read(&mut Reader::init(&decompress_buffer)) {
-                Ok(cm) => cm,
-                Err(err) => {
-                    return Err(cx
-                        .common
-                        .send_fatal_alert(AlertDescription::BadCertificate, err));
-                }
-            };
+        let cert_payload = match CertificatePayloadTls13::read_with_max_size(
+            &mut Reader::init(decompressed.decompressed()),
+            Ord::max(max_size, CERTIFICATE_MAX_SIZE_LIMIT),
+        ) {
+            Ok(cm) => cm,
+            Err(err) => {
+                return Err(cx
+                    .common
+                    .send_fatal_alert(AlertDescription::BadCertificate, err));
+            }
+        };
         trace!(
             "Server certificate decompressed using {:?} ({} bytes -> {})",
             compressed_cert.alg,
@@ -954,6 +1032,10 @@ impl State<ClientConnectionData> for ExpectCompressedCertificate {
                 .len(),
             compressed_cert.uncompressed_len,
         );
+        cx.common.received_cert_compression = Some(compress::CertCompressionStats::new(
+            &compressed_cert,
+            elapsed,
+        ));
 
         let m = Message {
             version: ProtocolVersion::TLSv1_3,
@@ -1029,12 +1111,14 @@ impl State<ClientConnectionData> for ExpectCertificate {
             ));
         }
         let end_entity_ocsp = cert_chain.end_entity_ocsp();
+        let intermediate_ocsp = cert_chain.intermediate_ocsp();
         let server_cert = ServerCertDetails::new(
             cert_chain
                 .into_certificate_chain()
                 .into_owned(),
             end_entity_ocsp,
-        );
+        )
+        .with_intermediate_ocsp(intermediate_ocsp);
 
         Ok(Box::new(ExpectCertificateVerify {
             config: self.config,
@@ -1093,14 +1177,32 @@ impl State<ClientConnectionData> for ExpectCertificateVerify<'_> {
 
         let now = self.config.current_time()?;
 
-        let cert_verified = self
-            .config
-            .verifier
-            .verify_server_cert(
+        let cert_verified = cx
+            .common
+            .with_user_data(|data| {
+                cx.data
+                    .verifier(&self.config)
+                    .verify_server_cert_with_user_data(
+                        end_entity,
+                        intermediates,
+                        &self.server_name,
+                        &self.server_cert.ocsp_response,
+                        now,
+                        data,
+                    )
+            })
+            .map_err(|err| {
+                cx.common
+                    .send_cert_verify_error_alert(err)
+            })?;
+
+        cx.data.peer_revocation_status = cx
+            .data
+            .verifier(&self.config)
+            .check_stapled_ocsp(
                 end_entity,
                 intermediates,
-                &self.server_name,
-                &self.server_cert.ocsp_response,
+                &self.server_cert.ocsp_responses(),
                 now,
             )
             .map_err(|err| {
@@ -1110,9 +1212,9 @@ impl State<ClientConnectionData> for ExpectCertificateVerify<'_> {
 
         // 2. Verify their signature on the handshake.
         let handshake_hash = self.transcript.current_hash();
-        let sig_verified = self
-            .config
-            .verifier
+        let sig_verified = cx
+            .data
+            .verifier(&self.config)
             .verify_tls13_signature(
                 &construct_server_verify_message(&handshake_hash),
                 end_entity,
@@ -1166,10 +1268,12 @@ fn emit_compressed_certificate_tls13(
     let mut cert_payload = CertificatePayloadTls13::new(certkey.cert.iter(), None);
     cert_payload.context = PayloadU8::new(auth_context.clone().unwrap_or_default());
 
-    let compressed = match config
-        .cert_compression_cache
-        .compression_for(compressor, &cert_payload)
-    {
+    let (compressed, elapsed) = compress::timed(|| {
+        config
+            .cert_compression_cache
+            .compression_for(compressor, &cert_payload)
+    });
+    let compressed = match compressed {
         Ok(compressed) => compressed,
         Err(_) => return emit_certificate_tls13(transcript, Some(certkey), auth_context, common),
     };
@@ -1181,6 +1285,10 @@ fn emit_compressed_certificate_tls13(
             payload: HandshakePayload::CompressedCertificate(compressed.compressed_cert_payload()),
         }),
     };
+    common.sent_cert_compression = Some(compress::CertCompressionStats::new(
+        &compressed.compressed_cert_payload(),
+        elapsed,
+    ));
     transcript.add_message(&m);
     common.send_msg(m, true);
 }
@@ -1380,9 +1488,8 @@ impl State<ClientConnectionData> for ExpectFinished {
 
         /* We're now sure this server supports TLS1.3.  But if we run out of TLS1.3 tickets
          * when connecting to it again, we definitely don't want to attempt a TLS1.2 resumption. */
-        st.config
-            .resumption
-            .store
+        cx.data
+            .session_store(&st.config)
             .remove_tls12_session(&st.server_name);
 
         /* Now move to our application traffic keys. */
@@ -1395,12 +1502,19 @@ impl State<ClientConnectionData> for ExpectFinished {
         // sending an alert and returning an error (potentially with retry configs) if the server
         // did not accept our ECH offer.
         if cx.data.ech_status == EchStatus::Rejected {
+            if let Some(resolver) = &st.config.ech_resolver {
+                resolver.ech_rejected(
+                    &st.server_name,
+                    st.ech_retry_configs
+                        .as_deref()
+                        .map(ech::config_list_bytes),
+                );
+            }
             return Err(ech::fatal_alert_required(st.ech_retry_configs, cx.common));
         }
 
         let st = ExpectTraffic {
             config: Arc::clone(&st.config),
-            session_storage: Arc::clone(&st.config.resumption.store),
             server_name: st.server_name,
             suite: st.suite,
             transcript: st.transcript,
@@ -1426,7 +1540,6 @@ impl State<ClientConnectionData> for ExpectFinished {
 // and application data.
 struct ExpectTraffic {
     config: Arc<ClientConfig>,
-    session_storage: Arc<dyn ClientSessionStore>,
     server_name: ServerName<'static>,
     suite: &'static Tls13CipherSuite,
     transcript: HandshakeHash,
@@ -1449,6 +1562,23 @@ impl ExpectTraffic {
             ));
         }
 
+        cx.common
+            .received_new_session_ticket()?;
+        let received = ReceivedTicket {
+            version: ProtocolVersion::TLSv1_3,
+            lifetime: Duration::from_secs(nst.lifetime.into()),
+            max_early_data_size: nst.max_early_data_size(),
+            nonce: Some(&nst.nonce.0),
+            ticket_len: nst.ticket.0.len(),
+        };
+        if !self
+            .config
+            .resumption
+            .accepts_ticket(&self.server_name, &received)
+        {
+            return Ok(());
+        }
+
         let handshake_hash = self.transcript.current_hash();
         let secret = self
             .key_schedule
@@ -1456,7 +1586,6 @@ impl ExpectTraffic {
 
         let now = self.config.current_time()?;
 
-        #[allow(unused_mut)]
         let mut value = persist::Tls13ClientSessionValue::new(
             self.suite,
             nst.ticket.0.clone(),
@@ -1471,6 +1600,11 @@ impl ExpectTraffic {
             nst.max_early_data_size()
                 .unwrap_or_default(),
         );
+        value.set_kx_group(
+            cx.common
+                .negotiated_key_exchange_group()
+                .map(|group| group.name()),
+        );
 
         if cx.common.is_quic() {
             if let Some(sz) = nst.max_early_data_size() {
@@ -1484,8 +1618,11 @@ impl ExpectTraffic {
             }
         }
 
-        self.session_storage
+        cx.data
+            .session_store(&self.config)
             .insert_tls13_ticket(self.server_name.clone(), value);
+        cx.common.note_ticket_received();
+        cx.data.ticket_refresh_due = false;
         Ok(())
     }
 
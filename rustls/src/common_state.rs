@@ -1,13 +1,25 @@
 use alloc::boxed::Box;
+use alloc::string::ToString;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt::{self, Debug};
+use core::time::Duration;
 
 use pki_types::CertificateDer;
 
+use crate::alpn::ApplicationProtocol;
+use crate::audit::{CloseReason, ConnectionAudit, ReceivesConnectionAudits};
+use crate::client::ClientConfig;
+use crate::compress::CertCompressionStats;
 use crate::crypto::SupportedKxGroup;
-use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
+use crate::diagnostics::{Diagnostic, DiagnosticLevel, ReceivesDiagnostics};
+use crate::enums::{
+    AlertDescription, ContentType, HandshakeType, MaxFragmentLength, ProtocolVersion,
+};
 use crate::error::{Error, InvalidMessage, PeerMisbehaved};
 #[cfg(feature = "logging")]
-use crate::log::{debug, error, warn};
+use crate::log::debug;
 use crate::msgs::alert::AlertMessagePayload;
 use crate::msgs::base::Payload;
 use crate::msgs::enums::{AlertLevel, KeyUpdateRequest};
@@ -17,8 +29,12 @@ use crate::msgs::message::{
     Message, MessagePayload, OutboundChunks, OutboundOpaqueMessage, OutboundPlainMessage,
     PlainMessage,
 };
-use crate::record_layer::PreEncryptAction;
+use crate::record_layer::{KeyLimitAction, PreEncryptAction};
+use crate::server::ServerConfig;
+#[cfg(feature = "state_trace")]
+use crate::state_trace::{self, StateTrace};
 use crate::suites::{PartiallyExtractedSecrets, SupportedCipherSuite};
+use crate::time_provider::TimeProvider;
 #[cfg(feature = "tls12")]
 use crate::tls12::ConnectionSecrets;
 use crate::unbuffered::{EncryptError, InsufficientSizeError};
@@ -29,6 +45,9 @@ use crate::{quic, record_layer};
 pub struct CommonState {
     pub(crate) negotiated_version: Option<ProtocolVersion>,
     pub(crate) handshake_kind: Option<HandshakeKind>,
+    pub(crate) resumption_kind: Option<ResumptionKind>,
+    pub(crate) sent_cert_compression: Option<CertCompressionStats>,
+    pub(crate) received_cert_compression: Option<CertCompressionStats>,
     pub(crate) side: Side,
     pub(crate) record_layer: record_layer::RecordLayer,
     pub(crate) suite: Option<SupportedCipherSuite>,
@@ -41,9 +60,17 @@ pub struct CommonState {
     sent_fatal_alert: bool,
     /// If the peer has signaled end of stream.
     pub(crate) has_received_close_notify: bool,
+    /// If we have signaled end of stream.
+    pub(crate) has_sent_close_notify: bool,
+    /// Whether to reply to the peer's close_notify with our own.
+    pub(crate) respond_to_close_notify: bool,
     #[cfg(feature = "std")]
     pub(crate) has_seen_eof: bool,
+    #[cfg(feature = "std")]
+    has_reported_eof: bool,
     pub(crate) peer_certificates: Option<CertificateChain<'static>>,
+    #[cfg(feature = "tls12")]
+    pub(crate) using_ems: bool,
     message_fragmenter: MessageFragmenter,
     pub(crate) received_plaintext: ChunkVecBuffer,
     pub(crate) sendable_tls: ChunkVecBuffer,
@@ -55,6 +82,26 @@ pub struct CommonState {
     pub(crate) enable_secret_extraction: bool,
     temper_counters: TemperCounters,
     pub(crate) refresh_traffic_keys_pending: bool,
+    handshake_deadline: Option<HandshakeDeadline>,
+    shutdown_observer: Option<Arc<dyn ObservesShutdown>>,
+    pub(crate) connection_observer: Option<Arc<dyn ObservesConnection>>,
+    pub(crate) handshake_mutator: Option<Arc<dyn MutatesHandshakeMessages>>,
+    /// The client random under which this connection's secrets are logged.
+    #[cfg(feature = "std")]
+    pub(crate) client_random: Option<[u8; 32]>,
+    #[cfg(feature = "std")]
+    user_data: Option<Box<std::sync::Mutex<Box<dyn Any + Send>>>>,
+    pub(crate) alert_policy: Option<Arc<dyn ChoosesAlerts>>,
+    /// Whether to omit fatal alerts sent before any other message.
+    pub(crate) silent_rejection: bool,
+    pub(crate) diagnostics: Option<Arc<dyn ReceivesDiagnostics>>,
+    pub(crate) audit_sink: Option<Arc<dyn ReceivesConnectionAudits>>,
+    /// Boxed so this does not grow every connection, and made when the first message is handled.
+    #[cfg(feature = "state_trace")]
+    pub(crate) state_trace: Option<Box<StateTrace>>,
+    audited: bool,
+    /// What happened during the current call to `process_new_packets()`.
+    pub(crate) io_events: IoEvents,
 }
 
 impl CommonState {
@@ -62,6 +109,9 @@ impl CommonState {
         Self {
             negotiated_version: None,
             handshake_kind: None,
+            resumption_kind: None,
+            sent_cert_compression: None,
+            received_cert_compression: None,
             side,
             record_layer: record_layer::RecordLayer::new(),
             suite: None,
@@ -73,9 +123,15 @@ impl CommonState {
             early_traffic: false,
             sent_fatal_alert: false,
             has_received_close_notify: false,
+            has_sent_close_notify: false,
+            respond_to_close_notify: false,
             #[cfg(feature = "std")]
             has_seen_eof: false,
+            #[cfg(feature = "std")]
+            has_reported_eof: false,
             peer_certificates: None,
+            #[cfg(feature = "tls12")]
+            using_ems: false,
             message_fragmenter: MessageFragmenter::default(),
             received_plaintext: ChunkVecBuffer::new(Some(DEFAULT_RECEIVED_PLAINTEXT_LIMIT)),
             sendable_tls: ChunkVecBuffer::new(Some(DEFAULT_BUFFER_LIMIT)),
@@ -85,7 +141,96 @@ impl CommonState {
             enable_secret_extraction: false,
             temper_counters: TemperCounters::default(),
             refresh_traffic_keys_pending: false,
+            handshake_deadline: None,
+            shutdown_observer: None,
+            connection_observer: None,
+            handshake_mutator: None,
+            #[cfg(feature = "std")]
+            client_random: None,
+            #[cfg(feature = "std")]
+            user_data: None,
+            alert_policy: None,
+            silent_rejection: false,
+            diagnostics: None,
+            audit_sink: None,
+            #[cfg(feature = "state_trace")]
+            state_trace: None,
+            audited: false,
+            io_events: IoEvents::default(),
+        }
+    }
+
+    /// Give a diagnostic to the configured [`ReceivesDiagnostics`], if any.
+    pub(crate) fn diagnose(
+        &self,
+        level: DiagnosticLevel,
+        message: fmt::Arguments<'_>,
+        alert: Option<AlertDescription>,
+        error: Option<&Error>,
+    ) {
+        if let Some(diagnostics) = &self.diagnostics {
+            if diagnostics.enabled(level) {
+                diagnostics.diagnostic(&Diagnostic {
+                    level,
+                    side: self.side,
+                    message,
+                    protocol_version: self.negotiated_version,
+                    alert,
+                    error,
+                });
+            }
+        }
+    }
+
+    /// Deliver the audit record for this connection, which ended because of `reason`.
+    ///
+    /// Only the first call for a connection has any effect.
+    pub(crate) fn audit_close(&mut self, reason: CloseReason, error: Option<&Error>) {
+        let sink = match (&self.audit_sink, self.audited) {
+            (Some(sink), false) => sink,
+            _ => return,
+        };
+        self.audited = true;
+
+        let (bytes_sent, bytes_received) = self.record_layer.bytes_transferred();
+        let digest = match (&self.peer_certificates, self.suite) {
+            (Some(chain), Some(suite)) => chain.first().map(|cert| {
+                let hash = suite.hash_provider();
+                (
+                    hash.algorithm(),
+                    hash.hash(cert.as_ref())
+                        .as_ref()
+                        .to_vec(),
+                )
+            }),
+            _ => None,
+        };
+
+        sink.connection_audit(&ConnectionAudit {
+            side: self.side,
+            protocol_version: self.negotiated_version,
+            cipher_suite: self.suite.map(|suite| suite.suite()),
+            key_exchange_group: self
+                .negotiated_key_exchange_group()
+                .map(|group| group.name()),
+            handshake_kind: self.handshake_kind,
+            alpn_protocol: self.alpn_protocol.clone(),
+            peer_certificate_digest_algorithm: digest.as_ref().map(|(alg, _)| *alg),
+            peer_certificate_digest: digest.map(|(_, digest)| digest),
+            bytes_sent,
+            bytes_received,
+            close_reason: reason,
+            error: error.map(|error| error.to_string()),
+        });
+    }
+
+    pub(crate) fn note_client_random(&mut self, random: &[u8; 32]) {
+        #[cfg(feature = "std")]
+        {
+            self.client_random = Some(*random);
         }
+        #[cfg(not(feature = "std"))]
+        let _ = random;
     }
 
     /// Returns true if the caller should call [`Connection::write_tls`] as soon as possible.
@@ -106,6 +251,122 @@ impl CommonState {
         !(self.may_send_application_data && self.may_receive_application_data)
     }
 
+    /// Returns true if the peer has sent us a close_notify alert.
+    ///
+    /// The peer will not send any further data on this connection.  Unless
+    /// [`ClientConfig::respond_to_close_notify`] or [`ServerConfig::respond_to_close_notify`]
+    /// is set, the connection is only half-closed: data may still be sent to the peer.
+    /// Otherwise, writes fail with [`std::io::ErrorKind::BrokenPipe`].
+    ///
+    /// [`ClientConfig::respond_to_close_notify`]: crate::ClientConfig::respond_to_close_notify
+    /// [`ServerConfig::respond_to_close_notify`]: crate::ServerConfig::respond_to_close_notify
+    pub fn peer_has_closed(&self) -> bool {
+        self.has_received_close_notify
+    }
+
+    /// Returns true if we have sent (or queued) a close_notify alert.
+    pub fn has_sent_close_notify(&self) -> bool {
+        self.has_sent_close_notify
+    }
+
+    /// Install an observer that is told how the peer ended this connection.
+    ///
+    /// See [`ObservesShutdown`] for when it is called.
+    pub fn set_shutdown_observer(&mut self, observer: Arc<dyn ObservesShutdown>) {
+        self.shutdown_observer = Some(observer);
+    }
+
+    /// Install an observer that is told about events in the life of this connection.
+    ///
+    /// See [`ObservesConnection`] for the events reported.
+    pub fn set_connection_observer(&mut self, observer: Arc<dyn ObservesConnection>) {
+        self.connection_observer = Some(observer);
+    }
+
+    /// Attach application data to this connection, replacing any attached before.
+    ///
+    /// rustls does not look at this data, other than to pass it to the callbacks made
+    /// for this connection: certificate verifiers, certificate resolvers and the
+    /// observers installed with [`CommonState::set_connection_observer()`] and
+    /// [`CommonState::set_shutdown_observer()`].  It is dropped along with the
+    /// connection, unless first removed with [`CommonState::take_user_data()`].  It
+    /// can be used to carry application state alongside the connection, instead of
+    /// keeping it in a separate map keyed by connection.
+    ///
+    /// The data need only be `Send`: the connection stays `Sync` because it only
+    /// gives out access to the data while it is borrowed mutably.
+    #[cfg(feature = "std")]
+    pub fn set_user_data(&mut self, data: Box<dyn Any + Send>) {
+        self.user_data = Some(Box::new(std::sync::Mutex::new(data)));
+    }
+
+    /// Mutable access to the application data attached with [`CommonState::set_user_data()`].
+    ///
+    /// Use `downcast_mut()` to recover its concrete type.
+    #[cfg(feature = "std")]
+    pub fn user_data_mut(&mut self) -> Option<&mut (dyn Any + Send)> {
+        // the data is only replaced in one step, so is valid even if a holder panicked
+        self.user_data.as_mut().map(|data| {
+            data.get_mut()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .as_mut()
+        })
+    }
+
+    /// Remove and return the application data attached with [`CommonState::set_user_data()`].
+    #[cfg(feature = "std")]
+    pub fn take_user_data(&mut self) -> Option<Box<dyn Any + Send>> {
+        self.user_data.take().map(|data| {
+            data.into_inner()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+        })
+    }
+
+    /// Call `f` with the application data attached to this connection, to pass to a callback.
+    #[cfg(feature = "std")]
+    pub(crate) fn with_user_data<R>(&self, f: impl FnOnce(Option<&(dyn Any + Send)>) -> R) -> R {
+        match &self.user_data {
+            Some(data) => f(Some(
+                data.lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner)
+                    .as_ref(),
+            )),
+            None => f(None),
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn with_user_data<R>(&self, f: impl FnOnce(Option<&(dyn Any + Send)>) -> R) -> R {
+        f(None)
+    }
+
+    /// Report an event to the connection observer, if there is one.
+    pub(crate) fn observe(
+        &self,
+        f: impl FnOnce(&dyn ObservesConnection, Option<&(dyn Any + Send)>),
+    ) {
+        if let Some(observer) = &self.connection_observer {
+            self.with_user_data(|data| f(observer.as_ref(), data));
+        }
+    }
+
+    /// Tell the shutdown observer about a truncated connection, once.
+    ///
+    /// This must be called after received records are processed, so a
+    /// close_notify that arrived just before EOF is accounted for.
+    #[cfg(feature = "std")]
+    pub(crate) fn check_unexpected_eof(&mut self) {
+        if !self.has_seen_eof || self.has_received_close_notify || self.has_reported_eof {
+            return;
+        }
+        self.has_reported_eof = true;
+        self.audit_close(CloseReason::UnexpectedEof, None);
+
+        if let Some(observer) = &self.shutdown_observer {
+            self.with_user_data(|data| observer.unexpected_eof(data));
+        }
+    }
+
     /// Retrieves the certificate chain used by the peer to authenticate.
     ///
     /// The order of the certificate chain is as it appears in the TLS
@@ -134,6 +395,16 @@ impl CommonState {
         self.get_alpn_protocol()
     }
 
+    /// Retrieves the protocol agreed with the peer via ALPN, recognising
+    /// well-known protocols.
+    ///
+    /// This is the same as [`CommonState::alpn_protocol()`], but as an
+    /// [`ApplicationProtocol`].
+    pub fn application_protocol(&self) -> Option<ApplicationProtocol<'_>> {
+        self.get_alpn_protocol()
+            .map(ApplicationProtocol::from_bytes)
+    }
+
     /// Retrieves the ciphersuite agreed with the peer.
     ///
     /// This returns None until the ciphersuite is agreed.
@@ -164,6 +435,14 @@ impl CommonState {
         self.negotiated_version
     }
 
+    /// The handshake state machine transitions made so far.
+    #[cfg(feature = "state_trace")]
+    pub fn state_trace(&self) -> &StateTrace {
+        self.state_trace
+            .as_deref()
+            .unwrap_or(&state_trace::EMPTY)
+    }
+
     /// Which kind of handshake was performed.
     ///
     /// This tells you whether the handshake was a resumption or not.
@@ -174,6 +453,48 @@ impl CommonState {
         self.handshake_kind
     }
 
+    /// Which mechanism was used to resume the previous session.
+    ///
+    /// This returns `None` unless [`CommonState::handshake_kind()`] is
+    /// [`HandshakeKind::Resumed`].
+    pub fn resumption_kind(&self) -> Option<ResumptionKind> {
+        self.resumption_kind
+    }
+
+    /// How the certificate this side sent was compressed.
+    ///
+    /// This returns `None` if no certificate was sent, or it was sent uncompressed.
+    /// Certificate compression is only available for TLS1.3: see [`compress`].
+    ///
+    /// [`compress`]: crate::compress
+    pub fn sent_cert_compression(&self) -> Option<&CertCompressionStats> {
+        self.sent_cert_compression.as_ref()
+    }
+
+    /// How the certificate received from the peer was compressed.
+    ///
+    /// This returns `None` if no certificate was received, or it was received uncompressed.
+    pub fn received_cert_compression(&self) -> Option<&CertCompressionStats> {
+        self.received_cert_compression.as_ref()
+    }
+
+    /// Whether the connection's master secret is bound to its handshake transcript.
+    ///
+    /// For TLS1.2 this tells you whether the extended master secret extension
+    /// ([RFC 7627]) was negotiated.  TLS1.3 always has this property.
+    ///
+    /// This returns `None` until the protocol version is agreed.
+    ///
+    /// [RFC 7627]: https://datatracker.ietf.org/doc/html/rfc7627
+    pub fn extended_master_secret(&self) -> Option<bool> {
+        match self.negotiated_version? {
+            ProtocolVersion::TLSv1_3 => Some(true),
+            #[cfg(feature = "tls12")]
+            ProtocolVersion::TLSv1_2 => Some(self.using_ems),
+            _ => Some(false),
+        }
+    }
+
     pub(crate) fn is_tls13(&self) -> bool {
         matches!(self.negotiated_version, Some(ProtocolVersion::TLSv1_3))
     }
@@ -195,17 +516,31 @@ impl CommonState {
             if msg.is_handshake_type(reject_ty) {
                 self.temper_counters
                     .received_renegotiation_request()?;
-                self.send_warning_alert(AlertDescription::NoRenegotiation);
+                diagnose!(
+                    self,
+                    warn,
+                    "Sending warning alert {:?}",
+                    AlertDescription::NoRenegotiation
+                );
+                self.send_warning_alert_no_log(AlertDescription::NoRenegotiation);
                 return Ok(state);
             }
         }
 
+        // the trace is of the handshake, so does not grow with the rest of the connection
+        #[cfg(feature = "state_trace")]
+        if self.is_handshaking() {
+            self.state_trace
+                .get_or_insert_with(Box::default)
+                .start(state.name(), &msg.payload);
+        }
+
         let mut cx = Context {
             common: self,
             data,
             sendable_plaintext,
         };
-        match state.handle(&mut cx, msg) {
+        let result = match state.handle(&mut cx, msg) {
             Ok(next) => {
                 state = next.into_owned();
                 Ok(state)
@@ -215,7 +550,17 @@ impl CommonState {
                 Err(self.send_fatal_alert(AlertDescription::UnexpectedMessage, e))
             }
             Err(e) => Err(e),
+        };
+
+        #[cfg(feature = "state_trace")]
+        if let Some(trace) = &mut self.state_trace {
+            trace.finish(
+                result
+                    .as_ref()
+                    .map(|state| state.name()),
+            );
         }
+        result
     }
 
     pub(crate) fn write_plaintext(
@@ -241,14 +586,15 @@ impl CommonState {
                 .pre_encrypt_action(f as u64)
             {
                 PreEncryptAction::Nothing => {}
-                PreEncryptAction::RefreshOrClose => match self.negotiated_version {
-                    Some(ProtocolVersion::TLSv1_3) => {
+                PreEncryptAction::RefreshOrClose => match self.can_refresh_traffic_keys() {
+                    true => {
                         // driven by caller, as we don't have the `State` here
                         self.refresh_traffic_keys_pending = true;
                     }
-                    _ => {
-                        error!("traffic keys exhausted, closing connection to prevent security failure");
+                    false => {
+                        diagnose!(self, error, "traffic keys exhausted, closing connection to prevent security failure");
                         self.send_close_notify();
+                        self.record_layer.exhaust_encrypter();
                         return Err(EncryptError::EncryptExhausted);
                     }
                 },
@@ -273,6 +619,12 @@ impl CommonState {
         Ok(self.write_fragments(outgoing_tls, fragments))
     }
 
+    /// Whether keys that reach their limit are replaced, rather than the connection closed.
+    fn can_refresh_traffic_keys(&self) -> bool {
+        self.negotiated_version == Some(ProtocolVersion::TLSv1_3)
+            && self.record_layer.key_limits().on_limit == KeyLimitAction::UpdateKeys
+    }
+
     // Changing the keys must not span any fragmented handshake
     // messages.  Otherwise the defragmented messages will have
     // been protected with two different record layer protections,
@@ -320,19 +672,26 @@ impl CommonState {
                 ProtocolVersion::TLSv1_2,
                 payload.split_at(len).0,
             );
+        let mut sent = 0;
         for m in iter {
-            self.send_single_fragment(m);
+            let fragment_len = m.payload.len();
+            if !self.send_single_fragment(m) {
+                break;
+            }
+            sent += fragment_len;
         }
 
-        len
+        sent
     }
 
-    fn send_single_fragment(&mut self, m: OutboundPlainMessage<'_>) {
+    /// Encrypt and queue `m`, returning false if it was discarded because
+    /// the traffic keys are exhausted.
+    fn send_single_fragment(&mut self, m: OutboundPlainMessage<'_>) -> bool {
         if m.typ == ContentType::Alert {
             // Alerts are always sendable -- never quashed by a PreEncryptAction.
             let em = self.record_layer.encrypt_outgoing(m);
             self.queue_tls_message(em);
-            return;
+            return true;
         }
 
         match self
@@ -344,15 +703,16 @@ impl CommonState {
             // Close connection once we start to run out of
             // sequence space.
             PreEncryptAction::RefreshOrClose => {
-                match self.negotiated_version {
-                    Some(ProtocolVersion::TLSv1_3) => {
+                match self.can_refresh_traffic_keys() {
+                    true => {
                         // driven by caller, as we don't have the `State` here
                         self.refresh_traffic_keys_pending = true;
                     }
-                    _ => {
-                        error!("traffic keys exhausted, closing connection to prevent security failure");
+                    false => {
+                        diagnose!(self, error, "traffic keys exhausted, closing connection to prevent security failure");
                         self.send_close_notify();
-                        return;
+                        self.record_layer.exhaust_encrypter();
+                        return false;
                     }
                 }
             }
@@ -360,12 +720,13 @@ impl CommonState {
             // Refuse to wrap counter at all costs.  This
             // is basically untestable unfortunately.
             PreEncryptAction::Refuse => {
-                return;
+                return false;
             }
         };
 
         let em = self.record_layer.encrypt_outgoing(m);
         self.queue_tls_message(em);
+        true
     }
 
     fn send_plain_non_buffering(&mut self, payload: OutboundChunks<'_>, limit: Limit) -> usize {
@@ -399,6 +760,27 @@ impl CommonState {
     pub(crate) fn start_traffic(&mut self, sendable_plaintext: &mut Option<&mut ChunkVecBuffer>) {
         self.may_receive_application_data = true;
         self.start_outgoing_traffic(sendable_plaintext);
+
+        self.diagnose(
+            DiagnosticLevel::Info,
+            format_args!(
+                "handshake complete: {:?} with {:?}",
+                self.handshake_kind,
+                self.suite.map(|suite| suite.suite())
+            ),
+            None,
+            None,
+        );
+
+        self.io_events.handshake_completed = self.handshake_kind;
+        self.observe(|observer, data| {
+            if let Some(kind) = self.resumption_kind {
+                observer.resumption_accepted(kind, data);
+            }
+            if let Some(kind) = self.handshake_kind {
+                observer.handshake_complete(kind, data);
+            }
+        });
     }
 
     /// Send any buffered plaintext.  Plaintext is buffered if
@@ -427,6 +809,15 @@ impl CommonState {
 
     /// Send a raw TLS message, fragmenting it if needed.
     pub(crate) fn send_msg(&mut self, m: Message<'_>, must_encrypt: bool) {
+        #[cfg(feature = "state_trace")]
+        if let Some(trace) = &mut self.state_trace {
+            trace.sent(&m.payload);
+        }
+
+        if !matches!(m.payload, MessagePayload::Alert(_)) {
+            self.silent_rejection = false;
+        }
+
         {
             if let Protocol::Quic = self.protocol {
                 if let MessagePayload::Alert(alert) = m.payload {
@@ -438,27 +829,77 @@ impl CommonState {
                     );
                     let mut bytes = Vec::new();
                     m.payload.encode(&mut bytes);
-                    self.quic
-                        .hs_queue
-                        .push_back((must_encrypt, bytes));
+                    if let Some(mutator) = &self.handshake_mutator {
+                        mutator.mutate(&mut bytes);
+                    }
+                    if !bytes.is_empty() {
+                        self.quic
+                            .hs_queue
+                            .push_back((must_encrypt, bytes));
+                    }
                 }
                 return;
             }
         }
+
+        let mut m = PlainMessage::from(m);
+        if let (ContentType::Handshake, Some(mutator)) = (m.typ, &self.handshake_mutator) {
+            let mut bytes = m.payload.into_vec();
+            mutator.mutate(&mut bytes);
+            if bytes.is_empty() {
+                return;
+            }
+            m.payload = Payload::new(bytes);
+        }
+        self.send_plain_msg(m, must_encrypt);
+    }
+
+    fn send_plain_msg(&mut self, m: PlainMessage, must_encrypt: bool) {
         if !must_encrypt {
-            let msg = &m.into();
             let iter = self
                 .message_fragmenter
-                .fragment_message(msg);
+                .fragment_message(&m);
             for m in iter {
+                self.record_layer
+                    .note_unprotected_outgoing(m.typ, m.payload.len());
                 self.queue_tls_message(m.to_unencrypted_opaque());
             }
         } else {
-            self.send_msg_encrypt(m.into());
+            self.send_msg_encrypt(m);
         }
     }
 
+    /// Queues `encoded` to be sent to the peer as a handshake message.
+    ///
+    /// `encoded` is a complete handshake message, including its four-byte header.
+    /// It is sent as-is: it is protected with the current keys (if any), but is
+    /// not included in the handshake transcript, does not change the state of
+    /// this connection, and is not passed to any [`MutatesHandshakeMessages`].
+    ///
+    /// This is intended for negative testing and protocol experiments.  The peer
+    /// is likely to treat the message as an error.
+    ///
+    /// This has no effect on QUIC connections.
+    pub fn dangerous_send_handshake_message(&mut self, encoded: Vec<u8>) {
+        if self.is_quic() {
+            return;
+        }
+
+        let must_encrypt = self.record_layer.is_encrypting();
+        self.send_plain_msg(
+            PlainMessage {
+                typ: ContentType::Handshake,
+                version: ProtocolVersion::TLSv1_2,
+                payload: Payload::new(encoded),
+            },
+            must_encrypt,
+        );
+    }
+
     pub(crate) fn take_received_plaintext(&mut self, bytes: Payload<'_>) {
+        if !bytes.bytes().is_empty() {
+            self.temper_counters.received_app_data();
+        }
         self.received_plaintext
             .append(bytes.into_vec());
     }
@@ -482,11 +923,6 @@ impl CommonState {
         self.send_fatal_alert(AlertDescription::MissingExtension, why)
     }
 
-    fn send_warning_alert(&mut self, desc: AlertDescription) {
-        warn!("Sending warning alert {:?}", desc);
-        self.send_warning_alert_no_log(desc);
-    }
-
     pub(crate) fn process_alert(&mut self, alert: &AlertMessagePayload) -> Result<(), Error> {
         // Reject unknown AlertLevels.
         if let AlertLevel::Unknown(_) = alert.level {
@@ -496,10 +932,28 @@ impl CommonState {
             ));
         }
 
+        self.observe(|observer, data| observer.alert_received(alert.description, data));
+        self.diagnose(
+            match alert.level {
+                AlertLevel::Fatal => DiagnosticLevel::Warn,
+                _ => DiagnosticLevel::Debug,
+            },
+            format_args!("received {:?} alert", alert.level),
+            Some(alert.description),
+            None,
+        );
+
         // If we get a CloseNotify, make a note to declare EOF to our
         // caller.  But do not treat unauthenticated alerts like this.
         if self.may_receive_application_data && alert.description == AlertDescription::CloseNotify {
             self.has_received_close_notify = true;
+            self.audit_close(CloseReason::CloseNotifyReceived, None);
+            if let Some(observer) = &self.shutdown_observer {
+                self.with_user_data(|data| observer.close_notify_received(data));
+            }
+            if self.respond_to_close_notify {
+                self.send_close_notify();
+            }
             return Ok(());
         }
 
@@ -512,11 +966,15 @@ impl CommonState {
             if self.is_tls13() && alert.description != AlertDescription::UserCanceled {
                 return Err(self.send_fatal_alert(AlertDescription::DecodeError, err));
             } else {
-                warn!("TLS alert warning received: {:?}", alert);
+                diagnose!(self, warn, "TLS alert warning received: {:?}", alert);
                 return Ok(());
             }
         }
 
+        self.audit_close(
+            CloseReason::FatalAlertReceived(alert.description),
+            Some(&err),
+        );
         Err(err)
     }
 
@@ -537,10 +995,33 @@ impl CommonState {
         err: impl Into<Error>,
     ) -> Error {
         debug_assert!(!self.sent_fatal_alert);
+        let err = err.into();
+        let desc = match &self.alert_policy {
+            Some(policy) => policy.alert_for(&err, desc),
+            None => desc,
+        };
+        self.sent_fatal_alert = true;
+        if self.silent_rejection {
+            debug!("Not sending fatal alert {desc:?}: rejecting silently");
+            self.diagnose(
+                DiagnosticLevel::Warn,
+                format_args!("rejecting silently instead of sending fatal alert"),
+                Some(desc),
+                Some(&err),
+            );
+            self.audit_close(CloseReason::RejectedSilently, Some(&err));
+            return err;
+        }
+        self.diagnose(
+            DiagnosticLevel::Warn,
+            format_args!("sending fatal alert"),
+            Some(desc),
+            Some(&err),
+        );
         let m = Message::build_alert(AlertLevel::Fatal, desc);
         self.send_msg(m, self.record_layer.is_encrypting());
-        self.sent_fatal_alert = true;
-        err.into()
+        self.audit_close(CloseReason::FatalAlertSent(desc), Some(&err));
+        err
     }
 
     /// Queues a `close_notify` warning alert to be sent in the next
@@ -554,9 +1035,16 @@ impl CommonState {
         if self.sent_fatal_alert {
             return;
         }
-        debug!("Sending warning alert {:?}", AlertDescription::CloseNotify);
+        diagnose!(
+            self,
+            debug,
+            "Sending warning alert {:?}",
+            AlertDescription::CloseNotify
+        );
         self.sent_fatal_alert = true;
+        self.has_sent_close_notify = true;
         self.send_warning_alert_no_log(AlertDescription::CloseNotify);
+        self.audit_close(CloseReason::CloseNotifySent, None);
     }
 
     pub(crate) fn eager_send_close_notify(
@@ -568,6 +1056,33 @@ impl CommonState {
         Ok(self.write_fragments(outgoing_tls, [].into_iter()))
     }
 
+    /// Queues a warning-level `alert` to be sent in the next
+    /// [`Connection::write_tls`] call.
+    ///
+    /// This is normally followed by [`CommonState::send_close_notify()`].
+    ///
+    /// Does nothing if any `close_notify` or fatal alert was already sent.
+    ///
+    /// [`Connection::write_tls`]: crate::Connection::write_tls
+    pub fn send_warning_alert(&mut self, alert: WarningAlert) {
+        if self.sent_fatal_alert {
+            return;
+        }
+        let desc = AlertDescription::from(alert);
+        diagnose!(self, debug, "Sending warning alert {:?}", desc);
+        self.send_warning_alert_no_log(desc);
+    }
+
+    pub(crate) fn eager_send_warning_alert(
+        &mut self,
+        alert: WarningAlert,
+        outgoing_tls: &mut [u8],
+    ) -> Result<usize, EncryptError> {
+        self.send_warning_alert(alert);
+        self.check_required_size(outgoing_tls, [].into_iter())?;
+        Ok(self.write_fragments(outgoing_tls, [].into_iter()))
+    }
+
     fn send_warning_alert_no_log(&mut self, desc: AlertDescription) {
         let m = Message::build_alert(AlertLevel::Warning, desc);
         self.send_msg(m, self.record_layer.is_encrypting());
@@ -622,11 +1137,101 @@ impl CommonState {
         written
     }
 
+    /// Apply the settings in `config` that live in the common state.
+    pub(crate) fn apply_client_config(&mut self, config: &ClientConfig) -> Result<(), Error> {
+        self.set_max_fragment_size(config.max_fragment_size)?;
+        self.set_handshake_timeout(config.handshake_timeout, &config.time_provider)?;
+        self.respond_to_close_notify = config.respond_to_close_notify;
+        self.handshake_mutator = config.handshake_mutator.clone();
+        self.alert_policy = config.alert_policy.clone();
+        self.diagnostics = config.diagnostics.clone();
+        self.audit_sink = config.audit_sink.clone();
+        self.record_layer
+            .set_observer(config.record_observer.clone());
+        self.record_layer
+            .set_key_limits(config.key_limits);
+        self.set_post_handshake_limits(config.post_handshake_limits);
+        self.enable_secret_extraction = config.enable_secret_extraction;
+        Ok(())
+    }
+
+    /// Apply the settings in `config` that live in the common state.
+    ///
+    /// This is used both by [`ServerConnection::new()`][crate::ServerConnection::new]
+    /// and by the [`Acceptor`][crate::server::Acceptor] paths, which must agree.
+    pub(crate) fn apply_server_config(&mut self, config: &ServerConfig) -> Result<(), Error> {
+        self.set_max_fragment_size(config.max_fragment_size)?;
+        self.set_handshake_timeout(config.handshake_timeout, &config.time_provider)?;
+        self.respond_to_close_notify = config.respond_to_close_notify;
+        self.handshake_mutator = config.handshake_mutator.clone();
+        self.alert_policy = config.alert_policy.clone();
+        self.diagnostics = config.diagnostics.clone();
+        self.audit_sink = config.audit_sink.clone();
+        self.silent_rejection = config.silent_rejection;
+        self.record_layer
+            .set_observer(config.record_observer.clone());
+        self.record_layer
+            .set_key_limits(config.key_limits);
+        self.set_post_handshake_limits(config.post_handshake_limits);
+        self.enable_secret_extraction = config.enable_secret_extraction;
+        Ok(())
+    }
+
     pub(crate) fn set_max_fragment_size(&mut self, new: Option<usize>) -> Result<(), Error> {
         self.message_fragmenter
             .set_max_fragment_size(new)
     }
 
+    pub(crate) fn set_post_handshake_limits(&mut self, limits: PostHandshakeLimits) {
+        self.temper_counters.set_limits(limits);
+    }
+
+    /// Arrange for the handshake to fail if it has not completed within `timeout`.
+    pub(crate) fn set_handshake_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+        time_provider: &Arc<dyn TimeProvider>,
+    ) -> Result<(), Error> {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+
+        let now = time_provider
+            .current_time_precise()
+            .ok_or(Error::FailedToGetCurrentTime)?;
+        self.handshake_deadline = Some(HandshakeDeadline {
+            expires_micros: as_micros(now.saturating_add(timeout)),
+            time_provider: Arc::clone(time_provider),
+        });
+        Ok(())
+    }
+
+    /// Fails with [`Error::HandshakeTimedOut`] if the handshake deadline has passed.
+    pub(crate) fn check_handshake_deadline(&self) -> Result<(), Error> {
+        let deadline = match &self.handshake_deadline {
+            Some(deadline) if self.is_handshaking() => deadline,
+            _ => return Ok(()),
+        };
+
+        match deadline
+            .time_provider
+            .current_time_precise()
+        {
+            Some(now) if as_micros(now) > deadline.expires_micros => Err(Error::HandshakeTimedOut),
+            Some(_) => Ok(()),
+            None => Err(Error::FailedToGetCurrentTime),
+        }
+    }
+
+    /// Apply a maximum fragment length negotiated with the peer (RFC6066).
+    pub(crate) fn limit_fragment_len(&mut self, max_fragment_len: MaxFragmentLength) {
+        if let Some(len) = max_fragment_len.fragment_len() {
+            self.message_fragmenter
+                .limit_fragment_len(len);
+        }
+    }
+
     pub(crate) fn get_alpn_protocol(&self) -> Option<&[u8]> {
         self.alpn_protocol
             .as_ref()
@@ -659,9 +1264,16 @@ impl CommonState {
             tls_bytes_to_write: self.sendable_tls.len(),
             plaintext_bytes_to_read: self.received_plaintext.len(),
             peer_has_closed: self.has_received_close_notify,
+            events: self.io_events,
         }
     }
 
+    /// Note that a session ticket from the server was stored.
+    pub(crate) fn note_ticket_received(&mut self) {
+        self.io_events.tickets_received += 1;
+        self.observe(|observer, data| observer.ticket_received(data));
+    }
+
     pub(crate) fn is_quic(&self) -> bool {
         self.protocol == Protocol::Quic
     }
@@ -673,6 +1285,9 @@ impl CommonState {
         self.temper_counters
             .received_key_update_request()?;
 
+        self.io_events.key_updates_received += 1;
+        self.observe(|observer, data| observer.key_update_received(data));
+
         match key_update_request {
             KeyUpdateRequest::UpdateNotRequested => Ok(false),
             KeyUpdateRequest::UpdateRequested => Ok(self.queued_key_update_message.is_none()),
@@ -684,6 +1299,7 @@ impl CommonState {
     }
 
     pub(crate) fn enqueue_key_update_notification(&mut self) {
+        self.observe(|observer, data| observer.key_update_sent(data));
         let message = PlainMessage::from(Message::build_key_update_notify());
         self.queued_key_update_message = Some(
             self.record_layer
@@ -696,6 +1312,11 @@ impl CommonState {
         self.temper_counters
             .received_tls13_change_cipher_spec()
     }
+
+    pub(crate) fn received_new_session_ticket(&mut self) -> Result<(), Error> {
+        self.temper_counters
+            .received_new_session_ticket()
+    }
 }
 
 #[cfg(feature = "std")]
@@ -709,9 +1330,15 @@ impl CommonState {
         &mut self,
         payload: OutboundChunks<'_>,
         sendable_plaintext: &mut ChunkVecBuffer,
-    ) -> usize {
+    ) -> Result<usize, EncryptError> {
         self.perhaps_write_key_update();
-        self.send_plain(payload, Limit::Yes, sendable_plaintext)
+        let is_empty = payload.is_empty();
+        match self.send_plain(payload, Limit::Yes, sendable_plaintext) {
+            0 if !is_empty && self.record_layer.is_encrypt_exhausted() => {
+                Err(EncryptError::EncryptExhausted)
+            }
+            len => Ok(len),
+        }
     }
 
     pub(crate) fn send_early_plaintext(&mut self, data: &[u8]) -> usize {
@@ -751,8 +1378,145 @@ impl CommonState {
     }
 }
 
+/// A warning-level alert that an application may send.
+///
+/// See [`CommonState::send_warning_alert()`].  Only alerts that are
+/// permitted at warning level in all supported protocol versions are
+/// included here.
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WarningAlert {
+    /// `user_canceled`: the handshake is being abandoned for some reason
+    /// unrelated to a protocol failure.
+    UserCanceled,
+}
+
+impl From<WarningAlert> for AlertDescription {
+    fn from(alert: WarningAlert) -> Self {
+        match alert {
+            WarningAlert::UserCanceled => Self::UserCanceled,
+        }
+    }
+}
+
+/// Receives notice of how the peer ended a connection.
+///
+/// Install one of these with [`CommonState::set_shutdown_observer()`].  This
+/// lets applications tell a clean shutdown from a truncated one as it
+/// happens, rather than by later inspecting read results.
+///
+/// Both methods are called synchronously from within rustls, so should
+/// return promptly.
+///
+/// Each method is given the data attached to the connection with
+/// [`CommonState::set_user_data()`], if any.
+pub trait ObservesShutdown: Debug + Send + Sync {
+    /// Called when an authenticated close_notify alert is received from the peer.
+    fn close_notify_received(&self, _user_data: Option<&(dyn Any + Send)>) {}
+
+    /// Called when the transport reaches EOF before the peer sent a close_notify.
+    ///
+    /// This is detected by [`Connection::process_new_packets()`] once EOF has
+    /// been seen by [`Connection::read_tls()`]; users of the unbuffered API
+    /// manage their own transport.
+    ///
+    /// [`Connection::process_new_packets()`]: crate::Connection::process_new_packets
+    /// [`Connection::read_tls()`]: crate::Connection::read_tls
+    fn unexpected_eof(&self, _user_data: Option<&(dyn Any + Send)>) {}
+}
+
+/// Chooses which fatal alert is sent to the peer when a connection fails.
+///
+/// rustls picks an alert for each error it detects; this lets applications
+/// send a different one, for example `access_denied` rather than
+/// `bad_certificate` when a certificate is rejected by local policy.
+///
+/// Install one with [`ClientConfig::alert_policy`] or [`ServerConfig::alert_policy`].
+///
+/// [`ClientConfig::alert_policy`]: crate::ClientConfig::alert_policy
+/// [`ServerConfig::alert_policy`]: crate::ServerConfig::alert_policy
+pub trait ChoosesAlerts: Debug + Send + Sync {
+    /// Return the alert to send to the peer because of `error`.
+    ///
+    /// `alert` is the one rustls would send otherwise.  The connection fails with
+    /// `error` whichever alert is chosen.
+    fn alert_for(&self, error: &Error, alert: AlertDescription) -> AlertDescription;
+}
+
+/// Receives notice of events in the life of a connection.
+///
+/// Install one of these with [`CommonState::set_connection_observer()`].  This lets
+/// applications react to these events as they happen, rather than by checking the
+/// connection's state after each call into rustls.
+///
+/// All methods are called synchronously from within rustls, so should return promptly.
+/// Each is given the data attached to the connection with [`CommonState::set_user_data()`],
+/// if any.
+pub trait ObservesConnection: Debug + Send + Sync {
+    /// Called when the handshake completes, with the kind of handshake that was done.
+    fn handshake_complete(&self, _kind: HandshakeKind, _user_data: Option<&(dyn Any + Send)>) {}
+
+    /// Called when a handshake that resumed a previous session completes, just
+    /// before [`ObservesConnection::handshake_complete()`].
+    fn resumption_accepted(&self, _kind: ResumptionKind, _user_data: Option<&(dyn Any + Send)>) {}
+
+    /// Called when a TLS1.3 `KeyUpdate` message is sent, updating our sending keys.
+    fn key_update_sent(&self, _user_data: Option<&(dyn Any + Send)>) {}
+
+    /// Called when a TLS1.3 `KeyUpdate` message is received from the peer.
+    fn key_update_received(&self, _user_data: Option<&(dyn Any + Send)>) {}
+
+    /// Called when a server sends a session ticket to the client.
+    fn ticket_sent(&self, _user_data: Option<&(dyn Any + Send)>) {}
+
+    /// Called when a client stores a session ticket received from the server.
+    ///
+    /// Tickets refused by a [`Resumption::ticket_filter()`] are not reported.
+    ///
+    /// [`Resumption::ticket_filter()`]: crate::client::Resumption::ticket_filter
+    fn ticket_received(&self, _user_data: Option<&(dyn Any + Send)>) {}
+
+    /// Called when an alert is received from the peer, including `close_notify`.
+    fn alert_received(&self, _alert: AlertDescription, _user_data: Option<&(dyn Any + Send)>) {}
+}
+
+/// Alters handshake messages just before they are sent.
+///
+/// Install one of these with [`DangerousClientConfig::set_handshake_mutator()`] or
+/// [`DangerousServerConfig::set_handshake_mutator()`].  It sees every handshake
+/// message produced by the handshake state machine, including `ClientHello`.
+///
+/// Mutation happens after the message is added to the handshake transcript,
+/// so any change will be detected by the peer when it checks `Finished`.  This
+/// is intended for negative testing and protocol experiments only.
+///
+/// [`DangerousClientConfig::set_handshake_mutator()`]: crate::client::danger::DangerousClientConfig::set_handshake_mutator
+/// [`DangerousServerConfig::set_handshake_mutator()`]: crate::server::danger::DangerousServerConfig::set_handshake_mutator
+pub trait MutatesHandshakeMessages: Debug + Send + Sync {
+    /// Alter `message` in place.
+    ///
+    /// `message` is the complete encoded handshake message, including its
+    /// four-byte header: the first byte is its [`HandshakeType`].  Leaving
+    /// `message` empty stops it from being sent.
+    ///
+    /// [`HandshakeType`]: crate::HandshakeType
+    fn mutate(&self, message: &mut Vec<u8>);
+}
+
+struct HandshakeDeadline {
+    /// Microseconds since the Unix epoch, from [`TimeProvider::current_time_precise()`].
+    ///
+    /// This is smaller than a `Duration`, which keeps connections small.
+    expires_micros: u64,
+    time_provider: Arc<dyn TimeProvider>,
+}
+
+fn as_micros(time: Duration) -> u64 {
+    u64::try_from(time.as_micros()).unwrap_or(u64::MAX)
+}
+
 /// Describes which sort of handshake happened.
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum HandshakeKind {
     /// A full handshake.
     ///
@@ -775,15 +1539,35 @@ pub enum HandshakeKind {
     Resumed,
 }
 
+/// Describes how a resumed handshake located the previous session.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ResumptionKind {
+    /// The session was identified by its TLS1.2 session ID, and found in the
+    /// server's session cache.
+    SessionId,
+
+    /// The session was identified by a ticket.
+    ///
+    /// TLS1.3 resumption always uses tickets.  These may be stateless (the session
+    /// is encrypted in the ticket) or stateful (the ticket is a key into the
+    /// server's session cache).
+    Ticket,
+}
+
 /// Values of this structure are returned from [`Connection::process_new_packets`]
-/// and tell the caller the current I/O state of the TLS connection.
+/// and tell the caller the current I/O state of the TLS connection, and what
+/// happened while processing the packets.
+///
+/// [`Connection::complete_io_with_state`] also returns one of these.
 ///
 /// [`Connection::process_new_packets`]: crate::Connection::process_new_packets
+/// [`Connection::complete_io_with_state`]: crate::Connection::complete_io_with_state
 #[derive(Debug, Eq, PartialEq)]
 pub struct IoState {
     tls_bytes_to_write: usize,
     plaintext_bytes_to_read: usize,
     peer_has_closed: bool,
+    events: IoEvents,
 }
 
 impl IoState {
@@ -812,6 +1596,61 @@ impl IoState {
     pub fn peer_has_closed(&self) -> bool {
         self.peer_has_closed
     }
+
+    /// The kind of handshake, if the handshake completed during this call.
+    ///
+    /// This is `Some` exactly once in the life of a connection.
+    pub fn handshake_completed(&self) -> Option<HandshakeKind> {
+        self.events.handshake_completed
+    }
+
+    /// How many session tickets were received from the server during this call.
+    ///
+    /// These have been stored in the client's [`ClientSessionStore`].
+    ///
+    /// [`ClientSessionStore`]: crate::client::ClientSessionStore
+    pub fn tickets_received(&self) -> usize {
+        self.events.tickets_received
+    }
+
+    /// How many TLS1.3 `KeyUpdate` messages were received from the peer during this call.
+    ///
+    /// Each of these changed the keys used for receiving, and may have queued a
+    /// `KeyUpdate` of our own to send.
+    pub fn key_updates_received(&self) -> usize {
+        self.events.key_updates_received
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn events(&self) -> IoEvents {
+        self.events
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn with_events(mut self, events: IoEvents) -> Self {
+        self.events = events;
+        self
+    }
+}
+
+/// The events reported by an [`IoState`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct IoEvents {
+    handshake_completed: Option<HandshakeKind>,
+    tickets_received: usize,
+    key_updates_received: usize,
+}
+
+impl IoEvents {
+    #[cfg(feature = "std")]
+    /// Combine the events of `self` with the later events of `other`.
+    pub(crate) fn merge(&mut self, other: Self) {
+        self.handshake_completed = self
+            .handshake_completed
+            .or(other.handshake_completed);
+        self.tickets_received += other.tickets_received;
+        self.key_updates_received += other.key_updates_received;
+    }
 }
 
 pub(crate) trait State<Data>: Send + Sync {
@@ -843,6 +1682,11 @@ pub(crate) trait State<Data>: Send + Sync {
     fn handle_decrypt_error(&self) {}
 
     fn into_owned(self: Box<Self>) -> Box<dyn State<Data> + 'static>;
+
+    #[cfg(feature = "state_trace")]
+    fn name(&self) -> &'static str {
+        core::any::type_name::<Self>()
+    }
 }
 
 pub(crate) struct Context<'a, Data> {
@@ -854,7 +1698,7 @@ pub(crate) struct Context<'a, Data> {
 }
 
 /// Side of the connection.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Side {
     /// A client initiates the connection.
     Client,
@@ -883,16 +1727,114 @@ enum Limit {
     No,
 }
 
+/// Limits on the messages a peer may send once the handshake is complete.
+///
+/// The protocol allows these messages at any time, but each one costs some work
+/// to process, so a peer sending many of them may be trying to use up resources.
+/// Exceeding a limit is a fatal error.  Set the limits with the methods here, and
+/// use the result as [`ClientConfig::post_handshake_limits`] or
+/// [`ServerConfig::post_handshake_limits`].
+///
+/// Post-handshake client authentication is not supported, so those messages are
+/// always refused.
+///
+/// [`ClientConfig::post_handshake_limits`]: crate::ClientConfig::post_handshake_limits
+/// [`ServerConfig::post_handshake_limits`]: crate::ServerConfig::post_handshake_limits
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PostHandshakeLimits {
+    max_key_updates: Option<u32>,
+    max_new_session_tickets: Option<u32>,
+    max_renegotiation_requests: Option<u32>,
+}
+
+impl PostHandshakeLimits {
+    /// Accept at most `max` TLS1.3 `KeyUpdate` messages without application data
+    /// between them.
+    ///
+    /// The default is 32.  This is a rate: receiving application data restores the
+    /// full allowance, so a well-behaved peer may update its keys any number of times
+    /// over a long-lived connection.  Exceeding this gives
+    /// [`PeerMisbehaved::TooManyKeyUpdateRequests`].
+    ///
+    /// Removing this limit is dangerous, as each `KeyUpdate` costs a key derivation:
+    /// see [`DangerousClientConfig::set_unlimited_key_updates()`] and
+    /// [`DangerousServerConfig::set_unlimited_key_updates()`].
+    ///
+    /// [`DangerousClientConfig::set_unlimited_key_updates()`]: crate::client::danger::DangerousClientConfig::set_unlimited_key_updates
+    /// [`DangerousServerConfig::set_unlimited_key_updates()`]: crate::server::danger::DangerousServerConfig::set_unlimited_key_updates
+    pub fn max_key_updates(mut self, max: u32) -> Self {
+        self.max_key_updates = Some(max);
+        self
+    }
+
+    /// Accept at most `max` TLS1.3 `NewSessionTicket` messages over the connection.
+    ///
+    /// By default there is no limit.  Exceeding this gives
+    /// [`PeerMisbehaved::TooManyNewSessionTickets`].  This does not apply to servers,
+    /// which never receive these messages.
+    pub fn max_new_session_tickets(mut self, max: u32) -> Self {
+        self.max_new_session_tickets = Some(max);
+        self
+    }
+
+    /// Refuse at most `max` TLS1.2 renegotiation requests over the connection, each
+    /// with a warning alert.
+    ///
+    /// The default is 1.  Exceeding this gives
+    /// [`PeerMisbehaved::TooManyRenegotiationRequests`].
+    pub fn max_renegotiation_requests(mut self, max: u32) -> Self {
+        self.max_renegotiation_requests = Some(max);
+        self
+    }
+
+    pub(crate) fn remove_key_update_limit(&mut self) {
+        self.max_key_updates = None;
+    }
+}
+
+impl Default for PostHandshakeLimits {
+    fn default() -> Self {
+        Self {
+            // cf. BoringSSL `kMaxKeyUpdates`
+            // <https://github.com/google/boringssl/blob/dec5989b793c56ad4dd32173bd2d8595ca78b398/ssl/tls13_both.cc#L35-L38>
+            max_key_updates: Some(32),
+
+            max_new_session_tickets: None,
+
+            // we rebuff renegotiation requests with a `NoRenegotiation` warning alerts.
+            // a second request after this is fatal.
+            max_renegotiation_requests: Some(1),
+        }
+    }
+}
+
 /// Tracking technically-allowed protocol actions
 /// that we limit to avoid denial-of-service vectors.
 struct TemperCounters {
+    limits: PostHandshakeLimits,
     allowed_warning_alerts: u8,
-    allowed_renegotiation_requests: u8,
-    allowed_key_update_requests: u8,
+    allowed_renegotiation_requests: Option<u32>,
+    allowed_key_update_requests: Option<u32>,
+    allowed_new_session_tickets: Option<u32>,
     allowed_middlebox_ccs: u8,
 }
 
 impl TemperCounters {
+    fn set_limits(&mut self, limits: PostHandshakeLimits) {
+        self.limits = limits;
+        self.allowed_renegotiation_requests = limits.max_renegotiation_requests;
+        self.allowed_key_update_requests = limits.max_key_updates;
+        self.allowed_new_session_tickets = limits.max_new_session_tickets;
+    }
+
+    /// A peer that sends application data is making progress, so the `KeyUpdate`s
+    /// peers send routinely as their keys wear out are limited per run rather than
+    /// over the whole connection.  The other allowances are not restored.
+    fn received_app_data(&mut self) {
+        self.allowed_key_update_requests = self.limits.max_key_updates;
+    }
+
     fn received_warning_alert(&mut self) -> Result<(), Error> {
         match self.allowed_warning_alerts {
             0 => Err(PeerMisbehaved::TooManyWarningAlertsReceived.into()),
@@ -904,22 +1846,34 @@ impl TemperCounters {
     }
 
     fn received_renegotiation_request(&mut self) -> Result<(), Error> {
-        match self.allowed_renegotiation_requests {
-            0 => Err(PeerMisbehaved::TooManyRenegotiationRequests.into()),
-            _ => {
-                self.allowed_renegotiation_requests -= 1;
-                Ok(())
-            }
-        }
+        Self::take_allowance(
+            &mut self.allowed_renegotiation_requests,
+            PeerMisbehaved::TooManyRenegotiationRequests,
+        )
     }
 
     fn received_key_update_request(&mut self) -> Result<(), Error> {
-        match self.allowed_key_update_requests {
-            0 => Err(PeerMisbehaved::TooManyKeyUpdateRequests.into()),
-            _ => {
-                self.allowed_key_update_requests -= 1;
+        Self::take_allowance(
+            &mut self.allowed_key_update_requests,
+            PeerMisbehaved::TooManyKeyUpdateRequests,
+        )
+    }
+
+    fn received_new_session_ticket(&mut self) -> Result<(), Error> {
+        Self::take_allowance(
+            &mut self.allowed_new_session_tickets,
+            PeerMisbehaved::TooManyNewSessionTickets,
+        )
+    }
+
+    fn take_allowance(allowed: &mut Option<u32>, exceeded: PeerMisbehaved) -> Result<(), Error> {
+        match allowed {
+            Some(0) => Err(exceeded.into()),
+            Some(allowed) => {
+                *allowed -= 1;
                 Ok(())
             }
+            None => Ok(()),
         }
     }
 
@@ -936,18 +1890,17 @@ impl TemperCounters {
 
 impl Default for TemperCounters {
     fn default() -> Self {
+        let limits = PostHandshakeLimits::default();
         Self {
+            limits,
+
             // cf. BoringSSL `kMaxWarningAlerts`
             // <https://github.com/google/boringssl/blob/dec5989b793c56ad4dd32173bd2d8595ca78b398/ssl/tls_record.cc#L137-L139>
             allowed_warning_alerts: 4,
 
-            // we rebuff renegotiation requests with a `NoRenegotiation` warning alerts.
-            // a second request after this is fatal.
-            allowed_renegotiation_requests: 1,
-
-            // cf. BoringSSL `kMaxKeyUpdates`
-            // <https://github.com/google/boringssl/blob/dec5989b793c56ad4dd32173bd2d8595ca78b398/ssl/tls13_both.cc#L35-L38>
-            allowed_key_update_requests: 32,
+            allowed_renegotiation_requests: limits.max_renegotiation_requests,
+            allowed_key_update_requests: limits.max_key_updates,
+            allowed_new_session_tickets: limits.max_new_session_tickets,
 
             // At most two CCS are allowed: one after each ClientHello (recall a second
             // ClientHello happens after a HelloRetryRequest).
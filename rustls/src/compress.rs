@@ -7,10 +7,13 @@
 //!
 //! # Getting started
 //!
-//! Build this crate with the `brotli` and/or `zlib` crate features.  This
+//! Build this crate with the `brotli`, `zlib` and/or `zstd` crate features.  This
 //! adds dependencies on these crates.  They are used by default if enabled.
 //!
 //! We especially recommend `brotli` as it has the widest deployment so far.
+//! However, `brotli` and `zstd` need the `std` feature, so `no_std` builds
+//! should use `zlib` instead.  `zstd` also builds the zstd C library, so needs a
+//! C compiler for the target.
 //!
 //! # Custom compression/decompression implementations
 //!
@@ -26,6 +29,14 @@
 //! | *Client* | [`ClientConfig::cert_compressors`][cc_cc] | [`ClientConfig::cert_decompressors`][cc_cd] |
 //! | *Server* | [`ServerConfig::cert_decompressors`][sc_cd] | [`ServerConfig::cert_compressors`][sc_cc] |
 //!
+//! # Shared dictionaries
+//!
+//! Where every peer is configured in advance, such as within a fleet of
+//! services, compression can use a dictionary of data common to the certificate
+//! chains in use: see `BrotliDictionary` and `ZstdDictionary`.  These are not
+//! negotiated, so must not be used with peers configured otherwise.  Only the
+//! brotli and zstd algorithms take a dictionary here; zlib is always used without one.
+//!
 //! [rfc8879]: https://datatracker.ietf.org/doc/html/rfc8879
 //! [cc_cc]: crate::ClientConfig::cert_compressors
 //! [sc_cc]: crate::ServerConfig::cert_compressors
@@ -35,11 +46,14 @@
 #[cfg(feature = "std")]
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt::Debug;
+use core::time::Duration;
 #[cfg(feature = "std")]
 use std::sync::Mutex;
 
+use crate::crypto;
 use crate::enums::CertificateCompressionAlgorithm;
 use crate::msgs::base::{Payload, PayloadU24};
 use crate::msgs::codec::Codec;
@@ -53,6 +67,8 @@ pub fn default_cert_decompressors() -> &'static [&'static dyn CertDecompressor]
         BROTLI_DECOMPRESSOR,
         #[cfg(feature = "zlib")]
         ZLIB_DECOMPRESSOR,
+        #[cfg(feature = "zstd")]
+        ZSTD_DECOMPRESSOR,
     ]
 }
 
@@ -66,10 +82,104 @@ pub trait CertDecompressor: Debug + Send + Sync {
     /// bytes than fit in `output`, or if the `input` is in any way malformed.
     fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed>;
 
+    /// Decompress `input`, writing the result to `output` as it is produced.
+    ///
+    /// This is what rustls calls.  Unlike [`CertDecompressor::decompress()`],
+    /// it does not need the whole output allocated up front, so a peer cannot
+    /// force a large allocation just by declaring a large decompressed length.
+    /// `output` refuses writes beyond the declared length, and decompression
+    /// fails unless exactly that much is written.
+    ///
+    /// The default implementation allocates the declared length and calls
+    /// [`CertDecompressor::decompress()`].
+    fn decompress_incremental(
+        &self,
+        input: &[u8],
+        output: &mut DecompressionOutput,
+    ) -> Result<(), DecompressionFailed> {
+        let mut buf = vec![0u8; output.declared_len()];
+        self.decompress(input, &mut buf)?;
+        output.write(&buf)
+    }
+
     /// Which algorithm this decompressor handles.
     fn algorithm(&self) -> CertificateCompressionAlgorithm;
 }
 
+/// Decompress `compressed` using `decompressor`, into exactly its declared length.
+pub(crate) fn decompress(
+    decompressor: &dyn CertDecompressor,
+    compressed: &CompressedCertificatePayload<'_>,
+) -> Result<Vec<u8>, DecompressionFailed> {
+    let mut output = DecompressionOutput::new(compressed.uncompressed_len as usize);
+    decompressor.decompress_incremental(compressed.compressed.0.bytes(), &mut output)?;
+    output.finish()
+}
+
+/// Reorder `items` so those for the algorithms in `preference` come first, in that order.
+///
+/// The rest keep their relative order.
+pub(crate) fn prefer_algorithms<T>(
+    items: &mut [T],
+    preference: &[CertificateCompressionAlgorithm],
+    algorithm: impl Fn(&T) -> CertificateCompressionAlgorithm,
+) {
+    items.sort_by_key(|item| {
+        let algorithm = algorithm(item);
+        preference
+            .iter()
+            .position(|preferred| *preferred == algorithm)
+            .unwrap_or(preference.len())
+    });
+}
+
+/// The output of [`CertDecompressor::decompress_incremental()`].
+///
+/// This grows as it is written to, up to the declared length of the
+/// decompressed data.
+#[derive(Debug)]
+pub struct DecompressionOutput {
+    buf: Vec<u8>,
+    declared_len: usize,
+}
+
+impl DecompressionOutput {
+    pub(crate) fn new(declared_len: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            declared_len,
+        }
+    }
+
+    /// Append `data` to the output.
+    ///
+    /// This fails, writing nothing, if the output would exceed the declared length.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), DecompressionFailed> {
+        if data.len() > self.declared_len - self.buf.len() {
+            return Err(DecompressionFailed);
+        }
+        self.buf.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// The length the peer declared for the decompressed data.
+    pub fn declared_len(&self) -> usize {
+        self.declared_len
+    }
+
+    /// How much has been written so far.
+    pub fn written_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn finish(self) -> Result<Vec<u8>, DecompressionFailed> {
+        match self.buf.len() == self.declared_len {
+            true => Ok(self.buf),
+            false => Err(DecompressionFailed),
+        }
+    }
+}
+
 /// Returns the supported `CertCompressor` implementations enabled
 /// by crate features.
 pub fn default_cert_compressors() -> &'static [&'static dyn CertCompressor] {
@@ -78,6 +188,8 @@ pub fn default_cert_compressors() -> &'static [&'static dyn CertCompressor] {
         BROTLI_COMPRESSOR,
         #[cfg(feature = "zlib")]
         ZLIB_COMPRESSOR,
+        #[cfg(feature = "zstd")]
+        ZSTD_COMPRESSOR,
     ]
 }
 
@@ -115,6 +227,140 @@ pub enum CompressionLevel {
     Amortized,
 }
 
+/// Decides, for each connection, whether and how a server compresses its certificate.
+///
+/// Install one of these as [`ServerConfig::cert_compression_policy`] to make the
+/// choice depend on the connection: for example, to avoid a slow algorithm for
+/// latency-sensitive server names.
+///
+/// [`ServerConfig::cert_compression_policy`]: crate::ServerConfig::cert_compression_policy
+pub trait ChoosesCertCompression: Debug + Send + Sync {
+    /// Choose how to compress the certificate for the connection described by `offer`.
+    ///
+    /// Return `None` to send the certificate uncompressed.  A choice of compressor
+    /// whose algorithm the client did not offer is ignored, and the certificate is
+    /// sent uncompressed.
+    ///
+    /// [`CertCompressionOffer::default_choice()`] gives the choice made when there
+    /// is no policy.
+    fn choose_compression(&self, offer: &CertCompressionOffer<'_>)
+        -> Option<CertCompressionChoice>;
+}
+
+/// A connection's certificate compression options, given to [`ChoosesCertCompression`].
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct CertCompressionOffer<'a> {
+    /// The compression algorithms offered by the client, in the order it sent them.
+    ///
+    /// The client's order does not denote any preference.
+    pub offered: &'a [CertificateCompressionAlgorithm],
+
+    /// The compressors configured in [`ServerConfig::cert_compressors`].
+    ///
+    /// [`ServerConfig::cert_compressors`]: crate::ServerConfig::cert_compressors
+    pub compressors: &'a [&'static dyn CertCompressor],
+
+    /// The server name indication sent by the client, if any.
+    pub server_name: Option<&'a str>,
+
+    /// The ALPN protocol agreed for the connection, if any.
+    pub alpn_protocol: Option<&'a [u8]>,
+}
+
+impl CertCompressionOffer<'_> {
+    /// The first of [`CertCompressionOffer::compressors`] that the client offered,
+    /// at the level chosen by [`ServerConfig::cert_compression_cache`].
+    ///
+    /// [`ServerConfig::cert_compression_cache`]: crate::ServerConfig::cert_compression_cache
+    pub fn default_choice(&self) -> Option<CertCompressionChoice> {
+        // prefer server order: the client's extension does not denote any preference.
+        self.compressors
+            .iter()
+            .find(|compressor| {
+                self.offered
+                    .contains(&compressor.algorithm())
+            })
+            .map(|&compressor| CertCompressionChoice {
+                compressor,
+                level: None,
+            })
+    }
+}
+
+/// How to compress a certificate, chosen by [`ChoosesCertCompression`].
+#[derive(Clone, Copy, Debug)]
+pub struct CertCompressionChoice {
+    /// The compressor to use.
+    pub compressor: &'static dyn CertCompressor,
+
+    /// The level to compress at.
+    ///
+    /// `None` leaves this to [`ServerConfig::cert_compression_cache`]: cached
+    /// compressions use [`CompressionLevel::Amortized`], and others use
+    /// [`CompressionLevel::Interactive`].  [`CompressionLevel::Interactive`] is
+    /// never cached, and [`CompressionLevel::Amortized`] is cached if the cache
+    /// is enabled.
+    ///
+    /// [`ServerConfig::cert_compression_cache`]: crate::ServerConfig::cert_compression_cache
+    pub level: Option<CompressionLevel>,
+}
+
+/// What happened when a certificate was compressed or decompressed during a handshake.
+///
+/// Get these from [`CommonState::sent_cert_compression()`] and
+/// [`CommonState::received_cert_compression()`].
+///
+/// [`CommonState::sent_cert_compression()`]: crate::CommonState::sent_cert_compression
+/// [`CommonState::received_cert_compression()`]: crate::CommonState::received_cert_compression
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CertCompressionStats {
+    /// The compression algorithm used.
+    pub algorithm: CertificateCompressionAlgorithm,
+
+    /// The length of the certificate message before compression.
+    pub uncompressed_len: usize,
+
+    /// The length of the compressed certificate.
+    pub compressed_len: usize,
+
+    /// How long compressing or decompressing took, including any cache lookup.
+    ///
+    /// This is only measured with the `std` crate feature.  A compression found
+    /// in a cache takes almost no time.
+    pub elapsed: Option<Duration>,
+}
+
+impl CertCompressionStats {
+    pub(crate) fn new(
+        compressed: &CompressedCertificatePayload<'_>,
+        elapsed: Option<Duration>,
+    ) -> Self {
+        Self {
+            algorithm: compressed.alg,
+            uncompressed_len: compressed.uncompressed_len as usize,
+            compressed_len: compressed.compressed.0.bytes().len(),
+            elapsed,
+        }
+    }
+}
+
+/// Call `f`, and measure how long it takes if the `std` crate feature is enabled.
+pub(crate) fn timed<T>(f: impl FnOnce() -> T) -> (T, Option<Duration>) {
+    #[cfg(feature = "std")]
+    {
+        let start = std::time::Instant::now();
+        let result = f();
+        (result, Some(start.elapsed()))
+    }
+
+    #[cfg(not(feature = "std"))]
+    {
+        (f(), None)
+    }
+}
+
 /// A content-less error for when `CertDecompressor::decompress` fails.
 #[derive(Debug)]
 pub struct DecompressionFailed;
@@ -145,11 +391,48 @@ mod feat_zlib_rs {
             }
         }
 
+        fn decompress_incremental(
+            &self,
+            input: &[u8],
+            output: &mut DecompressionOutput,
+        ) -> Result<(), DecompressionFailed> {
+            inflate_growing(input, output.declared_len(), |decompressed| {
+                output.write(decompressed)
+            })
+        }
+
         fn algorithm(&self) -> CertificateCompressionAlgorithm {
             CertificateCompressionAlgorithm::Zlib
         }
     }
 
+    /// Inflate `input`, of at most `max_len` bytes, and give the result to `f`.
+    ///
+    /// `zlib-rs` only streams through `unsafe` functions, so this instead inflates
+    /// into a buffer which starts small and doubles whenever it is outgrown.  That
+    /// allocates at most twice the actual decompressed length, however large
+    /// `max_len` is, at the cost of repeating some work for large outputs.
+    fn inflate_growing(
+        input: &[u8],
+        max_len: usize,
+        f: impl FnOnce(&[u8]) -> Result<(), DecompressionFailed>,
+    ) -> Result<(), DecompressionFailed> {
+        let mut buf = alloc::vec![0u8; Ord::min(max_len, BUFFER_SIZE)];
+        loop {
+            match inflate::uncompress_slice(&mut buf, input, inflate::InflateConfig::default()) {
+                (output_filled, ReturnCode::Ok) => return f(output_filled),
+                // the output did not fit
+                (_, ReturnCode::BufError) if buf.len() < max_len => {
+                    let len = Ord::min(max_len, buf.len().saturating_mul(2));
+                    buf.resize(len, 0);
+                }
+                (_, _) => return Err(DecompressionFailed),
+            }
+        }
+    }
+
+    const BUFFER_SIZE: usize = 4096;
+
     /// A certificate compressor for the Zlib algorithm using the `zlib-rs` crate.
     pub const ZLIB_COMPRESSOR: &dyn CertCompressor = &ZlibRsCompressor;
 
@@ -186,9 +469,13 @@ mod feat_zlib_rs {
 #[cfg(feature = "zlib")]
 pub use feat_zlib_rs::{ZLIB_COMPRESSOR, ZLIB_DECOMPRESSOR};
 
+// the `brotli` feature enables `std`, which the streaming implementation needs
 #[cfg(feature = "brotli")]
 mod feat_brotli {
-    use std::io::{Cursor, Write};
+    use alloc::borrow::Cow;
+    use std::io::{self, Cursor, Read, Write};
+
+    use brotli::enc::{BrotliEncoderParams, StandardAlloc};
 
     use super::*;
 
@@ -213,6 +500,14 @@ mod feat_brotli {
             Ok(())
         }
 
+        fn decompress_incremental(
+            &self,
+            input: &[u8],
+            output: &mut DecompressionOutput,
+        ) -> Result<(), DecompressionFailed> {
+            read_all(brotli::Decompressor::new(input, BUFFER_SIZE), output)
+        }
+
         fn algorithm(&self) -> CertificateCompressionAlgorithm {
             CertificateCompressionAlgorithm::Brotli
         }
@@ -247,6 +542,165 @@ mod feat_brotli {
         }
     }
 
+    /// Brotli compression and decompression using a dictionary agreed in advance.
+    ///
+    /// A dictionary holding data common to the certificate chains in use, such as
+    /// the CA certificates that issue them, can make compressed chains much smaller.
+    ///
+    /// The dictionary is not negotiated: compressed chains use the usual brotli
+    /// algorithm identifier, and can only be decompressed by a peer configured with
+    /// the same dictionary.  So only use this where all peers are configured in
+    /// advance, and in place of [`BROTLI_COMPRESSOR`] and [`BROTLI_DECOMPRESSOR`].
+    ///
+    /// ```
+    /// use rustls::compress::BrotliDictionary;
+    ///
+    /// static DICTIONARY: BrotliDictionary = BrotliDictionary::new(b"...");
+    ///
+    /// # fn configure(config: &mut rustls::ClientConfig) {
+    /// config.cert_compressors = vec![&DICTIONARY];
+    /// config.cert_decompressors = vec![&DICTIONARY];
+    /// # }
+    /// ```
+    pub struct BrotliDictionary {
+        dictionary: &'static [u8],
+    }
+
+    impl BrotliDictionary {
+        /// Compress and decompress using `dictionary`.
+        pub const fn new(dictionary: &'static [u8]) -> Self {
+            Self { dictionary }
+        }
+
+        fn decompressor<'a>(&self, input: &'a [u8]) -> impl Read + 'a {
+            brotli::reader::DecompressorCustomAlloc::new_with_custom_dictionary(
+                input,
+                DictionaryMemory(Cow::Owned(vec![0u8; BUFFER_SIZE])),
+                DictionaryAlloc,
+                StandardAlloc::default(),
+                StandardAlloc::default(),
+                DictionaryMemory(Cow::Borrowed(self.dictionary)),
+            )
+        }
+    }
+
+    /// Allocates memory for a brotli decompressor using a [`BrotliDictionary`].
+    struct DictionaryAlloc;
+
+    impl brotli::Allocator<u8> for DictionaryAlloc {
+        type AllocatedMemory = DictionaryMemory;
+
+        fn alloc_cell(&mut self, len: usize) -> DictionaryMemory {
+            DictionaryMemory(Cow::Owned(vec![0u8; len]))
+        }
+
+        fn free_cell(&mut self, _data: DictionaryMemory) {}
+    }
+
+    /// Memory for a brotli decompressor, which can also borrow the dictionary.
+    ///
+    /// The decompressor only reads its dictionary, so borrowing the `'static`
+    /// dictionary saves copying it for every decompression.  Should it ever be
+    /// written to, it is copied first.
+    #[derive(Default)]
+    struct DictionaryMemory(Cow<'static, [u8]>);
+
+    impl brotli::SliceWrapper<u8> for DictionaryMemory {
+        fn slice(&self) -> &[u8] {
+            &self.0
+        }
+    }
+
+    impl brotli::SliceWrapperMut<u8> for DictionaryMemory {
+        fn slice_mut(&mut self) -> &mut [u8] {
+            self.0.to_mut()
+        }
+    }
+
+    impl CertDecompressor for BrotliDictionary {
+        fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed> {
+            let mut decompressor = self.decompressor(input);
+            decompressor
+                .read_exact(output)
+                .map_err(|_| DecompressionFailed)?;
+
+            match decompressor.read(&mut [0u8]) {
+                Ok(0) => Ok(()),
+                _ => Err(DecompressionFailed),
+            }
+        }
+
+        fn decompress_incremental(
+            &self,
+            input: &[u8],
+            output: &mut DecompressionOutput,
+        ) -> Result<(), DecompressionFailed> {
+            read_all(self.decompressor(input), output)
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            CertificateCompressionAlgorithm::Brotli
+        }
+    }
+
+    impl CertCompressor for BrotliDictionary {
+        fn compress(
+            &self,
+            input: Vec<u8>,
+            level: CompressionLevel,
+        ) -> Result<Vec<u8>, CompressionFailed> {
+            let params = BrotliEncoderParams {
+                quality: match level {
+                    CompressionLevel::Interactive => QUALITY_FAST,
+                    CompressionLevel::Amortized => QUALITY_SLOW,
+                } as i32,
+                lgwin: LGWIN as i32,
+                ..BrotliEncoderParams::default()
+            };
+
+            let mut output = Vec::with_capacity(input.len() / 2);
+            brotli::BrotliCompressCustomIoCustomDict(
+                &mut brotli::IoReaderWrapper(&mut &input[..]),
+                &mut brotli::IoWriterWrapper(&mut output),
+                &mut [0u8; BUFFER_SIZE],
+                &mut [0u8; BUFFER_SIZE],
+                &params,
+                StandardAlloc::default(),
+                &mut |_, _, _, _| (),
+                self.dictionary,
+                io::Error::from(io::ErrorKind::UnexpectedEof),
+            )
+            .map_err(|_| CompressionFailed)?;
+            Ok(output)
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            CertificateCompressionAlgorithm::Brotli
+        }
+    }
+
+    impl Debug for BrotliDictionary {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("BrotliDictionary")
+                .field("len", &self.dictionary.len())
+                .finish()
+        }
+    }
+
+    fn read_all(
+        mut decompressor: impl Read,
+        output: &mut DecompressionOutput,
+    ) -> Result<(), DecompressionFailed> {
+        let mut chunk = [0u8; BUFFER_SIZE];
+        loop {
+            match decompressor.read(&mut chunk) {
+                Ok(0) => return Ok(()),
+                Ok(len) => output.write(&chunk[..len])?,
+                Err(_) => return Err(DecompressionFailed),
+            }
+        }
+    }
+
     /// Brotli buffer size.
     ///
     /// Chosen based on brotli `examples/compress.rs`.
@@ -264,7 +718,204 @@ mod feat_brotli {
 }
 
 #[cfg(feature = "brotli")]
-pub use feat_brotli::{BROTLI_COMPRESSOR, BROTLI_DECOMPRESSOR};
+pub use feat_brotli::{BrotliDictionary, BROTLI_COMPRESSOR, BROTLI_DECOMPRESSOR};
+
+// the `zstd` feature enables `std`, which the `zstd` crate needs
+#[cfg(feature = "zstd")]
+mod feat_zstd {
+    use std::io::Read;
+
+    use super::*;
+
+    /// A certificate decompressor for the Zstd algorithm using the `zstd` crate.
+    pub const ZSTD_DECOMPRESSOR: &dyn CertDecompressor = &ZstdDecompressor;
+
+    #[derive(Debug)]
+    struct ZstdDecompressor;
+
+    impl CertDecompressor for ZstdDecompressor {
+        fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed> {
+            decompress(input, output, &[])
+        }
+
+        fn decompress_incremental(
+            &self,
+            input: &[u8],
+            output: &mut DecompressionOutput,
+        ) -> Result<(), DecompressionFailed> {
+            decompress_incremental(input, output, &[])
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            CertificateCompressionAlgorithm::Zstd
+        }
+    }
+
+    /// A certificate compressor for the Zstd algorithm using the `zstd` crate.
+    pub const ZSTD_COMPRESSOR: &dyn CertCompressor = &ZstdCompressor;
+
+    #[derive(Debug)]
+    struct ZstdCompressor;
+
+    impl CertCompressor for ZstdCompressor {
+        fn compress(
+            &self,
+            input: Vec<u8>,
+            level: CompressionLevel,
+        ) -> Result<Vec<u8>, CompressionFailed> {
+            compress(&input, level, &[])
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            CertificateCompressionAlgorithm::Zstd
+        }
+    }
+
+    /// Zstd compression and decompression using a dictionary agreed in advance.
+    ///
+    /// The dictionary is not negotiated: compressed chains use the usual zstd
+    /// algorithm identifier, and can only be decompressed by a peer configured with
+    /// the same dictionary.  So only use this where all peers are configured in
+    /// advance, and in place of [`ZSTD_COMPRESSOR`] and [`ZSTD_DECOMPRESSOR`].
+    ///
+    /// Zstd's own dictionary format, as made by `zstd --train`, can be used as
+    /// well as raw data.
+    pub struct ZstdDictionary {
+        dictionary: &'static [u8],
+    }
+
+    impl ZstdDictionary {
+        /// Compress and decompress using `dictionary`.
+        pub const fn new(dictionary: &'static [u8]) -> Self {
+            Self { dictionary }
+        }
+    }
+
+    impl CertDecompressor for ZstdDictionary {
+        fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed> {
+            decompress(input, output, self.dictionary)
+        }
+
+        fn decompress_incremental(
+            &self,
+            input: &[u8],
+            output: &mut DecompressionOutput,
+        ) -> Result<(), DecompressionFailed> {
+            decompress_incremental(input, output, self.dictionary)
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            CertificateCompressionAlgorithm::Zstd
+        }
+    }
+
+    impl CertCompressor for ZstdDictionary {
+        fn compress(
+            &self,
+            input: Vec<u8>,
+            level: CompressionLevel,
+        ) -> Result<Vec<u8>, CompressionFailed> {
+            compress(&input, level, self.dictionary)
+        }
+
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            CertificateCompressionAlgorithm::Zstd
+        }
+    }
+
+    impl Debug for ZstdDictionary {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("ZstdDictionary")
+                .field("len", &self.dictionary.len())
+                .finish()
+        }
+    }
+
+    fn decompress(
+        input: &[u8],
+        output: &mut [u8],
+        dictionary: &[u8],
+    ) -> Result<(), DecompressionFailed> {
+        // this decompresses directly into `output`, so needs no other window
+        let len = zstd::bulk::Decompressor::with_dictionary(dictionary)
+            .and_then(|mut decompressor| decompressor.decompress_to_buffer(input, output))
+            .map_err(|_| DecompressionFailed)?;
+
+        match len == output.len() {
+            true => Ok(()),
+            false => Err(DecompressionFailed),
+        }
+    }
+
+    fn decompress_incremental(
+        input: &[u8],
+        output: &mut DecompressionOutput,
+        dictionary: &[u8],
+    ) -> Result<(), DecompressionFailed> {
+        let mut decoder = zstd::stream::read::Decoder::with_dictionary(input, dictionary)
+            .map_err(|_| DecompressionFailed)?;
+        decoder
+            .window_log_max(window_log(output.declared_len(), dictionary))
+            .map_err(|_| DecompressionFailed)?;
+        let mut chunk = [0u8; BUFFER_SIZE];
+        loop {
+            match decoder.read(&mut chunk) {
+                Ok(0) => return Ok(()),
+                Ok(len) => output.write(&chunk[..len])?,
+                Err(_) => return Err(DecompressionFailed),
+            }
+        }
+    }
+
+    fn compress(
+        input: &[u8],
+        level: CompressionLevel,
+        dictionary: &[u8],
+    ) -> Result<Vec<u8>, CompressionFailed> {
+        let level = match level {
+            CompressionLevel::Interactive => LEVEL_FAST,
+            CompressionLevel::Amortized => LEVEL_SLOW,
+        };
+        zstd::bulk::Compressor::with_dictionary(level, dictionary)
+            .and_then(|mut compressor| {
+                compressor.window_log(window_log(input.len(), dictionary))?;
+                compressor.compress(input)
+            })
+            .map_err(|_| CompressionFailed)
+    }
+
+    /// The window size, as a power of two, for `len` bytes of data using `dictionary`.
+    ///
+    /// The window need be no larger than the data and dictionary, and this bounds
+    /// how much memory a peer can make incremental decompression use.
+    fn window_log(len: usize, dictionary: &[u8]) -> u32 {
+        let len = len.saturating_add(dictionary.len());
+        let log = usize::BITS - len.saturating_sub(1).leading_zeros();
+        log.clamp(MIN_WINDOW_LOG, MAX_WINDOW_LOG)
+    }
+
+    /// Size of the chunks read during incremental decompression.
+    const BUFFER_SIZE: usize = 4096;
+
+    /// The smallest window zstd supports.
+    const MIN_WINDOW_LOG: u32 = 10;
+
+    /// The largest window we compress or decompress with: 128KiB, which is more
+    /// than most certificate chains need.
+    const MAX_WINDOW_LOG: u32 = 17;
+
+    /// Compression level we use for interactive compressions (zstd's default).
+    const LEVEL_FAST: i32 = 3;
+
+    /// Compression level we use for offline compressions.
+    ///
+    /// This is the highest level that does not need zstd's "ultra" mode, whose
+    /// larger windows would make decompression need more memory.
+    const LEVEL_SLOW: i32 = 19;
+}
+
+#[cfg(feature = "zstd")]
+pub use feat_zstd::{ZstdDictionary, ZSTD_COMPRESSOR, ZSTD_DECOMPRESSOR};
 
 /// An LRU cache for compressions.
 ///
@@ -323,13 +974,33 @@ impl CompressionCache {
         original: &CertificatePayloadTls13<'_>,
     ) -> Result<Arc<CompressionCacheEntry>, CompressionFailed> {
         match self {
-            Self::Disabled => Self::uncached_compression(compressor, original),
+            Self::Disabled => {
+                Self::uncached_compression(compressor, CompressionLevel::Interactive, original)
+            }
 
             #[cfg(feature = "std")]
             Self::Enabled(_) => self.compression_for_impl(compressor, original),
         }
     }
 
+    /// Like `compression_for`, but compressing at `level`.
+    ///
+    /// Only `Amortized` compressions are cached.
+    pub(crate) fn compression_at(
+        &self,
+        compressor: &dyn CertCompressor,
+        level: CompressionLevel,
+        original: &CertificatePayloadTls13<'_>,
+    ) -> Result<Arc<CompressionCacheEntry>, CompressionFailed> {
+        match (self, level) {
+            #[cfg(feature = "std")]
+            (Self::Enabled(_), CompressionLevel::Amortized) => {
+                self.compression_for_impl(compressor, original)
+            }
+            _ => Self::uncached_compression(compressor, level, original),
+        }
+    }
+
     #[cfg(feature = "std")]
     fn compression_for_impl(
         &self,
@@ -344,7 +1015,7 @@ impl CompressionCache {
         // context is a per-connection quantity, and included in the compressed data.
         // it is not suitable for inclusion in the cache.
         if !original.context.0.is_empty() {
-            return Self::uncached_compression(compressor, original);
+            return Self::uncached_compression(compressor, CompressionLevel::Interactive, original);
         }
 
         // cache probe:
@@ -388,15 +1059,16 @@ impl CompressionCache {
         Ok(new_entry)
     }
 
-    /// Compress `original` using `compressor` at `Interactive` level.
+    /// Compress `original` using `compressor` at `level`.
     fn uncached_compression(
         compressor: &dyn CertCompressor,
+        level: CompressionLevel,
         original: &CertificatePayloadTls13<'_>,
     ) -> Result<Arc<CompressionCacheEntry>, CompressionFailed> {
         let algorithm = compressor.algorithm();
         let encoding = original.get_encoding();
         let uncompressed_len = encoding.len() as u32;
-        let compressed = compressor.compress(encoding, CompressionLevel::Interactive)?;
+        let compressed = compressor.compress(encoding, level)?;
 
         // this `CompressionCacheEntry` in fact never makes it into the cache, so
         // `original` is left empty
@@ -444,7 +1116,178 @@ impl CompressionCacheEntry {
     }
 }
 
-#[cfg(all(test, any(feature = "brotli", feature = "zlib")))]
+/// An LRU cache for decompressions.
+///
+/// Servers tend to send the same compressed certificate chain to every client,
+/// so a client that makes repeated connections to a server can avoid decompressing
+/// that chain each time.  Entries are keyed by the compression algorithm and a hash
+/// of the compressed data.
+///
+/// Only decompression is avoided: the chain is still verified for every connection.
+#[derive(Debug)]
+pub enum DecompressionCache {
+    /// No caching happens, and decompression happens each time.
+    Disabled,
+
+    /// Decompressions are stored in an LRU cache.
+    #[cfg(feature = "std")]
+    Enabled(DecompressionCacheInner),
+}
+
+/// Innards of an enabled DecompressionCache.
+///
+/// You cannot make one of these directly. Use [`DecompressionCache::new`].
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct DecompressionCacheInner {
+    /// Maximum size of underlying storage.
+    size: usize,
+
+    /// LRU-order entries.
+    ///
+    /// First is least-used, last is most-used.
+    entries: Mutex<VecDeque<Arc<DecompressionCacheEntry>>>,
+}
+
+impl DecompressionCache {
+    /// Make a `DecompressionCache` that stores up to `size` decompressed
+    /// certificate messages.
+    #[cfg(feature = "std")]
+    pub fn new(size: usize) -> Self {
+        if size == 0 {
+            return Self::Disabled;
+        }
+
+        Self::Enabled(DecompressionCacheInner {
+            size,
+            entries: Mutex::new(VecDeque::with_capacity(size)),
+        })
+    }
+
+    /// Return a `DecompressionCacheEntry`, which holds the decompression of `compressed`.
+    ///
+    /// `decompressor` must match the algorithm of `compressed`.  `hash` is used to
+    /// identify `compressed` in the cache.
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    pub(crate) fn decompression_for(
+        &self,
+        decompressor: &dyn CertDecompressor,
+        hash: &dyn crypto::hash::Hash,
+        compressed: &CompressedCertificatePayload<'_>,
+    ) -> Result<Arc<DecompressionCacheEntry>, DecompressionFailed> {
+        match self {
+            Self::Disabled => Self::uncached_decompression(decompressor, compressed),
+
+            #[cfg(feature = "std")]
+            Self::Enabled(_) => self.decompression_for_impl(decompressor, hash, compressed),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn decompression_for_impl(
+        &self,
+        decompressor: &dyn CertDecompressor,
+        hash: &dyn crypto::hash::Hash,
+        compressed: &CompressedCertificatePayload<'_>,
+    ) -> Result<Arc<DecompressionCacheEntry>, DecompressionFailed> {
+        let (max_size, entries) = match self {
+            Self::Enabled(DecompressionCacheInner { size, entries }) => (*size, entries),
+            _ => unreachable!(),
+        };
+
+        // cache probe:
+        let hash_algorithm = hash.algorithm();
+        let digest = hash
+            .hash(compressed.compressed.0.bytes())
+            .as_ref()
+            .to_vec();
+
+        let mut cache = entries
+            .lock()
+            .map_err(|_| DecompressionFailed)?;
+        for (i, item) in cache.iter().enumerate() {
+            if item.algorithm == compressed.alg
+                && item.hash_algorithm == Some(hash_algorithm)
+                && item.digest == digest
+                && item.decompressed.len() == compressed.uncompressed_len as usize
+            {
+                // this item is now MRU
+                let item = cache.remove(i).unwrap();
+                cache.push_back(Arc::clone(&item));
+                return Ok(item);
+            }
+        }
+        drop(cache);
+
+        // do decompression:
+        let mut new_entry = Self::uncached_decompression(decompressor, compressed)?;
+        if let Some(entry) = Arc::get_mut(&mut new_entry) {
+            entry.hash_algorithm = Some(hash_algorithm);
+            entry.digest = digest;
+        }
+
+        // insert into cache
+        let mut cache = entries
+            .lock()
+            .map_err(|_| DecompressionFailed)?;
+        if cache.len() == max_size {
+            cache.pop_front();
+        }
+        cache.push_back(Arc::clone(&new_entry));
+        Ok(new_entry)
+    }
+
+    /// Decompress `compressed` using `decompressor`.
+    fn uncached_decompression(
+        decompressor: &dyn CertDecompressor,
+        compressed: &CompressedCertificatePayload<'_>,
+    ) -> Result<Arc<DecompressionCacheEntry>, DecompressionFailed> {
+        let decompressed = decompress(decompressor, compressed)?;
+
+        // the cache key is filled in by the caller if this entry is to be cached
+        Ok(Arc::new(DecompressionCacheEntry {
+            algorithm: compressed.alg,
+            hash_algorithm: None,
+            digest: Vec::new(),
+            decompressed,
+        }))
+    }
+}
+
+impl Default for DecompressionCache {
+    fn default() -> Self {
+        #[cfg(feature = "std")]
+        {
+            // 4 entries allows 2 certificate chains times 2 compression algorithms
+            Self::new(4)
+        }
+
+        #[cfg(not(feature = "std"))]
+        {
+            Self::Disabled
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+#[derive(Debug)]
+pub(crate) struct DecompressionCacheEntry {
+    // cache key is algorithm + hash of compressed data:
+    algorithm: CertificateCompressionAlgorithm,
+    hash_algorithm: Option<crypto::hash::HashAlgorithm>,
+    digest: Vec<u8>,
+
+    // cache value is decompression result:
+    decompressed: Vec<u8>,
+}
+
+impl DecompressionCacheEntry {
+    pub(crate) fn decompressed(&self) -> &[u8] {
+        &self.decompressed
+    }
+}
+
+#[cfg(all(test, any(feature = "brotli", feature = "zlib", feature = "zstd")))]
 pub mod tests {
     use std::{println, vec};
 
@@ -462,6 +1305,120 @@ pub mod tests {
         test_compressor(BROTLI_COMPRESSOR, BROTLI_DECOMPRESSOR);
     }
 
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_zstd() {
+        test_compressor(ZSTD_COMPRESSOR, ZSTD_DECOMPRESSOR);
+    }
+
+    #[test]
+    #[cfg(feature = "brotli")]
+    fn test_brotli_dictionary() {
+        static DICTIONARY: BrotliDictionary = BrotliDictionary::new(&PSEUDO_RANDOM);
+        test_compressor(&DICTIONARY, &DICTIONARY);
+        test_dictionary(
+            &DICTIONARY,
+            &DICTIONARY,
+            BROTLI_COMPRESSOR,
+            BROTLI_DECOMPRESSOR,
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_zstd_dictionary() {
+        static DICTIONARY: ZstdDictionary = ZstdDictionary::new(&PSEUDO_RANDOM);
+        test_compressor(&DICTIONARY, &DICTIONARY);
+        test_dictionary(&DICTIONARY, &DICTIONARY, ZSTD_COMPRESSOR, ZSTD_DECOMPRESSOR);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_zstd_window_limited_by_declared_len() {
+        // a frame of one raw block, with no content size and the given window size
+        let frame = |window_log: u8| {
+            let mut frame = vec![0x28, 0xb5, 0x2f, 0xfd, 0x00, (window_log - 10) << 3];
+            let block_header = 1 | (5 << 3);
+            frame.extend_from_slice(&[block_header, 0, 0]);
+            frame.extend_from_slice(b"hello");
+            frame
+        };
+
+        let mut output = DecompressionOutput::new(5);
+        ZSTD_DECOMPRESSOR
+            .decompress_incremental(&frame(10), &mut output)
+            .unwrap();
+        assert_eq!(output.finish().unwrap(), b"hello");
+
+        // a window much larger than the declared length is refused
+        let mut output = DecompressionOutput::new(5);
+        assert!(ZSTD_DECOMPRESSOR
+            .decompress_incremental(&frame(20), &mut output)
+            .is_err());
+    }
+
+    /// Check that a dictionary holding the input makes it compress much better,
+    /// and that compressions need the dictionary to decompress.
+    #[cfg(any(feature = "brotli", feature = "zstd"))]
+    fn test_dictionary(
+        comp: &dyn CertCompressor,
+        decomp: &dyn CertDecompressor,
+        plain_comp: &dyn CertCompressor,
+        plain_decomp: &dyn CertDecompressor,
+    ) {
+        let original = PSEUDO_RANDOM.to_vec();
+        let compressed = comp
+            .compress(original.clone(), CompressionLevel::Interactive)
+            .unwrap();
+        let plain_compressed = plain_comp
+            .compress(original.clone(), CompressionLevel::Interactive)
+            .unwrap();
+        println!(
+            "{:?} compressed {} -> {} with dictionary, {} without",
+            comp.algorithm(),
+            original.len(),
+            compressed.len(),
+            plain_compressed.len()
+        );
+        assert!(compressed.len() * 10 < plain_compressed.len());
+
+        let mut recovered = vec![0u8; original.len()];
+        decomp
+            .decompress(&compressed, &mut recovered)
+            .unwrap();
+        assert_eq!(original, recovered);
+
+        // compressions without the dictionary still decompress with it
+        decomp
+            .decompress(&plain_compressed, &mut recovered)
+            .unwrap();
+        assert_eq!(original, recovered);
+
+        let mut recovered = vec![0u8; original.len()];
+        assert!(
+            plain_decomp
+                .decompress(&compressed, &mut recovered)
+                .is_err()
+                || recovered != original
+        );
+    }
+
+    /// Incompressible without a dictionary.
+    #[cfg(any(feature = "brotli", feature = "zstd"))]
+    static PSEUDO_RANDOM: [u8; 4096] = {
+        let mut bytes = [0u8; 4096];
+        let mut state = 0x2545_f491u32;
+        let mut i = 0;
+        while i < bytes.len() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            bytes[i] = state as u8;
+            i += 1;
+        }
+        bytes
+    };
+
     fn test_compressor(comp: &dyn CertCompressor, decomp: &dyn CertDecompressor) {
         assert_eq!(comp.algorithm(), decomp.algorithm());
         for sz in [16, 64, 512, 2048, 8192, 16384] {
@@ -494,6 +1451,12 @@ pub mod tests {
                 .decompress(&compressed, &mut recovered)
                 .unwrap();
             assert_eq!(original, recovered);
+
+            let mut output = DecompressionOutput::new(plain_len);
+            decomp
+                .decompress_incremental(&compressed, &mut output)
+                .unwrap();
+            assert_eq!(original, output.finish().unwrap());
         }
     }
 
@@ -515,6 +1478,19 @@ pub mod tests {
         decomp
             .decompress(&compressed, &mut recovered)
             .unwrap_err();
+
+        // incrementally, too big
+        let mut output = DecompressionOutput::new(original.len() + 1);
+        decomp
+            .decompress_incremental(&compressed, &mut output)
+            .and_then(|()| output.finish())
+            .unwrap_err();
+
+        // incrementally, too small
+        let mut output = DecompressionOutput::new(original.len() - 1);
+        decomp
+            .decompress_incremental(&compressed, &mut output)
+            .unwrap_err();
     }
 
     fn test_decompress_garbage(decomp: &dyn CertDecompressor) {
@@ -5,9 +5,11 @@ use core::ops::{Deref, DerefMut};
 #[cfg(feature = "std")]
 use std::io;
 
-use crate::common_state::{CommonState, Context, IoState, State, DEFAULT_BUFFER_LIMIT};
+use crate::audit::CloseReason;
+use crate::common_state::{CommonState, Context, IoEvents, IoState, State, DEFAULT_BUFFER_LIMIT};
 use crate::enums::{AlertDescription, ContentType};
 use crate::error::{Error, PeerMisbehaved};
+use crate::exporter::KeyingMaterialExporter;
 #[cfg(feature = "logging")]
 use crate::log::trace;
 use crate::msgs::deframer::{
@@ -29,6 +31,7 @@ mod connection {
 
     use crate::common_state::{CommonState, IoState};
     use crate::error::Error;
+    use crate::exporter::KeyingMaterialExporter;
     use crate::msgs::message::OutboundChunks;
     use crate::suites::ExtractedSecrets;
     use crate::vecbuf::ChunkVecBuffer;
@@ -102,6 +105,16 @@ mod connection {
             }
         }
 
+        /// Get a [`KeyingMaterialExporter`].
+        ///
+        /// See [`ConnectionCommon::keying_material_exporter()`] for more information.
+        pub fn keying_material_exporter(&self) -> Result<KeyingMaterialExporter<'_>, Error> {
+            match self {
+                Self::Client(conn) => conn.keying_material_exporter(),
+                Self::Server(conn) => conn.keying_material_exporter(),
+            }
+        }
+
         /// This function uses `io` to complete any outstanding IO for this connection.
         ///
         /// See [`ConnectionCommon::complete_io()`] for more information.
@@ -116,6 +129,23 @@ mod connection {
             }
         }
 
+        /// Like [`Connection::complete_io()`], also returning an [`IoState`].
+        ///
+        /// See [`ConnectionCommon::complete_io_with_state()`] for more information.
+        pub fn complete_io_with_state<T>(
+            &mut self,
+            io: &mut T,
+        ) -> Result<(usize, usize, IoState), io::Error>
+        where
+            Self: Sized,
+            T: io::Read + io::Write,
+        {
+            match self {
+                Self::Client(conn) => conn.complete_io_with_state(io),
+                Self::Server(conn) => conn.complete_io_with_state(io),
+            }
+        }
+
         /// Extract secrets, so they can be used when configuring kTLS, for example.
         /// Should be used with care as it exposes secret key material.
         pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
@@ -312,15 +342,30 @@ https://docs.rs/rustls/latest/rustls/manual/_03_howto/index.html#unexpected-eof"
 
     impl<T> PlaintextSink for ConnectionCommon<T> {
         fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.respond_to_close_notify && self.has_received_close_notify {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "connection closed by peer",
+                ));
+            }
+
             let len = self
                 .core
                 .common_state
-                .buffer_plaintext(buf.into(), &mut self.sendable_plaintext);
+                .buffer_plaintext(buf.into(), &mut self.sendable_plaintext)
+                .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))?;
             self.core.maybe_refresh_traffic_keys();
             Ok(len)
         }
 
         fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+            if self.respond_to_close_notify && self.has_received_close_notify {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "connection closed by peer",
+                ));
+            }
+
             let payload_owner: Vec<&[u8]>;
             let payload = match bufs.len() {
                 0 => return Ok(0),
@@ -337,7 +382,8 @@ https://docs.rs/rustls/latest/rustls/manual/_03_howto/index.html#unexpected-eof"
             let len = self
                 .core
                 .common_state
-                .buffer_plaintext(payload, &mut self.sendable_plaintext);
+                .buffer_plaintext(payload, &mut self.sendable_plaintext)
+                .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))?;
             self.core.maybe_refresh_traffic_keys();
             Ok(len)
         }
@@ -394,8 +440,14 @@ impl<Data> ConnectionCommon<Data> {
     /// [`process_new_packets`]: Connection::process_new_packets
     #[inline]
     pub fn process_new_packets(&mut self) -> Result<IoState, Error> {
+        let io_state = self
+            .core
+            .process_new_packets(&mut self.deframer_buffer, &mut self.sendable_plaintext)?;
+        #[cfg(feature = "std")]
         self.core
-            .process_new_packets(&mut self.deframer_buffer, &mut self.sendable_plaintext)
+            .common_state
+            .check_unexpected_eof();
+        Ok(io_state)
     }
 
     /// Derives key material from the agreed connection secrets.
@@ -426,21 +478,19 @@ impl<Data> ConnectionCommon<Data> {
             .export_keying_material(output, label, context)
     }
 
+    /// Get a [`KeyingMaterialExporter`], for deriving key material from the agreed
+    /// connection secrets with checked parameters.
+    ///
+    /// This fails with [`Error::HandshakeNotComplete`] if called prior to the
+    /// handshake completing.
+    pub fn keying_material_exporter(&self) -> Result<KeyingMaterialExporter<'_>, Error> {
+        KeyingMaterialExporter::new(&self.core)
+    }
+
     /// Extract secrets, so they can be used when configuring kTLS, for example.
     /// Should be used with care as it exposes secret key material.
     pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
-        if !self.enable_secret_extraction {
-            return Err(Error::General("Secret extraction is disabled".into()));
-        }
-
-        let st = self.core.state?;
-
-        let record_layer = self.core.common_state.record_layer;
-        let PartiallyExtractedSecrets { tx, rx } = st.extract_secrets()?;
-        Ok(ExtractedSecrets {
-            tx: (record_layer.write_seq(), tx),
-            rx: (record_layer.read_seq(), rx),
-        })
+        self.core.dangerous_extract_secrets()
     }
 
     /// Sets a limit on the internal buffers used to buffer
@@ -571,6 +621,26 @@ impl<Data> ConnectionCommon<Data> {
     /// [`read_tls`]: ConnectionCommon::read_tls
     /// [`process_new_packets`]: ConnectionCommon::process_new_packets
     pub fn complete_io<T>(&mut self, io: &mut T) -> Result<(usize, usize), io::Error>
+    where
+        Self: Sized,
+        T: io::Read + io::Write,
+    {
+        self.complete_io_with_state(io)
+            .map(|(rdlen, wrlen, _)| (rdlen, wrlen))
+    }
+
+    /// Like [`ConnectionCommon::complete_io()`], also returning an [`IoState`].
+    ///
+    /// The [`IoState`] describes the connection after the call, and everything that
+    /// happened during it: for example, [`IoState::handshake_completed()`] is `Some`
+    /// if any of the calls to [`process_new_packets`] made by this function completed
+    /// the handshake.  This saves checking the connection's state afterwards.
+    ///
+    /// [`process_new_packets`]: ConnectionCommon::process_new_packets
+    pub fn complete_io_with_state<T>(
+        &mut self,
+        io: &mut T,
+    ) -> Result<(usize, usize, IoState), io::Error>
     where
         Self: Sized,
         T: io::Read + io::Write,
@@ -578,13 +648,14 @@ impl<Data> ConnectionCommon<Data> {
         let mut eof = false;
         let mut wrlen = 0;
         let mut rdlen = 0;
+        let mut events = IoEvents::default();
 
         loop {
             let until_handshaked = self.is_handshaking();
 
             if !self.wants_write() && !self.wants_read() {
                 // We will make no further progress.
-                return Ok((rdlen, wrlen));
+                return Ok((rdlen, wrlen, self.io_state_with(events)));
             }
 
             while self.wants_write() {
@@ -593,7 +664,7 @@ impl<Data> ConnectionCommon<Data> {
             io.flush()?;
 
             if !until_handshaked && wrlen > 0 {
-                return Ok((rdlen, wrlen));
+                return Ok((rdlen, wrlen, self.io_state_with(events)));
             }
 
             while !eof && self.wants_read() {
@@ -615,7 +686,7 @@ impl<Data> ConnectionCommon<Data> {
             }
 
             match self.process_new_packets() {
-                Ok(_) => {}
+                Ok(io_state) => events.merge(io_state.events()),
                 Err(e) => {
                     // In case we have an alert to send describing this error,
                     // try a last-gasp write -- but don't predate the primary
@@ -635,14 +706,21 @@ impl<Data> ConnectionCommon<Data> {
             }
 
             match (eof, until_handshaked, self.is_handshaking()) {
-                (_, true, false) => return Ok((rdlen, wrlen)),
-                (_, false, _) => return Ok((rdlen, wrlen)),
+                (_, true, false) => return Ok((rdlen, wrlen, self.io_state_with(events))),
+                (_, false, _) => return Ok((rdlen, wrlen, self.io_state_with(events))),
                 (true, true, true) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
                 (..) => {}
             }
         }
     }
 
+    fn io_state_with(&self, events: IoEvents) -> IoState {
+        self.core
+            .common_state
+            .current_io_state()
+            .with_events(events)
+    }
+
     /// Extract the first handshake message.
     ///
     /// This is a shortcut to the `process_new_packets()` -> `process_msg()` ->
@@ -651,16 +729,10 @@ impl<Data> ConnectionCommon<Data> {
         let mut deframer_buffer = self.deframer_buffer.borrow();
         let res = self
             .core
-            .deframe(None, &mut deframer_buffer)
-            .map(|opt| opt.map(|pm| Message::try_from(pm).map(|m| m.into_owned())));
+            .first_handshake_message(&mut deframer_buffer);
         let discard = deframer_buffer.pending_discard();
         self.deframer_buffer.discard(discard);
-
-        match res? {
-            Some(Ok(msg)) => Ok(Some(msg)),
-            Some(Err(err)) => Err(self.send_fatal_alert(AlertDescription::DecodeError, err)),
-            None => Ok(None),
-        }
+        res
     }
 
     pub(crate) fn replace_state(&mut self, new: Box<dyn State<Data>>) {
@@ -772,6 +844,14 @@ impl<Data> From<ConnectionCore<Data>> for UnbufferedConnectionCommon<Data> {
     }
 }
 
+impl<Data> UnbufferedConnectionCommon<Data> {
+    /// Extract secrets, so they can be used when configuring kTLS, for example.
+    /// Should be used with care as it exposes secret key material.
+    pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
+        self.core.dangerous_extract_secrets()
+    }
+}
+
 impl<T> Deref for UnbufferedConnectionCommon<T> {
     type Target = CommonState;
 
@@ -797,6 +877,26 @@ impl<Data> ConnectionCore<Data> {
         }
     }
 
+    pub(crate) fn dangerous_extract_secrets(mut self) -> Result<ExtractedSecrets, Error> {
+        if !self
+            .common_state
+            .enable_secret_extraction
+        {
+            return Err(Error::General("Secret extraction is disabled".into()));
+        }
+
+        let st = self.state?;
+
+        let PartiallyExtractedSecrets { tx, rx } = st.extract_secrets()?;
+        self.common_state
+            .audit_close(CloseReason::SecretsExtracted, None);
+        let record_layer = self.common_state.record_layer;
+        Ok(ExtractedSecrets {
+            tx: (record_layer.write_seq(), tx),
+            rx: (record_layer.read_seq(), rx),
+        })
+    }
+
     pub(crate) fn process_new_packets(
         &mut self,
         deframer_buffer: &mut DeframerVecBuffer,
@@ -810,6 +910,15 @@ impl<Data> ConnectionCore<Data> {
             }
         };
 
+        if let Err(e) = self
+            .common_state
+            .check_handshake_deadline()
+        {
+            self.state = Err(e.clone());
+            return Err(e);
+        }
+
+        self.common_state.io_events = IoEvents::default();
         let mut discard = 0;
         loop {
             let mut borrowed_buffer = deframer_buffer.borrow();
@@ -858,6 +967,27 @@ impl<Data> ConnectionCore<Data> {
         Ok(self.common_state.current_io_state())
     }
 
+    /// Extract the first handshake message from `deframer_buffer`.
+    ///
+    /// The caller is responsible for discarding `deframer_buffer.pending_discard()` bytes
+    /// afterwards, whatever the outcome.
+    pub(crate) fn first_handshake_message(
+        &mut self,
+        deframer_buffer: &mut DeframerSliceBuffer<'_>,
+    ) -> Result<Option<Message<'static>>, Error> {
+        let res = self
+            .deframe(None, deframer_buffer)
+            .map(|opt| opt.map(|pm| Message::try_from(pm).map(|m| m.into_owned())));
+
+        match res? {
+            Some(Ok(msg)) => Ok(Some(msg)),
+            Some(Err(err)) => Err(self
+                .common_state
+                .send_fatal_alert(AlertDescription::DecodeError, err)),
+            None => Ok(None),
+        }
+    }
+
     /// Pull a message out of the deframer and send any messages that need to be sent as a result.
     fn deframe<'b>(
         &mut self,
@@ -946,7 +1076,10 @@ impl<Data> ConnectionCore<Data> {
         }
 
         // Now we can fully parse the message payload.
-        let msg = match Message::try_from(msg) {
+        let max_certificate_size = self
+            .message_deframer
+            .max_certificate_size();
+        let msg = match Message::parse_with_max_certificate_size(msg, max_certificate_size) {
             Ok(msg) => msg,
             Err(err) => {
                 return Err(self
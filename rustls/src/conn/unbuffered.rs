@@ -10,7 +10,7 @@ use super::UnbufferedConnectionCommon;
 use crate::client::ClientConnectionData;
 use crate::msgs::deframer::DeframerSliceBuffer;
 use crate::server::ServerConnectionData;
-use crate::Error;
+use crate::{Error, WarningAlert};
 
 impl UnbufferedConnectionCommon<ClientConnectionData> {
     /// Processes the TLS records in `incoming_tls` buffer until a new [`UnbufferedStatus`] is
@@ -47,6 +47,18 @@ impl<Data> UnbufferedConnectionCommon<Data> {
     ) -> UnbufferedStatus<'c, 'i, Data> {
         let mut buffer = DeframerSliceBuffer::new(incoming_tls);
 
+        if let Err(e) = self
+            .core
+            .common_state
+            .check_handshake_deadline()
+        {
+            self.core.state = Err(e.clone());
+            return UnbufferedStatus {
+                discard: 0,
+                state: Err(e),
+            };
+        }
+
         let (discard, state) = loop {
             if let Some(value) = check(self) {
                 break (buffer.pending_discard(), execute(self, incoming_tls, value));
@@ -418,6 +430,23 @@ impl<Data> WriteTraffic<'_, Data> {
             .eager_send_close_notify(outgoing_tls)
     }
 
+    /// Encrypts a warning-level `alert` in `outgoing_tls`
+    ///
+    /// This is normally followed by [`WriteTraffic::queue_close_notify()`].
+    ///
+    /// Returns the number of bytes that were written into `outgoing_tls`, or an error if
+    /// the provided buffer is too small. In the error case, `outgoing_tls` is not modified
+    pub fn queue_warning_alert(
+        &mut self,
+        alert: WarningAlert,
+        outgoing_tls: &mut [u8],
+    ) -> Result<usize, EncryptError> {
+        self.conn
+            .core
+            .common_state
+            .eager_send_warning_alert(alert, outgoing_tls)
+    }
+
     /// Arranges for a TLS1.3 `key_update` to be sent.
     ///
     /// This consumes the `WriteTraffic` state:  to actually send the message,
@@ -0,0 +1,95 @@
+//! Just enough DER reading for the encrypted key formats we accept.
+
+/// Remove a DER element with the given `tag` from the front of `input`,
+/// returning its contents.
+pub(super) fn take<'a>(input: &mut &'a [u8], tag: u8) -> Option<&'a [u8]> {
+    let (&actual, rest) = input.split_first()?;
+    if actual != tag {
+        return None;
+    }
+
+    let (&len, mut rest) = rest.split_first()?;
+    let len = match len {
+        0..=0x7f => usize::from(len),
+        0x81..=0x83 => {
+            let (len, tail) = rest.split_at(usize::from(len & 0x7f).min(rest.len()));
+            rest = tail;
+            match len {
+                [] => return None,
+                [0, ..] => return None,
+                len => len
+                    .iter()
+                    .fold(0usize, |acc, byte| acc << 8 | usize::from(*byte)),
+            }
+        }
+        _ => return None,
+    };
+
+    let contents = rest.get(..len)?;
+    *input = &rest[len..];
+    Some(contents)
+}
+
+/// Remove a DER INTEGER that fits in a `u32` from the front of `input`.
+pub(super) fn take_u32(input: &mut &[u8]) -> Option<u32> {
+    let bytes = match take(input, INTEGER)? {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        [first, ..] if first & 0x80 != 0 => return None,
+        bytes => bytes,
+    };
+    if bytes.is_empty() || bytes.len() > 4 {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .fold(0u32, |acc, byte| acc << 8 | u32::from(*byte)),
+    )
+}
+
+/// Whether the next element in `input` has the given `tag`.
+pub(super) fn peek(input: &[u8], tag: u8) -> bool {
+    input.first() == Some(&tag)
+}
+
+pub(super) const INTEGER: u8 = 0x02;
+pub(super) const OCTET_STRING: u8 = 0x04;
+#[cfg(feature = "pkcs12")]
+pub(super) const NULL: u8 = 0x05;
+pub(super) const OID: u8 = 0x06;
+pub(super) const SEQUENCE: u8 = 0x30;
+#[cfg(feature = "pkcs12")]
+pub(super) const CONTEXT_0: u8 = 0x80;
+#[cfg(feature = "pkcs12")]
+pub(super) const CONTEXT_CONSTRUCTED_0: u8 = 0xa0;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_long_form_lengths() {
+        let mut input = &[0x04, 0x81, 0x01, 0xaa, 0xbb][..];
+        assert_eq!(take(&mut input, OCTET_STRING), Some(&[0xaa][..]));
+        assert_eq!(input, &[0xbb]);
+
+        // non-minimal and truncated lengths
+        assert_eq!(
+            take(&mut &[0x04, 0x82, 0x00, 0x01, 0xaa][..], OCTET_STRING),
+            None
+        );
+        assert_eq!(take(&mut &[0x04, 0x82, 0x01][..], OCTET_STRING), None);
+        assert_eq!(take(&mut &[0x04, 0x02, 0xaa][..], OCTET_STRING), None);
+    }
+
+    #[test]
+    fn reads_u32() {
+        assert_eq!(take_u32(&mut &[0x02, 0x02, 0x08, 0x00][..]), Some(2048));
+        assert_eq!(
+            take_u32(&mut &[0x02, 0x05, 0x00, 0xff, 0xff, 0xff, 0xff][..]),
+            Some(u32::MAX)
+        );
+        assert_eq!(take_u32(&mut &[0x02, 0x01, 0x80][..]), None);
+        assert_eq!(take_u32(&mut &[0x02, 0x00][..]), None);
+    }
+}
@@ -258,7 +258,7 @@ impl<const KEY_SIZE: usize, const KDF_SIZE: usize> HpkeAwsLcRs<KEY_SIZE, KDF_SIZ
     /// [0]: https://www.rfc-editor.org/rfc/rfc9180.html#section-5.1
     fn key_schedule(
         &self,
-        shared_secret: KemSharedSecret<KDF_SIZE>,
+        shared_secret: &KemSharedSecret<KDF_SIZE>,
         info: &[u8],
     ) -> Result<KeySchedule<KEY_SIZE>, Error> {
         // Note: we use an empty IKM for the `psk_id_hash` and `secret` labelled extractions because
@@ -275,13 +275,13 @@ impl<const KEY_SIZE: usize, const KDF_SIZE: usize> HpkeAwsLcRs<KEY_SIZE, KDF_SIZ
         .concat();
 
         let key = AeadKey(self.key_schedule_labeled_expand::<KEY_SIZE>(
-            &shared_secret,
+            shared_secret,
             &key_schedule_context,
             Label::Key,
         ));
 
         let base_nonce = self.key_schedule_labeled_expand::<NONCE_LEN>(
-            &shared_secret,
+            shared_secret,
             &key_schedule_context,
             Label::BaseNonce,
         );
@@ -393,6 +393,7 @@ impl<const KEY_SIZE: usize, const KDF_SIZE: usize> Debug for HpkeAwsLcRs<KEY_SIZ
 /// Adapts a [KeySchedule] and [AeadKey] for the role of a [HpkeSealer].
 struct Sealer<const KEY_SIZE: usize, const KDF_SIZE: usize> {
     key_schedule: KeySchedule<KEY_SIZE>,
+    shared_secret: KemSharedSecret<KDF_SIZE>,
 }
 
 impl<const KEY_SIZE: usize, const KDF_SIZE: usize> Sealer<KEY_SIZE, KDF_SIZE> {
@@ -410,8 +411,14 @@ impl<const KEY_SIZE: usize, const KDF_SIZE: usize> Sealer<KEY_SIZE, KDF_SIZE> {
         //                            default_psk, default_psk_id)
 
         let (shared_secret, enc) = suite.dh_kem.encap(pub_key)?;
-        let key_schedule = suite.key_schedule(shared_secret, info)?;
-        Ok((enc, Self { key_schedule }))
+        let key_schedule = suite.key_schedule(&shared_secret, info)?;
+        Ok((
+            enc,
+            Self {
+                key_schedule,
+                shared_secret,
+            },
+        ))
     }
 
     /// A **test only** constructor that uses a pre-specified ephemeral agreement private key
@@ -426,8 +433,14 @@ impl<const KEY_SIZE: usize, const KDF_SIZE: usize> Sealer<KEY_SIZE, KDF_SIZE> {
         let (shared_secret, enc) = suite
             .dh_kem
             .test_only_encap(pub_key, sk_e)?;
-        let key_schedule = suite.key_schedule(shared_secret, info)?;
-        Ok((enc, Self { key_schedule }))
+        let key_schedule = suite.key_schedule(&shared_secret, info)?;
+        Ok((
+            enc,
+            Self {
+                key_schedule,
+                shared_secret,
+            },
+        ))
     }
 }
 
@@ -449,6 +462,10 @@ impl<const KEY_SIZE: usize, const KDF_SIZE: usize> HpkeSealer for Sealer<KEY_SIZ
 
         Ok(in_out_buffer)
     }
+
+    fn kem_shared_secret(&self) -> Option<&[u8]> {
+        Some(&self.shared_secret.0)
+    }
 }
 
 impl<const KEY_SIZE: usize, const KDF_SIZE: usize> Debug for Sealer<KEY_SIZE, KDF_SIZE> {
@@ -477,7 +494,7 @@ impl<const KEY_SIZE: usize, const KDF_SIZE: usize> Opener<KEY_SIZE, KDF_SIZE> {
         //   return KeyScheduleR(mode_base, shared_secret, info,
         //                       default_psk, default_psk_id)
         Ok(Self {
-            key_schedule: suite.key_schedule(suite.dh_kem.decap(enc, secret_key)?, info)?,
+            key_schedule: suite.key_schedule(&suite.dh_kem.decap(enc, secret_key)?, info)?,
         })
     }
 }
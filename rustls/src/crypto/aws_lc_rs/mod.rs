@@ -19,15 +19,20 @@ use crate::Error;
 
 /// Hybrid public key encryption (HPKE).
 pub mod hpke;
+/// Loading certificates and keys from PKCS#12 bundles.
+#[cfg(feature = "pkcs12")]
+pub mod pkcs12;
 /// Using software keys for authentication.
 pub mod sign;
 
+mod der;
 #[path = "../ring/hash.rs"]
 pub(crate) mod hash;
 #[path = "../ring/hmac.rs"]
 pub(crate) mod hmac;
 #[path = "../ring/kx.rs"]
 pub(crate) mod kx;
+mod pbes2;
 #[path = "../ring/quic.rs"]
 pub(crate) mod quic;
 #[cfg(any(feature = "std", feature = "hashbrown"))]
@@ -88,6 +93,14 @@ impl KeyProvider for AwsLcRs {
         sign::any_supported_type(&key_der)
     }
 
+    fn load_encrypted_private_key(
+        &self,
+        encrypted: &[u8],
+        passphrase: &dyn Fn() -> Result<Vec<u8>, Error>,
+    ) -> Result<Arc<dyn SigningKey>, Error> {
+        pbes2::load_encrypted_private_key(encrypted, passphrase)
+    }
+
     fn fips(&self) -> bool {
         fips()
     }
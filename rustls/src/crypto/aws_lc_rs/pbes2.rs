@@ -0,0 +1,307 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::num::NonZeroU32;
+
+use aws_lc_rs::cipher::{
+    DecryptionContext, PaddedBlockDecryptingKey, UnboundCipherKey, AES_128, AES_256,
+};
+use aws_lc_rs::iv::FixedLength;
+use aws_lc_rs::pbkdf2;
+use pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use zeroize::Zeroize;
+
+use super::der::{self, take, take_u32};
+use super::sign::any_supported_type;
+use crate::error::{Error, KeyLoadingError};
+use crate::sign::SigningKey;
+
+/// Decrypt a DER-encoded PKCS#8 `EncryptedPrivateKeyInfo` protected with PBES2,
+/// and load the resulting key.
+///
+/// PBKDF2 with HMAC-SHA1, -SHA256, -SHA384 or -SHA512 is supported for key
+/// derivation, with at most [`MAX_ITERATIONS`] iterations, and AES-128-CBC or
+/// AES-256-CBC for encryption.
+pub(super) fn load_encrypted_private_key(
+    encrypted: &[u8],
+    passphrase: &dyn Fn() -> Result<Vec<u8>, Error>,
+) -> Result<Arc<dyn SigningKey>, Error> {
+    let mut passphrase = match parse_encrypted_private_key_info(encrypted) {
+        Some(_) => passphrase()?,
+        None => return Err(KeyLoadingError::UnsupportedFormat.into()),
+    };
+    let result = decrypt_private_key_info(encrypted, &passphrase);
+    passphrase.zeroize();
+
+    let mut plaintext = result?;
+    // A wrong passphrase usually shows up as bad padding, but may instead
+    // produce garbage that fails to parse.
+    let key = any_supported_type(&PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+        &plaintext[..],
+    )))
+    .map_err(|_| KeyLoadingError::WrongPassphrase.into());
+    plaintext.zeroize();
+    key
+}
+
+/// Decrypt a DER-encoded PKCS#8 `EncryptedPrivateKeyInfo`, returning the
+/// encoded `PrivateKeyInfo`.
+///
+/// The caller must zeroize the result.
+pub(super) fn decrypt_private_key_info(
+    encrypted: &[u8],
+    passphrase: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let (algorithm, encrypted_data) =
+        parse_encrypted_private_key_info(encrypted).ok_or(KeyLoadingError::UnsupportedFormat)?;
+    decrypt(algorithm, encrypted_data, passphrase).map_err(|err| {
+        match err {
+            DecryptError::Unsupported => KeyLoadingError::UnsupportedFormat,
+            DecryptError::Failed => KeyLoadingError::WrongPassphrase,
+        }
+        .into()
+    })
+}
+
+/// Decrypt `ciphertext` using the scheme described by `algorithm`, the
+/// contents of an `AlgorithmIdentifier`.
+///
+/// The caller must zeroize the result.
+pub(super) fn decrypt(
+    algorithm: &[u8],
+    ciphertext: &[u8],
+    passphrase: &[u8],
+) -> Result<Vec<u8>, DecryptError> {
+    let params = Pbes2Params::parse(algorithm).ok_or(DecryptError::Unsupported)?;
+
+    let mut key = [0u8; 32];
+    let key = &mut key[..params.cipher_key_len];
+    pbkdf2::derive(params.prf, params.iterations, params.salt, passphrase, key);
+    let cipher_key = UnboundCipherKey::new(params.cipher, key);
+    key.zeroize();
+
+    let mut plaintext = ciphertext.to_vec();
+    let len = cipher_key
+        .and_then(PaddedBlockDecryptingKey::cbc_pkcs7)
+        .and_then(|cipher_key| {
+            cipher_key
+                .decrypt(
+                    &mut plaintext,
+                    DecryptionContext::Iv128(FixedLength::from(params.iv)),
+                )
+                .map(|plaintext| plaintext.len())
+        });
+
+    match len {
+        Ok(len) => {
+            plaintext.truncate(len);
+            Ok(plaintext)
+        }
+        Err(_) => {
+            plaintext.zeroize();
+            Err(DecryptError::Failed)
+        }
+    }
+}
+
+pub(super) enum DecryptError {
+    /// The encryption scheme is malformed or not supported.
+    Unsupported,
+    /// Decryption failed, probably because the passphrase is wrong.
+    Failed,
+}
+
+/// Split an `EncryptedPrivateKeyInfo` into its algorithm and encrypted data.
+fn parse_encrypted_private_key_info(mut input: &[u8]) -> Option<(&[u8], &[u8])> {
+    // EncryptedPrivateKeyInfo ::= SEQUENCE {
+    //   encryptionAlgorithm AlgorithmIdentifier,
+    //   encryptedData OCTET STRING }
+    let mut info = take(&mut input, der::SEQUENCE)?;
+    if !input.is_empty() {
+        return None;
+    }
+    let algorithm = take(&mut info, der::SEQUENCE)?;
+    let encrypted_data = take(&mut info, der::OCTET_STRING)?;
+    Some((algorithm, encrypted_data))
+}
+
+struct Pbes2Params<'a> {
+    salt: &'a [u8],
+    iterations: NonZeroU32,
+    prf: pbkdf2::Algorithm,
+    cipher: &'static aws_lc_rs::cipher::Algorithm,
+    cipher_key_len: usize,
+    iv: [u8; 16],
+}
+
+impl<'a> Pbes2Params<'a> {
+    fn parse(mut algorithm: &'a [u8]) -> Option<Self> {
+        if take(&mut algorithm, der::OID)? != OID_PBES2 {
+            return None;
+        }
+
+        // PBES2-params ::= SEQUENCE {
+        //   keyDerivationFunc AlgorithmIdentifier,
+        //   encryptionScheme AlgorithmIdentifier }
+        let mut pbes2 = take(&mut algorithm, der::SEQUENCE)?;
+        let mut kdf = take(&mut pbes2, der::SEQUENCE)?;
+        let mut scheme = take(&mut pbes2, der::SEQUENCE)?;
+        if take(&mut kdf, der::OID)? != OID_PBKDF2 {
+            return None;
+        }
+
+        // PBKDF2-params ::= SEQUENCE {
+        //   salt OCTET STRING,
+        //   iterationCount INTEGER,
+        //   keyLength INTEGER OPTIONAL,
+        //   prf AlgorithmIdentifier DEFAULT hmacWithSHA1 }
+        let mut pbkdf2_params = take(&mut kdf, der::SEQUENCE)?;
+        let salt = take(&mut pbkdf2_params, der::OCTET_STRING)?;
+        let iterations = NonZeroU32::new(take_u32(&mut pbkdf2_params)?)?;
+        if iterations.get() > MAX_ITERATIONS {
+            return None;
+        }
+        let key_len = match der::peek(pbkdf2_params, der::INTEGER) {
+            true => Some(take_u32(&mut pbkdf2_params)? as usize),
+            false => None,
+        };
+        let prf = match pbkdf2_params.is_empty() {
+            false => {
+                let mut prf = take(&mut pbkdf2_params, der::SEQUENCE)?;
+                match take(&mut prf, der::OID)? {
+                    OID_HMAC_SHA1 => pbkdf2::PBKDF2_HMAC_SHA1,
+                    OID_HMAC_SHA256 => pbkdf2::PBKDF2_HMAC_SHA256,
+                    OID_HMAC_SHA384 => pbkdf2::PBKDF2_HMAC_SHA384,
+                    OID_HMAC_SHA512 => pbkdf2::PBKDF2_HMAC_SHA512,
+                    _ => return None,
+                }
+            }
+            true => pbkdf2::PBKDF2_HMAC_SHA1,
+        };
+
+        let (cipher, cipher_key_len) = match take(&mut scheme, der::OID)? {
+            OID_AES_128_CBC => (&AES_128, 16),
+            OID_AES_256_CBC => (&AES_256, 32),
+            _ => return None,
+        };
+        let iv = take(&mut scheme, der::OCTET_STRING)?
+            .try_into()
+            .ok()?;
+
+        match key_len {
+            Some(len) if len != cipher_key_len => None,
+            _ => Some(Self {
+                salt,
+                iterations,
+                prf,
+                cipher,
+                cipher_key_len,
+                iv,
+            }),
+        }
+    }
+}
+
+/// The most PBKDF2 iterations accepted.
+///
+/// The iteration count comes from the encrypted key, so is bounded to stop a
+/// malicious key from consuming unbounded CPU time when it is loaded.  This is
+/// well above the counts recommended for current use, such as the 600,000 that
+/// OWASP recommends for PBKDF2-HMAC-SHA256.
+pub(super) const MAX_ITERATIONS: u32 = 2_000_000;
+
+// 1.2.840.113549.1.5.13
+const OID_PBES2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0d];
+// 1.2.840.113549.1.5.12
+const OID_PBKDF2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0c];
+// 1.2.840.113549.2.{7,9,10,11}
+const OID_HMAC_SHA1: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x07];
+const OID_HMAC_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x09];
+const OID_HMAC_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x0a];
+const OID_HMAC_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x0b];
+// 2.16.840.1.101.3.4.1.{2,42}
+const OID_AES_128_CBC: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x02];
+const OID_AES_256_CBC: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2a];
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn can_load_encrypted_pkcs8() {
+        for encrypted in [
+            &include_bytes!("../../testdata/nistp256key.pkcs8.aes256-sha256.der")[..],
+            &include_bytes!("../../testdata/nistp256key.pkcs8.aes128-sha1.der")[..],
+        ] {
+            let key =
+                load_encrypted_private_key(encrypted, &|| Ok(b"rustls-test".to_vec())).unwrap();
+            assert_eq!(key.algorithm(), crate::SignatureAlgorithm::ECDSA);
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_passphrase() {
+        let encrypted = include_bytes!("../../testdata/nistp256key.pkcs8.aes256-sha256.der");
+        assert_eq!(
+            load_encrypted_private_key(encrypted, &|| Ok(b"wrong".to_vec())).err(),
+            Some(Error::KeyLoading(KeyLoadingError::WrongPassphrase))
+        );
+    }
+
+    #[test]
+    fn passphrase_errors_are_returned() {
+        let encrypted = include_bytes!("../../testdata/nistp256key.pkcs8.aes256-sha256.der");
+        assert_eq!(
+            load_encrypted_private_key(encrypted, &|| Err(Error::General("no tty".into()))).err(),
+            Some(Error::General("no tty".into()))
+        );
+    }
+
+    #[test]
+    fn rejects_too_many_iterations() {
+        fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+            let mut out = vec![tag, contents.len() as u8];
+            out.extend_from_slice(contents);
+            out
+        }
+
+        let algorithm = |iterations: u32| {
+            let kdf_params = [
+                tlv(der::OCTET_STRING, &[0; 8]),
+                tlv(der::INTEGER, &iterations.to_be_bytes()),
+            ]
+            .concat();
+            let kdf = [tlv(der::OID, OID_PBKDF2), tlv(der::SEQUENCE, &kdf_params)].concat();
+            let scheme = [
+                tlv(der::OID, OID_AES_128_CBC),
+                tlv(der::OCTET_STRING, &[0; 16]),
+            ]
+            .concat();
+            let pbes2 = [tlv(der::SEQUENCE, &kdf), tlv(der::SEQUENCE, &scheme)].concat();
+            [tlv(der::OID, OID_PBES2), tlv(der::SEQUENCE, &pbes2)].concat()
+        };
+
+        assert!(Pbes2Params::parse(&algorithm(MAX_ITERATIONS)).is_some());
+        assert!(Pbes2Params::parse(&algorithm(MAX_ITERATIONS + 1)).is_none());
+        assert!(matches!(
+            decrypt(&algorithm(u32::MAX >> 1), &[0; 16], b"rustls-test"),
+            Err(DecryptError::Unsupported)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let encrypted = include_bytes!("../../testdata/nistp256key.pkcs8.aes256-sha256.der");
+        for input in [
+            &encrypted[..encrypted.len() - 1],
+            &include_bytes!("../../testdata/nistp256key.pkcs8.der")[..],
+            &vec![0x30, 0x00][..],
+        ] {
+            assert_eq!(
+                load_encrypted_private_key(input, &|| Ok(b"rustls-test".to_vec())).err(),
+                Some(Error::KeyLoading(KeyLoadingError::UnsupportedFormat))
+            );
+        }
+    }
+}
@@ -0,0 +1,458 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use aws_lc_rs::{digest, hmac};
+use pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::der::{self, take, take_u32};
+use super::pbes2::{self, DecryptError};
+use super::sign::any_supported_type;
+use crate::error::{Error, InconsistentKeys, KeyLoadingError};
+use crate::server::ParsedCertificate;
+use crate::sign::CertifiedKey;
+
+/// Load a certificate chain and private key from a DER-encoded PKCS#12 bundle
+/// (commonly with a `.p12` or `.pfx` extension).
+///
+/// `passphrase` is called once to obtain the passphrase for the bundle, which must
+/// be UTF-8; any error it returns is returned from this function.  The bundle's
+/// integrity is checked before anything else is done with it: bundles without a
+/// MAC are rejected, unless loaded with [`load_certified_key_without_mac()`].
+///
+/// The bundle must contain exactly one private key, and a certificate matching it,
+/// which becomes the end-entity certificate of the returned chain.  The other
+/// certificates in the bundle follow it, in the order they appear in the bundle.
+///
+/// Bundles made by current versions of OpenSSL and other tools are supported: these use
+/// PBES2 (with PBKDF2 and AES-CBC) for encryption, and HMAC with SHA-1 or SHA-2 for
+/// integrity.  Bundles using the legacy RC2 or triple-DES encryption schemes (such as
+/// those made with `openssl pkcs12 -legacy`) are not supported, and must be converted.
+pub fn load_certified_key(
+    bundle: &[u8],
+    passphrase: &dyn Fn() -> Result<Vec<u8>, Error>,
+) -> Result<CertifiedKey, Error> {
+    load(bundle, passphrase, false)
+}
+
+/// Load a certificate chain and private key from a DER-encoded PKCS#12 bundle,
+/// which may lack a MAC.
+///
+/// This is like [`load_certified_key()`], except that a bundle without a MAC (such as
+/// one made with `openssl pkcs12 -nomac`) is accepted.  Nothing then checks that the
+/// bundle's certificates are the ones it was made with, so only use this for bundles
+/// that come from a trusted source.  A bundle that has a MAC is still checked.
+pub fn load_certified_key_without_mac(
+    bundle: &[u8],
+    passphrase: &dyn Fn() -> Result<Vec<u8>, Error>,
+) -> Result<CertifiedKey, Error> {
+    load(bundle, passphrase, true)
+}
+
+fn load(
+    bundle: &[u8],
+    passphrase: &dyn Fn() -> Result<Vec<u8>, Error>,
+    allow_no_mac: bool,
+) -> Result<CertifiedKey, Error> {
+    let pfx = Pfx::parse(bundle).ok_or_else(malformed)?;
+    if pfx.mac.is_none() && !allow_no_mac {
+        return Err(KeyLoadingError::MissingMac.into());
+    }
+
+    let passphrase = Zeroizing::new(passphrase()?);
+    if core::str::from_utf8(&passphrase).is_err() {
+        return Err(KeyLoadingError::PassphraseNotUtf8.into());
+    }
+    if let Some(mac) = &pfx.mac {
+        mac.verify(pfx.auth_safe, &passphrase)?;
+    }
+
+    let mut contents = BundleContents::default();
+    let mut safes = pfx.safes;
+    while !safes.is_empty() {
+        let mut content_info = take(&mut safes, der::SEQUENCE).ok_or_else(malformed)?;
+        let (content_type, mut content) =
+            parse_content_info(&mut content_info).ok_or_else(malformed)?;
+        match content_type {
+            OID_DATA => {
+                let safe_contents = take(&mut content, der::OCTET_STRING).ok_or_else(malformed)?;
+                contents.read_safe_contents(safe_contents, &passphrase)?;
+            }
+            OID_ENCRYPTED_DATA => {
+                let (algorithm, ciphertext) =
+                    parse_encrypted_data(content).ok_or_else(malformed)?;
+                let safe_contents =
+                    Zeroizing::new(pbes2::decrypt(algorithm, ciphertext, &passphrase).map_err(
+                        |err| match err {
+                            DecryptError::Unsupported => KeyLoadingError::UnsupportedFormat,
+                            DecryptError::Failed => KeyLoadingError::WrongPassphrase,
+                        },
+                    )?);
+                contents.read_safe_contents(&safe_contents, &passphrase)?;
+            }
+            _ => return Err(malformed()),
+        }
+    }
+
+    contents.into_certified_key()
+}
+
+#[derive(Default)]
+struct BundleContents {
+    certs: Vec<CertificateDer<'static>>,
+    key: Option<Zeroizing<Vec<u8>>>,
+}
+
+impl BundleContents {
+    /// Read the `SafeBag`s in a `SafeContents`.
+    fn read_safe_contents(&mut self, mut input: &[u8], passphrase: &[u8]) -> Result<(), Error> {
+        let mut bags = take(&mut input, der::SEQUENCE).ok_or_else(malformed)?;
+        while !bags.is_empty() {
+            // SafeBag ::= SEQUENCE {
+            //   bagId BAG-TYPE.&id ({PKCS12BagSet}),
+            //   bagValue [0] EXPLICIT BAG-TYPE.&Type({PKCS12BagSet}{@bagId}),
+            //   bagAttributes SET OF PKCS12Attribute OPTIONAL }
+            let mut bag = take(&mut bags, der::SEQUENCE).ok_or_else(malformed)?;
+            let bag_type = take(&mut bag, der::OID).ok_or_else(malformed)?;
+            let value = take(&mut bag, der::CONTEXT_CONSTRUCTED_0).ok_or_else(malformed)?;
+
+            match bag_type {
+                OID_KEY_BAG => self.set_key(value.to_vec())?,
+                OID_PKCS8_SHROUDED_KEY_BAG => {
+                    self.set_key(pbes2::decrypt_private_key_info(value, passphrase)?)?
+                }
+                OID_CERT_BAG => {
+                    // CertBag ::= SEQUENCE {
+                    //   certId BAG-TYPE.&id ({CertTypes}),
+                    //   certValue [0] EXPLICIT BAG-TYPE.&Type ({CertTypes}{@certId}) }
+                    let mut value = value;
+                    let mut cert_bag = take(&mut value, der::SEQUENCE).ok_or_else(malformed)?;
+                    if take(&mut cert_bag, der::OID).ok_or_else(malformed)? != OID_X509_CERTIFICATE
+                    {
+                        continue;
+                    }
+                    let mut cert =
+                        take(&mut cert_bag, der::CONTEXT_CONSTRUCTED_0).ok_or_else(malformed)?;
+                    let cert = take(&mut cert, der::OCTET_STRING).ok_or_else(malformed)?;
+                    self.certs
+                        .push(CertificateDer::from(cert.to_vec()));
+                }
+                // CRLs, secrets and nested SafeContents are not needed.
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn set_key(&mut self, key: Vec<u8>) -> Result<(), Error> {
+        let key = Zeroizing::new(key);
+        match self.key {
+            Some(_) => Err(KeyLoadingError::MultiplePrivateKeys.into()),
+            None => {
+                self.key = Some(key);
+                Ok(())
+            }
+        }
+    }
+
+    fn into_certified_key(mut self) -> Result<CertifiedKey, Error> {
+        let key = match &self.key {
+            Some(key) => {
+                any_supported_type(&PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(&key[..])))?
+            }
+            None => return Err(KeyLoadingError::NoPrivateKey.into()),
+        };
+        let key_spki = key
+            .public_key()
+            .ok_or(InconsistentKeys::Unknown)?;
+
+        let mut end_entity = None;
+        for (i, cert) in self.certs.iter().enumerate() {
+            if ParsedCertificate::try_from(cert)?.subject_public_key_info() == key_spki {
+                end_entity = Some(i);
+                break;
+            }
+        }
+        let end_entity = match end_entity {
+            Some(i) => self.certs.remove(i),
+            None => return Err(InconsistentKeys::KeyMismatch.into()),
+        };
+
+        let mut chain = vec![end_entity];
+        chain.append(&mut self.certs);
+        Ok(CertifiedKey::new(chain, key))
+    }
+}
+
+struct Pfx<'a> {
+    /// The encoded `AuthenticatedSafe`, which the MAC covers.
+    auth_safe: &'a [u8],
+    /// The contents of the `AuthenticatedSafe`: a sequence of `ContentInfo`.
+    safes: &'a [u8],
+    mac: Option<MacData<'a>>,
+}
+
+impl<'a> Pfx<'a> {
+    fn parse(mut input: &'a [u8]) -> Option<Self> {
+        // PFX ::= SEQUENCE {
+        //   version INTEGER {v3(3)}(v3,...),
+        //   authSafe ContentInfo,
+        //   macData MacData OPTIONAL }
+        let mut pfx = take(&mut input, der::SEQUENCE)?;
+        if !input.is_empty() || take_u32(&mut pfx)? != 3 {
+            return None;
+        }
+
+        let mut content_info = take(&mut pfx, der::SEQUENCE)?;
+        let (content_type, mut content) = parse_content_info(&mut content_info)?;
+        if content_type != OID_DATA {
+            return None;
+        }
+        let auth_safe = take(&mut content, der::OCTET_STRING)?;
+        let safes = take(&mut &auth_safe[..], der::SEQUENCE)?;
+
+        let mac = match pfx.is_empty() {
+            true => None,
+            false => Some(MacData::parse(take(&mut pfx, der::SEQUENCE)?)?),
+        };
+
+        Some(Self {
+            auth_safe,
+            safes,
+            mac,
+        })
+    }
+}
+
+struct MacData<'a> {
+    digest: &'static digest::Algorithm,
+    hmac: hmac::Algorithm,
+    mac: &'a [u8],
+    salt: &'a [u8],
+    iterations: u32,
+}
+
+impl<'a> MacData<'a> {
+    fn parse(mut input: &'a [u8]) -> Option<Self> {
+        // MacData ::= SEQUENCE {
+        //   mac DigestInfo,
+        //   macSalt OCTET STRING,
+        //   iterations INTEGER DEFAULT 1 }
+        //
+        // DigestInfo ::= SEQUENCE {
+        //   digestAlgorithm DigestAlgorithmIdentifier,
+        //   digest OCTET STRING }
+        let mut digest_info = take(&mut input, der::SEQUENCE)?;
+        let mut algorithm = take(&mut digest_info, der::SEQUENCE)?;
+        let (digest, hmac) = match take(&mut algorithm, der::OID)? {
+            OID_SHA1 => (
+                &digest::SHA1_FOR_LEGACY_USE_ONLY,
+                hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            ),
+            OID_SHA256 => (&digest::SHA256, hmac::HMAC_SHA256),
+            OID_SHA384 => (&digest::SHA384, hmac::HMAC_SHA384),
+            OID_SHA512 => (&digest::SHA512, hmac::HMAC_SHA512),
+            _ => return None,
+        };
+        if der::peek(algorithm, der::NULL) {
+            take(&mut algorithm, der::NULL)?;
+        }
+        let mac = take(&mut digest_info, der::OCTET_STRING)?;
+        let salt = take(&mut input, der::OCTET_STRING)?;
+        let iterations = match input.is_empty() {
+            true => 1,
+            false => take_u32(&mut input)?,
+        };
+
+        // the MAC key derivation is bounded like PBKDF2, for the same reason
+        match algorithm.is_empty() && iterations > 0 && iterations <= pbes2::MAX_ITERATIONS {
+            true => Some(Self {
+                digest,
+                hmac,
+                mac,
+                salt,
+                iterations,
+            }),
+            false => None,
+        }
+    }
+
+    fn verify(&self, auth_safe: &[u8], passphrase: &[u8]) -> Result<(), Error> {
+        let key = hmac::Key::new(self.hmac, self.mac_key(passphrase).as_ref());
+        hmac::verify(&key, auth_safe, self.mac).map_err(|_| KeyLoadingError::WrongPassphrase.into())
+    }
+
+    /// Derive the MAC key, using the key derivation function of [RFC7292 appendix B].
+    ///
+    /// Only one block of output is needed, which simplifies it considerably.
+    ///
+    /// [RFC7292 appendix B]: <https://www.rfc-editor.org/rfc/rfc7292#appendix-B>
+    fn mac_key(&self, passphrase: &[u8]) -> digest::Digest {
+        // The passphrase is used as a NUL-terminated BMPString.
+        let mut password = Vec::with_capacity(passphrase.len() * 2 + 2);
+        for unit in String::from_utf8_lossy(passphrase).encode_utf16() {
+            password.extend_from_slice(&unit.to_be_bytes());
+        }
+        password.extend_from_slice(&[0, 0]);
+
+        let block_len = self.digest.block_len();
+        let mut input = vec![MAC_KEY_ID; block_len];
+        extend_to_block_multiple(&mut input, self.salt, block_len);
+        extend_to_block_multiple(&mut input, &password, block_len);
+        password.zeroize();
+
+        let mut key = digest::digest(self.digest, &input);
+        input.zeroize();
+        for _ in 1..self.iterations {
+            key = digest::digest(self.digest, key.as_ref());
+        }
+        key
+    }
+}
+
+/// Append `data` to `out`, repeated as needed to fill a multiple of `block_len` bytes.
+fn extend_to_block_multiple(out: &mut Vec<u8>, data: &[u8], block_len: usize) {
+    let len = (data.len() + block_len - 1) / block_len * block_len;
+    out.extend(data.iter().cycle().take(len));
+}
+
+/// Parse a `ContentInfo`, returning its content type and content.
+fn parse_content_info<'a>(input: &mut &'a [u8]) -> Option<(&'a [u8], &'a [u8])> {
+    // ContentInfo ::= SEQUENCE {
+    //   contentType ContentType,
+    //   content [0] EXPLICIT ANY DEFINED BY contentType }
+    let content_type = take(input, der::OID)?;
+    let content = take(input, der::CONTEXT_CONSTRUCTED_0)?;
+    Some((content_type, content))
+}
+
+/// Parse an `EncryptedData`, returning its encryption algorithm and encrypted content.
+fn parse_encrypted_data(mut input: &[u8]) -> Option<(&[u8], &[u8])> {
+    // EncryptedData ::= SEQUENCE {
+    //   version CMSVersion,
+    //   encryptedContentInfo EncryptedContentInfo,
+    //   unprotectedAttrs [1] IMPLICIT UnprotectedAttributes OPTIONAL }
+    //
+    // EncryptedContentInfo ::= SEQUENCE {
+    //   contentType ContentType,
+    //   contentEncryptionAlgorithm ContentEncryptionAlgorithmIdentifier,
+    //   encryptedContent [0] IMPLICIT EncryptedContent OPTIONAL }
+    let mut encrypted_data = take(&mut input, der::SEQUENCE)?;
+    take_u32(&mut encrypted_data)?;
+    let mut content_info = take(&mut encrypted_data, der::SEQUENCE)?;
+    if take(&mut content_info, der::OID)? != OID_DATA {
+        return None;
+    }
+    let algorithm = take(&mut content_info, der::SEQUENCE)?;
+    let encrypted_content = take(&mut content_info, der::CONTEXT_0)?;
+    Some((algorithm, encrypted_content))
+}
+
+fn malformed() -> Error {
+    KeyLoadingError::UnsupportedFormat.into()
+}
+
+const MAC_KEY_ID: u8 = 3;
+
+// 1.2.840.113549.1.7.{1,6}
+const OID_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+const OID_ENCRYPTED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x06];
+// 1.2.840.113549.1.12.10.1.{1,2,3}
+const OID_KEY_BAG: &[u8] = &[
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x0a, 0x01, 0x01,
+];
+const OID_PKCS8_SHROUDED_KEY_BAG: &[u8] = &[
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x0a, 0x01, 0x02,
+];
+const OID_CERT_BAG: &[u8] = &[
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x0a, 0x01, 0x03,
+];
+// 1.2.840.113549.1.9.22.1
+const OID_X509_CERTIFICATE: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x16, 0x01];
+// 1.3.14.3.2.26
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+// 2.16.840.1.101.3.4.2.{1,2,3}
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OID_SHA384: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02];
+const OID_SHA512: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_load_bundles() {
+        for bundle in [
+            &include_bytes!("../../testdata/ecdsa-p256-end.p12")[..],
+            &include_bytes!("../../testdata/ecdsa-p256-end.sha1-nocertpbe.p12")[..],
+        ] {
+            let certified_key =
+                load_certified_key(bundle, &|| Ok(b"rustls-test".to_vec())).unwrap();
+            assert_eq!(certified_key.cert.len(), 3);
+            certified_key.keys_match().unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_passphrase() {
+        let bundle = include_bytes!("../../testdata/ecdsa-p256-end.p12");
+        assert_eq!(
+            load_certified_key(bundle, &|| Ok(b"wrong".to_vec())).err(),
+            Some(Error::KeyLoading(KeyLoadingError::WrongPassphrase))
+        );
+        assert_eq!(
+            load_certified_key(bundle, &|| Ok(vec![0xff])).err(),
+            Some(Error::KeyLoading(KeyLoadingError::PassphraseNotUtf8))
+        );
+    }
+
+    #[test]
+    fn requires_mac_unless_allowed() {
+        fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+            let mut out = vec![tag];
+            match contents.len() {
+                len @ 0..=0x7f => out.push(len as u8),
+                len => out.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]),
+            }
+            out.extend_from_slice(contents);
+            out
+        }
+
+        // rebuild the PFX without its macData
+        let bundle = include_bytes!("../../testdata/ecdsa-p256-end.p12");
+        let mut pfx = take(&mut &bundle[..], der::SEQUENCE).unwrap();
+        let version = take(&mut pfx, der::INTEGER).unwrap();
+        let auth_safe = take(&mut pfx, der::SEQUENCE).unwrap();
+        let unauthenticated = der(
+            der::SEQUENCE,
+            &[der(der::INTEGER, version), der(der::SEQUENCE, auth_safe)].concat(),
+        );
+
+        let passphrase = || Ok(b"rustls-test".to_vec());
+        assert_eq!(
+            load_certified_key(&unauthenticated, &passphrase).err(),
+            Some(Error::KeyLoading(KeyLoadingError::MissingMac))
+        );
+        load_certified_key_without_mac(&unauthenticated, &passphrase).unwrap();
+        load_certified_key_without_mac(bundle, &passphrase).unwrap();
+        assert_eq!(
+            load_certified_key_without_mac(bundle, &|| Ok(b"wrong".to_vec())).err(),
+            Some(Error::KeyLoading(KeyLoadingError::WrongPassphrase))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_bundles() {
+        let bundle = include_bytes!("../../testdata/ecdsa-p256-end.p12");
+        for input in [
+            &bundle[..bundle.len() - 1],
+            &include_bytes!("../../testdata/nistp256key.pkcs8.aes256-sha256.der")[..],
+        ] {
+            assert_eq!(
+                load_certified_key(input, &|| Ok(b"rustls-test".to_vec())).err(),
+                Some(Error::KeyLoading(KeyLoadingError::UnsupportedFormat))
+            );
+        }
+    }
+}
@@ -38,6 +38,22 @@ pub trait Tls13AeadAlgorithm: Send + Sync {
     fn fips(&self) -> bool {
         false
     }
+
+    /// Return `true` if this is backed by a formally verified implementation.
+    ///
+    /// This means the implementation has been proven, by machine-checked proofs,
+    /// to meet its specification.
+    fn formally_verified(&self) -> bool {
+        false
+    }
+
+    /// The length in bytes of the authentication tag added to each record.
+    ///
+    /// The default is 16, the tag length of every AEAD usable with TLS except
+    /// those with `_8` names, such as `AES_128_CCM_8`.
+    fn tag_len(&self) -> usize {
+        16
+    }
 }
 
 /// Factory trait for building `MessageEncrypter` and `MessageDecrypter` for a TLS1.2 cipher suite.
@@ -84,6 +100,22 @@ pub trait Tls12AeadAlgorithm: Send + Sync + 'static {
     fn fips(&self) -> bool {
         false
     }
+
+    /// Return `true` if this is backed by a formally verified implementation.
+    ///
+    /// This means the implementation has been proven, by machine-checked proofs,
+    /// to meet its specification.
+    fn formally_verified(&self) -> bool {
+        false
+    }
+
+    /// The length in bytes of the authentication tag added to each record.
+    ///
+    /// The default is 16, the tag length of every AEAD usable with TLS except
+    /// those with `_8` names, such as `AES_128_CCM_8`.
+    fn tag_len(&self) -> usize {
+        16
+    }
 }
 
 /// An error indicating that the AEAD algorithm does not support the requested operation.
@@ -23,6 +23,14 @@ pub trait Hash: Send + Sync {
     fn fips(&self) -> bool {
         false
     }
+
+    /// Return `true` if this is backed by a formally verified implementation.
+    ///
+    /// This means the implementation has been proven, by machine-checked proofs,
+    /// to meet its specification.
+    fn formally_verified(&self) -> bool {
+        false
+    }
 }
 
 /// A hash output, stored as a value.
@@ -95,6 +95,17 @@ pub trait HpkeSealer: Debug + Send + Sync + 'static {
     /// Seal the provided `plaintext` with additional data `aad`, returning
     /// ciphertext.
     fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// The KEM shared secret this context was set up with.
+    ///
+    /// This is only used for logging with [`KeyLog`] as `ECH_SECRET`, so that
+    /// tools can decrypt an encrypted client hello.  The default returns `None`,
+    /// and such secrets are not logged.
+    ///
+    /// [`KeyLog`]: crate::KeyLog
+    fn kem_shared_secret(&self) -> Option<&[u8]> {
+        None
+    }
 }
 
 /// An HPKE opener context.
@@ -0,0 +1,170 @@
+//! Support for implementing hybrid key exchange groups.
+//!
+//! A hybrid group, such as X25519MLKEM768, runs a classical key exchange and
+//! a post-quantum KEM together.  Its key shares and shared secret are each the
+//! concatenation of those of its two components.  [`HybridLayout`] describes
+//! where each component goes, so that providers need not slice and join the
+//! bytes by hand.
+//!
+//! An [`ActiveKeyExchange`] for a hybrid group should also implement
+//! [`ActiveKeyExchange::hybrid_component()`] and
+//! [`ActiveKeyExchange::complete_hybrid_component()`].
+//!
+//! [`ActiveKeyExchange`]: super::ActiveKeyExchange
+//! [`ActiveKeyExchange::hybrid_component()`]: super::ActiveKeyExchange::hybrid_component
+//! [`ActiveKeyExchange::complete_hybrid_component()`]: super::ActiveKeyExchange::complete_hybrid_component
+
+use alloc::vec::Vec;
+
+use super::SharedSecret;
+
+/// Where the two components of a hybrid group go, within its key shares and shared secret.
+///
+/// For example, X25519MLKEM768 puts the ML-KEM-768 component first:
+///
+/// ```
+/// use rustls::crypto::hybrid::HybridLayout;
+///
+/// const X25519MLKEM768: HybridLayout = HybridLayout {
+///     classical_share_len: 32,
+///     post_quantum_client_share_len: 1184,
+///     post_quantum_server_share_len: 1088,
+///     post_quantum_first: true,
+/// };
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HybridLayout {
+    /// The length of the classical component's key share.
+    ///
+    /// This is the same for clients and servers.
+    pub classical_share_len: usize,
+
+    /// The length of the post-quantum component's key share sent by the client:
+    /// its encapsulation key.
+    pub post_quantum_client_share_len: usize,
+
+    /// The length of the post-quantum component's key share sent by the server:
+    /// its ciphertext.
+    pub post_quantum_server_share_len: usize,
+
+    /// Whether the post-quantum component comes before the classical one.
+    pub post_quantum_first: bool,
+}
+
+impl HybridLayout {
+    /// Split a key share sent by the client into its components.
+    ///
+    /// This returns `None` if `share` is the wrong length.
+    pub fn split_client_share<'a>(&self, share: &'a [u8]) -> Option<HybridShares<'a>> {
+        self.split(share, self.post_quantum_client_share_len)
+    }
+
+    /// Split a key share sent by the server into its components.
+    ///
+    /// This returns `None` if `share` is the wrong length.
+    pub fn split_server_share<'a>(&self, share: &'a [u8]) -> Option<HybridShares<'a>> {
+        self.split(share, self.post_quantum_server_share_len)
+    }
+
+    /// Join the components of a key share, in order.
+    pub fn join_shares(&self, classical: &[u8], post_quantum: &[u8]) -> Vec<u8> {
+        let (first, second) = self.order(classical, post_quantum);
+        let mut share = Vec::with_capacity(first.len() + second.len());
+        share.extend_from_slice(first);
+        share.extend_from_slice(second);
+        share
+    }
+
+    /// Join the components' shared secrets into the hybrid group's shared secret.
+    pub fn combine_secrets(&self, classical: SharedSecret, post_quantum: &[u8]) -> SharedSecret {
+        let (first, second) = self.order(classical.secret_bytes(), post_quantum);
+        let mut buf = Vec::with_capacity(first.len() + second.len());
+        buf.extend_from_slice(first);
+        buf.extend_from_slice(second);
+        SharedSecret { buf, offset: 0 }
+    }
+
+    fn split<'a>(&self, share: &'a [u8], post_quantum_len: usize) -> Option<HybridShares<'a>> {
+        if share.len() != self.classical_share_len + post_quantum_len {
+            return None;
+        }
+
+        Some(match self.post_quantum_first {
+            true => {
+                let (post_quantum, classical) = share.split_at(post_quantum_len);
+                HybridShares {
+                    classical,
+                    post_quantum,
+                }
+            }
+            false => {
+                let (classical, post_quantum) = share.split_at(self.classical_share_len);
+                HybridShares {
+                    classical,
+                    post_quantum,
+                }
+            }
+        })
+    }
+
+    fn order<'a>(&self, classical: &'a [u8], post_quantum: &'a [u8]) -> (&'a [u8], &'a [u8]) {
+        match self.post_quantum_first {
+            true => (post_quantum, classical),
+            false => (classical, post_quantum),
+        }
+    }
+}
+
+/// The components of a hybrid key share, from [`HybridLayout`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HybridShares<'a> {
+    /// The classical component's key share.
+    pub classical: &'a [u8],
+
+    /// The post-quantum component's key share.
+    pub post_quantum: &'a [u8],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: HybridLayout = HybridLayout {
+        classical_share_len: 2,
+        post_quantum_client_share_len: 3,
+        post_quantum_server_share_len: 1,
+        post_quantum_first: true,
+    };
+
+    #[test]
+    fn splits_and_joins_shares() {
+        let shares = LAYOUT
+            .split_client_share(&[1, 1, 1, 2, 2])
+            .unwrap();
+        assert_eq!(shares.post_quantum, &[1, 1, 1]);
+        assert_eq!(shares.classical, &[2, 2]);
+        assert_eq!(
+            LAYOUT.join_shares(shares.classical, shares.post_quantum),
+            &[1, 1, 1, 2, 2]
+        );
+
+        let classical_first = HybridLayout {
+            post_quantum_first: false,
+            ..LAYOUT
+        };
+        let shares = classical_first
+            .split_server_share(&[2, 2, 1])
+            .unwrap();
+        assert_eq!(shares.classical, &[2, 2]);
+        assert_eq!(shares.post_quantum, &[1]);
+
+        assert_eq!(LAYOUT.split_client_share(&[1, 1, 1, 2]), None);
+        assert_eq!(LAYOUT.split_server_share(&[1, 1, 1, 2]), None);
+    }
+
+    #[test]
+    fn combines_secrets() {
+        let secret = LAYOUT.combine_secrets(SharedSecret::from(&[2, 2][..]), &[1, 1, 1]);
+        assert_eq!(secret.secret_bytes(), &[1, 1, 1, 2, 2]);
+    }
+}
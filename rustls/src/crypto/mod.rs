@@ -50,8 +50,11 @@ pub mod tls13;
 /// Hybrid public key encryption (RFC 9180).
 pub mod hpke;
 
+pub mod hybrid;
+
 // Message signing interfaces. Re-exported under rustls::sign. Kept crate-internal here to
 // avoid having two import paths to the same types.
+pub(crate) mod remote_signer;
 pub(crate) mod signer;
 
 pub use crate::msgs::handshake::KeyExchangeAlgorithm;
@@ -350,6 +353,33 @@ pub trait KeyProvider: Send + Sync + Debug {
         key_der: PrivateKeyDer<'static>,
     ) -> Result<Arc<dyn SigningKey>, Error>;
 
+    /// Decrypt and load a private signing key from `encrypted`, a DER-encoded
+    /// PKCS#8 `EncryptedPrivateKeyInfo` (as in [RFC5958 section 3]).
+    ///
+    /// `passphrase` is called, at most once, to obtain the passphrase the key was
+    /// encrypted with; any error it returns is returned from this function.  The
+    /// decrypted key is then loaded as if by [`KeyProvider::load_private_key()`].
+    /// Implementations should report keys they cannot load with [`Error::KeyLoading`],
+    /// telling a wrong passphrase apart from an unsupported format.
+    ///
+    /// The default implementation returns an error: providers that support
+    /// encrypted keys must override it.  The aws-lc-rs provider supports keys
+    /// encrypted with PBES2 ([RFC8018]), using PBKDF2 (with at most 2,000,000
+    /// iterations) and AES-CBC; the *ring* provider does not support encrypted keys.
+    ///
+    /// [RFC5958 section 3]: <https://www.rfc-editor.org/rfc/rfc5958#section-3>
+    /// [RFC8018]: <https://www.rfc-editor.org/rfc/rfc8018>
+    fn load_encrypted_private_key(
+        &self,
+        encrypted: &[u8],
+        passphrase: &dyn Fn() -> Result<Vec<u8>, Error>,
+    ) -> Result<Arc<dyn SigningKey>, Error> {
+        let _ = (encrypted, passphrase);
+        Err(Error::General(
+            "encrypted private keys are not supported by this key provider".into(),
+        ))
+    }
+
     /// Return `true` if this is backed by a FIPS-approved implementation.
     ///
     /// If this returns `true`, that must be the case for all possible key types
@@ -479,6 +509,36 @@ pub trait ActiveKeyExchange: Send + Sync {
 
     /// Return the group being used.
     fn group(&self) -> NamedGroup;
+
+    /// For a hybrid key exchange, the group and key share of its classical component.
+    ///
+    /// A hybrid key exchange should return this, so the classical component can be
+    /// completed alone with [`ActiveKeyExchange::complete_hybrid_component()`].  When
+    /// the classical group is also in the client's [`CryptoProvider::kx_groups`], the
+    /// client offers this key share as well as the hybrid one in its first
+    /// `ClientHello`, so a server that supports the classical group but not the hybrid
+    /// one can choose it without a retry.  [`hybrid::HybridLayout`] helps find the
+    /// component within [`ActiveKeyExchange::pub_key()`].
+    ///
+    /// The default returns `None`, meaning this is not a hybrid key exchange.
+    fn hybrid_component(&self) -> Option<(NamedGroup, &[u8])> {
+        None
+    }
+
+    /// Completes only the classical component of a hybrid key exchange, given the peer's
+    /// public key for the group returned by [`ActiveKeyExchange::hybrid_component()`].
+    ///
+    /// The result is the classical component's shared secret alone.  This is only
+    /// called if [`ActiveKeyExchange::hybrid_component()`] returns `Some`; the default
+    /// returns an error.
+    ///
+    /// This consumes and so terminates the [`ActiveKeyExchange`].
+    fn complete_hybrid_component(
+        self: Box<Self>,
+        _peer_pub_key: &[u8],
+    ) -> Result<SharedSecret, Error> {
+        Err(Error::General("not a hybrid key exchange".into()))
+    }
 }
 
 /// The result from [`SupportedKxGroup::start_and_complete()`].
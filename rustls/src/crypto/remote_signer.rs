@@ -0,0 +1,353 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use pki_types::SubjectPublicKeyInfoDer;
+
+use crate::crypto::hash::{Hash, HashAlgorithm};
+use crate::crypto::signer::{Signer, SigningKey};
+use crate::enums::{SignatureAlgorithm, SignatureScheme};
+use crate::error::Error;
+
+/// Sends signing requests to a remote key server, on behalf of a [`RemoteSigningKey`].
+///
+/// This allows the private key to be kept on a separate key server, with the
+/// TLS endpoint only holding the certificate chain ("keyless TLS").  The wire
+/// protocol, authentication of the key server, and connection management are
+/// all up to the implementation.
+pub trait RequestsSignatures: Debug + Send + Sync {
+    /// Ask the key server to fulfil `request`, returning the signature.
+    ///
+    /// This is called synchronously during the handshake.  It should give up
+    /// and return an error if no reply arrives within `timeout`.
+    fn request_signature(
+        &self,
+        request: &RemoteSignRequest<'_>,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Error>;
+}
+
+/// A request for a remote key server to make a signature.
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct RemoteSignRequest<'a> {
+    /// Which of the key server's keys to use.
+    pub key_id: &'a [u8],
+    /// The signature scheme to use, which determines the format of the signature.
+    pub scheme: SignatureScheme,
+    /// What to sign.
+    ///
+    /// If `prehashed` is true, this is the digest of the message, made with the
+    /// hash function implicit in `scheme`.  Otherwise it is the message itself,
+    /// to be hashed (if `scheme` calls for it) by the key server.
+    pub input: &'a [u8],
+    /// Whether `input` is a digest, rather than the message.
+    pub prehashed: bool,
+}
+
+/// A [`SigningKey`] whose private key is held by a remote key server.
+///
+/// Signing requests are sent using one of several [`RequestsSignatures`] transports,
+/// for example one for each replica of the key server.  They are tried in the order
+/// given, starting with the one that most recently succeeded; if one fails (including
+/// by timing out), the next is tried.  An error is only returned if all fail.
+///
+/// By default the whole message is sent to the key server.  Use
+/// [`RemoteSigningKey::with_prehash()`] to hash messages locally, and send only
+/// their digests.
+pub struct RemoteSigningKey {
+    key_id: Vec<u8>,
+    algorithm: SignatureAlgorithm,
+    schemes: Vec<SignatureScheme>,
+    public_key: Option<SubjectPublicKeyInfoDer<'static>>,
+    timeout: Duration,
+    hashes: Vec<&'static dyn Hash>,
+    servers: Arc<KeyServers>,
+}
+
+impl RemoteSigningKey {
+    /// Make a key that signs with the key server's key named `key_id`.
+    ///
+    /// `algorithm` is the key's algorithm, and `schemes` the signature schemes it
+    /// can be used with, in preference order.  Each request is given a timeout of
+    /// five seconds, which can be changed with [`RemoteSigningKey::with_timeout()`].
+    pub fn new(
+        key_id: Vec<u8>,
+        algorithm: SignatureAlgorithm,
+        schemes: Vec<SignatureScheme>,
+        transports: Vec<Arc<dyn RequestsSignatures>>,
+    ) -> Self {
+        Self {
+            key_id,
+            algorithm,
+            schemes,
+            public_key: None,
+            timeout: Duration::from_secs(5),
+            hashes: Vec::new(),
+            servers: Arc::new(KeyServers {
+                transports,
+                preferred: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Set the timeout given to each signing request.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the key's `SubjectPublicKeyInfo`, so that [`CertifiedKey::keys_match()`]
+    /// can check it against the certificate.
+    ///
+    /// [`CertifiedKey::keys_match()`]: crate::sign::CertifiedKey::keys_match
+    pub fn with_public_key(mut self, public_key: SubjectPublicKeyInfoDer<'static>) -> Self {
+        self.public_key = Some(public_key);
+        self
+    }
+
+    /// Hash messages locally using `hashes`, and send only their digests to the key server.
+    ///
+    /// This applies to signature schemes whose hash function is among `hashes`.
+    /// Messages for other schemes (including EdDSA schemes, which do not prehash)
+    /// are still sent whole.
+    pub fn with_prehash(mut self, hashes: &[&'static dyn Hash]) -> Self {
+        self.hashes = hashes.to_vec();
+        self
+    }
+}
+
+impl SigningKey for RemoteSigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        let scheme = *self
+            .schemes
+            .iter()
+            .find(|scheme| offered.contains(scheme))?;
+        let hash = scheme_hash(scheme).and_then(|algorithm| {
+            self.hashes
+                .iter()
+                .find(|hash| hash.algorithm() == algorithm)
+                .copied()
+        });
+
+        Some(Box::new(RemoteSigner {
+            key_id: self.key_id.clone(),
+            scheme,
+            hash,
+            timeout: self.timeout,
+            servers: Arc::clone(&self.servers),
+        }))
+    }
+
+    fn public_key(&self) -> Option<SubjectPublicKeyInfoDer<'_>> {
+        self.public_key.clone()
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.algorithm
+    }
+}
+
+impl Debug for RemoteSigningKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteSigningKey")
+            .field("key_id", &self.key_id)
+            .field("algorithm", &self.algorithm)
+            .field("schemes", &self.schemes)
+            .field("timeout", &self.timeout)
+            .field("transports", &self.servers.transports)
+            .finish_non_exhaustive()
+    }
+}
+
+struct KeyServers {
+    transports: Vec<Arc<dyn RequestsSignatures>>,
+    /// Index of the transport that most recently succeeded.
+    preferred: AtomicUsize,
+}
+
+struct RemoteSigner {
+    key_id: Vec<u8>,
+    scheme: SignatureScheme,
+    hash: Option<&'static dyn Hash>,
+    timeout: Duration,
+    servers: Arc<KeyServers>,
+}
+
+impl Signer for RemoteSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let digest = self.hash.map(|hash| hash.hash(message));
+        let request = RemoteSignRequest {
+            key_id: &self.key_id,
+            scheme: self.scheme,
+            input: match &digest {
+                Some(digest) => digest.as_ref(),
+                None => message,
+            },
+            prehashed: digest.is_some(),
+        };
+
+        let transports = &self.servers.transports;
+        let first = self
+            .servers
+            .preferred
+            .load(Ordering::Relaxed);
+        let mut result = Err(Error::General("no key servers configured".into()));
+        for i in 0..transports.len() {
+            let index = (first + i) % transports.len();
+            result = transports[index].request_signature(&request, self.timeout);
+            if result.is_ok() {
+                self.servers
+                    .preferred
+                    .store(index, Ordering::Relaxed);
+                break;
+            }
+        }
+        result
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+impl Debug for RemoteSigner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteSigner")
+            .field("scheme", &self.scheme)
+            .field("prehashed", &self.hash.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The hash function implicit in `scheme`, if it prehashes messages.
+fn scheme_hash(scheme: SignatureScheme) -> Option<HashAlgorithm> {
+    match scheme {
+        SignatureScheme::RSA_PKCS1_SHA1 | SignatureScheme::ECDSA_SHA1_Legacy => {
+            Some(HashAlgorithm::SHA1)
+        }
+        SignatureScheme::RSA_PKCS1_SHA256
+        | SignatureScheme::ECDSA_NISTP256_SHA256
+        | SignatureScheme::RSA_PSS_SHA256 => Some(HashAlgorithm::SHA256),
+        SignatureScheme::RSA_PKCS1_SHA384
+        | SignatureScheme::ECDSA_NISTP384_SHA384
+        | SignatureScheme::RSA_PSS_SHA384 => Some(HashAlgorithm::SHA384),
+        SignatureScheme::RSA_PKCS1_SHA512
+        | SignatureScheme::ECDSA_NISTP521_SHA512
+        | SignatureScheme::RSA_PSS_SHA512 => Some(HashAlgorithm::SHA512),
+        _ => None,
+    }
+}
+
+test_for_each_provider! {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::time::Duration;
+
+    use super::*;
+    use provider::hash::SHA256;
+
+    #[derive(Debug)]
+    struct FakeKeyServer {
+        fails: bool,
+        calls: AtomicUsize,
+    }
+
+    impl FakeKeyServer {
+        fn new(fails: bool) -> Arc<Self> {
+            Arc::new(Self {
+                fails,
+                calls: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    impl RequestsSignatures for FakeKeyServer {
+        fn request_signature(
+            &self,
+            request: &RemoteSignRequest<'_>,
+            timeout: Duration,
+        ) -> Result<Vec<u8>, Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            assert_eq!(request.key_id, b"key");
+            assert_eq!(timeout, Duration::from_secs(1));
+            match self.fails {
+                true => Err(Error::General("unreachable".into())),
+                false => Ok(vec![u8::from(request.prehashed), request.input.len() as u8]),
+            }
+        }
+    }
+
+    fn remote_key(servers: &[&Arc<FakeKeyServer>]) -> RemoteSigningKey {
+        RemoteSigningKey::new(
+            b"key".to_vec(),
+            SignatureAlgorithm::ECDSA,
+            vec![
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+            ],
+            servers
+                .iter()
+                .map(|server| -> Arc<dyn RequestsSignatures> { Arc::<FakeKeyServer>::clone(server) })
+                .collect(),
+        )
+        .with_timeout(Duration::from_secs(1))
+    }
+
+    #[test]
+    fn chooses_scheme_and_prehashes() {
+        let server = FakeKeyServer::new(false);
+        let key = remote_key(&[&server]).with_prehash(&[&SHA256]);
+        assert!(key
+            .choose_scheme(&[SignatureScheme::ED25519])
+            .is_none());
+
+        let signer = key
+            .choose_scheme(&[
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+            ])
+            .unwrap();
+        assert_eq!(signer.scheme(), SignatureScheme::ECDSA_NISTP384_SHA384);
+        // no SHA-384 available: the message is sent
+        assert_eq!(signer.sign(&[0; 100]).unwrap(), vec![0, 100]);
+
+        let signer = key
+            .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+            .unwrap();
+        assert_eq!(signer.sign(&[0; 100]).unwrap(), vec![1, 32]);
+    }
+
+    #[test]
+    fn fails_over_between_key_servers() {
+        let broken = FakeKeyServer::new(true);
+        let working = FakeKeyServer::new(false);
+        let key = remote_key(&[&broken, &working]);
+        let signer = key
+            .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+            .unwrap();
+
+        signer.sign(b"hello").unwrap();
+        assert_eq!(broken.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(working.calls.load(Ordering::SeqCst), 1);
+
+        // the working server is now tried first
+        signer.sign(b"hello").unwrap();
+        assert_eq!(broken.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(working.calls.load(Ordering::SeqCst), 2);
+
+        // if all fail, the last error is returned
+        let key = remote_key(&[&broken]);
+        assert_eq!(
+            key.choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+                .unwrap()
+                .sign(b"hello"),
+            Err(Error::General("unreachable".into()))
+        );
+    }
+}
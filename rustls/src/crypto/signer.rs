@@ -5,10 +5,12 @@ use core::fmt::Debug;
 
 use pki_types::{AlgorithmIdentifier, CertificateDer, SubjectPublicKeyInfoDer};
 
+use crate::crypto::WebPkiSupportedAlgorithms;
 use crate::enums::{SignatureAlgorithm, SignatureScheme};
 use crate::error::{Error, InconsistentKeys};
 use crate::server::ParsedCertificate;
-use crate::x509;
+use crate::verify::DigitallySignedStruct;
+use crate::{webpki, x509};
 
 /// An abstract signing key.
 ///
@@ -98,6 +100,9 @@ pub struct CertifiedKey {
     /// An optional OCSP response from the certificate issuer,
     /// attesting to its continued validity.
     pub ocsp: Option<Vec<u8>>,
+
+    /// See [`CertifiedKey::with_intermediate_ocsp()`].
+    intermediate_ocsp: Vec<Option<Vec<u8>>>,
 }
 
 impl CertifiedKey {
@@ -110,7 +115,64 @@ impl CertifiedKey {
             cert,
             key,
             ocsp: None,
+            intermediate_ocsp: Vec::new(),
+        }
+    }
+
+    /// Add OCSP responses for the intermediate certificates.
+    ///
+    /// `intermediate_ocsp[i]` is stapled to `cert[i + 1]`.  These are only
+    /// sent in TLS1.3, which allows a response for each certificate in the chain.
+    pub fn with_intermediate_ocsp(mut self, intermediate_ocsp: Vec<Option<Vec<u8>>>) -> Self {
+        self.intermediate_ocsp = intermediate_ocsp;
+        self
+    }
+
+    /// The OCSP responses for the intermediate certificates.
+    ///
+    /// See [`CertifiedKey::with_intermediate_ocsp()`].
+    pub fn intermediate_ocsp(&self) -> &[Option<Vec<u8>>] {
+        &self.intermediate_ocsp
+    }
+
+    /// Make a new CertifiedKey, checking that `key` and `cert` are consistent.
+    ///
+    /// This checks that the chain is not empty, that the key matches the end-entity
+    /// certificate (see [`CertifiedKey::keys_match()`]; keys that cannot report their
+    /// public key are accepted), and that the chain is in order (see
+    /// [`CertifiedKey::chain_in_order()`]).
+    pub fn new_checked(
+        cert: Vec<CertificateDer<'static>>,
+        key: Arc<dyn SigningKey>,
+    ) -> Result<Self, Error> {
+        let certified_key = Self::new(cert, key);
+        match certified_key.keys_match() {
+            // Don't treat unknown consistency as an error
+            Ok(()) | Err(Error::InconsistentKeys(InconsistentKeys::Unknown)) => (),
+            Err(err) => return Err(err),
+        }
+        certified_key.chain_in_order()?;
+        Ok(certified_key)
+    }
+
+    /// Check that each certificate in the chain is issued by the one following it.
+    ///
+    /// This compares the issuer of each certificate with the subject of the next,
+    /// and does not verify signatures.  It fails with
+    /// [`InconsistentKeys::ChainOutOfOrder`] for the first certificate whose
+    /// issuer does not match.
+    pub fn chain_in_order(&self) -> Result<(), Error> {
+        let parsed = self
+            .cert
+            .iter()
+            .map(ParsedCertificate::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        for (index, pair) in parsed.windows(2).enumerate() {
+            if pair[0].0.issuer() != pair[1].0.subject() {
+                return Err(InconsistentKeys::ChainOutOfOrder(index).into());
+            }
         }
+        Ok(())
     }
 
     /// Verify the consistency of this [`CertifiedKey`]'s public and private keys.
@@ -128,6 +190,57 @@ impl CertifiedKey {
         }
     }
 
+    /// The signature schemes from `supported_algs` that the key claims to support.
+    ///
+    /// This only asks the [`SigningKey`]; nothing is signed.  See
+    /// [`CertifiedKey::self_test()`] to check that the key actually works.
+    pub fn supported_schemes(
+        &self,
+        supported_algs: &WebPkiSupportedAlgorithms,
+    ) -> Vec<SignatureScheme> {
+        supported_algs
+            .supported_schemes()
+            .into_iter()
+            .filter(|scheme| {
+                self.key
+                    .choose_scheme(&[*scheme])
+                    .is_some()
+            })
+            .collect()
+    }
+
+    /// Check that the key makes signatures that verify against the end-entity certificate.
+    ///
+    /// For each scheme returned by [`CertifiedKey::supported_schemes()`], a test message
+    /// is signed, and the signature verified using the public key in the end-entity
+    /// certificate.  Call this when building a configuration to find keys that cannot
+    /// sign, such as those referring to a misconfigured HSM slot, before they are used
+    /// in a handshake.  This may be slow, as it makes one signature per scheme.
+    ///
+    /// Returns the schemes that were tested.  Errors from the [`Signer`] are returned
+    /// unchanged; a signature that does not verify is reported as
+    /// [`InconsistentKeys::SignatureInvalid`].
+    pub fn self_test(
+        &self,
+        supported_algs: &WebPkiSupportedAlgorithms,
+    ) -> Result<Vec<SignatureScheme>, Error> {
+        let cert = self.end_entity_cert()?;
+        let schemes = self.supported_schemes(supported_algs);
+        for scheme in &schemes {
+            let signer = match self.key.choose_scheme(&[*scheme]) {
+                Some(signer) => signer,
+                None => continue,
+            };
+            let dss = DigitallySignedStruct::new(*scheme, signer.sign(SELF_TEST_MESSAGE)?);
+            if webpki::verify_tls12_signature(SELF_TEST_MESSAGE, cert, &dss, supported_algs)
+                .is_err()
+            {
+                return Err(InconsistentKeys::SignatureInvalid(*scheme).into());
+            }
+        }
+        Ok(schemes)
+    }
+
     /// The end-entity certificate.
     pub fn end_entity_cert(&self) -> Result<&CertificateDer<'_>, Error> {
         self.cert
@@ -136,6 +249,8 @@ impl CertifiedKey {
     }
 }
 
+const SELF_TEST_MESSAGE: &[u8] = b"rustls signing key self-test";
+
 #[cfg_attr(not(any(feature = "aws_lc_rs", feature = "ring")), allow(dead_code))]
 pub(crate) fn public_key_to_spki(
     alg_id: &AlgorithmIdentifier,
@@ -68,6 +68,14 @@ pub trait Prf: Send + Sync {
     fn fips(&self) -> bool {
         false
     }
+
+    /// Return `true` if this is backed by a formally verified implementation.
+    ///
+    /// This means the implementation has been proven, by machine-checked proofs,
+    /// to meet its specification.
+    fn formally_verified(&self) -> bool {
+        false
+    }
 }
 
 pub(crate) fn prf(out: &mut [u8], hmac_key: &dyn hmac::Key, label: &[u8], seed: &[u8]) {
@@ -180,6 +180,14 @@ pub trait Hkdf: Send + Sync {
     fn fips(&self) -> bool {
         false
     }
+
+    /// Return `true` if this is backed by a formally verified implementation.
+    ///
+    /// This means the implementation has been proven, by machine-checked proofs,
+    /// to meet its specification.
+    fn formally_verified(&self) -> bool {
+        false
+    }
 }
 
 /// An extended HKDF implementation that supports directly extracting a pseudo-random key (PRK).
@@ -0,0 +1,67 @@
+use core::fmt::{self, Debug};
+
+use crate::common_state::Side;
+use crate::enums::{AlertDescription, ProtocolVersion};
+use crate::error::Error;
+
+/// Receives diagnostic messages about a connection.
+///
+/// This is an alternative to the `log` crate (used with the `logging` feature)
+/// for embedders that lack `log` infrastructure, such as `no_std` and FFI users.
+/// Unlike `log`, it is installed per configuration, and each [`Diagnostic`] comes
+/// with structured context about the connection.
+///
+/// It receives the messages rustls logs about a connection at the `debug` level and
+/// above, such as the choices made during the handshake, along with alerts sent and
+/// received (with the errors that caused them) and handshake completion.  Messages
+/// that do not concern a particular connection, such as those from loading
+/// configuration, and the more verbose `trace` output, are only available through `log`.
+///
+/// Install one with [`ClientConfig::diagnostics`] or [`ServerConfig::diagnostics`].
+/// Methods are called synchronously from within rustls, so should return promptly.
+///
+/// [`ClientConfig::diagnostics`]: crate::ClientConfig::diagnostics
+/// [`ServerConfig::diagnostics`]: crate::ServerConfig::diagnostics
+pub trait ReceivesDiagnostics: Debug + Send + Sync {
+    /// Whether diagnostics at `level` are wanted.
+    ///
+    /// Diagnostics are only built and given to [`ReceivesDiagnostics::diagnostic()`]
+    /// if this returns true.  The default accepts all levels.
+    fn enabled(&self, _level: DiagnosticLevel) -> bool {
+        true
+    }
+
+    /// Called with each diagnostic.
+    fn diagnostic(&self, diagnostic: &Diagnostic<'_>);
+}
+
+/// A diagnostic message about a connection, given to a [`ReceivesDiagnostics`].
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct Diagnostic<'a> {
+    /// How important this is.
+    pub level: DiagnosticLevel,
+    /// Which side of the connection this is from.
+    pub side: Side,
+    /// A human-readable description.
+    pub message: fmt::Arguments<'a>,
+    /// The protocol version of the connection, once negotiated.
+    pub protocol_version: Option<ProtocolVersion>,
+    /// The alert sent or received, if this concerns one.
+    pub alert: Option<AlertDescription>,
+    /// The error that ended the connection, if this concerns one.
+    pub error: Option<&'a Error>,
+}
+
+/// The importance of a [`Diagnostic`], ordered from most to least important.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DiagnosticLevel {
+    /// Something went wrong: for example, a fatal alert was sent or received.
+    Warn,
+    /// Something notable happened, such as the handshake completing.
+    Info,
+    /// Information useful for debugging, such as the cipher suite chosen or a warning
+    /// alert being received.
+    Debug,
+}
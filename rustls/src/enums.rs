@@ -511,6 +511,9 @@ enum_builder! {
         RSA_PSS_SHA512 => 0x0806,
         ED25519 => 0x0807,
         ED448 => 0x0808,
+        ML_DSA_44 => 0x0904,
+        ML_DSA_65 => 0x0905,
+        ML_DSA_87 => 0x0906,
     }
 }
 
@@ -551,6 +554,9 @@ impl SignatureScheme {
                 | Self::RSA_PSS_SHA384
                 | Self::RSA_PSS_SHA256
                 | Self::ED25519
+                | Self::ML_DSA_44
+                | Self::ML_DSA_65
+                | Self::ML_DSA_87
         )
     }
 }
@@ -583,6 +589,33 @@ enum_builder! {
     }
 }
 
+enum_builder! {
+    /// The `MaxFragmentLength` TLS protocol enum.  Values in this enum are taken
+    /// from [RFC6066].
+    ///
+    /// [RFC6066]: https://www.rfc-editor.org/rfc/rfc6066.html#section-4
+    @U8
+    pub enum MaxFragmentLength {
+        Len512 => 1,
+        Len1024 => 2,
+        Len2048 => 3,
+        Len4096 => 4,
+    }
+}
+
+impl MaxFragmentLength {
+    /// The maximum plaintext fragment length in bytes, or `None` for an unknown value.
+    pub fn fragment_len(&self) -> Option<usize> {
+        match self {
+            Self::Len512 => Some(512),
+            Self::Len1024 => Some(1024),
+            Self::Len2048 => Some(2048),
+            Self::Len4096 => Some(4096),
+            Self::Unknown(_) => None,
+        }
+    }
+}
+
 enum_builder! {
     /// The type of Encrypted Client Hello (`EchClientHelloType`).
     ///
@@ -610,6 +643,7 @@ mod tests {
             AlertDescription::CloseNotify,
             AlertDescription::NoApplicationProtocol,
         );
+        test_enum8::<MaxFragmentLength>(MaxFragmentLength::Len512, MaxFragmentLength::Len4096);
         test_enum16::<CertificateCompressionAlgorithm>(
             CertificateCompressionAlgorithm::Zlib,
             CertificateCompressionAlgorithm::Zstd,
@@ -5,7 +5,8 @@ use core::fmt;
 #[cfg(feature = "std")]
 use std::time::SystemTimeError;
 
-use crate::enums::{AlertDescription, ContentType, HandshakeType};
+use crate::client::ServiceBindingError;
+use crate::enums::{AlertDescription, ContentType, HandshakeType, SignatureScheme};
 use crate::msgs::handshake::{EchConfigPayload, KeyExchangeAlgorithm};
 use crate::rand;
 
@@ -87,6 +88,26 @@ pub enum Error {
     /// is complete.
     HandshakeNotComplete,
 
+    /// The handshake did not complete within the configured timeout.
+    ///
+    /// See [`ClientConfig::handshake_timeout`] and [`ServerConfig::handshake_timeout`].
+    ///
+    /// [`ClientConfig::handshake_timeout`]: crate::ClientConfig::handshake_timeout
+    /// [`ServerConfig::handshake_timeout`]: crate::ServerConfig::handshake_timeout
+    HandshakeTimedOut,
+
+    /// A [`HandshakeLimiter`] could not admit another handshake.
+    ///
+    /// [`HandshakeLimiter`]: crate::server::HandshakeLimiter
+    TooManyHandshakes,
+
+    /// The server's stapled OCSP response was not fresh enough to send.
+    ///
+    /// See [`StaleOcspPolicy::FailHandshake`].
+    ///
+    /// [`StaleOcspPolicy::FailHandshake`]: crate::server::StaleOcspPolicy::FailHandshake
+    StaleOcspResponse,
+
     /// The peer sent an oversized record/fragment.
     PeerSentOversizedRecord,
 
@@ -97,11 +118,40 @@ pub enum Error {
     /// or too large.
     BadMaxFragmentSize,
 
+    /// The `max_handshake_size` value supplied in configuration was too small,
+    /// or too large.
+    BadMaxHandshakeSize,
+
+    /// A list of ALPN protocols supplied in configuration could not be sent.
+    ///
+    /// This is reported by [`alpn::validate`].
+    ///
+    /// [`alpn::validate`]: crate::alpn::validate
+    InvalidAlpnProtocols(AlpnProtocolError),
+
     /// Specific failure cases from [`keys_match`].
     ///
     /// [`keys_match`]: crate::crypto::signer::CertifiedKey::keys_match
     InconsistentKeys(InconsistentKeys),
 
+    /// An encrypted private key, or a PKCS#12 bundle, could not be loaded.
+    ///
+    /// This is reported by [`KeyProvider::load_encrypted_private_key`], and by the
+    /// aws-lc-rs provider's PKCS#12 loading functions.
+    ///
+    /// [`KeyProvider::load_encrypted_private_key`]: crate::crypto::KeyProvider::load_encrypted_private_key
+    KeyLoading(KeyLoadingError),
+
+    /// The application used an API in a way that cannot work.
+    ApiMisuse(ApiMisuse),
+
+    /// A DNS service binding could not be used.
+    ///
+    /// This is reported by [`ServiceBinding::apply_to`].
+    ///
+    /// [`ServiceBinding::apply_to`]: crate::client::ServiceBinding::apply_to
+    InvalidServiceBinding(ServiceBindingError),
+
     /// Any other error.
     ///
     /// This variant should only be used when the error is not better described by a more
@@ -127,6 +177,22 @@ pub enum InconsistentKeys {
     ///
     /// [`SigningKey`]: crate::crypto::signer::SigningKey
     Unknown,
+
+    /// A signature made by the [`SigningKey`] with this scheme did not verify using
+    /// the public key in the certificate.
+    ///
+    /// This is reported by [`CertifiedKey::self_test`].
+    ///
+    /// [`SigningKey`]: crate::crypto::signer::SigningKey
+    /// [`CertifiedKey::self_test`]: crate::crypto::signer::CertifiedKey::self_test
+    SignatureInvalid(SignatureScheme),
+
+    /// The certificate at this index in the chain is not issued by the one following it.
+    ///
+    /// This is reported by [`CertifiedKey::chain_in_order`].
+    ///
+    /// [`CertifiedKey::chain_in_order`]: crate::crypto::signer::CertifiedKey::chain_in_order
+    ChainOutOfOrder(usize),
 }
 
 impl From<InconsistentKeys> for Error {
@@ -136,6 +202,87 @@ impl From<InconsistentKeys> for Error {
     }
 }
 
+/// Why an encrypted private key, or a PKCS#12 bundle, could not be loaded.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyLoadingError {
+    /// The key or bundle is malformed, or is encrypted with a scheme that is not supported.
+    UnsupportedFormat,
+
+    /// The key or bundle could not be decrypted, or the integrity of the bundle could
+    /// not be verified: the passphrase is probably wrong.
+    WrongPassphrase,
+
+    /// The passphrase of a PKCS#12 bundle is not UTF-8.
+    PassphraseNotUtf8,
+
+    /// A PKCS#12 bundle has no MAC, so its integrity cannot be checked.
+    MissingMac,
+
+    /// A PKCS#12 bundle contains no private key.
+    NoPrivateKey,
+
+    /// A PKCS#12 bundle contains more than one private key.
+    MultiplePrivateKeys,
+}
+
+impl From<KeyLoadingError> for Error {
+    #[inline]
+    fn from(e: KeyLoadingError) -> Self {
+        Self::KeyLoading(e)
+    }
+}
+
+/// Ways the application can use an API that cannot work.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ApiMisuse {
+    /// None of the key exchange groups given to [`ClientConnectionOptions::with_kx_groups()`]
+    /// are supported by the config.
+    ///
+    /// [`ClientConnectionOptions::with_kx_groups()`]: crate::client::ClientConnectionOptions::with_kx_groups
+    NoKxGroupsSelected,
+
+    /// A [`KeyingMaterialExporter`] label is empty, or reserved by RFC 5705.
+    ///
+    /// [`KeyingMaterialExporter`]: crate::KeyingMaterialExporter
+    ExporterLabelUnusable,
+
+    /// A [`KeyingMaterialExporter`] label is too long for TLS1.3.
+    ///
+    /// [`KeyingMaterialExporter`]: crate::KeyingMaterialExporter
+    ExporterLabelTooLong,
+
+    /// A [`KeyingMaterialExporter`] context is too long for TLS1.2.
+    ///
+    /// [`KeyingMaterialExporter`]: crate::KeyingMaterialExporter
+    ExporterContextTooLong,
+
+    /// A [`KeyingMaterialExporter`] was asked for no output.
+    ///
+    /// [`KeyingMaterialExporter`]: crate::KeyingMaterialExporter
+    ExporterOutputEmpty,
+
+    /// A [`KeyingMaterialExporter`] was asked for more than
+    /// [`KeyingMaterialExporter::max_output_len()`].
+    ///
+    /// [`KeyingMaterialExporter`]: crate::KeyingMaterialExporter
+    /// [`KeyingMaterialExporter::max_output_len()`]: crate::KeyingMaterialExporter::max_output_len
+    ExporterOutputTooLong,
+
+    /// [`KeyingMaterialExporter::eap_tls_keys()`] was used on a connection that is not TLS1.3.
+    ///
+    /// [`KeyingMaterialExporter::eap_tls_keys()`]: crate::KeyingMaterialExporter::eap_tls_keys
+    EapTlsKeysNeedTls13,
+}
+
+impl From<ApiMisuse> for Error {
+    #[inline]
+    fn from(e: ApiMisuse) -> Self {
+        Self::ApiMisuse(e)
+    }
+}
+
 /// A corrupt TLS message payload that resulted in an error.
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -245,6 +392,7 @@ pub enum PeerMisbehaved {
     OfferedEarlyDataWithOldProtocolVersion,
     OfferedEmptyApplicationProtocol,
     OfferedIncorrectCompressions,
+    OfferedInvalidMaxFragmentLength,
     PskExtensionMustBeLast,
     PskExtensionWithMismatchedIdsAndBinders,
     RefusedToFollowHelloRetryRequest,
@@ -254,6 +402,7 @@ pub enum PeerMisbehaved {
     ResumptionOfferedWithVariedEms,
     ResumptionOfferedWithIncompatibleCipherSuite,
     SelectedDifferentCipherSuiteAfterRetry,
+    SelectedDifferentMaxFragmentLength,
     SelectedInvalidPsk,
     SelectedTls12UsingTls13VersionExtension,
     SelectedUnofferedApplicationProtocol,
@@ -270,10 +419,13 @@ pub enum PeerMisbehaved {
     SignedHandshakeWithUnadvertisedSigScheme,
     TooManyEmptyFragments,
     TooManyKeyUpdateRequests,
+    TooManyNewSessionTickets,
     TooManyRenegotiationRequests,
     TooManyWarningAlertsReceived,
     TooMuchEarlyDataReceived,
     UnexpectedCleartextExtension,
+    UnexpectedDowngradeSentinel,
+    UnexpectedRecordVersion,
     UnsolicitedCertExtension,
     UnsolicitedEncryptedExtension,
     UnsolicitedSctList,
@@ -308,6 +460,7 @@ pub enum PeerIncompatible {
     NoKxGroupsInCommon,
     NoSignatureSchemesInCommon,
     NullCompressionRequired,
+    ResumedWithDifferentParameters,
     ServerDoesNotSupportTls12Or13,
     ServerSentHelloRetryRequestWithUnknownExtension,
     ServerTlsVersionIsDisabledByOurConfig,
@@ -373,6 +526,9 @@ pub enum CertificateError {
     /// The certificate is being used for a different purpose than allowed.
     InvalidPurpose,
 
+    /// The certificate chain has more intermediate certificates than allowed.
+    ChainTooDeep,
+
     /// The certificate is valid, but the handshake is rejected for other
     /// reasons.
     ApplicationVerificationFailure,
@@ -404,6 +560,7 @@ impl PartialEq<Self> for CertificateError {
             (BadSignature, BadSignature) => true,
             (NotValidForName, NotValidForName) => true,
             (InvalidPurpose, InvalidPurpose) => true,
+            (ChainTooDeep, ChainTooDeep) => true,
             (ApplicationVerificationFailure, ApplicationVerificationFailure) => true,
             (ExpiredRevocationList, ExpiredRevocationList) => true,
             _ => false,
@@ -426,7 +583,9 @@ impl From<CertificateError> for AlertDescription {
             Revoked => Self::CertificateRevoked,
             // OpenSSL, BoringSSL and AWS-LC all generate an Unknown CA alert for
             // the case where revocation status can not be determined, so we do the same here.
-            UnknownIssuer | UnknownRevocationStatus | ExpiredRevocationList => Self::UnknownCA,
+            UnknownIssuer | UnknownRevocationStatus | ExpiredRevocationList | ChainTooDeep => {
+                Self::UnknownCA
+            }
             BadSignature => Self::DecryptError,
             InvalidPurpose => Self::UnsupportedCertificate,
             ApplicationVerificationFailure => Self::AccessDenied,
@@ -536,6 +695,27 @@ impl From<EncryptedClientHelloError> for Error {
     }
 }
 
+/// The ways a list of ALPN protocols can be invalid.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AlpnProtocolError {
+    /// A protocol was empty.
+    Empty,
+    /// A protocol was longer than 255 bytes.
+    TooLong,
+    /// A protocol appeared more than once.
+    Duplicate,
+    /// The list was too long to be encoded.
+    ListTooLong,
+}
+
+impl From<AlpnProtocolError> for Error {
+    #[inline]
+    fn from(e: AlpnProtocolError) -> Self {
+        Self::InvalidAlpnProtocols(e)
+    }
+}
+
 fn join<T: fmt::Debug>(items: &[T]) -> String {
     items
         .iter()
@@ -586,15 +766,29 @@ impl fmt::Display for Error {
             Self::EncryptError => write!(f, "cannot encrypt message"),
             Self::PeerSentOversizedRecord => write!(f, "peer sent excess record size"),
             Self::HandshakeNotComplete => write!(f, "handshake not complete"),
+            Self::HandshakeTimedOut => write!(f, "handshake timed out"),
+            Self::TooManyHandshakes => write!(f, "too many handshakes in progress"),
+            Self::StaleOcspResponse => write!(f, "stale OCSP response"),
             Self::NoApplicationProtocol => write!(f, "peer doesn't support any known protocol"),
             Self::FailedToGetCurrentTime => write!(f, "failed to get current time"),
             Self::FailedToGetRandomBytes => write!(f, "failed to get random bytes"),
             Self::BadMaxFragmentSize => {
                 write!(f, "the supplied max_fragment_size was too small or large")
             }
+            Self::BadMaxHandshakeSize => {
+                write!(f, "the supplied max_handshake_size was too small or large")
+            }
+            Self::InvalidAlpnProtocols(ref why) => {
+                write!(f, "invalid ALPN protocols: {:?}", why)
+            }
             Self::InconsistentKeys(ref why) => {
                 write!(f, "keys may not be consistent: {:?}", why)
             }
+            Self::KeyLoading(ref why) => write!(f, "failed to load private key: {:?}", why),
+            Self::ApiMisuse(ref why) => write!(f, "API misuse: {:?}", why),
+            Self::InvalidServiceBinding(ref why) => {
+                write!(f, "invalid service binding: {}", why)
+            }
             Self::General(ref err) => write!(f, "unexpected error: {}", err),
             Self::Other(ref err) => write!(f, "other error: {}", err),
         }
@@ -676,7 +870,10 @@ mod tests {
     use std::prelude::v1::*;
     use std::{println, vec};
 
-    use super::{Error, InconsistentKeys, InvalidMessage};
+    use super::{
+        ApiMisuse, Error, InconsistentKeys, InvalidMessage, KeyLoadingError, SignatureScheme,
+    };
+    use crate::client::ServiceBindingError;
     use crate::error::{CertRevocationListError, OtherError};
 
     #[test]
@@ -691,6 +888,7 @@ mod tests {
         assert_eq!(BadSignature, BadSignature);
         assert_eq!(NotValidForName, NotValidForName);
         assert_eq!(InvalidPurpose, InvalidPurpose);
+        assert_eq!(ChainTooDeep, ChainTooDeep);
         assert_eq!(
             ApplicationVerificationFailure,
             ApplicationVerificationFailure
@@ -760,12 +958,22 @@ mod tests {
             Error::FailedToGetCurrentTime,
             Error::FailedToGetRandomBytes,
             Error::HandshakeNotComplete,
+            Error::HandshakeTimedOut,
+            Error::TooManyHandshakes,
+            Error::StaleOcspResponse,
             Error::PeerSentOversizedRecord,
             Error::NoApplicationProtocol,
             Error::BadMaxFragmentSize,
+            Error::BadMaxHandshakeSize,
+            Error::InvalidAlpnProtocols(super::AlpnProtocolError::Duplicate),
             Error::InconsistentKeys(InconsistentKeys::KeyMismatch),
             Error::InconsistentKeys(InconsistentKeys::Unknown),
+            Error::InconsistentKeys(InconsistentKeys::SignatureInvalid(SignatureScheme::ED25519)),
             Error::InvalidCertRevocationList(CertRevocationListError::BadSignature),
+            Error::KeyLoading(KeyLoadingError::WrongPassphrase),
+            Error::ApiMisuse(ApiMisuse::NoKxGroupsSelected),
+            Error::ApiMisuse(ApiMisuse::ExporterOutputEmpty),
+            Error::InvalidServiceBinding(ServiceBindingError::AliasMode),
             Error::Other(OtherError(
                 #[cfg(feature = "std")]
                 alloc::sync::Arc::from(Box::from("")),
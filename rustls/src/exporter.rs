@@ -0,0 +1,233 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use zeroize::Zeroize;
+
+use crate::conn::ConnectionCore;
+use crate::error::{ApiMisuse, Error};
+use crate::suites::SupportedCipherSuite;
+
+/// Derives keying material from an established connection, as described in
+/// [RFC 5705] and [RFC 8446 section 7.5].
+///
+/// Get one from [`ConnectionCommon::keying_material_exporter()`] once the handshake
+/// is complete.  It can be used for any number of exports, each with its own label,
+/// context and length, and borrows the connection so that it cannot outlive it.
+///
+/// Unlike [`ConnectionCommon::export_keying_material()`], invalid requests return
+/// an [`Error::ApiMisuse`] rather than panicking or exporting something unexpected:
+///
+/// - the label must not be empty, nor one of the labels reserved by RFC 5705;
+/// - for TLS1.3, the label must be no longer than 249 bytes;
+/// - for TLS1.2, the context must be no longer than 65535 bytes;
+/// - the output must not be empty, nor longer than [`KeyingMaterialExporter::max_output_len()`].
+///
+/// Note that for TLS1.2, having no context is not the same as having an empty
+/// context: they give different keying material.  For TLS1.3 they are the same.
+///
+/// [RFC 5705]: https://www.rfc-editor.org/rfc/rfc5705
+/// [RFC 8446 section 7.5]: https://www.rfc-editor.org/rfc/rfc8446#section-7.5
+/// [`ConnectionCommon::keying_material_exporter()`]: crate::ConnectionCommon::keying_material_exporter
+/// [`ConnectionCommon::export_keying_material()`]: crate::ConnectionCommon::export_keying_material
+pub struct KeyingMaterialExporter<'a> {
+    source: &'a dyn ExportsKeyingMaterial,
+    suite: SupportedCipherSuite,
+}
+
+impl<'a> KeyingMaterialExporter<'a> {
+    pub(crate) fn new<Data>(core: &'a ConnectionCore<Data>) -> Result<Self, Error> {
+        if let Err(err) = &core.state {
+            return Err(err.clone());
+        }
+
+        match (core.common_state.is_handshaking(), core.common_state.suite) {
+            (false, Some(suite)) => Ok(Self {
+                source: core,
+                suite,
+            }),
+            _ => Err(Error::HandshakeNotComplete),
+        }
+    }
+
+    /// Fill `output` with keying material for `label` and `context`.
+    pub fn export(
+        &self,
+        label: &[u8],
+        context: Option<&[u8]>,
+        output: &mut [u8],
+    ) -> Result<(), Error> {
+        self.check(label, context, output.len())?;
+        self.source
+            .export(output, label, context)
+    }
+
+    /// Return `len` bytes of keying material for `label` and `context`.
+    pub fn export_to_vec(
+        &self,
+        label: &[u8],
+        context: Option<&[u8]>,
+        len: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let mut output = vec![0u8; len];
+        self.export(label, context, &mut output)?;
+        Ok(output)
+    }
+
+    /// Derive the keys and session ID for EAP-TLS, as described in [RFC 9190 section 2.3].
+    ///
+    /// EAP-TLS is only specified this way for TLS1.3; this fails for earlier versions.
+    ///
+    /// [RFC 9190 section 2.3]: https://www.rfc-editor.org/rfc/rfc9190#section-2.3
+    pub fn eap_tls_keys(&self) -> Result<EapTlsKeys, Error> {
+        if !matches!(self.suite, SupportedCipherSuite::Tls13(_)) {
+            return Err(ApiMisuse::EapTlsKeysNeedTls13.into());
+        }
+
+        let mut key_material = [0u8; 128];
+        self.export(
+            b"EXPORTER_EAP_TLS_Key_Material",
+            Some(&[]),
+            &mut key_material,
+        )?;
+
+        let mut session_id = [0u8; 65];
+        session_id[0] = EAP_TLS_TYPE_CODE;
+        self.export(
+            b"EXPORTER_EAP_TLS_Method-Id",
+            Some(&[]),
+            &mut session_id[1..],
+        )?;
+
+        let mut keys = EapTlsKeys {
+            msk: [0u8; 64],
+            emsk: [0u8; 64],
+            session_id,
+        };
+        keys.msk
+            .copy_from_slice(&key_material[..64]);
+        keys.emsk
+            .copy_from_slice(&key_material[64..]);
+        key_material.zeroize();
+        Ok(keys)
+    }
+
+    /// The longest output that can be exported, or `None` if there is no limit.
+    ///
+    /// For TLS1.3 this is 255 times the length of the cipher suite's hash.
+    /// TLS1.2 has no limit.
+    pub fn max_output_len(&self) -> Option<usize> {
+        match self.suite {
+            SupportedCipherSuite::Tls13(_) => Some(255 * self.suite.hash_provider().output_len()),
+            #[cfg(feature = "tls12")]
+            SupportedCipherSuite::Tls12(_) => None,
+        }
+    }
+
+    fn check(&self, label: &[u8], context: Option<&[u8]>, len: usize) -> Result<(), Error> {
+        if label.is_empty() || RESERVED_LABELS.contains(&label) {
+            return Err(ApiMisuse::ExporterLabelUnusable.into());
+        }
+
+        if len == 0 {
+            return Err(ApiMisuse::ExporterOutputEmpty.into());
+        }
+
+        if let Some(max) = self.max_output_len() {
+            if len > max {
+                return Err(ApiMisuse::ExporterOutputTooLong.into());
+            }
+        }
+
+        match (self.suite, context) {
+            (SupportedCipherSuite::Tls13(_), _) if label.len() > MAX_TLS13_LABEL_LEN => {
+                Err(ApiMisuse::ExporterLabelTooLong.into())
+            }
+            #[cfg(feature = "tls12")]
+            (SupportedCipherSuite::Tls12(_), Some(context)) if context.len() > 0xffff => {
+                Err(ApiMisuse::ExporterContextTooLong.into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for KeyingMaterialExporter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyingMaterialExporter")
+            .field("suite", &self.suite)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The keys and session ID for EAP-TLS, from [`KeyingMaterialExporter::eap_tls_keys()`].
+///
+/// The keys are zeroed when this is dropped.
+pub struct EapTlsKeys {
+    msk: [u8; 64],
+    emsk: [u8; 64],
+    session_id: [u8; 65],
+}
+
+impl EapTlsKeys {
+    /// The Master Session Key.
+    pub fn msk(&self) -> &[u8; 64] {
+        &self.msk
+    }
+
+    /// The Extended Master Session Key.
+    pub fn emsk(&self) -> &[u8; 64] {
+        &self.emsk
+    }
+
+    /// The Session-Id: the EAP-TLS type code, followed by the Method-Id.
+    pub fn session_id(&self) -> &[u8; 65] {
+        &self.session_id
+    }
+
+    /// The Method-Id.
+    pub fn method_id(&self) -> &[u8] {
+        &self.session_id[1..]
+    }
+}
+
+impl fmt::Debug for EapTlsKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EapTlsKeys")
+            .field("session_id", &self.session_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for EapTlsKeys {
+    fn drop(&mut self) {
+        self.msk.zeroize();
+        self.emsk.zeroize();
+    }
+}
+
+pub(crate) trait ExportsKeyingMaterial {
+    fn export(&self, output: &mut [u8], label: &[u8], context: Option<&[u8]>) -> Result<(), Error>;
+}
+
+impl<Data> ExportsKeyingMaterial for ConnectionCore<Data> {
+    fn export(&self, output: &mut [u8], label: &[u8], context: Option<&[u8]>) -> Result<(), Error> {
+        self.export_keying_material(output, label, context)
+            .map(|_| ())
+    }
+}
+
+/// Labels used by TLS itself, which are reserved by RFC 5705 section 4 and RFC 7627.
+const RESERVED_LABELS: &[&[u8]] = &[
+    b"client finished",
+    b"server finished",
+    b"master secret",
+    b"extended master secret",
+    b"key expansion",
+];
+
+/// The EAP method type assigned to EAP-TLS.
+const EAP_TLS_TYPE_CODE: u8 = 0x0d;
+
+/// `HkdfLabel` holds the label with a `"tls13 "` prefix, in at most 255 bytes.
+const MAX_TLS13_LABEL_LEN: usize = 255 - 6;
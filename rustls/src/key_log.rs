@@ -1,3 +1,6 @@
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::fmt::Debug;
 
 #[cfg(all(doc, feature = "std"))]
@@ -23,6 +26,8 @@ pub trait KeyLog: Debug + Send + Sync {
     /// - `CLIENT_RANDOM`: `secret` is the master secret for a TLSv1.2 session.
     /// - `CLIENT_EARLY_TRAFFIC_SECRET`: `secret` encrypts early data
     ///   transmitted by a client
+    /// - `EARLY_EXPORTER_SECRET`: `secret` is the early exporter secret
+    ///   in a TLSv1.3 session that offers early data.
     /// - `SERVER_HANDSHAKE_TRAFFIC_SECRET`: `secret` encrypts
     ///   handshake messages from the server during a TLSv1.3 handshake.
     /// - `CLIENT_HANDSHAKE_TRAFFIC_SECRET`: `secret` encrypts
@@ -33,9 +38,20 @@ pub trait KeyLog: Debug + Send + Sync {
     ///   from the client in a TLSv1.3 session.
     /// - `EXPORTER_SECRET`: `secret` is the post-handshake exporter secret
     ///   in a TLSv1.3 session.
+    /// - `ECH_SECRET`: `secret` is the HPKE KEM shared secret used to encrypt a
+    ///   client hello with ECH.  This is only logged if the [`HpkeSealer`] provides
+    ///   it.
+    /// - `ECH_CONFIG`: `secret` is the ECH configuration used to encrypt a client
+    ///   hello.
+    ///
+    /// For `ECH_SECRET` and `ECH_CONFIG`, `client_random` is that of the outer
+    /// client hello.
     ///
     /// These strings are selected to match the NSS key log format:
     /// <https://developer.mozilla.org/en-US/docs/Mozilla/Projects/NSS/Key_Log_Format>
+    /// and <https://datatracker.ietf.org/doc/draft-ietf-tls-keylogfile/>.
+    ///
+    /// [`HpkeSealer`]: crate::crypto::hpke::HpkeSealer
     fn log(&self, label: &str, client_random: &[u8], secret: &[u8]);
 
     /// Indicates whether the secret with label `label` will be logged.
@@ -59,3 +75,54 @@ impl KeyLog for NoKeyLog {
         false
     }
 }
+
+/// A [`KeyLog`] that only passes on secrets with some labels to another.
+///
+/// For example, this can keep an `SSLKEYLOGFILE` to the secrets needed to
+/// decrypt application data, leaving handshakes encrypted:
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// # use std::sync::Arc;
+/// use rustls::{FilteredKeyLog, KeyLogFile};
+///
+/// let key_log = FilteredKeyLog::new(
+///     Arc::new(KeyLogFile::new()),
+///     &["CLIENT_TRAFFIC_SECRET_0", "SERVER_TRAFFIC_SECRET_0"],
+/// );
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FilteredKeyLog {
+    inner: Arc<dyn KeyLog>,
+    labels: Vec<String>,
+}
+
+impl FilteredKeyLog {
+    /// Pass on secrets labelled with one of `labels` to `inner`.
+    pub fn new(inner: Arc<dyn KeyLog>, labels: &[&str]) -> Self {
+        Self {
+            inner,
+            labels: labels
+                .iter()
+                .map(|label| label.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl KeyLog for FilteredKeyLog {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        if self.will_log(label) {
+            self.inner
+                .log(label, client_random, secret);
+        }
+    }
+
+    fn will_log(&self, label: &str) -> bool {
+        self.labels
+            .iter()
+            .any(|allowed| allowed == label)
+            && self.inner.will_log(label)
+    }
+}
@@ -0,0 +1,154 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter};
+use std::sync::Mutex;
+
+use crate::common_state::CommonState;
+use crate::KeyLog;
+
+/// [`KeyLog`] implementation that keeps secrets in memory.
+///
+/// This is intended for test harnesses that want to decrypt captures of their own
+/// connections, without writing every connection's secrets to a shared `SSLKEYLOGFILE`.
+/// Share one of these between all the connections using a configuration, then use
+/// [`InMemoryKeyLog::secrets_for()`] to retrieve just one connection's secrets.
+///
+/// Secrets are kept until removed with [`InMemoryKeyLog::take_secrets_for()`], so
+/// memory use will grow without bound if they are never collected.
+#[derive(Default)]
+pub struct InMemoryKeyLog {
+    entries: Mutex<Vec<KeyLogEntry>>,
+}
+
+impl InMemoryKeyLog {
+    /// Makes a new, empty `InMemoryKeyLog`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return a copy of the secrets logged so far for `conn`, in the order they were logged.
+    ///
+    /// This is empty if `conn` has not yet sent or received a `ClientHello`.
+    pub fn secrets_for(&self, conn: &CommonState) -> Vec<KeyLogEntry> {
+        let client_random = match &conn.client_random {
+            Some(random) => random,
+            None => return Vec::new(),
+        };
+
+        match self.entries.lock() {
+            Ok(entries) => entries
+                .iter()
+                .filter(|entry| entry.client_random == client_random[..])
+                .cloned()
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Remove and return the secrets logged so far for `conn`, in the order they were logged.
+    pub fn take_secrets_for(&self, conn: &CommonState) -> Vec<KeyLogEntry> {
+        let client_random = match &conn.client_random {
+            Some(random) => random,
+            None => return Vec::new(),
+        };
+
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+
+        let (taken, kept) = entries
+            .drain(..)
+            .partition(|entry| entry.client_random == client_random[..]);
+        *entries = kept;
+        taken
+    }
+}
+
+impl KeyLog for InMemoryKeyLog {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.push(KeyLogEntry {
+                label: label.to_string(),
+                client_random: client_random.to_vec(),
+                secret: secret.to_vec(),
+            });
+        }
+    }
+}
+
+impl Debug for InMemoryKeyLog {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let len = match self.entries.lock() {
+            Ok(entries) => entries.len(),
+            Err(_) => 0,
+        };
+        f.debug_struct("InMemoryKeyLog")
+            // Note: we omit the entries deliberately as they contain key data.
+            .field("entries", &len)
+            .finish()
+    }
+}
+
+/// One secret logged to an [`InMemoryKeyLog`].
+///
+/// See [`KeyLog::log()`] for the meaning of these fields.
+#[non_exhaustive]
+#[derive(Clone, PartialEq, Eq)]
+pub struct KeyLogEntry {
+    /// What the secret means, eg. `CLIENT_TRAFFIC_SECRET_0`.
+    pub label: String,
+    /// The client random of the connection.
+    pub client_random: Vec<u8>,
+    /// The secret itself.
+    pub secret: Vec<u8>,
+}
+
+impl KeyLogEntry {
+    /// Format this entry as a line of the NSS key log format, without a trailing newline.
+    pub fn to_nss_line(&self) -> String {
+        let mut line = self.label.clone();
+        line.push(' ');
+        push_hex(&mut line, &self.client_random);
+        line.push(' ');
+        push_hex(&mut line, &self.secret);
+        line
+    }
+}
+
+impl Debug for KeyLogEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("KeyLogEntry")
+            // Note: we omit the secret deliberately.
+            .field("label", &self.label)
+            .field("client_random", &self.client_random)
+            .finish_non_exhaustive()
+    }
+}
+
+fn push_hex(out: &mut String, bytes: &[u8]) {
+    for b in bytes {
+        out.push(char::from(HEX[usize::from(b >> 4)]));
+        out.push(char::from(HEX[usize::from(b & 0xf)]));
+    }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, vec};
+
+    use super::*;
+
+    #[test]
+    fn test_to_nss_line() {
+        let entry = KeyLogEntry {
+            label: "CLIENT_RANDOM".to_string(),
+            client_random: vec![0x01, 0xab],
+            secret: vec![0xff, 0x00],
+        };
+        assert_eq!(entry.to_nss_line(), "CLIENT_RANDOM 01ab ff00");
+        assert!(!format!("{entry:?}").contains("ff"));
+    }
+}
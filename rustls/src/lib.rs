@@ -279,11 +279,15 @@
 //!
 //! - `fips`: enable support for FIPS140-3-approved cryptography, via the aws-lc-rs crate.
 //!   This feature enables the `aws_lc_rs` feature, which makes the rustls crate depend
-//!   on [aws-lc-rs](https://github.com/aws/aws-lc-rs).  It also changes the default
-//!   for [`ServerConfig::require_ems`] and [`ClientConfig::require_ems`].
+//!   on [aws-lc-rs](https://github.com/aws/aws-lc-rs).
 //!
 //!   See [manual::_06_fips] for more details.
 //!
+//! - `pkcs12`: adds `crypto::aws_lc_rs::pkcs12::load_certified_key()`, to load a
+//!   certificate chain and private key from a PKCS#12 (`.p12` or `.pfx`) bundle.
+//!   Bundles without a MAC are only loaded by `load_certified_key_without_mac()`.
+//!   This feature enables the `aws_lc_rs` feature.
+//!
 //! - `tls12` (enabled by default): enable support for TLS version 1.2. Note that, due to the
 //!   additive nature of Cargo features and because it is enabled by default, other crates
 //!   in your dependency graph could re-enable it for your application. If you want to disable
@@ -294,14 +298,35 @@
 //!   rustls outputs interesting protocol-level messages at `trace!` and `debug!` level,
 //!   and protocol-level errors at `warn!` and `error!` level.  The log messages do not
 //!   contain secret key data, and so are safe to archive without affecting session security.
+//!   Whether or not this is enabled, a [`ReceivesDiagnostics`] can be configured to
+//!   receive diagnostics about the outcome of each connection.
 //!
 //! - `read_buf`: when building with Rust Nightly, adds support for the unstable
 //!   `std::io::ReadBuf` and related APIs. This reduces costs from initializing
 //!   buffers. Will do nothing on non-Nightly releases.
 //!
 //! - `brotli`: uses the `brotli` crate for RFC8879 certificate compression support.
+//!   This enables the `std` feature.
 //!
 //! - `zlib`: uses the `zlib-rs` crate for RFC8879 certificate compression support.
+//!   Unlike `brotli`, this works without the `std` feature.
+//!
+//! - `zstd`: uses the `zstd` crate for RFC8879 certificate compression support.
+//!   This enables the `std` feature.  Note that the `zstd` crate builds the zstd C
+//!   library, so this needs a C compiler for the target.
+//!
+//! - `cert_reload`: adds `server::ReloadingCertResolver`, which reloads a server's
+//!   certificate chain and private key from PEM files when they change.  This uses the
+//!   `rustls-pemfile` crate, and enables the `std` feature.
+//!
+//! - `state_trace`: records the handshake state machine transitions made by each
+//!   connection, available from `CommonState::state_trace()`, for diagnosing handshakes
+//!   that stall or fail.
+//!
+//! - `trust_bundles`: adds functions to load a [`RootCertStore`] from the operating
+//!   system's certificate bundle, an OpenSSL-style directory of hashed PEM files, or
+//!   a CCADB CSV report.  This uses the `rustls-pemfile` crate, and enables the `std`
+//!   feature.
 //!
 
 // Require docs for public APIs, deny unsafe code, etc.
@@ -381,7 +406,46 @@ mod log {
     macro_rules! trace    ( ($($tt:tt)*) => {{}} );
     macro_rules! debug    ( ($($tt:tt)*) => {{}} );
     macro_rules! warn     ( ($($tt:tt)*) => {{}} );
-    macro_rules! error    ( ($($tt:tt)*) => {{}} );
+}
+
+/// Report a diagnostic about a connection, from within the connection.
+///
+/// This goes to the `log` crate at the given level (with the `logging` feature),
+/// and to the connection's [`ReceivesDiagnostics`], if any.  `common` is the
+/// connection's `CommonState`, and `level` one of `debug`, `warn` or `error`.
+///
+/// [`ReceivesDiagnostics`]: crate::ReceivesDiagnostics
+macro_rules! diagnose {
+    ($common:expr, debug, $($arg:tt)+) => {{
+        #[cfg(feature = "logging")]
+        ::log::debug!($($arg)+);
+        $common.diagnose(
+            $crate::diagnostics::DiagnosticLevel::Debug,
+            format_args!($($arg)+),
+            None,
+            None,
+        );
+    }};
+    ($common:expr, warn, $($arg:tt)+) => {{
+        #[cfg(feature = "logging")]
+        ::log::warn!($($arg)+);
+        $common.diagnose(
+            $crate::diagnostics::DiagnosticLevel::Warn,
+            format_args!($($arg)+),
+            None,
+            None,
+        );
+    }};
+    ($common:expr, error, $($arg:tt)+) => {{
+        #[cfg(feature = "logging")]
+        ::log::error!($($arg)+);
+        $common.diagnose(
+            $crate::diagnostics::DiagnosticLevel::Warn,
+            format_args!($($arg)+),
+            None,
+            None,
+        );
+    }};
 }
 
 #[macro_use]
@@ -389,17 +453,22 @@ mod test_macros;
 
 #[macro_use]
 mod msgs;
+pub mod alpn;
 mod common_state;
 pub mod compress;
 mod conn;
 /// Crypto provider interface.
 pub mod crypto;
+mod diagnostics;
 mod error;
+mod exporter;
 mod hash_hs;
 #[cfg(any(feature = "std", feature = "hashbrown"))]
 mod limited_cache;
 mod rand;
 mod record_layer;
+#[cfg(feature = "state_trace")]
+mod state_trace;
 #[cfg(feature = "std")]
 mod stream;
 #[cfg(feature = "tls12")]
@@ -412,13 +481,15 @@ mod verifybench;
 mod x509;
 #[macro_use]
 mod check;
-#[cfg(feature = "logging")]
+mod audit;
 mod bs_debug;
 mod builder;
 mod enums;
 mod key_log;
 #[cfg(feature = "std")]
 mod key_log_file;
+#[cfg(feature = "std")]
+mod key_log_memory;
 mod suites;
 mod versions;
 mod webpki;
@@ -506,29 +577,44 @@ pub mod unbuffered {
 }
 
 // The public interface is:
+pub use crate::audit::{CloseReason, ConnectionAudit, ReceivesConnectionAudits};
 pub use crate::builder::{ConfigBuilder, ConfigSide, WantsVerifier, WantsVersions};
-pub use crate::common_state::{CommonState, HandshakeKind, IoState, Side};
+pub use crate::common_state::{
+    ChoosesAlerts, CommonState, HandshakeKind, IoState, ObservesConnection, ObservesShutdown,
+    PostHandshakeLimits, ResumptionKind, Side, WarningAlert,
+};
 #[cfg(feature = "std")]
 pub use crate::conn::{Connection, Reader, Writer};
 pub use crate::conn::{ConnectionCommon, SideData};
+pub use crate::diagnostics::{Diagnostic, DiagnosticLevel, ReceivesDiagnostics};
 pub use crate::enums::{
     AlertDescription, CertificateCompressionAlgorithm, CipherSuite, ContentType, HandshakeType,
-    ProtocolVersion, SignatureAlgorithm, SignatureScheme,
+    MaxFragmentLength, ProtocolVersion, SignatureAlgorithm, SignatureScheme,
 };
 pub use crate::error::{
-    CertRevocationListError, CertificateError, EncryptedClientHelloError, Error, InconsistentKeys,
-    InvalidMessage, OtherError, PeerIncompatible, PeerMisbehaved,
+    AlpnProtocolError, ApiMisuse, CertRevocationListError, CertificateError,
+    EncryptedClientHelloError, Error, InconsistentKeys, InvalidMessage, KeyLoadingError,
+    OtherError, PeerIncompatible, PeerMisbehaved,
 };
-pub use crate::key_log::{KeyLog, NoKeyLog};
+pub use crate::exporter::{EapTlsKeys, KeyingMaterialExporter};
+pub use crate::key_log::{FilteredKeyLog, KeyLog, NoKeyLog};
 #[cfg(feature = "std")]
 pub use crate::key_log_file::KeyLogFile;
+#[cfg(feature = "std")]
+pub use crate::key_log_memory::{InMemoryKeyLog, KeyLogEntry};
 pub use crate::msgs::enums::NamedGroup;
 pub use crate::msgs::ffdhe_groups;
 pub use crate::msgs::handshake::DistinguishedName;
+pub use crate::record_layer::{
+    KeyLimitAction, KeyLimits, ObservesRecords, RecordInfo, RecordStrictness, RecordVersionCheck,
+};
+#[cfg(feature = "state_trace")]
+pub use crate::state_trace::{StateTrace, StateTransition};
 #[cfg(feature = "std")]
 pub use crate::stream::{Stream, StreamOwned};
 pub use crate::suites::{
-    CipherSuiteCommon, ConnectionTrafficSecrets, ExtractedSecrets, SupportedCipherSuite,
+    CipherSuiteCommon, CipherSuiteInfo, ConnectionTrafficSecrets, ExtractedSecrets,
+    SupportedCipherSuite,
 };
 #[cfg(any(feature = "std", feature = "hashbrown"))]
 pub use crate::ticketer::TicketSwitcher;
@@ -538,6 +624,8 @@ pub use crate::tls13::Tls13CipherSuite;
 pub use crate::verify::DigitallySignedStruct;
 pub use crate::versions::{SupportedProtocolVersion, ALL_VERSIONS, DEFAULT_VERSIONS};
 pub use crate::webpki::RootCertStore;
+#[cfg(feature = "trust_bundles")]
+pub use crate::webpki::RootLoadStats;
 
 /// Items for use in a client.
 pub mod client {
@@ -547,25 +635,40 @@ pub mod client {
     mod ech;
     pub(super) mod handy;
     mod hs;
+    mod pinning;
+    mod profile;
+    mod reconnect;
+    mod svcb;
     #[cfg(feature = "tls12")]
     mod tls12;
     mod tls13;
 
     pub use builder::WantsClientCert;
     pub use client_conn::{
-        ClientConfig, ClientConnectionData, ClientSessionStore, EarlyDataError, ResolvesClientCert,
-        Resumption, Tls12Resumption, UnbufferedClientConnection,
+        CertificateRequest, ClientConfig, ClientConnectionData, ClientSessionStore,
+        DowngradeSentinel, EarlyDataError, FiltersTickets, ReceivedTicket, ResolvesClientCert,
+        Resumption, ResumptionDecision, Tls12Resumption, UnbufferedClientConnection,
     };
     #[cfg(feature = "std")]
-    pub use client_conn::{ClientConnection, WriteEarlyData};
-    pub use ech::{EchConfig, EchGreaseConfig, EchMode, EchStatus};
+    pub use client_conn::{ClientConnection, ClientConnectionOptions, WriteEarlyData};
+    pub use ech::{EchConfig, EchGreaseConfig, EchMode, EchStatus, ResolvesEchConfig};
     #[cfg(any(feature = "std", feature = "hashbrown"))]
     pub use handy::ClientSessionMemoryCache;
+    pub use handy::{ResolvesClientCertUsingFn, ResolvesClientCertUsingIssuers};
+    pub use pinning::{PinFailureReport, ReportsPinFailures, SpkiPinningVerifier};
+    pub use profile::ShapesClientHello;
+    pub use reconnect::ReconnectPolicy;
+    pub use svcb::{ServiceBinding, ServiceBindingError};
+
+    pub use crate::verify::RevocationStatus;
 
     /// Dangerous configuration that should be audited and used with extreme care.
     pub mod danger {
         pub use super::builder::danger::DangerousClientConfigBuilder;
         pub use super::client_conn::danger::DangerousClientConfig;
+        #[cfg(feature = "std")]
+        pub use super::client_conn::DangerousClientConnectionOptions;
+        pub use crate::common_state::MutatesHandshakeMessages;
         pub use crate::verify::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
     }
 
@@ -584,33 +687,60 @@ pub use client::ClientConnection;
 pub mod server {
     pub(crate) mod builder;
     mod common;
+    mod expiry;
+    #[cfg(feature = "std")]
+    mod handle;
     pub(crate) mod handy;
     mod hs;
+    #[cfg(feature = "std")]
+    mod identity;
+    #[cfg(feature = "std")]
+    mod limit;
+    #[cfg(feature = "cert_reload")]
+    mod reload;
     mod server_conn;
+    mod tickets;
     #[cfg(feature = "tls12")]
     mod tls12;
     mod tls13;
 
     pub use builder::WantsServerCert;
+    pub use expiry::{CertExpiryWarning, ObservesCertExpiry, OcspFreshness, StaleOcspPolicy};
+    #[cfg(feature = "std")]
+    pub use handle::{ServerConfigHandle, ServerConfigUpdate};
     pub use handy::NoServerSessionStorage;
     #[cfg(any(feature = "std", feature = "hashbrown"))]
     pub use handy::ResolvesServerCertUsingSni;
     #[cfg(any(feature = "std", feature = "hashbrown"))]
     pub use handy::ServerSessionMemoryCache;
+    pub use handy::{
+        ResolvesServerCertUsingFn, ResolvesServerCertUsingSignatureSchemes,
+        ResolvesServerCertUsingTrustAnchors,
+    };
+    #[cfg(feature = "std")]
+    pub use identity::ClientIdentity;
+    #[cfg(feature = "std")]
+    pub use limit::{HandshakeLimitPolicy, HandshakeLimiter};
+    #[cfg(feature = "cert_reload")]
+    pub use reload::ReloadingCertResolver;
     pub use server_conn::{
-        Accepted, ClientHello, ProducesTickets, ResolvesServerCert, ServerConfig,
-        ServerConnectionData, StoresServerSessions, UnbufferedServerConnection,
+        Accepted, AlpnPolicy, ClientHello, ProducesTickets, ResolvesServerCert, ScoresCipherSuites,
+        ServerConfig, ServerConnectionData, StoresServerSessions, UnbufferedAcceptStatus,
+        UnbufferedAccepted, UnbufferedAcceptor, UnbufferedServerConnection,
     };
     #[cfg(feature = "std")]
     pub use server_conn::{AcceptedAlert, Acceptor, ReadEarlyData, ServerConnection};
+    pub use tickets::{ObservesTickets, TicketInfo};
 
-    pub use crate::verify::NoClientAuth;
+    pub use crate::verify::{ClientCertDetails, NoClientAuth, RevocationCheck};
     pub use crate::webpki::{
         ClientCertVerifierBuilder, ParsedCertificate, VerifierBuilderError, WebPkiClientVerifier,
     };
 
     /// Dangerous configuration that should be audited and used with extreme care.
     pub mod danger {
+        pub use super::server_conn::danger::DangerousServerConfig;
+        pub use crate::common_state::MutatesHandshakeMessages;
         pub use crate::verify::{ClientCertVerified, ClientCertVerifier};
     }
 }
@@ -635,6 +765,9 @@ pub mod pki_types {
 
 /// Message signing interfaces.
 pub mod sign {
+    pub use crate::crypto::remote_signer::{
+        RemoteSignRequest, RemoteSigningKey, RequestsSignatures,
+    };
     pub use crate::crypto::signer::{CertifiedKey, Signer, SigningKey};
 }
 
@@ -650,6 +783,9 @@ pub mod manual;
 
 pub mod time_provider;
 
+#[cfg(feature = "std")]
+pub mod transcript;
+
 /// APIs abstracting over locking primitives.
 pub mod lock;
 
@@ -72,9 +72,41 @@ mod no_std_lock {
         fn lock(&self) -> Result<MutexGuard<'_, T>, Poisoned>;
     }
 
-    /// A lock builder.                                                                                     
+    /// A lock builder.
+    ///
+    /// Without the `std` feature, rustls has no mutex of its own: the types that need
+    /// one, such as [`ServerSessionMemoryCache`] and [`TicketSwitcher`], take an
+    /// implementation of this trait as a type parameter.  That can wrap the spinlock or
+    /// RTOS mutex suitable for the target.  For example, with the `spin` crate:
+    ///
+    /// ```rust,ignore
+    /// use std::sync::Arc;
+    /// use rustls::lock::{Lock, MakeMutex, MutexGuard, Poisoned};
+    ///
+    /// #[derive(Debug)]
+    /// struct SpinLock<T>(spin::Mutex<T>);
+    ///
+    /// impl<T: Send> Lock<T> for SpinLock<T> {
+    ///     fn lock(&self) -> Result<MutexGuard<'_, T>, Poisoned> {
+    ///         Ok(Box::new(self.0.lock()))
+    ///     }
+    /// }
+    ///
+    /// struct MakeSpinLock;
+    ///
+    /// impl MakeMutex for MakeSpinLock {
+    ///     fn make_mutex<T: Send + 'static>(value: T) -> Arc<dyn Lock<T>> {
+    ///         Arc::new(SpinLock(spin::Mutex::new(value)))
+    ///     }
+    /// }
+    ///
+    /// let cache = rustls::server::ServerSessionMemoryCache::new::<MakeSpinLock>(256);
+    /// ```
+    ///
+    /// [`ServerSessionMemoryCache`]: crate::server::ServerSessionMemoryCache
+    /// [`TicketSwitcher`]: crate::ticketer::TicketSwitcher
     pub trait MakeMutex {
-        /// Create a new mutex.                                                                             
+        /// Create a new mutex.
         fn make_mutex<T>(value: T) -> Arc<dyn Lock<T>>
         where
             T: Send + 'static;
@@ -25,6 +25,7 @@ APIs ([`CryptoProvider`] for example).
 * Server and optional client authentication
 * Extended master secret support ([RFC7627](https://tools.ietf.org/html/rfc7627))
 * Exporters ([RFC5705](https://tools.ietf.org/html/rfc5705))
+* EAP-TLS 1.3 key derivation ([RFC9190](https://tools.ietf.org/html/rfc9190))
 * OCSP stapling by servers
 * [RFC8879](https://tools.ietf.org/html/rfc8879) certificate compression by clients
   and servers `*`
@@ -306,6 +306,27 @@ impl<'a, T: Codec<'a> + TlsListElement + Debug> Codec<'a> for Vec<T> {
     }
 }
 
+/// Read a list with a 3-byte length prefix, like `Vec::<T>::read()`, but limiting
+/// its length to `max` bytes rather than the maximum given by `T::SIZE_LEN`.
+pub(crate) fn read_vec_u24_limited<'a, T: Codec<'a>>(
+    r: &mut Reader<'a>,
+    max: usize,
+    error: InvalidMessage,
+) -> Result<Vec<T>, InvalidMessage> {
+    let len = match usize::from(u24::read(r)?) {
+        len if len > max => return Err(error),
+        len => len,
+    };
+
+    let mut sub = r.sub(len)?;
+    let mut ret = Vec::new();
+    while sub.any_left() {
+        ret.push(T::read(&mut sub)?);
+    }
+
+    Ok(ret)
+}
+
 /// A trait for types that can be encoded and decoded in a list.
 ///
 /// This trait is used to implement `Codec` for `Vec<T>`. Lists in the TLS wire format are
@@ -8,16 +8,16 @@ use super::codec::Codec;
 use crate::enums::{ContentType, ProtocolVersion};
 use crate::error::{Error, InvalidMessage, PeerMisbehaved};
 use crate::msgs::codec;
+use crate::msgs::handshake::CERTIFICATE_MAX_SIZE_LIMIT;
 #[cfg(feature = "std")]
 use crate::msgs::message::MAX_WIRE_SIZE;
 use crate::msgs::message::{InboundOpaqueMessage, InboundPlainMessage, MessageError};
-use crate::record_layer::{Decrypted, RecordLayer};
+use crate::record_layer::{Decrypted, RecordLayer, RecordStrictness, RecordVersionCheck};
 
 /// This deframer works to reconstruct TLS messages from a stream of arbitrary-sized reads.
 ///
 /// It buffers incoming data into a `Vec` through `read()`, and returns messages through `pop()`.
 /// QUIC connections will call `push()` to append handshake payload data directly.
-#[derive(Default)]
 pub struct MessageDeframer {
     /// Set if the peer is not talking TLS, but some other
     /// protocol.  The caller should abort the connection, because
@@ -30,9 +30,58 @@ pub struct MessageDeframer {
     /// We limit consecutive empty fragments to avoid a route for the peer to send
     /// us significant but fruitless traffic.
     seen_consecutive_empty_fragments: u8,
+
+    /// The largest handshake message payload we will reassemble.
+    max_handshake_size: u32,
+
+    /// Which deviations from the standard framing we put up with.
+    strictness: RecordStrictness,
+}
+
+impl Default for MessageDeframer {
+    fn default() -> Self {
+        Self {
+            last_error: None,
+            joining_hs: None,
+            seen_consecutive_empty_fragments: 0,
+            max_handshake_size: DEFAULT_MAX_HANDSHAKE_SIZE,
+            strictness: RecordStrictness::default(),
+        }
+    }
 }
 
 impl MessageDeframer {
+    /// Set the largest handshake message payload that will be accepted.
+    ///
+    /// A `max_handshake_size` of `None` sets the default of 64KB.
+    ///
+    /// Returns BadMaxHandshakeSize if the size is smaller than 16384, or larger
+    /// than a handshake message can be.
+    pub fn set_max_handshake_size(
+        &mut self,
+        max_handshake_size: Option<usize>,
+    ) -> Result<(), Error> {
+        self.max_handshake_size = match max_handshake_size {
+            Some(sz @ MIN_HANDSHAKE_SIZE..=MAX_HANDSHAKE_SIZE_LIMIT) => sz as u32,
+            None => DEFAULT_MAX_HANDSHAKE_SIZE,
+            _ => return Err(Error::BadMaxHandshakeSize),
+        };
+        Ok(())
+    }
+
+    /// Set how strictly the framing of received records is checked.
+    pub fn set_record_strictness(&mut self, strictness: RecordStrictness) {
+        self.strictness = strictness;
+    }
+
+    /// The largest certificate chain that will be accepted.
+    ///
+    /// This is 64KB, unless the maximum handshake message size has been raised
+    /// above that.
+    pub(crate) fn max_certificate_size(&self) -> usize {
+        Ord::max(self.max_handshake_size as usize, CERTIFICATE_MAX_SIZE_LIMIT)
+    }
+
     /// Return any decrypted messages that the deframer has been able to parse.
     ///
     /// Returns an `Error` if the deframer failed to parse some message contents or if decryption
@@ -72,7 +121,7 @@ impl MessageDeframer {
             // contain a header, and that header has a length which falls within `buf`.
             // If so, deframe it and place the message onto the frames output queue.
             let mut rd = codec::ReaderMut::init(buffer.filled_get_mut(start..));
-            let m = match InboundOpaqueMessage::read(&mut rd) {
+            let m = match InboundOpaqueMessage::read(&mut rd, &self.strictness) {
                 Ok(m) => m,
                 Err(msg_err) => {
                     let err_kind = match msg_err {
@@ -91,8 +140,42 @@ impl MessageDeframer {
                 }
             };
 
-            // Return CCS messages and early plaintext alerts immediately without decrypting.
             let end = start + rd.used();
+            if self.strictness.legacy_record_version == RecordVersionCheck::Exact {
+                // "legacy_record_version:  MUST be set to 0x0303 for all records generated by a
+                // TLS 1.3 implementation other than an initial ClientHello (i.e., one not
+                // generated after a HelloRetryRequest), where it MAY also be 0x0301 for
+                // compatibility purposes."
+                // https://www.rfc-editor.org/rfc/rfc8446#section-5.1
+                let expected = match negotiated_version {
+                    Some(_) => m.version == ProtocolVersion::TLSv1_2,
+                    None => matches!(
+                        m.version,
+                        ProtocolVersion::TLSv1_0
+                            | ProtocolVersion::TLSv1_1
+                            | ProtocolVersion::TLSv1_2
+                    ),
+                };
+                if !expected {
+                    return Err(self.set_err(PeerMisbehaved::UnexpectedRecordVersion));
+                }
+            }
+
+            // Skip over CCS messages interleaved with a handshake message, if allowed.  These
+            // have no meaning unless TLS1.2 is in use.
+            if self.joining_hs.is_some()
+                && m.typ == ContentType::ChangeCipherSpec
+                && self
+                    .strictness
+                    .allow_interleaved_change_cipher_spec
+                && negotiated_version != Some(ProtocolVersion::TLSv1_2)
+            {
+                record_layer.note_unprotected_incoming(m.typ, m.payload.len());
+                self.skip_record(end, buffer);
+                continue;
+            }
+
+            // Return CCS messages and early plaintext alerts immediately without decrypting.
             let version_is_tls13 = matches!(negotiated_version, Some(ProtocolVersion::TLSv1_3));
             let allowed_plaintext = match m.typ {
                 // CCS messages are always plaintext.
@@ -114,11 +197,20 @@ impl MessageDeframer {
                 _ => false,
             };
             if self.joining_hs.is_none() && allowed_plaintext {
+                if m.payload.is_empty() {
+                    // only reachable if empty records are allowed
+                    record_layer.note_unprotected_incoming(m.typ, 0);
+                    self.note_empty_record()?;
+                    self.skip_record(end, buffer);
+                    continue;
+                }
+
                 let InboundOpaqueMessage {
                     typ,
                     version,
                     payload,
                 } = m;
+                record_layer.note_unprotected_incoming(typ, payload.len());
                 let raw_payload_slice = RawSlice::from(&*payload);
                 // This is unencrypted. We check the contents later.
                 buffer.queue_discard(end);
@@ -174,12 +266,11 @@ impl MessageDeframer {
 
             match plain_payload_slice.len {
                 0 => {
-                    if self.seen_consecutive_empty_fragments
-                        == ALLOWED_CONSECUTIVE_EMPTY_FRAGMENTS_MAX
-                    {
-                        return Err(self.set_err(PeerMisbehaved::TooManyEmptyFragments));
+                    self.note_empty_record()?;
+                    if typ != ContentType::ApplicationData && self.strictness.allow_empty_records {
+                        self.skip_record(end, buffer);
+                        continue;
                     }
-                    self.seen_consecutive_empty_fragments += 1;
                 }
                 _ => {
                     self.seen_consecutive_empty_fragments = 0;
@@ -227,8 +318,10 @@ impl MessageDeframer {
             // the payload start to point past the payload we're about to yield, and update the
             // `expected_len` to match the state of that remaining payload.
             meta.payload.start += expected_len;
-            meta.expected_len =
-                payload_size(buffer.filled_get(meta.payload.start..meta.payload.end))?;
+            meta.expected_len = payload_size(
+                buffer.filled_get(meta.payload.start..meta.payload.end),
+                self.max_handshake_size,
+            )?;
         } else {
             // Otherwise, we've yielded the last handshake payload in the buffer, so we can
             // discard all of the bytes that we're previously buffered as handshake data.
@@ -251,6 +344,26 @@ impl MessageDeframer {
         }))
     }
 
+    /// Count an empty record, failing if there have been too many in a row.
+    fn note_empty_record(&mut self) -> Result<(), Error> {
+        if self.seen_consecutive_empty_fragments == ALLOWED_CONSECUTIVE_EMPTY_FRAGMENTS_MAX {
+            return Err(self.set_err(PeerMisbehaved::TooManyEmptyFragments));
+        }
+        self.seen_consecutive_empty_fragments += 1;
+        Ok(())
+    }
+
+    /// Discard the record ending at `end`.
+    ///
+    /// If we're joining a handshake payload, the record's bytes are left in place
+    /// to be overwritten, and are discarded along with the handshake payload.
+    fn skip_record(&mut self, end: usize, buffer: &mut DeframerSliceBuffer<'_>) {
+        match &mut self.joining_hs {
+            Some(meta) => meta.message.end = end,
+            None => buffer.queue_discard(end),
+        }
+    }
+
     /// Fuses this deframer's error and returns the set value.
     ///
     /// Any future calls to `pop` will return `err` again.
@@ -283,8 +396,10 @@ impl MessageDeframer {
 
                 // If we haven't parsed the payload size yet, try to do so now.
                 if meta.expected_len.is_none() {
-                    meta.expected_len =
-                        payload_size(buffer.filled_get(meta.payload.start..meta.payload.end))?;
+                    meta.expected_len = payload_size(
+                        buffer.filled_get(meta.payload.start..meta.payload.end),
+                        self.max_handshake_size,
+                    )?;
                 }
 
                 meta
@@ -293,7 +408,7 @@ impl MessageDeframer {
                 // We've found a new handshake message here.
                 // Write it into the buffer and create the metadata.
 
-                let expected_len = payload.size(buffer)?;
+                let expected_len = payload.size(buffer, self.max_handshake_size)?;
                 buffer.copy(&payload, 0);
                 self.joining_hs
                     .insert(HandshakePayloadMeta {
@@ -332,7 +447,9 @@ impl MessageDeframer {
             return Err(Error::General(
                 "cannot push QUIC messages into unrelated connection".into(),
             ));
-        } else if let Err(err) = buffer.prepare_read(self.joining_hs.is_some()) {
+        } else if let Err(err) =
+            buffer.prepare_read(self.joining_hs.is_some(), self.max_handshake_size)
+        {
             return Err(Error::General(err.into()));
         }
 
@@ -347,7 +464,7 @@ impl MessageDeframer {
         rd: &mut dyn io::Read,
         buffer: &mut DeframerVecBuffer,
     ) -> io::Result<usize> {
-        if let Err(err) = buffer.prepare_read(self.joining_hs.is_some()) {
+        if let Err(err) = buffer.prepare_read(self.joining_hs.is_some(), self.max_handshake_size) {
             return Err(io::Error::new(io::ErrorKind::InvalidData, err));
         }
 
@@ -369,6 +486,7 @@ trait AppendPayload<'a>: Sized {
     fn size<B: DeframerBuffer<'a, Self>>(
         &self,
         internal_buffer: &B,
+        max_handshake_size: u32,
     ) -> Result<Option<usize>, Error>;
 }
 
@@ -381,8 +499,12 @@ impl<'a> AppendPayload<'a> for ExternalPayload<'a> {
         self.0.len()
     }
 
-    fn size<B: DeframerBuffer<'a, Self>>(&self, _: &B) -> Result<Option<usize>, Error> {
-        payload_size(self.0)
+    fn size<B: DeframerBuffer<'a, Self>>(
+        &self,
+        _: &B,
+        max_handshake_size: u32,
+    ) -> Result<Option<usize>, Error> {
+        payload_size(self.0, max_handshake_size)
     }
 }
 
@@ -398,8 +520,12 @@ impl<'a> AppendPayload<'a> for InternalPayload {
     fn size<B: DeframerBuffer<'a, Self>>(
         &self,
         internal_buffer: &B,
+        max_handshake_size: u32,
     ) -> Result<Option<usize>, Error> {
-        payload_size(internal_buffer.filled_get(self.0.clone()))
+        payload_size(
+            internal_buffer.filled_get(self.0.clone()),
+            max_handshake_size,
+        )
     }
 }
 
@@ -455,8 +581,13 @@ impl DeframerVecBuffer {
     }
 
     /// Resize the internal `buf` if necessary for reading more bytes.
-    fn prepare_read(&mut self, is_joining_hs: bool) -> Result<(), &'static str> {
-        // We allow a maximum of 64k of buffered data for handshake messages only. Enforce this
+    fn prepare_read(
+        &mut self,
+        is_joining_hs: bool,
+        max_handshake_size: u32,
+    ) -> Result<(), &'static str> {
+        // We allow a maximum of `max_handshake_size` (by default, 64k) of buffered data for
+        // handshake messages only. Enforce this
         // by varying the maximum allowed buffer size here based on whether a prefix of a
         // handshake payload is currently being buffered. Given that the first read of such a
         // payload will only ever be 4k bytes, the next time we come around here we allow a
@@ -464,7 +595,7 @@ impl DeframerVecBuffer {
         // the same flight have been consumed, `pop()` will call `discard()` to reset `used`.
         // At this point, the buffer resizing logic below should reduce the buffer size.
         let allow_max = match is_joining_hs {
-            true => MAX_HANDSHAKE_SIZE as usize,
+            true => Ord::max(max_handshake_size as usize, MAX_WIRE_SIZE),
             false => MAX_WIRE_SIZE,
         };
 
@@ -688,16 +819,16 @@ struct HandshakePayloadMeta {
 /// Determine the expected length of the payload as advertised in the header.
 ///
 /// Returns `Err` if the advertised length is larger than what we want to accept
-/// (`MAX_HANDSHAKE_SIZE`), `Ok(None)` if the buffer is too small to contain a complete header,
+/// (`max_handshake_size`), `Ok(None)` if the buffer is too small to contain a complete header,
 /// and `Ok(Some(len))` otherwise.
-fn payload_size(buf: &[u8]) -> Result<Option<usize>, Error> {
+fn payload_size(buf: &[u8], max_handshake_size: u32) -> Result<Option<usize>, Error> {
     if buf.len() < HANDSHAKE_HEADER_SIZE {
         return Ok(None);
     }
 
     let (header, _) = buf.split_at(HANDSHAKE_HEADER_SIZE);
     match codec::u24::read_bytes(&header[1..]) {
-        Ok(len) if len.0 > MAX_HANDSHAKE_SIZE => Err(Error::InvalidMessage(
+        Ok(len) if len.0 > max_handshake_size => Err(Error::InvalidMessage(
             InvalidMessage::HandshakePayloadTooLarge,
         )),
         Ok(len) => Ok(Some(HANDSHAKE_HEADER_SIZE + usize::from(len))),
@@ -715,10 +846,16 @@ pub struct Deframed<'a> {
 
 const HANDSHAKE_HEADER_SIZE: usize = 1 + 3;
 
-/// TLS allows for handshake messages of up to 16MB.  We
-/// restrict that to 64KB to limit potential for denial-of-
+/// TLS allows for handshake messages of up to 16MB.  By default
+/// we restrict that to 64KB to limit potential for denial-of-
 /// service.
-const MAX_HANDSHAKE_SIZE: u32 = 0xffff;
+pub(crate) const DEFAULT_MAX_HANDSHAKE_SIZE: u32 = 0xffff;
+
+/// The smallest configurable limit: one full record of handshake data.
+const MIN_HANDSHAKE_SIZE: usize = 0x4000;
+
+/// The largest length a handshake message header can express.
+const MAX_HANDSHAKE_SIZE_LIMIT: usize = 0xff_ffff;
 
 #[cfg(feature = "std")]
 const READ_SIZE: usize = 4096;
@@ -938,6 +1075,179 @@ mod tests {
         assert!(d.input_bytes(&message).is_err());
     }
 
+    #[test]
+    fn test_max_handshake_size() {
+        // A 20000-byte handshake message, split over two records.
+        let mut payload = vec![0x0b, 0x00, 0x4e, 0x20]; // Certificate
+        payload.resize(4 + 20_000, 0);
+        let mut message = Vec::new();
+        for chunk in payload.chunks(16_384) {
+            message.extend(&[0x16, 0x03, 0x03]); // Handshake, TLS1.2
+            message.extend((chunk.len() as u16).to_be_bytes());
+            message.extend(chunk);
+        }
+        let (first, second) = message.split_at(5 + 16_384);
+        let mut rl = RecordLayer::new();
+
+        let mut d = BufferedDeframer::default();
+        input_whole(&mut d, first);
+        assert!(d.pop_none(&mut rl));
+        input_whole(&mut d, second);
+        assert_eq!(
+            d.pop_message(&mut rl, None)
+                .payload
+                .bytes()
+                .len(),
+            20_004
+        );
+
+        let mut d = BufferedDeframer::default();
+        d.inner
+            .set_max_handshake_size(Some(16_384))
+            .unwrap();
+        input_whole(&mut d, first);
+        assert_eq!(
+            d.pop_error(&mut rl, None),
+            Error::InvalidMessage(InvalidMessage::HandshakePayloadTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_max_handshake_size_range() {
+        let mut d = MessageDeframer::default();
+        assert_eq!(
+            d.set_max_handshake_size(Some(16_383)),
+            Err(Error::BadMaxHandshakeSize)
+        );
+        assert_eq!(
+            d.set_max_handshake_size(Some(0x100_0000)),
+            Err(Error::BadMaxHandshakeSize)
+        );
+        assert_eq!(d.set_max_handshake_size(Some(16_384)), Ok(()));
+        assert_eq!(d.set_max_handshake_size(Some(0xff_ffff)), Ok(()));
+        assert_eq!(d.set_max_handshake_size(None), Ok(()));
+        assert_eq!(d.max_handshake_size, 0xffff);
+    }
+
+    #[test]
+    fn test_record_version_strictness() {
+        let hello_at = |version: &[u8]| {
+            let mut message = vec![0x16];
+            message.extend(version);
+            message.extend(&[0x00, 0x04, 0x01, 0x00, 0x00, 0x00]); // empty ClientHello
+            message
+        };
+        let mut rl = RecordLayer::new();
+
+        let mut d = BufferedDeframer::default();
+        d.inner
+            .set_record_strictness(RecordStrictness::relaxed());
+        input_whole(&mut d, &hello_at(&[0x01, 0x23]));
+        assert_eq!(
+            d.pop_message(&mut rl, None).version,
+            ProtocolVersion::Unknown(0x0123)
+        );
+        for (version, negotiated, ok) in [
+            (&[0x03, 0x01], None, true),
+            (&[0x03, 0x03], None, true),
+            (&[0x03, 0x04], None, false),
+            (&[0x03, 0x01], Some(ProtocolVersion::TLSv1_3), false),
+            (&[0x03, 0x03], Some(ProtocolVersion::TLSv1_3), true),
+        ] {
+            let mut d = BufferedDeframer::default();
+            d.inner
+                .set_record_strictness(RecordStrictness::strict());
+            input_whole(&mut d, &hello_at(version));
+            match ok {
+                true => assert_eq!(
+                    d.pop_message(&mut rl, negotiated).typ,
+                    ContentType::Handshake
+                ),
+                false => assert_eq!(
+                    d.pop_error(&mut rl, negotiated),
+                    PeerMisbehaved::UnexpectedRecordVersion.into()
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_allow_empty_records() {
+        let mut rl = RecordLayer::new();
+        let strictness = RecordStrictness {
+            allow_empty_records: true,
+            ..RecordStrictness::default()
+        };
+
+        let mut d = BufferedDeframer::default();
+        d.inner
+            .set_record_strictness(strictness);
+        input_whole(&mut d, INVALID_EMPTY_MESSAGE);
+        input_whole(&mut d, &[0x14, 0x03, 0x03, 0x00, 0x00]); // empty ChangeCipherSpec
+        assert!(d.pop_none(&mut rl));
+        assert!(!d.has_pending());
+
+        input_whole(&mut d, INVALID_EMPTY_MESSAGE);
+        input_whole(&mut d, FIRST_MESSAGE);
+        pop_first(&mut d, &mut rl);
+        assert!(!d.has_pending());
+
+        let mut d = BufferedDeframer::default();
+        d.inner
+            .set_record_strictness(strictness);
+        for _ in 0..=ALLOWED_CONSECUTIVE_EMPTY_FRAGMENTS_MAX {
+            input_whole(&mut d, INVALID_EMPTY_MESSAGE);
+        }
+        assert_eq!(
+            d.pop_error(&mut rl, None),
+            PeerMisbehaved::TooManyEmptyFragments.into()
+        );
+    }
+
+    #[test]
+    fn test_allow_interleaved_change_cipher_spec() {
+        // A Certificate message split over two records, with a ChangeCipherSpec between.
+        let mut message = vec![0x16, 0x03, 0x03, 0x00, 0x02, 0x0b, 0x00];
+        message.extend(&[0x14, 0x03, 0x03, 0x00, 0x01, 0x01]);
+        message.extend(&[0x16, 0x03, 0x03, 0x00, 0x02, 0x00, 0x00]);
+        let mut rl = RecordLayer::new();
+
+        let mut d = BufferedDeframer::default();
+        input_whole(&mut d, &message);
+        assert_eq!(
+            d.pop_error(&mut rl, None),
+            PeerMisbehaved::MessageInterleavedWithHandshakeMessage.into()
+        );
+
+        let mut d = BufferedDeframer::default();
+        d.inner
+            .set_record_strictness(RecordStrictness::relaxed());
+        input_whole(&mut d, &message);
+        assert_eq!(
+            d.pop_message(&mut rl, None)
+                .payload
+                .bytes(),
+            &[0x0b, 0x00, 0x00, 0x00]
+        );
+        assert!(!d.has_pending());
+
+        let mut d = BufferedDeframer::default();
+        d.inner
+            .set_record_strictness(RecordStrictness::relaxed());
+        input_whole(&mut d, &message);
+        assert_eq!(
+            d.pop_error(&mut rl, Some(ProtocolVersion::TLSv1_2)),
+            PeerMisbehaved::MessageInterleavedWithHandshakeMessage.into()
+        );
+    }
+
+    fn input_whole(d: &mut BufferedDeframer, bytes: &[u8]) {
+        let mut read = 0;
+        while read < bytes.len() {
+            read += d.input_bytes(&bytes[read..]).unwrap();
+        }
+    }
+
     fn input_error(d: &mut BufferedDeframer) {
         let error = io::Error::from(io::ErrorKind::TimedOut);
         let mut rd = ErrorRead::new(error);
@@ -1022,6 +1332,18 @@ mod tests {
             m
         }
 
+        fn pop_none(&mut self, record_layer: &mut RecordLayer) -> bool {
+            let mut deframer_buffer = self.buffer.borrow();
+            let none = matches!(
+                self.inner
+                    .pop(record_layer, None, &mut deframer_buffer),
+                Ok(None)
+            );
+            let discard = deframer_buffer.pending_discard();
+            self.buffer.discard(discard);
+            none
+        }
+
         fn read(&mut self, rd: &mut dyn io::Read) -> io::Result<usize> {
             self.inner.read(rd, &mut self.buffer)
         }
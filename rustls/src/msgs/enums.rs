@@ -114,6 +114,7 @@ enum_builder! {
         TransportParameters => 0x0039,
         NextProtocolNegotiation => 0x3374,
         ChannelId => 0x754f,
+        TrustAnchors => 0xca34, // https://datatracker.ietf.org/doc/html/draft-ietf-tls-trust-anchor-ids-00#section-4.1
         RenegotiationInfo => 0xff01,
         TransportParametersDraft => 0xffa5,
         EncryptedClientHello => 0xfe0d, // https://datatracker.ietf.org/doc/html/draft-ietf-tls-esni-18#section-11.1
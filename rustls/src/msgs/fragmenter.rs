@@ -68,6 +68,14 @@ impl MessageFragmenter {
         };
         Ok(())
     }
+
+    /// Reduce the maximum fragment length (excluding overhead) to `max_fragment_len`.
+    ///
+    /// This never increases the current limit, so honours any smaller
+    /// `max_fragment_size` set previously.
+    pub(crate) fn limit_fragment_len(&mut self, max_fragment_len: usize) {
+        self.max_frag = self.max_frag.min(max_fragment_len);
+    }
 }
 
 /// An iterator over borrowed fragments of a payload
@@ -229,4 +237,23 @@ mod tests {
         );
         msg_eq(&fragments[2], 13, &typ, &version, b"dddddddd");
     }
+
+    #[test]
+    fn limit_fragment_len_only_reduces() {
+        let payload = vec![0u8; 4096];
+        let m = PlainMessage {
+            typ: ContentType::Handshake,
+            version: ProtocolVersion::TLSv1_2,
+            payload: Payload::new(payload),
+        };
+
+        let mut frag = MessageFragmenter::default();
+        frag.limit_fragment_len(1024);
+        assert_eq!(frag.fragment_message(&m).count(), 4);
+
+        frag.set_max_fragment_size(Some(512 + PACKET_OVERHEAD))
+            .unwrap();
+        frag.limit_fragment_len(1024);
+        assert_eq!(frag.fragment_message(&m).count(), 8);
+    }
 }
@@ -13,7 +13,7 @@ use crate::crypto::ActiveKeyExchange;
 use crate::crypto::SecureRandom;
 use crate::enums::{
     CertificateCompressionAlgorithm, CipherSuite, EchClientHelloType, HandshakeType,
-    ProtocolVersion, SignatureScheme,
+    MaxFragmentLength, ProtocolVersion, SignatureScheme,
 };
 use crate::error::InvalidMessage;
 #[cfg(feature = "tls12")]
@@ -315,6 +315,16 @@ impl TlsListElement for ProtocolName {
     const SIZE_LEN: ListLength = ListLength::U16;
 }
 
+wrapped_payload!(
+    /// A trust anchor identifier, as defined by draft-ietf-tls-trust-anchor-ids.
+    pub struct TrustAnchorId,
+    PayloadU8,
+);
+
+impl TlsListElement for TrustAnchorId {
+    const SIZE_LEN: ListLength = ListLength::U16;
+}
+
 pub(crate) trait ConvertProtocolNameList {
     fn from_slices(names: &[&[u8]]) -> Self;
     fn to_slices(&self) -> Vec<&[u8]>;
@@ -545,7 +555,9 @@ pub enum ClientExtension {
     EcPointFormats(Vec<ECPointFormat>),
     NamedGroups(Vec<NamedGroup>),
     SignatureAlgorithms(Vec<SignatureScheme>),
+    SignatureAlgorithmsCert(Vec<SignatureScheme>),
     ServerName(Vec<ServerName>),
+    MaxFragmentLength(MaxFragmentLength),
     SessionTicket(ClientSessionTicket),
     Protocols(Vec<ProtocolName>),
     SupportedVersions(Vec<ProtocolVersion>),
@@ -561,6 +573,7 @@ pub enum ClientExtension {
     CertificateCompressionAlgorithms(Vec<CertificateCompressionAlgorithm>),
     EncryptedClientHello(EncryptedClientHello),
     EncryptedClientHelloOuterExtensions(Vec<ExtensionType>),
+    TrustAnchors(Vec<TrustAnchorId>),
     Unknown(UnknownExtension),
 }
 
@@ -570,7 +583,9 @@ impl ClientExtension {
             Self::EcPointFormats(_) => ExtensionType::ECPointFormats,
             Self::NamedGroups(_) => ExtensionType::EllipticCurves,
             Self::SignatureAlgorithms(_) => ExtensionType::SignatureAlgorithms,
+            Self::SignatureAlgorithmsCert(_) => ExtensionType::SignatureAlgorithmsCert,
             Self::ServerName(_) => ExtensionType::ServerName,
+            Self::MaxFragmentLength(_) => ExtensionType::MaxFragmentLength,
             Self::SessionTicket(_) => ExtensionType::SessionTicket,
             Self::Protocols(_) => ExtensionType::ALProtocolNegotiation,
             Self::SupportedVersions(_) => ExtensionType::SupportedVersions,
@@ -588,6 +603,7 @@ impl ClientExtension {
             Self::EncryptedClientHelloOuterExtensions(_) => {
                 ExtensionType::EncryptedClientHelloOuterExtensions
             }
+            Self::TrustAnchors(_) => ExtensionType::TrustAnchors,
             Self::Unknown(ref r) => r.typ,
         }
     }
@@ -601,8 +617,11 @@ impl Codec<'_> for ClientExtension {
         match *self {
             Self::EcPointFormats(ref r) => r.encode(nested.buf),
             Self::NamedGroups(ref r) => r.encode(nested.buf),
-            Self::SignatureAlgorithms(ref r) => r.encode(nested.buf),
+            Self::SignatureAlgorithms(ref r) | Self::SignatureAlgorithmsCert(ref r) => {
+                r.encode(nested.buf)
+            }
             Self::ServerName(ref r) => r.encode(nested.buf),
+            Self::MaxFragmentLength(ref r) => r.encode(nested.buf),
             Self::SessionTicket(ClientSessionTicket::Request)
             | Self::ExtendedMasterSecretRequest
             | Self::EarlyData => {}
@@ -620,6 +639,7 @@ impl Codec<'_> for ClientExtension {
             Self::CertificateCompressionAlgorithms(ref r) => r.encode(nested.buf),
             Self::EncryptedClientHello(ref r) => r.encode(nested.buf),
             Self::EncryptedClientHelloOuterExtensions(ref r) => r.encode(nested.buf),
+            Self::TrustAnchors(ref r) => r.encode(nested.buf),
             Self::Unknown(ref r) => r.encode(nested.buf),
         }
     }
@@ -633,7 +653,13 @@ impl Codec<'_> for ClientExtension {
             ExtensionType::ECPointFormats => Self::EcPointFormats(Vec::read(&mut sub)?),
             ExtensionType::EllipticCurves => Self::NamedGroups(Vec::read(&mut sub)?),
             ExtensionType::SignatureAlgorithms => Self::SignatureAlgorithms(Vec::read(&mut sub)?),
+            ExtensionType::SignatureAlgorithmsCert => {
+                Self::SignatureAlgorithmsCert(Vec::read(&mut sub)?)
+            }
             ExtensionType::ServerName => Self::ServerName(Vec::read(&mut sub)?),
+            ExtensionType::MaxFragmentLength => {
+                Self::MaxFragmentLength(MaxFragmentLength::read(&mut sub)?)
+            }
             ExtensionType::SessionTicket => {
                 if sub.any_left() {
                     let contents = Payload::read(&mut sub).into_owned();
@@ -666,6 +692,7 @@ impl Codec<'_> for ClientExtension {
             ExtensionType::EncryptedClientHelloOuterExtensions => {
                 Self::EncryptedClientHelloOuterExtensions(Vec::read(&mut sub)?)
             }
+            ExtensionType::TrustAnchors => Self::TrustAnchors(Vec::read(&mut sub)?),
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
@@ -711,6 +738,7 @@ pub enum ClientSessionTicket {
 pub enum ServerExtension {
     EcPointFormats(Vec<ECPointFormat>),
     ServerNameAck,
+    MaxFragmentLength(MaxFragmentLength),
     SessionTicketAck,
     RenegotiationInfo(PayloadU8),
     Protocols(Vec<ProtocolName>),
@@ -731,6 +759,7 @@ impl ServerExtension {
         match *self {
             Self::EcPointFormats(_) => ExtensionType::ECPointFormats,
             Self::ServerNameAck => ExtensionType::ServerName,
+            Self::MaxFragmentLength(_) => ExtensionType::MaxFragmentLength,
             Self::SessionTicketAck => ExtensionType::SessionTicket,
             Self::RenegotiationInfo(_) => ExtensionType::RenegotiationInfo,
             Self::Protocols(_) => ExtensionType::ALProtocolNegotiation,
@@ -760,6 +789,7 @@ impl Codec<'_> for ServerExtension {
             | Self::ExtendedMasterSecretAck
             | Self::CertificateStatusAck
             | Self::EarlyData => {}
+            Self::MaxFragmentLength(ref r) => r.encode(nested.buf),
             Self::RenegotiationInfo(ref r) => r.encode(nested.buf),
             Self::Protocols(ref r) => r.encode(nested.buf),
             Self::KeyShare(ref r) => r.encode(nested.buf),
@@ -781,6 +811,9 @@ impl Codec<'_> for ServerExtension {
         let ext = match typ {
             ExtensionType::ECPointFormats => Self::EcPointFormats(Vec::read(&mut sub)?),
             ExtensionType::ServerName => Self::ServerNameAck,
+            ExtensionType::MaxFragmentLength => {
+                Self::MaxFragmentLength(MaxFragmentLength::read(&mut sub)?)
+            }
             ExtensionType::SessionTicket => Self::SessionTicketAck,
             ExtensionType::StatusRequest => Self::CertificateStatusAck,
             ExtensionType::RenegotiationInfo => Self::RenegotiationInfo(PayloadU8::read(&mut sub)?),
@@ -983,6 +1016,14 @@ impl ClientHelloPayload {
         }
     }
 
+    pub(crate) fn sigalgs_cert_extension(&self) -> Option<&[SignatureScheme]> {
+        let ext = self.find_extension(ExtensionType::SignatureAlgorithmsCert)?;
+        match *ext {
+            ClientExtension::SignatureAlgorithmsCert(ref req) => Some(req),
+            _ => None,
+        }
+    }
+
     pub(crate) fn namedgroups_extension(&self) -> Option<&[NamedGroup]> {
         let ext = self.find_extension(ExtensionType::EllipticCurves)?;
         match *ext {
@@ -1008,6 +1049,22 @@ impl ClientHelloPayload {
         }
     }
 
+    pub(crate) fn max_fragment_length_extension(&self) -> Option<MaxFragmentLength> {
+        let ext = self.find_extension(ExtensionType::MaxFragmentLength)?;
+        match *ext {
+            ClientExtension::MaxFragmentLength(mfl) => Some(mfl),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn trust_anchors_extension(&self) -> Option<&Vec<TrustAnchorId>> {
+        let ext = self.find_extension(ExtensionType::TrustAnchors)?;
+        match *ext {
+            ClientExtension::TrustAnchors(ref req) => Some(req),
+            _ => None,
+        }
+    }
+
     pub(crate) fn quic_params_extension(&self) -> Option<Vec<u8>> {
         let ext = self
             .find_extension(ExtensionType::TransportParameters)
@@ -1426,6 +1483,18 @@ impl CertificateChain<'_> {
     }
 }
 
+impl<'a> CertificateChain<'a> {
+    /// Read a chain whose encoding may be up to `max_size` bytes, rather than
+    /// [`CERTIFICATE_MAX_SIZE_LIMIT`].
+    pub(crate) fn read_with_max_size(
+        r: &mut Reader<'a>,
+        max_size: usize,
+    ) -> Result<Self, InvalidMessage> {
+        codec::read_vec_u24_limited(r, max_size, InvalidMessage::CertificatePayloadTooLarge)
+            .map(Self)
+    }
+}
+
 impl<'a> Codec<'a> for CertificateChain<'a> {
     fn encode(&self, bytes: &mut Vec<u8>) {
         Vec::encode(&self.0, bytes)
@@ -1455,9 +1524,13 @@ impl TlsListElement for CertificateDer<'_> {
 /// plus a 16MB limit on any given certificate.
 ///
 /// We contract that to 64KB to limit the amount of memory allocation
-/// that is directly controllable by the peer.
+/// that is directly controllable by the peer.  Connections configured with
+/// a larger `max_handshake_size` accept chains of up to that size instead.
 pub(crate) const CERTIFICATE_MAX_SIZE_LIMIT: usize = 0x1_0000;
 
+/// The largest certificate chain that can be encoded.
+pub(crate) const CERTIFICATE_MAX_SIZE_PROTOCOL_LIMIT: usize = 0xff_ffff;
+
 #[derive(Debug)]
 pub(crate) enum CertificateExtension<'a> {
     CertificateStatus(CertificateStatus<'a>),
@@ -1609,6 +1682,22 @@ impl<'a> Codec<'a> for CertificatePayloadTls13<'a> {
 }
 
 impl<'a> CertificatePayloadTls13<'a> {
+    /// Read a payload whose certificate entries may be up to `max_size` bytes,
+    /// rather than [`CERTIFICATE_MAX_SIZE_LIMIT`].
+    pub(crate) fn read_with_max_size(
+        r: &mut Reader<'a>,
+        max_size: usize,
+    ) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            context: PayloadU8::read(r)?,
+            entries: codec::read_vec_u24_limited(
+                r,
+                max_size,
+                InvalidMessage::CertificatePayloadTooLarge,
+            )?,
+        })
+    }
+
     pub(crate) fn new(
         certs: impl Iterator<Item = &'a CertificateDer<'a>>,
         ocsp_response: Option<&'a [u8]>,
@@ -1638,6 +1727,25 @@ impl<'a> CertificatePayloadTls13<'a> {
         }
     }
 
+    /// Staple `responses` to the certificates after the end-entity, in order.
+    pub(crate) fn with_intermediate_ocsp(mut self, responses: &'a [Option<Vec<u8>>]) -> Self {
+        for (entry, ocsp) in self
+            .entries
+            .iter_mut()
+            .skip(1)
+            .zip(responses)
+        {
+            if let Some(ocsp) = ocsp {
+                entry
+                    .exts
+                    .push(CertificateExtension::CertificateStatus(
+                        CertificateStatus::new(ocsp),
+                    ));
+            }
+        }
+        self
+    }
+
     pub(crate) fn into_owned(self) -> CertificatePayloadTls13<'static> {
         CertificatePayloadTls13 {
             context: self.context,
@@ -1687,6 +1795,19 @@ impl<'a> CertificatePayloadTls13<'a> {
             .unwrap_or_default()
     }
 
+    pub(crate) fn intermediate_ocsp(&self) -> Vec<Vec<u8>> {
+        self.entries
+            .iter()
+            .skip(1)
+            .map(|entry| {
+                entry
+                    .ocsp_response()
+                    .map(|resp| resp.to_vec())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
     pub(crate) fn into_certificate_chain(self) -> CertificateChain<'a> {
         CertificateChain(
             self.entries
@@ -1698,7 +1819,7 @@ impl<'a> CertificatePayloadTls13<'a> {
 }
 
 /// Describes supported key exchange mechanisms.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum KeyExchangeAlgorithm {
     /// Diffie-Hellman Key exchange (with only known parameters as defined in [RFC 7919]).
@@ -2041,6 +2162,14 @@ pub(crate) trait HasServerExtensions {
         }
     }
 
+    fn max_fragment_length_extension(&self) -> Option<MaxFragmentLength> {
+        let ext = self.find_extension(ExtensionType::MaxFragmentLength)?;
+        match *ext {
+            ServerExtension::MaxFragmentLength(mfl) => Some(mfl),
+            _ => None,
+        }
+    }
+
     fn quic_params_extension(&self) -> Option<Vec<u8>> {
         let ext = self
             .find_extension(ExtensionType::TransportParameters)
@@ -2603,6 +2732,16 @@ impl<'a> HandshakeMessagePayload<'a> {
     pub(crate) fn read_version(
         r: &mut Reader<'a>,
         vers: ProtocolVersion,
+    ) -> Result<Self, InvalidMessage> {
+        Self::read_version_with_max_certificate_size(r, vers, CERTIFICATE_MAX_SIZE_LIMIT)
+    }
+
+    /// Like [`HandshakeMessagePayload::read_version()`], but accepting certificate
+    /// chains of up to `max_certificate_size` bytes.
+    pub(crate) fn read_version_with_max_certificate_size(
+        r: &mut Reader<'a>,
+        vers: ProtocolVersion,
+        max_certificate_size: usize,
     ) -> Result<Self, InvalidMessage> {
         let mut typ = HandshakeType::read(r)?;
         let len = codec::u24::read(r)?.0 as usize;
@@ -2630,12 +2769,13 @@ impl<'a> HandshakeMessagePayload<'a> {
                 }
             }
             HandshakeType::Certificate if vers == ProtocolVersion::TLSv1_3 => {
-                let p = CertificatePayloadTls13::read(&mut sub)?;
+                let p =
+                    CertificatePayloadTls13::read_with_max_size(&mut sub, max_certificate_size)?;
                 HandshakePayload::CertificateTls13(p)
             }
-            HandshakeType::Certificate => {
-                HandshakePayload::Certificate(CertificateChain::read(&mut sub)?)
-            }
+            HandshakeType::Certificate => HandshakePayload::Certificate(
+                CertificateChain::read_with_max_size(&mut sub, max_certificate_size)?,
+            ),
             HandshakeType::ServerKeyExchange => {
                 let p = ServerKeyExchangePayload::read(&mut sub)?;
                 HandshakePayload::ServerKeyExchange(p)
@@ -5,7 +5,8 @@ use pki_types::{CertificateDer, DnsName};
 
 use super::handshake::{ServerDhParams, ServerKeyExchange, ServerKeyExchangeParams};
 use crate::enums::{
-    CertificateCompressionAlgorithm, CipherSuite, HandshakeType, ProtocolVersion, SignatureScheme,
+    CertificateCompressionAlgorithm, CipherSuite, HandshakeType, MaxFragmentLength,
+    ProtocolVersion, SignatureScheme,
 };
 use crate::error::InvalidMessage;
 use crate::msgs::base::{Payload, PayloadU16, PayloadU24, PayloadU8};
@@ -24,7 +25,7 @@ use crate::msgs::handshake::{
     NewSessionTicketExtension, NewSessionTicketPayload, NewSessionTicketPayloadTls13,
     PresharedKeyBinder, PresharedKeyIdentity, PresharedKeyOffer, ProtocolName, Random,
     ServerEcdhParams, ServerExtension, ServerHelloPayload, ServerKeyExchangePayload, SessionId,
-    UnknownExtension,
+    TrustAnchorId, UnknownExtension,
 };
 use crate::verify::DigitallySignedStruct;
 
@@ -490,6 +491,28 @@ fn client_alpn_extension() {
     });
 }
 
+#[test]
+fn client_max_fragment_length_extension() {
+    test_client_extension_getter(ExtensionType::MaxFragmentLength, |chp| {
+        chp.max_fragment_length_extension()
+            .is_some()
+    });
+}
+
+#[test]
+fn client_sigalgs_cert_extension() {
+    test_client_extension_getter(ExtensionType::SignatureAlgorithmsCert, |chp| {
+        chp.sigalgs_cert_extension().is_some()
+    });
+}
+
+#[test]
+fn client_trust_anchors_extension() {
+    test_client_extension_getter(ExtensionType::TrustAnchors, |chp| {
+        chp.trust_anchors_extension().is_some()
+    });
+}
+
 #[test]
 fn client_quic_params_extension() {
     test_client_extension_getter(ExtensionType::TransportParameters, |chp| {
@@ -672,6 +695,14 @@ fn server_ecpoints_extension() {
     });
 }
 
+#[test]
+fn server_max_fragment_length_extension() {
+    test_server_extension_getter(ExtensionType::MaxFragmentLength, |shp| {
+        shp.max_fragment_length_extension()
+            .is_some()
+    });
+}
+
 #[test]
 fn server_supported_versions() {
     test_server_extension_getter(ExtensionType::SupportedVersions, |shp| {
@@ -686,6 +717,23 @@ fn cert_entry_ocsp_response() {
     });
 }
 
+#[test]
+fn certificate_payload_tls13_intermediate_ocsp() {
+    let certs = [
+        CertificateDer::from(vec![1]),
+        CertificateDer::from(vec![2]),
+        CertificateDer::from(vec![3]),
+    ];
+    let intermediate_ocsp = [None, Some(b"root".to_vec())];
+    let payload = CertificatePayloadTls13::new(certs.iter(), Some(b"ee"))
+        .with_intermediate_ocsp(&intermediate_ocsp);
+
+    let encoded = payload.get_encoding();
+    let decoded = CertificatePayloadTls13::read_bytes(&encoded).unwrap();
+    assert_eq!(decoded.end_entity_ocsp(), b"ee");
+    assert_eq!(decoded.intermediate_ocsp(), vec![vec![], b"root".to_vec()]);
+}
+
 fn test_cert_extension_getter(typ: ExtensionType, getter: fn(&CertificateEntry<'_>) -> bool) {
     let mut ce = sample_certificate_payload_tls13()
         .entries
@@ -886,6 +934,16 @@ fn cannot_decode_huge_certificate() {
         HandshakeMessagePayload::read_bytes(&buf[..0x10001 + 7]).unwrap_err(),
         InvalidMessage::CertificatePayloadTooLarge
     );
+
+    // unless a larger limit is configured
+    buf[9] = 0xfe;
+    let mut r = Reader::init(&buf[..0x10001 + 7]);
+    HandshakeMessagePayload::read_version_with_max_certificate_size(
+        &mut r,
+        ProtocolVersion::TLSv1_2,
+        0x10001,
+    )
+    .unwrap();
 }
 
 #[test]
@@ -933,7 +991,9 @@ fn sample_client_hello_payload() -> ClientHelloPayload {
             ClientExtension::EcPointFormats(ECPointFormat::SUPPORTED.to_vec()),
             ClientExtension::NamedGroups(vec![NamedGroup::X25519]),
             ClientExtension::SignatureAlgorithms(vec![SignatureScheme::ECDSA_NISTP256_SHA256]),
+            ClientExtension::SignatureAlgorithmsCert(vec![SignatureScheme::ED25519]),
             ClientExtension::make_sni(&DnsName::try_from("hello").unwrap()),
+            ClientExtension::MaxFragmentLength(MaxFragmentLength::Len1024),
             ClientExtension::SessionTicket(ClientSessionTicket::Request),
             ClientExtension::SessionTicket(ClientSessionTicket::Offer(Payload::Borrowed(&[]))),
             ClientExtension::Protocols(vec![ProtocolName::from(vec![0])]),
@@ -959,6 +1019,7 @@ fn sample_client_hello_payload() -> ClientHelloPayload {
                 CertificateCompressionAlgorithm::Brotli,
                 CertificateCompressionAlgorithm::Zlib,
             ]),
+            ClientExtension::TrustAnchors(vec![TrustAnchorId::from(vec![1, 2, 3])]),
             ClientExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload::Borrowed(&[1, 2, 3]),
@@ -977,6 +1038,7 @@ fn sample_server_hello_payload() -> ServerHelloPayload {
         extensions: vec![
             ServerExtension::EcPointFormats(ECPointFormat::SUPPORTED.to_vec()),
             ServerExtension::ServerNameAck,
+            ServerExtension::MaxFragmentLength(MaxFragmentLength::Len1024),
             ServerExtension::SessionTicketAck,
             ServerExtension::RenegotiationInfo(PayloadU8(vec![0])),
             ServerExtension::Protocols(vec![ProtocolName::from(vec![0])]),
@@ -6,6 +6,7 @@ use crate::enums::{ContentType, ProtocolVersion};
 use crate::error::{Error, PeerMisbehaved};
 use crate::msgs::codec::ReaderMut;
 use crate::msgs::fragmenter::MAX_FRAGMENT_LEN;
+use crate::record_layer::RecordStrictness;
 
 /// A TLS frame, named TLSPlaintext in the standard.
 ///
@@ -65,8 +66,11 @@ impl<'a> InboundOpaqueMessage<'a> {
         Ok(self.into_plain_message())
     }
 
-    pub(crate) fn read(r: &mut ReaderMut<'a>) -> Result<Self, MessageError> {
-        let (typ, version, len) = r.as_reader(read_opaque_message_header)?;
+    pub(crate) fn read(
+        r: &mut ReaderMut<'a>,
+        strictness: &RecordStrictness,
+    ) -> Result<Self, MessageError> {
+        let (typ, version, len) = r.as_reader(|r| read_opaque_message_header(r, strictness))?;
 
         let mut sub = r
             .sub(len as usize)
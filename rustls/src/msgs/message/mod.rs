@@ -5,7 +5,9 @@ use crate::msgs::base::Payload;
 use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::msgs::codec::{Codec, Reader};
 use crate::msgs::enums::{AlertLevel, KeyUpdateRequest};
-use crate::msgs::handshake::{HandshakeMessagePayload, HandshakePayload};
+use crate::msgs::handshake::{
+    HandshakeMessagePayload, HandshakePayload, CERTIFICATE_MAX_SIZE_LIMIT,
+};
 
 mod inbound;
 pub use inbound::{BorrowedPayload, InboundOpaqueMessage, InboundPlainMessage};
@@ -47,13 +49,29 @@ impl<'a> MessagePayload<'a> {
         typ: ContentType,
         vers: ProtocolVersion,
         payload: &'a [u8],
+    ) -> Result<Self, InvalidMessage> {
+        Self::new_with_max_certificate_size(typ, vers, payload, CERTIFICATE_MAX_SIZE_LIMIT)
+    }
+
+    /// Like [`MessagePayload::new()`], but accepting certificate chains of up to
+    /// `max_certificate_size` bytes.
+    pub(crate) fn new_with_max_certificate_size(
+        typ: ContentType,
+        vers: ProtocolVersion,
+        payload: &'a [u8],
+        max_certificate_size: usize,
     ) -> Result<Self, InvalidMessage> {
         let mut r = Reader::init(payload);
         match typ {
             ContentType::ApplicationData => Ok(Self::ApplicationData(Payload::Borrowed(payload))),
             ContentType::Alert => AlertMessagePayload::read(&mut r).map(MessagePayload::Alert),
             ContentType::Handshake => {
-                HandshakeMessagePayload::read_version(&mut r, vers).map(|parsed| Self::Handshake {
+                HandshakeMessagePayload::read_version_with_max_certificate_size(
+                    &mut r,
+                    vers,
+                    max_certificate_size,
+                )
+                .map(|parsed| Self::Handshake {
                     parsed,
                     encoded: Payload::Borrowed(payload),
                 })
@@ -192,7 +210,6 @@ impl Message<'_> {
         }
     }
 
-    #[cfg(feature = "std")]
     pub(crate) fn into_owned(self) -> Message<'static> {
         let Self { version, payload } = self;
         Message {
@@ -222,9 +239,25 @@ impl<'a> TryFrom<InboundPlainMessage<'a>> for Message<'a> {
     type Error = Error;
 
     fn try_from(plain: InboundPlainMessage<'a>) -> Result<Self, Self::Error> {
+        Self::parse_with_max_certificate_size(plain, CERTIFICATE_MAX_SIZE_LIMIT)
+    }
+}
+
+impl<'a> Message<'a> {
+    /// Like `Message::try_from()`, but accepting certificate chains of up to
+    /// `max_certificate_size` bytes.
+    pub(crate) fn parse_with_max_certificate_size(
+        plain: InboundPlainMessage<'a>,
+        max_certificate_size: usize,
+    ) -> Result<Self, Error> {
         Ok(Self {
             version: plain.version,
-            payload: MessagePayload::new(plain.typ, plain.version, plain.payload)?,
+            payload: MessagePayload::new_with_max_certificate_size(
+                plain.typ,
+                plain.version,
+                plain.payload,
+                max_certificate_size,
+            )?,
         })
     }
 }
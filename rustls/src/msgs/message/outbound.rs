@@ -5,6 +5,7 @@ use crate::enums::{ContentType, ProtocolVersion};
 use crate::internal::record_layer::RecordLayer;
 use crate::msgs::base::Payload;
 use crate::msgs::codec::{Codec, Reader};
+use crate::record_layer::{RecordStrictness, RecordVersionCheck};
 
 /// A TLS frame, named `TLSPlaintext` in the standard.
 ///
@@ -176,7 +177,7 @@ impl OutboundOpaqueMessage {
     /// `MessageError` allows callers to distinguish between valid prefixes (might
     /// become valid if we read more data) and invalid data.
     pub fn read(r: &mut Reader<'_>) -> Result<Self, MessageError> {
-        let (typ, version, len) = read_opaque_message_header(r)?;
+        let (typ, version, len) = read_opaque_message_header(r, &RecordStrictness::default())?;
 
         let content = r
             .take(len as usize)
@@ -273,6 +274,7 @@ impl<const N: usize> From<&[u8; N]> for PrefixedPayload {
 
 pub(crate) fn read_opaque_message_header(
     r: &mut Reader<'_>,
+    strictness: &RecordStrictness,
 ) -> Result<(ContentType, ProtocolVersion, u16), MessageError> {
     let typ = ContentType::read(r).map_err(|_| MessageError::TooShortForHeader)?;
     // Don't accept any new content-types.
@@ -281,8 +283,9 @@ pub(crate) fn read_opaque_message_header(
     }
 
     let version = ProtocolVersion::read(r).map_err(|_| MessageError::TooShortForHeader)?;
-    // Accept only versions 0x03XX for any XX.
+    // Unless relaxed, accept only versions 0x03XX for any XX.
     match version {
+        _ if strictness.legacy_record_version == RecordVersionCheck::Any => {}
         ProtocolVersion::Unknown(ref v) if (v & 0xff00) != 0x0300 => {
             return Err(MessageError::UnknownProtocolVersion);
         }
@@ -294,7 +297,7 @@ pub(crate) fn read_opaque_message_header(
     // Reject undersize messages
     //  implemented per section 5.1 of RFC8446 (TLSv1.3)
     //              per section 6.2.1 of RFC5246 (TLSv1.2)
-    if typ != ContentType::ApplicationData && len == 0 {
+    if typ != ContentType::ApplicationData && len == 0 && !strictness.allow_empty_records {
         return Err(MessageError::InvalidEmptyPayload);
     }
 
@@ -10,9 +10,11 @@ use crate::enums::{CipherSuite, ProtocolVersion};
 use crate::error::InvalidMessage;
 use crate::msgs::base::{PayloadU16, PayloadU8};
 use crate::msgs::codec::{Codec, Reader};
-use crate::msgs::handshake::CertificateChain;
+use crate::msgs::enums::NamedGroup;
 #[cfg(feature = "tls12")]
 use crate::msgs::handshake::SessionId;
+use crate::msgs::handshake::{CertificateChain, CERTIFICATE_MAX_SIZE_PROTOCOL_LIMIT};
+use crate::server::ProducesTickets;
 #[cfg(feature = "tls12")]
 use crate::tls12::Tls12CipherSuite;
 use crate::tls13::Tls13CipherSuite;
@@ -58,6 +60,19 @@ impl<T: core::ops::Deref<Target = ClientSessionCommon>> Retrieved<T> {
                 .saturating_add(u64::from(common.lifetime_secs))
                 < self.retrieved_at.as_secs()
     }
+
+    /// Whether this will have expired `window_secs` from when it was retrieved.
+    pub(crate) fn expires_within(&self, window_secs: u64) -> bool {
+        let common = &*self.value;
+        common.lifetime_secs != 0
+            && common
+                .epoch
+                .saturating_add(u64::from(common.lifetime_secs))
+                < self
+                    .retrieved_at
+                    .as_secs()
+                    .saturating_add(window_secs)
+    }
 }
 
 impl<T> core::ops::Deref for Retrieved<T> {
@@ -75,6 +90,7 @@ pub struct Tls13ClientSessionValue {
     max_early_data_size: u32,
     pub(crate) common: ClientSessionCommon,
     quic_params: PayloadU16,
+    kx_group: Option<NamedGroup>,
 }
 
 impl Tls13ClientSessionValue {
@@ -100,6 +116,7 @@ impl Tls13ClientSessionValue {
                 server_cert_chain,
             ),
             quic_params: PayloadU16(Vec::new()),
+            kx_group: None,
         }
     }
 
@@ -124,6 +141,15 @@ impl Tls13ClientSessionValue {
     pub fn quic_params(&self) -> Vec<u8> {
         self.quic_params.0.clone()
     }
+
+    pub(crate) fn set_kx_group(&mut self, kx_group: Option<NamedGroup>) {
+        self.kx_group = kx_group;
+    }
+
+    /// The key exchange group of the connection that received this ticket.
+    pub(crate) fn kx_group(&self) -> Option<NamedGroup> {
+        self.kx_group
+    }
 }
 
 impl core::ops::Deref for Tls13ClientSessionValue {
@@ -238,6 +264,28 @@ impl ClientSessionCommon {
     pub(crate) fn ticket(&self) -> &[u8] {
         self.ticket.0.as_ref()
     }
+
+    /// Encrypt the ticket and secret in place using `sealer`.
+    ///
+    /// On failure, this is left unchanged and must not be stored.
+    #[cfg_attr(not(any(feature = "std", feature = "hashbrown")), allow(dead_code))]
+    pub(crate) fn seal(&mut self, sealer: &dyn ProducesTickets) -> Option<()> {
+        let secret = sealer.encrypt(self.secret())?;
+        let ticket = sealer.encrypt(self.ticket())?;
+        self.secret = Zeroizing::new(PayloadU8(secret));
+        self.ticket = PayloadU16(ticket);
+        Some(())
+    }
+
+    /// Reverse `seal()`.
+    #[cfg_attr(not(any(feature = "std", feature = "hashbrown")), allow(dead_code))]
+    pub(crate) fn unseal(&mut self, sealer: &dyn ProducesTickets) -> Option<()> {
+        let secret = Zeroizing::new(PayloadU8(sealer.decrypt(self.secret())?));
+        let ticket = sealer.decrypt(self.ticket())?;
+        self.secret = secret;
+        self.ticket = PayloadU16(ticket);
+        Some(())
+    }
 }
 
 static MAX_TICKET_LIFETIME: u32 = 7 * 24 * 60 * 60;
@@ -315,7 +363,10 @@ impl Codec<'_> for ServerSessionValue {
         let ems = u8::read(r)?;
         let has_ccert = u8::read(r)? == 1;
         let ccert = if has_ccert {
-            Some(CertificateChain::read(r)?.into_owned())
+            Some(
+                CertificateChain::read_with_max_size(r, CERTIFICATE_MAX_SIZE_PROTOCOL_LIMIT)?
+                    .into_owned(),
+            )
         } else {
             None
         };
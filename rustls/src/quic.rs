@@ -180,7 +180,13 @@ mod connection {
                 Version::V1 | Version::V2 => ClientExtension::TransportParameters(params),
             };
 
-            let mut inner = ConnectionCore::for_client(config, name, vec![ext], Protocol::Quic)?;
+            let mut inner = ConnectionCore::for_client(
+                config,
+                name,
+                vec![ext],
+                Protocol::Quic,
+                ClientConnectionData::new(),
+            )?;
             inner.common_state.quic.version = quic_version;
             Ok(Self {
                 inner: inner.into(),
@@ -609,6 +615,14 @@ pub trait Algorithm: Send + Sync {
     fn fips(&self) -> bool {
         false
     }
+
+    /// Return `true` if this is backed by a formally verified implementation.
+    ///
+    /// This means the implementation has been proven, by machine-checked proofs,
+    /// to meet its specification.
+    fn formally_verified(&self) -> bool {
+        false
+    }
 }
 
 /// A QUIC header protection key
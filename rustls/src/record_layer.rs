@@ -1,11 +1,16 @@
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use core::cmp::min;
+use core::fmt::Debug;
 
 use crate::crypto::cipher::{InboundOpaqueMessage, MessageDecrypter, MessageEncrypter};
+use crate::enums::ContentType;
 use crate::error::Error;
 #[cfg(feature = "logging")]
 use crate::log::trace;
-use crate::msgs::message::{InboundPlainMessage, OutboundOpaqueMessage, OutboundPlainMessage};
+use crate::msgs::message::{
+    InboundPlainMessage, OutboundOpaqueMessage, OutboundPlainMessage, HEADER_SIZE,
+};
 
 #[derive(PartialEq)]
 enum DirectionState {
@@ -25,6 +30,7 @@ pub struct RecordLayer {
     message_decrypter: Box<dyn MessageDecrypter>,
     write_seq_max: u64,
     write_seq: u64,
+    write_exhausted: bool,
     read_seq: u64,
     has_decrypted: bool,
     encrypt_state: DirectionState,
@@ -34,6 +40,15 @@ pub struct RecordLayer {
     // should be swallowed by the caller.  This struct tracks the amount
     // of message size this is allowed for.
     trial_decryption_len: Option<usize>,
+
+    observer: Option<Arc<dyn ObservesRecords>>,
+    key_limits: KeyLimits,
+    write_epoch: u64,
+    read_epoch: u64,
+    unprotected_write_seq: u64,
+    unprotected_read_seq: u64,
+    bytes_written: u64,
+    bytes_read: u64,
 }
 
 impl RecordLayer {
@@ -44,11 +59,20 @@ impl RecordLayer {
             message_decrypter: <dyn MessageDecrypter>::invalid(),
             write_seq_max: 0,
             write_seq: 0,
+            write_exhausted: false,
             read_seq: 0,
             has_decrypted: false,
             encrypt_state: DirectionState::Invalid,
             decrypt_state: DirectionState::Invalid,
             trial_decryption_len: None,
+            observer: None,
+            key_limits: KeyLimits::default(),
+            write_epoch: 0,
+            read_epoch: 0,
+            unprotected_write_seq: 0,
+            unprotected_read_seq: 0,
+            bytes_written: 0,
+            bytes_read: 0,
         }
     }
 
@@ -62,9 +86,11 @@ impl RecordLayer {
         encr: InboundOpaqueMessage<'a>,
     ) -> Result<Option<Decrypted<'a>>, Error> {
         if self.decrypt_state != DirectionState::Active {
+            let plaintext = encr.into_plain_message();
+            self.note_unprotected_incoming(plaintext.typ, plaintext.payload.len());
             return Ok(Some(Decrypted {
                 want_close_before_decrypt: false,
-                plaintext: encr.into_plain_message(),
+                plaintext,
             }));
         }
 
@@ -79,11 +105,21 @@ impl RecordLayer {
         let want_close_before_decrypt = self.read_seq == SEQ_SOFT_LIMIT;
 
         let encrypted_len = encr.payload.len();
+        self.bytes_read += (HEADER_SIZE + encrypted_len) as u64;
         match self
             .message_decrypter
             .decrypt(encr, self.read_seq)
         {
             Ok(plaintext) => {
+                if let Some(observer) = &self.observer {
+                    observer.record_received(&RecordInfo {
+                        content_type: plaintext.typ,
+                        payload_len: plaintext.payload.len(),
+                        record_len: HEADER_SIZE + encrypted_len,
+                        epoch: self.read_epoch,
+                        sequence: self.read_seq,
+                    });
+                }
                 self.read_seq += 1;
                 if !self.has_decrypted {
                     self.has_decrypted = true;
@@ -110,12 +146,84 @@ impl RecordLayer {
         plain: OutboundPlainMessage<'_>,
     ) -> OutboundOpaqueMessage {
         debug_assert!(self.encrypt_state == DirectionState::Active);
-        assert!(self.next_pre_encrypt_action() != PreEncryptAction::Refuse);
+        assert!(self.write_seq < SEQ_HARD_LIMIT);
         let seq = self.write_seq;
         self.write_seq += 1;
-        self.message_encrypter
+        let (content_type, payload_len) = (plain.typ, plain.payload.len());
+        let encrypted = self
+            .message_encrypter
             .encrypt(plain, seq)
-            .unwrap()
+            .unwrap();
+        self.bytes_written += (HEADER_SIZE + encrypted.payload.as_ref().len()) as u64;
+
+        if let Some(observer) = &self.observer {
+            observer.record_sent(&RecordInfo {
+                content_type,
+                payload_len,
+                record_len: HEADER_SIZE + encrypted.payload.as_ref().len(),
+                epoch: self.write_epoch,
+                sequence: seq,
+            });
+        }
+        encrypted
+    }
+
+    /// Tell the observer (if any) about a record we are sending without protection.
+    pub(crate) fn note_unprotected_outgoing(
+        &mut self,
+        content_type: ContentType,
+        payload_len: usize,
+    ) {
+        let seq = self.unprotected_write_seq;
+        self.unprotected_write_seq += 1;
+        self.bytes_written += (HEADER_SIZE + payload_len) as u64;
+        if let Some(observer) = &self.observer {
+            observer.record_sent(&RecordInfo {
+                content_type,
+                payload_len,
+                record_len: HEADER_SIZE + payload_len,
+                epoch: 0,
+                sequence: seq,
+            });
+        }
+    }
+
+    /// Tell the observer (if any) about a record we received without protection.
+    pub(crate) fn note_unprotected_incoming(
+        &mut self,
+        content_type: ContentType,
+        payload_len: usize,
+    ) {
+        let seq = self.unprotected_read_seq;
+        self.unprotected_read_seq += 1;
+        self.bytes_read += (HEADER_SIZE + payload_len) as u64;
+        if let Some(observer) = &self.observer {
+            observer.record_received(&RecordInfo {
+                content_type,
+                payload_len,
+                record_len: HEADER_SIZE + payload_len,
+                epoch: 0,
+                sequence: seq,
+            });
+        }
+    }
+
+    /// The number of bytes of records sent and received so far, including headers.
+    pub(crate) fn bytes_transferred(&self) -> (u64, u64) {
+        (self.bytes_written, self.bytes_read)
+    }
+
+    pub(crate) fn set_observer(&mut self, observer: Option<Arc<dyn ObservesRecords>>) {
+        self.observer = observer;
+    }
+
+    /// Apply `key_limits` to keys installed from now on.
+    pub(crate) fn set_key_limits(&mut self, key_limits: KeyLimits) {
+        self.key_limits = key_limits;
+    }
+
+    pub(crate) fn key_limits(&self) -> &KeyLimits {
+        &self.key_limits
     }
 
     /// Prepare to use the given `MessageEncrypter` for future message encryption.
@@ -127,7 +235,11 @@ impl RecordLayer {
     ) {
         self.message_encrypter = cipher;
         self.write_seq = 0;
+        self.write_exhausted = false;
         self.write_seq_max = min(SEQ_SOFT_LIMIT, max_messages);
+        if let Some(max_records) = self.key_limits.max_records_per_key {
+            self.write_seq_max = min(self.write_seq_max, max_records);
+        }
         self.encrypt_state = DirectionState::Prepared;
     }
 
@@ -144,6 +256,7 @@ impl RecordLayer {
     pub(crate) fn start_encrypting(&mut self) {
         debug_assert!(self.encrypt_state == DirectionState::Prepared);
         self.encrypt_state = DirectionState::Active;
+        self.write_epoch += 1;
     }
 
     /// Start using the `MessageDecrypter` previously provided to the previous
@@ -151,6 +264,7 @@ impl RecordLayer {
     pub(crate) fn start_decrypting(&mut self) {
         debug_assert!(self.decrypt_state == DirectionState::Prepared);
         self.decrypt_state = DirectionState::Active;
+        self.read_epoch += 1;
     }
 
     /// Set and start using the given `MessageEncrypter` for future outgoing
@@ -199,12 +313,24 @@ impl RecordLayer {
     /// "the next message processed by `encrypt_outgoing`"
     pub(crate) fn pre_encrypt_action(&self, add: u64) -> PreEncryptAction {
         match self.write_seq.saturating_add(add) {
+            _ if self.write_exhausted => PreEncryptAction::Refuse,
             v if v == self.write_seq_max => PreEncryptAction::RefreshOrClose,
             SEQ_HARD_LIMIT.. => PreEncryptAction::Refuse,
             _ => PreEncryptAction::Nothing,
         }
     }
 
+    /// Refuse to encrypt anything but alerts with the current key.
+    pub(crate) fn exhaust_encrypter(&mut self) {
+        self.write_exhausted = true;
+    }
+
+    /// Whether [`Self::exhaust_encrypter()`] was called for the current key.
+    #[cfg(feature = "std")]
+    pub(crate) fn is_encrypt_exhausted(&self) -> bool {
+        self.write_exhausted
+    }
+
     pub(crate) fn is_encrypting(&self) -> bool {
         self.encrypt_state == DirectionState::Active
     }
@@ -242,6 +368,197 @@ impl RecordLayer {
     }
 }
 
+/// Receives metadata about each TLS record sent or received on a connection.
+///
+/// Install one of these with [`ClientConfig::record_observer`] or
+/// [`ServerConfig::record_observer`].  The contents of records are never
+/// passed to the observer.
+///
+/// This is not used for QUIC connections, which do not have a TLS record layer.
+///
+/// Both methods are called synchronously as records are processed, so should
+/// return promptly.
+///
+/// [`ClientConfig::record_observer`]: crate::ClientConfig::record_observer
+/// [`ServerConfig::record_observer`]: crate::ServerConfig::record_observer
+pub trait ObservesRecords: Debug + Send + Sync {
+    /// Called when a record is produced for sending to the peer.
+    fn record_sent(&self, record: &RecordInfo) {
+        let _ = record;
+    }
+
+    /// Called when a record from the peer has been successfully decoded
+    /// and (if protected) decrypted.
+    fn record_received(&self, record: &RecordInfo) {
+        let _ = record;
+    }
+}
+
+/// Metadata about one TLS record, given to an [`ObservesRecords`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordInfo {
+    /// The record's content type.
+    ///
+    /// For protected TLS1.3 records this is the inner content type, rather
+    /// than the `application_data` type that appears on the wire.
+    pub content_type: ContentType,
+
+    /// The length of the record's plaintext.
+    pub payload_len: usize,
+
+    /// The length of the record on the wire, including its header.
+    pub record_len: usize,
+
+    /// Which set of keys protects this record.
+    ///
+    /// 0 means the record is not protected.  Each change of keys in this
+    /// direction -- for example, moving to TLS1.3 handshake keys, then
+    /// application keys, then each key update -- starts a new epoch.
+    pub epoch: u64,
+
+    /// The record sequence number within `epoch`.
+    ///
+    /// For protected records this is the sequence number used by the cipher.
+    /// For unprotected records this counts them from zero.
+    pub sequence: u64,
+}
+
+/// Limits on how many records are encrypted with one key.
+///
+/// Every key is limited to the [`CipherSuiteCommon::confidentiality_limit`] of
+/// the negotiated cipher suite.  This can lower that limit for conservative
+/// deployments, and choose what happens when a key reaches it.  Set this as
+/// [`ClientConfig::key_limits`] or [`ServerConfig::key_limits`].
+///
+/// [`CipherSuiteCommon::confidentiality_limit`]: crate::crypto::CipherSuiteCommon::confidentiality_limit
+/// [`ClientConfig::key_limits`]: crate::ClientConfig::key_limits
+/// [`ServerConfig::key_limits`]: crate::ServerConfig::key_limits
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyLimits {
+    max_records_per_key: Option<u64>,
+
+    /// What to do when a key reaches its limit.
+    pub on_limit: KeyLimitAction,
+}
+
+impl KeyLimits {
+    /// Limit each key to `max_records` records, if fewer than the cipher suite's limit.
+    ///
+    /// By default only the cipher suite's limit applies.  Panics if `max_records` is zero.
+    pub fn max_records_per_key(mut self, max_records: u64) -> Self {
+        assert!(max_records > 0, "key limit must be at least one record");
+        self.max_records_per_key = Some(max_records);
+        self
+    }
+
+    /// Do `action` when a key reaches its limit.
+    pub fn on_limit(mut self, action: KeyLimitAction) -> Self {
+        self.on_limit = action;
+        self
+    }
+}
+
+/// What a connection does when a key reaches its [`KeyLimits`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyLimitAction {
+    /// For TLS1.3, send a `KeyUpdate` message and continue with new keys.  This is
+    /// the default.
+    ///
+    /// TLS1.2 cannot change keys, so instead the connection is closed as for
+    /// [`KeyLimitAction::Close`].
+    #[default]
+    UpdateKeys,
+
+    /// Send a `close_notify` alert, and encrypt nothing further.
+    ///
+    /// Application data that would need the key beyond its limit is not sent:
+    /// [`UnbufferedConnectionCommon`] reports [`EncryptError::EncryptExhausted`], and
+    /// [`Writer`] fails with an [`io::ErrorKind::BrokenPipe`] error wrapping it.
+    ///
+    /// [`UnbufferedConnectionCommon`]: crate::unbuffered::UnbufferedConnectionCommon
+    /// [`EncryptError::EncryptExhausted`]: crate::unbuffered::EncryptError::EncryptExhausted
+    /// [`Writer`]: crate::Writer
+    /// [`io::ErrorKind::BrokenPipe`]: std::io::ErrorKind::BrokenPipe
+    Close,
+}
+
+/// How strictly the framing of records received from the peer is checked.
+///
+/// The default follows the TLS specifications, and should only be relaxed to
+/// interoperate with peers known to break them.  Set this as
+/// [`ClientConfig::record_strictness`] or [`ServerConfig::record_strictness`].
+///
+/// [`ClientConfig::record_strictness`]: crate::ClientConfig::record_strictness
+/// [`ServerConfig::record_strictness`]: crate::ServerConfig::record_strictness
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecordStrictness {
+    /// How the `legacy_record_version` in each record header is checked.
+    pub legacy_record_version: RecordVersionCheck,
+
+    /// Whether empty handshake, alert and `change_cipher_spec` records are
+    /// ignored, rather than rejected with [`InvalidMessage::InvalidEmptyPayload`].
+    ///
+    /// Empty application data records are always allowed, though a run of
+    /// more than 32 empty records of any type is an error.
+    ///
+    /// [`InvalidMessage::InvalidEmptyPayload`]: crate::InvalidMessage::InvalidEmptyPayload
+    pub allow_empty_records: bool,
+
+    /// Whether `change_cipher_spec` records between the records of a fragmented
+    /// handshake message are ignored, rather than rejected with
+    /// [`PeerMisbehaved::MessageInterleavedWithHandshakeMessage`].
+    ///
+    /// Once TLS1.2 is negotiated such records are always rejected, because there they
+    /// change the keys in use.
+    ///
+    /// [`PeerMisbehaved::MessageInterleavedWithHandshakeMessage`]: crate::PeerMisbehaved::MessageInterleavedWithHandshakeMessage
+    pub allow_interleaved_change_cipher_spec: bool,
+}
+
+impl RecordStrictness {
+    /// Check everything that can be checked, including that the `legacy_record_version`
+    /// is exactly what a compliant peer sends.
+    pub fn strict() -> Self {
+        Self {
+            legacy_record_version: RecordVersionCheck::Exact,
+            ..Self::default()
+        }
+    }
+
+    /// Accept any `legacy_record_version`, ignore empty records, and ignore
+    /// `change_cipher_spec` records interleaved with handshake messages.
+    pub fn relaxed() -> Self {
+        Self {
+            legacy_record_version: RecordVersionCheck::Any,
+            allow_empty_records: true,
+            allow_interleaved_change_cipher_spec: true,
+        }
+    }
+}
+
+/// How [`RecordStrictness::legacy_record_version`] is checked.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecordVersionCheck {
+    /// Accept any version.
+    Any,
+
+    /// Accept any version from the TLS family, 0x03XX.  This is the default.
+    #[default]
+    Family,
+
+    /// Accept only 0x0303 once a version has been negotiated, and 0x0301 to 0x0303
+    /// before then.  Other versions are rejected with
+    /// [`PeerMisbehaved::UnexpectedRecordVersion`].
+    ///
+    /// [`PeerMisbehaved::UnexpectedRecordVersion`]: crate::PeerMisbehaved::UnexpectedRecordVersion
+    Exact,
+}
+
 /// Result of decryption.
 #[derive(Debug)]
 pub(crate) struct Decrypted<'a> {
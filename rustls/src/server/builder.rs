@@ -5,13 +5,15 @@ use core::marker::PhantomData;
 use pki_types::{CertificateDer, PrivateKeyDer};
 
 use crate::builder::{ConfigBuilder, WantsVerifier};
+use crate::common_state::PostHandshakeLimits;
 use crate::crypto::CryptoProvider;
 use crate::error::Error;
-use crate::server::{handy, ResolvesServerCert, ServerConfig};
+use crate::record_layer::{KeyLimits, RecordStrictness};
+use crate::server::{handy, AlpnPolicy, ResolvesServerCert, ServerConfig};
 use crate::sign::CertifiedKey;
 use crate::time_provider::TimeProvider;
 use crate::verify::{ClientCertVerifier, NoClientAuth};
-use crate::{compress, versions, InconsistentKeys, NoKeyLog};
+use crate::{compress, versions, NoKeyLog};
 
 impl ConfigBuilder<ServerConfig, WantsVerifier> {
     /// Choose how to verify client certificates.
@@ -65,7 +67,8 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
     ///
     /// This function fails if `key_der` is invalid, or if the
     /// `SubjectPublicKeyInfo` from the private key does not match the public
-    /// key for the end-entity certificate from the `cert_chain`.
+    /// key for the end-entity certificate from the `cert_chain`, or if
+    /// `cert_chain` is out of order.  See [`CertifiedKey::new_checked()`].
     pub fn with_single_cert(
         self,
         cert_chain: Vec<CertificateDer<'static>>,
@@ -77,12 +80,7 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
             .key_provider
             .load_private_key(key_der)?;
 
-        let certified_key = CertifiedKey::new(cert_chain, private_key);
-        match certified_key.keys_match() {
-            // Don't treat unknown consistency as an error
-            Ok(()) | Err(Error::InconsistentKeys(InconsistentKeys::Unknown)) => (),
-            Err(err) => return Err(err),
-        }
+        let certified_key = CertifiedKey::new_checked(cert_chain, private_key)?;
 
         let resolver = handy::AlwaysResolvesChain::new(certified_key);
         Ok(self.with_cert_resolver(Arc::new(resolver)))
@@ -100,7 +98,8 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
     ///
     /// This function fails if `key_der` is invalid, or if the
     /// `SubjectPublicKeyInfo` from the private key does not match the public
-    /// key for the end-entity certificate from the `cert_chain`.
+    /// key for the end-entity certificate from the `cert_chain`, or if
+    /// `cert_chain` is out of order.  See [`CertifiedKey::new_checked()`].
     pub fn with_single_cert_with_ocsp(
         self,
         cert_chain: Vec<CertificateDer<'static>>,
@@ -113,12 +112,7 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
             .key_provider
             .load_private_key(key_der)?;
 
-        let certified_key = CertifiedKey::new(cert_chain, private_key);
-        match certified_key.keys_match() {
-            // Don't treat unknown consistency as an error
-            Ok(()) | Err(Error::InconsistentKeys(InconsistentKeys::Unknown)) => (),
-            Err(err) => return Err(err),
-        }
+        let certified_key = CertifiedKey::new_checked(cert_chain, private_key)?;
 
         let resolver = handy::AlwaysResolvesChain::new_with_extras(certified_key, ocsp);
         Ok(self.with_cert_resolver(Arc::new(resolver)))
@@ -129,27 +123,50 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
         ServerConfig {
             provider: self.state.provider,
             verifier: self.state.verifier,
+            handshake_mutator: None,
             cert_resolver,
             ignore_client_order: false,
+            cipher_suite_scorer: None,
             max_fragment_size: None,
+            max_handshake_size: None,
+            max_decompressed_cert_size: None,
+            record_strictness: RecordStrictness::default(),
+            key_limits: KeyLimits::default(),
+            post_handshake_limits: PostHandshakeLimits::default(),
             #[cfg(feature = "std")]
             session_storage: handy::ServerSessionMemoryCache::new(256),
             #[cfg(not(feature = "std"))]
             session_storage: Arc::new(handy::NoServerSessionStorage {}),
+            session_lifetime: 24 * 60 * 60,
             ticketer: Arc::new(handy::NeverProducesTickets {}),
             alpn_protocols: Vec::new(),
+            alpn_policy: AlpnPolicy::Strict,
             versions: self.state.versions,
             key_log: Arc::new(NoKeyLog {}),
+            record_observer: None,
+            alert_policy: None,
+            diagnostics: None,
+            audit_sink: None,
+            silent_rejection: false,
             enable_secret_extraction: false,
             max_early_data_size: 0,
             send_half_rtt_data: false,
             send_tls13_tickets: 4,
+            ticket_lifetime: None,
+            randomize_ticket_age_add: true,
+            ticket_max_early_data_size: None,
+            ticket_observer: None,
             #[cfg(feature = "tls12")]
-            require_ems: cfg!(feature = "fips"),
+            require_ems: true,
             time_provider: self.state.time_provider,
+            handshake_timeout: None,
+            respond_to_close_notify: false,
             cert_compressors: compress::default_cert_compressors().to_vec(),
             cert_compression_cache: Arc::new(compress::CompressionCache::default()),
+            cert_compression_policy: None,
             cert_decompressors: compress::default_cert_decompressors().to_vec(),
+            cert_expiry_warning: None,
+            ocsp_freshness: None,
         }
     }
 }
@@ -1,11 +1,18 @@
-use pki_types::CertificateDer;
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
 
+use pki_types::{CertificateDer, UnixTime};
+
+use super::expiry::OcspFreshness;
+#[cfg(feature = "logging")]
+use crate::log::warn;
 use crate::sign;
 
 /// ActiveCertifiedKey wraps [`sign::CertifiedKey`] and tracks OSCP state in a single handshake.
 pub(super) struct ActiveCertifiedKey<'a> {
     key: &'a sign::CertifiedKey,
     ocsp: Option<&'a [u8]>,
+    intermediate_ocsp: Cow<'a, [Option<Vec<u8>>]>,
 }
 
 impl<'a> ActiveCertifiedKey<'a> {
@@ -13,7 +20,45 @@ impl<'a> ActiveCertifiedKey<'a> {
         ActiveCertifiedKey {
             key,
             ocsp: key.ocsp.as_deref(),
+            intermediate_ocsp: Cow::Borrowed(key.intermediate_ocsp()),
+        }
+    }
+
+    /// Forget the OCSP responses that `freshness` finds stale, returning whether there were any.
+    ///
+    /// The responses for intermediate certificates are only checked if `intermediates` is set.
+    pub(super) fn drop_stale_ocsp(
+        &mut self,
+        freshness: &OcspFreshness,
+        now: UnixTime,
+        intermediates: bool,
+    ) -> bool {
+        let mut stale = false;
+
+        if let Some(ocsp) = self.ocsp {
+            if !freshness.is_fresh(ocsp, now) {
+                warn!("stale OCSP response for end-entity certificate");
+                self.ocsp = None;
+                stale = true;
+            }
+        }
+
+        if !intermediates {
+            return stale;
+        }
+
+        for i in 0..self.intermediate_ocsp.len() {
+            let fresh = match &self.intermediate_ocsp[i] {
+                Some(ocsp) => freshness.is_fresh(ocsp, now),
+                None => true,
+            };
+            if !fresh {
+                warn!("stale OCSP response for intermediate certificate {}", i);
+                self.intermediate_ocsp.to_mut()[i] = None;
+                stale = true;
+            }
         }
+        stale
     }
 
     /// Get the certificate chain
@@ -32,4 +77,10 @@ impl<'a> ActiveCertifiedKey<'a> {
     pub(super) fn get_ocsp(&self) -> Option<&[u8]> {
         self.ocsp
     }
+
+    /// Get the OCSP responses for the intermediate certificates
+    #[inline]
+    pub(super) fn get_intermediate_ocsp(&self) -> &[Option<Vec<u8>>] {
+        &self.intermediate_ocsp
+    }
 }
@@ -0,0 +1,228 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Formatter};
+use core::time::Duration;
+
+use pki_types::UnixTime;
+
+use crate::lock::Mutex;
+use crate::sign::CertifiedKey;
+use crate::x509;
+
+/// Receives notice of server certificates that will soon expire.
+///
+/// Install one of these via [`CertExpiryWarning`] and
+/// [`ServerConfig::cert_expiry_warning`].
+///
+/// [`ServerConfig::cert_expiry_warning`]: crate::server::ServerConfig::cert_expiry_warning
+pub trait ObservesCertExpiry: Debug + Send + Sync {
+    /// Called when `certified_key` was chosen for a handshake, but its
+    /// end-entity certificate expires at `not_after`, which is within
+    /// the configured warning window.
+    ///
+    /// `server_name` is the server name indication sent by the client, if any.
+    ///
+    /// This is called synchronously during the handshake, so should return promptly.
+    fn cert_expiring(
+        &self,
+        server_name: Option<&str>,
+        certified_key: &CertifiedKey,
+        not_after: UnixTime,
+    );
+}
+
+/// Configuration for warning about server certificates that are close to expiry.
+///
+/// Each time a [`CertifiedKey`] is resolved, the `notAfter` time of its end-entity
+/// certificate is compared to the current time. If it falls within `window`, the
+/// [`ObservesCertExpiry`] is called.
+///
+/// Warnings are rate-limited per certificate: after one is delivered, no other warnings
+/// about the same end-entity certificate are delivered for the following `interval`
+/// (which defaults to one hour). This applies across all connections sharing this value.
+///
+/// The `notAfter` time is remembered for the most recently used certificates, so they
+/// are not parsed again on each handshake.
+pub struct CertExpiryWarning {
+    observer: Arc<dyn ObservesCertExpiry>,
+    window: Duration,
+    interval: Duration,
+    /// Recently seen end-entity certificates.
+    ///
+    /// First is least-used, last is most-used.
+    certs: Mutex<VecDeque<TrackedCert>>,
+}
+
+impl CertExpiryWarning {
+    /// Warn `observer` about certificates that expire within `window`.
+    #[cfg(feature = "std")]
+    pub fn new(observer: Arc<dyn ObservesCertExpiry>, window: Duration) -> Self {
+        Self {
+            observer,
+            window,
+            interval: DEFAULT_INTERVAL,
+            certs: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Warn `observer` about certificates that expire within `window`.
+    #[cfg(not(feature = "std"))]
+    pub fn new<M: crate::lock::MakeMutex>(
+        observer: Arc<dyn ObservesCertExpiry>,
+        window: Duration,
+    ) -> Self {
+        Self {
+            observer,
+            window,
+            interval: DEFAULT_INTERVAL,
+            certs: Mutex::new::<M>(VecDeque::new()),
+        }
+    }
+
+    /// Set the minimum time between two warnings about the same certificate.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub(crate) fn check(
+        &self,
+        server_name: Option<&str>,
+        certified_key: &CertifiedKey,
+        now: UnixTime,
+    ) {
+        let end_entity = match certified_key.end_entity_cert() {
+            Ok(cert) => cert.as_ref(),
+            Err(_) => return,
+        };
+
+        let not_after = match self.claim_warning(end_entity, now.as_secs()) {
+            Some(not_after) => not_after,
+            None => return,
+        };
+
+        self.observer
+            .cert_expiring(server_name, certified_key, not_after);
+    }
+
+    /// Return the `notAfter` time of `end_entity` if a warning about it is due now,
+    /// and start its rate limit.
+    fn claim_warning(&self, end_entity: &[u8], now: u64) -> Option<UnixTime> {
+        let mut certs = self.certs.lock()?;
+
+        let tracked = match certs
+            .iter()
+            .position(|tracked| tracked.end_entity == end_entity)
+        {
+            Some(index) => certs.remove(index)?,
+            None => TrackedCert {
+                end_entity: end_entity.to_vec(),
+                not_after: x509::not_after(end_entity),
+                next_warning: 0,
+            },
+        };
+
+        if certs.len() == MAX_TRACKED_CERTS {
+            certs.pop_front();
+        }
+        certs.push_back(tracked);
+        let tracked = certs.back_mut()?;
+
+        let not_after = tracked.not_after?;
+        if not_after.as_secs() > now.saturating_add(self.window.as_secs())
+            || now < tracked.next_warning
+        {
+            return None;
+        }
+
+        tracked.next_warning = now.saturating_add(self.interval.as_secs());
+        Some(not_after)
+    }
+}
+
+impl Debug for CertExpiryWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertExpiryWarning")
+            .field("observer", &self.observer)
+            .field("window", &self.window)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+/// Configuration for refusing to staple OCSP responses that have expired or soon will.
+///
+/// When a client asks for a stapled OCSP response, the `nextUpdate` time of each
+/// response held by the resolved [`CertifiedKey`] is compared to the current time.
+/// A response is stale if that time falls within `margin`, or has passed; so is a
+/// response that cannot be parsed.  Responses without a `nextUpdate` time are never stale.
+///
+/// A warning is logged for each stale response, and what happens next depends on the
+/// [`StaleOcspPolicy`].  By default the handshake fails: strict clients would reject
+/// the stale response anyway.
+#[derive(Clone, Copy, Debug)]
+pub struct OcspFreshness {
+    margin: Duration,
+    policy: StaleOcspPolicy,
+}
+
+impl OcspFreshness {
+    /// Treat OCSP responses as stale within `margin` of their `nextUpdate` time.
+    pub fn new(margin: Duration) -> Self {
+        Self {
+            margin,
+            policy: StaleOcspPolicy::FailHandshake,
+        }
+    }
+
+    /// Set what happens when a response is stale.
+    pub fn with_policy(mut self, policy: StaleOcspPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub(crate) fn policy(&self) -> StaleOcspPolicy {
+        self.policy
+    }
+
+    pub(crate) fn is_fresh(&self, response: &[u8], now: UnixTime) -> bool {
+        match x509::ocsp_next_update(response) {
+            Some(Some(next_update)) => {
+                next_update.as_secs()
+                    > now
+                        .as_secs()
+                        .saturating_add(self.margin.as_secs())
+            }
+            Some(None) => true,
+            None => false,
+        }
+    }
+}
+
+/// What a server does when a client asks for a stapled OCSP response that is stale.
+///
+/// See [`OcspFreshness`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StaleOcspPolicy {
+    /// Fail the handshake with an `internal_error` alert.
+    FailHandshake,
+
+    /// Continue the handshake without stapling the stale response.
+    OmitStaple,
+}
+
+/// A certificate seen by a [`CertExpiryWarning`].
+struct TrackedCert {
+    end_entity: Vec<u8>,
+    /// `None` if the certificate could not be parsed.
+    not_after: Option<UnixTime>,
+    /// Seconds since the UNIX epoch before which no new warning is delivered.
+    next_warning: u64,
+}
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How many certificates a [`CertExpiryWarning`] remembers.
+const MAX_TRACKED_CERTS: usize = 32;
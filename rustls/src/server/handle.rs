@@ -0,0 +1,110 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use std::sync::{Mutex, PoisonError};
+
+use crate::server::{ProducesTickets, ServerConfig};
+use crate::verify::{ClientCertVerifier, NoClientAuth};
+
+/// A [`ServerConfig`] that can be changed while the server is running.
+///
+/// Make new connections from [`ServerConfigHandle::current()`], and change it with
+/// [`ServerConfigHandle::update()`].  An update affects connections made after it;
+/// connections that already exist keep the configuration they were made with.
+///
+/// Only some settings can be changed, through [`ServerConfigUpdate`].
+#[derive(Debug)]
+pub struct ServerConfigHandle {
+    current: Mutex<Arc<ServerConfig>>,
+    client_verifier: Arc<dyn ClientCertVerifier>,
+}
+
+impl ServerConfigHandle {
+    /// Start with `config`.
+    pub fn new(config: Arc<ServerConfig>) -> Self {
+        Self {
+            client_verifier: Arc::clone(&config.verifier),
+            current: Mutex::new(config),
+        }
+    }
+
+    /// The configuration to use for a new connection.
+    pub fn current(&self) -> Arc<ServerConfig> {
+        Arc::clone(&self.lock())
+    }
+
+    /// Change the configuration for connections made from now on.
+    ///
+    /// `update` is given the current settings to change.  Concurrent updates
+    /// happen one after another, and [`ServerConfigHandle::current()`] never
+    /// returns a partly-updated configuration.
+    pub fn update(&self, update: impl FnOnce(&mut ServerConfigUpdate<'_>)) {
+        let mut current = self.lock();
+        let mut config = ServerConfig::clone(&current);
+        update(&mut ServerConfigUpdate {
+            config: &mut config,
+            client_verifier: &self.client_verifier,
+        });
+        *current = Arc::new(config);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Arc<ServerConfig>> {
+        // the configuration is only replaced once an update completes,
+        // so it is valid even if an update panicked
+        self.current
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The settings that [`ServerConfigHandle::update()`] can change.
+#[derive(Debug)]
+pub struct ServerConfigUpdate<'a> {
+    config: &'a mut ServerConfig,
+    client_verifier: &'a Arc<dyn ClientCertVerifier>,
+}
+
+impl ServerConfigUpdate<'_> {
+    /// Set [`ServerConfig::alpn_protocols`].
+    pub fn set_alpn_protocols(&mut self, alpn_protocols: Vec<Vec<u8>>) -> &mut Self {
+        self.config.alpn_protocols = alpn_protocols;
+        self
+    }
+
+    /// Set [`ServerConfig::ticketer`].
+    pub fn set_ticketer(&mut self, ticketer: Arc<dyn ProducesTickets>) -> &mut Self {
+        self.config.ticketer = ticketer;
+        self
+    }
+
+    /// Set [`ServerConfig::send_tls13_tickets`].
+    pub fn set_send_tls13_tickets(&mut self, send_tls13_tickets: usize) -> &mut Self {
+        self.config.send_tls13_tickets = send_tls13_tickets;
+        self
+    }
+
+    /// Set [`ServerConfig::ticket_lifetime`].
+    pub fn set_ticket_lifetime(&mut self, ticket_lifetime: Option<u32>) -> &mut Self {
+        self.config.ticket_lifetime = ticket_lifetime;
+        self
+    }
+
+    /// Turn client authentication on or off.
+    ///
+    /// Turning it on restores the client certificate verifier that the
+    /// [`ServerConfigHandle`] was made with, which does nothing if that
+    /// configuration did not authenticate clients.  If client authentication is
+    /// mandatory, sessions made without a client certificate (such as while it was
+    /// off) are not resumed.
+    pub fn set_client_auth(&mut self, enabled: bool) -> &mut Self {
+        self.config.verifier = match enabled {
+            true => Arc::clone(self.client_verifier),
+            false => Arc::new(NoClientAuth),
+        };
+        self
+    }
+
+    /// The settings as updated so far.
+    pub fn config(&self) -> &ServerConfig {
+        self.config
+    }
+}
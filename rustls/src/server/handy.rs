@@ -1,9 +1,11 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::fmt::Debug;
+use core::fmt::{self, Debug, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::server::ClientHello;
-use crate::{server, sign};
+use crate::enums::SignatureScheme;
+use crate::server::{ClientHello, ParsedCertificate};
+use crate::{server, sign, x509};
 
 /// Something which never stores sessions.
 #[derive(Debug)]
@@ -36,8 +38,12 @@ mod cache {
     /// An implementer of `StoresServerSessions` that stores everything
     /// in memory.  If enforces a limit on the number of stored sessions
     /// to bound memory usage.
+    ///
+    /// Use [`ServerSessionMemoryCache::new_encrypted()`] to keep the stored
+    /// sessions encrypted while they are not in use.
     pub struct ServerSessionMemoryCache {
         cache: Mutex<limited_cache::LimitedCache<Vec<u8>, Vec<u8>>>,
+        sealer: Option<Arc<dyn server::ProducesTickets>>,
     }
 
     impl ServerSessionMemoryCache {
@@ -48,6 +54,7 @@ mod cache {
         pub fn new(size: usize) -> Arc<Self> {
             Arc::new(Self {
                 cache: Mutex::new(limited_cache::LimitedCache::new(size)),
+                sealer: None,
             })
         }
 
@@ -58,12 +65,60 @@ mod cache {
         pub fn new<M: crate::lock::MakeMutex>(size: usize) -> Arc<Self> {
             Arc::new(Self {
                 cache: Mutex::new::<M>(limited_cache::LimitedCache::new(size)),
+                sealer: None,
+            })
+        }
+
+        /// Like [`ServerSessionMemoryCache::new()`], but sessions are stored
+        /// encrypted by `sealer`, and only decrypted when they are used.
+        ///
+        /// This limits the exposure of resumption secrets in memory dumps.
+        /// `sealer` should hold an ephemeral key, such as the `Ticketer` of a
+        /// crypto provider.  Sessions that `sealer` can no longer decrypt,
+        /// for example after a key rotation, are treated as absent.
+        #[cfg(feature = "std")]
+        pub fn new_encrypted(size: usize, sealer: Arc<dyn server::ProducesTickets>) -> Arc<Self> {
+            Arc::new(Self {
+                cache: Mutex::new(limited_cache::LimitedCache::new(size)),
+                sealer: Some(sealer),
             })
         }
+
+        /// Like [`ServerSessionMemoryCache::new()`], but sessions are stored
+        /// encrypted by `sealer`, and only decrypted when they are used.
+        ///
+        /// This limits the exposure of resumption secrets in memory dumps.
+        /// `sealer` should hold an ephemeral key, such as the `Ticketer` of a
+        /// crypto provider.  Sessions that `sealer` can no longer decrypt,
+        /// for example after a key rotation, are treated as absent.
+        #[cfg(not(feature = "std"))]
+        pub fn new_encrypted<M: crate::lock::MakeMutex>(
+            size: usize,
+            sealer: Arc<dyn server::ProducesTickets>,
+        ) -> Arc<Self> {
+            Arc::new(Self {
+                cache: Mutex::new::<M>(limited_cache::LimitedCache::new(size)),
+                sealer: Some(sealer),
+            })
+        }
+
+        fn open(&self, value: Vec<u8>) -> Option<Vec<u8>> {
+            match &self.sealer {
+                Some(sealer) => sealer.decrypt(&value),
+                None => Some(value),
+            }
+        }
     }
 
     impl server::StoresServerSessions for ServerSessionMemoryCache {
         fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+            let value = match &self.sealer {
+                Some(sealer) => match sealer.encrypt(&value) {
+                    Some(sealed) => sealed,
+                    None => return false,
+                },
+                None => value,
+            };
             self.cache
                 .lock()
                 .unwrap()
@@ -72,15 +127,18 @@ mod cache {
         }
 
         fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-            self.cache
+            let value = self
+                .cache
                 .lock()
                 .unwrap()
                 .get(key)
-                .cloned()
+                .cloned()?;
+            self.open(value)
         }
 
         fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
-            self.cache.lock().unwrap().remove(key)
+            let value = self.cache.lock().unwrap().remove(key)?;
+            self.open(value)
         }
 
         fn can_cache(&self) -> bool {
@@ -141,6 +199,57 @@ mod cache {
 
             assert!(count < 5);
         }
+
+        #[test]
+        fn test_serversessionmemorycache_encrypts_values() {
+            let c = ServerSessionMemoryCache::new_encrypted(4, Arc::new(XorSealer));
+            assert!(c.put(vec![0x01], vec![0x02, 0x03]));
+            assert_eq!(
+                c.cache
+                    .lock()
+                    .unwrap()
+                    .get(&[0x01][..])
+                    .cloned(),
+                Some(vec![0x5a, 0x02 ^ 0x5a, 0x03 ^ 0x5a])
+            );
+            assert_eq!(c.get(&[0x01]), Some(vec![0x02, 0x03]));
+            assert_eq!(c.take(&[0x01]), Some(vec![0x02, 0x03]));
+            assert_eq!(c.get(&[0x01]), None);
+
+            // values that no longer decrypt are absent
+            c.cache
+                .lock()
+                .unwrap()
+                .insert(vec![0x04], vec![0x05]);
+            assert_eq!(c.get(&[0x04]), None);
+        }
+
+        /// Prefixes a marker byte and XORs with it.  Not encryption!
+        #[derive(Debug)]
+        struct XorSealer;
+
+        impl server::ProducesTickets for XorSealer {
+            fn enabled(&self) -> bool {
+                true
+            }
+
+            fn lifetime(&self) -> u32 {
+                0
+            }
+
+            fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+                let mut sealed = vec![0x5a];
+                sealed.extend(plain.iter().map(|b| b ^ 0x5a));
+                Some(sealed)
+            }
+
+            fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+                match cipher.split_first() {
+                    Some((0x5a, rest)) => Some(rest.iter().map(|b| b ^ 0x5a).collect()),
+                    _ => None,
+                }
+            }
+        }
     }
 }
 
@@ -199,6 +308,244 @@ impl server::ResolvesServerCert for AlwaysResolvesChain {
     }
 }
 
+/// Something that resolves to different cert chains/keys based on the
+/// trust anchors the client says it accepts.
+///
+/// Each chain is registered under the identifier of the trust anchor it was
+/// issued by.  The client's [trust anchor identifiers] are considered in its
+/// order of preference, and the first that matches a registered chain wins.
+/// If none match, or the client sent none, the default chain (if any) is used.
+///
+/// [trust anchor identifiers]: ClientHello::trust_anchor_ids
+#[derive(Debug, Default)]
+pub struct ResolvesServerCertUsingTrustAnchors {
+    by_id: Vec<(Vec<u8>, Arc<sign::CertifiedKey>)>,
+    default: Option<Arc<sign::CertifiedKey>>,
+}
+
+impl ResolvesServerCertUsingTrustAnchors {
+    /// Create a new and empty (i.e., knows no certificates) resolver.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a new `sign::CertifiedKey` to be used when the client accepts `trust_anchor_id`.
+    ///
+    /// This replaces any chain previously added for the same identifier.
+    pub fn add(&mut self, trust_anchor_id: &[u8], ck: sign::CertifiedKey) {
+        let ck = Arc::new(ck);
+        match self
+            .by_id
+            .iter_mut()
+            .find(|(id, _)| id == trust_anchor_id)
+        {
+            Some((_, existing)) => *existing = ck,
+            None => self
+                .by_id
+                .push((trust_anchor_id.to_vec(), ck)),
+        }
+    }
+
+    /// Set the `sign::CertifiedKey` to be used when no registered trust anchor matches.
+    pub fn set_default(&mut self, ck: sign::CertifiedKey) {
+        self.default = Some(Arc::new(ck));
+    }
+}
+
+impl server::ResolvesServerCert for ResolvesServerCertUsingTrustAnchors {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<sign::CertifiedKey>> {
+        client_hello
+            .trust_anchor_ids()
+            .and_then(|mut offered| {
+                offered.find_map(|offered| {
+                    self.by_id
+                        .iter()
+                        .find(|(id, _)| id == offered)
+                        .map(|(_, ck)| Arc::clone(ck))
+                })
+            })
+            .or_else(|| self.default.clone())
+    }
+}
+
+/// Something that resolves server certificates by the client's signature schemes.
+///
+/// This holds several chains for the same identity, for example one certified
+/// with an ML-DSA key and another with a classical key, so that post-quantum
+/// certificates can be deployed to clients that support them.  Chains are considered
+/// in the order they were added, so add the chain to prefer first.
+///
+/// A chain is eligible if its key supports one of the client's signature schemes.
+/// The first eligible chain whose certificates are all signed with schemes the
+/// client accepts in certificates wins.  Those are from the client's
+/// `signature_algorithms_cert` extension, or its `signature_algorithms` extension if
+/// it sent none; self-signed certificates are not checked.  If no eligible chain
+/// meets that condition, the first eligible chain is used anyway, as
+/// [RFC 8446 section 4.4.2.2] recommends.
+///
+/// The number of times each chain was chosen is recorded, and is available from
+/// [`ResolvesServerCertUsingSignatureSchemes::selections()`].
+///
+/// [RFC 8446 section 4.4.2.2]: https://www.rfc-editor.org/rfc/rfc8446#section-4.4.2.2
+#[derive(Debug, Default)]
+pub struct ResolvesServerCertUsingSignatureSchemes {
+    chains: Vec<SchemeChain>,
+    unmatched: AtomicUsize,
+}
+
+impl ResolvesServerCertUsingSignatureSchemes {
+    /// Create a new and empty (i.e., knows no certificates) resolver.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a new `sign::CertifiedKey`, less preferred than those already added.
+    pub fn add(&mut self, ck: sign::CertifiedKey) {
+        let parsed = ck
+            .cert
+            .iter()
+            .map(|cert| ParsedCertificate::try_from(cert).ok())
+            .collect::<Vec<_>>();
+        let cert_schemes = ck
+            .cert
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| {
+                !matches!(
+                    &parsed[*i],
+                    Some(parsed) if parsed.0.issuer() == parsed.0.subject()
+                )
+            })
+            .map(|(i, cert)| {
+                // the issuer's key is needed to tell the scheme of an ECDSA signature
+                let issuer = match (&parsed[i], parsed.get(i + 1)) {
+                    (Some(cert), Some(Some(next))) if cert.0.issuer() == next.0.subject() => {
+                        Some(ck.cert[i + 1].as_ref())
+                    }
+                    _ => None,
+                };
+                x509::signature_scheme(cert, issuer)
+            })
+            .collect();
+
+        self.chains.push(SchemeChain {
+            certified_key: Arc::new(ck),
+            cert_schemes,
+            selected: AtomicUsize::new(0),
+        });
+    }
+
+    /// How many times each chain has been chosen, in the order they were added.
+    pub fn selections(&self) -> Vec<usize> {
+        self.chains
+            .iter()
+            .map(|chain| chain.selected.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// How many times no chain supported any of the client's signature schemes.
+    pub fn unmatched(&self) -> usize {
+        self.unmatched.load(Ordering::Relaxed)
+    }
+}
+
+impl server::ResolvesServerCert for ResolvesServerCertUsingSignatureSchemes {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<sign::CertifiedKey>> {
+        let accepted_in_certs = client_hello
+            .certificate_signature_schemes()
+            .unwrap_or(client_hello.signature_schemes());
+        let mut eligible = self.chains.iter().filter(|chain| {
+            chain
+                .certified_key
+                .key
+                .choose_scheme(client_hello.signature_schemes())
+                .is_some()
+        });
+
+        let first = eligible.next();
+        let chosen = first
+            .into_iter()
+            .chain(eligible)
+            .find(|chain| {
+                chain.cert_schemes.iter().all(
+                    |scheme| matches!(scheme, Some(scheme) if accepted_in_certs.contains(scheme)),
+                )
+            })
+            .or(first);
+
+        match chosen {
+            Some(chain) => {
+                chain
+                    .selected
+                    .fetch_add(1, Ordering::Relaxed);
+                Some(Arc::clone(&chain.certified_key))
+            }
+            None => {
+                self.unmatched
+                    .fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SchemeChain {
+    certified_key: Arc<sign::CertifiedKey>,
+    /// The signature scheme of each certificate that is not self-signed, if known.
+    cert_schemes: Vec<Option<SignatureScheme>>,
+    selected: AtomicUsize,
+}
+
+/// Something that resolves server certificates by calling a closure.
+///
+/// This avoids defining a type and implementing [`server::ResolvesServerCert`]
+/// for simple cases.  For example:
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use rustls::server::ResolvesServerCertUsingFn;
+/// # fn example(default: Arc<rustls::sign::CertifiedKey>) {
+/// let resolver = ResolvesServerCertUsingFn::new(move |client_hello| {
+///     match client_hello.server_name() {
+///         Some("internal.example.com") => None,
+///         _ => Some(Arc::clone(&default)),
+///     }
+/// });
+/// # }
+/// ```
+pub struct ResolvesServerCertUsingFn<F> {
+    resolve: F,
+}
+
+impl<F> ResolvesServerCertUsingFn<F>
+where
+    F: Fn(&ClientHello<'_>) -> Option<Arc<sign::CertifiedKey>> + Send + Sync,
+{
+    /// Make a resolver that calls `resolve` for each handshake.
+    ///
+    /// Return `None` from `resolve` to abort the handshake.
+    pub fn new(resolve: F) -> Self {
+        Self { resolve }
+    }
+}
+
+impl<F> server::ResolvesServerCert for ResolvesServerCertUsingFn<F>
+where
+    F: Fn(&ClientHello<'_>) -> Option<Arc<sign::CertifiedKey>> + Send + Sync,
+{
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<sign::CertifiedKey>> {
+        (self.resolve)(&client_hello)
+    }
+}
+
+impl<F> Debug for ResolvesServerCertUsingFn<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResolvesServerCertUsingFn")
+            .finish_non_exhaustive()
+    }
+}
+
 #[cfg(any(feature = "std", feature = "hashbrown"))]
 mod sni_resolver {
     use alloc::string::{String, ToString};
@@ -282,7 +629,7 @@ mod sni_resolver {
         fn test_resolvesservercertusingsni_requires_sni() {
             let rscsni = ResolvesServerCertUsingSni::new();
             assert!(rscsni
-                .resolve(ClientHello::new(&None, &[], None, &[]))
+                .resolve(ClientHello::new(&None, &[], None, None, &[], None))
                 .is_none());
         }
 
@@ -293,7 +640,7 @@ mod sni_resolver {
                 .unwrap()
                 .to_owned();
             assert!(rscsni
-                .resolve(ClientHello::new(&Some(name), &[], None, &[]))
+                .resolve(ClientHello::new(&Some(name), &[], None, None, &[], None))
                 .is_none());
         }
     }
@@ -304,10 +651,13 @@ pub use sni_resolver::ResolvesServerCertUsingSni;
 
 #[cfg(test)]
 mod tests {
+    use std::prelude::v1::*;
     use std::vec;
 
+    use pki_types::CertificateDer;
+
     use super::*;
-    use crate::server::{ProducesTickets, StoresServerSessions};
+    use crate::server::{ProducesTickets, ResolvesServerCert, StoresServerSessions};
 
     #[test]
     fn test_noserversessionstorage_drops_put() {
@@ -332,6 +682,89 @@ mod tests {
         assert_eq!(c.take(&[0x02]), None);
     }
 
+    #[test]
+    fn test_resolvesservercertusingtrustanchors_requires_default() {
+        let rscta = ResolvesServerCertUsingTrustAnchors::new();
+        assert!(rscta
+            .resolve(ClientHello::new(&None, &[], None, None, &[], None))
+            .is_none());
+    }
+
+    #[test]
+    fn test_resolvesservercertusingsignatureschemes_honours_cert_schemes() {
+        #[derive(Debug)]
+        struct AnySchemeKey;
+
+        impl sign::SigningKey for AnySchemeKey {
+            fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+                match offered.is_empty() {
+                    true => None,
+                    false => Some(Box::new(Self)),
+                }
+            }
+
+            fn algorithm(&self) -> crate::SignatureAlgorithm {
+                unimplemented!("Not meant to be called during tests")
+            }
+        }
+
+        impl sign::Signer for AnySchemeKey {
+            fn sign(&self, _message: &[u8]) -> Result<Vec<u8>, crate::Error> {
+                unimplemented!("Not meant to be called during tests")
+            }
+
+            fn scheme(&self) -> SignatureScheme {
+                unimplemented!("Not meant to be called during tests")
+            }
+        }
+
+        let mut resolver = ResolvesServerCertUsingSignatureSchemes::new();
+        for chain in [
+            &[
+                &include_bytes!("../../../test-ca/ecdsa-p256/end.der")[..],
+                &include_bytes!("../../../test-ca/ecdsa-p256/inter.der")[..],
+                &include_bytes!("../../../test-ca/ecdsa-p256/ca.der")[..],
+            ],
+            &[
+                &include_bytes!("../../../test-ca/rsa-2048/end.der")[..],
+                &include_bytes!("../../../test-ca/rsa-2048/inter.der")[..],
+                &include_bytes!("../../../test-ca/rsa-2048/ca.der")[..],
+            ],
+        ] {
+            resolver.add(sign::CertifiedKey::new(
+                chain
+                    .iter()
+                    .map(|cert| CertificateDer::from(cert.to_vec()))
+                    .collect(),
+                Arc::new(AnySchemeKey),
+            ));
+        }
+        let end_entity = |cert: &[SignatureScheme]| {
+            let schemes = [SignatureScheme::ECDSA_NISTP256_SHA256];
+            let ch = ClientHello::new(&None, &schemes, Some(cert), None, &[], None);
+            resolver
+                .resolve(ch)
+                .map(|ck| ck.cert[0].clone())
+        };
+
+        let chosen = end_entity(&[SignatureScheme::RSA_PKCS1_SHA256]).unwrap();
+        assert_eq!(
+            chosen.as_ref(),
+            include_bytes!("../../../test-ca/rsa-2048/end.der")
+        );
+        let chosen = end_entity(&[SignatureScheme::ED25519]).unwrap();
+        assert_eq!(
+            chosen.as_ref(),
+            include_bytes!("../../../test-ca/ecdsa-p256/end.der")
+        );
+        assert_eq!(resolver.selections(), vec![1, 1]);
+
+        assert!(resolver
+            .resolve(ClientHello::new(&None, &[], None, None, &[], None))
+            .is_none());
+        assert_eq!(resolver.unmatched(), 1);
+    }
+
     #[test]
     fn test_neverproducestickets_does_nothing() {
         let npt = NeverProducesTickets {};
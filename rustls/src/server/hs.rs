@@ -2,8 +2,9 @@ use alloc::borrow::ToOwned;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::cmp::Reverse;
 
-use pki_types::DnsName;
+use pki_types::{DnsName, UnixTime};
 
 use super::server_conn::ServerConnectionData;
 #[cfg(feature = "tls12")]
@@ -18,7 +19,7 @@ use crate::enums::{
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
 #[cfg(feature = "logging")]
-use crate::log::{debug, trace};
+use crate::log::trace;
 use crate::msgs::enums::{Compression, ExtensionType, NamedGroup};
 #[cfg(feature = "tls12")]
 use crate::msgs::handshake::SessionId;
@@ -29,7 +30,7 @@ use crate::msgs::handshake::{
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
 use crate::server::common::ActiveCertifiedKey;
-use crate::server::{tls13, ClientHello, ServerConfig};
+use crate::server::{tls13, AlpnPolicy, ClientHello, ServerConfig, StaleOcspPolicy};
 use crate::{suites, SupportedCipherSuite};
 
 pub(super) type NextState<'a> = Box<dyn State<ServerConnectionData> + 'a>;
@@ -40,6 +41,7 @@ pub(super) fn can_resume(
     suite: SupportedCipherSuite,
     sni: &Option<DnsName<'_>>,
     using_ems: bool,
+    client_auth_mandatory: bool,
     resumedata: &persist::ServerSessionValue,
 ) -> bool {
     // The RFCs underspecify what happens if we try to resume to
@@ -52,6 +54,20 @@ pub(super) fn can_resume(
     resumedata.cipher_suite == suite.suite()
         && (resumedata.extended_ms == using_ems || (resumedata.extended_ms && !using_ems))
         && &resumedata.sni == sni
+        // a session made while client auth was optional (or off) must not skip it
+        && (resumedata.client_cert_chain.is_some() || !client_auth_mandatory)
+}
+
+/// Whether a session retrieved from `session_storage` is young enough to resume.
+pub(super) fn stored_session_is_live(
+    config: &ServerConfig,
+    resumedata: &persist::ServerSessionValue,
+    now: UnixTime,
+) -> bool {
+    let expiry = resumedata
+        .creation_time_sec
+        .saturating_add(u64::from(config.session_lifetime));
+    now.as_secs() < expiry
 }
 
 #[derive(Default)]
@@ -94,10 +110,15 @@ impl ExtensionProcessing {
                 .find(|protocol| their_protocols.contains(&protocol.as_slice()))
                 .cloned();
             if let Some(ref selected_protocol) = cx.common.alpn_protocol {
-                debug!("Chosen ALPN protocol {:?}", selected_protocol);
+                diagnose!(
+                    cx.common,
+                    debug,
+                    "Chosen ALPN protocol {:?}",
+                    selected_protocol
+                );
                 self.exts
                     .push(ServerExtension::make_alpn(&[selected_protocol]));
-            } else if !our_protocols.is_empty() {
+            } else if !our_protocols.is_empty() && config.alpn_policy == AlpnPolicy::Strict {
                 return Err(cx.common.send_fatal_alert(
                     AlertDescription::NoApplicationProtocol,
                     Error::NoApplicationProtocol,
@@ -132,6 +153,32 @@ impl ExtensionProcessing {
             }
         }
 
+        // RFC6066 max_fragment_length.  This is not used with QUIC.
+        if let Some(max_fragment_length) = hello.max_fragment_length_extension() {
+            if max_fragment_length
+                .fragment_len()
+                .is_none()
+            {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::IllegalParameter,
+                    PeerMisbehaved::OfferedInvalidMaxFragmentLength,
+                ));
+            }
+
+            if !cx.common.is_quic() {
+                diagnose!(
+                    cx.common,
+                    debug,
+                    "Max fragment length is {:?}",
+                    max_fragment_length
+                );
+                cx.common
+                    .limit_fragment_len(max_fragment_length);
+                self.exts
+                    .push(ServerExtension::MaxFragmentLength(max_fragment_length));
+            }
+        }
+
         let for_resume = resumedata.is_some();
         // SNI
         if !for_resume && hello.sni_extension().is_some() {
@@ -166,6 +213,7 @@ impl ExtensionProcessing {
     pub(super) fn process_tls12(
         &mut self,
         config: &ServerConfig,
+        data: &ServerConnectionData,
         hello: &ClientHelloPayload,
         using_ems: bool,
     ) {
@@ -189,7 +237,7 @@ impl ExtensionProcessing {
         if hello
             .find_extension(ExtensionType::SessionTicket)
             .is_some()
-            && config.ticketer.enabled()
+            && data.ticketer(config).enabled()
         {
             self.send_ticket = true;
             self.exts
@@ -317,14 +365,17 @@ impl ExpectClientHello {
             let client_hello = ClientHello::new(
                 &cx.data.sni,
                 &sig_schemes,
+                client_hello.sigalgs_cert_extension(),
                 client_hello.alpn_extension(),
                 &client_hello.cipher_suites,
+                client_hello.trust_anchors_extension(),
             );
 
-            let certkey = self
-                .config
-                .cert_resolver
-                .resolve(client_hello);
+            let certkey = cx.common.with_user_data(|data| {
+                self.config
+                    .cert_resolver
+                    .resolve(client_hello.with_user_data(data))
+            });
 
             certkey.ok_or_else(|| {
                 cx.common.send_fatal_alert(
@@ -333,7 +384,31 @@ impl ExpectClientHello {
                 )
             })?
         };
-        let certkey = ActiveCertifiedKey::from_certified_key(&certkey);
+
+        if let Some(warning) = &self.config.cert_expiry_warning {
+            if let Ok(now) = self.config.current_time() {
+                warning.check(cx.data.get_sni_str(), &certkey, now);
+            }
+        }
+
+        let mut certkey = ActiveCertifiedKey::from_certified_key(&certkey);
+
+        if let Some(freshness) = &self.config.ocsp_freshness {
+            if client_hello
+                .find_extension(ExtensionType::StatusRequest)
+                .is_some()
+                && certkey.drop_stale_ocsp(
+                    freshness,
+                    self.config.current_time()?,
+                    version == ProtocolVersion::TLSv1_3,
+                )
+                && freshness.policy() == StaleOcspPolicy::FailHandshake
+            {
+                return Err(cx
+                    .common
+                    .send_fatal_alert(AlertDescription::InternalError, Error::StaleOcspResponse));
+            }
+        }
 
         let (suite, skxg) = self
             .choose_suite_and_kx_group(
@@ -350,7 +425,7 @@ impl ExpectClientHello {
                     .send_fatal_alert(AlertDescription::HandshakeFailure, incompat)
             })?;
 
-        debug!("decided upon suite {:?}", suite);
+        diagnose!(cx.common, debug, "decided upon suite {:?}", suite);
         cx.common.suite = Some(suite);
         cx.common.kx_state = KxState::Start(skxg);
 
@@ -376,6 +451,8 @@ impl ExpectClientHello {
             client_hello.random,
             Random::new(self.config.provider.secure_random)?,
         );
+        cx.common
+            .note_client_random(&randoms.client);
         match suite {
             SupportedCipherSuite::Tls13(suite) => tls13::CompleteClientHelloHandling {
                 config: self.config,
@@ -465,7 +542,7 @@ impl ExpectClientHello {
             return Err(PeerIncompatible::NoKxGroupsInCommon);
         }
 
-        let mut suitable_suites_iter = self
+        let suitable_suites_iter = self
             .config
             .provider
             .cipher_suites
@@ -488,18 +565,29 @@ impl ExpectClientHello {
         // proposes FFDHE4096 and we only support FFDHE2048), so we ignore that requirement here,
         // and continue to send HandshakeFailure.
 
-        let suite = if self.config.ignore_client_order {
-            suitable_suites_iter.find(|suite| client_suites.contains(&suite.suite()))
-        } else {
-            let suitable_suites = suitable_suites_iter.collect::<Vec<_>>();
-            client_suites
-                .iter()
-                .find_map(|client_suite| {
-                    suitable_suites
-                        .iter()
-                        .find(|x| *client_suite == x.suite())
+        let mut candidates = suitable_suites_iter
+            .filter(|suite| client_suites.contains(&suite.suite()))
+            .collect::<Vec<_>>();
+        if !self.config.ignore_client_order {
+            candidates.sort_by_key(|suite| {
+                client_suites
+                    .iter()
+                    .position(|client_suite| *client_suite == suite.suite())
+            });
+        }
+
+        let suite = match &self.config.cipher_suite_scorer {
+            // `min_by_key` keeps the first of equally-scored suites.
+            Some(scorer) => candidates
+                .into_iter()
+                .filter_map(|suite| {
+                    scorer
+                        .score(*suite)
+                        .map(|score| (score, suite))
                 })
-                .copied()
+                .min_by_key(|(score, _)| Reverse(*score))
+                .map(|(_, suite)| suite),
+            None => candidates.first().copied(),
         }
         .ok_or(PeerIncompatible::NoCipherSuitesInCommon)?;
 
@@ -0,0 +1,65 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use pki_types::{CertificateDer, SubjectPublicKeyInfoDer};
+
+use crate::crypto::hash;
+use crate::error::Error;
+use crate::msgs::handshake::DistinguishedName;
+use crate::webpki::ParsedCertificate;
+
+/// The identity of an authenticated client, as presented in its end-entity certificate.
+///
+/// This is obtained from [`ServerConnection::client_identity()`], and is parsed
+/// at most once per connection.
+///
+/// The fields here are extracted from a certificate that was accepted by the
+/// configured [`ClientCertVerifier`]; rustls does no further validation of them.
+///
+/// [`ServerConnection::client_identity()`]: crate::server::ServerConnection::client_identity
+/// [`ClientCertVerifier`]: crate::server::danger::ClientCertVerifier
+#[derive(Clone, Debug)]
+pub struct ClientIdentity {
+    subject: DistinguishedName,
+    dns_names: Vec<String>,
+    spki: SubjectPublicKeyInfoDer<'static>,
+}
+
+impl ClientIdentity {
+    pub(crate) fn from_end_entity(cert: &CertificateDer<'_>) -> Result<Self, Error> {
+        let parsed = ParsedCertificate::try_from(cert)?;
+        Ok(Self {
+            subject: DistinguishedName::in_sequence(parsed.0.subject()),
+            dns_names: parsed
+                .0
+                .valid_dns_names()
+                .map(ToString::to_string)
+                .collect(),
+            spki: parsed.subject_public_key_info(),
+        })
+    }
+
+    /// The certificate's subject, as a DER-encoded `Name`.
+    pub fn subject(&self) -> &DistinguishedName {
+        &self.subject
+    }
+
+    /// The DNS names in the certificate's subject alternative names extension.
+    ///
+    /// Names that are not syntactically valid DNS names (or wildcards) are omitted.
+    pub fn dns_names(&self) -> &[String] {
+        &self.dns_names
+    }
+
+    /// The certificate's DER-encoded SubjectPublicKeyInfo (SPKI).
+    pub fn subject_public_key_info(&self) -> &SubjectPublicKeyInfoDer<'static> {
+        &self.spki
+    }
+
+    /// Hash the certificate's SubjectPublicKeyInfo with `hash`.
+    ///
+    /// This is suitable for comparing against a set of public key pins.
+    pub fn spki_hash(&self, hash: &dyn hash::Hash) -> hash::Output {
+        hash.hash(self.spki.as_ref())
+    }
+}
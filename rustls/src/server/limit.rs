@@ -0,0 +1,152 @@
+use alloc::sync::Arc;
+use core::time::Duration;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+use crate::error::Error;
+use crate::server::Acceptor;
+
+/// Bounds how many handshakes a server has in progress at once.
+///
+/// Make each connection's [`Acceptor`] with [`HandshakeLimiter::acceptor()`] rather
+/// than [`Acceptor::default()`].  The handshake counts against the limit until it
+/// completes, or until its `Acceptor`, [`Accepted`] or [`ServerConnection`] is dropped.
+/// What happens to handshakes over the limit depends on the [`HandshakeLimitPolicy`].
+///
+/// Clones of a `HandshakeLimiter` share the same limit.
+///
+/// [`Accepted`]: crate::server::Accepted
+/// [`ServerConnection`]: crate::server::ServerConnection
+#[derive(Clone, Debug)]
+pub struct HandshakeLimiter {
+    shared: Arc<Shared>,
+}
+
+impl HandshakeLimiter {
+    /// Allow at most `max_in_flight` handshakes in progress at once.
+    ///
+    /// Panics if `max_in_flight` is zero.
+    pub fn new(max_in_flight: usize, policy: HandshakeLimitPolicy) -> Self {
+        assert!(max_in_flight > 0, "handshake limit must be at least one");
+        Self {
+            shared: Arc::new(Shared {
+                max_in_flight,
+                policy,
+                in_flight: Mutex::new(0),
+                released: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Return an [`Acceptor`] for a new client connection, once its handshake is
+    /// within the limit.
+    ///
+    /// If the limit is reached, this waits for another handshake to finish as the
+    /// [`HandshakeLimitPolicy`] allows, then fails with [`Error::TooManyHandshakes`].
+    /// The application should close a rejected connection without reading from it.
+    pub fn acceptor(&self) -> Result<Acceptor, Error> {
+        let mut in_flight = self.shared.lock();
+        let deadline = match self.shared.policy {
+            HandshakeLimitPolicy::QueueFor(timeout) => Instant::now().checked_add(timeout),
+            _ => None,
+        };
+
+        while *in_flight >= self.shared.max_in_flight {
+            in_flight = match (self.shared.policy, deadline) {
+                (HandshakeLimitPolicy::Reject, _) => return Err(Error::TooManyHandshakes),
+                (HandshakeLimitPolicy::QueueFor(_), Some(deadline)) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(Error::TooManyHandshakes);
+                    }
+                    self.shared
+                        .released
+                        .wait_timeout(in_flight, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                // a `QueueFor` deadline too far away to represent waits like `Queue`
+                (HandshakeLimitPolicy::Queue | HandshakeLimitPolicy::QueueFor(_), _) => self
+                    .shared
+                    .released
+                    .wait(in_flight)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+
+        Ok(self.admit(in_flight))
+    }
+
+    /// Return an [`Acceptor`] for a new client connection if its handshake is within
+    /// the limit, without waiting whatever the [`HandshakeLimitPolicy`].
+    ///
+    /// Fails with [`Error::TooManyHandshakes`] if the limit is reached.
+    pub fn try_acceptor(&self) -> Result<Acceptor, Error> {
+        let in_flight = self.shared.lock();
+        if *in_flight >= self.shared.max_in_flight {
+            return Err(Error::TooManyHandshakes);
+        }
+
+        Ok(self.admit(in_flight))
+    }
+
+    /// How many handshakes are in progress.
+    pub fn in_flight(&self) -> usize {
+        *self.shared.lock()
+    }
+
+    fn admit(&self, mut in_flight: MutexGuard<'_, usize>) -> Acceptor {
+        *in_flight += 1;
+        Acceptor::with_permit(HandshakePermit {
+            shared: Arc::clone(&self.shared),
+        })
+    }
+}
+
+/// What [`HandshakeLimiter::acceptor()`] does when the limit is reached.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeLimitPolicy {
+    /// Fail straight away.
+    Reject,
+
+    /// Block until another handshake finishes.
+    ///
+    /// This can wait indefinitely if handshakes stall; prefer
+    /// [`HandshakeLimitPolicy::QueueFor`] unless handshakes have their own timeout.
+    Queue,
+
+    /// Block until another handshake finishes, failing if none does within the
+    /// given duration.
+    QueueFor(Duration),
+}
+
+#[derive(Debug)]
+struct Shared {
+    max_in_flight: usize,
+    policy: HandshakeLimitPolicy,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        // the count is updated in one step, so is valid even if a holder panicked
+        self.in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// One handshake's place within a [`HandshakeLimiter`], given back when this is dropped.
+#[derive(Debug)]
+pub(crate) struct HandshakePermit {
+    shared: Arc<Shared>,
+}
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        *self.shared.lock() -= 1;
+        self.shared.released.notify_one();
+    }
+}
@@ -0,0 +1,320 @@
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Formatter};
+use core::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{fs, io, thread};
+
+use pki_types::CertificateDer;
+use zeroize::Zeroize;
+
+use crate::crypto::CryptoProvider;
+use crate::error::{Error, InconsistentKeys};
+use crate::lock::Mutex;
+#[cfg(feature = "logging")]
+use crate::log::warn;
+use crate::server::{ClientHello, ResolvesServerCert};
+use crate::sign::CertifiedKey;
+
+/// A [`ResolvesServerCert`] that reloads its certificate chain and private key
+/// from PEM files when they change.
+///
+/// Call [`ReloadingCertResolver::reload()`] to check the files for changes, or
+/// [`ReloadingCertResolver::watch()`] to check them periodically from a background
+/// thread.  A file is considered changed when its modification time or length changes.
+///
+/// When either file changes, both are read again and the result is validated: the
+/// certificate file must contain at least one certificate, the key file must contain
+/// a private key that [`CryptoProvider::key_provider`] can load, and the key must
+/// match the first (end-entity) certificate.  Only then is the new [`CertifiedKey`]
+/// swapped in, to be used for subsequent handshakes.  If validation fails, the
+/// previous [`CertifiedKey`] remains in use, and loading is tried again the next time
+/// either file changes.
+///
+/// To avoid needing to watch for partially-written files, replace the files
+/// atomically (for example, by writing to a temporary file and renaming it).
+pub struct ReloadingCertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: Mutex<Arc<CertifiedKey>>,
+    state: Mutex<ReloadState>,
+}
+
+impl ReloadingCertResolver {
+    /// Load a certificate chain from `cert_path` and a private key from `key_path`.
+    ///
+    /// `cert_path` must contain a sequence of PEM-encoded certificates, starting
+    /// with the end-entity certificate.  `key_path` must contain a PEM-encoded
+    /// private key, as PKCS#1, PKCS#8 or SEC1.  The key is loaded using `provider`.
+    ///
+    /// This fails if the files cannot be read or validated, as described above.
+    pub fn new(
+        cert_path: impl Into<PathBuf>,
+        key_path: impl Into<PathBuf>,
+        provider: Arc<CryptoProvider>,
+    ) -> Result<Self, Error> {
+        let cert_path = cert_path.into();
+        let key_path = key_path.into();
+        let versions = [FileVersion::of(&cert_path), FileVersion::of(&key_path)];
+        let certified_key = load(&cert_path, &key_path, &provider)?;
+
+        Ok(Self {
+            cert_path,
+            key_path,
+            provider,
+            current: Mutex::new(Arc::new(certified_key)),
+            state: Mutex::new(ReloadState {
+                versions,
+                last_error: None,
+            }),
+        })
+    }
+
+    /// Check the files for changes, and reload them if they changed.
+    ///
+    /// Returns `Ok(true)` if a new [`CertifiedKey`] is now in use, and `Ok(false)` if
+    /// neither file changed.  If the files changed but could not be loaded, the error
+    /// is returned and the previous [`CertifiedKey`] remains in use.
+    pub fn reload(&self) -> Result<bool, Error> {
+        let mut state = match self.state.lock() {
+            Some(state) => state,
+            None => return Err(Error::General("certificate reload state poisoned".into())),
+        };
+
+        let versions = [
+            FileVersion::of(&self.cert_path),
+            FileVersion::of(&self.key_path),
+        ];
+        if versions == state.versions {
+            return Ok(false);
+        }
+        state.versions = versions;
+
+        match load(&self.cert_path, &self.key_path, &self.provider) {
+            Ok(certified_key) => {
+                if let Some(mut current) = self.current.lock() {
+                    *current = Arc::new(certified_key);
+                }
+                state.last_error = None;
+                Ok(true)
+            }
+            Err(err) => {
+                state.last_error = Some(err.clone());
+                Err(err)
+            }
+        }
+    }
+
+    /// Check the files for changes every `interval`, from a new background thread.
+    ///
+    /// Errors are logged with the `logging` feature, and are available from
+    /// [`ReloadingCertResolver::last_error()`].  The thread exits once this
+    /// resolver is dropped.
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> io::Result<()> {
+        let resolver = Arc::downgrade(self);
+        thread::Builder::new()
+            .name("rustls-cert-reload".into())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                let resolver = match resolver.upgrade() {
+                    Some(resolver) => resolver,
+                    None => return,
+                };
+                if let Err(_err) = resolver.reload() {
+                    #[cfg(feature = "logging")]
+                    warn!(
+                        "failed to reload certificate from {} and {}: {}",
+                        resolver.cert_path.display(),
+                        resolver.key_path.display(),
+                        _err
+                    );
+                }
+            })
+            .map(|_| ())
+    }
+
+    /// The [`CertifiedKey`] currently in use.
+    pub fn current(&self) -> Option<Arc<CertifiedKey>> {
+        self.current
+            .lock()
+            .map(|current| Arc::clone(&current))
+    }
+
+    /// The error from the most recent reload, if it failed.
+    ///
+    /// This is cleared by a successful reload.
+    pub fn last_error(&self) -> Option<Error> {
+        self.state
+            .lock()
+            .and_then(|state| state.last_error.clone())
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.current()
+    }
+}
+
+impl Debug for ReloadingCertResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadingCertResolver")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .finish_non_exhaustive()
+    }
+}
+
+struct ReloadState {
+    versions: [Option<FileVersion>; 2],
+    last_error: Option<Error>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FileVersion {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl FileVersion {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+fn load(
+    cert_path: &Path,
+    key_path: &Path,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, Error> {
+    let cert_pem = fs::read(cert_path).map_err(|err| io_error(cert_path, err))?;
+    let cert_chain = rustls_pemfile::certs(&mut &cert_pem[..])
+        .collect::<Result<Vec<CertificateDer<'static>>, _>>()
+        .map_err(|err| io_error(cert_path, err))?;
+    if cert_chain.is_empty() {
+        return Err(Error::General(format!(
+            "no certificates found in {}",
+            cert_path.display()
+        )));
+    }
+
+    let mut key_pem = fs::read(key_path).map_err(|err| io_error(key_path, err))?;
+    let key_der = rustls_pemfile::private_key(&mut &key_pem[..]);
+    key_pem.zeroize();
+    let key_der = match key_der.map_err(|err| io_error(key_path, err))? {
+        Some(key_der) => key_der,
+        None => {
+            return Err(Error::General(format!(
+                "no private key found in {}",
+                key_path.display()
+            )))
+        }
+    };
+
+    let certified_key = CertifiedKey::new(
+        cert_chain,
+        provider
+            .key_provider
+            .load_private_key(key_der)?,
+    );
+    match certified_key.keys_match() {
+        // Don't treat unknown consistency as an error
+        Ok(()) | Err(Error::InconsistentKeys(InconsistentKeys::Unknown)) => Ok(certified_key),
+        Err(err) => Err(err),
+    }
+}
+
+fn io_error(path: &Path, err: io::Error) -> Error {
+    Error::General(format!("failed to read {}: {}", path.display(), err))
+}
+
+test_for_each_provider! {
+    use alloc::format;
+    use alloc::sync::Arc;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::ReloadingCertResolver;
+    use crate::error::{Error, InconsistentKeys};
+    use provider::default_provider;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "rustls-{}-{}-{}",
+                module_path!().replace("::", "-"),
+                name,
+                std::process::id()
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn install(&self, name: &str, contents: &[u8]) -> PathBuf {
+            // write then rename, so the modification time or length always changes
+            let path = self.0.join(name);
+            let tmp = self.0.join(format!("{name}.tmp"));
+            fs::write(&tmp, contents).unwrap();
+            fs::rename(&tmp, &path).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn reloads_changed_files_and_keeps_previous_on_error() {
+        let dir = TempDir::new(stringify!(reloads_changed_files));
+        let cert = dir.install("cert.pem", include_bytes!("../../../test-ca/ecdsa-p256/end.fullchain"));
+        let key = dir.install("key.pem", include_bytes!("../../../test-ca/ecdsa-p256/end.key"));
+
+        let resolver = ReloadingCertResolver::new(&cert, &key, Arc::new(default_provider())).unwrap();
+        let first = resolver.current().unwrap();
+        assert!(!resolver.reload().unwrap());
+
+        // a key that doesn't match the certificate: rolled back
+        dir.install("key.pem", include_bytes!("../../../test-ca/rsa-2048/end.key"));
+        assert_eq!(
+            resolver.reload(),
+            Err(Error::InconsistentKeys(InconsistentKeys::KeyMismatch))
+        );
+        assert!(Arc::ptr_eq(&first, &resolver.current().unwrap()));
+        assert!(resolver.last_error().is_some());
+
+        // an unparseable certificate file: rolled back
+        dir.install("cert.pem", b"not a certificate");
+        assert!(matches!(resolver.reload(), Err(Error::General(_))));
+        assert!(Arc::ptr_eq(&first, &resolver.current().unwrap()));
+
+        // a consistent pair: swapped in
+        dir.install("cert.pem", include_bytes!("../../../test-ca/rsa-2048/end.fullchain"));
+        assert!(resolver.reload().unwrap());
+        let second = resolver.current().unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!(second.cert.len(), 3);
+        assert_eq!(resolver.last_error(), None);
+    }
+
+    #[test]
+    fn initial_load_must_succeed() {
+        let dir = TempDir::new(stringify!(initial_load_must_succeed));
+        let cert = dir.install("cert.pem", include_bytes!("../../../test-ca/ecdsa-p256/end.fullchain"));
+        let key = dir.install("key.pem", include_bytes!("../../../test-ca/rsa-2048/end.key"));
+
+        assert!(ReloadingCertResolver::new(&cert, &key, Arc::new(default_provider())).is_err());
+        assert!(ReloadingCertResolver::new(&cert, dir.0.join("missing.pem"), Arc::new(default_provider())).is_err());
+    }
+}
@@ -1,38 +1,60 @@
 use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::any::Any;
 use core::fmt;
 use core::fmt::{Debug, Formatter};
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
+use core::time::Duration;
 #[cfg(feature = "std")]
 use std::io;
 
+#[cfg(feature = "std")]
+use once_cell::race::OnceBox;
 use pki_types::{DnsName, UnixTime};
 
+use super::expiry::{CertExpiryWarning, OcspFreshness};
 use super::hs;
+#[cfg(feature = "std")]
+use super::identity::ClientIdentity;
+#[cfg(feature = "std")]
+use super::limit::HandshakePermit;
+use crate::audit::ReceivesConnectionAudits;
 use crate::builder::ConfigBuilder;
 #[cfg(feature = "std")]
 use crate::common_state::Protocol;
-use crate::common_state::{CommonState, Side, State};
+use crate::common_state::{
+    ChoosesAlerts, CommonState, Context, MutatesHandshakeMessages, PostHandshakeLimits, Side, State,
+};
 use crate::conn::{ConnectionCommon, ConnectionCore, UnbufferedConnectionCommon};
 #[cfg(doc)]
 use crate::crypto;
 use crate::crypto::CryptoProvider;
-use crate::enums::{CipherSuite, ProtocolVersion, SignatureScheme};
+use crate::diagnostics::ReceivesDiagnostics;
+use crate::enums::{
+    CertificateCompressionAlgorithm, CipherSuite, ProtocolVersion, SignatureScheme,
+};
 use crate::error::Error;
 #[cfg(feature = "logging")]
 use crate::log::trace;
 use crate::msgs::base::Payload;
-use crate::msgs::handshake::{ClientHelloPayload, ProtocolName, ServerExtension};
+use crate::msgs::deframer::{DeframerSliceBuffer, MessageDeframer};
+use crate::msgs::handshake::{ClientHelloPayload, ProtocolName, ServerExtension, TrustAnchorId};
 use crate::msgs::message::Message;
+use crate::record_layer::{KeyLimits, RecordStrictness};
+use crate::server::ObservesTickets;
+use crate::suites::ExtractedSecrets;
 #[cfg(feature = "std")]
 use crate::time_provider::DefaultTimeProvider;
 use crate::time_provider::TimeProvider;
 use crate::vecbuf::ChunkVecBuffer;
 #[cfg(feature = "std")]
 use crate::WantsVerifier;
-use crate::{compress, sign, verify, versions, KeyLog, WantsVersions};
+use crate::{
+    alpn, compress, sign, verify, versions, KeyLog, ObservesRecords, SupportedCipherSuite,
+    WantsVersions,
+};
 
 /// A trait for the ability to store server session data.
 ///
@@ -105,6 +127,18 @@ pub trait ProducesTickets: Debug + Send + Sync {
     fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>>;
 }
 
+/// A trait for adjusting the server's choice of cipher suite.
+///
+/// Install one of these via [`ServerConfig::cipher_suite_scorer`].
+pub trait ScoresCipherSuites: Debug + Send + Sync {
+    /// Return a score for `suite`, or `None` if `suite` must not be used.
+    ///
+    /// This is called for each cipher suite that is supported by both peers
+    /// and usable for the handshake.  The one with the highest score is
+    /// chosen; ties are broken according to [`ServerConfig::ignore_client_order`].
+    fn score(&self, suite: SupportedCipherSuite) -> Option<u32>;
+}
+
 /// How to choose a certificate chain and signing key for use
 /// in server authentication.
 ///
@@ -126,8 +160,11 @@ pub trait ResolvesServerCert: Debug + Send + Sync {
 pub struct ClientHello<'a> {
     server_name: &'a Option<DnsName<'a>>,
     signature_schemes: &'a [SignatureScheme],
+    certificate_signature_schemes: Option<&'a [SignatureScheme]>,
     alpn: Option<&'a Vec<ProtocolName>>,
     cipher_suites: &'a [CipherSuite],
+    trust_anchor_ids: Option<&'a Vec<TrustAnchorId>>,
+    user_data: Option<&'a (dyn Any + Send)>,
 }
 
 impl<'a> ClientHello<'a> {
@@ -135,22 +172,34 @@ impl<'a> ClientHello<'a> {
     pub(super) fn new(
         server_name: &'a Option<DnsName<'_>>,
         signature_schemes: &'a [SignatureScheme],
+        certificate_signature_schemes: Option<&'a [SignatureScheme]>,
         alpn: Option<&'a Vec<ProtocolName>>,
         cipher_suites: &'a [CipherSuite],
+        trust_anchor_ids: Option<&'a Vec<TrustAnchorId>>,
     ) -> Self {
         trace!("sni {:?}", server_name);
         trace!("sig schemes {:?}", signature_schemes);
+        trace!("cert sig schemes {:?}", certificate_signature_schemes);
         trace!("alpn protocols {:?}", alpn);
         trace!("cipher suites {:?}", cipher_suites);
+        trace!("trust anchor ids {:?}", trust_anchor_ids);
 
         ClientHello {
             server_name,
             signature_schemes,
+            certificate_signature_schemes,
             alpn,
             cipher_suites,
+            trust_anchor_ids,
+            user_data: None,
         }
     }
 
+    pub(super) fn with_user_data(mut self, user_data: Option<&'a (dyn Any + Send)>) -> Self {
+        self.user_data = user_data;
+        self
+    }
+
     /// Get the server name indicator.
     ///
     /// Returns `None` if the client did not supply a SNI.
@@ -167,6 +216,15 @@ impl<'a> ClientHello<'a> {
         self.signature_schemes
     }
 
+    /// Get the signature schemes the client accepts in certificates, from its
+    /// `signature_algorithms_cert` extension.
+    ///
+    /// Returns `None` if the client omitted this extension, in which case
+    /// [`ClientHello::signature_schemes()`] also applies to certificates.
+    pub fn certificate_signature_schemes(&self) -> Option<&[SignatureScheme]> {
+        self.certificate_signature_schemes
+    }
+
     /// Get the ALPN protocol identifiers submitted by the client.
     ///
     /// Returns `None` if the client did not include an ALPN extension.
@@ -196,6 +254,50 @@ impl<'a> ClientHello<'a> {
     pub fn cipher_suites(&self) -> &[CipherSuite] {
         self.cipher_suites
     }
+
+    /// Get the trust anchor identifiers submitted by the client, in its order of preference.
+    ///
+    /// Returns `None` if the client did not include a `trust_anchors` extension.
+    ///
+    /// A resolver may use these to pick a certificate chain that the client
+    /// will accept; see [`ResolvesServerCertUsingTrustAnchors`] for one that does so.
+    ///
+    /// [`ResolvesServerCertUsingTrustAnchors`]: crate::server::ResolvesServerCertUsingTrustAnchors
+    pub fn trust_anchor_ids(&self) -> Option<impl Iterator<Item = &'a [u8]>> {
+        self.trust_anchor_ids
+            .map(|ids| ids.iter().map(|id| id.as_ref()))
+    }
+
+    /// The application data attached to the connection with [`CommonState::set_user_data()`].
+    ///
+    /// This is always `None` from [`Accepted::client_hello()`], as data can only be
+    /// attached to the connection it becomes.
+    ///
+    /// [`CommonState::set_user_data()`]: crate::CommonState::set_user_data
+    pub fn user_data(&self) -> Option<&'a (dyn Any + Send)> {
+        self.user_data
+    }
+}
+
+/// How a server responds to a client whose ALPN protocols are all unsupported.
+///
+/// See [`ServerConfig::alpn_policy`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlpnPolicy {
+    /// Fail the handshake with a `no_application_protocol` alert, as
+    /// [RFC 7301](https://www.rfc-editor.org/rfc/rfc7301#section-3.2) specifies.
+    #[default]
+    Strict,
+
+    /// Continue the handshake without negotiating a protocol.
+    ///
+    /// [`CommonState::alpn_protocol()`] will return `None`, and the application
+    /// can decide what to do.  This suits ecosystems where clients offer ALPN
+    /// opportunistically, and servers that predate ALPN support.
+    ///
+    /// [`CommonState::alpn_protocol()`]: crate::CommonState::alpn_protocol
+    Lenient,
 }
 
 /// Common configuration for a set of server sessions.
@@ -210,17 +312,44 @@ impl<'a> ClientHello<'a> {
 /// # Defaults
 ///
 /// * [`ServerConfig::max_fragment_size`]: the default is `None` (meaning 16kB).
+/// * [`ServerConfig::max_handshake_size`]: the default is `None` (meaning 64kB).
+/// * [`ServerConfig::max_decompressed_cert_size`]: the default is `None` (meaning the
+///   same as `max_handshake_size`).
+/// * [`ServerConfig::record_strictness`]: the default follows the TLS specifications.
+/// * [`ServerConfig::key_limits`]: the default is the cipher suite's limit, and updating keys at it.
+/// * [`ServerConfig::post_handshake_limits`]: 32 key updates and one renegotiation request.
 /// * [`ServerConfig::session_storage`]: if the `std` feature is enabled, the default stores 256
 ///   sessions in memory. If the `std` feature is not enabled, the default is to not store any
 ///   sessions. In a no-std context, by enabling the `hashbrown` feature you may provide your
 ///   own `session_storage` using [`ServerSessionMemoryCache`] and a `crate::lock::MakeMutex`
 ///   implementation.
+/// * [`ServerConfig::session_lifetime`]: sessions in `session_storage` can be resumed for 24 hours.
 /// * [`ServerConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
+/// * [`ServerConfig::alpn_policy`]: the default is [`AlpnPolicy::Strict`] -- the handshake
+///   fails if no ALPN protocol is shared with a client that offers some.
 /// * [`ServerConfig::key_log`]: key material is not logged.
+/// * [`ServerConfig::record_observer`]: the default is `None` -- records are not observed.
+/// * [`ServerConfig::alert_policy`]: the default is `None` -- rustls chooses which alert to send.
+/// * [`ServerConfig::diagnostics`]: the default is `None` -- diagnostics are only logged.
+/// * [`ServerConfig::audit_sink`]: the default is `None` -- connections are not audited.
+/// * [`ServerConfig::silent_rejection`]: the default is false -- rejected clients are sent an alert.
 /// * [`ServerConfig::send_tls13_tickets`]: 4 tickets are sent.
+/// * [`ServerConfig::ticket_lifetime`]: the default is `None` -- the lifetime of the
+///   session is advertised.
+/// * [`ServerConfig::ticket_max_early_data_size`]: the default is `None` -- tickets
+///   allow [`ServerConfig::max_early_data_size`] bytes of early data.
+/// * [`ServerConfig::ticket_observer`]: the default is `None` -- tickets are not observed.
 /// * [`ServerConfig::cert_compressors`]: depends on the crate features, see [`compress::default_cert_compressors()`].
 /// * [`ServerConfig::cert_compression_cache`]: caches the most recently used 4 compressions
+/// * [`ServerConfig::cert_compression_policy`]: the default is `None` -- the first of
+///   [`ServerConfig::cert_compressors`] offered by the client is used.
 /// * [`ServerConfig::cert_decompressors`]: depends on the crate features, see [`compress::default_cert_decompressors()`].
+/// * [`ServerConfig::ignore_client_order`]: the default is `false` -- the client's preference
+///   order is honoured, with no [`ServerConfig::cipher_suite_scorer`].
+/// * [`ServerConfig::cert_expiry_warning`]: the default is `None` -- no warnings are delivered.
+/// * [`ServerConfig::ocsp_freshness`]: the default is `None` -- OCSP responses are
+///   stapled whatever their `nextUpdate` time.
+/// * [`ServerConfig::handshake_timeout`]: the default is `None` -- handshakes are not timed out.
 ///
 /// [`RootCertStore`]: crate::RootCertStore
 /// [`ServerSessionMemoryCache`]: crate::server::handy::ServerSessionMemoryCache
@@ -232,8 +361,19 @@ pub struct ServerConfig {
     /// Ignore the client's ciphersuite order. Instead,
     /// choose the top ciphersuite in the server list
     /// which is supported by the client.
+    ///
+    /// The default is `false`: the client's preference order is honoured.
     pub ignore_client_order: bool,
 
+    /// How to score the cipher suites supported by both peers.
+    ///
+    /// If set, this takes precedence over the preference order chosen by
+    /// [`ServerConfig::ignore_client_order`], which then only breaks ties.
+    /// See [`ScoresCipherSuites`].
+    ///
+    /// The default is `None`: suites are chosen only by preference order.
+    pub cipher_suite_scorer: Option<Arc<dyn ScoresCipherSuites>>,
+
     /// The maximum size of plaintext input to be emitted in a single TLS record.
     /// A value of None is equivalent to the [TLS maximum] of 16 kB.
     ///
@@ -247,9 +387,70 @@ pub struct ServerConfig {
     /// [ServerConnection::new]: crate::server::ServerConnection::new
     pub max_fragment_size: Option<usize>,
 
+    /// The maximum size of a handshake message we will accept from the peer.
+    /// A value of None is equivalent to 64 kB.
+    ///
+    /// TLS allows handshake messages of up to 16 MB, which must be buffered
+    /// whole, so this limits the memory a peer can make us use.  It may need
+    /// raising if peers send large certificate chains, for example with
+    /// post-quantum signatures.
+    ///
+    /// rustls enforces a minimum of 16 kB for this field.
+    /// Out of range values are reported as errors from [ServerConnection::new].
+    ///
+    /// [ServerConnection::new]: crate::server::ServerConnection::new
+    pub max_handshake_size: Option<usize>,
+
+    /// The largest decompressed size we will accept for the client's certificate
+    /// chain, as declared in its `CompressedCertificate` message.
+    /// A value of None is equivalent to [`ServerConfig::max_handshake_size`].
+    ///
+    /// Larger declared sizes are rejected before anything is decompressed.
+    /// This only applies to TLS1.3 connections using certificate compression.
+    pub max_decompressed_cert_size: Option<usize>,
+
+    /// How strictly the framing of records received from the client is checked.
+    ///
+    /// The default follows the TLS specifications.  See [`RecordStrictness`].
+    ///
+    /// A [`ClientHello`] read by an [`Acceptor`] is always checked with the default.
+    ///
+    /// [`ClientHello`]: crate::server::ClientHello
+    pub record_strictness: RecordStrictness,
+
+    /// Limits on how many records are encrypted with one key.
+    ///
+    /// The default is the cipher suite's limit, after which TLS1.3 keys are updated.
+    /// See [`KeyLimits`].
+    pub key_limits: KeyLimits,
+
+    /// Limits on the messages the client may send after the handshake.
+    ///
+    /// See [`PostHandshakeLimits`] for the defaults.
+    pub post_handshake_limits: PostHandshakeLimits,
+
     /// How to store client sessions.
+    ///
+    /// This is the session cache used for TLS1.2 session ID resumption, and for
+    /// TLS1.3 stateful resumption (when [`ServerConfig::ticketer`] is disabled).
+    /// Use [`NoServerSessionStorage`] to disable these, and
+    /// [`ServerSessionMemoryCache::new()`] to choose the cache's capacity.
+    ///
+    /// Ticket-based resumption is controlled separately by [`ServerConfig::ticketer`].
+    ///
+    /// [`NoServerSessionStorage`]: crate::server::NoServerSessionStorage
+    /// [`ServerSessionMemoryCache::new()`]: crate::server::handy::ServerSessionMemoryCache::new
     pub session_storage: Arc<dyn StoresServerSessions + Send + Sync>,
 
+    /// How long, in seconds, a session in [`ServerConfig::session_storage`] can be resumed.
+    ///
+    /// Sessions older than this are not resumed, and a full handshake is done instead.
+    /// This does not apply to sessions resumed using a ticket from [`ServerConfig::ticketer`];
+    /// that lifetime is controlled by the [`ProducesTickets`] implementation.
+    ///
+    /// The default is 24 hours.
+    pub session_lifetime: u32,
+
     /// How to produce tickets.
     pub ticketer: Arc<dyn ProducesTickets>,
 
@@ -262,6 +463,15 @@ pub struct ServerConfig {
     /// If empty we don't do ALPN at all.
     pub alpn_protocols: Vec<Vec<u8>>,
 
+    /// What to do when the client offers ALPN protocols, but none of them
+    /// are in [`ServerConfig::alpn_protocols`].
+    ///
+    /// This has no effect if [`ServerConfig::alpn_protocols`] is empty.  QUIC
+    /// connections always fail, as required by RFC 9001.
+    ///
+    /// The default is [`AlpnPolicy::Strict`].
+    pub alpn_policy: AlpnPolicy,
+
     /// Supported protocol versions, in no particular order.
     /// The default is all supported versions.
     pub(super) versions: versions::EnabledVersions,
@@ -269,10 +479,65 @@ pub struct ServerConfig {
     /// How to verify client certificates.
     pub(super) verifier: Arc<dyn verify::ClientCertVerifier>,
 
+    /// Alters outgoing handshake messages; see [`DangerousServerConfig::set_handshake_mutator`].
+    ///
+    /// [`DangerousServerConfig::set_handshake_mutator`]: danger::DangerousServerConfig::set_handshake_mutator
+    pub(crate) handshake_mutator: Option<Arc<dyn MutatesHandshakeMessages>>,
+
     /// How to output key material for debugging.  The default
     /// does nothing.
     pub key_log: Arc<dyn KeyLog>,
 
+    /// Receives metadata about each TLS record this connection sends or receives,
+    /// for debugging and accounting.  See [`ObservesRecords`].
+    ///
+    /// When connections are made by an [`Acceptor`], the client's initial
+    /// `ClientHello` is received before this is known, so it is not observed.
+    ///
+    /// The default is `None`: records are not observed.
+    ///
+    /// [`ObservesRecords`]: crate::ObservesRecords
+    pub record_observer: Option<Arc<dyn ObservesRecords>>,
+
+    /// Chooses which alert is sent when a connection fails.  See [`ChoosesAlerts`].
+    ///
+    /// The default is `None`: rustls chooses.
+    ///
+    /// [`ChoosesAlerts`]: crate::ChoosesAlerts
+    pub alert_policy: Option<Arc<dyn ChoosesAlerts>>,
+
+    /// Receives diagnostics about connections.  See [`ReceivesDiagnostics`].
+    ///
+    /// This works whether or not the `logging` feature is enabled.
+    ///
+    /// The default is `None`: diagnostics are only logged.
+    ///
+    /// [`ReceivesDiagnostics`]: crate::ReceivesDiagnostics
+    pub diagnostics: Option<Arc<dyn ReceivesDiagnostics>>,
+
+    /// Receives a summary of each connection when it closes.
+    /// See [`ReceivesConnectionAudits`].
+    ///
+    /// The default is `None`: connections are not audited.
+    ///
+    /// [`ReceivesConnectionAudits`]: crate::ReceivesConnectionAudits
+    pub audit_sink: Option<Arc<dyn ReceivesConnectionAudits>>,
+
+    /// Whether to reject a `ClientHello` without sending an alert.
+    ///
+    /// If this is set, and a connection fails before the server has sent any
+    /// message (for example, because no certificate is available for the requested
+    /// SNI name, or the client offers nothing the server supports), no alert is
+    /// sent: the application should then just close the transport connection.  This
+    /// reveals less about the server to scanners.
+    ///
+    /// Errors detected later in the handshake are reported to the client as usual.
+    /// [`Acceptor`] users can also reject connections silently, by dropping the
+    /// [`Accepted`] instead of calling [`Accepted::into_connection()`].
+    ///
+    /// The default is false.
+    pub silent_rejection: bool,
+
     /// Allows traffic secrets to be extracted after the handshake,
     /// e.g. for kTLS setup.
     pub enable_secret_extraction: bool,
@@ -323,24 +588,84 @@ pub struct ServerConfig {
     /// do any resumption.
     pub send_tls13_tickets: usize,
 
-    /// If set to `true`, requires the client to support the extended
-    /// master secret extraction method defined in [RFC 7627].
+    /// The lifetime, in seconds, advertised in tickets sent to clients.
+    ///
+    /// If `None`, the advertised lifetime is [`ProducesTickets::lifetime()`] for
+    /// tickets from [`ServerConfig::ticketer`], or [`ServerConfig::session_lifetime`]
+    /// for sessions in [`ServerConfig::session_storage`].  A longer value than
+    /// those is reduced to them, as the session could not be resumed that late.
+    ///
+    /// Shortening the lifetime makes clients discard their tickets sooner, which
+    /// limits how long after a handshake it can be resumed.
+    ///
+    /// The default is `None`.
+    pub ticket_lifetime: Option<u32>,
+
+    /// Whether TLS1.3 tickets have a random `ticket_age_add`.
+    ///
+    /// See [`DangerousServerConfig::set_randomize_ticket_age_add`].
+    ///
+    /// [`DangerousServerConfig::set_randomize_ticket_age_add`]: danger::DangerousServerConfig::set_randomize_ticket_age_add
+    pub(super) randomize_ticket_age_add: bool,
+
+    /// The maximum amount of early data that TLS1.3 tickets allow clients to send.
+    ///
+    /// If `None`, this is [`ServerConfig::max_early_data_size`].  A larger value
+    /// than that is reduced to it, and early data is never offered in tickets if
+    /// [`ServerConfig::max_early_data_size`] is 0.  Specify `Some(0)` to accept
+    /// early data for tickets issued by other configs, without issuing any
+    /// new tickets that allow it.
     ///
-    /// The default is `true` if the "fips" crate feature is enabled,
-    /// `false` otherwise.
+    /// The default is `None`.
+    pub ticket_max_early_data_size: Option<u32>,
+
+    /// Receives notice of tickets being issued and redeemed.  See [`ObservesTickets`].
+    ///
+    /// The default is `None`.
+    pub ticket_observer: Option<Arc<dyn ObservesTickets>>,
+
+    /// Whether to require the client to support the extended master secret
+    /// extraction method defined in RFC 7627.
     ///
-    /// It must be set to `true` to meet FIPS requirement mentioned in section
-    /// **D.Q Transition of the TLS 1.2 KDF to Support the Extended Master
-    /// Secret** from [FIPS 140-3 IG.pdf].
+    /// See [`DangerousServerConfig::set_require_ems`].
     ///
-    /// [RFC 7627]: https://datatracker.ietf.org/doc/html/rfc7627
-    /// [FIPS 140-3 IG.pdf]: https://csrc.nist.gov/csrc/media/Projects/cryptographic-module-validation-program/documents/fips%20140-3/FIPS%20140-3%20IG.pdf
+    /// [`DangerousServerConfig::set_require_ems`]: danger::DangerousServerConfig::set_require_ems
     #[cfg(feature = "tls12")]
-    pub require_ems: bool,
+    pub(super) require_ems: bool,
 
     /// Provides the current system time
     pub time_provider: Arc<dyn TimeProvider>,
 
+    /// How long the handshake may take before it is abandoned.
+    ///
+    /// This is measured with [`ServerConfig::time_provider`] from when the connection is
+    /// created.  Once exceeded, processing of received data fails with
+    /// [`Error::HandshakeTimedOut`]; this is checked only when received data is
+    /// processed, so a peer that sends nothing must be handled by the application.
+    ///
+    /// The default is `None`: handshakes may take any amount of time.
+    ///
+    /// [`Error::HandshakeTimedOut`]: crate::Error::HandshakeTimedOut
+    pub handshake_timeout: Option<Duration>,
+
+    /// Whether to reply to a close_notify alert from the peer with our own.
+    ///
+    /// If `true`, receiving close_notify shuts down both directions of the
+    /// connection: a close_notify is queued in response, and further writes fail.
+    /// TLS1.2 ([RFC 5246 section 7.2.1]) requires this behaviour.
+    ///
+    /// If `false`, the connection is half-closed: the application may continue
+    /// to write data, and is responsible for eventually calling
+    /// [`CommonState::send_close_notify()`].  This is permitted by TLS1.3
+    /// ([RFC 8446 section 6.1]) and is needed by proxies.
+    ///
+    /// The default is `false`.
+    ///
+    /// [RFC 5246 section 7.2.1]: https://www.rfc-editor.org/rfc/rfc5246#section-7.2.1
+    /// [RFC 8446 section 6.1]: https://www.rfc-editor.org/rfc/rfc8446#section-6.1
+    /// [`CommonState::send_close_notify()`]: crate::CommonState::send_close_notify
+    pub respond_to_close_notify: bool,
+
     /// How to compress the server's certificate chain.
     ///
     /// If a client supports this extension, and advertises support
@@ -359,6 +684,13 @@ pub struct ServerConfig {
     /// a cache that does no caching.
     pub cert_compression_cache: Arc<compress::CompressionCache>,
 
+    /// Chooses whether and how to compress the certificate for each connection.
+    ///
+    /// When this is `None`, the first of [`ServerConfig::cert_compressors`] offered
+    /// by the client is used.  Like compression itself, this is ignored for TLS1.2
+    /// connections.
+    pub cert_compression_policy: Option<Arc<dyn compress::ChoosesCertCompression>>,
+
     /// How to decompress the clients's certificate chain.
     ///
     /// If this is non-empty, the [RFC8779] certificate compression
@@ -371,6 +703,19 @@ pub struct ServerConfig {
     ///
     /// [RFC8779]: https://datatracker.ietf.org/doc/rfc8879/
     pub cert_decompressors: Vec<&'static dyn compress::CertDecompressor>,
+
+    /// How to warn about server certificates that will soon expire.
+    ///
+    /// If set, the end-entity certificate of each resolved [`sign::CertifiedKey`]
+    /// is checked against the current time, and its observer called if it expires within
+    /// the configured window.  See [`CertExpiryWarning`] for details.
+    pub cert_expiry_warning: Option<Arc<CertExpiryWarning>>,
+
+    /// How to treat stapled OCSP responses that have expired or will soon.
+    ///
+    /// If set, the responses held by each resolved [`sign::CertifiedKey`] are checked
+    /// against the current time before being stapled.  See [`OcspFreshness`] for details.
+    pub ocsp_freshness: Option<OcspFreshness>,
 }
 
 impl ServerConfig {
@@ -476,11 +821,109 @@ impl ServerConfig {
         }
     }
 
+    /// Set [`ServerConfig::alpn_protocols`], after checking them with [`alpn::validate()`].
+    ///
+    /// ```
+    /// # fn example(config: rustls::ServerConfig) -> Result<rustls::ServerConfig, rustls::Error> {
+    /// use rustls::alpn;
+    /// let config = config.with_alpn_protocols([alpn::H2, alpn::HTTP_1_1])?;
+    /// # Ok(config)
+    /// # }
+    /// ```
+    pub fn with_alpn_protocols(
+        mut self,
+        protocols: impl IntoIterator<Item = impl Into<Vec<u8>>>,
+    ) -> Result<Self, Error> {
+        let protocols = protocols
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<_>>();
+        alpn::validate(&protocols)?;
+        self.alpn_protocols = protocols;
+        Ok(self)
+    }
+
+    /// Set [`ServerConfig::ticket_lifetime`].
+    pub fn with_ticket_lifetime(mut self, seconds: u32) -> Self {
+        self.ticket_lifetime = Some(seconds);
+        self
+    }
+
+    /// Set [`ServerConfig::ticket_max_early_data_size`].
+    pub fn with_ticket_max_early_data_size(mut self, size: u32) -> Self {
+        self.ticket_max_early_data_size = Some(size);
+        self
+    }
+
+    /// Resume sessions using tickets from `ticketer`, as [`ServerConfig::ticketer`].
+    ///
+    /// This is independent of [`ServerConfig::session_storage`]: for TLS1.2, session
+    /// ID resumption continues to work alongside tickets.
+    pub fn with_session_tickets(mut self, ticketer: Arc<dyn ProducesTickets>) -> Self {
+        self.ticketer = ticketer;
+        self
+    }
+
+    /// Stop issuing and accepting tickets from [`ServerConfig::ticketer`].
+    ///
+    /// This avoids having to manage ticket encryption keys, while keeping the
+    /// resumption provided by [`ServerConfig::session_storage`]: TLS1.2 session
+    /// ID resumption, and TLS1.3 tickets that refer to stored sessions.  Tickets
+    /// offered by TLS1.2 clients are ignored, so that they can resume using a
+    /// session ID instead.
+    pub fn without_session_tickets(mut self) -> Self {
+        self.ticketer = Arc::new(super::handy::NeverProducesTickets {});
+        self
+    }
+
+    /// Store sessions in `storage`, as [`ServerConfig::session_storage`].
+    ///
+    /// This is independent of [`ServerConfig::ticketer`].
+    pub fn with_session_storage(
+        mut self,
+        storage: Arc<dyn StoresServerSessions + Send + Sync>,
+    ) -> Self {
+        self.session_storage = storage;
+        self
+    }
+
+    /// Stop storing sessions in [`ServerConfig::session_storage`].
+    ///
+    /// This disables TLS1.2 session ID resumption, and TLS1.3 resumption other than
+    /// with tickets from [`ServerConfig::ticketer`].
+    pub fn without_session_storage(mut self) -> Self {
+        self.session_storage = Arc::new(super::handy::NoServerSessionStorage {});
+        self
+    }
+
+    /// Prefer the certificate compression algorithms in `preference`, in that order.
+    ///
+    /// This reorders [`ServerConfig::cert_compressors`] and
+    /// [`ServerConfig::cert_decompressors`] so that those for the algorithms in
+    /// `preference` come first; the rest keep their order after them.  Unless
+    /// [`ServerConfig::cert_compression_policy`] chooses otherwise, the server's
+    /// certificate is compressed with the first of its compressors that the
+    /// client supports, and its decompressors are offered to the client in order.
+    pub fn with_cert_compression_preference(
+        mut self,
+        preference: &[CertificateCompressionAlgorithm],
+    ) -> Self {
+        compress::prefer_algorithms(&mut self.cert_compressors, preference, |c| c.algorithm());
+        compress::prefer_algorithms(&mut self.cert_decompressors, preference, |d| d.algorithm());
+        self
+    }
+
     /// Return the crypto provider used to construct this client configuration.
     pub fn crypto_provider(&self) -> &Arc<CryptoProvider> {
         &self.provider
     }
 
+    /// Access configuration options whose use is dangerous and requires
+    /// extra care.
+    pub fn dangerous(&mut self) -> danger::DangerousServerConfig<'_> {
+        danger::DangerousServerConfig { cfg: self }
+    }
+
     /// We support a given TLS version if it's quoted in the configured
     /// versions *and* at least one ciphersuite for this version is
     /// also configured.
@@ -501,6 +944,14 @@ impl ServerConfig {
             .any(|cs| cs.usable_for_protocol(proto))
     }
 
+    /// The lifetime to advertise for a ticket that can be resumed for `limit` seconds.
+    pub(super) fn advertised_ticket_lifetime(&self, limit: u32) -> u32 {
+        match self.ticket_lifetime {
+            Some(lifetime) => lifetime.min(limit),
+            None => limit,
+        }
+    }
+
     pub(super) fn current_time(&self) -> Result<UnixTime, Error> {
         self.time_provider
             .current_time()
@@ -508,6 +959,82 @@ impl ServerConfig {
     }
 }
 
+/// Container for unsafe APIs
+pub(super) mod danger {
+    use alloc::sync::Arc;
+
+    use super::ServerConfig;
+    use crate::common_state::MutatesHandshakeMessages;
+
+    /// Accessor for dangerous configuration options.
+    #[derive(Debug)]
+    pub struct DangerousServerConfig<'a> {
+        /// The underlying ServerConfig
+        pub cfg: &'a mut ServerConfig,
+    }
+
+    impl<'a> DangerousServerConfig<'a> {
+        /// Alters each handshake message sent by connections using this config.
+        ///
+        /// See [`MutatesHandshakeMessages`].  This is intended for negative testing
+        /// and protocol experiments: connections using it are unlikely to interoperate.
+        pub fn set_handshake_mutator(&mut self, mutator: Arc<dyn MutatesHandshakeMessages>) {
+            self.cfg.handshake_mutator = Some(mutator);
+        }
+
+        /// Sets whether the client must support the extended master secret extraction
+        /// method defined in [RFC 7627].  TLS1.2 handshakes with peers that do not
+        /// support it are aborted.
+        ///
+        /// The default is `true`.
+        ///
+        /// Setting this to `false` is dangerous: it permits legacy TLS1.2 peers that do
+        /// not support extended master secret, exposing those connections to the triple
+        /// handshake attack.  Use [`CommonState::extended_master_secret()`] to find out
+        /// whether a given connection negotiated it.
+        ///
+        /// It must be set to `true` to meet FIPS requirement mentioned in section
+        /// **D.Q Transition of the TLS 1.2 KDF to Support the Extended Master
+        /// Secret** from [FIPS 140-3 IG.pdf]: see [`ServerConfig::fips()`].
+        ///
+        /// [RFC 7627]: https://datatracker.ietf.org/doc/html/rfc7627
+        /// [FIPS 140-3 IG.pdf]: https://csrc.nist.gov/csrc/media/Projects/cryptographic-module-validation-program/documents/fips%20140-3/FIPS%20140-3%20IG.pdf
+        /// [`CommonState::extended_master_secret()`]: crate::CommonState::extended_master_secret
+        #[cfg(feature = "tls12")]
+        pub fn set_require_ems(&mut self, require_ems: bool) {
+            self.cfg.require_ems = require_ems;
+        }
+
+        /// Sets whether TLS1.3 tickets have a random `ticket_age_add`.  If `false`,
+        /// zero is used instead.
+        ///
+        /// The default is `true`.
+        ///
+        /// Setting this to `false` is dangerous: clients add this value to the age of
+        /// a ticket when offering it, so without it passive observers can use the age
+        /// to link the new connection to the one the ticket was issued on.
+        /// [RFC 8446 section 4.6.1] requires it to be random.
+        ///
+        /// [RFC 8446 section 4.6.1]: https://datatracker.ietf.org/doc/html/rfc8446#section-4.6.1
+        pub fn set_randomize_ticket_age_add(&mut self, randomize_ticket_age_add: bool) {
+            self.cfg.randomize_ticket_age_add = randomize_ticket_age_add;
+        }
+
+        /// Accept any number of TLS1.3 `KeyUpdate` messages from the peer, rather
+        /// than the limit set by [`PostHandshakeLimits::max_key_updates()`].
+        ///
+        /// This is dangerous: each `KeyUpdate` costs a key derivation, so a peer may
+        /// send a stream of them to use up resources.
+        ///
+        /// [`PostHandshakeLimits::max_key_updates()`]: crate::PostHandshakeLimits::max_key_updates
+        pub fn set_unlimited_key_updates(&mut self) {
+            self.cfg
+                .post_handshake_limits
+                .remove_key_update_limit();
+        }
+    }
+}
+
 #[cfg(feature = "std")]
 mod connection {
     use alloc::boxed::Box;
@@ -518,13 +1045,17 @@ mod connection {
     use core::ops::{Deref, DerefMut};
     use std::io;
 
-    use super::{Accepted, Accepting, EarlyDataState, ServerConfig, ServerConnectionData};
+    use super::{
+        Accepted, Accepting, ClientIdentity, EarlyDataState, HandshakePermit, ServerConfig,
+        ServerConnectionData,
+    };
     use crate::common_state::{CommonState, Context, Side};
     use crate::conn::{ConnectionCommon, ConnectionCore};
     use crate::error::Error;
     use crate::server::hs;
     use crate::suites::ExtractedSecrets;
     use crate::vecbuf::ChunkVecBuffer;
+    use crate::verify::ClientCertDetails;
 
     /// Allows reading of early data in resumed TLS1.3 connections.
     ///
@@ -564,14 +1095,26 @@ mod connection {
         /// Make a new ServerConnection.  `config` controls how
         /// we behave in the TLS protocol.
         pub fn new(config: Arc<ServerConfig>) -> Result<Self, Error> {
-            let mut common = CommonState::new(Side::Server);
-            common.set_max_fragment_size(config.max_fragment_size)?;
-            common.enable_secret_extraction = config.enable_secret_extraction;
             Ok(Self {
                 inner: ConnectionCommon::from(ConnectionCore::for_server(config, Vec::new())?),
             })
         }
 
+        /// Make a new ServerConnection that does not resume a previous session,
+        /// nor store or send tickets for this session.
+        ///
+        /// This is equivalent to [`ServerConnection::new()`] with a `config` where
+        /// [`ServerConfig::session_storage`] is [`NoServerSessionStorage`],
+        /// [`ServerConfig::ticketer`] never produces tickets and
+        /// [`ServerConfig::send_tls13_tickets`] is zero.
+        ///
+        /// [`NoServerSessionStorage`]: crate::server::NoServerSessionStorage
+        pub fn new_without_resumption(config: Arc<ServerConfig>) -> Result<Self, Error> {
+            let mut conn = Self::new(config)?;
+            conn.inner.core.data.without_resumption = true;
+            Ok(conn)
+        }
+
         /// Retrieves the server name, if any, used to select the certificate and
         /// private key.
         ///
@@ -591,6 +1134,38 @@ mod connection {
             self.inner.core.get_sni_str()
         }
 
+        /// Retrieves the identity of the authenticated client, if any.
+        ///
+        /// This returns `None` until the client's certificate has been received and
+        /// verified, and it will always be `None` if the client did not authenticate.
+        /// It is also `None` if the client's end-entity certificate cannot be parsed,
+        /// which can only happen if a custom [`ClientCertVerifier`] accepted it.
+        ///
+        /// The certificate is parsed on the first call, and the result is retained
+        /// for the lifetime of the connection.
+        ///
+        /// [`ClientCertVerifier`]: crate::server::danger::ClientCertVerifier
+        pub fn client_identity(&self) -> Option<&ClientIdentity> {
+            self.inner.core.client_identity()
+        }
+
+        /// Retrieves how the client's certificate chain was verified, if known.
+        ///
+        /// This is `None` unless the configured [`ClientCertVerifier`] reported
+        /// [`ClientCertDetails`] for a certificate received on this connection;
+        /// [`WebPkiClientVerifier`] always does.  It is also `None` for resumed
+        /// sessions, whose certificates are not verified again.
+        ///
+        /// [`ClientCertVerifier`]: crate::server::danger::ClientCertVerifier
+        /// [`WebPkiClientVerifier`]: crate::server::WebPkiClientVerifier
+        pub fn client_cert_details(&self) -> Option<&ClientCertDetails> {
+            self.inner
+                .core
+                .data
+                .client_cert_details
+                .as_deref()
+        }
+
         /// Application-controlled portion of the resumption ticket supplied by the client, if any.
         ///
         /// Recovered from the prior session's `set_resumption_data`. Integrity is guaranteed by rustls.
@@ -690,7 +1265,10 @@ mod connection {
     /// and don't want to use the blocking interface provided by
     /// [`super::ResolvesServerCert`].
     ///
-    /// Create an Acceptor with [`Acceptor::default()`].
+    /// Create an Acceptor with [`Acceptor::default()`], or with
+    /// [`HandshakeLimiter::acceptor()`] to bound how many handshakes are in progress at once.
+    ///
+    /// [`HandshakeLimiter::acceptor()`]: crate::server::HandshakeLimiter::acceptor
     ///
     /// # Example
     ///
@@ -733,20 +1311,28 @@ mod connection {
     impl Default for Acceptor {
         /// Return an empty Acceptor, ready to receive bytes from a new client connection.
         fn default() -> Self {
+            Self::with_data(ServerConnectionData::default())
+        }
+    }
+
+    impl Acceptor {
+        /// Return an empty Acceptor whose handshake holds `permit` until it completes.
+        pub(crate) fn with_permit(permit: HandshakePermit) -> Self {
+            Self::with_data(ServerConnectionData {
+                handshake_permit: Some(permit),
+                ..ServerConnectionData::default()
+            })
+        }
+
+        fn with_data(data: ServerConnectionData) -> Self {
             Self {
                 inner: Some(
-                    ConnectionCore::new(
-                        Box::new(Accepting),
-                        ServerConnectionData::default(),
-                        CommonState::new(Side::Server),
-                    )
-                    .into(),
+                    ConnectionCore::new(Box::new(Accepting), data, CommonState::new(Side::Server))
+                        .into(),
                 ),
             }
         }
-    }
 
-    impl Acceptor {
         /// Read TLS content from `rd`.
         ///
         /// Returns an error if this `Acceptor` has already yielded an [`Accepted`]. For more details,
@@ -874,6 +1460,12 @@ impl UnbufferedServerConnection {
             )?),
         })
     }
+
+    /// Extract secrets, so they can be used when configuring kTLS, for example.
+    /// Should be used with care as it exposes secret key material.
+    pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
+        self.inner.dangerous_extract_secrets()
+    }
 }
 
 impl Deref for UnbufferedServerConnection {
@@ -908,13 +1500,7 @@ pub struct Accepted {
 impl Accepted {
     /// Get the [`ClientHello`] for this connection.
     pub fn client_hello(&self) -> ClientHello<'_> {
-        let payload = Self::client_hello_payload(&self.message);
-        ClientHello::new(
-            &self.connection.core.data.sni,
-            &self.sig_schemes,
-            payload.alpn_extension(),
-            &payload.cipher_suites,
-        )
+        Self::client_hello_for(&self.connection.core.data, &self.message, &self.sig_schemes)
     }
 
     /// Convert the [`Accepted`] into a [`ServerConnection`].
@@ -929,21 +1515,22 @@ impl Accepted {
     ) -> Result<ServerConnection, (Error, AcceptedAlert)> {
         if let Err(err) = self
             .connection
-            .set_max_fragment_size(config.max_fragment_size)
+            .core
+            .message_deframer
+            .set_max_handshake_size(config.max_handshake_size)
         {
-            // We have a connection here, but it won't contain an alert since the error
-            // is with the fragment size configured in the `ServerConfig`.
-            return Err((err, AcceptedAlert::empty()));
+            return Err((err, AcceptedAlert::from(self.connection)));
         }
+        self.connection
+            .core
+            .message_deframer
+            .set_record_strictness(config.record_strictness);
 
-        self.connection.enable_secret_extraction = config.enable_secret_extraction;
-
-        let state = hs::ExpectClientHello::new(config, Vec::new());
         let mut cx = hs::ServerContext::from(&mut self.connection);
-
-        let ch = Self::client_hello_payload(&self.message);
-        let new = match state.with_certified_key(self.sig_schemes, ch, &self.message, &mut cx) {
+        let new = match Self::continue_handshake(config, &self.message, self.sig_schemes, &mut cx) {
             Ok(new) => new,
+            // If the error is with the fragment size or timeout configured in the
+            // `ServerConfig`, the connection won't contain an alert.
             Err(err) => return Err((err, AcceptedAlert::from(self.connection))),
         };
 
@@ -953,6 +1540,36 @@ impl Accepted {
         })
     }
 
+    fn client_hello_for<'a>(
+        data: &'a ServerConnectionData,
+        message: &'a Message<'_>,
+        sig_schemes: &'a [SignatureScheme],
+    ) -> ClientHello<'a> {
+        let payload = Self::client_hello_payload(message);
+        ClientHello::new(
+            &data.sni,
+            sig_schemes,
+            payload.sigalgs_cert_extension(),
+            payload.alpn_extension(),
+            &payload.cipher_suites,
+            payload.trust_anchors_extension(),
+        )
+    }
+
+    /// Apply `config` to an accepted connection, and process its `ClientHello` in `message`.
+    fn continue_handshake(
+        config: Arc<ServerConfig>,
+        message: &Message<'_>,
+        sig_schemes: Vec<SignatureScheme>,
+        cx: &mut hs::ServerContext<'_>,
+    ) -> hs::NextStateOrError<'static> {
+        cx.common.apply_server_config(&config)?;
+
+        let state = hs::ExpectClientHello::new(config, Vec::new());
+        let ch = Self::client_hello_payload(message);
+        state.with_certified_key(sig_schemes, ch, message, cx)
+    }
+
     fn client_hello_payload<'a>(message: &'a Message<'_>) -> &'a ClientHelloPayload {
         match &message.payload {
             crate::msgs::message::MessagePayload::Handshake { parsed, .. } => match &parsed.payload
@@ -971,6 +1588,186 @@ impl Debug for Accepted {
     }
 }
 
+/// Unbuffered version of [`Acceptor`].
+///
+/// This reads a `ClientHello` from caller-provided buffers, so that a [`ServerConfig`]
+/// can be chosen based on its contents (for example, its SNI or ALPN extensions) before
+/// the handshake continues in an [`UnbufferedServerConnection`].
+///
+/// See the [`crate::unbuffered`] module docs for more details.
+///
+/// [`Acceptor`]: crate::server::Acceptor
+pub struct UnbufferedAcceptor {
+    inner: Option<ConnectionCore<ServerConnectionData>>,
+}
+
+impl Default for UnbufferedAcceptor {
+    /// Return an empty `UnbufferedAcceptor`, ready to receive bytes from a new client connection.
+    fn default() -> Self {
+        Self {
+            inner: Some(ConnectionCore::new(
+                Box::new(Accepting),
+                ServerConnectionData::default(),
+                CommonState::new(Side::Server),
+            )),
+        }
+    }
+}
+
+impl UnbufferedAcceptor {
+    /// Look for a complete `ClientHello` message in `incoming_tls`.
+    ///
+    /// Whatever the outcome, the caller must discard [`UnbufferedAcceptStatus::discard`]
+    /// bytes from the front of `incoming_tls` before calling this function again.
+    ///
+    /// Do not call this function again after it has yielded an [`UnbufferedAccepted`] or
+    /// an error.
+    pub fn accept(&mut self, incoming_tls: &mut [u8]) -> UnbufferedAcceptStatus {
+        let mut core = match self.inner.take() {
+            Some(core) => core,
+            None => {
+                return UnbufferedAcceptStatus {
+                    discard: 0,
+                    state: Err((
+                        Error::General("Acceptor polled after completion".into()),
+                        Vec::new(),
+                    )),
+                };
+            }
+        };
+
+        let mut buffer = DeframerSliceBuffer::new(incoming_tls);
+        let res = core.first_handshake_message(&mut buffer);
+        let discard = buffer.pending_discard();
+
+        let message = match res {
+            Ok(Some(msg)) => msg,
+            Ok(None) => {
+                self.inner = Some(core);
+                return UnbufferedAcceptStatus {
+                    discard,
+                    state: Ok(None),
+                };
+            }
+            Err(err) => {
+                return UnbufferedAcceptStatus {
+                    discard,
+                    state: Err((err, take_alert(&mut core))),
+                };
+            }
+        };
+
+        let mut cx = Context {
+            common: &mut core.common_state,
+            data: &mut core.data,
+            sendable_plaintext: None,
+        };
+        let state = match hs::process_client_hello(&message, false, &mut cx) {
+            Ok((_, sig_schemes)) => Ok(Some(UnbufferedAccepted {
+                core,
+                message,
+                sig_schemes,
+            })),
+            Err(err) => Err((err, take_alert(&mut core))),
+        };
+
+        UnbufferedAcceptStatus { discard, state }
+    }
+}
+
+impl Debug for UnbufferedAcceptor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnbufferedAcceptor")
+            .finish()
+    }
+}
+
+/// The result of [`UnbufferedAcceptor::accept()`].
+#[must_use]
+#[derive(Debug)]
+pub struct UnbufferedAcceptStatus {
+    /// Number of bytes to discard from the front of `incoming_tls`.
+    pub discard: usize,
+
+    /// `Ok(None)` if the complete `ClientHello` has not yet been received: receive more
+    /// data and call [`UnbufferedAcceptor::accept()`] again.
+    ///
+    /// `Ok(Some(accepted))` once it has been received: call
+    /// [`UnbufferedAccepted::into_connection()`] to continue.
+    ///
+    /// `Err((err, alert))` if an error occurred. `alert` holds TLS data (possibly
+    /// empty) that should be sent to the client before closing the connection.
+    pub state: Result<Option<UnbufferedAccepted>, (Error, Vec<u8>)>,
+}
+
+/// Represents a `ClientHello` message received through the [`UnbufferedAcceptor`].
+///
+/// Contains the state required to resume the connection through
+/// [`UnbufferedAccepted::into_connection()`].
+pub struct UnbufferedAccepted {
+    core: ConnectionCore<ServerConnectionData>,
+    message: Message<'static>,
+    sig_schemes: Vec<SignatureScheme>,
+}
+
+impl UnbufferedAccepted {
+    /// Get the [`ClientHello`] for this connection.
+    pub fn client_hello(&self) -> ClientHello<'_> {
+        Accepted::client_hello_for(&self.core.data, &self.message, &self.sig_schemes)
+    }
+
+    /// Convert the [`UnbufferedAccepted`] into an [`UnbufferedServerConnection`].
+    ///
+    /// Returns an error if configuration-dependent validation of the received `ClientHello`
+    /// message fails, along with TLS data (possibly empty) that should be sent to the client
+    /// before closing the connection.
+    pub fn into_connection(
+        mut self,
+        config: Arc<ServerConfig>,
+    ) -> Result<UnbufferedServerConnection, (Error, Vec<u8>)> {
+        if let Err(err) = self
+            .core
+            .message_deframer
+            .set_max_handshake_size(config.max_handshake_size)
+        {
+            return Err((err, take_alert(&mut self.core)));
+        }
+        self.core
+            .message_deframer
+            .set_record_strictness(config.record_strictness);
+
+        let mut cx = Context {
+            common: &mut self.core.common_state,
+            data: &mut self.core.data,
+            sendable_plaintext: None,
+        };
+        match Accepted::continue_handshake(config, &self.message, self.sig_schemes, &mut cx) {
+            Ok(new) => self.core.state = Ok(new),
+            Err(err) => return Err((err, take_alert(&mut self.core))),
+        }
+
+        Ok(UnbufferedServerConnection {
+            inner: UnbufferedConnectionCommon::from(self.core),
+        })
+    }
+}
+
+impl Debug for UnbufferedAccepted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnbufferedAccepted")
+            .finish()
+    }
+}
+
+/// Collect any TLS data (ie, an alert) queued for sending on `core`.
+fn take_alert(core: &mut ConnectionCore<ServerConnectionData>) -> Vec<u8> {
+    let mut alert = Vec::new();
+    while let Some(chunk) = core.common_state.sendable_tls.pop() {
+        alert.extend_from_slice(&chunk);
+    }
+    alert
+}
+
 struct Accepting;
 
 impl State<ServerConnectionData> for Accepting {
@@ -1071,13 +1868,17 @@ impl ConnectionCore<ServerConnectionData> {
         extra_exts: Vec<ServerExtension>,
     ) -> Result<Self, Error> {
         let mut common = CommonState::new(Side::Server);
-        common.set_max_fragment_size(config.max_fragment_size)?;
-        common.enable_secret_extraction = config.enable_secret_extraction;
-        Ok(Self::new(
+        common.apply_server_config(&config)?;
+        let mut message_deframer = MessageDeframer::default();
+        message_deframer.set_max_handshake_size(config.max_handshake_size)?;
+        message_deframer.set_record_strictness(config.record_strictness);
+        let mut core = Self::new(
             Box::new(hs::ExpectClientHello::new(config, extra_exts)),
             ServerConnectionData::default(),
             common,
-        ))
+        );
+        core.message_deframer = message_deframer;
+        Ok(core)
     }
 
     #[cfg(feature = "std")]
@@ -1093,6 +1894,18 @@ impl ConnectionCore<ServerConnectionData> {
     pub(crate) fn get_sni_str(&self) -> Option<&str> {
         self.data.get_sni_str()
     }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn client_identity(&self) -> Option<&ClientIdentity> {
+        let end_entity = self
+            .common_state
+            .peer_certificates()?
+            .first()?;
+        self.data
+            .client_identity
+            .get_or_try_init(|| ClientIdentity::from_end_entity(end_entity).map(Box::new))
+            .ok()
+    }
 }
 
 /// State associated with a server connection.
@@ -1102,13 +1915,54 @@ pub struct ServerConnectionData {
     pub(super) received_resumption_data: Option<Vec<u8>>,
     pub(super) resumption_data: Vec<u8>,
     pub(super) early_data: EarlyDataState,
+    pub(super) client_cert_details: Option<Box<verify::ClientCertDetails>>,
+    #[cfg(feature = "std")]
+    client_identity: OnceBox<ClientIdentity>,
+    #[cfg(feature = "std")]
+    handshake_permit: Option<HandshakePermit>,
+    /// Set by [`ServerConnection::new_without_resumption()`].
+    pub(super) without_resumption: bool,
 }
 
 impl ServerConnectionData {
-    #[cfg(feature = "std")]
     pub(super) fn get_sni_str(&self) -> Option<&str> {
         self.sni.as_ref().map(AsRef::as_ref)
     }
+
+    /// The session storage used by this connection.
+    pub(super) fn session_storage<'a>(
+        &self,
+        config: &'a ServerConfig,
+    ) -> &'a dyn StoresServerSessions {
+        match self.without_resumption {
+            true => &super::handy::NoServerSessionStorage {},
+            false => config.session_storage.as_ref(),
+        }
+    }
+
+    /// The ticketer used by this connection.
+    pub(super) fn ticketer<'a>(&self, config: &'a ServerConfig) -> &'a dyn ProducesTickets {
+        match self.without_resumption {
+            true => &super::handy::NeverProducesTickets {},
+            false => config.ticketer.as_ref(),
+        }
+    }
+
+    /// The number of TLS1.3 tickets this connection sends after the handshake.
+    pub(super) fn send_tls13_tickets(&self, config: &ServerConfig) -> usize {
+        match self.without_resumption {
+            true => 0,
+            false => config.send_tls13_tickets,
+        }
+    }
+
+    /// Called when the handshake completes, to let another one start.
+    pub(super) fn handshake_finished(&mut self) {
+        #[cfg(feature = "std")]
+        {
+            self.handshake_permit = None;
+        }
+    }
 }
 
 impl crate::conn::SideData for ServerConnectionData {}
@@ -0,0 +1,87 @@
+use core::fmt::Debug;
+use core::time::Duration;
+
+use pki_types::UnixTime;
+
+use crate::enums::{CipherSuite, ProtocolVersion};
+use crate::msgs::persist::ServerSessionValue;
+
+/// Receives notice of session tickets that a server issues and redeems.
+///
+/// Install one of these as [`ServerConfig::ticket_observer`].  This is for keeping
+/// external session-token infrastructure (such as metrics, audit logs or revocation
+/// lists) in step with rustls.  To use a custom ticket format, implement
+/// [`ProducesTickets`] instead.
+///
+/// All methods are called synchronously during the handshake, so should return promptly.
+///
+/// [`ServerConfig::ticket_observer`]: crate::server::ServerConfig::ticket_observer
+/// [`ProducesTickets`]: crate::server::ProducesTickets
+pub trait ObservesTickets: Debug + Send + Sync {
+    /// Called when a ticket is sent to a client.
+    fn ticket_issued(&self, _ticket: &TicketInfo<'_>) {}
+
+    /// Called when a client resumes a session using a ticket.
+    ///
+    /// Tickets that cannot be used, for example because they do not decrypt or
+    /// have expired, are not reported.
+    fn ticket_redeemed(&self, _ticket: &TicketInfo<'_>) {}
+}
+
+/// A ticket reported to [`ObservesTickets`], and the session it resumes.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug)]
+pub struct TicketInfo<'a> {
+    /// The length of the ticket, as sent to the client.
+    pub ticket_len: usize,
+
+    /// Whether the ticket came from [`ServerConfig::ticketer`], rather than being
+    /// a key into [`ServerConfig::session_storage`].
+    ///
+    /// [`ServerConfig::ticketer`]: crate::server::ServerConfig::ticketer
+    /// [`ServerConfig::session_storage`]: crate::server::ServerConfig::session_storage
+    pub stateless: bool,
+
+    /// The lifetime advertised to the client when the ticket was issued.
+    ///
+    /// This is only known by [`ObservesTickets::ticket_issued()`].
+    pub lifetime: Option<Duration>,
+
+    /// When the session was first established.
+    pub created: UnixTime,
+
+    /// The protocol version of the session.
+    pub version: ProtocolVersion,
+
+    /// The cipher suite of the session.
+    pub cipher_suite: CipherSuite,
+
+    /// The server name indication sent by the client, if any.
+    pub server_name: Option<&'a str>,
+
+    /// The ALPN protocol agreed for the session, if any.
+    pub alpn_protocol: Option<&'a [u8]>,
+}
+
+impl<'a> TicketInfo<'a> {
+    pub(crate) fn new(
+        ticket_len: usize,
+        stateless: bool,
+        lifetime: Option<u32>,
+        session: &'a ServerSessionValue,
+    ) -> Self {
+        Self {
+            ticket_len,
+            stateless,
+            lifetime: lifetime.map(|secs| Duration::from_secs(secs.into())),
+            created: UnixTime::since_unix_epoch(Duration::from_secs(session.creation_time_sec)),
+            version: session.version,
+            cipher_suite: session.cipher_suite,
+            server_name: session.sni.as_ref().map(AsRef::as_ref),
+            alpn_protocol: session
+                .alpn
+                .as_ref()
+                .map(|alpn| &alpn.0[..]),
+        }
+    }
+}
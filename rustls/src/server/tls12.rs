@@ -10,16 +10,17 @@ use subtle::ConstantTimeEq;
 
 use super::common::ActiveCertifiedKey;
 use super::hs::{self, ServerContext};
-use super::server_conn::{ProducesTickets, ServerConfig, ServerConnectionData};
+use super::server_conn::{ServerConfig, ServerConnectionData};
+use super::tickets::TicketInfo;
 use crate::check::inappropriate_message;
-use crate::common_state::{CommonState, HandshakeKind, Side, State};
+use crate::common_state::{CommonState, HandshakeKind, ResumptionKind, Side, State};
 use crate::conn::ConnectionRandoms;
 use crate::crypto::ActiveKeyExchange;
 use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHash;
 #[cfg(feature = "logging")]
-use crate::log::{debug, trace};
+use crate::log::trace;
 use crate::msgs::base::Payload;
 use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::msgs::codec::Codec;
@@ -76,6 +77,7 @@ mod client_hello {
 
             if client_hello.ems_support_offered() {
                 self.using_ems = true;
+                cx.common.using_ems = true;
             } else if self.config.require_ems {
                 return Err(cx.common.send_fatal_alert(
                     AlertDescription::HandshakeFailure,
@@ -111,15 +113,22 @@ mod client_hello {
             // 2. receiving a sessionid that is in our cache
             //
             // If we receive a ticket, the sessionid won't be in our
-            // cache, so don't check.
+            // cache, so don't check.  Tickets are ignored if we don't
+            // issue them, so that session ID resumption still works for
+            // clients holding tickets from before they were disabled.
             //
             // If either works, we end up with a ServerConnectionValue
             // which is passed to start_resumption and concludes
             // our handling of the ClientHello.
             //
+            let now = self.config.current_time()?;
+            let ticketer = cx.data.ticketer(&self.config);
+            let session_storage = cx.data.session_storage(&self.config);
             let mut ticket_received = false;
+            let mut ticket_len = 0;
             let resume_data = client_hello
                 .ticket_extension()
+                .filter(|_| ticketer.enabled())
                 .and_then(|ticket_ext| match ticket_ext {
                     ClientExtension::SessionTicket(ClientSessionTicket::Offer(ticket)) => {
                         Some(ticket)
@@ -128,13 +137,11 @@ mod client_hello {
                 })
                 .and_then(|ticket| {
                     ticket_received = true;
-                    debug!("Ticket received");
-                    let data = self
-                        .config
-                        .ticketer
-                        .decrypt(ticket.bytes());
+                    ticket_len = ticket.bytes().len();
+                    diagnose!(cx.common, debug, "Ticket received");
+                    let data = ticketer.decrypt(ticket.bytes());
                     if data.is_none() {
-                        debug!("Ticket didn't decrypt");
+                        diagnose!(cx.common, debug, "Ticket didn't decrypt");
                     }
                     data
                 })
@@ -145,17 +152,39 @@ mod client_hello {
                         return None;
                     }
 
-                    self.config
-                        .session_storage
-                        .get(&client_hello.session_id.get_encoding())
+                    session_storage.get(&client_hello.session_id.get_encoding())
                 })
                 .and_then(|x| persist::ServerSessionValue::read_bytes(&x).ok())
                 .filter(|resumedata| {
-                    hs::can_resume(self.suite.into(), &cx.data.sni, self.using_ems, resumedata)
+                    (ticket_received || hs::stored_session_is_live(&self.config, resumedata, now))
+                        && hs::can_resume(
+                            self.suite.into(),
+                            &cx.data.sni,
+                            self.using_ems,
+                            self.config
+                                .verifier
+                                .client_auth_mandatory(),
+                            resumedata,
+                        )
                 });
 
             if let Some(data) = resume_data {
-                return self.start_resumption(cx, client_hello, &client_hello.session_id, data);
+                if let (true, Some(observer)) = (ticket_received, &self.config.ticket_observer) {
+                    observer.ticket_redeemed(&TicketInfo::new(ticket_len, true, None, &data));
+                }
+
+                let kind = if ticket_received {
+                    ResumptionKind::Ticket
+                } else {
+                    ResumptionKind::SessionId
+                };
+                return self.start_resumption(
+                    cx,
+                    client_hello,
+                    &client_hello.session_id,
+                    data,
+                    kind,
+                );
             }
 
             // Now we have chosen a ciphersuite, we can make kx decisions.
@@ -186,7 +215,7 @@ mod client_hello {
             let mut ocsp_response = server_key.get_ocsp();
 
             // If we're not offered a ticket or a potential session ID, allocate a session ID.
-            if !self.config.session_storage.can_cache() {
+            if !session_storage.can_cache() {
                 self.session_id = SessionId::empty();
             } else if self.session_id.is_empty() && !ticket_received {
                 self.session_id = SessionId::random(self.config.provider.secure_random)?;
@@ -255,8 +284,9 @@ mod client_hello {
             client_hello: &ClientHelloPayload,
             id: &SessionId,
             resumedata: persist::ServerSessionValue,
+            kind: ResumptionKind,
         ) -> hs::NextStateOrError<'static> {
-            debug!("Resuming connection");
+            diagnose!(cx.common, debug, "Resuming connection");
 
             if resumedata.extended_ms && !self.using_ems {
                 return Err(cx.common.send_fatal_alert(
@@ -294,6 +324,7 @@ mod client_hello {
                 .start_encryption_tls12(&secrets, Side::Server);
             cx.common.peer_certificates = resumedata.client_cert_chain;
             cx.common.handshake_kind = Some(HandshakeKind::Resumed);
+            cx.common.resumption_kind = Some(kind);
 
             if self.send_ticket {
                 let now = self.config.current_time()?;
@@ -303,7 +334,7 @@ mod client_hello {
                     &mut self.transcript,
                     self.using_ems,
                     cx,
-                    &*self.config.ticketer,
+                    &self.config,
                     now,
                 )?;
             }
@@ -340,7 +371,7 @@ mod client_hello {
     ) -> Result<bool, Error> {
         let mut ep = hs::ExtensionProcessing::new();
         ep.process_common(config, cx, ocsp_response, hello, resumedata, extra_exts)?;
-        ep.process_tls12(config, hello, using_ems);
+        ep.process_tls12(config, cx.data, hello, using_ems);
 
         let sh = Message {
             version: ProtocolVersion::TLSv1_2,
@@ -532,20 +563,29 @@ impl State<ServerConnectionData> for ExpectCertificate {
                 ));
             }
             None => {
-                debug!("client auth requested but no certificate supplied");
+                diagnose!(
+                    cx.common,
+                    debug,
+                    "client auth requested but no certificate supplied"
+                );
                 self.transcript.abandon_client_auth();
                 None
             }
             Some((end_entity, intermediates)) => {
                 let now = self.config.current_time()?;
 
-                self.config
-                    .verifier
-                    .verify_client_cert(end_entity, intermediates, now)
+                let verified = cx
+                    .common
+                    .with_user_data(|data| {
+                        self.config
+                            .verifier
+                            .verify_client_cert_with_user_data(end_entity, intermediates, now, data)
+                    })
                     .map_err(|err| {
                         cx.common
                             .send_cert_verify_error_alert(err)
                     })?;
+                cx.data.client_cert_details = verified.into_details();
 
                 Some(cert_chain)
             }
@@ -833,17 +873,20 @@ fn emit_ticket(
     transcript: &mut HandshakeHash,
     using_ems: bool,
     cx: &mut ServerContext<'_>,
-    ticketer: &dyn ProducesTickets,
+    config: &ServerConfig,
     now: UnixTime,
 ) -> Result<(), Error> {
-    let plain = get_server_connection_value_tls12(secrets, using_ems, cx, now).get_encoding();
+    let value = get_server_connection_value_tls12(secrets, using_ems, cx, now);
+    let plain = value.get_encoding();
 
     // If we can't produce a ticket for some reason, we can't
     // report an error. Send an empty one.
+    let ticketer = cx.data.ticketer(config);
     let ticket = ticketer
         .encrypt(&plain)
         .unwrap_or_default();
-    let ticket_lifetime = ticketer.lifetime();
+    let ticket_lifetime = config.advertised_ticket_lifetime(ticketer.lifetime());
+    let ticket_len = ticket.len();
 
     let m = Message {
         version: ProtocolVersion::TLSv1_2,
@@ -858,6 +901,16 @@ fn emit_ticket(
 
     transcript.add_message(&m);
     cx.common.send_msg(m, false);
+    cx.common
+        .observe(|observer, data| observer.ticket_sent(data));
+    if let (true, Some(observer)) = (ticket_len > 0, &config.ticket_observer) {
+        observer.ticket_issued(&TicketInfo::new(
+            ticket_len,
+            true,
+            Some(ticket_lifetime),
+            &value,
+        ));
+    }
     Ok(())
 }
 
@@ -934,14 +987,14 @@ impl State<ServerConnectionData> for ExpectFinished {
 
             let value = get_server_connection_value_tls12(&self.secrets, self.using_ems, cx, now);
 
-            let worked = self
-                .config
-                .session_storage
+            let worked = cx
+                .data
+                .session_storage(&self.config)
                 .put(self.session_id.get_encoding(), value.get_encoding());
             if worked {
-                debug!("Session saved");
+                diagnose!(cx.common, debug, "Session saved");
             } else {
-                debug!("Session not saved");
+                diagnose!(cx.common, debug, "Session not saved");
             }
         }
 
@@ -955,7 +1008,7 @@ impl State<ServerConnectionData> for ExpectFinished {
                     &mut self.transcript,
                     self.using_ems,
                     cx,
-                    &*self.config.ticketer,
+                    &self.config,
                     now,
                 )?;
             }
@@ -968,6 +1021,7 @@ impl State<ServerConnectionData> for ExpectFinished {
 
         cx.common
             .start_traffic(&mut cx.sendable_plaintext);
+        cx.data.handshake_finished();
         Ok(Box::new(ExpectTraffic {
             secrets: self.secrets,
             _fin_verified,
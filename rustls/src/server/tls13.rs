@@ -1,6 +1,5 @@
 use alloc::boxed::Box;
 use alloc::sync::Arc;
-use alloc::vec;
 use alloc::vec::Vec;
 
 pub(super) use client_hello::CompleteClientHelloHandling;
@@ -10,22 +9,23 @@ use subtle::ConstantTimeEq;
 use super::hs::{self, HandshakeHashOrBuffer, ServerContext};
 use super::server_conn::ServerConnectionData;
 use crate::check::{inappropriate_handshake_message, inappropriate_message};
-use crate::common_state::{CommonState, HandshakeKind, Protocol, Side, State};
+use crate::common_state::{CommonState, HandshakeKind, Protocol, ResumptionKind, Side, State};
 use crate::conn::ConnectionRandoms;
 use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
 use crate::error::{Error, InvalidMessage, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHash;
 #[cfg(feature = "logging")]
-use crate::log::{debug, trace, warn};
+use crate::log::trace;
 use crate::msgs::codec::{Codec, Reader};
-use crate::msgs::enums::KeyUpdateRequest;
+use crate::msgs::deframer::DEFAULT_MAX_HANDSHAKE_SIZE;
+use crate::msgs::enums::{ExtensionType, KeyUpdateRequest};
 use crate::msgs::handshake::{
     CertificateChain, CertificatePayloadTls13, HandshakeMessagePayload, HandshakePayload,
     NewSessionTicketExtension, NewSessionTicketPayloadTls13, CERTIFICATE_MAX_SIZE_LIMIT,
 };
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
-use crate::server::ServerConfig;
+use crate::server::{ServerConfig, TicketInfo};
 use crate::suites::PartiallyExtractedSecrets;
 use crate::tls13::key_schedule::{KeyScheduleTraffic, KeyScheduleTrafficWithClientFinishedPending};
 use crate::tls13::{
@@ -35,7 +35,7 @@ use crate::{compress, rand, verify};
 
 mod client_hello {
     use super::*;
-    use crate::compress::CertCompressor;
+    use crate::compress::{CertCompressionChoice, CertCompressionOffer, CertCompressionStats};
     use crate::crypto::SupportedKxGroup;
     use crate::enums::SignatureScheme;
     use crate::msgs::base::{Payload, PayloadU8};
@@ -111,18 +111,20 @@ mod client_hello {
 
         fn attempt_tls13_ticket_decryption(
             &mut self,
+            data: &ServerConnectionData,
             ticket: &[u8],
+            now: UnixTime,
         ) -> Option<persist::ServerSessionValue> {
-            if self.config.ticketer.enabled() {
-                self.config
-                    .ticketer
+            let ticketer = data.ticketer(&self.config);
+            if ticketer.enabled() {
+                ticketer
                     .decrypt(ticket)
                     .and_then(|plain| persist::ServerSessionValue::read_bytes(&plain).ok())
             } else {
-                self.config
-                    .session_storage
+                data.session_storage(&self.config)
                     .take(ticket)
                     .and_then(|plain| persist::ServerSessionValue::read_bytes(&plain).ok())
+                    .filter(|resumedata| hs::stored_session_is_live(&self.config, resumedata, now))
             }
         }
 
@@ -167,17 +169,6 @@ mod client_hello {
                 ));
             }
 
-            let cert_compressor = client_hello
-                .certificate_compression_extension()
-                .and_then(|offered|
-                    // prefer server order when choosing a compression: the client's
-                    // extension here does not denote any preference.
-                    self.config
-                        .cert_compressors
-                        .iter()
-                        .find(|compressor| offered.contains(&compressor.algorithm()))
-                        .cloned());
-
             let early_data_requested = client_hello.early_data_extension_offered();
 
             // EarlyData extension is illegal in second ClientHello
@@ -244,6 +235,7 @@ mod client_hello {
             };
 
             let mut chosen_psk_index = None;
+            let mut chosen_ticket_len = 0;
             let mut resumedata = None;
 
             if let Some(psk_offer) = client_hello.psk() {
@@ -283,12 +275,20 @@ mod client_hello {
                     let now = self.config.current_time()?;
 
                     let resume = match self
-                        .attempt_tls13_ticket_decryption(&psk_id.identity.0)
+                        .attempt_tls13_ticket_decryption(cx.data, &psk_id.identity.0, now)
                         .map(|resumedata| {
                             resumedata.set_freshness(psk_id.obfuscated_ticket_age, now)
                         })
                         .filter(|resumedata| {
-                            hs::can_resume(self.suite.into(), &cx.data.sni, false, resumedata)
+                            hs::can_resume(
+                                self.suite.into(),
+                                &cx.data.sni,
+                                false,
+                                self.config
+                                    .verifier
+                                    .client_auth_mandatory(),
+                                resumedata,
+                            )
                         }) {
                         Some(resume) => resume,
                         None => continue,
@@ -307,18 +307,23 @@ mod client_hello {
                     }
 
                     chosen_psk_index = Some(i);
+                    chosen_ticket_len = psk_id.identity.0.len();
                     resumedata = Some(resume);
                     break;
                 }
             }
 
             if !client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_DHE_KE) {
-                debug!("Client unwilling to resume, DHE_KE not offered");
+                diagnose!(
+                    cx.common,
+                    debug,
+                    "Client unwilling to resume, DHE_KE not offered"
+                );
                 self.send_tickets = 0;
                 chosen_psk_index = None;
                 resumedata = None;
             } else {
-                self.send_tickets = self.config.send_tls13_tickets;
+                self.send_tickets = cx.data.send_tls13_tickets(&self.config);
             }
 
             if let Some(ref resume) = resumedata {
@@ -326,6 +331,15 @@ mod client_hello {
                 cx.common
                     .peer_certificates
                     .clone_from(&resume.client_cert_chain);
+
+                if let Some(observer) = &self.config.ticket_observer {
+                    observer.ticket_redeemed(&TicketInfo::new(
+                        chosen_ticket_len,
+                        cx.data.ticketer(&self.config).enabled(),
+                        None,
+                        resume,
+                    ));
+                }
             }
 
             let full_handshake = resumedata.is_none();
@@ -354,6 +368,7 @@ mod client_hello {
                 cx.common.kx_state.complete();
             } else {
                 cx.common.handshake_kind = Some(HandshakeKind::Resumed);
+                cx.common.resumption_kind = Some(ResumptionKind::Ticket);
             }
 
             let mut ocsp_response = server_key.get_ocsp();
@@ -372,14 +387,35 @@ mod client_hello {
                 let client_auth =
                     emit_certificate_req_tls13(&mut self.transcript, cx, &self.config)?;
 
-                if let Some(compressor) = cert_compressor {
+                let intermediate_ocsp =
+                    match client_hello.find_extension(ExtensionType::StatusRequest) {
+                        Some(_) => server_key.get_intermediate_ocsp(),
+                        None => &[],
+                    };
+
+                let cert_compression = client_hello
+                    .certificate_compression_extension()
+                    .and_then(|offered| {
+                        choose_cert_compression(
+                            &self.config,
+                            &CertCompressionOffer {
+                                offered,
+                                compressors: &self.config.cert_compressors,
+                                server_name: cx.data.sni.as_ref().map(AsRef::as_ref),
+                                alpn_protocol: cx.common.alpn_protocol.as_deref(),
+                            },
+                        )
+                    });
+
+                if let Some(choice) = cert_compression {
                     emit_compressed_certificate_tls13(
                         &mut self.transcript,
                         cx.common,
                         &self.config,
                         server_key.get_cert(),
                         ocsp_response,
-                        compressor,
+                        intermediate_ocsp,
+                        choice,
                     );
                 } else {
                     emit_certificate_tls13(
@@ -387,6 +423,7 @@ mod client_hello {
                         cx.common,
                         server_key.get_cert(),
                         ocsp_response,
+                        intermediate_ocsp,
                     );
                 }
                 emit_certificate_verify_tls13(
@@ -408,7 +445,7 @@ mod client_hello {
                     cx.data.early_data.reject();
                 }
                 EarlyDataDecision::RequestedButRejected => {
-                    debug!("Client requested early_data, but not accepted: switching to handshake keys with trial decryption");
+                    diagnose!(cx.common, debug, "Client requested early_data, but not accepted: switching to handshake keys with trial decryption");
                     key_schedule.set_handshake_decrypter(
                         Some(max_early_data_size(self.config.max_early_data_size)),
                         cx.common,
@@ -759,8 +796,10 @@ mod client_hello {
         common: &mut CommonState,
         cert_chain: &[CertificateDer<'static>],
         ocsp_response: Option<&[u8]>,
+        intermediate_ocsp: &[Option<Vec<u8>>],
     ) {
-        let cert_body = CertificatePayloadTls13::new(cert_chain.iter(), ocsp_response);
+        let cert_body = CertificatePayloadTls13::new(cert_chain.iter(), ocsp_response)
+            .with_intermediate_ocsp(intermediate_ocsp);
         let c = Message {
             version: ProtocolVersion::TLSv1_3,
             payload: MessagePayload::handshake(HandshakeMessagePayload {
@@ -774,22 +813,52 @@ mod client_hello {
         common.send_msg(c, true);
     }
 
+    fn choose_cert_compression(
+        config: &ServerConfig,
+        offer: &CertCompressionOffer<'_>,
+    ) -> Option<CertCompressionChoice> {
+        let policy = match &config.cert_compression_policy {
+            Some(policy) => policy,
+            None => return offer.default_choice(),
+        };
+
+        policy
+            .choose_compression(offer)
+            .filter(|choice| {
+                offer
+                    .offered
+                    .contains(&choice.compressor.algorithm())
+            })
+    }
+
     fn emit_compressed_certificate_tls13(
         transcript: &mut HandshakeHash,
         common: &mut CommonState,
         config: &ServerConfig,
         cert_chain: &[CertificateDer<'static>],
         ocsp_response: Option<&[u8]>,
-        cert_compressor: &'static dyn CertCompressor,
+        intermediate_ocsp: &[Option<Vec<u8>>],
+        choice: CertCompressionChoice,
     ) {
-        let payload = CertificatePayloadTls13::new(cert_chain.iter(), ocsp_response);
-
-        let entry = match config
-            .cert_compression_cache
-            .compression_for(cert_compressor, &payload)
-        {
+        let payload = CertificatePayloadTls13::new(cert_chain.iter(), ocsp_response)
+            .with_intermediate_ocsp(intermediate_ocsp);
+
+        let cache = &config.cert_compression_cache;
+        let (compressed, elapsed) = compress::timed(|| match choice.level {
+            Some(level) => cache.compression_at(choice.compressor, level, &payload),
+            None => cache.compression_for(choice.compressor, &payload),
+        });
+        let entry = match compressed {
             Ok(entry) => entry,
-            Err(_) => return emit_certificate_tls13(transcript, common, cert_chain, ocsp_response),
+            Err(_) => {
+                return emit_certificate_tls13(
+                    transcript,
+                    common,
+                    cert_chain,
+                    ocsp_response,
+                    intermediate_ocsp,
+                )
+            }
         };
 
         let c = Message {
@@ -801,6 +870,10 @@ mod client_hello {
         };
 
         trace!("sending compressed certificate {:?}", c);
+        common.sent_cert_compression = Some(CertCompressionStats::new(
+            &entry.compressed_cert_payload(),
+            elapsed,
+        ));
         transcript.add_message(&c);
         common.send_msg(c, true);
     }
@@ -1015,32 +1088,41 @@ impl State<ServerConnectionData> for ExpectCompressedCertificate {
             }
         };
 
-        if compressed_cert.uncompressed_len as usize > CERTIFICATE_MAX_SIZE_LIMIT {
+        let max_size = self
+            .config
+            .max_decompressed_cert_size
+            .or(self.config.max_handshake_size)
+            .unwrap_or(DEFAULT_MAX_HANDSHAKE_SIZE as usize);
+        if compressed_cert.uncompressed_len as usize > max_size {
             return Err(cx.common.send_fatal_alert(
                 AlertDescription::BadCertificate,
                 InvalidMessage::MessageTooLarge,
             ));
         }
 
-        let mut decompress_buffer = vec![0u8; compressed_cert.uncompressed_len as usize];
-        if let Err(compress::DecompressionFailed) =
-            decompressor.decompress(compressed_cert.compressed.0.bytes(), &mut decompress_buffer)
-        {
-            return Err(cx.common.send_fatal_alert(
-                AlertDescription::BadCertificate,
-                PeerMisbehaved::InvalidCertCompression,
-            ));
-        }
+        let (decompressed, elapsed) =
+            compress::timed(|| compress::decompress(*decompressor, &compressed_cert));
+        let decompress_buffer = match decompressed {
+            Ok(decompressed) => decompressed,
+            Err(compress::DecompressionFailed) => {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::BadCertificate,
+                    PeerMisbehaved::InvalidCertCompression,
+                ));
+            }
+        };
 
-        let cert_payload =
-            match CertificatePayloadTls13::read(&mut Reader::init(&decompress_buffer)) {
-                Ok(cm) => cm,
-                Err(err) => {
-                    return Err(cx
-                        .common
-                        .send_fatal_alert(AlertDescription::BadCertificate, err));
-                }
-            };
+        let cert_payload = match CertificatePayloadTls13::read_with_max_size(
+            &mut Reader::init(&decompress_buffer),
+            Ord::max(max_size, CERTIFICATE_MAX_SIZE_LIMIT),
+        ) {
+            Ok(cm) => cm,
+            Err(err) => {
+                return Err(cx
+                    .common
+                    .send_fatal_alert(AlertDescription::BadCertificate, err));
+            }
+        };
         trace!(
             "Client certificate decompressed using {:?} ({} bytes -> {})",
             compressed_cert.alg,
@@ -1051,6 +1133,10 @@ impl State<ServerConnectionData> for ExpectCompressedCertificate {
                 .len(),
             compressed_cert.uncompressed_len,
         );
+        cx.common.received_cert_compression = Some(compress::CertCompressionStats::new(
+            &compressed_cert,
+            elapsed,
+        ));
 
         let m = Message {
             version: ProtocolVersion::TLSv1_3,
@@ -1119,7 +1205,11 @@ impl State<ServerConnectionData> for ExpectCertificate {
         let (end_entity, intermediates) = match client_cert.split_first() {
             None => {
                 if !mandatory {
-                    debug!("client auth requested but no certificate supplied");
+                    diagnose!(
+                        cx.common,
+                        debug,
+                        "client auth requested but no certificate supplied"
+                    );
                     self.transcript.abandon_client_auth();
                     return Ok(Box::new(ExpectFinished {
                         config: self.config,
@@ -1140,13 +1230,18 @@ impl State<ServerConnectionData> for ExpectCertificate {
 
         let now = self.config.current_time()?;
 
-        self.config
-            .verifier
-            .verify_client_cert(end_entity, intermediates, now)
+        let verified = cx
+            .common
+            .with_user_data(|data| {
+                self.config
+                    .verifier
+                    .verify_client_cert_with_user_data(end_entity, intermediates, now, data)
+            })
             .map_err(|err| {
                 cx.common
                     .send_cert_verify_error_alert(err)
             })?;
+        cx.data.client_cert_details = verified.into_details();
 
         Ok(Box::new(ExpectCertificateVerify {
             config: self.config,
@@ -1334,47 +1429,59 @@ impl ExpectFinished {
     ) -> Result<(), Error> {
         let secure_random = config.provider.secure_random;
         let nonce = rand::random_vec(secure_random, 32)?;
-        let age_add = rand::random_u32(secure_random)?;
+        let age_add = match config.randomize_ticket_age_add {
+            true => rand::random_u32(secure_random)?,
+            false => 0,
+        };
 
         let now = config.current_time()?;
 
-        let plain =
-            get_server_session_value(transcript, suite, key_schedule, cx, &nonce, now, age_add)
-                .get_encoding();
+        let value =
+            get_server_session_value(transcript, suite, key_schedule, cx, &nonce, now, age_add);
+        let plain = value.get_encoding();
 
-        let stateless = config.ticketer.enabled();
+        let ticketer = cx.data.ticketer(config);
+        let stateless = ticketer.enabled();
         let (ticket, lifetime) = if stateless {
-            let ticket = match config.ticketer.encrypt(&plain) {
+            let ticket = match ticketer.encrypt(&plain) {
                 Some(t) => t,
                 None => return Ok(()),
             };
-            (ticket, config.ticketer.lifetime())
+            (ticket, ticketer.lifetime())
         } else {
             let id = rand::random_vec(secure_random, 32)?;
-            let stored = config
-                .session_storage
+            let stored = cx
+                .data
+                .session_storage(config)
                 .put(id.clone(), plain);
             if !stored {
                 trace!("resumption not available; not issuing ticket");
                 return Ok(());
             }
-            let stateful_lifetime = 24 * 60 * 60; // this is a bit of a punt
-            (id, stateful_lifetime)
+            (id, config.session_lifetime)
         };
 
+        let lifetime = config.advertised_ticket_lifetime(lifetime);
+        let ticket_len = ticket.len();
         let mut payload = NewSessionTicketPayloadTls13::new(lifetime, age_add, nonce, ticket);
 
-        if config.max_early_data_size > 0 {
+        let max_early_data_size = match config.ticket_max_early_data_size {
+            Some(size) => size.min(config.max_early_data_size),
+            None => config.max_early_data_size,
+        };
+        if max_early_data_size > 0 {
             if !stateless {
                 payload
                     .exts
-                    .push(NewSessionTicketExtension::EarlyData(
-                        config.max_early_data_size,
-                    ));
+                    .push(NewSessionTicketExtension::EarlyData(max_early_data_size));
             } else {
                 // We implement RFC8446 section 8.1: by enforcing that 0-RTT is
                 // only possible if using stateful resumption
-                warn!("early_data with stateless resumption is not allowed");
+                diagnose!(
+                    cx.common,
+                    warn,
+                    "early_data with stateless resumption is not allowed"
+                );
             }
         }
 
@@ -1388,6 +1495,16 @@ impl ExpectFinished {
 
         trace!("sending new ticket {:?} (stateless: {})", m, stateless);
         cx.common.send_msg(m, true);
+        cx.common
+            .observe(|observer, data| observer.ticket_sent(data));
+        if let Some(observer) = &config.ticket_observer {
+            observer.ticket_issued(&TicketInfo::new(
+                ticket_len,
+                stateless,
+                Some(lifetime),
+                &value,
+            ));
+        }
         Ok(())
     }
 }
@@ -1438,6 +1555,7 @@ impl State<ServerConnectionData> for ExpectFinished {
         // Application data may now flow, even if we have client auth enabled.
         cx.common
             .start_traffic(&mut cx.sendable_plaintext);
+        cx.data.handshake_finished();
 
         Ok(match cx.common.is_quic() {
             true => Box::new(ExpectQuicTraffic {
@@ -0,0 +1,161 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::error::Error;
+use crate::msgs::message::MessagePayload;
+
+/// The trace of a connection that has not handled a message yet.
+pub(crate) static EMPTY: StateTrace = StateTrace {
+    transitions: Vec::new(),
+    current: None,
+};
+
+/// A record of the handshake state machine transitions made by a connection.
+///
+/// This is available from [`CommonState::state_trace()`] with the `state_trace`
+/// feature, and is meant for diagnosing handshakes that stall or fail, especially
+/// in sans-io integrations.  Each message received from the peer is handled by the
+/// current state, giving one [`StateTransition`].  Recording stops once the
+/// handshake is complete, so messages received after that are not included.
+///
+/// Only messages sent while handling a received message are recorded.  That leaves
+/// out the messages a connection sends without receiving one, such as a client's
+/// first ClientHello, and alerts sent by the application (such as close_notify).
+///
+/// This formats with `Display` as text, one transition per line, and
+/// [`StateTrace::to_dot()`] renders it for Graphviz.
+///
+/// The names of states are those of rustls' internal types, and may change between
+/// releases.
+///
+/// [`CommonState::state_trace()`]: crate::CommonState::state_trace
+#[derive(Clone, Debug, Default)]
+pub struct StateTrace {
+    transitions: Vec<StateTransition>,
+    current: Option<StateTransition>,
+}
+
+impl StateTrace {
+    /// The transitions made so far, oldest first.
+    pub fn transitions(&self) -> &[StateTransition] {
+        &self.transitions
+    }
+
+    /// Render the transitions as a Graphviz DOT digraph.
+    ///
+    /// Each state is a node, and each transition an edge labelled with the
+    /// message received and the messages sent in response.  A transition that
+    /// failed leads to a node describing the error.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph handshake {\n");
+        for (i, transition) in self.transitions.iter().enumerate() {
+            let to = match (&transition.to, &transition.error) {
+                (Some(to), _) => String::from(*to),
+                (None, Some(error)) => format!("error: {error}"),
+                (None, None) => String::from("error"),
+            };
+            let mut label = format!("{}. {}", i + 1, transition.received);
+            if !transition.sent.is_empty() {
+                label.push_str(" / ");
+                label.push_str(&transition.sent.join(", "));
+            }
+            dot.push_str(&format!(
+                "  \"{}\" -> \"{}\" [label=\"{}\"];\n",
+                escape(transition.from),
+                escape(&to),
+                escape(&label)
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub(crate) fn start(&mut self, from: &'static str, received: &MessagePayload<'_>) {
+        self.current = Some(StateTransition {
+            from: short_name(from),
+            received: describe(received),
+            to: None,
+            sent: Vec::new(),
+            error: None,
+        });
+    }
+
+    pub(crate) fn sent(&mut self, message: &MessagePayload<'_>) {
+        if let Some(current) = &mut self.current {
+            current.sent.push(describe(message));
+        }
+    }
+
+    pub(crate) fn finish(&mut self, result: Result<&'static str, &Error>) {
+        if let Some(mut current) = self.current.take() {
+            match result {
+                Ok(to) => current.to = Some(short_name(to)),
+                Err(error) => current.error = Some(error.clone()),
+            }
+            self.transitions.push(current);
+        }
+    }
+}
+
+impl fmt::Display for StateTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for transition in &self.transitions {
+            writeln!(f, "{transition}")?;
+        }
+        Ok(())
+    }
+}
+
+/// One transition of the handshake state machine, made when handling a message
+/// from the peer.  See [`StateTrace`].
+#[non_exhaustive]
+#[derive(Clone, Debug)]
+pub struct StateTransition {
+    /// The state that handled the message.
+    pub from: &'static str,
+    /// The message received, such as `ServerHello` or `Alert(UnexpectedMessage)`.
+    pub received: String,
+    /// The resulting state, or `None` if handling the message failed.
+    pub to: Option<&'static str>,
+    /// The messages sent while handling the message, in order.
+    pub sent: Vec<String>,
+    /// The error, if handling the message failed.
+    pub error: Option<Error>,
+}
+
+impl fmt::Display for StateTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} --{}--> ", self.from, self.received)?;
+        match (&self.to, &self.error) {
+            (Some(to), _) => write!(f, "{to}")?,
+            (None, Some(error)) => write!(f, "error: {error}")?,
+            (None, None) => write!(f, "error")?,
+        }
+        if !self.sent.is_empty() {
+            write!(f, " (sent {})", self.sent.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn describe(message: &MessagePayload<'_>) -> String {
+    match message {
+        MessagePayload::Alert(alert) => format!("Alert({:?})", alert.description),
+        MessagePayload::Handshake { parsed, .. } => format!("{:?}", parsed.typ),
+        MessagePayload::ChangeCipherSpec(_) => String::from("ChangeCipherSpec"),
+        MessagePayload::ApplicationData(_) => String::from("ApplicationData"),
+    }
+}
+
+/// Remove the crate name from a state's type name.
+fn short_name(name: &'static str) -> &'static str {
+    name.strip_prefix("rustls::")
+        .unwrap_or(name)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+}
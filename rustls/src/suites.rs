@@ -1,9 +1,10 @@
 use core::fmt;
 
 use crate::common_state::Protocol;
-use crate::crypto::cipher::{AeadKey, Iv};
+use crate::crypto::cipher::{AeadKey, Iv, NONCE_LEN};
+use crate::crypto::hash::HashAlgorithm;
 use crate::crypto::{self, KeyExchangeAlgorithm};
-use crate::enums::{CipherSuite, SignatureAlgorithm, SignatureScheme};
+use crate::enums::{CipherSuite, ProtocolVersion, SignatureAlgorithm, SignatureScheme};
 use crate::msgs::handshake::ALL_KEY_EXCHANGE_ALGORITHMS;
 #[cfg(feature = "tls12")]
 use crate::tls12::Tls12CipherSuite;
@@ -56,6 +57,14 @@ impl CipherSuiteCommon {
     pub fn fips(&self) -> bool {
         self.hash_provider.fips()
     }
+
+    /// Return `true` if this is backed by a formally verified implementation.
+    ///
+    /// This means all the constituent parts that do cryptography return `true`
+    /// for `formally_verified()`.
+    pub fn formally_verified(&self) -> bool {
+        self.hash_provider.formally_verified()
+    }
 }
 
 /// A cipher suite supported by rustls.
@@ -144,6 +153,66 @@ impl SupportedCipherSuite {
         }
     }
 
+    /// Return `true` if this is backed by a formally verified implementation.
+    pub fn formally_verified(&self) -> bool {
+        match self {
+            #[cfg(feature = "tls12")]
+            Self::Tls12(cs) => cs.formally_verified(),
+            Self::Tls13(cs) => cs.formally_verified(),
+        }
+    }
+
+    /// Describe the algorithms and limits of this suite.
+    ///
+    /// The sizes are those declared by the suite's implementation, not values looked
+    /// up by name, so this also describes suites from third-party providers.
+    pub fn info(&self) -> CipherSuiteInfo {
+        let common = self.common();
+        let (version, key_len, nonce_len, tag_len, key_exchange, quic) = match self {
+            #[cfg(feature = "tls12")]
+            Self::Tls12(cs) => {
+                let shape = cs.aead_alg.key_block_shape();
+                (
+                    ProtocolVersion::TLSv1_2,
+                    shape.enc_key_len,
+                    shape.fixed_iv_len + shape.explicit_nonce_len,
+                    cs.aead_alg.tag_len(),
+                    Some(cs.kx),
+                    false,
+                )
+            }
+            Self::Tls13(cs) => (
+                ProtocolVersion::TLSv1_3,
+                cs.aead_alg.key_len(),
+                NONCE_LEN,
+                cs.aead_alg.tag_len(),
+                None,
+                cs.quic.is_some(),
+            ),
+        };
+
+        CipherSuiteInfo {
+            suite: common.suite,
+            version,
+            hash: common.hash_provider.algorithm(),
+            hash_len: common.hash_provider.output_len(),
+            key_len,
+            nonce_len,
+            tag_len,
+            confidentiality_limit: common.confidentiality_limit,
+            key_exchange,
+            quic,
+            quantum_resistant: matches!(
+                common.suite,
+                CipherSuite::TLS13_AES_256_GCM_SHA384
+                    | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+                    | CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
+            ),
+            fips: self.fips(),
+            formally_verified: self.formally_verified(),
+        }
+    }
+
     /// Return the list of `KeyExchangeAlgorithm`s supported by this cipher suite.
     ///
     /// TLS 1.3 cipher suites support both ECDHE and DHE key exchange, but TLS 1.2 suites
@@ -175,6 +244,66 @@ impl fmt::Debug for SupportedCipherSuite {
     }
 }
 
+/// The algorithms and limits of a [`SupportedCipherSuite`], from [`SupportedCipherSuite::info()`].
+///
+/// This is meant for tooling, such as reports of what a configuration allows.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CipherSuiteInfo {
+    /// The TLS enumeration naming the suite.
+    pub suite: CipherSuite,
+
+    /// The protocol version the suite is for.
+    pub version: ProtocolVersion,
+
+    /// The suite's hash function.
+    pub hash: HashAlgorithm,
+
+    /// The length of the hash function's output, in bytes.
+    pub hash_len: usize,
+
+    /// The length of the AEAD key, in bytes.
+    pub key_len: usize,
+
+    /// The length of the AEAD nonce, in bytes.
+    pub nonce_len: usize,
+
+    /// The length of the AEAD authentication tag, in bytes.
+    pub tag_len: usize,
+
+    /// The suite's [`CipherSuiteCommon::confidentiality_limit`].
+    pub confidentiality_limit: u64,
+
+    /// For TLS1.2 suites, the key exchange algorithm the suite requires.
+    ///
+    /// This is `None` for TLS1.3 suites, which work with any key exchange.
+    pub key_exchange: Option<KeyExchangeAlgorithm>,
+
+    /// Whether the suite can be used for QUIC.
+    pub quic: bool,
+
+    /// Whether the suite's symmetric algorithms are approved for use against an
+    /// attacker with a quantum computer.
+    ///
+    /// This follows the NSA's CNSA 2.0 suite, which approves AES-256 with SHA-384,
+    /// so only the `AES_256_GCM_SHA384` suites are quantum resistant.  The
+    /// `CHACHA20_POLY1305` suites are not, despite their 256-bit keys, and nor are
+    /// suites that rustls does not know by name.  This only concerns the suite:
+    /// whether a connection is protected against such attackers also depends on
+    /// its key exchange group and certificates.
+    pub quantum_resistant: bool,
+
+    /// Whether the suite is backed by a FIPS-approved implementation.
+    ///
+    /// See [`SupportedCipherSuite::fips()`].
+    pub fips: bool,
+
+    /// Whether the suite is backed by a formally verified implementation.
+    ///
+    /// See [`SupportedCipherSuite::formally_verified()`].
+    pub formally_verified: bool,
+}
+
 /// Return true if `sigscheme` is usable by any of the given suites.
 pub(crate) fn compatible_sigscheme_for_suites(
     sigscheme: SignatureScheme,
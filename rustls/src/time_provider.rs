@@ -1,6 +1,7 @@
 //! The library's source of time.
 
 use core::fmt::Debug;
+use core::time::Duration;
 
 use pki_types::UnixTime;
 
@@ -15,6 +16,21 @@ pub trait TimeProvider: Debug + Send + Sync {
     ///
     /// Return `None` if unable to retrieve the time.
     fn current_time(&self) -> Option<UnixTime>;
+
+    /// Returns the current wall time as a duration since the Unix epoch, with the
+    /// best available precision.
+    ///
+    /// This is used for measuring timeouts, such as [`ClientConfig::handshake_timeout`].
+    /// The default implementation uses [`TimeProvider::current_time()`], so only has a
+    /// precision of one second.
+    ///
+    /// Return `None` if unable to retrieve the time.
+    ///
+    /// [`ClientConfig::handshake_timeout`]: crate::ClientConfig::handshake_timeout
+    fn current_time_precise(&self) -> Option<Duration> {
+        self.current_time()
+            .map(|now| Duration::from_secs(now.as_secs()))
+    }
 }
 
 #[derive(Debug)]
@@ -27,4 +43,10 @@ impl TimeProvider for DefaultTimeProvider {
     fn current_time(&self) -> Option<UnixTime> {
         Some(UnixTime::now())
     }
+
+    fn current_time_precise(&self) -> Option<Duration> {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+    }
 }
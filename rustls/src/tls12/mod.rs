@@ -69,6 +69,16 @@ impl Tls12CipherSuite {
     pub fn fips(&self) -> bool {
         self.common.fips() && self.prf_provider.fips() && self.aead_alg.fips()
     }
+
+    /// Return `true` if this is backed by a formally verified implementation.
+    ///
+    /// This means all the constituent parts that do cryptography return `true`
+    /// for `formally_verified()`.
+    pub fn formally_verified(&self) -> bool {
+        self.common.formally_verified()
+            && self.prf_provider.formally_verified()
+            && self.aead_alg.formally_verified()
+    }
 }
 
 impl From<&'static Tls12CipherSuite> for SupportedCipherSuite {
@@ -337,6 +347,8 @@ pub(crate) fn decode_kx_params<'a, T: KxDecode<'a>>(
 }
 
 pub(crate) const DOWNGRADE_SENTINEL: [u8; 8] = [0x44, 0x4f, 0x57, 0x4e, 0x47, 0x52, 0x44, 0x01];
+pub(crate) const DOWNGRADE_SENTINEL_TLS11: [u8; 8] =
+    [0x44, 0x4f, 0x57, 0x4e, 0x47, 0x52, 0x44, 0x00];
 
 test_for_each_provider! {
     use super::*;
@@ -17,6 +17,7 @@ use crate::{quic, KeyLog, Tls13CipherSuite};
 enum SecretKind {
     ResumptionPskBinderKey,
     ClientEarlyTrafficSecret,
+    EarlyExporterMasterSecret,
     ClientHandshakeTrafficSecret,
     ServerHandshakeTrafficSecret,
     ClientApplicationTrafficSecret,
@@ -34,6 +35,7 @@ impl SecretKind {
         match self {
             ResumptionPskBinderKey => b"res binder",
             ClientEarlyTrafficSecret => b"c e traffic",
+            EarlyExporterMasterSecret => b"e exp master",
             ClientHandshakeTrafficSecret => b"c hs traffic",
             ServerHandshakeTrafficSecret => b"s hs traffic",
             ClientApplicationTrafficSecret => b"c ap traffic",
@@ -52,6 +54,7 @@ impl SecretKind {
         use self::SecretKind::*;
         Some(match self {
             ClientEarlyTrafficSecret => "CLIENT_EARLY_TRAFFIC_SECRET",
+            EarlyExporterMasterSecret => "EARLY_EXPORTER_SECRET",
             ClientHandshakeTrafficSecret => "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
             ServerHandshakeTrafficSecret => "SERVER_HANDSHAKE_TRAFFIC_SECRET",
             ClientApplicationTrafficSecret => "CLIENT_TRAFFIC_SECRET_0",
@@ -104,6 +107,16 @@ impl KeyScheduleEarly {
             client_random,
         );
 
+        // rustls has no early exporter, so this is only derived to be logged
+        if key_log.will_log("EARLY_EXPORTER_SECRET") {
+            self.ks.derive_logged_secret(
+                SecretKind::EarlyExporterMasterSecret,
+                hs_hash.as_ref(),
+                key_log,
+                client_random,
+            );
+        }
+
         match common.side {
             Side::Client => self
                 .ks
@@ -503,6 +516,7 @@ impl KeyScheduleTraffic {
     ) -> Result<(), Error> {
         common.check_aligned_handshake()?;
         common.send_msg_encrypt(Message::build_key_update_request().into());
+        common.observe(|observer, data| observer.key_update_sent(data));
         let secret = self.next_application_traffic_secret(common.side);
         self.ks.set_encrypter(&secret, common);
         Ok(())
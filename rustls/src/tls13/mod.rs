@@ -59,6 +59,25 @@ impl Tls13CipherSuite {
             && quic.map(|q| q.fips()).unwrap_or(true)
     }
 
+    /// Return `true` if this is backed by a formally verified implementation.
+    ///
+    /// This means all the constituent parts that do cryptography return `true`
+    /// for `formally_verified()`.
+    pub fn formally_verified(&self) -> bool {
+        let Self {
+            common,
+            hkdf_provider,
+            aead_alg,
+            quic,
+        } = self;
+        common.formally_verified()
+            && hkdf_provider.formally_verified()
+            && aead_alg.formally_verified()
+            && quic
+                .map(|q| q.formally_verified())
+                .unwrap_or(true)
+    }
+
     /// Returns a `quic::Suite` for the ciphersuite, if supported.
     pub fn quic_suite(&'static self) -> Option<crate::quic::Suite> {
         self.quic
@@ -0,0 +1,667 @@
+//! Recording the bytes a connection receives, and feeding them into another.
+//!
+//! A [`Transcript`] holds the TLS bytes a connection received from its peer, and
+//! when each chunk arrived.  Record one by wrapping the connection's socket in a
+//! [`RecordingReader`] (or by calling [`Transcript::push()`] directly, when using
+//! the [`unbuffered`][crate::unbuffered] API), and save it with
+//! [`Transcript::encode()`].
+//!
+//! Later, [`Transcript::feed()`] feeds the recorded bytes into a new connection, which
+//! can reproduce a failure seen in production.  The new connection should have the
+//! same configuration, and take its time from a [`TranscriptClock`].  The times are
+//! recorded to the second, as that is all rustls reads from a [`TimeProvider`].
+//!
+//! To replay the original connection exactly, including the messages the peer
+//! encrypted, both it and the new connection must make the same random values
+//! and ephemeral keys.  Give both a [`CryptoProvider`] whose
+//! [`secure_random`][CryptoProvider::secure_random] is a fresh
+//! [`danger::DeterministicRandom`], whose only key exchange group is a
+//! [`danger::DeterministicX25519`] with the same seed, and whose signature schemes
+//! are deterministic (such as Ed25519).  Otherwise the new connection only behaves
+//! like the original up to the point where the peer's messages depend on these
+//! values: for example, a server can reproduce its handling of a troublesome
+//! `ClientHello`, but not of the client's encrypted `Finished` message.
+//!
+//! [`CryptoProvider`]: crate::crypto::CryptoProvider
+//! [CryptoProvider::secure_random]: crate::crypto::CryptoProvider::secure_random
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use std::io;
+
+use pki_types::UnixTime;
+
+use crate::conn::ConnectionCommon;
+use crate::error::{Error, InvalidMessage};
+use crate::msgs::codec::{Codec, Reader};
+use crate::time_provider::TimeProvider;
+
+/// The bytes a connection received from its peer, and when they arrived.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transcript {
+    entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    /// Make an empty transcript.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `data` was received at time `at`.
+    pub fn push(&mut self, at: UnixTime, data: &[u8]) {
+        if !data.is_empty() {
+            self.entries.push(TranscriptEntry {
+                at,
+                data: data.to_vec(),
+            });
+        }
+    }
+
+    /// The chunks received, oldest first.
+    pub fn entries(&self) -> &[TranscriptEntry] {
+        &self.entries
+    }
+
+    /// Encode the transcript, for saving to a file.
+    ///
+    /// Each entry is encoded as its time in seconds (8 bytes), the length of its
+    /// data (4 bytes), then its data, all big-endian.  [`Transcript::read()`]
+    /// decodes the result.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for entry in &self.entries {
+            entry.at.as_secs().encode(&mut bytes);
+            (entry.data.len() as u32).encode(&mut bytes);
+            bytes.extend_from_slice(&entry.data);
+        }
+        bytes
+    }
+
+    /// Decode a transcript made by [`Transcript::encode()`].
+    pub fn read(bytes: &[u8]) -> Result<Self, InvalidMessage> {
+        let mut r = Reader::init(bytes);
+        let mut entries = Vec::new();
+        while r.any_left() {
+            let at = UnixTime::since_unix_epoch(Duration::from_secs(u64::read(&mut r)?));
+            let len = u32::read(&mut r)? as usize;
+            let data = r
+                .take(len)
+                .ok_or(InvalidMessage::MessageTooShort)?;
+            entries.push(TranscriptEntry {
+                at,
+                data: data.to_vec(),
+            });
+        }
+        Ok(Self { entries })
+    }
+
+    /// Feed the recorded bytes into `conn`, processing them as they were received.
+    ///
+    /// Before each chunk is processed, `clock` is set to the time it was received.
+    /// Anything `conn` writes is discarded.
+    ///
+    /// This returns the first error from `conn.process_new_packets()` as
+    /// [`FeedError::Connection`].
+    pub fn feed<Data>(
+        &self,
+        conn: &mut ConnectionCommon<Data>,
+        clock: &TranscriptClock,
+    ) -> Result<(), FeedError> {
+        for entry in &self.entries {
+            clock.set(entry.at);
+            let mut data = &entry.data[..];
+            while !data.is_empty() {
+                let read = conn
+                    .read_tls(&mut data)
+                    .map_err(FeedError::Io)?;
+                conn.process_new_packets()?;
+                if read == 0 {
+                    // after close_notify: nothing more is read
+                    return Ok(());
+                }
+                while conn.wants_write() {
+                    conn.write_tls(&mut io::sink())
+                        .map_err(FeedError::Io)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An error from [`Transcript::feed()`].
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum FeedError {
+    /// The connection failed while processing the transcript.
+    Connection(Error),
+    /// The connection did not accept the transcript's bytes, for example because
+    /// its buffer of received plaintext is full.
+    Io(io::Error),
+}
+
+impl From<Error> for FeedError {
+    #[inline]
+    fn from(err: Error) -> Self {
+        Self::Connection(err)
+    }
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connection(err) => write!(f, "connection failed during transcript: {err}"),
+            Self::Io(err) => write!(f, "feeding transcript failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {}
+
+/// A chunk of a [`Transcript`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    /// When the chunk was received, to the second.
+    pub at: UnixTime,
+    /// The bytes received.
+    pub data: Vec<u8>,
+}
+
+/// An [`io::Read`] that records what is read through it in a [`Transcript`].
+///
+/// Pass this to [`ConnectionCommon::read_tls()`] in place of the socket.
+#[derive(Debug)]
+pub struct RecordingReader<R> {
+    inner: R,
+    time_provider: Arc<dyn TimeProvider>,
+    transcript: Transcript,
+}
+
+impl<R: io::Read> RecordingReader<R> {
+    /// Read from `inner`, recording the system time for each read.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            time_provider: Arc::new(crate::time_provider::DefaultTimeProvider),
+            transcript: Transcript::new(),
+        }
+    }
+
+    /// Take the time for each read from `time_provider`.
+    pub fn with_time_provider(mut self, time_provider: Arc<dyn TimeProvider>) -> Self {
+        self.time_provider = time_provider;
+        self
+    }
+
+    /// The transcript so far.
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    /// Take the transcript so far, leaving an empty one.
+    pub fn take_transcript(&mut self) -> Transcript {
+        core::mem::take(&mut self.transcript)
+    }
+
+    /// Return the inner reader, and the transcript.
+    pub fn into_parts(self) -> (R, Transcript) {
+        (self.inner, self.transcript)
+    }
+}
+
+impl<R: io::Read> io::Read for RecordingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        let at = self
+            .time_provider
+            .current_time()
+            .unwrap_or(UnixTime::since_unix_epoch(Duration::ZERO));
+        self.transcript.push(at, &buf[..len]);
+        Ok(len)
+    }
+}
+
+/// A [`TimeProvider`] reporting the time of the [`Transcript`] entry being fed.
+///
+/// Clones share the same time.  Set it as the [`ClientConfig::time_provider`] or
+/// [`ServerConfig::time_provider`] of the connection passed to
+/// [`Transcript::feed()`].
+///
+/// [`ClientConfig::time_provider`]: crate::ClientConfig::time_provider
+/// [`ServerConfig::time_provider`]: crate::ServerConfig::time_provider
+#[derive(Clone, Debug, Default)]
+pub struct TranscriptClock {
+    secs: Arc<AtomicU64>,
+}
+
+impl TranscriptClock {
+    /// Make a clock, reporting the Unix epoch until a transcript is fed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn set(&self, at: UnixTime) {
+        self.secs
+            .store(at.as_secs(), Ordering::Relaxed);
+    }
+}
+
+impl TimeProvider for TranscriptClock {
+    fn current_time(&self) -> Option<UnixTime> {
+        Some(UnixTime::since_unix_epoch(Duration::from_secs(
+            self.secs.load(Ordering::Relaxed),
+        )))
+    }
+}
+
+/// Dangerous APIs for use with transcripts.
+pub mod danger {
+    use alloc::boxed::Box;
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    use crate::crypto::{
+        ActiveKeyExchange, GetRandomFailed, SecureRandom, SharedSecret, SupportedKxGroup,
+    };
+    use crate::error::{Error, PeerMisbehaved};
+    use crate::msgs::enums::NamedGroup;
+
+    /// A [`SecureRandom`] producing the same bytes for the same seed.
+    ///
+    /// This is **not secure**: its output is predictable from the seed.  Only use
+    /// it in tests, as the [`CryptoProvider::secure_random`] of a connection
+    /// passed to [`Transcript::feed()`].
+    ///
+    /// [`CryptoProvider::secure_random`]: crate::crypto::CryptoProvider::secure_random
+    /// [`Transcript::feed()`]: super::Transcript::feed
+    #[derive(Debug)]
+    pub struct DeterministicRandom {
+        state: AtomicU64,
+    }
+
+    impl DeterministicRandom {
+        /// Make a generator from `seed`.
+        pub const fn new(seed: u64) -> Self {
+            Self {
+                state: AtomicU64::new(seed),
+            }
+        }
+    }
+
+    impl SecureRandom for DeterministicRandom {
+        fn fill(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed> {
+            // SplitMix64
+            for chunk in buf.chunks_mut(8) {
+                let mut z = self
+                    .state
+                    .fetch_add(GAMMA, Ordering::Relaxed)
+                    .wrapping_add(GAMMA);
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                z ^= z >> 31;
+                chunk.copy_from_slice(&z.to_be_bytes()[..chunk.len()]);
+            }
+            Ok(())
+        }
+    }
+
+    const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+    /// An X25519 key exchange group whose private key is derived from a seed.
+    ///
+    /// This is **not secure**: every key exchange uses the same private key, which is
+    /// predictable from the seed.  Only use it in tests, as the only
+    /// [`CryptoProvider::kx_groups`] entry of a connection passed to
+    /// [`Transcript::feed()`], and of the connection that recorded the transcript.
+    ///
+    /// [`CryptoProvider::kx_groups`]: crate::crypto::CryptoProvider::kx_groups
+    /// [`Transcript::feed()`]: super::Transcript::feed
+    #[derive(Debug)]
+    pub struct DeterministicX25519 {
+        seed: u64,
+    }
+
+    impl DeterministicX25519 {
+        /// Make a group whose private key is derived from `seed`.
+        pub const fn new(seed: u64) -> Self {
+            Self { seed }
+        }
+    }
+
+    impl SupportedKxGroup for DeterministicX25519 {
+        fn start(&self) -> Result<Box<dyn ActiveKeyExchange>, Error> {
+            let mut priv_key = [0u8; 32];
+            DeterministicRandom::new(self.seed).fill(&mut priv_key)?;
+            let pub_key = x25519::scalar_mult(&priv_key, &x25519::BASE_POINT);
+            Ok(Box::new(X25519KeyExchange { priv_key, pub_key }))
+        }
+
+        fn name(&self) -> NamedGroup {
+            NamedGroup::X25519
+        }
+    }
+
+    struct X25519KeyExchange {
+        priv_key: [u8; 32],
+        pub_key: [u8; 32],
+    }
+
+    impl ActiveKeyExchange for X25519KeyExchange {
+        fn complete(self: Box<Self>, peer_pub_key: &[u8]) -> Result<SharedSecret, Error> {
+            let peer_pub_key: &[u8; 32] = peer_pub_key
+                .try_into()
+                .map_err(|_| PeerMisbehaved::InvalidKeyShare)?;
+            let shared = x25519::scalar_mult(&self.priv_key, peer_pub_key);
+            // RFC7748 section 6.1: reject low order points
+            match shared == [0u8; 32] {
+                true => Err(PeerMisbehaved::InvalidKeyShare.into()),
+                false => Ok(SharedSecret::from(&shared[..])),
+            }
+        }
+
+        fn pub_key(&self) -> &[u8] {
+            &self.pub_key
+        }
+
+        fn group(&self) -> NamedGroup {
+            NamedGroup::X25519
+        }
+    }
+
+    /// X25519 from RFC7748, with field elements as five 51-bit limbs.
+    ///
+    /// This is neither fast nor constant-time, which is fine for its only use above.
+    mod x25519 {
+        pub(super) const BASE_POINT: [u8; 32] = {
+            let mut u = [0u8; 32];
+            u[0] = 9;
+            u
+        };
+
+        pub(super) fn scalar_mult(scalar: &[u8; 32], u: &[u8; 32]) -> [u8; 32] {
+            let mut k = *scalar;
+            k[0] &= 248;
+            k[31] &= 127;
+            k[31] |= 64;
+
+            let x1 = Fe::from_bytes(u);
+            let (mut x2, mut z2) = (Fe::ONE, Fe::ZERO);
+            let (mut x3, mut z3) = (x1, Fe::ONE);
+            let mut swap = false;
+            for t in (0..255).rev() {
+                let bit = (k[t / 8] >> (t % 8)) & 1 == 1;
+                if swap != bit {
+                    core::mem::swap(&mut x2, &mut x3);
+                    core::mem::swap(&mut z2, &mut z3);
+                }
+                swap = bit;
+
+                let a = x2.add(&z2);
+                let aa = a.mul(&a);
+                let b = x2.sub(&z2);
+                let bb = b.mul(&b);
+                let e = aa.sub(&bb);
+                let c = x3.add(&z3);
+                let d = x3.sub(&z3);
+                let da = d.mul(&a);
+                let cb = c.mul(&b);
+                let sum = da.add(&cb);
+                x3 = sum.mul(&sum);
+                let diff = da.sub(&cb);
+                z3 = x1.mul(&diff.mul(&diff));
+                x2 = aa.mul(&bb);
+                z2 = e.mul(&aa.add(&A24.mul(&e)));
+            }
+            if swap {
+                core::mem::swap(&mut x2, &mut x3);
+                core::mem::swap(&mut z2, &mut z3);
+            }
+
+            x2.mul(&z2.invert()).to_bytes()
+        }
+
+        const MASK: u64 = (1 << 51) - 1;
+
+        /// (486662 - 2) / 4
+        const A24: Fe = Fe([121665, 0, 0, 0, 0]);
+
+        /// An element of GF(2^255 - 19), with every limb below 2^52.
+        #[derive(Clone, Copy)]
+        struct Fe([u64; 5]);
+
+        impl Fe {
+            const ZERO: Self = Self([0; 5]);
+            const ONE: Self = Self([1, 0, 0, 0, 0]);
+
+            fn from_bytes(bytes: &[u8; 32]) -> Self {
+                let load = |i: usize| {
+                    let mut word = [0u8; 8];
+                    word.copy_from_slice(&bytes[i..i + 8]);
+                    u64::from_le_bytes(word)
+                };
+                // the top bit is ignored, so limbs 0 to 4 hold bits 0 to 254
+                Self([
+                    load(0) & MASK,
+                    (load(6) >> 3) & MASK,
+                    (load(12) >> 6) & MASK,
+                    (load(19) >> 1) & MASK,
+                    (load(24) >> 12) & MASK,
+                ])
+            }
+
+            fn to_bytes(self) -> [u8; 32] {
+                let mut l = self.carry().0;
+                // subtract p once if l >= p, by adding 19 and dropping bit 255
+                let mut q = (l[0] + 19) >> 51;
+                for limb in &l[1..] {
+                    q = (limb + q) >> 51;
+                }
+                l[0] += 19 * q;
+                for i in 0..4 {
+                    l[i + 1] += l[i] >> 51;
+                    l[i] &= MASK;
+                }
+                l[4] &= MASK;
+
+                let mut bytes = [0u8; 32];
+                let (mut acc, mut bits, mut out) = (0u128, 0, 0);
+                for limb in l {
+                    acc |= u128::from(limb) << bits;
+                    bits += 51;
+                    while bits >= 8 {
+                        bytes[out] = acc as u8;
+                        acc >>= 8;
+                        bits -= 8;
+                        out += 1;
+                    }
+                }
+                bytes[out] = acc as u8;
+                bytes
+            }
+
+            fn add(&self, other: &Self) -> Self {
+                let mut l = self.0;
+                for (limb, other) in l.iter_mut().zip(other.0) {
+                    *limb += other;
+                }
+                Self(l).carry()
+            }
+
+            fn sub(&self, other: &Self) -> Self {
+                // add 16 * p first, so no limb underflows
+                let mut l = self.0;
+                l[0] += 16 * (MASK - 18);
+                for limb in &mut l[1..] {
+                    *limb += 16 * MASK;
+                }
+                for (limb, other) in l.iter_mut().zip(other.0) {
+                    *limb -= other;
+                }
+                Self(l).carry()
+            }
+
+            fn mul(&self, other: &Self) -> Self {
+                let m = |a: u64, b: u64| u128::from(a) * u128::from(b);
+                let [a0, a1, a2, a3, a4] = self.0;
+                let [b0, b1, b2, b3, b4] = other.0;
+                // 2^255 = 19 (mod p)
+                let (b1_19, b2_19, b3_19, b4_19) = (b1 * 19, b2 * 19, b3 * 19, b4 * 19);
+
+                let r = [
+                    m(a0, b0) + m(a1, b4_19) + m(a2, b3_19) + m(a3, b2_19) + m(a4, b1_19),
+                    m(a0, b1) + m(a1, b0) + m(a2, b4_19) + m(a3, b3_19) + m(a4, b2_19),
+                    m(a0, b2) + m(a1, b1) + m(a2, b0) + m(a3, b4_19) + m(a4, b3_19),
+                    m(a0, b3) + m(a1, b2) + m(a2, b1) + m(a3, b0) + m(a4, b4_19),
+                    m(a0, b4) + m(a1, b3) + m(a2, b2) + m(a3, b1) + m(a4, b0),
+                ];
+
+                let mut l = [0u64; 5];
+                let mut carry = 0u128;
+                for (limb, r) in l.iter_mut().zip(r) {
+                    let r = r + carry;
+                    *limb = (r as u64) & MASK;
+                    carry = r >> 51;
+                }
+                l[0] += (carry as u64) * 19;
+                Self(l).carry()
+            }
+
+            /// Raise to the power p - 2 = 2^255 - 21.
+            fn invert(&self) -> Self {
+                let mut r = Self::ONE;
+                for bit in (0..255).rev() {
+                    r = r.mul(&r);
+                    // every bit of p - 2 is set, except bits 2 and 4
+                    if bit != 2 && bit != 4 {
+                        r = r.mul(self);
+                    }
+                }
+                r
+            }
+
+            fn carry(self) -> Self {
+                let mut l = self.0;
+                for i in 0..4 {
+                    l[i + 1] += l[i] >> 51;
+                    l[i] &= MASK;
+                }
+                l[0] += (l[4] >> 51) * 19;
+                l[4] &= MASK;
+                Self(l)
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+
+            #[test]
+            fn rfc7748_test_vectors() {
+                // RFC7748 section 5.2
+                let mut scalar = [0u8; 32];
+                hex_decode(
+                    "a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4",
+                    &mut scalar,
+                );
+                let mut u = [0u8; 32];
+                hex_decode(
+                    "e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c",
+                    &mut u,
+                );
+                let mut expected = [0u8; 32];
+                hex_decode(
+                    "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552",
+                    &mut expected,
+                );
+                assert_eq!(scalar_mult(&scalar, &u), expected);
+
+                // RFC7748 section 6.1
+                let mut alice_priv = [0u8; 32];
+                hex_decode(
+                    "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a",
+                    &mut alice_priv,
+                );
+                let mut bob_priv = [0u8; 32];
+                hex_decode(
+                    "5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb",
+                    &mut bob_priv,
+                );
+                let mut alice_pub = [0u8; 32];
+                hex_decode(
+                    "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a",
+                    &mut alice_pub,
+                );
+                let mut shared = [0u8; 32];
+                hex_decode(
+                    "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742",
+                    &mut shared,
+                );
+                assert_eq!(scalar_mult(&alice_priv, &BASE_POINT), alice_pub);
+                let bob_pub = scalar_mult(&bob_priv, &BASE_POINT);
+                assert_eq!(scalar_mult(&alice_priv, &bob_pub), shared);
+                assert_eq!(scalar_mult(&bob_priv, &alice_pub), shared);
+            }
+
+            fn hex_decode(hex: &str, out: &mut [u8; 32]) {
+                for (byte, pair) in out
+                    .iter_mut()
+                    .zip(hex.as_bytes().chunks(2))
+                {
+                    *byte = u8::from_str_radix(core::str::from_utf8(pair).unwrap(), 16).unwrap();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::prelude::v1::*;
+
+    use super::danger::DeterministicRandom;
+    use super::*;
+    use crate::crypto::SecureRandom;
+
+    #[test]
+    fn records_and_decodes_transcripts() {
+        let mut reader = RecordingReader::new(&b"hello world"[..]);
+        let mut buf = [0u8; 5];
+        reader.read_exact(&mut buf).unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+
+        let transcript = reader.take_transcript();
+        assert_eq!(transcript.entries().len(), 2);
+        assert_eq!(transcript.entries()[0].data, b"hello");
+        assert_eq!(transcript.entries()[1].data, b" world");
+        assert!(reader.transcript().entries().is_empty());
+
+        let decoded = Transcript::read(&transcript.encode()).unwrap();
+        assert_eq!(decoded, transcript);
+
+        let mut truncated = transcript.encode();
+        truncated.pop();
+        assert!(Transcript::read(&truncated).is_err());
+    }
+
+    #[test]
+    fn deterministic_random_repeats() {
+        let (mut a, mut b) = ([0u8; 37], [0u8; 37]);
+        DeterministicRandom::new(1)
+            .fill(&mut a)
+            .unwrap();
+        DeterministicRandom::new(1)
+            .fill(&mut b)
+            .unwrap();
+        assert_eq!(a, b);
+        DeterministicRandom::new(2)
+            .fill(&mut b)
+            .unwrap();
+        assert_ne!(a, b);
+    }
+}
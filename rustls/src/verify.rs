@@ -1,4 +1,6 @@
+use alloc::boxed::Box;
 use alloc::vec::Vec;
+use core::any::Any;
 use core::fmt::Debug;
 
 use pki_types::{CertificateDer, ServerName, UnixTime};
@@ -52,17 +54,87 @@ impl ServerCertVerified {
     }
 }
 
-/// Zero-sized marker type representing verification of a client cert chain.
+/// Marker type representing verification of a client cert chain.
+///
+/// This can optionally carry [`ClientCertDetails`] describing how the chain
+/// was verified, which the server makes available through
+/// [`ServerConnection::client_cert_details()`].
+///
+/// [`ServerConnection::client_cert_details()`]: crate::server::ServerConnection::client_cert_details
 #[derive(Debug)]
-pub struct ClientCertVerified(());
+pub struct ClientCertVerified(Option<Box<ClientCertDetails>>);
 
 impl ClientCertVerified {
     /// Make a `ClientCertVerified`
     pub fn assertion() -> Self {
-        Self(())
+        Self(None)
+    }
+
+    /// Make a `ClientCertVerified` recording how the chain was verified.
+    pub fn with_details(details: ClientCertDetails) -> Self {
+        Self(Some(Box::new(details)))
+    }
+
+    /// How the chain was verified, if the verifier said.
+    pub fn details(&self) -> Option<&ClientCertDetails> {
+        self.0.as_deref()
+    }
+
+    pub(crate) fn into_details(self) -> Option<Box<ClientCertDetails>> {
+        self.0
     }
 }
 
+/// How a client certificate chain was verified, as reported by a [`ClientCertVerifier`].
+///
+/// The end-entity certificate itself is available from
+/// [`CommonState::peer_certificates()`].
+///
+/// [`CommonState::peer_certificates()`]: crate::CommonState::peer_certificates
+#[non_exhaustive]
+#[derive(Clone, Debug, Default)]
+pub struct ClientCertDetails {
+    /// The intermediate certificates in the verified path, starting with the
+    /// issuer of the end-entity certificate.
+    ///
+    /// These need not be all the intermediates the client sent, nor in the
+    /// order it sent them.
+    pub intermediates: Vec<CertificateDer<'static>>,
+
+    /// The subject of the trust anchor the path ends at.
+    pub trust_anchor_subject: Option<DistinguishedName>,
+
+    /// The key purpose OIDs in the end-entity certificate's extended key usage
+    /// extension, as the contents of each DER-encoded OBJECT IDENTIFIER.
+    pub extended_key_usages: Vec<Vec<u8>>,
+
+    /// The policy OIDs in the end-entity certificate's certificate policies
+    /// extension, as the contents of each DER-encoded OBJECT IDENTIFIER.
+    pub policies: Vec<Vec<u8>>,
+
+    /// Which certificates were checked for revocation.
+    pub revocation: RevocationCheck,
+}
+
+/// Which certificates in a verified chain were checked for revocation.
+///
+/// A certificate is only reported as checked if it was found not to be revoked.
+/// If unknown revocation status was allowed, a certificate that no CRL covers
+/// also counts as checked.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RevocationCheck {
+    /// No revocation checking was done.
+    #[default]
+    NotChecked,
+
+    /// Only the end-entity certificate was checked.
+    EndEntity,
+
+    /// The end-entity certificate and every intermediate were checked.
+    Chain,
+}
+
 /// Something that can verify a server certificate chain, and verify
 /// signatures made by certificates.
 #[allow(unreachable_pub)]
@@ -88,6 +160,27 @@ pub trait ServerCertVerifier: Debug + Send + Sync {
         now: UnixTime,
     ) -> Result<ServerCertVerified, Error>;
 
+    /// Verify the server's certificate chain, given the application data attached to
+    /// the connection.
+    ///
+    /// rustls calls this, rather than [`ServerCertVerifier::verify_server_cert`], with
+    /// the data attached with [`CommonState::set_user_data()`], if any.  The default
+    /// implementation ignores `user_data` and calls [`ServerCertVerifier::verify_server_cert`].
+    ///
+    /// [`CommonState::set_user_data()`]: crate::CommonState::set_user_data
+    fn verify_server_cert_with_user_data(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+        user_data: Option<&(dyn Any + Send)>,
+    ) -> Result<ServerCertVerified, Error> {
+        let _ = user_data;
+        self.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
     /// Verify a signature allegedly by the given server certificate.
     ///
     /// `message` is not hashed, and needs hashing during the verification.
@@ -136,6 +229,53 @@ pub trait ServerCertVerifier: Debug + Send + Sync {
     ///
     /// This should be in priority order, with the most preferred first.
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme>;
+
+    /// Check the OCSP responses stapled to each certificate in the server's chain.
+    ///
+    /// `ocsp_responses` has one item for each certificate: the first is for
+    /// `end_entity`, and the rest are in the same order as `intermediates`.  An item
+    /// is empty if no response was stapled to that certificate.  Only TLS1.3 allows
+    /// stapling responses for intermediate certificates.
+    ///
+    /// This is called after a successful [`ServerCertVerifier::verify_server_cert`].
+    /// Return an error to reject the certificate chain, or the [`RevocationStatus`]
+    /// of each certificate in the same order as `ocsp_responses`.  These are available
+    /// to the application from [`ClientConnection::peer_revocation_status()`].
+    ///
+    /// The default implementation does no checking, and reports every certificate
+    /// as [`RevocationStatus::Unknown`].
+    ///
+    /// [`ClientConnection::peer_revocation_status()`]: crate::client::ClientConnection::peer_revocation_status
+    fn check_stapled_ocsp(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        ocsp_responses: &[&[u8]],
+        now: UnixTime,
+    ) -> Result<Vec<RevocationStatus>, Error> {
+        let _ = (end_entity, intermediates, now);
+        Ok(ocsp_responses
+            .iter()
+            .map(|_| RevocationStatus::Unknown)
+            .collect())
+    }
+}
+
+/// The revocation status of one certificate in the server's chain.
+///
+/// This is determined by [`ServerCertVerifier::check_stapled_ocsp`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RevocationStatus {
+    /// The revocation status of the certificate was not determined.
+    Unknown,
+
+    /// A valid OCSP response attests that the certificate is not revoked.
+    Good,
+
+    /// A valid OCSP response says the certificate is revoked, but the
+    /// verifier chose to accept the certificate chain anyway.
+    Revoked,
 }
 
 /// Something that can verify a client certificate chain
@@ -206,6 +346,25 @@ pub trait ClientCertVerifier: Debug + Send + Sync {
         now: UnixTime,
     ) -> Result<ClientCertVerified, Error>;
 
+    /// Verify the client's certificate chain, given the application data attached to
+    /// the connection.
+    ///
+    /// rustls calls this, rather than [`ClientCertVerifier::verify_client_cert`], with
+    /// the data attached with [`CommonState::set_user_data()`], if any.  The default
+    /// implementation ignores `user_data` and calls [`ClientCertVerifier::verify_client_cert`].
+    ///
+    /// [`CommonState::set_user_data()`]: crate::CommonState::set_user_data
+    fn verify_client_cert_with_user_data(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+        user_data: Option<&(dyn Any + Send)>,
+    ) -> Result<ClientCertVerified, Error> {
+        let _ = user_data;
+        self.verify_client_cert(end_entity, intermediates, now)
+    }
+
     /// Verify a signature allegedly by the given client certificate.
     ///
     /// `message` is not hashed, and needs hashing during the verification.
@@ -341,7 +500,7 @@ fn assertions_are_debug() {
 
     assert_eq!(
         format!("{:?}", ClientCertVerified::assertion()),
-        "ClientCertVerified(())"
+        "ClientCertVerified(None)"
     );
     assert_eq!(
         format!("{:?}", HandshakeSignatureValid::assertion()),
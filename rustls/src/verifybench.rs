@@ -199,10 +199,7 @@ impl Context {
                 .map(|bytes| CertificateDer::from(bytes.to_vec()))
                 .collect(),
             now: UnixTime::since_unix_epoch(Duration::from_secs(1_640_870_720)),
-            verifier: WebPkiServerVerifier::new_without_revocation(
-                roots,
-                provider.signature_verification_algorithms,
-            ),
+            verifier: WebPkiServerVerifier::new_without_revocation(roots, &provider),
         }
     }
 
@@ -1,7 +1,7 @@
 use alloc::vec::Vec;
-use alloc::{fmt, format};
+use alloc::{fmt, format, vec};
 
-use pki_types::{CertificateDer, TrustAnchor};
+use pki_types::{CertificateDer, Der, TrustAnchor};
 use webpki::anchor_from_trusted_cert;
 
 use super::pki_error;
@@ -11,6 +11,14 @@ use crate::{DistinguishedName, Error};
 
 /// A container for root certificates able to provide a root-of-trust
 /// for connection authentication.
+///
+/// The methods that add roots do not add one that is already in the store.
+/// Changing `roots` directly bypasses that.
+///
+/// When many configurations use the same roots, they need not each hold a copy:
+/// load them with [`RootCertStore::add_der_region()`] to borrow them from
+/// (for example) a `static` byte array, and make one `Arc<RootCertStore>` whose
+/// clones are given to each verifier.
 #[derive(Clone)]
 pub struct RootCertStore {
     /// The list of roots.
@@ -29,13 +37,15 @@ impl RootCertStore {
     /// This is because large collections of root certificates often
     /// include ancient or syntactically invalid certificates.
     ///
-    /// Returns the number of certificates added, and the number that were ignored.
+    /// Returns the number of valid certificates (including any already in the store),
+    /// and the number that were ignored.
     pub fn add_parsable_certificates<'a>(
         &mut self,
         der_certs: impl IntoIterator<Item = CertificateDer<'a>>,
     ) -> (usize, usize) {
         let mut valid_count = 0;
         let mut invalid_count = 0;
+        let start = self.roots.len();
 
         for der_cert in der_certs {
             #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
@@ -52,6 +62,7 @@ impl RootCertStore {
             };
         }
 
+        self.remove_duplicates_from(start);
         debug!(
             "add_parsable_certificates processed {} valid and {} invalid certs",
             valid_count, invalid_count
@@ -70,14 +81,63 @@ impl RootCertStore {
     /// in order to add as many valid roots as possible and to understand how many certificates
     /// have been diagnosed as malformed.
     pub fn add(&mut self, der: CertificateDer<'_>) -> Result<(), Error> {
-        self.roots.push(
-            anchor_from_trusted_cert(&der)
-                .map_err(pki_error)?
-                .to_owned(),
-        );
+        let anchor = anchor_from_trusted_cert(&der)
+            .map_err(pki_error)?
+            .to_owned();
+        if !self.roots.contains(&anchor) {
+            self.roots.push(anchor);
+        }
         Ok(())
     }
 
+    /// Add the certificates in `region`, a concatenation of DER-encoded certificates,
+    /// in a best-effort fashion like [`RootCertStore::add_parsable_certificates`].
+    ///
+    /// The roots borrow from `region`, rather than being copied: this saves memory
+    /// when `region` is (for example) a `static` array made with `include_bytes!`.
+    /// The certificates are still all parsed by this function.
+    ///
+    /// Returns the number of valid certificates (including any already in the store),
+    /// and the number that were ignored.  If `region` does not divide into DER
+    /// elements, the undivided remainder is ignored, counting as one certificate.
+    pub fn add_der_region(&mut self, region: &'static [u8]) -> (usize, usize) {
+        let mut valid_count = 0;
+        let mut invalid_count = 0;
+        let start = self.roots.len();
+
+        let mut rest = region;
+        while !rest.is_empty() {
+            let cert = match take_der_element(&mut rest) {
+                Some(cert) => cert,
+                None => {
+                    invalid_count += 1;
+                    break;
+                }
+            };
+
+            #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+            match anchor_from_trusted_cert(&CertificateDer::from(cert)) {
+                Ok(anchor) => {
+                    self.roots
+                        .push(borrowed_anchor(&anchor, cert).unwrap_or_else(|| anchor.to_owned()));
+                    valid_count += 1;
+                }
+                Err(err) => {
+                    debug!("certificate parsing failed: {:?}", err);
+                    invalid_count += 1;
+                }
+            }
+        }
+
+        self.remove_duplicates_from(start);
+        debug!(
+            "add_der_region processed {} valid and {} invalid certs",
+            valid_count, invalid_count
+        );
+
+        (valid_count, invalid_count)
+    }
+
     /// Return the DER encoded [`DistinguishedName`] of each trust anchor subject in the root
     /// cert store.
     ///
@@ -101,19 +161,56 @@ impl RootCertStore {
     pub fn len(&self) -> usize {
         self.roots.len()
     }
+
+    /// Remove the roots from index `start` onwards that duplicate an earlier one.
+    ///
+    /// This sorts the roots, rather than comparing each new root with every other.
+    fn remove_duplicates_from(&mut self, start: usize) {
+        let mut order = (0..self.roots.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| anchor_key(&self.roots[*a]).cmp(&anchor_key(&self.roots[*b])));
+
+        let mut duplicate = vec![false; self.roots.len()];
+        for pair in order.windows(2) {
+            let (first, second) = (pair[0], pair[1]);
+            // a stable sort keeps equal roots in index order
+            if second >= start && self.roots[first] == self.roots[second] {
+                duplicate[second] = true;
+            }
+        }
+
+        let mut index = 0;
+        self.roots.retain(|_| {
+            index += 1;
+            !duplicate[index - 1]
+        });
+    }
+}
+
+/// The fields of `anchor`, for ordering roots.
+fn anchor_key<'a>(anchor: &'a TrustAnchor<'_>) -> (&'a [u8], &'a [u8], Option<&'a [u8]>) {
+    (
+        anchor.subject.as_ref(),
+        anchor.subject_public_key_info.as_ref(),
+        anchor
+            .name_constraints
+            .as_ref()
+            .map(|nc| nc.as_ref()),
+    )
 }
 
 impl FromIterator<TrustAnchor<'static>> for RootCertStore {
     fn from_iter<T: IntoIterator<Item = TrustAnchor<'static>>>(iter: T) -> Self {
-        Self {
-            roots: iter.into_iter().collect(),
-        }
+        let mut store = Self::empty();
+        store.extend(iter);
+        store
     }
 }
 
 impl Extend<TrustAnchor<'static>> for RootCertStore {
     fn extend<T: IntoIterator<Item = TrustAnchor<'static>>>(&mut self, iter: T) {
+        let start = self.roots.len();
         self.roots.extend(iter);
+        self.remove_duplicates_from(start);
     }
 }
 
@@ -125,21 +222,106 @@ impl fmt::Debug for RootCertStore {
     }
 }
 
+/// Remove a DER element from the front of `input`, returning the whole element.
+fn take_der_element(input: &mut &'static [u8]) -> Option<&'static [u8]> {
+    let (header, len) = match input {
+        [_, len @ 0..=0x7f, ..] => (2, usize::from(*len)),
+        [_, 0x81, len, ..] => (3, usize::from(*len)),
+        [_, 0x82, hi, lo, ..] => (4, usize::from(*hi) << 8 | usize::from(*lo)),
+        [_, 0x83, b2, b1, b0, ..] => (
+            5,
+            usize::from(*b2) << 16 | usize::from(*b1) << 8 | usize::from(*b0),
+        ),
+        _ => return None,
+    };
+
+    let total = header + len;
+    let element = input.get(..total)?;
+    *input = &input[total..];
+    Some(element)
+}
+
+/// Make a copy of `anchor` borrowing from `cert`, the certificate it was parsed from.
+fn borrowed_anchor(anchor: &TrustAnchor<'_>, cert: &'static [u8]) -> Option<TrustAnchor<'static>> {
+    Some(TrustAnchor {
+        subject: Der::from_slice(rebase(&anchor.subject, cert)?),
+        subject_public_key_info: Der::from_slice(rebase(&anchor.subject_public_key_info, cert)?),
+        name_constraints: match &anchor.name_constraints {
+            Some(nc) => Some(Der::from_slice(rebase(nc, cert)?)),
+            None => None,
+        },
+    })
+}
+
+/// Find `inner` within `outer`, if it is a subslice of it.
+fn rebase<'a>(inner: &[u8], outer: &'a [u8]) -> Option<&'a [u8]> {
+    let start = (inner.as_ptr() as usize).checked_sub(outer.as_ptr() as usize)?;
+    outer.get(start..start.checked_add(inner.len())?)
+}
+
 #[test]
 fn root_cert_store_debug() {
-    use core::iter;
-
     use pki_types::Der;
 
-    let ta = TrustAnchor {
-        subject: Der::from_slice(&[]),
+    let store = RootCertStore::from_iter((0..138u8).map(|i| TrustAnchor {
+        subject: Der::from(vec![i]),
         subject_public_key_info: Der::from_slice(&[]),
         name_constraints: None,
-    };
-    let store = RootCertStore::from_iter(iter::repeat(ta).take(138));
+    }));
 
     assert_eq!(
         format!("{:?}", store),
         "RootCertStore { roots: \"(138 roots)\" }"
     );
 }
+
+#[test]
+fn root_cert_store_deduplicates() {
+    let der = CertificateDer::from(&include_bytes!("../../../test-ca/rsa-2048/ca.der")[..]);
+    let mut store = RootCertStore::empty();
+    store.add(der.clone()).unwrap();
+    store.add(der.clone()).unwrap();
+    assert_eq!(store.add_parsable_certificates([der]), (1, 0));
+    assert_eq!(store.len(), 1);
+}
+
+#[test]
+fn root_cert_store_add_der_region() {
+    static REGION: &[u8] = include_bytes!("../../../test-ca/rsa-2048/ca.der");
+
+    let mut store = RootCertStore::empty();
+    assert_eq!(store.add_der_region(REGION), (1, 0));
+    let subject: &[u8] = store.roots[0].subject.as_ref();
+    assert!(REGION
+        .as_ptr_range()
+        .contains(&subject.as_ptr()));
+
+    let mut copied = RootCertStore::empty();
+    copied
+        .add(CertificateDer::from(REGION))
+        .unwrap();
+    assert_eq!(store.roots, copied.roots);
+
+    // truncated
+    assert_eq!(
+        RootCertStore::empty().add_der_region(&REGION[..REGION.len() - 1]),
+        (0, 1)
+    );
+}
+
+#[test]
+fn root_cert_store_extend_deduplicates() {
+    let der = CertificateDer::from(&include_bytes!("../../../test-ca/rsa-2048/ca.der")[..]);
+    let other = CertificateDer::from(&include_bytes!("../../../test-ca/ecdsa-p256/ca.der")[..]);
+    let anchor = |der| {
+        anchor_from_trusted_cert(der)
+            .unwrap()
+            .to_owned()
+    };
+
+    let mut store = RootCertStore::from_iter([anchor(&der), anchor(&other), anchor(&der)]);
+    assert_eq!(store.roots, [anchor(&der), anchor(&other)]);
+
+    store.extend([anchor(&other), anchor(&der)]);
+    assert_eq!(store.len(), 2);
+}
@@ -0,0 +1,331 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use std::path::Path;
+use std::{env, fs, io};
+
+use super::anchors::RootCertStore;
+use crate::error::Error;
+
+/// How many certificates were processed when loading a bundle of roots
+/// into a [`RootCertStore`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RootLoadStats {
+    /// Certificates that were added to the store, or were already in it.
+    pub valid: usize,
+    /// Certificates that could not be used as trust anchors.
+    pub invalid: usize,
+    /// Other items, such as PEM sections that are not certificates, files in a
+    /// directory that are not named like certificates, or CCADB entries that
+    /// are not trusted for websites.
+    pub skipped: usize,
+}
+
+impl RootLoadStats {
+    fn add(&mut self, (valid, invalid): (usize, usize)) {
+        self.valid += valid;
+        self.invalid += invalid;
+    }
+}
+
+impl RootCertStore {
+    /// Add the roots in the operating system's bundle of trusted certificates.
+    ///
+    /// The bundle is the file named by the `SSL_CERT_FILE` environment variable,
+    /// if set, and otherwise the first that exists of the well-known locations
+    /// used by common Linux and BSD distributions (such as
+    /// `/etc/ssl/certs/ca-certificates.crt`).  It is read with
+    /// [`RootCertStore::add_pem_file()`].
+    ///
+    /// This fails if no bundle is found, or it cannot be read.
+    pub fn add_system_bundle(&mut self) -> Result<RootLoadStats, Error> {
+        if let Some(path) = env::var_os("SSL_CERT_FILE") {
+            return self.add_pem_file(path);
+        }
+
+        match SYSTEM_BUNDLES
+            .iter()
+            .map(Path::new)
+            .find(|path| path.is_file())
+        {
+            Some(path) => self.add_pem_file(path),
+            None => Err(Error::General("no system certificate bundle found".into())),
+        }
+    }
+
+    /// Add the certificates in a PEM file, such as a `ca-certificates.crt` bundle.
+    ///
+    /// Certificates that cannot be used as trust anchors are ignored, like
+    /// [`RootCertStore::add_parsable_certificates()`], and other PEM sections
+    /// are skipped.  If the file is not valid PEM, the certificates before the
+    /// problem are added, and the problem counts as one invalid certificate.
+    pub fn add_pem_file(&mut self, path: impl AsRef<Path>) -> Result<RootLoadStats, Error> {
+        let path = path.as_ref();
+        let pem = fs::read(path).map_err(|err| io_error(path, err))?;
+        let mut stats = RootLoadStats::default();
+        self.add_pem(&pem, &mut stats);
+        Ok(stats)
+    }
+
+    /// Add the certificates in a directory of PEM files named by subject hash, as
+    /// made by OpenSSL's `c_rehash` (for example `/etc/ssl/certs`).
+    ///
+    /// Files named like `5ad8a5d6.0` are read as for [`RootCertStore::add_pem_file()`],
+    /// and others are skipped.  Unreadable files count as one invalid certificate.
+    ///
+    /// This fails if the directory cannot be read.
+    pub fn add_hashed_dir(&mut self, dir: impl AsRef<Path>) -> Result<RootLoadStats, Error> {
+        let dir = dir.as_ref();
+        let mut names = fs::read_dir(dir)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.file_name()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|err| io_error(dir, err))?;
+        // for a deterministic order of roots
+        names.sort();
+
+        let mut stats = RootLoadStats::default();
+        for name in names {
+            let is_hashed = name
+                .to_str()
+                .map_or(false, is_hashed_name);
+            if !is_hashed {
+                stats.skipped += 1;
+                continue;
+            }
+
+            match fs::read(dir.join(name)) {
+                Ok(pem) => self.add_pem(&pem, &mut stats),
+                Err(_) => stats.invalid += 1,
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Add the certificates in a CSV report from the [CCADB], such as Mozilla's
+    /// `MozillaIncludedCACertificateReportPEMCSV`.
+    ///
+    /// Certificates are taken from the `PEM Info` column.  If there is a
+    /// `Trust Bits` column, rows that do not include `Websites` are skipped.
+    ///
+    /// This fails if `csv` has no `PEM Info` column, or is not valid CSV.
+    ///
+    /// [CCADB]: https://www.ccadb.org/resources
+    pub fn add_ccadb_csv(&mut self, csv: &[u8]) -> Result<RootLoadStats, Error> {
+        let csv = core::str::from_utf8(csv)
+            .map_err(|_| Error::General("CCADB report is not UTF-8".into()))?;
+        let mut rows = csv_rows(csv)?.into_iter();
+        let header = rows.next().unwrap_or_default();
+        let column = |name: &str| {
+            header
+                .iter()
+                .position(|field| field.trim() == name)
+        };
+        let pem_column = column("PEM Info")
+            .ok_or_else(|| Error::General("CCADB report has no PEM Info column".into()))?;
+        let trust_column = column("Trust Bits");
+
+        let mut stats = RootLoadStats::default();
+        for row in rows {
+            if row.iter().all(|field| field.is_empty()) {
+                continue;
+            }
+
+            let trusted = match trust_column {
+                Some(trust_column) => row
+                    .get(trust_column)
+                    .map_or(false, |bits| {
+                        bits.split(';')
+                            .any(|bit| bit.trim() == "Websites")
+                    }),
+                None => true,
+            };
+            if !trusted {
+                stats.skipped += 1;
+                continue;
+            }
+
+            match row.get(pem_column) {
+                // CCADB quotes the PEM in single quotes
+                Some(pem) => self.add_pem(pem.trim_matches('\'').as_bytes(), &mut stats),
+                None => stats.invalid += 1,
+            }
+        }
+        Ok(stats)
+    }
+
+    fn add_pem(&mut self, mut pem: &[u8], stats: &mut RootLoadStats) {
+        let mut certs = Vec::new();
+        for item in rustls_pemfile::read_all(&mut pem) {
+            match item {
+                Ok(rustls_pemfile::Item::X509Certificate(cert)) => certs.push(cert),
+                Ok(_) => stats.skipped += 1,
+                Err(_) => {
+                    stats.invalid += 1;
+                    break;
+                }
+            }
+        }
+        stats.add(self.add_parsable_certificates(certs));
+    }
+}
+
+/// Whether `name` looks like `[0-9a-f]{8}.[0-9]+`.
+fn is_hashed_name(name: &str) -> bool {
+    match name.split_once('.') {
+        Some((hash, suffix)) => {
+            hash.len() == 8
+                && hash
+                    .bytes()
+                    .all(|b| b.is_ascii_hexdigit())
+                && !suffix.is_empty()
+                && suffix
+                    .bytes()
+                    .all(|b| b.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// Split `csv` into rows of fields, per RFC 4180.
+fn csv_rows(csv: &str) -> Result<Vec<Vec<String>>, Error> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = csv.chars().peekable();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => row.push(core::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {}
+            (false, '\n') => {
+                row.push(core::mem::take(&mut field));
+                rows.push(core::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(Error::General("unterminated quote in CCADB report".into()));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn io_error(path: &Path, err: io::Error) -> Error {
+    Error::General(format!("failed to read {}: {}", path.display(), err))
+}
+
+/// Where Linux and BSD distributions keep their bundle of trusted certificates.
+const SYSTEM_BUNDLES: &[&str] = &[
+    // Debian, Ubuntu, Arch, Gentoo
+    "/etc/ssl/certs/ca-certificates.crt",
+    // Fedora, RHEL
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/pki/ca-trust/extracted/pem/tls-ca-bundle.pem",
+    // openSUSE
+    "/etc/ssl/ca-bundle.pem",
+    // Alpine, macOS, OpenBSD
+    "/etc/ssl/cert.pem",
+    // FreeBSD
+    "/usr/local/share/certs/ca-root-nss.crt",
+];
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+    use std::prelude::v1::*;
+
+    use super::*;
+
+    const CA_PEM: &str = include_str!("../../../test-ca/rsa-2048/ca.cert");
+
+    #[test]
+    fn parses_csv() {
+        assert_eq!(
+            csv_rows("a,\"b,\"\"c\"\"\"\r\n\"multi\nline\",\n").unwrap(),
+            vec![
+                vec!["a".to_string(), "b,\"c\"".to_string()],
+                vec!["multi\nline".to_string(), String::new()],
+            ]
+        );
+        assert!(csv_rows("\"open").is_err());
+    }
+
+    #[test]
+    fn recognises_hashed_names() {
+        assert!(is_hashed_name("5ad8a5d6.0"));
+        assert!(is_hashed_name("5AD8A5D6.12"));
+        assert!(!is_hashed_name("5ad8a5d6.r0"));
+        assert!(!is_hashed_name("ca.pem"));
+        assert!(!is_hashed_name("5ad8a5d6"));
+    }
+
+    #[test]
+    fn loads_ccadb_csv() {
+        let csv = format!(
+            "CA Owner,Trust Bits,PEM Info\n\
+             Test,Email;Websites,\"'{CA_PEM}'\"\n\
+             Test,Email,\"'{CA_PEM}'\"\n\
+             Broken,Websites,\"'-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----'\"\n"
+        );
+        let mut store = RootCertStore::empty();
+        assert_eq!(
+            store
+                .add_ccadb_csv(csv.as_bytes())
+                .unwrap(),
+            RootLoadStats {
+                valid: 1,
+                invalid: 1,
+                skipped: 1,
+            }
+        );
+        assert_eq!(store.len(), 1);
+
+        assert!(RootCertStore::empty()
+            .add_ccadb_csv(b"CA Owner,Certificate\n")
+            .is_err());
+    }
+
+    #[test]
+    fn loads_hashed_dir() {
+        let dir = env::temp_dir().join(format!("rustls-webpki-bundle-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("0123abcd.0"), CA_PEM).unwrap();
+        fs::write(
+            dir.join("0123abcd.1"),
+            include_str!("../../../test-ca/ecdsa-p256/ca.cert"),
+        )
+        .unwrap();
+        fs::write(dir.join("README"), "not a certificate").unwrap();
+
+        let mut store = RootCertStore::empty();
+        let stats = store.add_hashed_dir(&dir);
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(
+            stats.unwrap(),
+            RootLoadStats {
+                valid: 2,
+                invalid: 0,
+                skipped: 1,
+            }
+        );
+        assert_eq!(store.len(), 2);
+    }
+}
@@ -11,14 +11,16 @@ use crate::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
 #[cfg(doc)]
 use crate::server::ServerConfig;
 use crate::verify::{
-    ClientCertVerified, ClientCertVerifier, DigitallySignedStruct, HandshakeSignatureValid,
-    NoClientAuth,
+    ClientCertDetails, ClientCertVerified, ClientCertVerifier, DigitallySignedStruct,
+    HandshakeSignatureValid, NoClientAuth, RevocationCheck,
 };
 use crate::webpki::parse_crls;
-use crate::webpki::verify::{verify_tls12_signature, verify_tls13_signature, ParsedCertificate};
+use crate::webpki::verify::{
+    check_chain_depth, verify_tls12_signature, verify_tls13_signature, ParsedCertificate,
+};
 #[cfg(doc)]
 use crate::ConfigBuilder;
-use crate::{DistinguishedName, Error, RootCertStore, SignatureScheme};
+use crate::{x509, DistinguishedName, Error, RootCertStore, SignatureScheme};
 
 /// A builder for configuring a `webpki` client certificate verifier.
 ///
@@ -33,6 +35,7 @@ pub struct ClientCertVerifierBuilder {
     revocation_expiration_policy: ExpirationPolicy,
     anon_policy: AnonymousClientPolicy,
     supported_algs: WebPkiSupportedAlgorithms,
+    max_chain_depth: Option<usize>,
 }
 
 impl ClientCertVerifierBuilder {
@@ -49,6 +52,7 @@ impl ClientCertVerifierBuilder {
             unknown_revocation_policy: UnknownStatusPolicy::Deny,
             revocation_expiration_policy: ExpirationPolicy::Ignore,
             supported_algs,
+            max_chain_depth: None,
         }
     }
 
@@ -83,6 +87,35 @@ impl ClientCertVerifierBuilder {
         self
     }
 
+    /// Keep only the trust anchor hint subjects for which `keep` returns true.
+    ///
+    /// Use this to avoid advertising the names of internal or rarely-used certificate
+    /// authorities, while still accepting client certificates they issued.
+    ///
+    /// See [`ClientCertVerifier::root_hint_subjects`] for more information on
+    /// circumstances where you may want to override the default hint subjects.
+    pub fn retain_root_hint_subjects(
+        mut self,
+        keep: impl FnMut(&DistinguishedName) -> bool,
+    ) -> Self {
+        self.root_hint_subjects.retain(keep);
+        self
+    }
+
+    /// Send at most `max` trust anchor hint subjects.
+    ///
+    /// Hint subjects are sent in the order they were added, so the subjects from the
+    /// root cert store come first.  If there are more than `max`, the remainder are
+    /// dropped.  A large list of hint subjects can make a `CertificateRequest`
+    /// message very large.
+    ///
+    /// See [`ClientCertVerifier::root_hint_subjects`] for more information on
+    /// circumstances where you may want to override the default hint subjects.
+    pub fn limit_root_hint_subjects(mut self, max: usize) -> Self {
+        self.root_hint_subjects.truncate(max);
+        self
+    }
+
     /// Verify the revocation state of presented client certificates against the provided
     /// certificate revocation lists (CRLs). Calling `with_crls` multiple times appends the
     /// given CRLs to the existing collection.
@@ -153,6 +186,22 @@ impl ClientCertVerifierBuilder {
         self
     }
 
+    /// Limit the number of intermediate certificates between the end-entity
+    /// certificate and the trust anchor.
+    ///
+    /// For example, a depth of 0 only accepts certificates issued directly by a trust
+    /// anchor, like OpenSSL's verify depth.  Chains that are too deep are rejected with
+    /// [`CertificateError::ChainTooDeep`].
+    ///
+    /// By default, and at most, webpki allows six intermediate certificates; larger
+    /// values have no effect.
+    ///
+    /// [`CertificateError::ChainTooDeep`]: crate::CertificateError::ChainTooDeep
+    pub fn with_max_chain_depth(mut self, depth: usize) -> Self {
+        self.max_chain_depth = Some(depth);
+        self
+    }
+
     /// Build a client certificate verifier. The built verifier will be used for the server to offer
     /// client certificate authentication, to control how offered client certificates are validated,
     /// and to determine what to do with anonymous clients that do not respond to the client
@@ -183,6 +232,7 @@ impl ClientCertVerifierBuilder {
             self.revocation_expiration_policy,
             self.anon_policy,
             self.supported_algs,
+            self.max_chain_depth,
         )))
     }
 }
@@ -256,6 +306,7 @@ pub struct WebPkiClientVerifier {
     revocation_expiration_policy: ExpirationPolicy,
     anonymous_policy: AnonymousClientPolicy,
     supported_algs: WebPkiSupportedAlgorithms,
+    max_chain_depth: Option<usize>,
 }
 
 impl WebPkiClientVerifier {
@@ -316,6 +367,7 @@ impl WebPkiClientVerifier {
     /// * `anonymous_policy` controls whether client authentication is required, or if anonymous
     ///   clients can connect.
     /// * `supported_algs` specifies which signature verification algorithms should be used.
+    /// * `max_chain_depth` limits the number of intermediate certificates, if given.
     pub(crate) fn new(
         roots: Arc<RootCertStore>,
         root_hint_subjects: Vec<DistinguishedName>,
@@ -325,6 +377,7 @@ impl WebPkiClientVerifier {
         revocation_expiration_policy: ExpirationPolicy,
         anonymous_policy: AnonymousClientPolicy,
         supported_algs: WebPkiSupportedAlgorithms,
+        max_chain_depth: Option<usize>,
     ) -> Self {
         Self {
             roots,
@@ -335,6 +388,7 @@ impl WebPkiClientVerifier {
             revocation_expiration_policy,
             anonymous_policy,
             supported_algs,
+            max_chain_depth,
         }
     }
 }
@@ -388,10 +442,35 @@ impl ClientCertVerifier for WebPkiClientVerifier {
                 now,
                 webpki::KeyUsage::client_auth(),
                 revocation,
-                None,
+                Some(&|path: &webpki::VerifiedPath<'_>| {
+                    check_chain_depth(path, self.max_chain_depth)
+                }),
             )
             .map_err(pki_error)
-            .map(|_| ClientCertVerified::assertion())
+            .map(|path| {
+                ClientCertVerified::with_details(ClientCertDetails {
+                    intermediates: path
+                        .intermediate_certificates()
+                        .map(|cert| cert.der().into_owned())
+                        .collect(),
+                    trust_anchor_subject: Some(DistinguishedName::in_sequence(
+                        path.anchor().subject.as_ref(),
+                    )),
+                    extended_key_usages: x509::extended_key_usages(end_entity)
+                        .into_iter()
+                        .map(<[u8]>::to_vec)
+                        .collect(),
+                    policies: x509::certificate_policies(end_entity)
+                        .into_iter()
+                        .map(<[u8]>::to_vec)
+                        .collect(),
+                    revocation: match (self.crls.is_empty(), self.revocation_check_depth) {
+                        (true, _) => RevocationCheck::NotChecked,
+                        (false, RevocationCheckDepth::EndEntity) => RevocationCheck::EndEntity,
+                        (false, RevocationCheckDepth::Chain) => RevocationCheck::Chain,
+                    },
+                })
+            })
     }
 
     fn verify_tls12_signature(
@@ -9,11 +9,16 @@ use webpki::{CertRevocationList, OwnedCertRevocationList};
 use crate::error::{CertRevocationListError, CertificateError, Error, OtherError};
 
 mod anchors;
+#[cfg(feature = "trust_bundles")]
+mod bundle;
 mod client_verifier;
+mod ocsp;
 mod server_verifier;
 mod verify;
 
 pub use anchors::RootCertStore;
+#[cfg(feature = "trust_bundles")]
+pub use bundle::RootLoadStats;
 pub use client_verifier::{ClientCertVerifierBuilder, WebPkiClientVerifier};
 pub use server_verifier::{ServerCertVerifierBuilder, WebPkiServerVerifier};
 // Conditionally exported from crate.
@@ -63,6 +68,7 @@ fn pki_error(error: webpki::Error) -> Error {
         UnknownRevocationStatus => CertificateError::UnknownRevocationStatus.into(),
         CrlExpired => CertificateError::ExpiredRevocationList.into(),
         IssuerNotCrlSigner => CertRevocationListError::IssuerInvalidForCrl.into(),
+        MaximumPathDepthExceeded => CertificateError::ChainTooDeep.into(),
 
         InvalidSignatureForPublicKey
         | UnsupportedSignatureAlgorithm
@@ -0,0 +1,320 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Formatter};
+use core::time::Duration;
+
+use pki_types::{CertificateDer, SignatureVerificationAlgorithm, UnixTime};
+
+use crate::crypto::hash::{self, HashAlgorithm};
+use crate::crypto::CryptoProvider;
+use crate::verify::RevocationStatus;
+use crate::webpki::RootCertStore;
+use crate::x509::{self, BasicOcspResponse, CertId, OcspCertStatus, SignedData};
+
+/// How long after its `thisUpdate` time a response without a `nextUpdate` time is current.
+///
+/// Newer information is always available for such a response ([RFC 6960 section 4.2.2.1]),
+/// so it is only trusted for as long as the CA/Browser Forum Baseline Requirements allow
+/// any OCSP response to be valid.
+///
+/// [RFC 6960 section 4.2.2.1]: https://www.rfc-editor.org/rfc/rfc6960#section-4.2.2.1
+const MAX_AGE_WITHOUT_NEXT_UPDATE: Duration = Duration::from_secs(10 * 24 * 60 * 60);
+
+/// Check a stapled OCSP response for `cert`.
+///
+/// The response must be signed by the issuer of `cert`, or by a certificate included
+/// in the response which that issuer authorized to sign OCSP responses, as described
+/// in [RFC 6960 section 4.2.2.2].  The issuer is looked for among `chain`, then `roots`.
+///
+/// The response is about `cert` if it has a `CertID` naming the issuer that verified it,
+/// by the hashes of its name and public key, and the serial number of `cert`, as
+/// described in [RFC 6960 section 4.1.1].  `hashes` computes those hashes.
+///
+/// Returns `None` if `response` is empty, malformed, not verified, not current at
+/// `now`, or not about `cert`.
+///
+/// [RFC 6960 section 4.1.1]: https://www.rfc-editor.org/rfc/rfc6960#section-4.1.1
+/// [RFC 6960 section 4.2.2.2]: https://www.rfc-editor.org/rfc/rfc6960#section-4.2.2.2
+pub(super) fn check_response(
+    cert: &CertificateDer<'_>,
+    chain: &[&CertificateDer<'_>],
+    roots: &RootCertStore,
+    response: &[u8],
+    now: UnixTime,
+    supported_algs: &[&dyn SignatureVerificationAlgorithm],
+    hashes: &CertIdHashes,
+) -> Option<RevocationStatus> {
+    if response.is_empty() {
+        return None;
+    }
+
+    let response = BasicOcspResponse::parse(response)?;
+    let cert = webpki::EndEntityCert::try_from(cert).ok()?;
+
+    let issuers = chain
+        .iter()
+        .filter_map(|candidate| webpki::EndEntityCert::try_from(*candidate).ok())
+        .filter(|candidate| candidate.subject() == cert.issuer())
+        .map(|candidate| {
+            candidate
+                .subject_public_key_info()
+                .to_vec()
+        })
+        .chain(
+            roots
+                .roots
+                .iter()
+                .filter(|anchor| anchor.subject.as_ref() == cert.issuer())
+                .map(|anchor| x509::wrap_in_sequence(anchor.subject_public_key_info.as_ref())),
+        )
+        .collect::<Vec<_>>();
+
+    let issuer_name = x509::wrap_in_sequence(cert.issuer());
+    let single = issuers
+        .iter()
+        .filter(|issuer| {
+            verify_signed(&response.signed, issuer, supported_algs)
+                || response.certs.iter().any(|responder| {
+                    is_authorized_responder(responder, issuer, now, supported_algs)
+                        .map_or(false, |responder| {
+                            verify_signed(&response.signed, &responder, supported_algs)
+                        })
+                })
+        })
+        .find_map(|issuer| {
+            response
+                .responses
+                .iter()
+                .find(|single| {
+                    single
+                        .cert_id()
+                        .map_or(false, |cert_id| {
+                            hashes.matches(&cert_id, &issuer_name, issuer, cert.serial())
+                        })
+                })
+        })?;
+
+    let current = single.this_update.as_secs() <= now.as_secs()
+        && match single.next_update {
+            Some(next_update) => now.as_secs() < next_update.as_secs(),
+            None => {
+                now.as_secs() - single.this_update.as_secs() < MAX_AGE_WITHOUT_NEXT_UPDATE.as_secs()
+            }
+        };
+    if !current {
+        return None;
+    }
+
+    Some(match single.status {
+        OcspCertStatus::Good => RevocationStatus::Good,
+        OcspCertStatus::Revoked => RevocationStatus::Revoked,
+        OcspCertStatus::Unknown => RevocationStatus::Unknown,
+    })
+}
+
+/// The hash functions available for matching the `CertID` of a response: those of the
+/// cipher suites of a [`CryptoProvider`], and SHA-1.
+#[derive(Clone)]
+pub(crate) struct CertIdHashes(Vec<&'static dyn hash::Hash>);
+
+impl CertIdHashes {
+    pub(crate) fn new(provider: &CryptoProvider) -> Self {
+        let mut hashes = Vec::<&'static dyn hash::Hash>::new();
+        for suite in &provider.cipher_suites {
+            let hash = suite.hash_provider();
+            if !hashes
+                .iter()
+                .any(|known| known.algorithm() == hash.algorithm())
+            {
+                hashes.push(hash);
+            }
+        }
+        Self(hashes)
+    }
+
+    /// Whether `cert_id` names the certificate with contents of `serialNumber` `serial`,
+    /// issued under the DER-encoded `issuer_name` by the holder of `issuer_spki`.
+    fn matches(
+        &self,
+        cert_id: &CertId<'_>,
+        issuer_name: &[u8],
+        issuer_spki: &[u8],
+        serial: &[u8],
+    ) -> bool {
+        let issuer_key = match x509::spki_parts(issuer_spki) {
+            Some((_, key)) => key,
+            None => return false,
+        };
+
+        cert_id.serial_number == serial
+            && self
+                .hash(cert_id.hash_algorithm, issuer_name)
+                .as_deref()
+                == Some(cert_id.issuer_name_hash)
+            && self
+                .hash(cert_id.hash_algorithm, issuer_key)
+                .as_deref()
+                == Some(cert_id.issuer_key_hash)
+    }
+
+    fn hash(&self, algorithm: HashAlgorithm, data: &[u8]) -> Option<Vec<u8>> {
+        match algorithm {
+            HashAlgorithm::SHA1 => Some(sha1(data).to_vec()),
+            _ => self
+                .0
+                .iter()
+                .find(|hash| hash.algorithm() == algorithm)
+                .map(|hash| hash.hash(data).as_ref().to_vec()),
+        }
+    }
+}
+
+impl Debug for CertIdHashes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.0
+                    .iter()
+                    .map(|hash| hash.algorithm()),
+            )
+            .finish()
+    }
+}
+
+/// SHA-1, which most `CertID`s are made with, but crypto providers need not offer.
+///
+/// This only identifies the issuer of the certificate a response is about: it is the
+/// signature which authenticates the response.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (w, word) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *w = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut out = [0u8; 20];
+    for (out, word) in out.chunks_exact_mut(4).zip(state) {
+        out.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+/// If `responder` is a delegated OCSP responder certificate, valid at `now` and
+/// signed by the holder of `issuer_spki`, return its `SubjectPublicKeyInfo`.
+fn is_authorized_responder(
+    responder: &[u8],
+    issuer_spki: &[u8],
+    now: UnixTime,
+    supported_algs: &[&dyn SignatureVerificationAlgorithm],
+) -> Option<Vec<u8>> {
+    let (signed, _) = SignedData::read(responder)?;
+    if !x509::allows_ocsp_signing(responder) || !verify_signed(&signed, issuer_spki, supported_algs)
+    {
+        return None;
+    }
+
+    let (not_before, not_after) = (x509::not_before(responder)?, x509::not_after(responder)?);
+    if now.as_secs() < not_before.as_secs() || not_after.as_secs() < now.as_secs() {
+        return None;
+    }
+
+    let responder = CertificateDer::from(responder);
+    let responder = webpki::EndEntityCert::try_from(&responder).ok()?;
+    Some(
+        responder
+            .subject_public_key_info()
+            .to_vec(),
+    )
+}
+
+/// Whether `signed` has a valid signature by the key in `spki` (a DER-encoded
+/// `SubjectPublicKeyInfo`), using one of `supported_algs`.
+fn verify_signed(
+    signed: &SignedData<'_>,
+    spki: &[u8],
+    supported_algs: &[&dyn SignatureVerificationAlgorithm],
+) -> bool {
+    let (key_algorithm, key) = match x509::spki_parts(spki) {
+        Some(parts) => parts,
+        None => return false,
+    };
+
+    supported_algs
+        .iter()
+        .filter(|alg| {
+            alg.public_key_alg_id().as_ref() == key_algorithm
+                && alg.signature_alg_id().as_ref() == signed.algorithm
+        })
+        .any(|alg| {
+            alg.verify_signature(key, signed.data, signed.signature)
+                .is_ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sha1;
+
+    #[test]
+    fn test_sha1() {
+        // FIPS 180-2 appendix A
+        assert_eq!(
+            sha1(b"abc"),
+            [
+                0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e, 0x25, 0x71, 0x78, 0x50,
+                0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d
+            ]
+        );
+        assert_eq!(
+            sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            [
+                0x84, 0x98, 0x3e, 0x44, 0x1c, 0x3b, 0xd2, 0x6e, 0xba, 0xae, 0x4a, 0xa1, 0xf9, 0x51,
+                0x29, 0xe5, 0xe5, 0x46, 0x70, 0xf1
+            ]
+        );
+        assert_eq!(
+            sha1(b""),
+            [
+                0xda, 0x39, 0xa3, 0xee, 0x5e, 0x6b, 0x4b, 0x0d, 0x32, 0x55, 0xbf, 0xef, 0x95, 0x60,
+                0x18, 0x90, 0xaf, 0xd8, 0x07, 0x09
+            ]
+        );
+    }
+}
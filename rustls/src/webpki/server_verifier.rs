@@ -8,8 +8,10 @@ use crate::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
 #[cfg(feature = "logging")]
 use crate::log::trace;
 use crate::verify::{
-    DigitallySignedStruct, HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+    DigitallySignedStruct, HandshakeSignatureValid, RevocationStatus, ServerCertVerified,
+    ServerCertVerifier,
 };
+use crate::webpki::ocsp::{self, CertIdHashes};
 use crate::webpki::verify::{
     verify_server_cert_signed_by_trust_anchor_impl, verify_tls12_signature, verify_tls13_signature,
     ParsedCertificate,
@@ -17,7 +19,7 @@ use crate::webpki::verify::{
 use crate::webpki::{parse_crls, verify_server_name, VerifierBuilderError};
 #[cfg(doc)]
 use crate::{crypto, ConfigBuilder, ServerConfig};
-use crate::{Error, RootCertStore, SignatureScheme};
+use crate::{CertificateError, Error, RootCertStore, SignatureScheme};
 
 /// A builder for configuring a `webpki` server certificate verifier.
 ///
@@ -30,12 +32,16 @@ pub struct ServerCertVerifierBuilder {
     unknown_revocation_policy: UnknownStatusPolicy,
     revocation_expiration_policy: ExpirationPolicy,
     supported_algs: WebPkiSupportedAlgorithms,
+    cert_id_hashes: CertIdHashes,
+    max_chain_depth: Option<usize>,
+    intermediates: Vec<CertificateDer<'static>>,
 }
 
 impl ServerCertVerifierBuilder {
     pub(crate) fn new(
         roots: Arc<RootCertStore>,
         supported_algs: WebPkiSupportedAlgorithms,
+        cert_id_hashes: CertIdHashes,
     ) -> Self {
         Self {
             roots,
@@ -44,6 +50,9 @@ impl ServerCertVerifierBuilder {
             unknown_revocation_policy: UnknownStatusPolicy::Deny,
             revocation_expiration_policy: ExpirationPolicy::Ignore,
             supported_algs,
+            cert_id_hashes,
+            max_chain_depth: None,
+            intermediates: Vec::new(),
         }
     }
 
@@ -98,6 +107,37 @@ impl ServerCertVerifierBuilder {
         self
     }
 
+    /// Limit the number of intermediate certificates between the end-entity
+    /// certificate and the trust anchor.
+    ///
+    /// For example, a depth of 0 only accepts certificates issued directly by a trust
+    /// anchor, like OpenSSL's verify depth.  Chains that are too deep are rejected with
+    /// [`CertificateError::ChainTooDeep`].
+    ///
+    /// By default, and at most, webpki allows six intermediate certificates; larger
+    /// values have no effect.
+    ///
+    /// [`CertificateError::ChainTooDeep`]: crate::CertificateError::ChainTooDeep
+    pub fn with_max_chain_depth(mut self, depth: usize) -> Self {
+        self.max_chain_depth = Some(depth);
+        self
+    }
+
+    /// Also consider these intermediate certificates when building a path to a trust anchor.
+    ///
+    /// They are tried after the intermediates sent by the server.  This completes chains
+    /// from servers that omit an intermediate, or that send one cross-signed by a root
+    /// that is not trusted, when a version of it issued by a trusted root is supplied here.
+    /// These certificates are not trusted themselves.  Calling `with_intermediates`
+    /// multiple times appends the given certificates to the existing collection.
+    pub fn with_intermediates(
+        mut self,
+        intermediates: impl IntoIterator<Item = CertificateDer<'static>>,
+    ) -> Self {
+        self.intermediates.extend(intermediates);
+        self
+    }
+
     /// Build a server certificate verifier, allowing control over the root certificates to use as
     /// trust anchors, and to control how server certificate revocation checking is performed.
     ///
@@ -124,12 +164,22 @@ impl ServerCertVerifierBuilder {
             self.unknown_revocation_policy,
             self.revocation_expiration_policy,
             self.supported_algs,
+            self.cert_id_hashes,
+            self.max_chain_depth,
+            self.intermediates,
         )
         .into())
     }
 }
 
 /// Default `ServerCertVerifier`, see the trait impl for more information.
+///
+/// Paths are built from the end-entity certificate to a trust anchor by trying
+/// each candidate issuer in turn, backtracking when one does not lead to a trust
+/// anchor.  So when several chains are possible (for example, through an intermediate
+/// cross-signed by an old and a new root) verification succeeds if any of them is
+/// valid.  Use [`ServerCertVerifierBuilder::with_intermediates()`] to supply candidate
+/// issuers that servers do not send.
 #[allow(unreachable_pub)]
 #[derive(Debug)]
 pub struct WebPkiServerVerifier {
@@ -139,6 +189,9 @@ pub struct WebPkiServerVerifier {
     unknown_revocation_policy: UnknownStatusPolicy,
     revocation_expiration_policy: ExpirationPolicy,
     supported: WebPkiSupportedAlgorithms,
+    cert_id_hashes: CertIdHashes,
+    max_chain_depth: Option<usize>,
+    intermediates: Vec<CertificateDer<'static>>,
 }
 
 #[allow(unreachable_pub)]
@@ -170,14 +223,18 @@ impl WebPkiServerVerifier {
         roots: Arc<RootCertStore>,
         provider: Arc<CryptoProvider>,
     ) -> ServerCertVerifierBuilder {
-        ServerCertVerifierBuilder::new(roots, provider.signature_verification_algorithms)
+        ServerCertVerifierBuilder::new(
+            roots,
+            provider.signature_verification_algorithms,
+            CertIdHashes::new(&provider),
+        )
     }
 
     /// Short-cut for creating a `WebPkiServerVerifier` that does not perform certificate revocation
     /// checking, avoiding the need to use a builder.
     pub(crate) fn new_without_revocation(
         roots: impl Into<Arc<RootCertStore>>,
-        supported_algs: WebPkiSupportedAlgorithms,
+        provider: &CryptoProvider,
     ) -> Self {
         Self::new(
             roots,
@@ -185,7 +242,10 @@ impl WebPkiServerVerifier {
             RevocationCheckDepth::Chain,
             UnknownStatusPolicy::Allow,
             ExpirationPolicy::Ignore,
-            supported_algs,
+            provider.signature_verification_algorithms,
+            CertIdHashes::new(provider),
+            None,
+            Vec::new(),
         )
     }
 
@@ -200,6 +260,9 @@ impl WebPkiServerVerifier {
     ///   are handled when `crls` are provided.
     /// * `supported` is the set of supported algorithms that will be used for
     ///   certificate verification and TLS handshake signature verification.
+    /// * `cert_id_hashes` are used to check which certificate a stapled OCSP response is about.
+    /// * `max_chain_depth` limits the number of intermediate certificates, if given.
+    /// * `intermediates` are extra candidate issuers for path building.
     pub(crate) fn new(
         roots: impl Into<Arc<RootCertStore>>,
         crls: Vec<CertRevocationList<'static>>,
@@ -207,6 +270,9 @@ impl WebPkiServerVerifier {
         unknown_revocation_policy: UnknownStatusPolicy,
         revocation_expiration_policy: ExpirationPolicy,
         supported: WebPkiSupportedAlgorithms,
+        cert_id_hashes: CertIdHashes,
+        max_chain_depth: Option<usize>,
+        intermediates: Vec<CertificateDer<'static>>,
     ) -> Self {
         Self {
             roots: roots.into(),
@@ -215,6 +281,9 @@ impl WebPkiServerVerifier {
             unknown_revocation_policy,
             revocation_expiration_policy,
             supported,
+            cert_id_hashes,
+            max_chain_depth,
+            intermediates,
         }
     }
 }
@@ -235,7 +304,7 @@ impl ServerCertVerifier for WebPkiServerVerifier {
         end_entity: &CertificateDer<'_>,
         intermediates: &[CertificateDer<'_>],
         server_name: &ServerName<'_>,
-        ocsp_response: &[u8],
+        _ocsp_response: &[u8],
         now: UnixTime,
     ) -> Result<ServerCertVerified, Error> {
         let cert = ParsedCertificate::try_from(end_entity)?;
@@ -259,6 +328,20 @@ impl ServerCertVerifier for WebPkiServerVerifier {
             )
         };
 
+        // the presented intermediates come first, so are tried first
+        let candidates;
+        let intermediates = match self.intermediates.is_empty() {
+            true => intermediates,
+            false => {
+                candidates = intermediates
+                    .iter()
+                    .chain(&self.intermediates)
+                    .map(|cert| CertificateDer::from(cert.as_ref()))
+                    .collect::<Vec<_>>();
+                &candidates[..]
+            }
+        };
+
         // Note: we use the crate-internal `_impl` fn here in order to provide revocation
         // checking information, if applicable.
         verify_server_cert_signed_by_trust_anchor_impl(
@@ -268,12 +351,9 @@ impl ServerCertVerifier for WebPkiServerVerifier {
             revocation,
             now,
             self.supported.all,
+            self.max_chain_depth,
         )?;
 
-        if !ocsp_response.is_empty() {
-            trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
-        }
-
         verify_server_name(&cert, server_name)?;
         Ok(ServerCertVerified::assertion())
     }
@@ -299,6 +379,51 @@ impl ServerCertVerifier for WebPkiServerVerifier {
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
         self.supported.supported_schemes()
     }
+
+    /// Checks each stapled OCSP response is signed by the issuer of its certificate
+    /// (or a responder it delegated to), is current, and is about that certificate.
+    ///
+    /// A certificate with a verified response of "revoked" is rejected with
+    /// [`CertificateError::Revoked`].  Otherwise, a certificate's status is
+    /// [`RevocationStatus::Unknown`] unless it has a verified response.
+    fn check_stapled_ocsp(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        ocsp_responses: &[&[u8]],
+        now: UnixTime,
+    ) -> Result<Vec<RevocationStatus>, Error> {
+        let chain = intermediates
+            .iter()
+            .chain(&self.intermediates)
+            .collect::<Vec<_>>();
+
+        let mut statuses = Vec::with_capacity(ocsp_responses.len());
+        for (cert, response) in [end_entity]
+            .into_iter()
+            .chain(intermediates)
+            .zip(ocsp_responses)
+        {
+            let status = ocsp::check_response(
+                cert,
+                &chain,
+                &self.roots,
+                response,
+                now,
+                self.supported.all,
+                &self.cert_id_hashes,
+            )
+            .unwrap_or(RevocationStatus::Unknown);
+            if status == RevocationStatus::Revoked {
+                return Err(CertificateError::Revoked.into());
+            }
+            trace!("Stapled OCSP response status: {:?}", status);
+            statuses.push(status);
+        }
+
+        statuses.resize(ocsp_responses.len(), RevocationStatus::Unknown);
+        Ok(statuses)
+    }
 }
 
 test_for_each_provider! {
@@ -309,7 +434,8 @@ test_for_each_provider! {
     use pki_types::{CertificateDer, CertificateRevocationListDer};
 
     use super::{VerifierBuilderError, WebPkiServerVerifier};
-    use crate::RootCertStore;
+    use crate::verify::ServerCertVerifier;
+    use crate::{CertificateError, Error, RootCertStore};
 
     fn load_crls(crls_der: &[&[u8]]) -> Vec<CertificateRevocationListDer<'static>> {
         crls_der
@@ -347,6 +473,78 @@ test_for_each_provider! {
         ])
     }
 
+    #[test]
+    fn test_max_chain_depth() {
+        let end_entity = CertificateDer::from(
+            include_bytes!("../../../test-ca/rsa-2048/end.der").as_slice(),
+        );
+        let intermediates = [CertificateDer::from(
+            include_bytes!("../../../test-ca/rsa-2048/inter.der").as_slice(),
+        )];
+        let server_name = pki_types::ServerName::try_from("testserver.com").unwrap();
+        let now = pki_types::UnixTime::since_unix_epoch(core::time::Duration::from_secs(
+            1_700_000_000,
+        ));
+
+        let verify = |depth| {
+            WebPkiServerVerifier::builder_with_provider(
+                test_roots(),
+                provider::default_provider().into(),
+            )
+            .with_max_chain_depth(depth)
+            .build()
+            .unwrap()
+            .verify_server_cert(&end_entity, &intermediates, &server_name, &[], now)
+        };
+
+        // the chain has one intermediate certificate
+        assert!(verify(1).is_ok());
+        assert_eq!(
+            verify(0).unwrap_err(),
+            Error::InvalidCertificate(CertificateError::ChainTooDeep)
+        );
+    }
+
+    #[test]
+    fn test_with_intermediates() {
+        let end_entity = CertificateDer::from(
+            include_bytes!("../../../test-ca/rsa-2048/end.der").as_slice(),
+        );
+        let issuer = CertificateDer::from(
+            include_bytes!("../../../test-ca/rsa-2048/inter.der").as_slice(),
+        );
+        let unrelated = CertificateDer::from(
+            include_bytes!("../../../test-ca/ecdsa-p256/inter.der").as_slice(),
+        );
+        let server_name = pki_types::ServerName::try_from("testserver.com").unwrap();
+        let now = pki_types::UnixTime::since_unix_epoch(core::time::Duration::from_secs(
+            1_700_000_000,
+        ));
+
+        let verify = |presented: &[CertificateDer<'_>], configured: &[CertificateDer<'static>]| {
+            WebPkiServerVerifier::builder_with_provider(
+                test_roots(),
+                provider::default_provider().into(),
+            )
+            .with_intermediates(configured.to_vec())
+            .build()
+            .unwrap()
+            .verify_server_cert(&end_entity, presented, &server_name, &[], now)
+        };
+
+        let unknown_issuer = Error::InvalidCertificate(CertificateError::UnknownIssuer);
+        assert_eq!(verify(&[], &[]).unwrap_err(), unknown_issuer);
+        assert_eq!(
+            verify(core::slice::from_ref(&unrelated), &[]).unwrap_err(),
+            unknown_issuer
+        );
+
+        // the missing issuer is found among the configured intermediates
+        assert!(verify(&[], &[issuer.clone().into_owned()]).is_ok());
+        assert!(verify(core::slice::from_ref(&unrelated), &[issuer.clone().into_owned()]).is_ok());
+        assert!(verify(&[issuer], &[unrelated.into_owned()]).is_ok());
+    }
+
     #[test]
     fn test_with_invalid_crls() {
         // Trying to build a server verifier with invalid CRLs should error at build time.
@@ -7,9 +7,10 @@ use pki_types::{
 
 use super::anchors::RootCertStore;
 use super::pki_error;
-use crate::enums::SignatureScheme;
+use crate::enums::{SignatureAlgorithm, SignatureScheme};
 use crate::error::{Error, PeerMisbehaved};
 use crate::verify::{DigitallySignedStruct, HandshakeSignatureValid};
+use crate::{x509, DistinguishedName};
 
 /// Verify that the end-entity certificate `end_entity` is a valid server cert
 /// and chains to at least one of the trust anchors in the `roots` [RootCertStore].
@@ -37,6 +38,7 @@ pub fn verify_server_cert_signed_by_trust_anchor(
         None, // No revocation checking supported with this API.
         now,
         supported_algs,
+        None,
     )
 }
 
@@ -124,7 +126,11 @@ impl fmt::Debug for WebPkiSupportedAlgorithms {
 
 /// Wrapper around internal representation of a parsed certificate.
 ///
-/// This is used in order to avoid parsing twice when specifying custom verification
+/// This is used in order to avoid parsing twice when specifying custom verification.
+///
+/// It also gives a minimal view of the certificate's contents, for logging or
+/// simple policy decisions.  None of these are validated: the certificate must
+/// be verified before they can be trusted.
 pub struct ParsedCertificate<'a>(pub(crate) webpki::EndEntityCert<'a>);
 
 impl<'a> ParsedCertificate<'a> {
@@ -132,6 +138,63 @@ impl<'a> ParsedCertificate<'a> {
     pub fn subject_public_key_info(&self) -> SubjectPublicKeyInfoDer<'static> {
         self.0.subject_public_key_info()
     }
+
+    /// The certificate's subject, as a DER-encoded X.500 distinguished name.
+    pub fn subject(&self) -> DistinguishedName {
+        DistinguishedName::in_sequence(self.0.subject())
+    }
+
+    /// The certificate's issuer, as a DER-encoded X.500 distinguished name.
+    pub fn issuer(&self) -> DistinguishedName {
+        DistinguishedName::in_sequence(self.0.issuer())
+    }
+
+    /// The certificate's serial number, as the big-endian bytes of a DER `INTEGER`.
+    pub fn serial(&self) -> &[u8] {
+        self.0.serial()
+    }
+
+    /// The start of the certificate's validity period.
+    pub fn not_before(&self) -> Option<UnixTime> {
+        x509::not_before(&self.0.der())
+    }
+
+    /// The end of the certificate's validity period.
+    pub fn not_after(&self) -> Option<UnixTime> {
+        x509::not_after(&self.0.der())
+    }
+
+    /// The DNS names among the certificate's subject alternative names.
+    ///
+    /// This must not be used to check the certificate is valid for a name: use
+    /// [`verify_server_name`] for that.
+    pub fn dns_names(&self) -> impl Iterator<Item = &str> {
+        self.0.valid_dns_names()
+    }
+
+    /// The IP addresses among the certificate's subject alternative names.
+    #[cfg(feature = "std")]
+    pub fn ip_addresses(&self) -> Vec<std::net::IpAddr> {
+        x509::subject_alt_ip_addresses(&self.0.der())
+            .into_iter()
+            .filter_map(|address| match <[u8; 16]>::try_from(address) {
+                Ok(v6) => Some(std::net::IpAddr::from(v6)),
+                Err(_) => <[u8; 4]>::try_from(address)
+                    .ok()
+                    .map(std::net::IpAddr::from),
+            })
+            .collect()
+    }
+
+    /// The algorithm of the certificate's public key, or `None` if it is not one
+    /// of the algorithms in [`SignatureAlgorithm`].
+    pub fn key_algorithm(&self) -> Option<SignatureAlgorithm> {
+        x509::key_algorithm(
+            self.0
+                .subject_public_key_info()
+                .as_ref(),
+        )
+    }
 }
 
 impl<'a> TryFrom<&'a CertificateDer<'a>> for ParsedCertificate<'a> {
@@ -207,6 +270,9 @@ pub fn verify_tls13_signature(
 ///
 /// `revocation` controls how revocation checking is performed, if at all.
 ///
+/// `max_chain_depth` limits the number of intermediate certificates in the
+/// verified chain, if given.
+///
 /// This function exists to be used by [`verify_server_cert_signed_by_trust_anchor`],
 /// and differs only in providing a `Option<webpki::RevocationOptions>` argument. We
 /// can't include this argument in `verify_server_cert_signed_by_trust_anchor` because
@@ -218,6 +284,7 @@ pub(crate) fn verify_server_cert_signed_by_trust_anchor_impl(
     revocation: Option<webpki::RevocationOptions<'_>>,
     now: UnixTime,
     supported_algs: &[&dyn SignatureVerificationAlgorithm],
+    max_chain_depth: Option<usize>,
 ) -> Result<(), Error> {
     let result = cert.0.verify_for_usage(
         supported_algs,
@@ -226,7 +293,7 @@ pub(crate) fn verify_server_cert_signed_by_trust_anchor_impl(
         now,
         webpki::KeyUsage::server_auth(),
         revocation,
-        None,
+        Some(&|path: &webpki::VerifiedPath<'_>| check_chain_depth(path, max_chain_depth)),
     );
     match result {
         Ok(_) => Ok(()),
@@ -234,6 +301,19 @@ pub(crate) fn verify_server_cert_signed_by_trust_anchor_impl(
     }
 }
 
+/// Reject `path` if it has more than `max_chain_depth` intermediate certificates.
+pub(crate) fn check_chain_depth(
+    path: &webpki::VerifiedPath<'_>,
+    max_chain_depth: Option<usize>,
+) -> Result<(), webpki::Error> {
+    match max_chain_depth {
+        Some(max) if path.intermediate_certificates().count() > max => {
+            Err(webpki::Error::MaximumPathDepthExceeded)
+        }
+        _ => Ok(()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::format;
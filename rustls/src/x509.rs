@@ -2,6 +2,12 @@
 
 use alloc::vec::Vec;
 
+use pki_types::UnixTime;
+use webpki::alg_id;
+
+use crate::crypto::hash::HashAlgorithm;
+use crate::enums::{SignatureAlgorithm, SignatureScheme};
+
 /// Prepend stuff to `bytes` to put it in a DER SEQUENCE.
 pub(crate) fn wrap_in_sequence(bytes: &[u8]) -> Vec<u8> {
     asn1_wrap(DER_SEQUENCE_TAG, bytes, &[])
@@ -57,9 +63,627 @@ fn asn1_wrap(tag: u8, bytes_a: &[u8], bytes_b: &[u8]) -> Vec<u8> {
     }
 }
 
+/// Extract the `notAfter` time from the validity period of a DER-encoded certificate.
+///
+/// This does no validation of the certificate beyond what is needed to find the field.
+pub(crate) fn not_after(cert: &[u8]) -> Option<UnixTime> {
+    let (_, validity) = validity(cert)?;
+    let (validity, _not_before) = read_time(validity)?;
+    let (_, not_after) = read_time(validity)?;
+    Some(not_after)
+}
+
+/// Extract the `notBefore` time from the validity period of a DER-encoded certificate.
+///
+/// This does no validation of the certificate beyond what is needed to find the field.
+pub(crate) fn not_before(cert: &[u8]) -> Option<UnixTime> {
+    let (_, validity) = validity(cert)?;
+    let (_, not_before) = read_time(validity)?;
+    Some(not_before)
+}
+
+/// The earliest `nextUpdate` time of the responses in a DER-encoded `OCSPResponse`,
+/// as described in [RFC 6960 section 4.2.1].
+///
+/// Returns `None` if `response` cannot be parsed or was not successful, and `Some(None)`
+/// if none of its responses has a `nextUpdate` time.  This does no validation of the
+/// response beyond what is needed to find the fields.
+///
+/// [RFC 6960 section 4.2.1]: https://www.rfc-editor.org/rfc/rfc6960#section-4.2.1
+pub(crate) fn ocsp_next_update(response: &[u8]) -> Option<Option<UnixTime>> {
+    let mut earliest: Option<UnixTime> = None;
+    for response in BasicOcspResponse::parse(response)?.responses {
+        if let Some(next_update) = response.next_update {
+            earliest = match earliest {
+                Some(earliest) if earliest.as_secs() <= next_update.as_secs() => Some(earliest),
+                _ => Some(next_update),
+            };
+        }
+    }
+    Some(earliest)
+}
+
+/// The `BasicOCSPResponse` in a successful DER-encoded `OCSPResponse`, as described
+/// in [RFC 6960 section 4.2.1].
+///
+/// This does no validation of the response beyond what is needed to find the fields.
+///
+/// [RFC 6960 section 4.2.1]: https://www.rfc-editor.org/rfc/rfc6960#section-4.2.1
+pub(crate) struct BasicOcspResponse<'a> {
+    /// `tbsResponseData`, over which `signature` is made.
+    pub(crate) signed: SignedData<'a>,
+    /// The DER-encoded certificates that help to verify the signature.
+    pub(crate) certs: Vec<&'a [u8]>,
+    pub(crate) responses: Vec<SingleOcspResponse<'a>>,
+}
+
+impl<'a> BasicOcspResponse<'a> {
+    /// Returns `None` if `response` cannot be parsed or was not successful.
+    pub(crate) fn parse(response: &'a [u8]) -> Option<Self> {
+        let (response, _) = read_tlv(DER_SEQUENCE_TAG, response)?;
+        let (status, response) = read_tlv(DER_ENUMERATED_TAG, response)?;
+        if status != [OCSP_SUCCESSFUL] {
+            return None;
+        }
+
+        let (response_bytes, _) = read_tlv(DER_OCSP_RESPONSE_BYTES_TAG, response)?;
+        let (response_bytes, _) = read_tlv(DER_SEQUENCE_TAG, response_bytes)?;
+        let (response_type, response_bytes) = read_tlv(DER_OID_TAG, response_bytes)?;
+        if response_type != OID_OCSP_BASIC {
+            return None;
+        }
+        let (basic, _) = read_tlv(DER_OCTET_STRING_TAG, response_bytes)?;
+        let (signed, rest) = SignedData::read(basic)?;
+
+        // certs [0] EXPLICIT SEQUENCE OF Certificate OPTIONAL
+        let mut certs = Vec::new();
+        if let Some((list, _)) = read_tlv(DER_OCSP_CERTS_TAG, rest) {
+            let (mut list, _) = read_tlv(DER_SEQUENCE_TAG, list)?;
+            while !list.is_empty() {
+                let (cert, rest) = read_tlv_whole(DER_SEQUENCE_TAG, list)?;
+                certs.push(cert);
+                list = rest;
+            }
+        }
+
+        let (tbs, _) = read_tlv(DER_SEQUENCE_TAG, signed.data)?;
+        // version [0] EXPLICIT Version DEFAULT v1
+        let tbs = match read_tlv(DER_VERSION_TAG, tbs) {
+            Some((_, rest)) => rest,
+            None => tbs,
+        };
+        let (_responder_id, tbs) =
+            read_tlv(DER_OCSP_BY_NAME_TAG, tbs).or_else(|| read_tlv(DER_OCSP_BY_KEY_TAG, tbs))?;
+        let (tbs, _produced_at) = read_time(tbs)?;
+        let (mut list, _) = read_tlv(DER_SEQUENCE_TAG, tbs)?;
+
+        let mut responses = Vec::new();
+        while !list.is_empty() {
+            let (response, rest) = read_tlv(DER_SEQUENCE_TAG, list)?;
+            list = rest;
+
+            let (cert_id, response) = read_tlv(DER_SEQUENCE_TAG, response)?;
+            let (&cert_status_tag, _) = response.split_first()?;
+            let (_, response) = read_tlv(cert_status_tag, response)?;
+            let status = match cert_status_tag {
+                DER_OCSP_GOOD_TAG => OcspCertStatus::Good,
+                DER_OCSP_REVOKED_TAG => OcspCertStatus::Revoked,
+                DER_OCSP_UNKNOWN_TAG => OcspCertStatus::Unknown,
+                _ => return None,
+            };
+            let (response, this_update) = read_time(response)?;
+
+            // nextUpdate [0] EXPLICIT GeneralizedTime OPTIONAL
+            let next_update = match read_tlv(DER_OCSP_NEXT_UPDATE_TAG, response) {
+                Some((next_update, _)) => Some(read_time(next_update)?.1),
+                None => None,
+            };
+
+            responses.push(SingleOcspResponse {
+                cert_id,
+                status,
+                this_update,
+                next_update,
+            });
+        }
+
+        Some(Self {
+            signed,
+            certs,
+            responses,
+        })
+    }
+}
+
+/// One `SingleResponse` from a [`BasicOcspResponse`].
+pub(crate) struct SingleOcspResponse<'a> {
+    /// The contents of `certID`.
+    cert_id: &'a [u8],
+    pub(crate) status: OcspCertStatus,
+    pub(crate) this_update: UnixTime,
+    pub(crate) next_update: Option<UnixTime>,
+}
+
+impl<'a> SingleOcspResponse<'a> {
+    /// The `CertID` identifying the certificate this response is about.
+    ///
+    /// Returns `None` if it is malformed, or made with a hash function other than
+    /// SHA-1, SHA-256, SHA-384 or SHA-512.
+    pub(crate) fn cert_id(&self) -> Option<CertId<'a>> {
+        let (hash_algorithm, cert_id) = read_tlv(DER_SEQUENCE_TAG, self.cert_id)?;
+        let (hash_algorithm, _parameters) = read_tlv(DER_OID_TAG, hash_algorithm)?;
+        let (issuer_name_hash, cert_id) = read_tlv(DER_OCTET_STRING_TAG, cert_id)?;
+        let (issuer_key_hash, cert_id) = read_tlv(DER_OCTET_STRING_TAG, cert_id)?;
+        let (serial_number, _) = read_tlv(DER_INTEGER_TAG, cert_id)?;
+        Some(CertId {
+            hash_algorithm: match hash_algorithm {
+                OID_SHA1 => HashAlgorithm::SHA1,
+                OID_SHA256 => HashAlgorithm::SHA256,
+                OID_SHA384 => HashAlgorithm::SHA384,
+                OID_SHA512 => HashAlgorithm::SHA512,
+                _ => return None,
+            },
+            issuer_name_hash,
+            issuer_key_hash,
+            serial_number,
+        })
+    }
+}
+
+/// The `CertID` of a [`SingleOcspResponse`], as described in [RFC 6960 section 4.1.1].
+///
+/// [RFC 6960 section 4.1.1]: https://www.rfc-editor.org/rfc/rfc6960#section-4.1.1
+pub(crate) struct CertId<'a> {
+    /// The hash function of `issuer_name_hash` and `issuer_key_hash`.
+    pub(crate) hash_algorithm: HashAlgorithm,
+    /// The hash of the DER encoding of the issuer's name.
+    pub(crate) issuer_name_hash: &'a [u8],
+    /// The hash of the issuer's public key, excluding the BIT STRING tag, length and
+    /// number of unused bits.
+    pub(crate) issuer_key_hash: &'a [u8],
+    /// The contents of the `serialNumber` of the certificate.
+    pub(crate) serial_number: &'a [u8],
+}
+
+/// The `certStatus` of a [`SingleOcspResponse`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OcspCertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+/// Some data, and a signature over it, as found in certificates and OCSP responses.
+pub(crate) struct SignedData<'a> {
+    /// The whole DER encoding of the signed data.
+    pub(crate) data: &'a [u8],
+    /// The contents of the `AlgorithmIdentifier` of the signature.
+    pub(crate) algorithm: &'a [u8],
+    pub(crate) signature: &'a [u8],
+}
+
+impl<'a> SignedData<'a> {
+    /// Read a SEQUENCE containing the signed data, an `AlgorithmIdentifier` and a
+    /// BIT STRING signature, from the front of `input`.
+    ///
+    /// Returns the signed data and whatever follows the signature in the SEQUENCE.
+    pub(crate) fn read(input: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        let (input, _) = read_tlv(DER_SEQUENCE_TAG, input)?;
+        let (data, input) = read_tlv_whole(DER_SEQUENCE_TAG, input)?;
+        let (algorithm, input) = read_tlv(DER_SEQUENCE_TAG, input)?;
+        let (signature, rest) = read_tlv(DER_BIT_STRING_TAG, input)?;
+        let signature = match signature.split_first()? {
+            (0, signature) => signature,
+            _ => return None,
+        };
+        Some((
+            Self {
+                data,
+                algorithm,
+                signature,
+            },
+            rest,
+        ))
+    }
+}
+
+/// Split a DER-encoded `SubjectPublicKeyInfo` into the contents of its
+/// `AlgorithmIdentifier`, and its public key.
+pub(crate) fn spki_parts(spki: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (spki, _) = read_tlv(DER_SEQUENCE_TAG, spki)?;
+    let (algorithm, spki) = read_tlv(DER_SEQUENCE_TAG, spki)?;
+    let (key, _) = read_tlv(DER_BIT_STRING_TAG, spki)?;
+    match key.split_first()? {
+        (0, key) => Some((algorithm, key)),
+        _ => None,
+    }
+}
+
+/// Whether the extended key usage extension of a DER-encoded certificate
+/// allows signing OCSP responses.
+pub(crate) fn allows_ocsp_signing(cert: &[u8]) -> bool {
+    extended_key_usages(cert).contains(&OID_KP_OCSP_SIGNING)
+}
+
+/// The `iPAddress` entries in the subject alternative names of a DER-encoded
+/// certificate: each 4 (IPv4) or 16 (IPv6) bytes long.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub(crate) fn subject_alt_ip_addresses(cert: &[u8]) -> Vec<&[u8]> {
+    let mut addresses = Vec::new();
+    let mut names = match extension(cert, OID_SUBJECT_ALT_NAME)
+        .and_then(|san| read_tlv(DER_SEQUENCE_TAG, san))
+    {
+        Some((names, _)) => names,
+        None => return addresses,
+    };
+
+    while let Some((&tag, _)) = names.split_first() {
+        let (name, rest) = match read_tlv(tag, names) {
+            Some(name) => name,
+            None => break,
+        };
+        if tag == DER_SAN_IP_ADDRESS_TAG && matches!(name.len(), 4 | 16) {
+            addresses.push(name);
+        }
+        names = rest;
+    }
+    addresses
+}
+
+/// The key purpose OIDs in the extended key usage extension of a DER-encoded
+/// certificate, as the contents of each OBJECT IDENTIFIER.
+pub(crate) fn extended_key_usages(cert: &[u8]) -> Vec<&[u8]> {
+    let mut usages = Vec::new();
+    let mut oids = match extension(cert, OID_EXTENDED_KEY_USAGE)
+        .and_then(|eku| read_tlv(DER_SEQUENCE_TAG, eku))
+    {
+        Some((oids, _)) => oids,
+        None => return usages,
+    };
+
+    while let Some((oid, rest)) = read_tlv(DER_OID_TAG, oids) {
+        usages.push(oid);
+        oids = rest;
+    }
+    usages
+}
+
+/// The policy OIDs in the certificate policies extension of a DER-encoded
+/// certificate, as the contents of each OBJECT IDENTIFIER.
+///
+/// Any policy qualifiers are ignored.
+pub(crate) fn certificate_policies(cert: &[u8]) -> Vec<&[u8]> {
+    let mut policies = Vec::new();
+    let mut infos = match extension(cert, OID_CERTIFICATE_POLICIES)
+        .and_then(|policies| read_tlv(DER_SEQUENCE_TAG, policies))
+    {
+        Some((infos, _)) => infos,
+        None => return policies,
+    };
+
+    while let Some((info, rest)) = read_tlv(DER_SEQUENCE_TAG, infos) {
+        if let Some((oid, _)) = read_tlv(DER_OID_TAG, info) {
+            policies.push(oid);
+        }
+        infos = rest;
+    }
+    policies
+}
+
+/// The algorithm of a DER-encoded `SubjectPublicKeyInfo`, if it is one usable in TLS.
+pub(crate) fn key_algorithm(spki: &[u8]) -> Option<SignatureAlgorithm> {
+    let (spki, _) = read_tlv(DER_SEQUENCE_TAG, spki)?;
+    let (algorithm, _) = read_tlv(DER_SEQUENCE_TAG, spki)?;
+    let (oid, _) = read_tlv(DER_OID_TAG, algorithm)?;
+
+    match oid {
+        OID_RSA_ENCRYPTION => Some(SignatureAlgorithm::RSA),
+        OID_EC_PUBLIC_KEY => Some(SignatureAlgorithm::ECDSA),
+        OID_ED25519 => Some(SignatureAlgorithm::ED25519),
+        OID_ED448 => Some(SignatureAlgorithm::ED448),
+        _ => None,
+    }
+}
+
+/// The value of the extension with the given `oid` in a DER-encoded certificate.
+fn extension<'a>(cert: &'a [u8], oid: &[u8]) -> Option<&'a [u8]> {
+    let (tbs, _) = validity(cert)?;
+    let (_subject, tbs) = read_tlv(DER_SEQUENCE_TAG, tbs)?;
+    let (_spki, mut tbs) = read_tlv(DER_SEQUENCE_TAG, tbs)?;
+
+    // issuerUniqueID [1] and subjectUniqueID [2] are optional
+    for tag in [DER_ISSUER_UID_TAG, DER_SUBJECT_UID_TAG] {
+        if let Some((_, rest)) = read_tlv(tag, tbs) {
+            tbs = rest;
+        }
+    }
+
+    let (extensions, _) = read_tlv(DER_EXTENSIONS_TAG, tbs)?;
+    let (mut extensions, _) = read_tlv(DER_SEQUENCE_TAG, extensions)?;
+    while !extensions.is_empty() {
+        let (extension, rest) = read_tlv(DER_SEQUENCE_TAG, extensions)?;
+        extensions = rest;
+
+        let (id, extension) = read_tlv(DER_OID_TAG, extension)?;
+        if id != oid {
+            continue;
+        }
+        // critical BOOLEAN DEFAULT FALSE
+        let extension = match read_tlv(DER_BOOLEAN_TAG, extension) {
+            Some((_, rest)) => rest,
+            None => extension,
+        };
+        let (value, _) = read_tlv(DER_OCTET_STRING_TAG, extension)?;
+        return Some(value);
+    }
+    None
+}
+
+/// The signature scheme with which a DER-encoded certificate was signed, as it
+/// would be named in the `signature_algorithms_cert` extension.
+///
+/// An ECDSA signature's scheme depends on the curve of the issuer's key as well as
+/// the hash, so `issuer` should be the DER-encoded certificate of the issuer where
+/// that is known.  A signature that has no scheme, such as P-256 with SHA-384, is
+/// then `None`.  Without `issuer`, the scheme pairing the hash with the curve of
+/// the same size is assumed.  Returns `None` for unknown algorithms.
+pub(crate) fn signature_scheme(cert: &[u8], issuer: Option<&[u8]>) -> Option<SignatureScheme> {
+    let (cert, _) = read_tlv(DER_SEQUENCE_TAG, cert)?;
+    let (_tbs, cert) = read_tlv(DER_SEQUENCE_TAG, cert)?;
+    let (algorithm, _) = read_tlv(DER_SEQUENCE_TAG, cert)?;
+
+    let ecdsa = |hash_scheme, curve| match issuer {
+        Some(issuer) if ec_curve(issuer)? != curve => None,
+        _ => Some(hash_scheme),
+    };
+
+    let scheme = match algorithm {
+        alg if alg == alg_id::ECDSA_SHA256.as_ref() => {
+            ecdsa(SignatureScheme::ECDSA_NISTP256_SHA256, OID_SECP256R1)?
+        }
+        alg if alg == alg_id::ECDSA_SHA384.as_ref() => {
+            ecdsa(SignatureScheme::ECDSA_NISTP384_SHA384, OID_SECP384R1)?
+        }
+        alg if alg == alg_id::ECDSA_SHA512.as_ref() => {
+            ecdsa(SignatureScheme::ECDSA_NISTP521_SHA512, OID_SECP521R1)?
+        }
+        alg if alg == alg_id::RSA_PKCS1_SHA256.as_ref() => SignatureScheme::RSA_PKCS1_SHA256,
+        alg if alg == alg_id::RSA_PKCS1_SHA384.as_ref() => SignatureScheme::RSA_PKCS1_SHA384,
+        alg if alg == alg_id::RSA_PKCS1_SHA512.as_ref() => SignatureScheme::RSA_PKCS1_SHA512,
+        alg if alg == alg_id::RSA_PSS_SHA256.as_ref() => SignatureScheme::RSA_PSS_SHA256,
+        alg if alg == alg_id::RSA_PSS_SHA384.as_ref() => SignatureScheme::RSA_PSS_SHA384,
+        alg if alg == alg_id::RSA_PSS_SHA512.as_ref() => SignatureScheme::RSA_PSS_SHA512,
+        alg if alg == alg_id::ED25519.as_ref() => SignatureScheme::ED25519,
+        ALG_ID_ML_DSA_44 => SignatureScheme::ML_DSA_44,
+        ALG_ID_ML_DSA_65 => SignatureScheme::ML_DSA_65,
+        ALG_ID_ML_DSA_87 => SignatureScheme::ML_DSA_87,
+        _ => return None,
+    };
+    Some(scheme)
+}
+
+/// The named curve of a DER-encoded certificate's EC public key.
+fn ec_curve(cert: &[u8]) -> Option<&[u8]> {
+    let (tbs, _) = validity(cert)?;
+    let (_subject, tbs) = read_tlv(DER_SEQUENCE_TAG, tbs)?;
+    let (spki, _) = read_tlv(DER_SEQUENCE_TAG, tbs)?;
+    let (algorithm, _) = read_tlv(DER_SEQUENCE_TAG, spki)?;
+    let (oid, algorithm) = read_tlv(DER_OID_TAG, algorithm)?;
+    if oid != OID_EC_PUBLIC_KEY {
+        return None;
+    }
+    let (curve, _) = read_tlv(DER_OID_TAG, algorithm)?;
+    Some(curve)
+}
+
+/// Return the remainder of the certificate after its `validity`, and the `validity` contents.
+fn validity(cert: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (cert, _) = read_tlv(DER_SEQUENCE_TAG, cert)?;
+    let (tbs, _) = read_tlv(DER_SEQUENCE_TAG, cert)?;
+
+    // version [0] EXPLICIT Version DEFAULT v1
+    let tbs = match read_tlv(DER_VERSION_TAG, tbs) {
+        Some((_, rest)) => rest,
+        None => tbs,
+    };
+
+    let (_serial, tbs) = read_tlv(DER_INTEGER_TAG, tbs)?;
+    let (_signature, tbs) = read_tlv(DER_SEQUENCE_TAG, tbs)?;
+    let (_issuer, tbs) = read_tlv(DER_SEQUENCE_TAG, tbs)?;
+    let (validity, tbs) = read_tlv(DER_SEQUENCE_TAG, tbs)?;
+    Some((tbs, validity))
+}
+
+/// Read a `Time` (either `UTCTime` or `GeneralizedTime`) from the front of `input`.
+///
+/// Returns the remaining input and the parsed time.
+fn read_time(input: &[u8]) -> Option<(&[u8], UnixTime)> {
+    let (&tag, _) = input.split_first()?;
+    let (value, rest) = read_tlv(tag, input)?;
+
+    let (year, value) = match tag {
+        DER_UTC_TIME_TAG if value.len() == 13 => {
+            // RFC 5280 4.1.2.5.1: YY >= 50 is 19YY, otherwise 20YY.
+            let yy = digits(&value[..2])?;
+            let year = if yy >= 50 { 1900 + yy } else { 2000 + yy };
+            (year, &value[2..])
+        }
+        DER_GENERALIZED_TIME_TAG if value.len() == 15 => (digits(&value[..4])?, &value[4..]),
+        _ => return None,
+    };
+
+    if value.last() != Some(&b'Z') {
+        return None;
+    }
+
+    let month = digits(&value[0..2])?;
+    let day = digits(&value[2..4])?;
+    let hours = digits(&value[4..6])?;
+    let minutes = digits(&value[6..8])?;
+    let seconds = digits(&value[8..10])?;
+
+    if !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+        || hours > 23
+        || minutes > 59
+        || seconds > 59
+    {
+        return None;
+    }
+
+    let days = days_since_epoch(year, month, day)?;
+    let secs = days * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    Some((
+        rest,
+        UnixTime::since_unix_epoch(core::time::Duration::from_secs(secs)),
+    ))
+}
+
+/// Number of days in `month` (1 to 12) of `year` in the proleptic Gregorian calendar.
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+/// Number of days between 1970-01-01 and the given date in the proleptic Gregorian calendar.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> Option<u64> {
+    if year < 1970 {
+        return None;
+    }
+
+    // cf. <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+    let (year, month) = match month {
+        1 | 2 => (year - 1, month + 9),
+        _ => (year, month - 3),
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146_097 + day_of_era - 719_468)
+}
+
+fn digits(bytes: &[u8]) -> Option<u64> {
+    bytes
+        .iter()
+        .try_fold(0u64, |acc, b| match b {
+            b'0'..=b'9' => Some(acc * 10 + u64::from(b - b'0')),
+            _ => None,
+        })
+}
+
+/// Like [`read_tlv()`], but returns the whole TLV rather than the value.
+fn read_tlv_whole(tag: u8, input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (_, rest) = read_tlv(tag, input)?;
+    Some(input.split_at(input.len() - rest.len()))
+}
+
+/// Read a DER TLV with the given `tag` from the front of `input`.
+///
+/// Returns the value and the remaining input.
+fn read_tlv(tag: u8, input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&actual, input) = input.split_first()?;
+    if actual != tag {
+        return None;
+    }
+
+    let (&first, input) = input.split_first()?;
+    let (len, input) = match first {
+        len @ 0x00..=0x7f => (len as usize, input),
+        0x81..=0x84 => {
+            let count = (first & 0x7f) as usize;
+            if input.len() < count {
+                return None;
+            }
+            let (len_bytes, input) = input.split_at(count);
+            let len = len_bytes
+                .iter()
+                .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+            (len, input)
+        }
+        _ => return None,
+    };
+
+    if input.len() < len {
+        return None;
+    }
+    Some(input.split_at(len))
+}
+
+// id-ml-dsa-{44,65,87}: 2.16.840.1.101.3.4.3.{17,18,19}, with absent parameters
+const ALG_ID_ML_DSA_44: &[u8] = &[
+    0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x03, 0x11,
+];
+const ALG_ID_ML_DSA_65: &[u8] = &[
+    0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x03, 0x12,
+];
+const ALG_ID_ML_DSA_87: &[u8] = &[
+    0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x03, 0x13,
+];
+
+// id-ce-subjectAltName: 2.5.29.17
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+// id-ce-certificatePolicies: 2.5.29.32
+const OID_CERTIFICATE_POLICIES: &[u8] = &[0x55, 0x1d, 0x20];
+// id-ce-extKeyUsage: 2.5.29.37
+const OID_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+// rsaEncryption: 1.2.840.113549.1.1.1
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+// id-ecPublicKey: 1.2.840.10045.2.1
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+// secp256r1: 1.2.840.10045.3.1.7
+const OID_SECP256R1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+// secp384r1: 1.3.132.0.34
+const OID_SECP384R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+// secp521r1: 1.3.132.0.35
+const OID_SECP521R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x23];
+// id-Ed25519: 1.3.101.112
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+// id-Ed448: 1.3.101.113
+const OID_ED448: &[u8] = &[0x2b, 0x65, 0x71];
+// id-kp-OCSPSigning: 1.3.6.1.5.5.7.3.9
+const OID_KP_OCSP_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+// id-pkix-ocsp-basic: 1.3.6.1.5.5.7.48.1.1
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+// id-sha1: 1.3.14.3.2.26
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+// id-sha256: 2.16.840.1.101.3.4.2.1
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+// id-sha384: 2.16.840.1.101.3.4.2.2
+const OID_SHA384: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02];
+// id-sha512: 2.16.840.1.101.3.4.2.3
+const OID_SHA512: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03];
+
 const DER_SEQUENCE_TAG: u8 = 0x30;
+const DER_BOOLEAN_TAG: u8 = 0x01;
+const DER_OID_TAG: u8 = 0x06;
+const DER_ISSUER_UID_TAG: u8 = 0x81;
+const DER_SUBJECT_UID_TAG: u8 = 0x82;
+const DER_EXTENSIONS_TAG: u8 = 0xa3;
+const DER_SAN_IP_ADDRESS_TAG: u8 = 0x87;
 const DER_BIT_STRING_TAG: u8 = 0x03;
 const DER_OCTET_STRING_TAG: u8 = 0x04;
+const DER_INTEGER_TAG: u8 = 0x02;
+const DER_UTC_TIME_TAG: u8 = 0x17;
+const DER_GENERALIZED_TIME_TAG: u8 = 0x18;
+const DER_VERSION_TAG: u8 = 0xa0;
+const DER_ENUMERATED_TAG: u8 = 0x0a;
+const DER_OCSP_RESPONSE_BYTES_TAG: u8 = 0xa0;
+const DER_OCSP_BY_NAME_TAG: u8 = 0xa1;
+const DER_OCSP_BY_KEY_TAG: u8 = 0xa2;
+const DER_OCSP_NEXT_UPDATE_TAG: u8 = 0xa0;
+const DER_OCSP_CERTS_TAG: u8 = 0xa0;
+const DER_OCSP_GOOD_TAG: u8 = 0x80;
+const DER_OCSP_REVOKED_TAG: u8 = 0xa1;
+const DER_OCSP_UNKNOWN_TAG: u8 = 0x82;
+
+/// `OCSPResponseStatus` value `successful`.
+const OCSP_SUCCESSFUL: u8 = 0;
 
 #[cfg(test)]
 mod tests {
@@ -67,6 +691,43 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_signature_scheme() {
+        for (cert, scheme) in [
+            (
+                &include_bytes!("../../test-ca/ecdsa-p256/end.der")[..],
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+            ),
+            (
+                &include_bytes!("../../test-ca/ecdsa-p384/end.der")[..],
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+            ),
+            (
+                &include_bytes!("../../test-ca/rsa-2048/end.der")[..],
+                SignatureScheme::RSA_PKCS1_SHA256,
+            ),
+            (
+                &include_bytes!("../../test-ca/eddsa/end.der")[..],
+                SignatureScheme::ED25519,
+            ),
+        ] {
+            assert_eq!(signature_scheme(cert, None), Some(scheme));
+        }
+        assert_eq!(signature_scheme(&[0x30, 0x00], None), None);
+
+        // the issuer's curve must agree with the hash
+        let p256_end = include_bytes!("../../test-ca/ecdsa-p256/end.der");
+        let p256_inter = include_bytes!("../../test-ca/ecdsa-p256/inter.der");
+        let p384_inter = include_bytes!("../../test-ca/ecdsa-p384/inter.der");
+        assert_eq!(
+            signature_scheme(p256_end, Some(p256_inter)),
+            Some(SignatureScheme::ECDSA_NISTP256_SHA256)
+        );
+        assert_eq!(signature_scheme(p256_end, Some(p384_inter)), None);
+        let rsa_inter = include_bytes!("../../test-ca/rsa-2048/inter.der");
+        assert_eq!(signature_scheme(p256_end, Some(rsa_inter)), None);
+    }
+
     #[test]
     fn test_empty() {
         assert_eq!(vec![0x30, 0x00], wrap_in_sequence(&[]));
@@ -144,4 +805,127 @@ mod tests {
         // ^ tag   ^ len   ^ no unused bits    ^ value
         assert_eq!(wrap_in_bit_string(&[0x55u8]), vec![0x03, 0x02, 0x00, 0x55]);
     }
+
+    #[test]
+    fn test_not_after_utc_time() {
+        // notAfter=Mar 30 23:59:59 2022 GMT
+        let cert = include_bytes!("testdata/cert-github.0.der");
+        assert_eq!(not_after(cert).unwrap().as_secs(), 1_648_684_799);
+    }
+
+    #[test]
+    fn test_not_after_generalized_time() {
+        // notAfter=Jan  1 00:00:00 4096 GMT
+        let cert = include_bytes!("../../test-ca/rsa-2048/end.der");
+        assert_eq!(not_after(cert).unwrap().as_secs(), 67_090_118_400);
+    }
+
+    #[test]
+    fn test_not_before() {
+        // notBefore=Jan  1 00:00:00 1975 GMT
+        let cert = include_bytes!("../../test-ca/rsa-2048/end.der");
+        assert_eq!(not_before(cert).unwrap().as_secs(), 157_766_400);
+        assert_eq!(not_before(&[0x30, 0x00]), None);
+    }
+
+    #[test]
+    fn test_subject_alt_ip_addresses() {
+        let cert = include_bytes!("../../test-ca/ecdsa-p256/end.der");
+        assert_eq!(
+            subject_alt_ip_addresses(cert),
+            vec![
+                &[198, 51, 100, 1][..],
+                &[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1][..],
+            ]
+        );
+        let ca = include_bytes!("../../test-ca/ecdsa-p256/ca.der");
+        assert!(subject_alt_ip_addresses(ca).is_empty());
+    }
+
+    #[test]
+    fn test_extended_key_usages() {
+        // id-kp-clientAuth: 1.3.6.1.5.5.7.3.2
+        let client = include_bytes!("../../test-ca/rsa-2048/client.der");
+        assert_eq!(
+            extended_key_usages(client),
+            vec![&[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02][..]]
+        );
+        assert!(certificate_policies(client).is_empty());
+        assert!(extended_key_usages(&[0x30, 0x00]).is_empty());
+    }
+
+    #[test]
+    fn test_ocsp_next_update() {
+        // nextUpdate=Jan  1 00:00:00 2030 GMT
+        let response = ocsp_response(0, Some(b"20300101000000Z"));
+        assert_eq!(
+            ocsp_next_update(&response)
+                .unwrap()
+                .unwrap()
+                .as_secs(),
+            1_893_456_000
+        );
+
+        assert_eq!(ocsp_next_update(&ocsp_response(0, None)), Some(None));
+        // tryLater
+        assert_eq!(ocsp_next_update(&ocsp_response(3, None)), None);
+        assert_eq!(ocsp_next_update(b"hello-ocsp-world!"), None);
+        assert_eq!(ocsp_next_update(&[0x30, 0x00]), None);
+    }
+
+    /// A minimal `OCSPResponse`: neither signed nor about any particular certificate.
+    fn ocsp_response(status: u8, next_update: Option<&[u8]>) -> Vec<u8> {
+        let time = |time: &[u8]| asn1_wrap(DER_GENERALIZED_TIME_TAG, time, &[]);
+
+        let mut single = wrap_in_sequence(&[]); // certID
+        single.extend_from_slice(&[0x80, 0x00]); // certStatus: good
+        single.extend(time(b"20240101000000Z")); // thisUpdate
+        if let Some(next_update) = next_update {
+            single.extend(asn1_wrap(DER_OCSP_NEXT_UPDATE_TAG, &time(next_update), &[]));
+        }
+
+        let mut tbs = asn1_wrap(DER_OCSP_BY_KEY_TAG, &wrap_in_octet_string(&[1; 20]), &[]);
+        tbs.extend(time(b"20240101000000Z")); // producedAt
+        tbs.extend(wrap_in_sequence(&wrap_in_sequence(&single)));
+
+        let mut basic = wrap_in_sequence(&tbs);
+        basic.extend(wrap_in_sequence(&[])); // signatureAlgorithm
+        basic.extend(wrap_in_bit_string(&[])); // signature
+
+        let mut response_bytes = asn1_wrap(DER_OID_TAG, OID_OCSP_BASIC, &[]);
+        response_bytes.extend(wrap_in_octet_string(&wrap_in_sequence(&basic)));
+
+        let mut response = asn1_wrap(DER_ENUMERATED_TAG, &[status], &[]);
+        if status == OCSP_SUCCESSFUL {
+            response.extend(asn1_wrap(
+                DER_OCSP_RESPONSE_BYTES_TAG,
+                &wrap_in_sequence(&response_bytes),
+                &[],
+            ));
+        }
+        wrap_in_sequence(&response)
+    }
+
+    #[test]
+    fn test_not_after_rejects_junk() {
+        assert_eq!(not_after(&[]), None);
+        assert_eq!(not_after(&[0x30, 0x00]), None);
+        assert_eq!(not_after(&[0x30, 0x85, 0x00]), None);
+    }
+
+    #[test]
+    fn test_read_time_validates_dates() {
+        fn generalized(time: &[u8]) -> Option<u64> {
+            let der = asn1_wrap(DER_GENERALIZED_TIME_TAG, time, &[]);
+            read_time(&der).map(|(_, time)| time.as_secs())
+        }
+
+        assert_eq!(generalized(b"20240229000000Z"), Some(1_709_164_800));
+        assert_eq!(generalized(b"20000229000000Z"), Some(951_782_400));
+        assert_eq!(generalized(b"20230229000000Z"), None);
+        assert_eq!(generalized(b"21000229000000Z"), None);
+        assert_eq!(generalized(b"20240231000000Z"), None);
+        assert_eq!(generalized(b"20240431000000Z"), None);
+        assert_eq!(generalized(b"20240430000000Z"), Some(1_714_435_200));
+    }
 }
@@ -7,10 +7,15 @@ use std::io::{self, IoSlice, Read, Write};
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{fmt, mem};
 
 use pki_types::{CertificateDer, IpAddr, ServerName, UnixTime};
-use rustls::client::{verify_server_cert_signed_by_trust_anchor, ResolvesClientCert, Resumption};
+use rustls::client::{
+    verify_server_cert_signed_by_trust_anchor, DowngradeSentinel, ResolvesClientCert,
+    ResolvesClientCertUsingFn, ResolvesClientCertUsingIssuers, Resumption, ResumptionDecision,
+    ServiceBinding, ServiceBindingError,
+};
 use rustls::crypto::CryptoProvider;
 use rustls::internal::msgs::base::Payload;
 use rustls::internal::msgs::codec::Codec;
@@ -20,10 +25,22 @@ use rustls::internal::msgs::handshake::{
     ServerName as ServerNameExtensionItem, SessionId,
 };
 use rustls::internal::msgs::message::{Message, MessagePayload, PlainMessage};
-use rustls::server::{ClientHello, ParsedCertificate, ResolvesServerCert};
+use rustls::server::{
+    ClientHello, ParsedCertificate, ResolvesServerCert, ResolvesServerCertUsingFn,
+    ResolvesServerCertUsingSignatureSchemes, RevocationCheck,
+};
+use rustls::{
+    alpn, sign, AlertDescription, AlpnProtocolError, ApiMisuse, CertificateError, CipherSuite,
+    ClientConfig, ClientConnection, ConnectionCommon, ConnectionTrafficSecrets, ContentType,
+    DistinguishedName, Error, HandshakeKind, HandshakeType, InconsistentKeys, InvalidMessage,
+    KeyLimitAction, KeyLimits, KeyLog, MaxFragmentLength, NamedGroup, PeerIncompatible,
+    PeerMisbehaved, PostHandshakeLimits, ProtocolVersion, ResumptionKind, ServerConfig,
+    ServerConnection, SideData, SignatureScheme, Stream, StreamOwned, SupportedCipherSuite,
+    WarningAlert,
+};
 #[cfg(feature = "aws_lc_rs")]
 use rustls::{
-    client::{EchConfig, EchGreaseConfig, EchMode},
+    client::{EchConfig, EchGreaseConfig, EchMode, EchStatus, ResolvesEchConfig},
     crypto::aws_lc_rs::hpke::ALL_SUPPORTED_SUITES,
     internal::msgs::base::PayloadU16,
     internal::msgs::handshake::{
@@ -31,13 +48,6 @@ use rustls::{
     },
     pki_types::{DnsName, EchConfigListBytes},
 };
-use rustls::{
-    sign, AlertDescription, CertificateError, CipherSuite, ClientConfig, ClientConnection,
-    ConnectionCommon, ConnectionTrafficSecrets, ContentType, DistinguishedName, Error,
-    HandshakeKind, HandshakeType, InconsistentKeys, InvalidMessage, KeyLog, NamedGroup,
-    PeerIncompatible, PeerMisbehaved, ProtocolVersion, ServerConfig, ServerConnection, SideData,
-    SignatureScheme, Stream, StreamOwned, SupportedCipherSuite,
-};
 
 use super::*;
 
@@ -115,6 +125,54 @@ fn alpn() {
     );
 }
 
+#[test]
+fn alpn_lenient_policy() {
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.alpn_protocols = vec![b"server-proto".to_vec()];
+    server_config.alpn_policy = rustls::server::AlpnPolicy::Lenient;
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.alpn_protocols = vec![b"client-proto".to_vec()];
+
+        // no overlap: the handshake continues without a protocol
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.alpn_protocol(), None);
+        assert_eq!(server.alpn_protocol(), None);
+    }
+}
+
+#[test]
+fn alpn_with_known_protocols() {
+    let server_config = make_server_config(KeyType::Rsa2048)
+        .with_alpn_protocols([alpn::H2, alpn::HTTP_1_1])
+        .unwrap();
+    let client_config = make_client_config(KeyType::Rsa2048)
+        .with_alpn_protocols([alpn::HTTP_1_1, b"spdy/1"])
+        .unwrap();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(client.application_protocol(), None);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client.application_protocol(),
+        Some(alpn::ApplicationProtocol::Http11)
+    );
+    assert_eq!(
+        server.application_protocol(),
+        Some(alpn::ApplicationProtocol::Http11)
+    );
+
+    assert_eq!(
+        make_client_config(KeyType::Rsa2048)
+            .with_alpn_protocols([alpn::H2, alpn::H2])
+            .err(),
+        Some(Error::InvalidAlpnProtocols(AlpnProtocolError::Duplicate))
+    );
+}
+
 fn version_test(
     client_versions: &[&'static rustls::SupportedProtocolVersion],
     server_versions: &[&'static rustls::SupportedProtocolVersion],
@@ -713,6 +771,63 @@ fn client_close_notify() {
     }
 }
 
+#[test]
+fn half_close_by_default() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+        do_handshake(&mut client, &mut server);
+
+        client.send_close_notify();
+        assert!(client.has_sent_close_notify());
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        assert!(server.peer_has_closed());
+        assert!(!server.has_sent_close_notify());
+        assert!(!server.wants_write());
+
+        // the server may continue sending
+        server
+            .writer()
+            .write_all(b"from-server!")
+            .unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        check_read(&mut client.reader(), b"from-server!");
+    }
+}
+
+#[test]
+fn respond_to_close_notify() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.respond_to_close_notify = true;
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+
+        client.send_close_notify();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        assert!(server.peer_has_closed());
+        assert!(server.has_sent_close_notify());
+        assert_eq!(
+            server
+                .writer()
+                .write(b"from-server!")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::BrokenPipe
+        );
+
+        transfer(&mut server, &mut client);
+        let io_state = client.process_new_packets().unwrap();
+        assert!(io_state.peer_has_closed());
+        assert!(client.peer_has_closed());
+    }
+}
+
 #[test]
 fn server_closes_uncleanly() {
     let kt = KeyType::Rsa2048;
@@ -803,3252 +918,5893 @@ fn client_closes_uncleanly() {
     }
 }
 
-#[test]
-fn test_tls13_valid_early_plaintext_alert() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+#[derive(Debug, Default)]
+struct ShutdownCounter {
+    close_notify: AtomicUsize,
+    unexpected_eof: AtomicUsize,
+}
 
-    // Perform the start of a TLS 1.3 handshake, sending a client hello to the server.
-    // The client will not have written a CCS or any encrypted messages to the server yet.
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
+impl ShutdownCounter {
+    fn counts(&self) -> (usize, usize) {
+        (
+            self.close_notify.load(Ordering::SeqCst),
+            self.unexpected_eof
+                .load(Ordering::SeqCst),
+        )
+    }
+}
 
-    // Inject a plaintext alert from the client. The server should accept this since:
-    //  * It hasn't decrypted any messages from the peer yet.
-    //  * The message content type is Alert.
-    //  * The payload size is indicative of a plaintext alert message.
-    //  * The negotiated protocol version is TLS 1.3.
-    server
-        .read_tls(&mut io::Cursor::new(&build_alert(
-            AlertLevel::Fatal,
-            AlertDescription::UnknownCA,
-            &[],
-        )))
-        .unwrap();
+impl rustls::ObservesShutdown for ShutdownCounter {
+    fn close_notify_received(&self, _: Option<&(dyn std::any::Any + Send)>) {
+        self.close_notify
+            .fetch_add(1, Ordering::SeqCst);
+    }
 
-    // The server should process the plaintext alert without error.
-    assert_eq!(
-        server.process_new_packets(),
-        Err(Error::AlertReceived(AlertDescription::UnknownCA)),
-    );
+    fn unexpected_eof(&self, _: Option<&(dyn std::any::Any + Send)>) {
+        self.unexpected_eof
+            .fetch_add(1, Ordering::SeqCst);
+    }
 }
 
 #[test]
-fn test_tls13_too_short_early_plaintext_alert() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+fn shutdown_observer_sees_close_notify() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        let server_config = make_server_config(KeyType::Rsa2048);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        let observer = Arc::new(ShutdownCounter::default());
+        server.set_shutdown_observer(observer.clone());
+        do_handshake(&mut client, &mut server);
 
-    // Perform the start of a TLS 1.3 handshake, sending a client hello to the server.
-    // The client will not have written a CCS or any encrypted messages to the server yet.
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
+        client.send_close_notify();
+        transfer(&mut client, &mut server);
+        transfer_eof(&mut server);
+        server.process_new_packets().unwrap();
+        assert_eq!(observer.counts(), (1, 0));
+    }
+}
 
-    // Inject a plaintext alert from the client. The server should attempt to decrypt this message
-    // because the payload length is too large to be considered an early plaintext alert.
-    server
-        .read_tls(&mut io::Cursor::new(&build_alert(
-            AlertLevel::Fatal,
-            AlertDescription::UnknownCA,
-            &[0xff],
-        )))
-        .unwrap();
+#[test]
+fn shutdown_observer_sees_unexpected_eof() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        let server_config = make_server_config(KeyType::Rsa2048);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        let observer = Arc::new(ShutdownCounter::default());
+        client.set_shutdown_observer(observer.clone());
+        do_handshake(&mut client, &mut server);
 
-    // The server should produce a decrypt error trying to decrypt the plaintext alert.
-    assert_eq!(server.process_new_packets(), Err(Error::DecryptError),);
+        transfer_eof(&mut client);
+        transfer_eof(&mut client);
+        client.process_new_packets().unwrap();
+        assert_eq!(observer.counts(), (0, 1));
+    }
 }
 
 #[test]
-fn test_tls13_late_plaintext_alert() {
+fn connection_user_data() {
+    #[derive(Debug, PartialEq)]
+    struct RequestId(u64);
+
     let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    assert!(client.user_data_mut().is_none());
 
-    // Complete a bi-directional TLS1.3 handshake. After this point no plaintext messages
-    // should occur.
+    client.set_user_data(Box::new(RequestId(1)));
+    // the data need only be `Send`
+    server.set_user_data(Box::new(std::cell::Cell::new("server")));
     do_handshake(&mut client, &mut server);
 
-    // Inject a plaintext alert from the client. The server should attempt to decrypt this message.
-    server
-        .read_tls(&mut io::Cursor::new(&build_alert(
-            AlertLevel::Fatal,
-            AlertDescription::UnknownCA,
-            &[],
-        )))
-        .unwrap();
-
-    // The server should produce a decrypt error, trying to decrypt a plaintext alert.
-    assert_eq!(server.process_new_packets(), Err(Error::DecryptError));
+    if let Some(id) = client
+        .user_data_mut()
+        .and_then(|data| data.downcast_mut::<RequestId>())
+    {
+        id.0 += 1;
+    }
+    assert_eq!(
+        client
+            .take_user_data()
+            .and_then(|data| data.downcast::<RequestId>().ok()),
+        Some(Box::new(RequestId(2)))
+    );
+    assert!(client.user_data_mut().is_none());
+    assert_eq!(
+        server
+            .user_data_mut()
+            .and_then(|data| data.downcast_mut::<std::cell::Cell<&str>>())
+            .map(|data| data.get()),
+        Some("server")
+    );
 }
 
-fn build_alert(level: AlertLevel, desc: AlertDescription, suffix: &[u8]) -> Vec<u8> {
-    let mut v = vec![ContentType::Alert.into()];
-    ProtocolVersion::TLSv1_2.encode(&mut v);
-    ((2 + suffix.len()) as u16).encode(&mut v);
-    level.encode(&mut v);
-    desc.encode(&mut v);
-    v.extend_from_slice(suffix);
-    v
-}
+#[test]
+fn connection_user_data_reaches_callbacks() {
+    use std::any::Any;
+    use std::cell::Cell;
 
-#[derive(Default, Debug)]
-struct ServerCheckCertResolve {
-    expected_sni: Option<String>,
-    expected_sigalgs: Option<Vec<SignatureScheme>>,
-    expected_alpn: Option<Vec<Vec<u8>>>,
-    expected_cipher_suites: Option<Vec<CipherSuite>>,
-}
+    use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls::DigitallySignedStruct;
 
-impl ResolvesServerCert for ServerCheckCertResolve {
-    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
-        if client_hello
-            .signature_schemes()
-            .is_empty()
-        {
-            panic!("no signature schemes shared by client");
-        }
+    #[derive(Debug, Default)]
+    struct Seen(Mutex<Vec<String>>);
 
-        if client_hello.cipher_suites().is_empty() {
-            panic!("no cipher suites shared by client");
+    impl Seen {
+        fn note(&self, callback: &str, data: Option<&(dyn Any + Send)>) {
+            let label = data
+                .and_then(|data| data.downcast_ref::<Cell<&str>>())
+                .map(Cell::get);
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{callback} {label:?}"));
         }
+    }
 
-        if let Some(expected_sni) = &self.expected_sni {
-            let sni: &str = client_hello
-                .server_name()
-                .expect("sni unexpectedly absent");
-            assert_eq!(expected_sni, sni);
+    #[derive(Debug)]
+    struct Resolver(Arc<dyn ResolvesServerCert>, Arc<Seen>);
+
+    impl ResolvesServerCert for Resolver {
+        fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<sign::CertifiedKey>> {
+            self.1
+                .note("resolve", client_hello.user_data());
+            self.0.resolve(client_hello)
         }
+    }
 
-        if let Some(expected_sigalgs) = &self.expected_sigalgs {
-            assert_eq!(
-                expected_sigalgs,
-                client_hello.signature_schemes(),
-                "unexpected signature schemes"
-            );
+    #[derive(Debug)]
+    struct Verifier(Arc<dyn ServerCertVerifier>, Arc<Seen>);
+
+    impl ServerCertVerifier for Verifier {
+        fn verify_server_cert(
+            &self,
+            _: &CertificateDer<'_>,
+            _: &[CertificateDer<'_>],
+            _: &ServerName<'_>,
+            _: &[u8],
+            _: UnixTime,
+        ) -> Result<ServerCertVerified, Error> {
+            unreachable!("verify_server_cert_with_user_data is called instead");
         }
 
-        if let Some(expected_alpn) = &self.expected_alpn {
-            let alpn = client_hello
-                .alpn()
-                .expect("alpn unexpectedly absent")
-                .collect::<Vec<_>>();
-            assert_eq!(alpn.len(), expected_alpn.len());
+        fn verify_server_cert_with_user_data(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            intermediates: &[CertificateDer<'_>],
+            server_name: &ServerName<'_>,
+            ocsp_response: &[u8],
+            now: UnixTime,
+            user_data: Option<&(dyn Any + Send)>,
+        ) -> Result<ServerCertVerified, Error> {
+            self.1.note("verify", user_data);
+            self.0
+                .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        }
 
-            for (got, wanted) in alpn.iter().zip(expected_alpn.iter()) {
-                assert_eq!(got, &wanted.as_slice());
-            }
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            self.0
+                .verify_tls12_signature(message, cert, dss)
         }
 
-        if let Some(expected_cipher_suites) = &self.expected_cipher_suites {
-            assert_eq!(
-                expected_cipher_suites,
-                client_hello.cipher_suites(),
-                "unexpected cipher suites"
-            );
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            self.0
+                .verify_tls13_signature(message, cert, dss)
         }
 
-        None
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.0.supported_verify_schemes()
+        }
     }
-}
 
-#[test]
-fn server_cert_resolve_with_sni() {
-    for kt in ALL_KEY_TYPES {
-        let client_config = make_client_config(*kt);
-        let mut server_config = make_server_config(*kt);
+    #[derive(Debug)]
+    struct Observer(Arc<Seen>);
 
-        server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
-            expected_sni: Some("the-value-from-sni".into()),
-            ..Default::default()
-        });
+    impl rustls::ObservesConnection for Observer {
+        fn handshake_complete(&self, _: HandshakeKind, user_data: Option<&(dyn Any + Send)>) {
+            self.0
+                .note("handshake_complete", user_data);
+        }
+    }
 
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), server_name("the-value-from-sni"))
-                .unwrap();
-        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+    for version in rustls::ALL_VERSIONS {
+        let seen = Arc::new(Seen::default());
+        let kt = KeyType::Rsa2048;
 
-        let err = do_handshake_until_error(&mut client, &mut server);
-        assert!(err.is_err());
-    }
-}
-
-#[test]
-fn server_cert_resolve_with_alpn() {
-    for kt in ALL_KEY_TYPES {
-        let mut client_config = make_client_config(*kt);
-        client_config.alpn_protocols = vec!["foo".into(), "bar".into()];
-
-        let mut server_config = make_server_config(*kt);
-        server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
-            expected_alpn: Some(vec![b"foo".to_vec(), b"bar".to_vec()]),
-            ..Default::default()
-        });
+        let mut client_config = make_client_config_with_versions(kt, &[version]);
+        let verifier = webpki_server_verifier_builder(get_client_root_store(kt))
+            .build()
+            .unwrap();
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(Verifier(verifier, Arc::clone(&seen))));
+        let mut server_config = make_server_config(kt);
+        server_config.cert_resolver = Arc::new(Resolver(
+            Arc::clone(&server_config.cert_resolver),
+            Arc::clone(&seen),
+        ));
 
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), server_name("sni-value")).unwrap();
-        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        client.set_user_data(Box::new(Cell::new("client")));
+        server.set_user_data(Box::new(Cell::new("server")));
+        client.set_connection_observer(Arc::new(Observer(Arc::clone(&seen))));
+        server.set_connection_observer(Arc::new(Observer(Arc::clone(&seen))));
+        do_handshake(&mut client, &mut server);
 
-        let err = do_handshake_until_error(&mut client, &mut server);
-        assert!(err.is_err());
+        let mut seen = seen.0.lock().unwrap().clone();
+        seen.sort();
+        assert_eq!(
+            seen,
+            [
+                "handshake_complete Some(\"client\")",
+                "handshake_complete Some(\"server\")",
+                "resolve Some(\"server\")",
+                "verify Some(\"client\")",
+            ]
+        );
     }
 }
 
 #[test]
-fn client_trims_terminating_dot() {
-    for kt in ALL_KEY_TYPES {
-        let client_config = make_client_config(*kt);
-        let mut server_config = make_server_config(*kt);
+fn alert_policy_overrides_alert() {
+    #[derive(Debug)]
+    struct DenyBadCertificates;
 
-        server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
-            expected_sni: Some("some-host.com".into()),
-            ..Default::default()
-        });
+    impl rustls::ChoosesAlerts for DenyBadCertificates {
+        fn alert_for(&self, error: &Error, alert: AlertDescription) -> AlertDescription {
+            match error {
+                Error::InvalidCertificate(_) => AlertDescription::AccessDenied,
+                _ => alert,
+            }
+        }
+    }
 
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), server_name("some-host.com.")).unwrap();
-        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.alert_policy = Some(Arc::new(DenyBadCertificates));
+        let mut client = ClientConnection::new(
+            Arc::new(client_config),
+            server_name("not-the-right-hostname.com"),
+        )
+        .unwrap();
+        let mut server =
+            ServerConnection::new(Arc::new(make_server_config(KeyType::Rsa2048))).unwrap();
 
-        let err = do_handshake_until_error(&mut client, &mut server);
-        assert!(err.is_err());
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                CertificateError::NotValidForName
+            )))
+        );
+        transfer(&mut client, &mut server);
+        assert_eq!(
+            server.process_new_packets(),
+            Err(Error::AlertReceived(AlertDescription::AccessDenied))
+        );
     }
 }
 
 #[test]
-fn server_ignores_sni_with_ip_address() {
-    fn insert_ip_address_server_name(msg: &mut Message) -> Altered {
-        alter_sni_extension(
-            msg,
-            |snr| {
-                snr.clear();
-                snr.push(ServerNameExtensionItem::read_bytes(b"\x00\x00\x071.1.1.1").unwrap());
-            },
-            |parsed, _encoded| Payload::new(parsed.get_encoding()),
-        )
+fn server_silent_rejection() {
+    for version in rustls::ALL_VERSIONS {
+        // no certificate is available for any name
+        let client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.cert_resolver = Arc::new(rustls::server::ResolvesServerCertUsingSni::new());
+        server_config.silent_rejection = true;
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+        transfer(&mut client, &mut server);
+        assert!(server.process_new_packets().is_err());
+        assert!(!server.wants_write());
     }
 
-    check_sni_error(
-        insert_ip_address_server_name,
-        Error::General("no server certificate chain resolved".to_string()),
+    // errors after the server's first flight are still reported
+    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa2048);
+    server_config.silent_rejection = true;
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa2048), server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
     );
+    assert!(server.wants_write());
 }
 
-#[test]
-fn server_rejects_sni_with_illegal_dns_name() {
-    fn insert_illegal_server_name(msg: &mut Message) -> Altered {
-        alter_sni_extension(
-            msg,
-            |_| (),
-            |_, encoded| {
-                // replace "localhost" with invalid DNS name
-                let mut altered = encoded.clone().into_vec();
-                let needle = b"localhost";
-                let index = altered
-                    .windows(needle.len())
-                    .position(|window| window == needle)
-                    .unwrap();
-                altered[index..index + needle.len()].copy_from_slice(b"ab@cd.com");
-                Payload::new(altered)
-            },
-        )
+#[derive(Debug, Default)]
+struct EventLog(Mutex<Vec<String>>);
+
+impl EventLog {
+    fn take(&self) -> Vec<String> {
+        core::mem::take(&mut *self.0.lock().unwrap())
     }
 
-    check_sni_error(
-        insert_illegal_server_name,
-        Error::InvalidMessage(InvalidMessage::InvalidServerName),
-    );
+    fn push(&self, event: String) {
+        self.0.lock().unwrap().push(event);
+    }
 }
 
-fn alter_sni_extension(
-    msg: &mut Message,
-    alter_inner: impl Fn(&mut Vec<ServerNameExtensionItem>),
-    alter_encoding: impl Fn(&mut HandshakeMessagePayload, &mut Payload) -> Payload<'static>,
-) -> Altered {
-    if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
-        if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
-            for mut ext in ch.extensions.iter_mut() {
-                if let ClientExtension::ServerName(snr) = &mut ext {
-                    alter_inner(snr);
-                }
-            }
-            *encoded = alter_encoding(parsed, encoded);
-        }
+impl rustls::ObservesConnection for EventLog {
+    fn handshake_complete(&self, kind: HandshakeKind, _: Option<&(dyn std::any::Any + Send)>) {
+        self.push(format!("handshake_complete {kind:?}"));
     }
 
-    Altered::InPlace
-}
+    fn resumption_accepted(
+        &self,
+        kind: rustls::ResumptionKind,
+        _: Option<&(dyn std::any::Any + Send)>,
+    ) {
+        self.push(format!("resumption_accepted {kind:?}"));
+    }
 
-fn check_sni_error(alteration: impl Fn(&mut Message) -> Altered, expected_error: Error) {
-    for kt in ALL_KEY_TYPES {
-        let client_config = make_client_config(*kt);
-        let mut server_config = make_server_config(*kt);
+    fn key_update_sent(&self, _: Option<&(dyn std::any::Any + Send)>) {
+        self.push("key_update_sent".into());
+    }
 
-        server_config.cert_resolver = Arc::new(ServerCheckNoSni {});
+    fn key_update_received(&self, _: Option<&(dyn std::any::Any + Send)>) {
+        self.push("key_update_received".into());
+    }
 
-        let client =
-            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-        let server = ServerConnection::new(Arc::new(server_config)).unwrap();
-        let (mut client, mut server) = (client.into(), server.into());
+    fn ticket_sent(&self, _: Option<&(dyn std::any::Any + Send)>) {
+        self.push("ticket_sent".into());
+    }
 
-        transfer_altered(&mut client, &alteration, &mut server);
-        assert_eq!(server.process_new_packets(), Err(expected_error.clone()),);
+    fn ticket_received(&self, _: Option<&(dyn std::any::Any + Send)>) {
+        self.push("ticket_received".into());
+    }
 
-        let server_inner = match server {
-            rustls::Connection::Server(server) => server,
-            _ => unreachable!(),
-        };
-        assert_eq!(None, server_inner.server_name());
+    fn alert_received(&self, alert: AlertDescription, _: Option<&(dyn std::any::Any + Send)>) {
+        self.push(format!("alert_received {alert:?}"));
     }
 }
 
-#[cfg(feature = "tls12")]
-fn check_sigalgs_reduced_by_ciphersuite(
-    kt: KeyType,
-    suite: CipherSuite,
-    expected_sigalgs: Vec<SignatureScheme>,
-) {
-    let client_config = finish_client_config(
-        kt,
-        ClientConfig::builder_with_provider(
-            CryptoProvider {
-                cipher_suites: vec![find_suite(suite)],
-                ..provider::default_provider()
-            }
-            .into(),
-        )
-        .with_safe_default_protocol_versions()
-        .unwrap(),
-    );
+#[test]
+fn connection_observer_sees_events() {
+    let client_config = Arc::new(make_client_config_with_versions(
+        KeyType::Rsa2048,
+        &[&rustls::version::TLS13],
+    ));
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.send_tls13_tickets = 1;
+    let server_config = Arc::new(server_config);
 
-    let mut server_config = make_server_config(kt);
+    let client_log = Arc::new(EventLog::default());
+    let server_log = Arc::new(EventLog::default());
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    client.set_connection_observer(client_log.clone());
+    server.set_connection_observer(server_log.clone());
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
 
-    server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
-        expected_sigalgs: Some(expected_sigalgs),
-        expected_cipher_suites: Some(vec![suite, CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV]),
-        ..Default::default()
-    });
+    assert_eq!(
+        client_log.take(),
+        vec!["handshake_complete Full", "ticket_received"]
+    );
+    assert_eq!(
+        server_log.take(),
+        vec!["ticket_sent", "handshake_complete Full"]
+    );
 
-    let mut client =
-        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-    let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+    client.refresh_traffic_keys().unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert_eq!(client_log.take(), vec!["key_update_sent"]);
+    assert_eq!(
+        server_log.take(),
+        vec!["key_update_received", "key_update_sent"]
+    );
 
-    let err = do_handshake_until_error(&mut client, &mut server);
-    assert!(err.is_err());
-}
+    client.send_close_notify();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert_eq!(server_log.take(), vec!["alert_received CloseNotify"]);
 
-#[cfg(feature = "tls12")]
-#[test]
-fn server_cert_resolve_reduces_sigalgs_for_rsa_ciphersuite() {
-    check_sigalgs_reduced_by_ciphersuite(
-        KeyType::Rsa2048,
-        CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    client.set_connection_observer(client_log.clone());
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client_log.take(),
         vec![
-            SignatureScheme::RSA_PSS_SHA512,
-            SignatureScheme::RSA_PSS_SHA384,
-            SignatureScheme::RSA_PSS_SHA256,
-            SignatureScheme::RSA_PKCS1_SHA512,
-            SignatureScheme::RSA_PKCS1_SHA384,
-            SignatureScheme::RSA_PKCS1_SHA256,
-        ],
+            "resumption_accepted Ticket",
+            "handshake_complete Resumed",
+            "ticket_received"
+        ]
     );
 }
 
-#[cfg(feature = "tls12")]
-#[test]
-fn server_cert_resolve_reduces_sigalgs_for_ecdsa_ciphersuite() {
-    check_sigalgs_reduced_by_ciphersuite(
-        KeyType::EcdsaP256,
-        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
-        if provider_is_aws_lc_rs() {
-            vec![
-                SignatureScheme::ECDSA_NISTP384_SHA384,
-                SignatureScheme::ECDSA_NISTP256_SHA256,
-                SignatureScheme::ECDSA_NISTP521_SHA512,
-                SignatureScheme::ED25519,
-            ]
-        } else {
-            vec![
-                SignatureScheme::ECDSA_NISTP384_SHA384,
-                SignatureScheme::ECDSA_NISTP256_SHA256,
-                SignatureScheme::ED25519,
-            ]
-        },
-    );
-}
+#[derive(Debug, Default)]
+struct DiagnosticLog(Mutex<Vec<LoggedDiagnostic>>);
 
-#[derive(Debug)]
-struct ServerCheckNoSni {}
+type LoggedDiagnostic = (
+    rustls::DiagnosticLevel,
+    Option<AlertDescription>,
+    Option<Error>,
+);
 
-impl ResolvesServerCert for ServerCheckNoSni {
-    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
-        assert!(client_hello.server_name().is_none());
+impl rustls::ReceivesDiagnostics for DiagnosticLog {
+    fn enabled(&self, level: rustls::DiagnosticLevel) -> bool {
+        level <= rustls::DiagnosticLevel::Info
+    }
 
-        None
+    fn diagnostic(&self, diagnostic: &rustls::Diagnostic<'_>) {
+        assert!(!diagnostic
+            .message
+            .to_string()
+            .is_empty());
+        self.0.lock().unwrap().push((
+            diagnostic.level,
+            diagnostic.alert,
+            diagnostic.error.cloned(),
+        ));
     }
 }
 
 #[test]
-fn client_with_sni_disabled_does_not_send_sni() {
-    for kt in ALL_KEY_TYPES {
-        let mut server_config = make_server_config(*kt);
-        server_config.cert_resolver = Arc::new(ServerCheckNoSni {});
-        let server_config = Arc::new(server_config);
+fn diagnostics_report_alerts() {
+    use rustls::DiagnosticLevel;
 
-        for version in rustls::ALL_VERSIONS {
-            let mut client_config = make_client_config_with_versions(*kt, &[version]);
-            client_config.enable_sni = false;
+    let client_log = Arc::new(DiagnosticLog::default());
+    let server_log = Arc::new(DiagnosticLog::default());
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.diagnostics = Some(client_log.clone());
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.diagnostics = Some(server_log.clone());
 
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("value-not-sent"))
-                    .unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let (mut client, mut server) = make_pair_for_configs(client_config.clone(), server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client_log
+            .0
+            .lock()
+            .unwrap()
+            .drain(..)
+            .collect::<Vec<_>>(),
+        vec![(DiagnosticLevel::Info, None, None)]
+    );
 
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert!(err.is_err());
+    client.send_close_notify();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    server_log.0.lock().unwrap().clear();
+
+    let mut client = ClientConnection::new(
+        Arc::new(client_config),
+        server_name("not-the-right-hostname.com"),
+    )
+    .unwrap();
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.diagnostics = Some(server_log.clone());
+    let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+    do_handshake_until_error(&mut client, &mut server).unwrap_err();
+    transfer(&mut client, &mut server);
+    server
+        .process_new_packets()
+        .unwrap_err();
+
+    assert_eq!(
+        client_log
+            .0
+            .lock()
+            .unwrap()
+            .drain(..)
+            .collect::<Vec<_>>(),
+        vec![(
+            DiagnosticLevel::Warn,
+            Some(AlertDescription::BadCertificate),
+            Some(Error::InvalidCertificate(CertificateError::NotValidForName))
+        )]
+    );
+    assert_eq!(
+        server_log
+            .0
+            .lock()
+            .unwrap()
+            .drain(..)
+            .collect::<Vec<_>>(),
+        vec![(
+            DiagnosticLevel::Warn,
+            Some(AlertDescription::BadCertificate),
+            None
+        )]
+    );
+}
+
+#[test]
+fn diagnostics_report_handshake_details() {
+    #[derive(Debug, Default)]
+    struct MessageLog(Mutex<Vec<(rustls::DiagnosticLevel, String)>>);
+
+    impl rustls::ReceivesDiagnostics for MessageLog {
+        fn diagnostic(&self, diagnostic: &rustls::Diagnostic<'_>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((diagnostic.level, diagnostic.message.to_string()));
         }
     }
+
+    let client_log = Arc::new(MessageLog::default());
+    let server_log = Arc::new(MessageLog::default());
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.diagnostics = Some(client_log.clone());
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.diagnostics = Some(server_log.clone());
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    let suite = client
+        .negotiated_cipher_suite()
+        .unwrap()
+        .suite();
+    assert!(client_log.0.lock().unwrap().contains(&(
+        rustls::DiagnosticLevel::Debug,
+        format!("Using ciphersuite {suite:?}")
+    )));
+    assert!(server_log
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(level, message)| *level == rustls::DiagnosticLevel::Debug
+            && message.starts_with("decided upon suite")));
 }
 
-#[test]
-fn client_checks_server_certificate_with_given_name() {
-    for kt in ALL_KEY_TYPES {
-        let server_config = Arc::new(make_server_config(*kt));
+#[derive(Debug, Default)]
+struct AuditLog(Mutex<Vec<rustls::ConnectionAudit>>);
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions(*kt, &[version]);
-            let mut client = ClientConnection::new(
-                Arc::new(client_config),
-                server_name("not-the-right-hostname.com"),
-            )
-            .unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+impl rustls::ReceivesConnectionAudits for AuditLog {
+    fn connection_audit(&self, audit: &rustls::ConnectionAudit) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(audit.clone());
+    }
+}
 
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::NotValidForName
-                )))
-            );
-        }
+impl AuditLog {
+    fn take(&self) -> rustls::ConnectionAudit {
+        let mut audits = self.0.lock().unwrap();
+        assert_eq!(audits.len(), 1);
+        audits.pop().unwrap()
     }
 }
 
 #[test]
-fn client_checks_server_certificate_with_given_ip_address() {
-    fn check_server_name(
-        client_config: Arc<ClientConfig>,
-        server_config: Arc<ServerConfig>,
-        name: &'static str,
-    ) -> Result<(), ErrorFromPeer> {
-        let mut client = ClientConnection::new(client_config, server_name(name)).unwrap();
-        let mut server = ServerConnection::new(server_config).unwrap();
-        do_handshake_until_error(&mut client, &mut server)
+fn audit_sink_receives_connection_summary() {
+    use rustls::CloseReason;
+
+    for version in rustls::ALL_VERSIONS {
+        let client_log = Arc::new(AuditLog::default());
+        let server_log = Arc::new(AuditLog::default());
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.audit_sink = Some(client_log.clone());
+        client_config.alpn_protocols = vec![b"proto".to_vec()];
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.audit_sink = Some(server_log.clone());
+        server_config.alpn_protocols = vec![b"proto".to_vec()];
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        client.send_close_notify();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        let suite = client
+            .negotiated_cipher_suite()
+            .unwrap();
+        let hash = match suite {
+            SupportedCipherSuite::Tls12(suite) => suite.common.hash_provider,
+            SupportedCipherSuite::Tls13(suite) => suite.common.hash_provider,
+        };
+        drop(client);
+        drop(server);
+
+        let client_audit = client_log.take();
+        let server_audit = server_log.take();
+        assert_eq!(client_audit.side, rustls::Side::Client);
+        assert_eq!(client_audit.protocol_version, Some(version.version));
+        assert_eq!(client_audit.cipher_suite, Some(suite.suite()));
+        assert_eq!(client_audit.handshake_kind, Some(HandshakeKind::Full));
+        assert_eq!(client_audit.alpn_protocol, Some(b"proto".to_vec()));
+        assert_eq!(
+            client_audit.peer_certificate_digest_algorithm,
+            Some(hash.algorithm())
+        );
+        assert_eq!(
+            client_audit.peer_certificate_digest,
+            Some(
+                hash.hash(KeyType::Rsa2048.get_chain()[0].as_ref())
+                    .as_ref()
+                    .to_vec()
+            )
+        );
+        assert_eq!(client_audit.close_reason, CloseReason::CloseNotifySent);
+
+        assert_eq!(server_audit.side, rustls::Side::Server);
+        assert_eq!(server_audit.peer_certificate_digest, None);
+        assert_eq!(server_audit.bytes_received, client_audit.bytes_sent);
+        assert_eq!(server_audit.bytes_sent, client_audit.bytes_received);
+        assert_eq!(server_audit.close_reason, CloseReason::CloseNotifyReceived);
     }
 
-    for kt in ALL_KEY_TYPES {
-        let server_config = Arc::new(make_server_config(*kt));
+    let client_log = Arc::new(AuditLog::default());
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.audit_sink = Some(client_log.clone());
+    let mut client = ClientConnection::new(
+        Arc::new(client_config),
+        server_name("not-the-right-hostname.com"),
+    )
+    .unwrap();
+    let mut server = ServerConnection::new(Arc::new(make_server_config(KeyType::Rsa2048))).unwrap();
+    do_handshake_until_error(&mut client, &mut server).unwrap_err();
+    let audit = client_log.take();
+    assert_eq!(
+        audit.close_reason,
+        CloseReason::FatalAlertSent(AlertDescription::BadCertificate)
+    );
+    assert_eq!(
+        audit.error,
+        Some(Error::InvalidCertificate(CertificateError::NotValidForName).to_string())
+    );
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config = Arc::new(make_client_config_with_versions(*kt, &[version]));
+    // a connection dropped without closing is not audited
+    let client_log = Arc::new(AuditLog::default());
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.audit_sink = Some(client_log.clone());
+    drop(ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap());
+    assert!(client_log.0.lock().unwrap().is_empty());
 
-            // positive ipv4 case
-            assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.1"),
-                Ok(()),
-            );
+    let client_log = Arc::new(AuditLog::default());
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.audit_sink = Some(client_log.clone());
+    client_config.enable_secret_extraction = true;
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.enable_secret_extraction = true;
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    client
+        .dangerous_extract_secrets()
+        .unwrap();
+    assert_eq!(
+        client_log.take().close_reason,
+        CloseReason::SecretsExtracted
+    );
+}
 
-            // negative ipv4 case
-            assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.2"),
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::NotValidForName
-                )))
-            );
+#[derive(Debug, Default)]
+struct RecordLog {
+    sent: Mutex<Vec<rustls::RecordInfo>>,
+    received: Mutex<Vec<rustls::RecordInfo>>,
+}
 
-            // positive ipv6 case
-            assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::1"),
-                Ok(()),
-            );
+impl rustls::ObservesRecords for RecordLog {
+    fn record_sent(&self, record: &rustls::RecordInfo) {
+        self.sent.lock().unwrap().push(*record);
+    }
 
-            // negative ipv6 case
-            assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::2"),
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::NotValidForName
-                )))
-            );
-        }
+    fn record_received(&self, record: &rustls::RecordInfo) {
+        self.received
+            .lock()
+            .unwrap()
+            .push(*record);
     }
 }
 
 #[test]
-fn client_check_server_certificate_ee_revoked() {
-    for kt in ALL_KEY_TYPES {
-        let server_config = Arc::new(make_server_config(*kt));
+fn record_observer_sees_both_directions() {
+    for version in rustls::ALL_VERSIONS {
+        let client_log = Arc::new(RecordLog::default());
+        let server_log = Arc::new(RecordLog::default());
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.record_observer = Some(client_log.clone());
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.record_observer = Some(server_log.clone());
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
 
-        // Setup a server verifier that will check the EE certificate's revocation status.
-        let crls = vec![kt.end_entity_crl()];
-        let builder = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(crls)
-            .only_check_end_entity_revocation();
+        do_handshake(&mut client, &mut server);
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_verifier(&[version], builder.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        let client_sent = client_log.sent.lock().unwrap().clone();
+        let server_received = server_log
+            .received
+            .lock()
+            .unwrap()
+            .clone();
+        assert_eq!(client_sent, server_received);
+        assert_eq!(
+            *server_log.sent.lock().unwrap(),
+            *client_log.received.lock().unwrap()
+        );
 
-            // We expect the handshake to fail since the server's EE certificate is revoked.
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
-            );
-        }
+        let first = client_sent.first().unwrap();
+        assert_eq!(first.content_type, ContentType::Handshake);
+        assert_eq!((first.epoch, first.sequence), (0, 0));
+
+        let last = client_sent.last().unwrap();
+        assert_eq!(last.content_type, ContentType::ApplicationData);
+        assert_eq!(last.payload_len, 5);
+        assert!(last.record_len > 5 + last.payload_len);
+        let expected_epoch = match version.version {
+            ProtocolVersion::TLSv1_3 => 2,
+            _ => 1,
+        };
+        assert_eq!(last.epoch, expected_epoch);
     }
 }
 
 #[test]
-fn client_check_server_certificate_ee_unknown_revocation() {
-    for kt in ALL_KEY_TYPES {
-        let server_config = Arc::new(make_server_config(*kt));
-
-        // Setup a server verifier builder that will check the EE certificate's revocation status, but not
-        // allow unknown revocation status (the default). We'll provide CRLs that are not relevant
-        // to the EE cert to ensure its status is unknown.
-        let unrelated_crls = vec![kt.intermediate_crl()];
-        let forbid_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls.clone())
-            .only_check_end_entity_revocation();
+fn fault_injector_without_faults_is_transparent() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+        let mut to_server = FaultInjector::new();
+        let mut to_client = FaultInjector::new();
+
+        while client.is_handshaking() || server.is_handshaking() {
+            to_server.transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+            to_client.transfer(&mut server, &mut client);
+            client.process_new_packets().unwrap();
+        }
 
-        // Also set up a verifier builder that will allow unknown revocation status.
-        let allow_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls)
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        to_server.transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), b"hello");
+        assert!(to_server.records() > 0);
+    }
+}
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config =
-                make_client_config_with_verifier(&[version], forbid_unknown_verifier.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+#[test]
+fn damaged_application_data_is_rejected() {
+    for version in rustls::ALL_VERSIONS {
+        for fault in [Fault::Drop, Fault::Duplicate, Fault::Delay, Fault::Corrupt] {
+            let client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+            do_handshake(&mut client, &mut server);
 
-            // We expect if we use the forbid_unknown_verifier that the handshake will fail since the
-            // server's EE certificate's revocation status is unknown given the CRLs we've provided.
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert!(matches!(
-                err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::UnknownRevocationStatus
-                )))
-            ));
+            let mut injector = FaultInjector::new().with(0, fault);
+            client
+                .writer()
+                .write_all(b"first")
+                .unwrap();
+            client
+                .writer()
+                .write_all(b"second")
+                .unwrap();
+            injector.transfer(&mut client, &mut server);
+            assert_eq!(injector.records(), 2);
 
-            // We expect if we use the allow_unknown_verifier that the handshake will not fail.
-            let client_config =
-                make_client_config_with_verifier(&[version], allow_unknown_verifier.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(res.is_ok());
+            assert_eq!(
+                server.process_new_packets(),
+                Err(Error::DecryptError),
+                "{fault:?} with {version:?}"
+            );
         }
     }
 }
 
 #[test]
-fn client_check_server_certificate_intermediate_revoked() {
-    for kt in ALL_KEY_TYPES {
-        let server_config = Arc::new(make_server_config(*kt));
-
-        // Setup a server verifier builder that will check the full chain revocation status against a CRL
-        // that marks the intermediate certificate as revoked. We allow unknown revocation status
-        // so the EE cert's unknown status doesn't cause an error.
-        let crls = vec![kt.intermediate_crl()];
-        let full_chain_verifier_builder =
-            webpki_server_verifier_builder(get_client_root_store(*kt))
-                .with_crls(crls.clone())
-                .allow_unknown_revocation_status();
-
-        // Also set up a verifier builder that will use the same CRL, but only check the EE certificate
-        // revocation status.
-        let ee_verifier_builder = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(crls.clone())
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
+fn dropped_client_hello_stalls_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    let mut injector = FaultInjector::new().with(0, Fault::Drop);
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config =
-                make_client_config_with_verifier(&[version], full_chain_verifier_builder.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    assert_eq!(injector.transfer(&mut client, &mut server), 0);
+    server.process_new_packets().unwrap();
+    assert!(server.is_handshaking());
+    assert!(!server.wants_write());
+}
 
-            // We expect the handshake to fail when using the full chain verifier since the intermediate's
-            // EE certificate is revoked.
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
-            );
+#[derive(Debug, Default)]
+struct HandshakeMutations {
+    seen: Mutex<Vec<HandshakeType>>,
+    flip_byte_of: Option<(HandshakeType, usize)>,
+}
 
-            let client_config =
-                make_client_config_with_verifier(&[version], ee_verifier_builder.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-            // We expect the handshake to succeed when we use the verifier that only checks the EE certificate
-            // revocation status. The revoked intermediate status should not be checked.
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(res.is_ok())
+impl rustls::client::danger::MutatesHandshakeMessages for HandshakeMutations {
+    fn mutate(&self, message: &mut Vec<u8>) {
+        let typ = HandshakeType::from(message[0]);
+        self.seen.lock().unwrap().push(typ);
+        if let Some((flip_typ, offset)) = self.flip_byte_of {
+            if flip_typ == typ {
+                message[offset] ^= 0x01;
+            }
         }
     }
 }
 
 #[test]
-fn client_check_server_certificate_ee_crl_expired() {
-    for kt in ALL_KEY_TYPES {
-        let server_config = Arc::new(make_server_config(*kt));
-
-        // Setup a server verifier that will check the EE certificate's revocation status, with CRL expiration enforced.
-        let crls = vec![kt.end_entity_crl_expired()];
-        let enforce_expiration_builder = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(crls)
-            .only_check_end_entity_revocation()
-            .enforce_revocation_expiration();
-
-        // Also setup a server verifier without CRL expiration enforced.
-        let crls = vec![kt.end_entity_crl_expired()];
-        let ignore_expiration_builder = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(crls)
-            .only_check_end_entity_revocation();
-
-        for version in rustls::ALL_VERSIONS {
-            let client_config =
-                make_client_config_with_verifier(&[version], enforce_expiration_builder.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-
-            // We expect the handshake to fail since the CRL is expired.
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::ExpiredRevocationList
-                )))
-            );
+fn handshake_mutator_sees_all_handshake_messages() {
+    let client_mutator = Arc::new(HandshakeMutations::default());
+    let server_mutator = Arc::new(HandshakeMutations::default());
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    // so the server sends an uncompressed `Certificate`
+    client_config.cert_decompressors = vec![];
+    client_config
+        .dangerous()
+        .set_handshake_mutator(client_mutator.clone());
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config
+        .dangerous()
+        .set_handshake_mutator(server_mutator.clone());
 
-            let client_config =
-                make_client_config_with_verifier(&[version], ignore_expiration_builder.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
 
-            // We expect the handshake to succeed when CRL expiration is ignored.
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(res.is_ok())
-        }
-    }
+    assert_eq!(
+        *client_mutator.seen.lock().unwrap(),
+        vec![HandshakeType::ClientHello, HandshakeType::Finished]
+    );
+    assert_eq!(
+        server_mutator.seen.lock().unwrap()[..5],
+        [
+            HandshakeType::ServerHello,
+            HandshakeType::EncryptedExtensions,
+            HandshakeType::Certificate,
+            HandshakeType::CertificateVerify,
+            HandshakeType::Finished,
+        ]
+    );
 }
 
-/// Simple smoke-test of the webpki verify_server_cert_signed_by_trust_anchor helper API.
-/// This public API is intended to be used by consumers implementing their own verifier and
-/// so isn't used by the other existing verifier tests.
 #[test]
-fn client_check_server_certificate_helper_api() {
-    for kt in ALL_KEY_TYPES {
-        let chain = kt.get_chain();
-        let correct_roots = get_client_root_store(*kt);
-        let incorrect_roots = get_client_root_store(match kt {
-            KeyType::Rsa2048 => KeyType::EcdsaP256,
-            _ => KeyType::Rsa2048,
+fn handshake_mutator_changes_are_detected() {
+    for version in rustls::ALL_VERSIONS {
+        // flip a bit of the ClientHello's random
+        let mutator = Arc::new(HandshakeMutations {
+            flip_byte_of: Some((HandshakeType::ClientHello, 4 + 2)),
+            ..Default::default()
         });
-        // Using the correct trust anchors, we should verify without error.
-        assert!(verify_server_cert_signed_by_trust_anchor(
-            &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
-            &correct_roots,
-            &[chain.get(1).unwrap().clone()],
-            UnixTime::now(),
-            webpki::ALL_VERIFICATION_ALGS,
-        )
-        .is_ok());
-        // Using the wrong trust anchors, we should get the expected error.
-        assert_eq!(
-            verify_server_cert_signed_by_trust_anchor(
-                &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
-                &incorrect_roots,
-                &[chain.get(1).unwrap().clone()],
-                UnixTime::now(),
-                webpki::ALL_VERIFICATION_ALGS,
-            )
-            .unwrap_err(),
-            Error::InvalidCertificate(CertificateError::UnknownIssuer)
-        );
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config
+            .dangerous()
+            .set_handshake_mutator(mutator);
+
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+        assert!(do_handshake_until_error(&mut client, &mut server).is_err());
     }
 }
 
-#[derive(Debug)]
-struct ClientCheckCertResolve {
-    query_count: AtomicUsize,
-    expect_queries: usize,
-    expect_root_hint_subjects: Vec<Vec<u8>>,
-    expect_sigschemes: Vec<SignatureScheme>,
-}
+#[test]
+fn dangerous_send_handshake_message_reaches_peer() {
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]),
+        make_server_config(KeyType::Rsa2048),
+    );
+    do_handshake(&mut client, &mut server);
 
-impl ClientCheckCertResolve {
-    fn new(
-        expect_queries: usize,
-        expect_root_hint_subjects: Vec<Vec<u8>>,
-        expect_sigschemes: Vec<SignatureScheme>,
-    ) -> Self {
-        Self {
-            query_count: AtomicUsize::new(0),
-            expect_queries,
-            expect_root_hint_subjects,
-            expect_sigschemes,
-        }
-    }
+    // a `Finished` with an empty body, after the handshake
+    server.dangerous_send_handshake_message(vec![0x14, 0x00, 0x00, 0x00]);
+    transfer(&mut server, &mut client);
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::InappropriateHandshakeMessage {
+            expect_types: vec![HandshakeType::NewSessionTicket, HandshakeType::KeyUpdate],
+            got_type: HandshakeType::Finished,
+        })
+    );
 }
 
-impl Drop for ClientCheckCertResolve {
-    fn drop(&mut self) {
-        if !std::thread::panicking() {
-            let count = self.query_count.load(Ordering::SeqCst);
-            assert_eq!(count, self.expect_queries);
-        }
-    }
-}
+#[test]
+fn reconnect_policy_disables_early_data_then_rotates_key_share() {
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.enable_early_data = true;
+    let original_groups = client_config
+        .crypto_provider()
+        .kx_groups
+        .iter()
+        .map(|group| group.name())
+        .collect::<Vec<_>>();
+    assert!(original_groups.len() > 1);
 
-impl ResolvesClientCert for ClientCheckCertResolve {
-    fn resolve(
-        &self,
-        root_hint_subjects: &[&[u8]],
-        sigschemes: &[SignatureScheme],
-    ) -> Option<Arc<sign::CertifiedKey>> {
-        self.query_count
-            .fetch_add(1, Ordering::SeqCst);
+    let mut policy = rustls::client::ReconnectPolicy::new(Arc::new(client_config));
+    let failure = Error::AlertReceived(AlertDescription::HandshakeFailure);
 
-        if sigschemes.is_empty() {
-            panic!("no signature schemes shared by server");
-        }
+    let retry = policy.failed(&failure).unwrap();
+    assert!(!retry.enable_early_data);
+    assert!(Arc::ptr_eq(&retry, policy.config()));
 
-        assert_eq!(sigschemes, self.expect_sigschemes);
-        assert_eq!(root_hint_subjects, self.expect_root_hint_subjects);
+    let retry = policy.failed(&failure).unwrap();
+    let retry_groups = retry
+        .crypto_provider()
+        .kx_groups
+        .iter()
+        .map(|group| group.name())
+        .collect::<Vec<_>>();
+    let mut expected_groups = original_groups.clone();
+    expected_groups.rotate_left(1);
+    assert_eq!(retry_groups, expected_groups);
 
-        None
-    }
+    // three attempts in total by default
+    assert!(policy.failed(&failure).is_none());
+}
 
-    fn has_certs(&self) -> bool {
-        true
+#[test]
+fn reconnect_policy_refuses_insecure_retries() {
+    for error in [
+        Error::InvalidCertificate(CertificateError::UnknownIssuer),
+        PeerMisbehaved::UnexpectedDowngradeSentinel.into(),
+        PeerMisbehaved::AttemptedDowngradeToTls12WhenTls13IsSupported.into(),
+        Error::AlertReceived(AlertDescription::ProtocolVersion),
+        Error::DecryptError,
+    ] {
+        let mut client_config = make_client_config(KeyType::Rsa2048);
+        client_config.enable_early_data = true;
+        let mut policy = rustls::client::ReconnectPolicy::new(Arc::new(client_config));
+        assert!(policy.failed(&error).is_none(), "{error:?}");
     }
 }
 
-fn test_client_cert_resolve(
-    key_type: KeyType,
-    server_config: Arc<ServerConfig>,
-    expected_root_hint_subjects: Vec<Vec<u8>>,
-) {
-    for version in rustls::ALL_VERSIONS {
-        println!("{:?} {:?}:", version.version, key_type);
+#[test]
+fn test_tls13_valid_early_plaintext_alert() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
 
-        let mut client_config = make_client_config_with_versions(key_type, &[version]);
-        client_config.client_auth_cert_resolver = Arc::new(ClientCheckCertResolve::new(
-            1,
-            expected_root_hint_subjects.clone(),
-            default_signature_schemes(version.version),
-        ));
+    // Perform the start of a TLS 1.3 handshake, sending a client hello to the server.
+    // The client will not have written a CCS or any encrypted messages to the server yet.
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
 
-        let (mut client, mut server) =
-            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    // Inject a plaintext alert from the client. The server should accept this since:
+    //  * It hasn't decrypted any messages from the peer yet.
+    //  * The message content type is Alert.
+    //  * The payload size is indicative of a plaintext alert message.
+    //  * The negotiated protocol version is TLS 1.3.
+    server
+        .read_tls(&mut io::Cursor::new(&build_alert(
+            AlertLevel::Fatal,
+            AlertDescription::UnknownCA,
+            &[],
+        )))
+        .unwrap();
 
-        assert_eq!(
-            do_handshake_until_error(&mut client, &mut server),
-            Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
-        );
-    }
+    // The server should process the plaintext alert without error.
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::UnknownCA)),
+    );
 }
 
-fn default_signature_schemes(version: ProtocolVersion) -> Vec<SignatureScheme> {
-    let mut v = vec![];
+#[test]
+fn test_tls13_too_short_early_plaintext_alert() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
 
-    v.extend_from_slice(&[
-        SignatureScheme::ECDSA_NISTP384_SHA384,
-        SignatureScheme::ECDSA_NISTP256_SHA256,
-        SignatureScheme::ED25519,
-        SignatureScheme::RSA_PSS_SHA512,
-        SignatureScheme::RSA_PSS_SHA384,
-        SignatureScheme::RSA_PSS_SHA256,
-    ]);
-
-    if provider_is_aws_lc_rs() {
-        v.insert(2, SignatureScheme::ECDSA_NISTP521_SHA512);
-    }
+    // Perform the start of a TLS 1.3 handshake, sending a client hello to the server.
+    // The client will not have written a CCS or any encrypted messages to the server yet.
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
 
-    if version == ProtocolVersion::TLSv1_2 {
-        v.extend_from_slice(&[
-            SignatureScheme::RSA_PKCS1_SHA512,
-            SignatureScheme::RSA_PKCS1_SHA384,
-            SignatureScheme::RSA_PKCS1_SHA256,
-        ]);
-    }
+    // Inject a plaintext alert from the client. The server should attempt to decrypt this message
+    // because the payload length is too large to be considered an early plaintext alert.
+    server
+        .read_tls(&mut io::Cursor::new(&build_alert(
+            AlertLevel::Fatal,
+            AlertDescription::UnknownCA,
+            &[0xff],
+        )))
+        .unwrap();
 
-    v
+    // The server should produce a decrypt error trying to decrypt the plaintext alert.
+    assert_eq!(server.process_new_packets(), Err(Error::DecryptError),);
 }
 
 #[test]
-fn client_cert_resolve_default() {
-    // Test that in the default configuration that a client cert resolver gets the expected
-    // CA subject hints, and supported signature algorithms.
-    for key_type in ALL_KEY_TYPES {
-        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*key_type));
+fn test_tls13_late_plaintext_alert() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
 
-        // In a default configuration we expect that the verifier's trust anchors are used
-        // for the hint subjects.
-        let expected_root_hint_subjects = vec![key_type
-            .ca_distinguished_name()
-            .to_vec()];
+    // Complete a bi-directional TLS1.3 handshake. After this point no plaintext messages
+    // should occur.
+    do_handshake(&mut client, &mut server);
 
-        test_client_cert_resolve(*key_type, server_config, expected_root_hint_subjects);
-    }
-}
+    // Inject a plaintext alert from the client. The server should attempt to decrypt this message.
+    server
+        .read_tls(&mut io::Cursor::new(&build_alert(
+            AlertLevel::Fatal,
+            AlertDescription::UnknownCA,
+            &[],
+        )))
+        .unwrap();
 
-#[test]
-fn client_cert_resolve_server_no_hints() {
-    // Test that a server can provide no hints and the client cert resolver gets the expected
-    // arguments.
-    for key_type in ALL_KEY_TYPES {
-        // Build a verifier with no hint subjects.
-        let verifier = webpki_client_verifier_builder(get_client_root_store(*key_type))
-            .clear_root_hint_subjects();
-        let server_config = make_server_config_with_client_verifier(*key_type, verifier);
-        let expected_root_hint_subjects = Vec::default(); // no hints expected.
-        test_client_cert_resolve(*key_type, server_config.into(), expected_root_hint_subjects);
-    }
+    // The server should produce a decrypt error, trying to decrypt a plaintext alert.
+    assert_eq!(server.process_new_packets(), Err(Error::DecryptError));
 }
 
-#[test]
-fn client_cert_resolve_server_added_hint() {
-    // Test that a server can add an extra subject above/beyond those found in its trust store
-    // and the client cert resolver gets the expected arguments.
-    let extra_name = b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponyland IDK CA".to_vec();
-    for key_type in ALL_KEY_TYPES {
-        let expected_hint_subjects = vec![
-            key_type
-                .ca_distinguished_name()
-                .to_vec(),
-            extra_name.clone(),
-        ];
-        // Create a verifier that adds the extra_name as a hint subject in addition to the ones
-        // from the root cert store.
-        let verifier = webpki_client_verifier_builder(get_client_root_store(*key_type))
-            .add_root_hint_subjects([DistinguishedName::from(extra_name.clone())].into_iter());
-        let server_config = make_server_config_with_client_verifier(*key_type, verifier);
-        test_client_cert_resolve(*key_type, server_config.into(), expected_hint_subjects);
-    }
+fn build_alert(level: AlertLevel, desc: AlertDescription, suffix: &[u8]) -> Vec<u8> {
+    let mut v = vec![ContentType::Alert.into()];
+    ProtocolVersion::TLSv1_2.encode(&mut v);
+    ((2 + suffix.len()) as u16).encode(&mut v);
+    level.encode(&mut v);
+    desc.encode(&mut v);
+    v.extend_from_slice(suffix);
+    v
 }
 
-#[test]
-fn client_auth_works() {
-    for kt in ALL_KEY_TYPES {
-        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*kt));
-
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
-            do_handshake(&mut client, &mut server);
-        }
-    }
+#[derive(Default, Debug)]
+struct ServerCheckCertResolve {
+    expected_sni: Option<String>,
+    expected_sigalgs: Option<Vec<SignatureScheme>>,
+    expected_alpn: Option<Vec<Vec<u8>>>,
+    expected_cipher_suites: Option<Vec<CipherSuite>>,
 }
 
-#[test]
-fn client_mandatory_auth_client_revocation_works() {
-    for kt in ALL_KEY_TYPES {
-        // Create a server configuration that includes a CRL that specifies the client certificate
-        // is revoked.
-        let relevant_crls = vec![kt.client_crl()];
-        // Only check the EE certificate status. See client_mandatory_auth_intermediate_revocation_works
-        // for testing revocation status of the whole chain.
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(relevant_crls)
-            .only_check_end_entity_revocation();
-        let revoked_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            ee_verifier_builder,
-        ));
+impl ResolvesServerCert for ServerCheckCertResolve {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        if client_hello
+            .signature_schemes()
+            .is_empty()
+        {
+            panic!("no signature schemes shared by client");
+        }
 
-        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
-        // and uses the default behaviour of treating unknown revocation status as an error.
-        let unrelated_crls = vec![kt.intermediate_crl()];
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls.clone())
-            .only_check_end_entity_revocation();
-        let missing_client_crl_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            ee_verifier_builder,
-        ));
+        if client_hello.cipher_suites().is_empty() {
+            panic!("no cipher suites shared by client");
+        }
 
-        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
-        // but change the builder to allow unknown revocation status.
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls.clone())
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
-        let allow_missing_client_crl_server_config = Arc::new(
-            make_server_config_with_client_verifier(*kt, ee_verifier_builder),
-        );
+        if let Some(expected_sni) = &self.expected_sni {
+            let sni: &str = client_hello
+                .server_name()
+                .expect("sni unexpectedly absent");
+            assert_eq!(expected_sni, sni);
+        }
 
-        for version in rustls::ALL_VERSIONS {
-            // Connecting to the server with a CRL that indicates the client certificate is revoked
-            // should fail with the expected error.
-            let client_config =
-                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &revoked_server_config);
-            let err = do_handshake_until_error(&mut client, &mut server);
+        if let Some(expected_sigalgs) = &self.expected_sigalgs {
             assert_eq!(
-                err,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
+                expected_sigalgs,
+                client_hello.signature_schemes(),
+                "unexpected signature schemes"
             );
-            // Connecting to the server missing CRL information for the client certificate should
-            // fail with the expected unknown revocation status error.
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &missing_client_crl_server_config);
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(matches!(
-                res,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::UnknownRevocationStatus
-                )))
-            ));
-            // Connecting to the server missing CRL information for the client should not error
-            // if the server's verifier allows unknown revocation status.
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &allow_missing_client_crl_server_config);
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(res.is_ok());
         }
-    }
-}
 
-#[test]
-fn client_mandatory_auth_intermediate_revocation_works() {
-    for kt in ALL_KEY_TYPES {
-        // Create a server configuration that includes a CRL that specifies the intermediate certificate
-        // is revoked. We check the full chain for revocation status (default), and allow unknown
-        // revocation status so the EE's unknown revocation status isn't an error.
-        let crls = vec![kt.intermediate_crl()];
-        let full_chain_verifier_builder =
-            webpki_client_verifier_builder(get_client_root_store(*kt))
-                .with_crls(crls.clone())
-                .allow_unknown_revocation_status();
-        let full_chain_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            full_chain_verifier_builder,
-        ));
+        if let Some(expected_alpn) = &self.expected_alpn {
+            let alpn = client_hello
+                .alpn()
+                .expect("alpn unexpectedly absent")
+                .collect::<Vec<_>>();
+            assert_eq!(alpn.len(), expected_alpn.len());
 
-        // Also create a server configuration that uses the same CRL, but that only checks the EE
-        // cert revocation status.
-        let ee_only_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(crls)
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
-        let ee_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            ee_only_verifier_builder,
-        ));
+            for (got, wanted) in alpn.iter().zip(expected_alpn.iter()) {
+                assert_eq!(got, &wanted.as_slice());
+            }
+        }
 
-        for version in rustls::ALL_VERSIONS {
-            // When checking the full chain, we expect an error - the intermediate is revoked.
-            let client_config =
-                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &full_chain_server_config);
-            let err = do_handshake_until_error(&mut client, &mut server);
+        if let Some(expected_cipher_suites) = &self.expected_cipher_suites {
             assert_eq!(
-                err,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
+                expected_cipher_suites,
+                client_hello.cipher_suites(),
+                "unexpected cipher suites"
             );
-            // However, when checking just the EE cert we expect no error - the intermediate's
-            // revocation status should not be checked.
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &ee_server_config);
-            assert!(do_handshake_until_error(&mut client, &mut server).is_ok());
         }
+
+        None
     }
 }
 
 #[test]
-fn client_optional_auth_client_revocation_works() {
+fn server_cert_resolve_with_sni() {
     for kt in ALL_KEY_TYPES {
-        // Create a server configuration that includes a CRL that specifies the client certificate
-        // is revoked.
-        let crls = vec![kt.client_crl()];
-        let server_config = Arc::new(make_server_config_with_optional_client_auth(*kt, crls));
+        let client_config = make_client_config(*kt);
+        let mut server_config = make_server_config(*kt);
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
-            // Because the client certificate is revoked, the handshake should fail.
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
-            );
-        }
-    }
-}
+        server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
+            expected_sni: Some("the-value-from-sni".into()),
+            ..Default::default()
+        });
 
-#[test]
-fn client_error_is_sticky() {
-    let (mut client, _) = make_pair(KeyType::Rsa2048);
-    client
-        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
-        .unwrap();
-    let mut err = client.process_new_packets();
-    assert!(err.is_err());
-    err = client.process_new_packets();
-    assert!(err.is_err());
-}
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("the-value-from-sni"))
+                .unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
 
-#[test]
-fn server_error_is_sticky() {
-    let (_, mut server) = make_pair(KeyType::Rsa2048);
-    server
-        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
-        .unwrap();
-    let mut err = server.process_new_packets();
-    assert!(err.is_err());
-    err = server.process_new_packets();
-    assert!(err.is_err());
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert!(err.is_err());
+    }
 }
 
-#[test]
-fn server_flush_does_nothing() {
-    let (_, mut server) = make_pair(KeyType::Rsa2048);
-    assert!(matches!(server.writer().flush(), Ok(())));
-}
+/// A stand-in for an ML-DSA key, which makes ECDSA signatures.
+#[derive(Debug)]
+struct FakeMlDsaKey(Arc<dyn sign::SigningKey>);
+
+impl sign::SigningKey for FakeMlDsaKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        match offered.contains(&SignatureScheme::ML_DSA_65) {
+            true => Some(Box::new(FakeMlDsaSigner(
+                self.0
+                    .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])?,
+            ))),
+            false => None,
+        }
+    }
 
-#[test]
-fn client_flush_does_nothing() {
-    let (mut client, _) = make_pair(KeyType::Rsa2048);
-    assert!(matches!(client.writer().flush(), Ok(())));
+    fn algorithm(&self) -> rustls::SignatureAlgorithm {
+        rustls::SignatureAlgorithm::Unknown(0)
+    }
 }
 
-#[allow(clippy::no_effect)]
-#[test]
-fn server_is_send_and_sync() {
-    let (_, server) = make_pair(KeyType::Rsa2048);
-    &server as &dyn Send;
-    &server as &dyn Sync;
-}
+#[derive(Debug)]
+struct FakeMlDsaSigner(Box<dyn sign::Signer>);
 
-#[allow(clippy::no_effect)]
-#[test]
-fn client_is_send_and_sync() {
-    let (client, _) = make_pair(KeyType::Rsa2048);
-    &client as &dyn Send;
-    &client as &dyn Sync;
+impl sign::Signer for FakeMlDsaSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        self.0.sign(message)
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        SignatureScheme::ML_DSA_65
+    }
 }
 
 #[test]
-fn server_respects_buffer_limit_pre_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
-
-    server.set_buffer_limit(Some(32));
+fn server_cert_resolve_using_signature_schemes() {
+    let kt = KeyType::EcdsaP256;
+    let key = || {
+        provider::default_provider()
+            .key_provider
+            .load_private_key(kt.get_key())
+            .unwrap()
+    };
+    let mut resolver = ResolvesServerCertUsingSignatureSchemes::new();
+    resolver.add(sign::CertifiedKey::new(
+        kt.get_chain(),
+        Arc::new(FakeMlDsaKey(key())),
+    ));
+    resolver.add(sign::CertifiedKey::new(kt.get_chain(), key()));
+    let resolver = Arc::new(resolver);
 
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        12
-    );
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = resolver.clone();
+    let server_config = Arc::new(server_config);
 
-    do_handshake(&mut client, &mut server);
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+    for (offered, works) in [
+        (
+            vec![
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::ML_DSA_65,
+            ],
+            true,
+        ),
+        (vec![SignatureScheme::ECDSA_NISTP256_SHA256], true),
+        (vec![SignatureScheme::ED25519], false),
+    ] {
+        let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(MockServerVerifier::offers_signature_schemes(
+                offered,
+            )));
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server).is_ok(),
+            works
+        );
+    }
 
-    check_read(&mut client.reader(), b"01234567890123456789012345678901");
+    assert_eq!(resolver.selections(), vec![1, 1]);
+    assert_eq!(resolver.unmatched(), 1);
 }
 
 #[test]
-fn server_respects_buffer_limit_pre_handshake_with_vectored_write() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
-
-    server.set_buffer_limit(Some(32));
+fn server_cert_resolve_with_alpn() {
+    for kt in ALL_KEY_TYPES {
+        let mut client_config = make_client_config(*kt);
+        client_config.alpn_protocols = vec!["foo".into(), "bar".into()];
 
-    assert_eq!(
-        server
-            .writer()
-            .write_vectored(&[
-                IoSlice::new(b"01234567890123456789"),
-                IoSlice::new(b"01234567890123456789")
-            ])
-            .unwrap(),
-        32
-    );
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
+            expected_alpn: Some(vec![b"foo".to_vec(), b"bar".to_vec()]),
+            ..Default::default()
+        });
 
-    do_handshake(&mut client, &mut server);
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("sni-value")).unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
 
-    check_read(&mut client.reader(), b"01234567890123456789012345678901");
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert!(err.is_err());
+    }
 }
 
 #[test]
-fn server_respects_buffer_limit_post_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
-
-    // this test will vary in behaviour depending on the default suites
-    do_handshake(&mut client, &mut server);
-    server.set_buffer_limit(Some(48));
+fn client_trims_terminating_dot() {
+    for kt in ALL_KEY_TYPES {
+        let client_config = make_client_config(*kt);
+        let mut server_config = make_server_config(*kt);
 
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        6
-    );
+        server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
+            expected_sni: Some("some-host.com".into()),
+            ..Default::default()
+        });
 
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("some-host.com.")).unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
 
-    check_read(&mut client.reader(), b"01234567890123456789012345");
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert!(err.is_err());
+    }
 }
 
 #[test]
-fn client_respects_buffer_limit_pre_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
-
-    client.set_buffer_limit(Some(32));
+fn server_ignores_sni_with_ip_address() {
+    fn insert_ip_address_server_name(msg: &mut Message) -> Altered {
+        alter_sni_extension(
+            msg,
+            |snr| {
+                snr.clear();
+                snr.push(ServerNameExtensionItem::read_bytes(b"\x00\x00\x071.1.1.1").unwrap());
+            },
+            |parsed, _encoded| Payload::new(parsed.get_encoding()),
+        )
+    }
 
-    assert_eq!(
-        client
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
-        client
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        12
+    check_sni_error(
+        insert_ip_address_server_name,
+        Error::General("no server certificate chain resolved".to_string()),
     );
-
-    do_handshake(&mut client, &mut server);
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-
-    check_read(&mut server.reader(), b"01234567890123456789012345678901");
 }
 
 #[test]
-fn client_respects_buffer_limit_pre_handshake_with_vectored_write() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+fn server_rejects_sni_with_illegal_dns_name() {
+    fn insert_illegal_server_name(msg: &mut Message) -> Altered {
+        alter_sni_extension(
+            msg,
+            |_| (),
+            |_, encoded| {
+                // replace "localhost" with invalid DNS name
+                let mut altered = encoded.clone().into_vec();
+                let needle = b"localhost";
+                let index = altered
+                    .windows(needle.len())
+                    .position(|window| window == needle)
+                    .unwrap();
+                altered[index..index + needle.len()].copy_from_slice(b"ab@cd.com");
+                Payload::new(altered)
+            },
+        )
+    }
 
-    client.set_buffer_limit(Some(32));
-
-    assert_eq!(
-        client
-            .writer()
-            .write_vectored(&[
-                IoSlice::new(b"01234567890123456789"),
-                IoSlice::new(b"01234567890123456789")
-            ])
-            .unwrap(),
-        32
+    check_sni_error(
+        insert_illegal_server_name,
+        Error::InvalidMessage(InvalidMessage::InvalidServerName),
     );
-
-    do_handshake(&mut client, &mut server);
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-
-    check_read(&mut server.reader(), b"01234567890123456789012345678901");
 }
 
-#[test]
-fn client_respects_buffer_limit_post_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
-
-    do_handshake(&mut client, &mut server);
-    client.set_buffer_limit(Some(48));
+fn alter_sni_extension(
+    msg: &mut Message,
+    alter_inner: impl Fn(&mut Vec<ServerNameExtensionItem>),
+    alter_encoding: impl Fn(&mut HandshakeMessagePayload, &mut Payload) -> Payload<'static>,
+) -> Altered {
+    if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+        if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+            for mut ext in ch.extensions.iter_mut() {
+                if let ClientExtension::ServerName(snr) = &mut ext {
+                    alter_inner(snr);
+                }
+            }
+            *encoded = alter_encoding(parsed, encoded);
+        }
+    }
 
-    assert_eq!(
-        client
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
-        client
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        6
-    );
+    Altered::InPlace
+}
 
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
+fn check_sni_error(alteration: impl Fn(&mut Message) -> Altered, expected_error: Error) {
+    for kt in ALL_KEY_TYPES {
+        let client_config = make_client_config(*kt);
+        let mut server_config = make_server_config(*kt);
 
-    check_read(&mut server.reader(), b"01234567890123456789012345");
-}
+        server_config.cert_resolver = Arc::new(ServerCheckNoSni {});
 
-struct OtherSession<'a, C, S>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
-    S: SideData,
-{
-    sess: &'a mut C,
-    pub reads: usize,
-    pub writevs: Vec<Vec<usize>>,
-    fail_ok: bool,
-    pub short_writes: bool,
-    pub last_error: Option<rustls::Error>,
-    pub buffered: bool,
-    buffer: Vec<Vec<u8>>,
-}
+        let client =
+            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+        let server = ServerConnection::new(Arc::new(server_config)).unwrap();
+        let (mut client, mut server) = (client.into(), server.into());
 
-impl<'a, C, S> OtherSession<'a, C, S>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
-    S: SideData,
-{
-    fn new(sess: &'a mut C) -> OtherSession<'a, C, S> {
-        OtherSession {
-            sess,
-            reads: 0,
-            writevs: vec![],
-            fail_ok: false,
-            short_writes: false,
-            last_error: None,
-            buffered: false,
-            buffer: vec![],
-        }
-    }
+        transfer_altered(&mut client, &alteration, &mut server);
+        assert_eq!(server.process_new_packets(), Err(expected_error.clone()),);
 
-    fn new_buffered(sess: &'a mut C) -> OtherSession<'a, C, S> {
-        let mut os = OtherSession::new(sess);
-        os.buffered = true;
-        os
+        let server_inner = match server {
+            rustls::Connection::Server(server) => server,
+            _ => unreachable!(),
+        };
+        assert_eq!(None, server_inner.server_name());
     }
+}
 
-    fn new_fails(sess: &'a mut C) -> OtherSession<'a, C, S> {
-        let mut os = OtherSession::new(sess);
-        os.fail_ok = true;
-        os
-    }
+#[cfg(feature = "tls12")]
+fn check_sigalgs_reduced_by_ciphersuite(
+    kt: KeyType,
+    suite: CipherSuite,
+    expected_sigalgs: Vec<SignatureScheme>,
+) {
+    let client_config = finish_client_config(
+        kt,
+        ClientConfig::builder_with_provider(
+            CryptoProvider {
+                cipher_suites: vec![find_suite(suite)],
+                ..provider::default_provider()
+            }
+            .into(),
+        )
+        .with_safe_default_protocol_versions()
+        .unwrap(),
+    );
 
-    fn flush_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
-        let mut total = 0;
-        let mut lengths = vec![];
-        for bytes in b {
-            let write_len = if self.short_writes {
-                if bytes.len() > 5 {
-                    bytes.len() / 2
-                } else {
-                    bytes.len()
-                }
-            } else {
-                bytes.len()
-            };
+    let mut server_config = make_server_config(kt);
 
-            let l = self
-                .sess
-                .read_tls(&mut io::Cursor::new(&bytes[..write_len]))?;
-            lengths.push(l);
-            total += l;
-            if bytes.len() != l {
-                break;
-            }
-        }
+    server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
+        expected_sigalgs: Some(expected_sigalgs),
+        expected_cipher_suites: Some(vec![suite, CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV]),
+        ..Default::default()
+    });
 
-        let rc = self.sess.process_new_packets();
-        if !self.fail_ok {
-            rc.unwrap();
-        } else if rc.is_err() {
-            self.last_error = rc.err();
-        }
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
 
-        self.writevs.push(lengths);
-        Ok(total)
-    }
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert!(err.is_err());
 }
 
-impl<'a, C, S> io::Read for OtherSession<'a, C, S>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
-    S: SideData,
-{
-    fn read(&mut self, mut b: &mut [u8]) -> io::Result<usize> {
-        self.reads += 1;
-        self.sess.write_tls(b.by_ref())
-    }
+#[cfg(feature = "tls12")]
+#[test]
+fn server_cert_resolve_reduces_sigalgs_for_rsa_ciphersuite() {
+    check_sigalgs_reduced_by_ciphersuite(
+        KeyType::Rsa2048,
+        CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+        vec![
+            SignatureScheme::RSA_PSS_SHA512,
+            SignatureScheme::RSA_PSS_SHA384,
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA512,
+            SignatureScheme::RSA_PKCS1_SHA384,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ],
+    );
 }
 
-impl<'a, C, S> io::Write for OtherSession<'a, C, S>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
-    S: SideData,
-{
-    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
-        unreachable!()
-    }
+#[cfg(feature = "tls12")]
+#[test]
+fn server_cert_resolve_reduces_sigalgs_for_ecdsa_ciphersuite() {
+    check_sigalgs_reduced_by_ciphersuite(
+        KeyType::EcdsaP256,
+        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+        if provider_is_aws_lc_rs() {
+            vec![
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::ECDSA_NISTP521_SHA512,
+                SignatureScheme::ED25519,
+            ]
+        } else {
+            vec![
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::ED25519,
+            ]
+        },
+    );
+}
 
-    fn flush(&mut self) -> io::Result<()> {
-        if !self.buffer.is_empty() {
-            let buffer = mem::take(&mut self.buffer);
-            let slices = buffer
-                .iter()
-                .map(|b| io::IoSlice::new(b))
-                .collect::<Vec<_>>();
-            self.flush_vectored(&slices)?;
-        }
-        Ok(())
-    }
+#[derive(Debug, Default)]
+struct CountingExpiryObserver {
+    count: AtomicUsize,
+}
 
-    fn write_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
-        if self.buffered {
-            self.buffer
-                .extend(b.iter().map(|s| s.to_vec()));
-            return Ok(b.iter().map(|s| s.len()).sum());
-        }
-        self.flush_vectored(b)
+impl rustls::server::ObservesCertExpiry for CountingExpiryObserver {
+    fn cert_expiring(
+        &self,
+        server_name: Option<&str>,
+        _certified_key: &sign::CertifiedKey,
+        not_after: UnixTime,
+    ) {
+        assert_eq!(server_name, Some("localhost"));
+        // test-ca end-entity certificates expire at 4096-01-01T00:00:00Z
+        assert_eq!(not_after.as_secs(), 67_090_118_400);
+        self.count
+            .fetch_add(1, Ordering::SeqCst);
     }
 }
 
 #[test]
-fn server_read_returns_wouldblock_when_no_data() {
-    let (_, mut server) = make_pair(KeyType::Rsa2048);
-    assert!(matches!(server.reader().read(&mut [0u8; 1]),
-                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
-}
+fn server_handshake_timeout() {
+    let clock = ManualTime::new(UnixTime::now().as_secs());
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.time_provider = clock.clone();
+    server_config.handshake_timeout = Some(Duration::from_secs(10));
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa2048), server_config);
 
-#[test]
-fn client_read_returns_wouldblock_when_no_data() {
-    let (mut client, _) = make_pair(KeyType::Rsa2048);
-    assert!(matches!(client.reader().read(&mut [0u8; 1]),
-                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+    transfer(&mut client, &mut server);
+    clock.advance(11);
+    assert_eq!(server.process_new_packets(), Err(Error::HandshakeTimedOut));
+    // the error is sticky
+    assert_eq!(server.process_new_packets(), Err(Error::HandshakeTimedOut));
 }
 
 #[test]
-fn new_server_returns_initial_io_state() {
-    let (_, mut server) = make_pair(KeyType::Rsa2048);
-    let io_state = server.process_new_packets().unwrap();
-    println!("IoState is Debug {:?}", io_state);
-    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
-    assert!(!io_state.peer_has_closed());
-    assert_eq!(io_state.tls_bytes_to_write(), 0);
-}
+fn server_handshake_timeout_via_acceptor() {
+    use rustls::server::Acceptor;
 
-#[test]
-fn new_client_returns_initial_io_state() {
-    let (mut client, _) = make_pair(KeyType::Rsa2048);
-    let io_state = client.process_new_packets().unwrap();
-    println!("IoState is Debug {:?}", io_state);
-    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
-    assert!(!io_state.peer_has_closed());
-    assert!(io_state.tls_bytes_to_write() > 200);
-}
+    let clock = ManualTime::new(UnixTime::now().as_secs());
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.time_provider = clock.clone();
+    server_config.handshake_timeout = Some(Duration::from_secs(10));
 
-#[test]
-fn client_complete_io_for_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    let client_config = Arc::new(make_client_config(KeyType::Rsa2048));
+    let mut client = ClientConnection::new(client_config, server_name("localhost")).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
 
-    assert!(client.is_handshaking());
-    let (rdlen, wrlen) = client
-        .complete_io(&mut OtherSession::new(&mut server))
+    let mut acceptor = Acceptor::default();
+    acceptor
+        .read_tls(&mut buf.as_slice())
         .unwrap();
-    assert!(rdlen > 0 && wrlen > 0);
-    assert!(!client.is_handshaking());
-    assert!(!client.wants_write());
+    let mut server = acceptor
+        .accept()
+        .unwrap()
+        .unwrap()
+        .into_connection(Arc::new(server_config))
+        .unwrap();
+
+    clock.advance(11);
+    assert_eq!(server.process_new_packets(), Err(Error::HandshakeTimedOut));
 }
 
 #[test]
-fn buffered_client_complete_io_for_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+fn handshake_timeout_is_not_rounded_to_seconds() {
+    // start just before a second boundary
+    let clock = ManualTime::new(UnixTime::now().as_secs());
+    clock.advance_millis(900);
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.time_provider = clock.clone();
+    server_config.handshake_timeout = Some(Duration::from_millis(500));
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa2048), server_config);
 
-    assert!(client.is_handshaking());
-    let (rdlen, wrlen) = client
-        .complete_io(&mut OtherSession::new_buffered(&mut server))
-        .unwrap();
-    assert!(rdlen > 0 && wrlen > 0);
-    assert!(!client.is_handshaking());
-    assert!(!client.wants_write());
+    transfer(&mut client, &mut server);
+    clock.advance_millis(400);
+    server.process_new_packets().unwrap();
+    clock.advance_millis(200);
+    assert_eq!(server.process_new_packets(), Err(Error::HandshakeTimedOut));
 }
 
 #[test]
-fn client_complete_io_for_handshake_eof() {
-    let (mut client, _) = make_pair(KeyType::Rsa2048);
-    let mut input = io::Cursor::new(Vec::new());
+fn client_handshake_timeout() {
+    let clock = ManualTime::new(UnixTime::now().as_secs());
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.time_provider = clock.clone();
+    client_config.handshake_timeout = Some(Duration::from_secs(10));
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
 
-    assert!(client.is_handshaking());
-    let err = client
-        .complete_io(&mut input)
-        .unwrap_err();
-    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    clock.advance(11);
+    assert_eq!(client.process_new_packets(), Err(Error::HandshakeTimedOut));
 }
 
 #[test]
-fn client_complete_io_for_write() {
-    for kt in ALL_KEY_TYPES {
-        let (mut client, mut server) = make_pair(*kt);
+fn handshake_timeout_does_not_apply_after_handshake() {
+    let clock = ManualTime::new(UnixTime::now().as_secs());
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.time_provider = clock.clone();
+    server_config.handshake_timeout = Some(Duration::from_secs(10));
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa2048), server_config);
 
-        do_handshake(&mut client, &mut server);
+    clock.advance(10);
+    do_handshake(&mut client, &mut server);
+    clock.advance(60);
 
-        client
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        client
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
-            assert!(rdlen == 0 && wrlen > 0);
-            println!("{:?}", pipe.writevs);
-            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-        }
-        check_read(
-            &mut server.reader(),
-            b"0123456789012345678901234567890123456789",
-        );
-    }
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"hello");
 }
 
-#[test]
-fn buffered_client_complete_io_for_write() {
-    for kt in ALL_KEY_TYPES {
-        let (mut client, mut server) = make_pair(*kt);
-
-        do_handshake(&mut client, &mut server);
+#[derive(Debug)]
+struct FixedTime(UnixTime);
 
-        client
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        client
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new_buffered(&mut server);
-            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
-            assert!(rdlen == 0 && wrlen > 0);
-            println!("{:?}", pipe.writevs);
-            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-        }
-        check_read(
-            &mut server.reader(),
-            b"0123456789012345678901234567890123456789",
-        );
+impl rustls::time_provider::TimeProvider for FixedTime {
+    fn current_time(&self) -> Option<UnixTime> {
+        Some(self.0)
     }
 }
 
-#[test]
-fn client_complete_io_for_read() {
-    for kt in ALL_KEY_TYPES {
-        let (mut client, mut server) = make_pair(*kt);
+fn handshakes_with_expiry_warning(now: u64, window_days: u64) -> usize {
+    let observer = Arc::new(CountingExpiryObserver::default());
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.time_provider = Arc::new(FixedTime(UnixTime::since_unix_epoch(
+        Duration::from_secs(now),
+    )));
+    server_config.cert_expiry_warning = Some(Arc::new(rustls::server::CertExpiryWarning::new(
+        observer.clone(),
+        Duration::from_secs(window_days * 86_400),
+    )));
+    let server_config = Arc::new(server_config);
+    let client_config = Arc::new(make_client_config(KeyType::Rsa2048));
 
+    for _ in 0..2 {
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
         do_handshake(&mut client, &mut server);
-
-        server
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
-            assert!(rdlen > 0 && wrlen == 0);
-            assert_eq!(pipe.reads, 1);
-        }
-        check_read(&mut client.reader(), b"01234567890123456789");
     }
+
+    observer.count.load(Ordering::SeqCst)
 }
 
 #[test]
-fn server_complete_io_for_handshake() {
-    for kt in ALL_KEY_TYPES {
-        let (mut client, mut server) = make_pair(*kt);
-
-        assert!(server.is_handshaking());
-        let (rdlen, wrlen) = server
-            .complete_io(&mut OtherSession::new(&mut client))
-            .unwrap();
-        assert!(rdlen > 0 && wrlen > 0);
-        assert!(!server.is_handshaking());
-        assert!(!server.wants_write());
-    }
+fn server_cert_expiry_warning_within_window() {
+    let one_day_before_expiry = 67_090_118_400 - 86_400;
+    // the second handshake is suppressed by the rate limit
+    assert_eq!(handshakes_with_expiry_warning(one_day_before_expiry, 30), 1);
 }
 
 #[test]
-fn server_complete_io_for_handshake_eof() {
-    let (_, mut server) = make_pair(KeyType::Rsa2048);
-    let mut input = io::Cursor::new(Vec::new());
-
-    assert!(server.is_handshaking());
-    let err = server
-        .complete_io(&mut input)
-        .unwrap_err();
-    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+fn server_cert_expiry_warning_outside_window() {
+    let sixty_days_before_expiry = 67_090_118_400 - 60 * 86_400;
+    assert_eq!(
+        handshakes_with_expiry_warning(sixty_days_before_expiry, 30),
+        0
+    );
 }
 
 #[test]
-fn server_complete_io_for_write() {
-    for kt in ALL_KEY_TYPES {
-        let (mut client, mut server) = make_pair(*kt);
+fn server_cert_expiry_warning_is_rate_limited_per_certificate() {
+    let one_day_before_expiry = 67_090_118_400 - 86_400;
+    let observer = Arc::new(CountingExpiryObserver::default());
+    let warning = Arc::new(rustls::server::CertExpiryWarning::new(
+        observer.clone(),
+        Duration::from_secs(30 * 86_400),
+    ));
 
-        do_handshake(&mut client, &mut server);
+    for kt in [KeyType::Rsa2048, KeyType::EcdsaP256] {
+        let mut server_config = make_server_config(kt);
+        server_config.time_provider = Arc::new(FixedTime(UnixTime::since_unix_epoch(
+            Duration::from_secs(one_day_before_expiry),
+        )));
+        server_config.cert_expiry_warning = Some(warning.clone());
+        let server_config = Arc::new(server_config);
+        let client_config = Arc::new(make_client_config(kt));
 
-        server
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        server
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
-            assert!(rdlen == 0 && wrlen > 0);
-            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+        for _ in 0..2 {
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &server_config);
+            do_handshake(&mut client, &mut server);
         }
-        check_read(
-            &mut client.reader(),
-            b"0123456789012345678901234567890123456789",
-        );
+    }
+
+    // one warning for each certificate
+    assert_eq!(observer.count.load(Ordering::SeqCst), 2);
+}
+
+#[derive(Debug)]
+struct ServerCheckNoSni {}
+
+impl ResolvesServerCert for ServerCheckNoSni {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        assert!(client_hello.server_name().is_none());
+
+        None
     }
 }
 
 #[test]
-fn server_complete_io_for_read() {
+fn client_with_sni_disabled_does_not_send_sni() {
     for kt in ALL_KEY_TYPES {
-        let (mut client, mut server) = make_pair(*kt);
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ServerCheckNoSni {});
+        let server_config = Arc::new(server_config);
 
-        do_handshake(&mut client, &mut server);
+        for version in rustls::ALL_VERSIONS {
+            let mut client_config = make_client_config_with_versions(*kt, &[version]);
+            client_config.enable_sni = false;
 
-        client
-            .writer()
-            .write_all(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
-            assert!(rdlen > 0 && wrlen == 0);
-            assert_eq!(pipe.reads, 1);
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("value-not-sent"))
+                    .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert!(err.is_err());
         }
-        check_read(&mut server.reader(), b"01234567890123456789");
     }
 }
 
 #[test]
-fn client_stream_write() {
-    test_client_stream_write(StreamKind::Ref);
-    test_client_stream_write(StreamKind::Owned);
-}
+fn client_checks_server_certificate_with_given_name() {
+    for kt in ALL_KEY_TYPES {
+        let server_config = Arc::new(make_server_config(*kt));
 
-#[test]
-fn server_stream_write() {
-    test_server_stream_write(StreamKind::Ref);
-    test_server_stream_write(StreamKind::Owned);
-}
-
-#[derive(Debug, Copy, Clone)]
-enum StreamKind {
-    Owned,
-    Ref,
-}
-
-fn test_client_stream_write(stream_kind: StreamKind) {
-    for kt in ALL_KEY_TYPES {
-        let (mut client, mut server) = make_pair(*kt);
-        let data = b"hello";
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            let mut stream: Box<dyn Write> = match stream_kind {
-                StreamKind::Ref => Box::new(Stream::new(&mut client, &mut pipe)),
-                StreamKind::Owned => Box::new(StreamOwned::new(client, pipe)),
-            };
-            assert_eq!(stream.write(data).unwrap(), 5);
-        }
-        check_read(&mut server.reader(), data);
-    }
-}
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions(*kt, &[version]);
+            let mut client = ClientConnection::new(
+                Arc::new(client_config),
+                server_name("not-the-right-hostname.com"),
+            )
+            .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
 
-fn test_server_stream_write(stream_kind: StreamKind) {
-    for kt in ALL_KEY_TYPES {
-        let (mut client, mut server) = make_pair(*kt);
-        let data = b"hello";
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            let mut stream: Box<dyn Write> = match stream_kind {
-                StreamKind::Ref => Box::new(Stream::new(&mut server, &mut pipe)),
-                StreamKind::Owned => Box::new(StreamOwned::new(server, pipe)),
-            };
-            assert_eq!(stream.write(data).unwrap(), 5);
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::NotValidForName
+                )))
+            );
         }
-        check_read(&mut client.reader(), data);
-    }
-}
-
-#[test]
-fn client_stream_read() {
-    test_client_stream_read(StreamKind::Ref, ReadKind::Buf);
-    test_client_stream_read(StreamKind::Owned, ReadKind::Buf);
-    #[cfg(read_buf)]
-    {
-        test_client_stream_read(StreamKind::Ref, ReadKind::BorrowedBuf);
-        test_client_stream_read(StreamKind::Owned, ReadKind::BorrowedBuf);
     }
 }
 
 #[test]
-fn server_stream_read() {
-    test_server_stream_read(StreamKind::Ref, ReadKind::Buf);
-    test_server_stream_read(StreamKind::Owned, ReadKind::Buf);
-    #[cfg(read_buf)]
-    {
-        test_server_stream_read(StreamKind::Ref, ReadKind::BorrowedBuf);
-        test_server_stream_read(StreamKind::Owned, ReadKind::BorrowedBuf);
+fn client_checks_server_certificate_with_given_ip_address() {
+    fn check_server_name(
+        client_config: Arc<ClientConfig>,
+        server_config: Arc<ServerConfig>,
+        name: &'static str,
+    ) -> Result<(), ErrorFromPeer> {
+        let mut client = ClientConnection::new(client_config, server_name(name)).unwrap();
+        let mut server = ServerConnection::new(server_config).unwrap();
+        do_handshake_until_error(&mut client, &mut server)
     }
-}
 
-#[derive(Debug, Copy, Clone)]
-enum ReadKind {
-    Buf,
-    #[cfg(read_buf)]
-    BorrowedBuf,
-}
+    for kt in ALL_KEY_TYPES {
+        let server_config = Arc::new(make_server_config(*kt));
 
-fn test_stream_read(read_kind: ReadKind, mut stream: impl Read, data: &[u8]) {
-    match read_kind {
-        ReadKind::Buf => {
-            check_read(&mut stream, data);
-            check_read_err(&mut stream, io::ErrorKind::UnexpectedEof)
-        }
-        #[cfg(read_buf)]
-        ReadKind::BorrowedBuf => {
-            check_read_buf(&mut stream, data);
-            check_read_buf_err(&mut stream, io::ErrorKind::UnexpectedEof)
-        }
-    }
-}
+        for version in rustls::ALL_VERSIONS {
+            let client_config = Arc::new(make_client_config_with_versions(*kt, &[version]));
 
-fn test_client_stream_read(stream_kind: StreamKind, read_kind: ReadKind) {
-    for kt in ALL_KEY_TYPES {
-        let (mut client, mut server) = make_pair(*kt);
-        let data = b"world";
-        server.writer().write_all(data).unwrap();
+            // positive ipv4 case
+            assert_eq!(
+                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.1"),
+                Ok(()),
+            );
 
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            transfer_eof(&mut client);
+            // negative ipv4 case
+            assert_eq!(
+                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.2"),
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::NotValidForName
+                )))
+            );
 
-            let stream: Box<dyn Read> = match stream_kind {
-                StreamKind::Ref => Box::new(Stream::new(&mut client, &mut pipe)),
-                StreamKind::Owned => Box::new(StreamOwned::new(client, pipe)),
-            };
+            // positive ipv6 case
+            assert_eq!(
+                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::1"),
+                Ok(()),
+            );
 
-            test_stream_read(read_kind, stream, data)
+            // negative ipv6 case
+            assert_eq!(
+                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::2"),
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::NotValidForName
+                )))
+            );
         }
     }
 }
 
-fn test_server_stream_read(stream_kind: StreamKind, read_kind: ReadKind) {
+#[test]
+fn client_check_server_certificate_ee_revoked() {
     for kt in ALL_KEY_TYPES {
-        let (mut client, mut server) = make_pair(*kt);
-        let data = b"world";
-        client.writer().write_all(data).unwrap();
+        let server_config = Arc::new(make_server_config(*kt));
 
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            transfer_eof(&mut server);
+        // Setup a server verifier that will check the EE certificate's revocation status.
+        let crls = vec![kt.end_entity_crl()];
+        let builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(crls)
+            .only_check_end_entity_revocation();
 
-            let stream: Box<dyn Read> = match stream_kind {
-                StreamKind::Ref => Box::new(Stream::new(&mut server, &mut pipe)),
-                StreamKind::Owned => Box::new(StreamOwned::new(server, pipe)),
-            };
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_verifier(&[version], builder.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
 
-            test_stream_read(read_kind, stream, data)
+            // We expect the handshake to fail since the server's EE certificate is revoked.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
         }
     }
 }
 
 #[test]
-fn test_client_write_and_vectored_write_equivalence() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
-    do_handshake(&mut client, &mut server);
-
-    const N: usize = 1000;
-
-    let data_chunked: Vec<IoSlice> = std::iter::repeat(IoSlice::new(b"A"))
-        .take(N)
-        .collect();
-    let bytes_written_chunked = client
-        .writer()
-        .write_vectored(&data_chunked)
-        .unwrap();
-    let bytes_sent_chunked = transfer(&mut client, &mut server);
-    println!("write_vectored returned {bytes_written_chunked} and sent {bytes_sent_chunked}");
+fn client_check_server_certificate_ee_unknown_revocation() {
+    for kt in ALL_KEY_TYPES {
+        let server_config = Arc::new(make_server_config(*kt));
 
-    let data_contiguous = &[b'A'; N];
-    let bytes_written_contiguous = client
-        .writer()
-        .write(data_contiguous)
-        .unwrap();
-    let bytes_sent_contiguous = transfer(&mut client, &mut server);
-    println!("write returned {bytes_written_contiguous} and sent {bytes_sent_contiguous}");
+        // Setup a server verifier builder that will check the EE certificate's revocation status, but not
+        // allow unknown revocation status (the default). We'll provide CRLs that are not relevant
+        // to the EE cert to ensure its status is unknown.
+        let unrelated_crls = vec![kt.intermediate_crl()];
+        let forbid_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls.clone())
+            .only_check_end_entity_revocation();
 
-    assert_eq!(bytes_written_chunked, bytes_written_contiguous);
-    assert_eq!(bytes_sent_chunked, bytes_sent_contiguous);
-}
+        // Also set up a verifier builder that will allow unknown revocation status.
+        let allow_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls)
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
 
-struct FailsWrites {
-    errkind: io::ErrorKind,
-    after: usize,
-}
+        for version in rustls::ALL_VERSIONS {
+            let client_config =
+                make_client_config_with_verifier(&[version], forbid_unknown_verifier.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
 
-impl io::Read for FailsWrites {
-    fn read(&mut self, _b: &mut [u8]) -> io::Result<usize> {
-        Ok(0)
-    }
-}
+            // We expect if we use the forbid_unknown_verifier that the handshake will fail since the
+            // server's EE certificate's revocation status is unknown given the CRLs we've provided.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert!(matches!(
+                err,
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::UnknownRevocationStatus
+                )))
+            ));
 
-impl io::Write for FailsWrites {
-    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
-        if self.after > 0 {
-            self.after -= 1;
-            Ok(b.len())
-        } else {
-            Err(io::Error::new(self.errkind, "oops"))
+            // We expect if we use the allow_unknown_verifier that the handshake will not fail.
+            let client_config =
+                make_client_config_with_verifier(&[version], allow_unknown_verifier.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(res.is_ok());
         }
     }
-
-    fn flush(&mut self) -> io::Result<()> {
-        Ok(())
-    }
 }
 
 #[test]
-fn stream_write_reports_underlying_io_error_before_plaintext_processed() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
-    do_handshake(&mut client, &mut server);
-
-    let mut pipe = FailsWrites {
-        errkind: io::ErrorKind::ConnectionAborted,
-        after: 0,
-    };
-    client
-        .writer()
-        .write_all(b"hello")
-        .unwrap();
-    let mut client_stream = Stream::new(&mut client, &mut pipe);
-    let rc = client_stream.write(b"world");
-    assert!(rc.is_err());
-    let err = rc.err().unwrap();
-    assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
-}
-
-#[test]
-fn stream_write_swallows_underlying_io_error_after_plaintext_processed() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
-    do_handshake(&mut client, &mut server);
-
-    let mut pipe = FailsWrites {
-        errkind: io::ErrorKind::ConnectionAborted,
-        after: 1,
-    };
-    client
-        .writer()
-        .write_all(b"hello")
-        .unwrap();
-    let mut client_stream = Stream::new(&mut client, &mut pipe);
-    let rc = client_stream.write(b"world");
-    assert_eq!(format!("{:?}", rc), "Ok(5)");
-}
+fn client_check_server_certificate_intermediate_revoked() {
+    for kt in ALL_KEY_TYPES {
+        let server_config = Arc::new(make_server_config(*kt));
 
-fn make_disjoint_suite_configs() -> (ClientConfig, ServerConfig) {
-    let kt = KeyType::Rsa2048;
-    let client_provider = CryptoProvider {
-        cipher_suites: vec![cipher_suite::TLS13_CHACHA20_POLY1305_SHA256],
-        ..provider::default_provider()
-    };
-    let server_config = finish_server_config(
-        kt,
-        ServerConfig::builder_with_provider(client_provider.into())
-            .with_safe_default_protocol_versions()
-            .unwrap(),
-    );
+        // Setup a server verifier builder that will check the full chain revocation status against a CRL
+        // that marks the intermediate certificate as revoked. We allow unknown revocation status
+        // so the EE cert's unknown status doesn't cause an error.
+        let crls = vec![kt.intermediate_crl()];
+        let full_chain_verifier_builder =
+            webpki_server_verifier_builder(get_client_root_store(*kt))
+                .with_crls(crls.clone())
+                .allow_unknown_revocation_status();
 
-    let server_provider = CryptoProvider {
-        cipher_suites: vec![cipher_suite::TLS13_AES_256_GCM_SHA384],
-        ..provider::default_provider()
-    };
-    let client_config = finish_client_config(
-        kt,
-        ClientConfig::builder_with_provider(server_provider.into())
-            .with_safe_default_protocol_versions()
-            .unwrap(),
-    );
+        // Also set up a verifier builder that will use the same CRL, but only check the EE certificate
+        // revocation status.
+        let ee_verifier_builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(crls.clone())
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
 
-    (client_config, server_config)
-}
+        for version in rustls::ALL_VERSIONS {
+            let client_config =
+                make_client_config_with_verifier(&[version], full_chain_verifier_builder.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
 
-#[test]
-fn client_stream_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+            // We expect the handshake to fail when using the full chain verifier since the intermediate's
+            // EE certificate is revoked.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
 
-    {
-        let mut pipe = OtherSession::new_fails(&mut server);
-        let mut client_stream = Stream::new(&mut client, &mut pipe);
-        let rc = client_stream.write(b"hello");
-        assert!(rc.is_err());
-        assert_eq!(
-            format!("{:?}", rc),
-            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
-        );
-        let rc = client_stream.write(b"hello");
-        assert!(rc.is_err());
-        assert_eq!(
-            format!("{:?}", rc),
-            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
-        );
+            let client_config =
+                make_client_config_with_verifier(&[version], ee_verifier_builder.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            // We expect the handshake to succeed when we use the verifier that only checks the EE certificate
+            // revocation status. The revoked intermediate status should not be checked.
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(res.is_ok())
+        }
     }
 }
 
 #[test]
-fn client_streamowned_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (client, mut server) = make_pair_for_configs(client_config, server_config);
+fn client_check_server_certificate_ee_crl_expired() {
+    for kt in ALL_KEY_TYPES {
+        let server_config = Arc::new(make_server_config(*kt));
 
-    let pipe = OtherSession::new_fails(&mut server);
-    let mut client_stream = StreamOwned::new(client, pipe);
-    let rc = client_stream.write(b"hello");
-    assert!(rc.is_err());
-    assert_eq!(
-        format!("{:?}", rc),
-        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
-    );
-    let rc = client_stream.write(b"hello");
-    assert!(rc.is_err());
-    assert_eq!(
-        format!("{:?}", rc),
-        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
-    );
+        // Setup a server verifier that will check the EE certificate's revocation status, with CRL expiration enforced.
+        let crls = vec![kt.end_entity_crl_expired()];
+        let enforce_expiration_builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(crls)
+            .only_check_end_entity_revocation()
+            .enforce_revocation_expiration();
 
-    let (_, _) = client_stream.into_parts();
-}
+        // Also setup a server verifier without CRL expiration enforced.
+        let crls = vec![kt.end_entity_crl_expired()];
+        let ignore_expiration_builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(crls)
+            .only_check_end_entity_revocation();
 
-#[test]
-fn server_stream_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        for version in rustls::ALL_VERSIONS {
+            let client_config =
+                make_client_config_with_verifier(&[version], enforce_expiration_builder.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
 
-    client
-        .writer()
-        .write_all(b"world")
-        .unwrap();
+            // We expect the handshake to fail since the CRL is expired.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::ExpiredRevocationList
+                )))
+            );
 
-    {
-        let mut pipe = OtherSession::new_fails(&mut client);
-        let mut server_stream = Stream::new(&mut server, &mut pipe);
-        let mut bytes = [0u8; 5];
-        let rc = server_stream.read(&mut bytes);
-        assert!(rc.is_err());
-        assert_eq!(
-            format!("{:?}", rc),
-            "Err(Custom { kind: InvalidData, error: PeerIncompatible(NoCipherSuitesInCommon) })"
-        );
+            let client_config =
+                make_client_config_with_verifier(&[version], ignore_expiration_builder.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            // We expect the handshake to succeed when CRL expiration is ignored.
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(res.is_ok())
+        }
     }
 }
 
+/// Simple smoke-test of the webpki verify_server_cert_signed_by_trust_anchor helper API.
+/// This public API is intended to be used by consumers implementing their own verifier and
+/// so isn't used by the other existing verifier tests.
 #[test]
-fn server_streamowned_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, server) = make_pair_for_configs(client_config, server_config);
-
-    client
-        .writer()
-        .write_all(b"world")
-        .unwrap();
-
-    let pipe = OtherSession::new_fails(&mut client);
-    let mut server_stream = StreamOwned::new(server, pipe);
-    let mut bytes = [0u8; 5];
-    let rc = server_stream.read(&mut bytes);
-    assert!(rc.is_err());
-    assert_eq!(
-        format!("{:?}", rc),
-        "Err(Custom { kind: InvalidData, error: PeerIncompatible(NoCipherSuitesInCommon) })"
-    );
+fn client_check_server_certificate_helper_api() {
+    for kt in ALL_KEY_TYPES {
+        let chain = kt.get_chain();
+        let correct_roots = get_client_root_store(*kt);
+        let incorrect_roots = get_client_root_store(match kt {
+            KeyType::Rsa2048 => KeyType::EcdsaP256,
+            _ => KeyType::Rsa2048,
+        });
+        // Using the correct trust anchors, we should verify without error.
+        assert!(verify_server_cert_signed_by_trust_anchor(
+            &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
+            &correct_roots,
+            &[chain.get(1).unwrap().clone()],
+            UnixTime::now(),
+            webpki::ALL_VERIFICATION_ALGS,
+        )
+        .is_ok());
+        // Using the wrong trust anchors, we should get the expected error.
+        assert_eq!(
+            verify_server_cert_signed_by_trust_anchor(
+                &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
+                &incorrect_roots,
+                &[chain.get(1).unwrap().clone()],
+                UnixTime::now(),
+                webpki::ALL_VERIFICATION_ALGS,
+            )
+            .unwrap_err(),
+            Error::InvalidCertificate(CertificateError::UnknownIssuer)
+        );
+    }
 }
 
-#[test]
-fn server_config_is_clone() {
-    let _ = make_server_config(KeyType::Rsa2048);
+#[derive(Debug)]
+struct ClientCheckCertResolve {
+    query_count: AtomicUsize,
+    expect_queries: usize,
+    expect_root_hint_subjects: Vec<Vec<u8>>,
+    expect_sigschemes: Vec<SignatureScheme>,
 }
 
-#[test]
-fn client_config_is_clone() {
-    let _ = make_client_config(KeyType::Rsa2048);
-}
-
-#[test]
-fn client_connection_is_debug() {
-    let (client, _) = make_pair(KeyType::Rsa2048);
-    println!("{:?}", client);
+impl ClientCheckCertResolve {
+    fn new(
+        expect_queries: usize,
+        expect_root_hint_subjects: Vec<Vec<u8>>,
+        expect_sigschemes: Vec<SignatureScheme>,
+    ) -> Self {
+        Self {
+            query_count: AtomicUsize::new(0),
+            expect_queries,
+            expect_root_hint_subjects,
+            expect_sigschemes,
+        }
+    }
 }
 
-#[test]
-fn server_connection_is_debug() {
-    let (_, server) = make_pair(KeyType::Rsa2048);
-    println!("{:?}", server);
+impl Drop for ClientCheckCertResolve {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let count = self.query_count.load(Ordering::SeqCst);
+            assert_eq!(count, self.expect_queries);
+        }
+    }
 }
 
-#[test]
-fn server_complete_io_for_handshake_ending_with_alert() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
-
-    assert!(server.is_handshaking());
+impl ResolvesClientCert for ClientCheckCertResolve {
+    fn resolve(
+        &self,
+        root_hint_subjects: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<sign::CertifiedKey>> {
+        self.query_count
+            .fetch_add(1, Ordering::SeqCst);
 
-    let mut pipe = OtherSession::new_fails(&mut client);
-    let rc = server.complete_io(&mut pipe);
-    assert!(rc.is_err(), "server io failed due to handshake failure");
-    assert!(!server.wants_write(), "but server did send its alert");
-    assert_eq!(
-        format!("{:?}", pipe.last_error),
-        "Some(AlertReceived(HandshakeFailure))",
-        "which was received by client"
-    );
-}
+        if sigschemes.is_empty() {
+            panic!("no signature schemes shared by server");
+        }
 
-#[test]
-fn server_exposes_offered_sni() {
-    let kt = KeyType::Rsa2048;
-    for version in rustls::ALL_VERSIONS {
-        let client_config = make_client_config_with_versions(kt, &[version]);
-        let mut client = ClientConnection::new(
-            Arc::new(client_config),
-            server_name("second.testserver.com"),
-        )
-        .unwrap();
-        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
+        assert_eq!(sigschemes, self.expect_sigschemes);
+        assert_eq!(root_hint_subjects, self.expect_root_hint_subjects);
 
-        assert_eq!(None, server.server_name());
-        do_handshake(&mut client, &mut server);
-        assert_eq!(Some("second.testserver.com"), server.server_name());
+        None
     }
-}
-
-#[test]
-fn server_exposes_offered_sni_smashed_to_lowercase() {
-    // webpki actually does this for us in its DnsName type
-    let kt = KeyType::Rsa2048;
-    for version in rustls::ALL_VERSIONS {
-        let client_config = make_client_config_with_versions(kt, &[version]);
-        let mut client = ClientConnection::new(
-            Arc::new(client_config),
-            server_name("SECOND.TESTServer.com"),
-        )
-        .unwrap();
-        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
 
-        assert_eq!(None, server.server_name());
-        do_handshake(&mut client, &mut server);
-        assert_eq!(Some("second.testserver.com"), server.server_name());
+    fn has_certs(&self) -> bool {
+        true
     }
 }
 
-#[test]
-fn server_exposes_offered_sni_even_if_resolver_fails() {
-    let kt = KeyType::Rsa2048;
-    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
+fn test_client_cert_resolve(
+    key_type: KeyType,
+    server_config: Arc<ServerConfig>,
+    expected_root_hint_subjects: Vec<Vec<u8>>,
+) {
+    for version in rustls::ALL_VERSIONS {
+        println!("{:?} {:?}:", version.version, key_type);
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+        let mut client_config = make_client_config_with_versions(key_type, &[version]);
+        client_config.client_auth_cert_resolver = Arc::new(ClientCheckCertResolve::new(
+            1,
+            expected_root_hint_subjects.clone(),
+            default_signature_schemes(version.version),
+        ));
 
-    for version in rustls::ALL_VERSIONS {
-        let client_config = make_client_config_with_versions(kt, &[version]);
-        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), server_name("thisdoesNOTexist.com"))
-                .unwrap();
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
 
-        assert_eq!(None, server.server_name());
-        transfer(&mut client, &mut server);
         assert_eq!(
-            server.process_new_packets(),
-            Err(Error::General(
-                "no server certificate chain resolved".to_string()
-            ))
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
         );
-        assert_eq!(Some("thisdoesnotexist.com"), server.server_name());
     }
 }
 
-#[test]
-fn sni_resolver_works() {
-    let kt = KeyType::Rsa2048;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
-    resolver
-        .add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone()),
-        )
-        .unwrap();
-
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+fn default_signature_schemes(version: ProtocolVersion) -> Vec<SignatureScheme> {
+    let mut v = vec![];
 
-    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client1 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
-    let err = do_handshake_until_error(&mut client1, &mut server1);
-    assert_eq!(err, Ok(()));
+    v.extend_from_slice(&[
+        SignatureScheme::ECDSA_NISTP384_SHA384,
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        SignatureScheme::ED25519,
+        SignatureScheme::RSA_PSS_SHA512,
+        SignatureScheme::RSA_PSS_SHA384,
+        SignatureScheme::RSA_PSS_SHA256,
+    ]);
 
-    let mut server2 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client2 = ClientConnection::new(
-        Arc::new(make_client_config(kt)),
-        server_name("notlocalhost"),
-    )
-    .unwrap();
-    let err = do_handshake_until_error(&mut client2, &mut server2);
-    assert_eq!(
-        err,
-        Err(ErrorFromPeer::Server(Error::General(
-            "no server certificate chain resolved".into()
-        )))
-    );
-}
+    if provider_is_aws_lc_rs() {
+        v.insert(2, SignatureScheme::ECDSA_NISTP521_SHA512);
+    }
 
-#[test]
-fn sni_resolver_rejects_wrong_names() {
-    let kt = KeyType::Rsa2048;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+    if version == ProtocolVersion::TLSv1_2 {
+        v.extend_from_slice(&[
+            SignatureScheme::RSA_PKCS1_SHA512,
+            SignatureScheme::RSA_PKCS1_SHA384,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ]);
+    }
 
-    assert_eq!(
-        Ok(()),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
-    assert_eq!(
-        Err(Error::InvalidCertificate(CertificateError::NotValidForName)),
-        resolver.add(
-            "not-localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
-    assert_eq!(
-        Err(Error::General("Bad DNS name".into())),
-        resolver.add(
-            "not ascii 🦀",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
+    v
 }
 
 #[test]
-fn sni_resolver_lower_cases_configured_names() {
-    let kt = KeyType::Rsa2048;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
-
-    assert_eq!(
-        Ok(()),
-        resolver.add(
-            "LOCALHOST",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
+fn client_cert_resolve_default() {
+    // Test that in the default configuration that a client cert resolver gets the expected
+    // CA subject hints, and supported signature algorithms.
+    for key_type in ALL_KEY_TYPES {
+        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*key_type));
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+        // In a default configuration we expect that the verifier's trust anchors are used
+        // for the hint subjects.
+        let expected_root_hint_subjects = vec![key_type
+            .ca_distinguished_name()
+            .to_vec()];
 
-    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client1 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
-    let err = do_handshake_until_error(&mut client1, &mut server1);
-    assert_eq!(err, Ok(()));
+        test_client_cert_resolve(*key_type, server_config, expected_root_hint_subjects);
+    }
 }
 
 #[test]
-fn sni_resolver_lower_cases_queried_names() {
-    // actually, the handshake parser does this, but the effect is the same.
-    let kt = KeyType::Rsa2048;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
-
-    assert_eq!(
-        Ok(()),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
+fn client_cert_resolve_server_no_hints() {
+    // Test that a server can provide no hints and the client cert resolver gets the expected
+    // arguments.
+    for key_type in ALL_KEY_TYPES {
+        // Build a verifier with no hint subjects.
+        let verifier = webpki_client_verifier_builder(get_client_root_store(*key_type))
+            .clear_root_hint_subjects();
+        let server_config = make_server_config_with_client_verifier(*key_type, verifier);
+        let expected_root_hint_subjects = Vec::default(); // no hints expected.
+        test_client_cert_resolve(*key_type, server_config.into(), expected_root_hint_subjects);
+    }
+}
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+#[test]
+fn client_cert_resolve_server_added_hint() {
+    // Test that a server can add an extra subject above/beyond those found in its trust store
+    // and the client cert resolver gets the expected arguments.
+    let extra_name = b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponyland IDK CA".to_vec();
+    for key_type in ALL_KEY_TYPES {
+        let expected_hint_subjects = vec![
+            key_type
+                .ca_distinguished_name()
+                .to_vec(),
+            extra_name.clone(),
+        ];
+        // Create a verifier that adds the extra_name as a hint subject in addition to the ones
+        // from the root cert store.
+        let verifier = webpki_client_verifier_builder(get_client_root_store(*key_type))
+            .add_root_hint_subjects([DistinguishedName::from(extra_name.clone())].into_iter());
+        let server_config = make_server_config_with_client_verifier(*key_type, verifier);
+        test_client_cert_resolve(*key_type, server_config.into(), expected_hint_subjects);
+    }
+}
 
-    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client1 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("LOCALHOST")).unwrap();
-    let err = do_handshake_until_error(&mut client1, &mut server1);
-    assert_eq!(err, Ok(()));
+#[test]
+fn client_cert_resolve_server_limited_hints() {
+    // Test that a server can send a subset of its hint subjects.
+    let extra_name = b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponyland IDK CA".to_vec();
+    for key_type in ALL_KEY_TYPES {
+        let verifier = webpki_client_verifier_builder(get_client_root_store(*key_type))
+            .add_root_hint_subjects([DistinguishedName::from(extra_name.clone())])
+            .limit_root_hint_subjects(1);
+        let server_config = make_server_config_with_client_verifier(*key_type, verifier);
+        let expected_hint_subjects = vec![key_type
+            .ca_distinguished_name()
+            .to_vec()];
+        test_client_cert_resolve(*key_type, server_config.into(), expected_hint_subjects);
+
+        let verifier = webpki_client_verifier_builder(get_client_root_store(*key_type))
+            .add_root_hint_subjects([DistinguishedName::from(extra_name.clone())])
+            .retain_root_hint_subjects(|name| name.as_ref() == extra_name);
+        let server_config = make_server_config_with_client_verifier(*key_type, verifier);
+        test_client_cert_resolve(*key_type, server_config.into(), vec![extra_name.clone()]);
+    }
 }
 
 #[test]
-fn sni_resolver_rejects_bad_certs() {
-    let kt = KeyType::Rsa2048;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+fn client_cert_resolve_using_issuers() {
+    let certified_key = |kt: KeyType| {
+        let key = provider::default_provider()
+            .key_provider
+            .load_private_key(kt.get_client_key())
+            .unwrap();
+        sign::CertifiedKey::new(kt.get_client_chain(), key)
+    };
+    let end_entity = |kt: KeyType| Some(kt.get_client_chain()[0].clone());
+
+    let mut resolver = ResolvesClientCertUsingIssuers::new();
+    assert!(!resolver.has_certs());
+    resolver
+        .add(certified_key(KeyType::Rsa2048))
+        .unwrap();
+    resolver
+        .add(certified_key(KeyType::EcdsaP256))
+        .unwrap();
+    resolver.set_default(certified_key(KeyType::Ed25519));
+    assert!(resolver.has_certs());
+    let resolver = Arc::new(resolver);
+
+    // with hints, the first eligible match wins, otherwise the default
+    for kt in [KeyType::EcdsaP256, KeyType::Rsa2048, KeyType::Ed25519] {
+        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(kt));
+        let mut client_config = make_client_config(kt);
+        client_config.client_auth_cert_resolver = resolver.clone();
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            server
+                .peer_certificates()
+                .map(|certs| certs[0].clone()),
+            end_entity(kt)
+        );
+    }
 
+    // without hints, the first eligible chain wins
+    let chosen = |hints: &[&[u8]], sigschemes: &[SignatureScheme]| {
+        resolver
+            .resolve(hints, sigschemes)
+            .map(|ck| ck.cert[0].clone())
+    };
     assert_eq!(
-        Err(Error::NoCertificatesPresented),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(vec![], signing_key.clone())
-        )
+        chosen(
+            &[],
+            &[
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::RSA_PSS_SHA256
+            ]
+        ),
+        end_entity(KeyType::Rsa2048)
+    );
+    assert_eq!(
+        chosen(&[], &[SignatureScheme::ECDSA_NISTP256_SHA256]),
+        end_entity(KeyType::EcdsaP256)
     );
 
-    let bad_chain = vec![CertificateDer::from(vec![0xa0])];
+    // the default is only used if it supports the server's signature schemes
     assert_eq!(
-        Err(Error::InvalidCertificate(CertificateError::BadEncoding)),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(bad_chain, signing_key.clone())
-        )
+        chosen(&[b"unknown"], &[SignatureScheme::ED25519]),
+        end_entity(KeyType::Ed25519)
+    );
+    assert_eq!(
+        chosen(&[b"unknown"], &[SignatureScheme::ECDSA_NISTP256_SHA256]),
+        None
     );
 }
 
-#[test]
-fn test_keys_match() {
-    // Consistent: Both of these should have the same SPKI values
-    let expect_consistent =
-        sign::CertifiedKey::new(KeyType::Rsa2048.get_chain(), Arc::new(SigningKeySomeSpki));
-    assert!(matches!(expect_consistent.keys_match(), Ok(())));
-
-    // Inconsistent: These should not have the same SPKI values
-    let expect_inconsistent =
-        sign::CertifiedKey::new(KeyType::EcdsaP256.get_chain(), Arc::new(SigningKeySomeSpki));
-    assert!(matches!(
-        expect_inconsistent.keys_match(),
-        Err(Error::InconsistentKeys(InconsistentKeys::KeyMismatch))
-    ));
-
-    // Unknown: This signing key returns None for its SPKI, so we can't tell if the certified key is consistent
-    let expect_unknown =
-        sign::CertifiedKey::new(KeyType::Rsa2048.get_chain(), Arc::new(SigningKeyNoneSpki));
-    assert!(matches!(
-        expect_unknown.keys_match(),
-        Err(Error::InconsistentKeys(InconsistentKeys::Unknown))
-    ));
+#[derive(Debug, Default)]
+struct RecordsCertificateRequest {
+    seen: Mutex<Vec<SeenCertificateRequest>>,
 }
 
-/// Represents a SigningKey that returns None for its SPKI via the default impl.
 #[derive(Debug)]
-struct SigningKeyNoneSpki;
+struct SeenCertificateRequest {
+    version: ProtocolVersion,
+    context: Option<Vec<u8>>,
+    extensions: Vec<u16>,
+    hints: Vec<Vec<u8>>,
+}
 
-impl sign::SigningKey for SigningKeyNoneSpki {
-    fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
-        unimplemented!("Not meant to be called during tests")
+impl ResolvesClientCert for RecordsCertificateRequest {
+    fn resolve(&self, _: &[&[u8]], _: &[SignatureScheme]) -> Option<Arc<sign::CertifiedKey>> {
+        unreachable!("resolve_request is overridden")
     }
 
-    fn algorithm(&self) -> rustls::SignatureAlgorithm {
-        unimplemented!("Not meant to be called during tests")
+    fn resolve_request(
+        &self,
+        request: &rustls::client::CertificateRequest<'_>,
+    ) -> Option<Arc<sign::CertifiedKey>> {
+        assert!(!request.signature_schemes().is_empty());
+        self.seen
+            .lock()
+            .unwrap()
+            .push(SeenCertificateRequest {
+                version: request.version(),
+                context: request.context().map(|c| c.to_vec()),
+                extensions: request
+                    .extensions()
+                    .map(|(typ, _)| typ)
+                    .collect(),
+                hints: request
+                    .root_hint_subjects()
+                    .iter()
+                    .map(|s| s.to_vec())
+                    .collect(),
+            });
+        None
     }
-}
-
-/// Represents a SigningKey that returns Some for its SPKI.
-#[derive(Debug)]
-struct SigningKeySomeSpki;
 
-impl sign::SigningKey for SigningKeySomeSpki {
-    fn public_key(&self) -> Option<pki_types::SubjectPublicKeyInfoDer> {
-        let chain = KeyType::Rsa2048.get_chain();
-        let cert = ParsedCertificate::try_from(chain.first().unwrap()).unwrap();
-        Some(
-            cert.subject_public_key_info()
-                .into_owned(),
-        )
+    fn has_certs(&self) -> bool {
+        true
     }
+}
 
-    fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
-        unimplemented!("Not meant to be called during tests")
-    }
+#[test]
+fn client_cert_resolve_request_details() {
+    let kt = KeyType::EcdsaP256;
+    let server_config = Arc::new(make_server_config_with_mandatory_client_auth(kt));
 
-    fn algorithm(&self) -> rustls::SignatureAlgorithm {
-        unimplemented!("Not meant to be called during tests")
+    for version in rustls::ALL_VERSIONS {
+        let resolver = Arc::new(RecordsCertificateRequest::default());
+        let mut client_config = make_client_config_with_versions(kt, &[version]);
+        client_config.client_auth_cert_resolver = resolver.clone();
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+
+        let seen = resolver.seen.lock().unwrap();
+        assert_eq!(seen[0].version, version.version);
+        assert_eq!(seen[0].hints, vec![kt.ca_distinguished_name().to_vec()]);
+        match version.version {
+            ProtocolVersion::TLSv1_3 => {
+                assert_eq!(seen[0].context.as_deref(), Some(&[][..]));
+                // signature_algorithms and certificate_authorities
+                assert!(seen[0].extensions.contains(&13));
+                assert!(seen[0].extensions.contains(&47));
+            }
+            _ => {
+                assert_eq!(seen[0].context, None);
+                assert!(seen[0].extensions.is_empty());
+            }
+        }
     }
 }
 
-fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
-    let mut client_secret = [0u8; 64];
-    let mut server_secret = [0u8; 64];
-
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+#[test]
+fn cert_resolvers_using_fn() {
+    let kt = KeyType::EcdsaP256;
+    let load = |chain: Vec<CertificateDer<'static>>, key| {
+        let key = provider::default_provider()
+            .key_provider
+            .load_private_key(key)
+            .unwrap();
+        Arc::new(sign::CertifiedKey::new(chain, key))
+    };
+    let server_key = load(kt.get_chain(), kt.get_key());
+    let client_key = load(kt.get_client_chain(), kt.get_client_key());
 
-    assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
-    );
-    assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
-    );
-    do_handshake(&mut client, &mut server);
+    let mut server_config = make_server_config_with_mandatory_client_auth(kt);
+    server_config.cert_resolver = Arc::new(ResolvesServerCertUsingFn::new(move |hello| {
+        assert_eq!(hello.server_name(), Some("localhost"));
+        Some(Arc::clone(&server_key))
+    }));
 
-    assert!(client
-        .export_keying_material(&mut client_secret, b"label", Some(b"context"))
-        .is_ok());
-    assert!(server
-        .export_keying_material(&mut server_secret, b"label", Some(b"context"))
-        .is_ok());
-    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+    let hinted = Arc::new(AtomicUsize::new(0));
+    let hinted_by_server = Arc::clone(&hinted);
+    let mut client_config = make_client_config(kt);
+    client_config.client_auth_cert_resolver =
+        Arc::new(ResolvesClientCertUsingFn::new(move |hints, _sigschemes| {
+            hinted_by_server.fetch_add(hints.len(), Ordering::SeqCst);
+            Some(Arc::clone(&client_key))
+        }));
+    assert!(client_config
+        .client_auth_cert_resolver
+        .has_certs());
 
-    let mut empty = vec![];
-    assert_eq!(
-        client
-            .export_keying_material(&mut empty, b"label", Some(b"context"))
-            .err(),
-        Some(Error::General(
-            "export_keying_material with zero-length output".into()
-        ))
-    );
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(hinted.load(Ordering::SeqCst), 1);
     assert_eq!(
         server
-            .export_keying_material(&mut empty, b"label", Some(b"context"))
-            .err(),
-        Some(Error::General(
-            "export_keying_material with zero-length output".into()
-        ))
+            .peer_certificates()
+            .map(|certs| certs[0].clone()),
+        Some(kt.get_client_chain()[0].clone())
     );
-
-    assert!(client
-        .export_keying_material(&mut client_secret, b"label", None)
-        .is_ok());
-    assert_ne!(client_secret.to_vec(), server_secret.to_vec());
-    assert!(server
-        .export_keying_material(&mut server_secret, b"label", None)
-        .is_ok());
-    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
 }
 
-#[cfg(feature = "tls12")]
 #[test]
-fn test_tls12_exporter() {
+fn client_auth_works() {
     for kt in ALL_KEY_TYPES {
-        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS12]);
-        let server_config = make_server_config(*kt);
+        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*kt));
 
-        do_exporter_test(client_config, server_config);
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            do_handshake(&mut client, &mut server);
+        }
     }
 }
 
 #[test]
-fn test_tls13_exporter() {
-    for kt in ALL_KEY_TYPES {
-        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS13]);
-        let server_config = make_server_config(*kt);
+fn server_exposes_client_identity() {
+    let server_config = Arc::new(make_server_config_with_mandatory_client_auth(
+        KeyType::Rsa2048,
+    ));
 
-        do_exporter_test(client_config, server_config);
+    for version in rustls::ALL_VERSIONS {
+        let client_config =
+            make_client_config_with_versions_with_auth(KeyType::Rsa2048, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert!(server.client_identity().is_none());
+        do_handshake(&mut client, &mut server);
+
+        let identity = server.client_identity().unwrap();
+        assert!(identity
+            .subject()
+            .as_ref()
+            .windows(15)
+            .any(|w| w == b"ponytown client"));
+        assert_eq!(
+            identity.dns_names(),
+            &["testserver.com", "second.testserver.com", "localhost"]
+        );
+
+        let client_cert = KeyType::Rsa2048.get_client_chain()[0].clone();
+        let spki = ParsedCertificate::try_from(&client_cert)
+            .unwrap()
+            .subject_public_key_info();
+        assert_eq!(identity.subject_public_key_info(), &spki);
+
+        let sha256 = cipher_suite::TLS13_AES_128_GCM_SHA256
+            .tls13()
+            .unwrap()
+            .common
+            .hash_provider;
+        assert_eq!(
+            identity.spki_hash(sha256).as_ref(),
+            sha256.hash(spki.as_ref()).as_ref()
+        );
     }
 }
 
 #[test]
-fn test_tls13_exporter_maximum_output_length() {
+fn server_has_no_client_identity_without_client_auth() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    do_handshake(&mut client, &mut server);
+    assert!(server.client_identity().is_none());
+}
+
+#[test]
+fn server_exposes_client_cert_details() {
+    let kt = KeyType::Rsa2048;
+    let roots = get_client_root_store(kt);
+    let client_chain = kt.get_client_chain();
+    let unchecked_server_config = Arc::new(make_server_config_with_mandatory_client_auth(kt));
+    let checked_server_config = Arc::new(make_server_config_with_client_verifier(
+        kt,
+        webpki_client_verifier_builder(Arc::clone(&roots))
+            .with_crls(vec![kt.end_entity_crl()])
+            .allow_unknown_revocation_status(),
+    ));
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions_with_auth(kt, &[version]));
+
+        for (server_config, revocation) in [
+            (&unchecked_server_config, RevocationCheck::NotChecked),
+            (&checked_server_config, RevocationCheck::Chain),
+        ] {
+            let (mut client, mut server) = make_pair_for_arc_configs(&client_config, server_config);
+            assert!(server.client_cert_details().is_none());
+            do_handshake(&mut client, &mut server);
+
+            let details = server.client_cert_details().unwrap();
+            assert_eq!(details.intermediates, &client_chain[1..2]);
+            assert_eq!(
+                details
+                    .trust_anchor_subject
+                    .as_ref()
+                    .map(|subject| subject.as_ref()),
+                Some(roots.subjects()[0].as_ref())
+            );
+            // id-kp-clientAuth: 1.3.6.1.5.5.7.3.2
+            assert_eq!(
+                details.extended_key_usages,
+                vec![vec![0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02]]
+            );
+            assert!(details.policies.is_empty());
+            assert_eq!(details.revocation, revocation);
+        }
+    }
+
+    let (mut client, mut server) = make_pair(kt);
+    do_handshake(&mut client, &mut server);
+    assert!(server.client_cert_details().is_none());
+}
+
+#[test]
+fn client_mandatory_auth_client_revocation_works() {
+    for kt in ALL_KEY_TYPES {
+        // Create a server configuration that includes a CRL that specifies the client certificate
+        // is revoked.
+        let relevant_crls = vec![kt.client_crl()];
+        // Only check the EE certificate status. See client_mandatory_auth_intermediate_revocation_works
+        // for testing revocation status of the whole chain.
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(relevant_crls)
+            .only_check_end_entity_revocation();
+        let revoked_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            ee_verifier_builder,
+        ));
+
+        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
+        // and uses the default behaviour of treating unknown revocation status as an error.
+        let unrelated_crls = vec![kt.intermediate_crl()];
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls.clone())
+            .only_check_end_entity_revocation();
+        let missing_client_crl_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            ee_verifier_builder,
+        ));
+
+        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
+        // but change the builder to allow unknown revocation status.
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls.clone())
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
+        let allow_missing_client_crl_server_config = Arc::new(
+            make_server_config_with_client_verifier(*kt, ee_verifier_builder),
+        );
+
+        for version in rustls::ALL_VERSIONS {
+            // Connecting to the server with a CRL that indicates the client certificate is revoked
+            // should fail with the expected error.
+            let client_config =
+                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &revoked_server_config);
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
+            // Connecting to the server missing CRL information for the client certificate should
+            // fail with the expected unknown revocation status error.
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &missing_client_crl_server_config);
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(matches!(
+                res,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::UnknownRevocationStatus
+                )))
+            ));
+            // Connecting to the server missing CRL information for the client should not error
+            // if the server's verifier allows unknown revocation status.
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &allow_missing_client_crl_server_config);
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(res.is_ok());
+        }
+    }
+}
+
+#[test]
+fn client_mandatory_auth_intermediate_revocation_works() {
+    for kt in ALL_KEY_TYPES {
+        // Create a server configuration that includes a CRL that specifies the intermediate certificate
+        // is revoked. We check the full chain for revocation status (default), and allow unknown
+        // revocation status so the EE's unknown revocation status isn't an error.
+        let crls = vec![kt.intermediate_crl()];
+        let full_chain_verifier_builder =
+            webpki_client_verifier_builder(get_client_root_store(*kt))
+                .with_crls(crls.clone())
+                .allow_unknown_revocation_status();
+        let full_chain_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            full_chain_verifier_builder,
+        ));
+
+        // Also create a server configuration that uses the same CRL, but that only checks the EE
+        // cert revocation status.
+        let ee_only_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(crls)
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
+        let ee_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            ee_only_verifier_builder,
+        ));
+
+        for version in rustls::ALL_VERSIONS {
+            // When checking the full chain, we expect an error - the intermediate is revoked.
+            let client_config =
+                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &full_chain_server_config);
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
+            // However, when checking just the EE cert we expect no error - the intermediate's
+            // revocation status should not be checked.
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &ee_server_config);
+            assert!(do_handshake_until_error(&mut client, &mut server).is_ok());
+        }
+    }
+}
+
+#[test]
+fn client_optional_auth_client_revocation_works() {
+    for kt in ALL_KEY_TYPES {
+        // Create a server configuration that includes a CRL that specifies the client certificate
+        // is revoked.
+        let crls = vec![kt.client_crl()];
+        let server_config = Arc::new(make_server_config_with_optional_client_auth(*kt, crls));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            // Because the client certificate is revoked, the handshake should fail.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
+        }
+    }
+}
+
+#[test]
+fn client_error_is_sticky() {
+    let (mut client, _) = make_pair(KeyType::Rsa2048);
+    client
+        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
+        .unwrap();
+    let mut err = client.process_new_packets();
+    assert!(err.is_err());
+    err = client.process_new_packets();
+    assert!(err.is_err());
+}
+
+#[test]
+fn server_error_is_sticky() {
+    let (_, mut server) = make_pair(KeyType::Rsa2048);
+    server
+        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
+        .unwrap();
+    let mut err = server.process_new_packets();
+    assert!(err.is_err());
+    err = server.process_new_packets();
+    assert!(err.is_err());
+}
+
+#[test]
+fn server_flush_does_nothing() {
+    let (_, mut server) = make_pair(KeyType::Rsa2048);
+    assert!(matches!(server.writer().flush(), Ok(())));
+}
+
+#[test]
+fn client_flush_does_nothing() {
+    let (mut client, _) = make_pair(KeyType::Rsa2048);
+    assert!(matches!(client.writer().flush(), Ok(())));
+}
+
+#[allow(clippy::no_effect)]
+#[test]
+fn server_is_send_and_sync() {
+    let (_, server) = make_pair(KeyType::Rsa2048);
+    &server as &dyn Send;
+    &server as &dyn Sync;
+}
+
+#[allow(clippy::no_effect)]
+#[test]
+fn client_is_send_and_sync() {
+    let (client, _) = make_pair(KeyType::Rsa2048);
+    &client as &dyn Send;
+    &client as &dyn Sync;
+}
+
+#[test]
+fn server_respects_buffer_limit_pre_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+
+    server.set_buffer_limit(Some(32));
+
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        12
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    check_read(&mut client.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
+fn server_respects_buffer_limit_pre_handshake_with_vectored_write() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+
+    server.set_buffer_limit(Some(32));
+
+    assert_eq!(
+        server
+            .writer()
+            .write_vectored(&[
+                IoSlice::new(b"01234567890123456789"),
+                IoSlice::new(b"01234567890123456789")
+            ])
+            .unwrap(),
+        32
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    check_read(&mut client.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
+fn server_respects_buffer_limit_post_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+
+    // this test will vary in behaviour depending on the default suites
+    do_handshake(&mut client, &mut server);
+    server.set_buffer_limit(Some(48));
+
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        6
+    );
+
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    check_read(&mut client.reader(), b"01234567890123456789012345");
+}
+
+#[test]
+fn client_respects_buffer_limit_pre_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+
+    client.set_buffer_limit(Some(32));
+
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        12
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    check_read(&mut server.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
+fn client_respects_buffer_limit_pre_handshake_with_vectored_write() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+
+    client.set_buffer_limit(Some(32));
+
+    assert_eq!(
+        client
+            .writer()
+            .write_vectored(&[
+                IoSlice::new(b"01234567890123456789"),
+                IoSlice::new(b"01234567890123456789")
+            ])
+            .unwrap(),
+        32
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    check_read(&mut server.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
+fn client_respects_buffer_limit_post_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+
+    do_handshake(&mut client, &mut server);
+    client.set_buffer_limit(Some(48));
+
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        6
+    );
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    check_read(&mut server.reader(), b"01234567890123456789012345");
+}
+
+struct OtherSession<'a, C, S>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    sess: &'a mut C,
+    pub reads: usize,
+    pub writevs: Vec<Vec<usize>>,
+    fail_ok: bool,
+    pub short_writes: bool,
+    pub last_error: Option<rustls::Error>,
+    pub buffered: bool,
+    buffer: Vec<Vec<u8>>,
+}
+
+impl<'a, C, S> OtherSession<'a, C, S>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    fn new(sess: &'a mut C) -> OtherSession<'a, C, S> {
+        OtherSession {
+            sess,
+            reads: 0,
+            writevs: vec![],
+            fail_ok: false,
+            short_writes: false,
+            last_error: None,
+            buffered: false,
+            buffer: vec![],
+        }
+    }
+
+    fn new_buffered(sess: &'a mut C) -> OtherSession<'a, C, S> {
+        let mut os = OtherSession::new(sess);
+        os.buffered = true;
+        os
+    }
+
+    fn new_fails(sess: &'a mut C) -> OtherSession<'a, C, S> {
+        let mut os = OtherSession::new(sess);
+        os.fail_ok = true;
+        os
+    }
+
+    fn flush_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let mut total = 0;
+        let mut lengths = vec![];
+        for bytes in b {
+            let write_len = if self.short_writes {
+                if bytes.len() > 5 {
+                    bytes.len() / 2
+                } else {
+                    bytes.len()
+                }
+            } else {
+                bytes.len()
+            };
+
+            let l = self
+                .sess
+                .read_tls(&mut io::Cursor::new(&bytes[..write_len]))?;
+            lengths.push(l);
+            total += l;
+            if bytes.len() != l {
+                break;
+            }
+        }
+
+        let rc = self.sess.process_new_packets();
+        if !self.fail_ok {
+            rc.unwrap();
+        } else if rc.is_err() {
+            self.last_error = rc.err();
+        }
+
+        self.writevs.push(lengths);
+        Ok(total)
+    }
+}
+
+impl<'a, C, S> io::Read for OtherSession<'a, C, S>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    fn read(&mut self, mut b: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        self.sess.write_tls(b.by_ref())
+    }
+}
+
+impl<'a, C, S> io::Write for OtherSession<'a, C, S>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        unreachable!()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let buffer = mem::take(&mut self.buffer);
+            let slices = buffer
+                .iter()
+                .map(|b| io::IoSlice::new(b))
+                .collect::<Vec<_>>();
+            self.flush_vectored(&slices)?;
+        }
+        Ok(())
+    }
+
+    fn write_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        if self.buffered {
+            self.buffer
+                .extend(b.iter().map(|s| s.to_vec()));
+            return Ok(b.iter().map(|s| s.len()).sum());
+        }
+        self.flush_vectored(b)
+    }
+}
+
+#[test]
+fn server_read_returns_wouldblock_when_no_data() {
+    let (_, mut server) = make_pair(KeyType::Rsa2048);
+    assert!(matches!(server.reader().read(&mut [0u8; 1]),
+                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+}
+
+#[test]
+fn client_read_returns_wouldblock_when_no_data() {
+    let (mut client, _) = make_pair(KeyType::Rsa2048);
+    assert!(matches!(client.reader().read(&mut [0u8; 1]),
+                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+}
+
+#[test]
+fn new_server_returns_initial_io_state() {
+    let (_, mut server) = make_pair(KeyType::Rsa2048);
+    let io_state = server.process_new_packets().unwrap();
+    println!("IoState is Debug {:?}", io_state);
+    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
+    assert!(!io_state.peer_has_closed());
+    assert_eq!(io_state.tls_bytes_to_write(), 0);
+}
+
+#[test]
+fn new_client_returns_initial_io_state() {
+    let (mut client, _) = make_pair(KeyType::Rsa2048);
+    let io_state = client.process_new_packets().unwrap();
+    println!("IoState is Debug {:?}", io_state);
+    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
+    assert!(!io_state.peer_has_closed());
+    assert!(io_state.tls_bytes_to_write() > 200);
+}
+
+#[test]
+fn client_complete_io_for_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+
+    assert!(client.is_handshaking());
+    let (rdlen, wrlen) = client
+        .complete_io(&mut OtherSession::new(&mut server))
+        .unwrap();
+    assert!(rdlen > 0 && wrlen > 0);
+    assert!(!client.is_handshaking());
+    assert!(!client.wants_write());
+}
+
+#[test]
+fn io_state_reports_events() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+
+    let (rdlen, wrlen, io_state) = client
+        .complete_io_with_state(&mut OtherSession::new(&mut server))
+        .unwrap();
+    assert!(rdlen > 0 && wrlen > 0);
+    assert_eq!(io_state.handshake_completed(), Some(HandshakeKind::Full));
+    assert_eq!(io_state.tickets_received(), 0);
+
+    transfer(&mut server, &mut client);
+    let io_state = client.process_new_packets().unwrap();
+    assert_eq!(io_state.handshake_completed(), None);
+    assert_eq!(io_state.tickets_received(), 4);
+    assert_eq!(io_state.key_updates_received(), 0);
+
+    server.refresh_traffic_keys().unwrap();
+    transfer(&mut server, &mut client);
+    let io_state = client.process_new_packets().unwrap();
+    assert_eq!(io_state.tickets_received(), 0);
+    assert_eq!(io_state.key_updates_received(), 1);
+
+    let io_state = server.process_new_packets().unwrap();
+    assert_eq!(io_state.handshake_completed(), None);
+}
+
+#[test]
+fn buffered_client_complete_io_for_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+
+    assert!(client.is_handshaking());
+    let (rdlen, wrlen) = client
+        .complete_io(&mut OtherSession::new_buffered(&mut server))
+        .unwrap();
+    assert!(rdlen > 0 && wrlen > 0);
+    assert!(!client.is_handshaking());
+    assert!(!client.wants_write());
+}
+
+#[test]
+fn client_complete_io_for_handshake_eof() {
+    let (mut client, _) = make_pair(KeyType::Rsa2048);
+    let mut input = io::Cursor::new(Vec::new());
+
+    assert!(client.is_handshaking());
+    let err = client
+        .complete_io(&mut input)
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+}
+
+#[test]
+fn client_complete_io_for_write() {
+    for kt in ALL_KEY_TYPES {
+        let (mut client, mut server) = make_pair(*kt);
+
+        do_handshake(&mut client, &mut server);
+
+        client
+            .writer()
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        client
+            .writer()
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
+            assert!(rdlen == 0 && wrlen > 0);
+            println!("{:?}", pipe.writevs);
+            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+        }
+        check_read(
+            &mut server.reader(),
+            b"0123456789012345678901234567890123456789",
+        );
+    }
+}
+
+#[test]
+fn buffered_client_complete_io_for_write() {
+    for kt in ALL_KEY_TYPES {
+        let (mut client, mut server) = make_pair(*kt);
+
+        do_handshake(&mut client, &mut server);
+
+        client
+            .writer()
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        client
+            .writer()
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new_buffered(&mut server);
+            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
+            assert!(rdlen == 0 && wrlen > 0);
+            println!("{:?}", pipe.writevs);
+            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+        }
+        check_read(
+            &mut server.reader(),
+            b"0123456789012345678901234567890123456789",
+        );
+    }
+}
+
+#[test]
+fn client_complete_io_for_read() {
+    for kt in ALL_KEY_TYPES {
+        let (mut client, mut server) = make_pair(*kt);
+
+        do_handshake(&mut client, &mut server);
+
+        server
+            .writer()
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
+            assert!(rdlen > 0 && wrlen == 0);
+            assert_eq!(pipe.reads, 1);
+        }
+        check_read(&mut client.reader(), b"01234567890123456789");
+    }
+}
+
+#[test]
+fn server_complete_io_for_handshake() {
+    for kt in ALL_KEY_TYPES {
+        let (mut client, mut server) = make_pair(*kt);
+
+        assert!(server.is_handshaking());
+        let (rdlen, wrlen) = server
+            .complete_io(&mut OtherSession::new(&mut client))
+            .unwrap();
+        assert!(rdlen > 0 && wrlen > 0);
+        assert!(!server.is_handshaking());
+        assert!(!server.wants_write());
+    }
+}
+
+#[test]
+fn server_complete_io_for_handshake_eof() {
+    let (_, mut server) = make_pair(KeyType::Rsa2048);
+    let mut input = io::Cursor::new(Vec::new());
+
+    assert!(server.is_handshaking());
+    let err = server
+        .complete_io(&mut input)
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+}
+
+#[test]
+fn server_complete_io_for_write() {
+    for kt in ALL_KEY_TYPES {
+        let (mut client, mut server) = make_pair(*kt);
+
+        do_handshake(&mut client, &mut server);
+
+        server
+            .writer()
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        server
+            .writer()
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
+            assert!(rdlen == 0 && wrlen > 0);
+            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+        }
+        check_read(
+            &mut client.reader(),
+            b"0123456789012345678901234567890123456789",
+        );
+    }
+}
+
+#[test]
+fn server_complete_io_for_read() {
+    for kt in ALL_KEY_TYPES {
+        let (mut client, mut server) = make_pair(*kt);
+
+        do_handshake(&mut client, &mut server);
+
+        client
+            .writer()
+            .write_all(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
+            assert!(rdlen > 0 && wrlen == 0);
+            assert_eq!(pipe.reads, 1);
+        }
+        check_read(&mut server.reader(), b"01234567890123456789");
+    }
+}
+
+#[test]
+fn client_stream_write() {
+    test_client_stream_write(StreamKind::Ref);
+    test_client_stream_write(StreamKind::Owned);
+}
+
+#[test]
+fn server_stream_write() {
+    test_server_stream_write(StreamKind::Ref);
+    test_server_stream_write(StreamKind::Owned);
+}
+
+#[derive(Debug, Copy, Clone)]
+enum StreamKind {
+    Owned,
+    Ref,
+}
+
+fn test_client_stream_write(stream_kind: StreamKind) {
+    for kt in ALL_KEY_TYPES {
+        let (mut client, mut server) = make_pair(*kt);
+        let data = b"hello";
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            let mut stream: Box<dyn Write> = match stream_kind {
+                StreamKind::Ref => Box::new(Stream::new(&mut client, &mut pipe)),
+                StreamKind::Owned => Box::new(StreamOwned::new(client, pipe)),
+            };
+            assert_eq!(stream.write(data).unwrap(), 5);
+        }
+        check_read(&mut server.reader(), data);
+    }
+}
+
+fn test_server_stream_write(stream_kind: StreamKind) {
+    for kt in ALL_KEY_TYPES {
+        let (mut client, mut server) = make_pair(*kt);
+        let data = b"hello";
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            let mut stream: Box<dyn Write> = match stream_kind {
+                StreamKind::Ref => Box::new(Stream::new(&mut server, &mut pipe)),
+                StreamKind::Owned => Box::new(StreamOwned::new(server, pipe)),
+            };
+            assert_eq!(stream.write(data).unwrap(), 5);
+        }
+        check_read(&mut client.reader(), data);
+    }
+}
+
+#[test]
+fn client_stream_read() {
+    test_client_stream_read(StreamKind::Ref, ReadKind::Buf);
+    test_client_stream_read(StreamKind::Owned, ReadKind::Buf);
+    #[cfg(read_buf)]
+    {
+        test_client_stream_read(StreamKind::Ref, ReadKind::BorrowedBuf);
+        test_client_stream_read(StreamKind::Owned, ReadKind::BorrowedBuf);
+    }
+}
+
+#[test]
+fn server_stream_read() {
+    test_server_stream_read(StreamKind::Ref, ReadKind::Buf);
+    test_server_stream_read(StreamKind::Owned, ReadKind::Buf);
+    #[cfg(read_buf)]
+    {
+        test_server_stream_read(StreamKind::Ref, ReadKind::BorrowedBuf);
+        test_server_stream_read(StreamKind::Owned, ReadKind::BorrowedBuf);
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum ReadKind {
+    Buf,
+    #[cfg(read_buf)]
+    BorrowedBuf,
+}
+
+fn test_stream_read(read_kind: ReadKind, mut stream: impl Read, data: &[u8]) {
+    match read_kind {
+        ReadKind::Buf => {
+            check_read(&mut stream, data);
+            check_read_err(&mut stream, io::ErrorKind::UnexpectedEof)
+        }
+        #[cfg(read_buf)]
+        ReadKind::BorrowedBuf => {
+            check_read_buf(&mut stream, data);
+            check_read_buf_err(&mut stream, io::ErrorKind::UnexpectedEof)
+        }
+    }
+}
+
+fn test_client_stream_read(stream_kind: StreamKind, read_kind: ReadKind) {
+    for kt in ALL_KEY_TYPES {
+        let (mut client, mut server) = make_pair(*kt);
+        let data = b"world";
+        server.writer().write_all(data).unwrap();
+
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            transfer_eof(&mut client);
+
+            let stream: Box<dyn Read> = match stream_kind {
+                StreamKind::Ref => Box::new(Stream::new(&mut client, &mut pipe)),
+                StreamKind::Owned => Box::new(StreamOwned::new(client, pipe)),
+            };
+
+            test_stream_read(read_kind, stream, data)
+        }
+    }
+}
+
+fn test_server_stream_read(stream_kind: StreamKind, read_kind: ReadKind) {
+    for kt in ALL_KEY_TYPES {
+        let (mut client, mut server) = make_pair(*kt);
+        let data = b"world";
+        client.writer().write_all(data).unwrap();
+
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            transfer_eof(&mut server);
+
+            let stream: Box<dyn Read> = match stream_kind {
+                StreamKind::Ref => Box::new(Stream::new(&mut server, &mut pipe)),
+                StreamKind::Owned => Box::new(StreamOwned::new(server, pipe)),
+            };
+
+            test_stream_read(read_kind, stream, data)
+        }
+    }
+}
+
+#[test]
+fn test_client_write_and_vectored_write_equivalence() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    do_handshake(&mut client, &mut server);
+
+    const N: usize = 1000;
+
+    let data_chunked: Vec<IoSlice> = std::iter::repeat(IoSlice::new(b"A"))
+        .take(N)
+        .collect();
+    let bytes_written_chunked = client
+        .writer()
+        .write_vectored(&data_chunked)
+        .unwrap();
+    let bytes_sent_chunked = transfer(&mut client, &mut server);
+    println!("write_vectored returned {bytes_written_chunked} and sent {bytes_sent_chunked}");
+
+    let data_contiguous = &[b'A'; N];
+    let bytes_written_contiguous = client
+        .writer()
+        .write(data_contiguous)
+        .unwrap();
+    let bytes_sent_contiguous = transfer(&mut client, &mut server);
+    println!("write returned {bytes_written_contiguous} and sent {bytes_sent_contiguous}");
+
+    assert_eq!(bytes_written_chunked, bytes_written_contiguous);
+    assert_eq!(bytes_sent_chunked, bytes_sent_contiguous);
+}
+
+struct FailsWrites {
+    errkind: io::ErrorKind,
+    after: usize,
+}
+
+impl io::Read for FailsWrites {
+    fn read(&mut self, _b: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl io::Write for FailsWrites {
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        if self.after > 0 {
+            self.after -= 1;
+            Ok(b.len())
+        } else {
+            Err(io::Error::new(self.errkind, "oops"))
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn stream_write_reports_underlying_io_error_before_plaintext_processed() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    do_handshake(&mut client, &mut server);
+
+    let mut pipe = FailsWrites {
+        errkind: io::ErrorKind::ConnectionAborted,
+        after: 0,
+    };
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let mut client_stream = Stream::new(&mut client, &mut pipe);
+    let rc = client_stream.write(b"world");
+    assert!(rc.is_err());
+    let err = rc.err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+}
+
+#[test]
+fn stream_write_swallows_underlying_io_error_after_plaintext_processed() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    do_handshake(&mut client, &mut server);
+
+    let mut pipe = FailsWrites {
+        errkind: io::ErrorKind::ConnectionAborted,
+        after: 1,
+    };
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let mut client_stream = Stream::new(&mut client, &mut pipe);
+    let rc = client_stream.write(b"world");
+    assert_eq!(format!("{:?}", rc), "Ok(5)");
+}
+
+fn make_disjoint_suite_configs() -> (ClientConfig, ServerConfig) {
+    let kt = KeyType::Rsa2048;
+    let client_provider = CryptoProvider {
+        cipher_suites: vec![cipher_suite::TLS13_CHACHA20_POLY1305_SHA256],
+        ..provider::default_provider()
+    };
+    let server_config = finish_server_config(
+        kt,
+        ServerConfig::builder_with_provider(client_provider.into())
+            .with_safe_default_protocol_versions()
+            .unwrap(),
+    );
+
+    let server_provider = CryptoProvider {
+        cipher_suites: vec![cipher_suite::TLS13_AES_256_GCM_SHA384],
+        ..provider::default_provider()
+    };
+    let client_config = finish_client_config(
+        kt,
+        ClientConfig::builder_with_provider(server_provider.into())
+            .with_safe_default_protocol_versions()
+            .unwrap(),
+    );
+
+    (client_config, server_config)
+}
+
+#[test]
+fn client_stream_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    {
+        let mut pipe = OtherSession::new_fails(&mut server);
+        let mut client_stream = Stream::new(&mut client, &mut pipe);
+        let rc = client_stream.write(b"hello");
+        assert!(rc.is_err());
+        assert_eq!(
+            format!("{:?}", rc),
+            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+        );
+        let rc = client_stream.write(b"hello");
+        assert!(rc.is_err());
+        assert_eq!(
+            format!("{:?}", rc),
+            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+        );
+    }
+}
+
+#[test]
+fn client_streamowned_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    let pipe = OtherSession::new_fails(&mut server);
+    let mut client_stream = StreamOwned::new(client, pipe);
+    let rc = client_stream.write(b"hello");
+    assert!(rc.is_err());
+    assert_eq!(
+        format!("{:?}", rc),
+        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+    );
+    let rc = client_stream.write(b"hello");
+    assert!(rc.is_err());
+    assert_eq!(
+        format!("{:?}", rc),
+        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+    );
+
+    let (_, _) = client_stream.into_parts();
+}
+
+#[test]
+fn server_stream_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    client
+        .writer()
+        .write_all(b"world")
+        .unwrap();
+
+    {
+        let mut pipe = OtherSession::new_fails(&mut client);
+        let mut server_stream = Stream::new(&mut server, &mut pipe);
+        let mut bytes = [0u8; 5];
+        let rc = server_stream.read(&mut bytes);
+        assert!(rc.is_err());
+        assert_eq!(
+            format!("{:?}", rc),
+            "Err(Custom { kind: InvalidData, error: PeerIncompatible(NoCipherSuitesInCommon) })"
+        );
+    }
+}
+
+#[test]
+fn server_streamowned_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, server) = make_pair_for_configs(client_config, server_config);
+
+    client
+        .writer()
+        .write_all(b"world")
+        .unwrap();
+
+    let pipe = OtherSession::new_fails(&mut client);
+    let mut server_stream = StreamOwned::new(server, pipe);
+    let mut bytes = [0u8; 5];
+    let rc = server_stream.read(&mut bytes);
+    assert!(rc.is_err());
+    assert_eq!(
+        format!("{:?}", rc),
+        "Err(Custom { kind: InvalidData, error: PeerIncompatible(NoCipherSuitesInCommon) })"
+    );
+}
+
+#[test]
+fn server_config_is_clone() {
+    let _ = make_server_config(KeyType::Rsa2048);
+}
+
+#[test]
+fn client_config_is_clone() {
+    let _ = make_client_config(KeyType::Rsa2048);
+}
+
+#[test]
+fn client_connection_is_debug() {
+    let (client, _) = make_pair(KeyType::Rsa2048);
+    println!("{:?}", client);
+}
+
+#[test]
+fn server_connection_is_debug() {
+    let (_, server) = make_pair(KeyType::Rsa2048);
+    println!("{:?}", server);
+}
+
+#[test]
+fn server_complete_io_for_handshake_ending_with_alert() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    assert!(server.is_handshaking());
+
+    let mut pipe = OtherSession::new_fails(&mut client);
+    let rc = server.complete_io(&mut pipe);
+    assert!(rc.is_err(), "server io failed due to handshake failure");
+    assert!(!server.wants_write(), "but server did send its alert");
+    assert_eq!(
+        format!("{:?}", pipe.last_error),
+        "Some(AlertReceived(HandshakeFailure))",
+        "which was received by client"
+    );
+}
+
+#[test]
+fn server_exposes_offered_sni() {
+    let kt = KeyType::Rsa2048;
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        let mut client = ClientConnection::new(
+            Arc::new(client_config),
+            server_name("second.testserver.com"),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
+
+        assert_eq!(None, server.server_name());
+        do_handshake(&mut client, &mut server);
+        assert_eq!(Some("second.testserver.com"), server.server_name());
+    }
+}
+
+#[test]
+fn server_exposes_offered_sni_smashed_to_lowercase() {
+    // webpki actually does this for us in its DnsName type
+    let kt = KeyType::Rsa2048;
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        let mut client = ClientConnection::new(
+            Arc::new(client_config),
+            server_name("SECOND.TESTServer.com"),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
+
+        assert_eq!(None, server.server_name());
+        do_handshake(&mut client, &mut server);
+        assert_eq!(Some("second.testserver.com"), server.server_name());
+    }
+}
+
+#[test]
+fn server_exposes_offered_sni_even_if_resolver_fails() {
+    let kt = KeyType::Rsa2048;
+    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("thisdoesNOTexist.com"))
+                .unwrap();
+
+        assert_eq!(None, server.server_name());
+        transfer(&mut client, &mut server);
+        assert_eq!(
+            server.process_new_packets(),
+            Err(Error::General(
+                "no server certificate chain resolved".to_string()
+            ))
+        );
+        assert_eq!(Some("thisdoesnotexist.com"), server.server_name());
+    }
+}
+
+#[test]
+fn sni_resolver_works() {
+    let kt = KeyType::Rsa2048;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+    resolver
+        .add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone()),
+        )
+        .unwrap();
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client1 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
+    let err = do_handshake_until_error(&mut client1, &mut server1);
+    assert_eq!(err, Ok(()));
+
+    let mut server2 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client2 = ClientConnection::new(
+        Arc::new(make_client_config(kt)),
+        server_name("notlocalhost"),
+    )
+    .unwrap();
+    let err = do_handshake_until_error(&mut client2, &mut server2);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Server(Error::General(
+            "no server certificate chain resolved".into()
+        )))
+    );
+}
+
+#[test]
+fn sni_resolver_rejects_wrong_names() {
+    let kt = KeyType::Rsa2048;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+
+    assert_eq!(
+        Ok(()),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+    assert_eq!(
+        Err(Error::InvalidCertificate(CertificateError::NotValidForName)),
+        resolver.add(
+            "not-localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+    assert_eq!(
+        Err(Error::General("Bad DNS name".into())),
+        resolver.add(
+            "not ascii 🦀",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+}
+
+#[test]
+fn sni_resolver_lower_cases_configured_names() {
+    let kt = KeyType::Rsa2048;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+
+    assert_eq!(
+        Ok(()),
+        resolver.add(
+            "LOCALHOST",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client1 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
+    let err = do_handshake_until_error(&mut client1, &mut server1);
+    assert_eq!(err, Ok(()));
+}
+
+#[test]
+fn sni_resolver_lower_cases_queried_names() {
+    // actually, the handshake parser does this, but the effect is the same.
+    let kt = KeyType::Rsa2048;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+
+    assert_eq!(
+        Ok(()),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client1 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("LOCALHOST")).unwrap();
+    let err = do_handshake_until_error(&mut client1, &mut server1);
+    assert_eq!(err, Ok(()));
+}
+
+#[test]
+fn sni_resolver_rejects_bad_certs() {
+    let kt = KeyType::Rsa2048;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+
+    assert_eq!(
+        Err(Error::NoCertificatesPresented),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(vec![], signing_key.clone())
+        )
+    );
+
+    let bad_chain = vec![CertificateDer::from(vec![0xa0])];
+    assert_eq!(
+        Err(Error::InvalidCertificate(CertificateError::BadEncoding)),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(bad_chain, signing_key.clone())
+        )
+    );
+}
+
+#[test]
+fn test_resolves_server_cert_using_trust_anchors() {
+    let certified_key = |kt: KeyType| {
+        let key = provider::default_provider()
+            .key_provider
+            .load_private_key(kt.get_key())
+            .unwrap();
+        sign::CertifiedKey::new(kt.get_chain(), key)
+    };
+
+    let mut resolver = rustls::server::ResolvesServerCertUsingTrustAnchors::new();
+    resolver.add(&[0x01, 0x02], certified_key(KeyType::EcdsaP256));
+    resolver.add(&[0x03], certified_key(KeyType::Ed25519));
+    resolver.set_default(certified_key(KeyType::Rsa2048));
+
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    for (kt, trust_anchor_ids) in [
+        (KeyType::Rsa2048, vec![]),
+        (KeyType::Rsa2048, vec![vec![0x09]]),
+        (KeyType::EcdsaP256, vec![vec![0x09], vec![0x01, 0x02]]),
+        (KeyType::Ed25519, vec![vec![0x03], vec![0x01, 0x02]]),
+    ] {
+        let mut client_config = make_client_config(kt);
+        client_config.trust_anchor_ids = trust_anchor_ids;
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(
+            client
+                .peer_certificates()
+                .map(|certs| certs.to_vec()),
+            Some(kt.get_chain())
+        );
+    }
+}
+
+#[test]
+fn test_keys_match() {
+    // Consistent: Both of these should have the same SPKI values
+    let expect_consistent =
+        sign::CertifiedKey::new(KeyType::Rsa2048.get_chain(), Arc::new(SigningKeySomeSpki));
+    assert!(matches!(expect_consistent.keys_match(), Ok(())));
+
+    // Inconsistent: These should not have the same SPKI values
+    let expect_inconsistent =
+        sign::CertifiedKey::new(KeyType::EcdsaP256.get_chain(), Arc::new(SigningKeySomeSpki));
+    assert!(matches!(
+        expect_inconsistent.keys_match(),
+        Err(Error::InconsistentKeys(InconsistentKeys::KeyMismatch))
+    ));
+
+    // Unknown: This signing key returns None for its SPKI, so we can't tell if the certified key is consistent
+    let expect_unknown =
+        sign::CertifiedKey::new(KeyType::Rsa2048.get_chain(), Arc::new(SigningKeyNoneSpki));
+    assert!(matches!(
+        expect_unknown.keys_match(),
+        Err(Error::InconsistentKeys(InconsistentKeys::Unknown))
+    ));
+}
+
+#[test]
+fn test_parsed_certificate_metadata() {
+    for (kt, algorithm) in [
+        (KeyType::Rsa2048, rustls::SignatureAlgorithm::RSA),
+        (KeyType::EcdsaP256, rustls::SignatureAlgorithm::ECDSA),
+        (KeyType::Ed25519, rustls::SignatureAlgorithm::ED25519),
+    ] {
+        let chain = kt.get_chain();
+        let end = ParsedCertificate::try_from(&chain[0]).unwrap();
+        let intermediate = ParsedCertificate::try_from(&chain[1]).unwrap();
+        let ca = ParsedCertificate::try_from(&chain[2]).unwrap();
+
+        assert_eq!(end.key_algorithm(), Some(algorithm));
+        assert_eq!(end.issuer().as_ref(), intermediate.subject().as_ref());
+        assert_eq!(ca.subject().as_ref(), kt.ca_distinguished_name());
+        assert!(!end.serial().is_empty());
+        assert_eq!(end.not_before().unwrap().as_secs(), 157_766_400);
+        assert_eq!(end.not_after().unwrap().as_secs(), 67_090_118_400);
+        assert_eq!(
+            end.dns_names().collect::<Vec<_>>(),
+            vec!["testserver.com", "second.testserver.com", "localhost"]
+        );
+        assert_eq!(
+            end.ip_addresses(),
+            vec![
+                "198.51.100.1"
+                    .parse::<std::net::IpAddr>()
+                    .unwrap(),
+                "2001:db8::1".parse().unwrap(),
+            ]
+        );
+        assert!(ca.ip_addresses().is_empty());
+    }
+}
+
+#[test]
+fn test_certified_key_new_checked() {
+    let key = || {
+        provider::default_provider()
+            .key_provider
+            .load_private_key(KeyType::EcdsaP256.get_key())
+            .unwrap()
+    };
+    sign::CertifiedKey::new_checked(KeyType::EcdsaP256.get_chain(), key()).unwrap();
+
+    assert_eq!(
+        sign::CertifiedKey::new_checked(KeyType::Rsa2048.get_chain(), key()).err(),
+        Some(Error::InconsistentKeys(InconsistentKeys::KeyMismatch))
+    );
+    assert_eq!(
+        sign::CertifiedKey::new_checked(Vec::new(), key()).err(),
+        Some(Error::NoCertificatesPresented)
+    );
+
+    // intermediate and root swapped
+    let mut chain = KeyType::EcdsaP256.get_chain();
+    chain.swap(1, 2);
+    assert_eq!(
+        sign::CertifiedKey::new_checked(chain.clone(), key()).err(),
+        Some(Error::InconsistentKeys(InconsistentKeys::ChainOutOfOrder(
+            0
+        )))
+    );
+
+    // with_single_cert checks the same things
+    assert_eq!(
+        server_config_builder()
+            .with_no_client_auth()
+            .with_single_cert(chain, KeyType::EcdsaP256.get_key())
+            .err(),
+        Some(Error::InconsistentKeys(InconsistentKeys::ChainOutOfOrder(
+            0
+        )))
+    );
+}
+
+#[test]
+fn test_certified_key_self_test() {
+    let algs = provider::default_provider().signature_verification_algorithms;
+    for kt in ALL_KEY_TYPES {
+        let key = provider::default_provider()
+            .key_provider
+            .load_private_key(kt.get_key())
+            .unwrap();
+        let ck = sign::CertifiedKey::new(kt.get_chain(), key);
+        let schemes = ck.self_test(&algs).unwrap();
+        assert!(!schemes.is_empty());
+        assert_eq!(schemes, ck.supported_schemes(&algs));
+    }
+
+    // the client key is a different key of the same type as the server's
+    let key = provider::default_provider()
+        .key_provider
+        .load_private_key(KeyType::EcdsaP256.get_client_key())
+        .unwrap();
+    let ck = sign::CertifiedKey::new(KeyType::EcdsaP256.get_chain(), key);
+    assert_eq!(
+        ck.self_test(&algs),
+        Err(Error::InconsistentKeys(InconsistentKeys::SignatureInvalid(
+            SignatureScheme::ECDSA_NISTP256_SHA256
+        )))
+    );
+}
+
+/// Represents a SigningKey that returns None for its SPKI via the default impl.
+#[derive(Debug)]
+struct SigningKeyNoneSpki;
+
+impl sign::SigningKey for SigningKeyNoneSpki {
+    fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        unimplemented!("Not meant to be called during tests")
+    }
+
+    fn algorithm(&self) -> rustls::SignatureAlgorithm {
+        unimplemented!("Not meant to be called during tests")
+    }
+}
+
+/// Represents a SigningKey that returns Some for its SPKI.
+#[derive(Debug)]
+struct SigningKeySomeSpki;
+
+impl sign::SigningKey for SigningKeySomeSpki {
+    fn public_key(&self) -> Option<pki_types::SubjectPublicKeyInfoDer> {
+        let chain = KeyType::Rsa2048.get_chain();
+        let cert = ParsedCertificate::try_from(chain.first().unwrap()).unwrap();
+        Some(
+            cert.subject_public_key_info()
+                .into_owned(),
+        )
+    }
+
+    fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        unimplemented!("Not meant to be called during tests")
+    }
+
+    fn algorithm(&self) -> rustls::SignatureAlgorithm {
+        unimplemented!("Not meant to be called during tests")
+    }
+}
+
+fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
+    let mut client_secret = [0u8; 64];
+    let mut server_secret = [0u8; 64];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    assert_eq!(
+        Err(Error::HandshakeNotComplete),
+        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
+    );
+    assert_eq!(
+        Err(Error::HandshakeNotComplete),
+        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+    );
+    do_handshake(&mut client, &mut server);
+
+    assert!(client
+        .export_keying_material(&mut client_secret, b"label", Some(b"context"))
+        .is_ok());
+    assert!(server
+        .export_keying_material(&mut server_secret, b"label", Some(b"context"))
+        .is_ok());
+    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+
+    let mut empty = vec![];
+    assert_eq!(
+        client
+            .export_keying_material(&mut empty, b"label", Some(b"context"))
+            .err(),
+        Some(Error::General(
+            "export_keying_material with zero-length output".into()
+        ))
+    );
+    assert_eq!(
+        server
+            .export_keying_material(&mut empty, b"label", Some(b"context"))
+            .err(),
+        Some(Error::General(
+            "export_keying_material with zero-length output".into()
+        ))
+    );
+
+    assert!(client
+        .export_keying_material(&mut client_secret, b"label", None)
+        .is_ok());
+    assert_ne!(client_secret.to_vec(), server_secret.to_vec());
+    assert!(server
+        .export_keying_material(&mut server_secret, b"label", None)
+        .is_ok());
+    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn test_tls12_exporter() {
+    for kt in ALL_KEY_TYPES {
+        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS12]);
+        let server_config = make_server_config(*kt);
+
+        do_exporter_test(client_config, server_config);
+    }
+}
+
+#[test]
+fn test_tls13_exporter() {
+    for kt in ALL_KEY_TYPES {
+        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS13]);
+        let server_config = make_server_config(*kt);
+
+        do_exporter_test(client_config, server_config);
+    }
+}
+
+#[test]
+fn test_tls13_exporter_maximum_output_length() {
     let client_config =
         make_client_config_with_versions(KeyType::EcdsaP256, &[&rustls::version::TLS13]);
     let server_config = make_server_config(KeyType::EcdsaP256);
 
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(
+        client.negotiated_cipher_suite(),
+        Some(find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384))
+    );
+
+    let mut maximum_allowed_output_client = [0u8; 255 * 48];
+    let mut maximum_allowed_output_server = [0u8; 255 * 48];
+    client
+        .export_keying_material(
+            &mut maximum_allowed_output_client,
+            b"label",
+            Some(b"context"),
+        )
+        .unwrap();
+    server
+        .export_keying_material(
+            &mut maximum_allowed_output_server,
+            b"label",
+            Some(b"context"),
+        )
+        .unwrap();
+
+    assert_eq!(maximum_allowed_output_client, maximum_allowed_output_server);
+
+    let mut too_long_output = [0u8; 255 * 48 + 1];
+    assert_eq!(
+        client
+            .export_keying_material(&mut too_long_output, b"label", Some(b"context"),)
+            .err(),
+        Some(Error::General("exporting too much".into()))
+    );
+    assert_eq!(
+        server
+            .export_keying_material(&mut too_long_output, b"label", Some(b"context"),)
+            .err(),
+        Some(Error::General("exporting too much".into()))
+    );
+}
+
+#[test]
+fn test_keying_material_exporter() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::EcdsaP256, &[version]);
+        let server_config = make_server_config(KeyType::EcdsaP256);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            client.keying_material_exporter().err(),
+            Some(Error::HandshakeNotComplete)
+        );
+        do_handshake(&mut client, &mut server);
+
+        let client_exporter = client
+            .keying_material_exporter()
+            .unwrap();
+        let server_exporter = server
+            .keying_material_exporter()
+            .unwrap();
+
+        // many exports, of any length, from one exporter
+        for (context, len) in [(&b"one"[..], 7), (&b"two"[..], 100), (&b"three"[..], 1000)] {
+            let exported = client_exporter
+                .export_to_vec(b"EXPORTER-test", Some(context), len)
+                .unwrap();
+            assert_eq!(exported.len(), len);
+            assert_eq!(
+                exported,
+                server_exporter
+                    .export_to_vec(b"EXPORTER-test", Some(context), len)
+                    .unwrap()
+            );
+
+            let mut legacy = vec![0u8; len];
+            client
+                .export_keying_material(&mut legacy, b"EXPORTER-test", Some(context))
+                .unwrap();
+            assert_eq!(exported, legacy);
+        }
+        assert_ne!(
+            client_exporter.export_to_vec(b"EXPORTER-test", Some(b"one"), 32),
+            client_exporter.export_to_vec(b"EXPORTER-test", Some(b"two"), 32)
+        );
+
+        let unusable = Err(Error::ApiMisuse(ApiMisuse::ExporterLabelUnusable));
+        assert_eq!(client_exporter.export_to_vec(b"", None, 32), unusable);
+        assert_eq!(
+            client_exporter.export_to_vec(b"key expansion", None, 32),
+            unusable
+        );
+        assert_eq!(
+            client_exporter.export_to_vec(b"EXPORTER-test", None, 0),
+            Err(Error::ApiMisuse(ApiMisuse::ExporterOutputEmpty))
+        );
+
+        match version.version {
+            ProtocolVersion::TLSv1_3 => {
+                assert_eq!(client_exporter.max_output_len(), Some(255 * 48));
+                assert_eq!(
+                    client_exporter.export_to_vec(b"EXPORTER-test", None, 255 * 48 + 1),
+                    Err(Error::ApiMisuse(ApiMisuse::ExporterOutputTooLong))
+                );
+                assert_eq!(
+                    client_exporter.export_to_vec(&[b'a'; 250], None, 32),
+                    Err(Error::ApiMisuse(ApiMisuse::ExporterLabelTooLong))
+                );
+            }
+            _ => {
+                assert_eq!(client_exporter.max_output_len(), None);
+                assert_eq!(
+                    client_exporter.export_to_vec(b"EXPORTER-test", Some(&[0u8; 0x10000]), 32),
+                    Err(Error::ApiMisuse(ApiMisuse::ExporterContextTooLong))
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_eap_tls_keys() {
+    let client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+    do_handshake(&mut client, &mut server);
+
+    let client_keys = client
+        .keying_material_exporter()
+        .unwrap()
+        .eap_tls_keys()
+        .unwrap();
+    let server_keys = server
+        .keying_material_exporter()
+        .unwrap()
+        .eap_tls_keys()
+        .unwrap();
+    assert_eq!(client_keys.msk(), server_keys.msk());
+    assert_eq!(client_keys.emsk(), server_keys.emsk());
+    assert_eq!(client_keys.session_id(), server_keys.session_id());
+    assert_ne!(client_keys.msk(), client_keys.emsk());
+    assert_eq!(client_keys.session_id()[0], 0x0d);
+
+    let mut key_material = [0u8; 128];
+    client
+        .export_keying_material(&mut key_material, b"EXPORTER_EAP_TLS_Key_Material", None)
+        .unwrap();
+    assert_eq!(&key_material[..64], client_keys.msk());
+    assert_eq!(&key_material[64..], client_keys.emsk());
+    let mut method_id = [0u8; 64];
+    client
+        .export_keying_material(&mut method_id, b"EXPORTER_EAP_TLS_Method-Id", None)
+        .unwrap();
+    assert_eq!(&method_id[..], client_keys.method_id());
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn test_eap_tls_keys_need_tls13() {
+    let client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS12]);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(
+        client
+            .keying_material_exporter()
+            .unwrap()
+            .eap_tls_keys()
+            .err(),
+        Some(Error::ApiMisuse(ApiMisuse::EapTlsKeysNeedTls13))
+    );
+}
+
+fn find_suite(suite: CipherSuite) -> SupportedCipherSuite {
+    for scs in provider::ALL_CIPHER_SUITES
+        .iter()
+        .copied()
+    {
+        if scs.suite() == suite {
+            return scs;
+        }
+    }
+
+    panic!("find_suite given unsupported suite");
+}
+
+#[test]
+fn test_cipher_suite_info() {
+    use rustls::crypto::hash::HashAlgorithm;
+
+    let info = find_suite(CipherSuite::TLS13_AES_128_GCM_SHA256).info();
+    assert_eq!(info.suite, CipherSuite::TLS13_AES_128_GCM_SHA256);
+    assert_eq!(info.version, ProtocolVersion::TLSv1_3);
+    assert_eq!(info.hash, HashAlgorithm::SHA256);
+    assert_eq!(info.hash_len, 32);
+    assert_eq!((info.key_len, info.nonce_len, info.tag_len), (16, 12, 16));
+    assert_eq!(info.key_exchange, None);
+    assert!(info.quic);
+    assert!(!info.quantum_resistant);
+
+    let info = find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384).info();
+    assert_eq!(info.hash, HashAlgorithm::SHA384);
+    assert_eq!(info.hash_len, 48);
+    assert_eq!(info.key_len, 32);
+    assert!(info.quantum_resistant);
+
+    #[cfg(feature = "tls12")]
+    {
+        let info = find_suite(CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384).info();
+        assert_eq!(info.version, ProtocolVersion::TLSv1_2);
+        assert_eq!((info.key_len, info.nonce_len, info.tag_len), (32, 12, 16));
+        assert_eq!(
+            info.key_exchange,
+            Some(rustls::crypto::KeyExchangeAlgorithm::ECDHE)
+        );
+        assert!(!info.quic);
+
+        let info = find_suite(CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256).info();
+        assert_eq!((info.key_len, info.nonce_len, info.tag_len), (32, 12, 16));
+        assert!(!info.quantum_resistant);
+    }
+
+    for suite in provider::ALL_CIPHER_SUITES {
+        let info = suite.info();
+        assert_eq!(info.suite, suite.suite());
+        assert_eq!(info.fips, suite.fips());
+        assert_eq!((info.nonce_len, info.tag_len), (12, 16));
+
+        // neither built-in provider is formally verified
+        assert!(!info.formally_verified);
+        assert_eq!(info.formally_verified, suite.formally_verified());
+    }
+
+    let info = find_suite(CipherSuite::TLS13_CHACHA20_POLY1305_SHA256).info();
+    assert_eq!(info.confidentiality_limit, u64::MAX);
+    assert_eq!(info.key_len, 32);
+    assert!(!info.quantum_resistant);
+}
+
+fn test_ciphersuites() -> Vec<(
+    &'static rustls::SupportedProtocolVersion,
+    KeyType,
+    CipherSuite,
+)> {
+    let mut v = vec![
+        (
+            &rustls::version::TLS13,
+            KeyType::Rsa2048,
+            CipherSuite::TLS13_AES_256_GCM_SHA384,
+        ),
+        (
+            &rustls::version::TLS13,
+            KeyType::Rsa2048,
+            CipherSuite::TLS13_AES_128_GCM_SHA256,
+        ),
+        #[cfg(feature = "tls12")]
+        (
+            &rustls::version::TLS12,
+            KeyType::EcdsaP384,
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+        ),
+        #[cfg(feature = "tls12")]
+        (
+            &rustls::version::TLS12,
+            KeyType::EcdsaP384,
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+        ),
+        #[cfg(feature = "tls12")]
+        (
+            &rustls::version::TLS12,
+            KeyType::Rsa2048,
+            CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+        ),
+        #[cfg(feature = "tls12")]
+        (
+            &rustls::version::TLS12,
+            KeyType::Rsa2048,
+            CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+        ),
+    ];
+
+    if !provider_is_fips() {
+        v.extend_from_slice(&[
+            (
+                &rustls::version::TLS13,
+                KeyType::Rsa2048,
+                CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+            ),
+            #[cfg(feature = "tls12")]
+            (
+                &rustls::version::TLS12,
+                KeyType::EcdsaP256,
+                CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+            ),
+            #[cfg(feature = "tls12")]
+            (
+                &rustls::version::TLS12,
+                KeyType::Rsa2048,
+                CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+            ),
+        ]);
+    }
+
+    v
+}
+
+#[test]
+fn negotiated_ciphersuite_default() {
+    let expected_kx = match provider_is_fips() {
+        true => NamedGroup::secp256r1,
+        false => NamedGroup::X25519,
+    };
+    for kt in ALL_KEY_TYPES {
+        do_suite_and_kx_test(
+            make_client_config(*kt),
+            make_server_config(*kt),
+            find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384),
+            expected_kx,
+            ProtocolVersion::TLSv1_3,
+        );
+    }
+}
+
+#[test]
+fn all_suites_covered() {
+    assert_eq!(
+        provider::DEFAULT_CIPHER_SUITES.len(),
+        test_ciphersuites().len()
+    );
+}
+
+#[test]
+fn negotiated_ciphersuite_client() {
+    let expected_kx = match provider_is_fips() {
+        true => NamedGroup::secp256r1,
+        false => NamedGroup::X25519,
+    };
+    for (version, kt, suite) in test_ciphersuites() {
+        let scs = find_suite(suite);
+        let client_config = finish_client_config(
+            kt,
+            ClientConfig::builder_with_provider(
+                CryptoProvider {
+                    cipher_suites: vec![scs],
+                    ..provider::default_provider()
+                }
+                .into(),
+            )
+            .with_protocol_versions(&[version])
+            .unwrap(),
+        );
+
+        do_suite_and_kx_test(
+            client_config,
+            make_server_config(kt),
+            scs,
+            expected_kx,
+            version.version,
+        );
+    }
+}
+
+#[test]
+fn negotiated_ciphersuite_server() {
+    let expected_kx = match provider_is_fips() {
+        true => NamedGroup::secp256r1,
+        false => NamedGroup::X25519,
+    };
+    for (version, kt, suite) in test_ciphersuites() {
+        let scs = find_suite(suite);
+        let server_config = finish_server_config(
+            kt,
+            ServerConfig::builder_with_provider(
+                CryptoProvider {
+                    cipher_suites: vec![scs],
+                    ..provider::default_provider()
+                }
+                .into(),
+            )
+            .with_protocol_versions(&[version])
+            .unwrap(),
+        );
+
+        do_suite_and_kx_test(
+            make_client_config(kt),
+            server_config,
+            scs,
+            expected_kx,
+            version.version,
+        );
+    }
+}
+
+#[test]
+fn negotiated_ciphersuite_server_ignoring_client_preference() {
+    let expected_kx = match provider_is_fips() {
+        true => NamedGroup::secp256r1,
+        false => NamedGroup::X25519,
+    };
+    for (version, kt, suite) in test_ciphersuites() {
+        let scs = find_suite(suite);
+        let scs_other = if scs.suite() == CipherSuite::TLS13_AES_256_GCM_SHA384 {
+            find_suite(CipherSuite::TLS13_AES_128_GCM_SHA256)
+        } else {
+            find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384)
+        };
+        let mut server_config = finish_server_config(
+            kt,
+            ServerConfig::builder_with_provider(
+                CryptoProvider {
+                    cipher_suites: vec![scs, scs_other],
+                    ..provider::default_provider()
+                }
+                .into(),
+            )
+            .with_protocol_versions(&[version])
+            .unwrap(),
+        );
+        server_config.ignore_client_order = true;
+
+        let client_config = finish_client_config(
+            kt,
+            ClientConfig::builder_with_provider(
+                CryptoProvider {
+                    cipher_suites: vec![scs_other, scs],
+                    ..provider::default_provider()
+                }
+                .into(),
+            )
+            .with_safe_default_protocol_versions()
+            .unwrap(),
+        );
+
+        do_suite_and_kx_test(
+            client_config,
+            server_config,
+            scs,
+            expected_kx,
+            version.version,
+        );
+    }
+}
+
+fn make_tls13_configs_with_suite_orders(
+    server_suites: &[CipherSuite],
+    client_suites: &[CipherSuite],
+) -> (ClientConfig, ServerConfig) {
+    let kt = KeyType::Rsa2048;
+    let provider_with = |suites: &[CipherSuite]| {
+        Arc::new(CryptoProvider {
+            cipher_suites: suites
+                .iter()
+                .map(|suite| find_suite(*suite))
+                .collect(),
+            ..provider::default_provider()
+        })
+    };
+
+    let server_config = finish_server_config(
+        kt,
+        ServerConfig::builder_with_provider(provider_with(server_suites))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap(),
+    );
+    let client_config = finish_client_config(
+        kt,
+        ClientConfig::builder_with_provider(provider_with(client_suites))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap(),
+    );
+    (client_config, server_config)
+}
+
+#[test]
+fn negotiated_ciphersuite_server_honouring_client_preference() {
+    let (client_config, server_config) = make_tls13_configs_with_suite_orders(
+        &[
+            CipherSuite::TLS13_AES_256_GCM_SHA384,
+            CipherSuite::TLS13_AES_128_GCM_SHA256,
+        ],
+        &[
+            CipherSuite::TLS13_AES_128_GCM_SHA256,
+            CipherSuite::TLS13_AES_256_GCM_SHA384,
+        ],
+    );
+    assert!(!server_config.ignore_client_order);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        server
+            .negotiated_cipher_suite()
+            .map(|scs| scs.suite()),
+        Some(CipherSuite::TLS13_AES_128_GCM_SHA256)
+    );
+}
+
+#[derive(Debug)]
+struct PreferSuite(CipherSuite);
+
+impl rustls::server::ScoresCipherSuites for PreferSuite {
+    fn score(&self, suite: SupportedCipherSuite) -> Option<u32> {
+        Some(match suite.suite() == self.0 {
+            true => 1,
+            false => 0,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct RejectSuites;
+
+impl rustls::server::ScoresCipherSuites for RejectSuites {
+    fn score(&self, _suite: SupportedCipherSuite) -> Option<u32> {
+        None
+    }
+}
+
+#[test]
+fn cipher_suite_scorer_overrides_preference_order() {
+    let suites = [
+        CipherSuite::TLS13_AES_256_GCM_SHA384,
+        CipherSuite::TLS13_AES_128_GCM_SHA256,
+    ];
+
+    for ignore_client_order in [false, true] {
+        let (client_config, mut server_config) =
+            make_tls13_configs_with_suite_orders(&suites, &suites);
+        server_config.ignore_client_order = ignore_client_order;
+        server_config.cipher_suite_scorer =
+            Some(Arc::new(PreferSuite(CipherSuite::TLS13_AES_128_GCM_SHA256)));
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            server
+                .negotiated_cipher_suite()
+                .map(|scs| scs.suite()),
+            Some(CipherSuite::TLS13_AES_128_GCM_SHA256)
+        );
+    }
+}
+
+#[test]
+fn cipher_suite_scorer_ties_follow_preference_order() {
+    let (client_config, mut server_config) = make_tls13_configs_with_suite_orders(
+        &[
+            CipherSuite::TLS13_AES_256_GCM_SHA384,
+            CipherSuite::TLS13_AES_128_GCM_SHA256,
+        ],
+        &[
+            CipherSuite::TLS13_AES_128_GCM_SHA256,
+            CipherSuite::TLS13_AES_256_GCM_SHA384,
+        ],
+    );
+    server_config.ignore_client_order = true;
+    // scores every suite equally
+    server_config.cipher_suite_scorer = Some(Arc::new(PreferSuite(
+        CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+    )));
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        server
+            .negotiated_cipher_suite()
+            .map(|scs| scs.suite()),
+        Some(CipherSuite::TLS13_AES_256_GCM_SHA384)
+    );
+}
+
+#[test]
+fn cipher_suite_scorer_can_reject_all_suites() {
+    let suites = [CipherSuite::TLS13_AES_128_GCM_SHA256];
+    let (client_config, mut server_config) = make_tls13_configs_with_suite_orders(&suites, &suites);
+    server_config.cipher_suite_scorer = Some(Arc::new(RejectSuites));
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::NoCipherSuitesInCommon
+        )))
+    );
+}
+
+#[derive(Debug, PartialEq)]
+struct KeyLogItem {
+    label: String,
+    client_random: Vec<u8>,
+    secret: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct KeyLogToVec {
+    label: &'static str,
+    items: Mutex<Vec<KeyLogItem>>,
+}
+
+impl KeyLogToVec {
+    fn new(who: &'static str) -> Self {
+        Self {
+            label: who,
+            items: Mutex::new(vec![]),
+        }
+    }
+
+    fn take(&self) -> Vec<KeyLogItem> {
+        std::mem::take(&mut self.items.lock().unwrap())
+    }
+}
+
+impl KeyLog for KeyLogToVec {
+    fn log(&self, label: &str, client: &[u8], secret: &[u8]) {
+        let value = KeyLogItem {
+            label: label.into(),
+            client_random: client.into(),
+            secret: secret.into(),
+        };
+
+        println!("key log {:?}: {:?}", self.label, value);
+
+        self.items.lock().unwrap().push(value);
+    }
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn key_log_for_tls12() {
+    let client_key_log = Arc::new(KeyLogToVec::new("client"));
+    let server_key_log = Arc::new(KeyLogToVec::new("server"));
+
+    let kt = KeyType::Rsa2048;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS12]);
+    client_config.key_log = client_key_log.clone();
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.key_log = server_key_log.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_full_log = client_key_log.take();
+    let server_full_log = server_key_log.take();
+    assert_eq!(client_full_log, server_full_log);
+    assert_eq!(1, client_full_log.len());
+    assert_eq!("CLIENT_RANDOM", client_full_log[0].label);
+
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_resume_log = client_key_log.take();
+    let server_resume_log = server_key_log.take();
+    assert_eq!(client_resume_log, server_resume_log);
+    assert_eq!(1, client_resume_log.len());
+    assert_eq!("CLIENT_RANDOM", client_resume_log[0].label);
+    assert_eq!(client_full_log[0].secret, client_resume_log[0].secret);
+}
+
+#[test]
+fn key_log_for_tls13() {
+    let client_key_log = Arc::new(KeyLogToVec::new("client"));
+    let server_key_log = Arc::new(KeyLogToVec::new("server"));
+
+    let kt = KeyType::Rsa2048;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.key_log = client_key_log.clone();
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.key_log = server_key_log.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_full_log = client_key_log.take();
+    let server_full_log = server_key_log.take();
+
+    assert_eq!(5, client_full_log.len());
+    assert_eq!("CLIENT_HANDSHAKE_TRAFFIC_SECRET", client_full_log[0].label);
+    assert_eq!("SERVER_HANDSHAKE_TRAFFIC_SECRET", client_full_log[1].label);
+    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_full_log[2].label);
+    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_full_log[3].label);
+    assert_eq!("EXPORTER_SECRET", client_full_log[4].label);
+
+    assert_eq!(client_full_log[0], server_full_log[0]);
+    assert_eq!(client_full_log[1], server_full_log[1]);
+    assert_eq!(client_full_log[2], server_full_log[2]);
+    assert_eq!(client_full_log[3], server_full_log[3]);
+    assert_eq!(client_full_log[4], server_full_log[4]);
+
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
     do_handshake(&mut client, &mut server);
 
+    let client_resume_log = client_key_log.take();
+    let server_resume_log = server_key_log.take();
+
+    assert_eq!(5, client_resume_log.len());
+    assert_eq!(
+        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+        client_resume_log[0].label
+    );
+    assert_eq!(
+        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+        client_resume_log[1].label
+    );
+    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_resume_log[2].label);
+    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_resume_log[3].label);
+    assert_eq!("EXPORTER_SECRET", client_resume_log[4].label);
+
+    assert_eq!(7, server_resume_log.len());
+    assert_eq!("CLIENT_EARLY_TRAFFIC_SECRET", server_resume_log[0].label);
+    assert_eq!("EARLY_EXPORTER_SECRET", server_resume_log[1].label);
     assert_eq!(
-        client.negotiated_cipher_suite(),
-        Some(find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384))
+        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+        server_resume_log[2].label
+    );
+    assert_eq!(
+        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+        server_resume_log[3].label
+    );
+    assert_eq!("CLIENT_TRAFFIC_SECRET_0", server_resume_log[4].label);
+    assert_eq!("SERVER_TRAFFIC_SECRET_0", server_resume_log[5].label);
+    assert_eq!("EXPORTER_SECRET", server_resume_log[6].label);
+
+    assert_eq!(client_resume_log[0], server_resume_log[2]);
+    assert_eq!(client_resume_log[1], server_resume_log[3]);
+    assert_eq!(client_resume_log[2], server_resume_log[4]);
+    assert_eq!(client_resume_log[3], server_resume_log[5]);
+    assert_eq!(client_resume_log[4], server_resume_log[6]);
+}
+
+#[test]
+fn filtered_key_log() {
+    let key_log = Arc::new(KeyLogToVec::new("client"));
+
+    let kt = KeyType::Rsa2048;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.key_log = Arc::new(rustls::FilteredKeyLog::new(
+        key_log.clone(),
+        &["CLIENT_TRAFFIC_SECRET_0", "SERVER_TRAFFIC_SECRET_0"],
+    ));
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+    do_handshake(&mut client, &mut server);
+
+    let labels = key_log
+        .take()
+        .into_iter()
+        .map(|item| item.label)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        labels,
+        ["CLIENT_TRAFFIC_SECRET_0", "SERVER_TRAFFIC_SECRET_0"]
+    );
+}
+
+#[test]
+fn in_memory_key_log_separates_connections() {
+    let key_log = Arc::new(rustls::InMemoryKeyLog::new());
+
+    let kt = KeyType::Rsa2048;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.key_log = key_log.clone();
+    client_config.resumption = Resumption::disabled();
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.key_log = key_log.clone();
+    let server_config = Arc::new(server_config);
+
+    let (mut client1, mut server1) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(key_log.secrets_for(&client1).is_empty());
+    let (mut client2, mut server2) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client1, &mut server1);
+    do_handshake(&mut client2, &mut server2);
+
+    // both peers log the same secrets under the same client random
+    let secrets = key_log.secrets_for(&client1);
+    let mut labels = secrets
+        .iter()
+        .map(|entry| entry.label.as_str())
+        .collect::<Vec<_>>();
+    labels.sort();
+    assert_eq!(
+        labels,
+        [
+            "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+            "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+            "CLIENT_TRAFFIC_SECRET_0",
+            "CLIENT_TRAFFIC_SECRET_0",
+            "EXPORTER_SECRET",
+            "EXPORTER_SECRET",
+            "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+            "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+            "SERVER_TRAFFIC_SECRET_0",
+            "SERVER_TRAFFIC_SECRET_0",
+        ]
+    );
+    assert_eq!(key_log.secrets_for(&server1), secrets);
+
+    let other = key_log.take_secrets_for(&client2);
+    assert_eq!(other.len(), 10);
+    assert!(other
+        .iter()
+        .all(|entry| !secrets.contains(entry)));
+    assert!(key_log.secrets_for(&server2).is_empty());
+    assert_eq!(key_log.take_secrets_for(&server1), secrets);
+
+    // TLS1.2 logs the master secret
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS12]);
+    client_config.key_log = key_log.clone();
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    do_handshake(&mut client, &mut server);
+    let secrets = key_log.take_secrets_for(&client);
+    assert_eq!(secrets.len(), 2);
+    assert_eq!(secrets[0].label, "CLIENT_RANDOM");
+    assert_eq!(secrets[0], secrets[1]);
+    assert!(secrets[0]
+        .to_nss_line()
+        .starts_with("CLIENT_RANDOM "));
+}
+
+#[test]
+fn vectored_write_for_server_appdata() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    do_handshake(&mut client, &mut server);
+
+    server
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    server
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        assert_eq!(84, wrlen);
+        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+    }
+    check_read(
+        &mut client.reader(),
+        b"0123456789012345678901234567890123456789",
+    );
+}
+
+#[test]
+fn vectored_write_for_client_appdata() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    do_handshake(&mut client, &mut server);
+
+    client
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    client
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert_eq!(84, wrlen);
+        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+    }
+    check_read(
+        &mut server.reader(),
+        b"0123456789012345678901234567890123456789",
+    );
+}
+
+#[test]
+fn vectored_write_for_server_handshake_with_half_rtt_data() {
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.send_half_rtt_data = true;
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_auth(KeyType::Rsa2048),
+        server_config,
+    );
+
+    server
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    server
+        .writer()
+        .write_all(b"0123456789")
+        .unwrap();
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        // don't assert exact sizes here, to avoid a brittle test
+        assert!(wrlen > 2500); // its pretty big (contains cert chain)
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert_eq!(pipe.writevs[0].len(), 8); // at least a server hello/ccs/cert/serverkx/0.5rtt data
+    }
+
+    client.process_new_packets().unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        // 4 tickets
+        assert_eq!(wrlen, 103 * 4);
+        assert_eq!(pipe.writevs, vec![vec![103, 103, 103, 103]]);
+    }
+
+    assert!(!server.is_handshaking());
+    assert!(!client.is_handshaking());
+    check_read(&mut client.reader(), b"012345678901234567890123456789");
+}
+
+fn check_half_rtt_does_not_work(server_config: ServerConfig) {
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_auth(KeyType::Rsa2048),
+        server_config,
     );
 
-    let mut maximum_allowed_output_client = [0u8; 255 * 48];
-    let mut maximum_allowed_output_server = [0u8; 255 * 48];
+    server
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    server
+        .writer()
+        .write_all(b"0123456789")
+        .unwrap();
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        // don't assert exact sizes here, to avoid a brittle test
+        assert!(wrlen > 2500); // its pretty big (contains cert chain)
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert!(pipe.writevs[0].len() >= 6); // at least a server hello/ccs/cert/serverkx data
+    }
+
+    // client second flight
+    client.process_new_packets().unwrap();
+    transfer(&mut client, &mut server);
+
+    // when client auth is enabled, we don't sent 0.5-rtt data, as we'd be sending
+    // it to an unauthenticated peer. so it happens here, in the server's second
+    // flight (42 and 32 are lengths of appdata sent above).
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        assert_eq!(wrlen, 486);
+        assert_eq!(pipe.writevs, vec![vec![103, 103, 103, 103, 42, 32]]);
+    }
+
+    assert!(!server.is_handshaking());
+    assert!(!client.is_handshaking());
+    check_read(&mut client.reader(), b"012345678901234567890123456789");
+}
+
+#[test]
+fn vectored_write_for_server_handshake_no_half_rtt_with_client_auth() {
+    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa2048);
+    server_config.send_half_rtt_data = true; // ask even though it will be ignored
+    check_half_rtt_does_not_work(server_config);
+}
+
+#[test]
+fn vectored_write_for_server_handshake_no_half_rtt_by_default() {
+    let server_config = make_server_config(KeyType::Rsa2048);
+    assert!(!server_config.send_half_rtt_data);
+    check_half_rtt_does_not_work(server_config);
+}
+
+#[test]
+fn vectored_write_for_client_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+
     client
-        .export_keying_material(
-            &mut maximum_allowed_output_client,
-            b"label",
-            Some(b"context"),
-        )
+        .writer()
+        .write_all(b"01234567890123456789")
         .unwrap();
-    server
-        .export_keying_material(
-            &mut maximum_allowed_output_server,
-            b"label",
-            Some(b"context"),
-        )
+    client
+        .writer()
+        .write_all(b"0123456789")
         .unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        // don't assert exact sizes here, to avoid a brittle test
+        assert!(wrlen > 200); // just the client hello
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert!(pipe.writevs[0].len() == 1); // only a client hello
+    }
 
-    assert_eq!(maximum_allowed_output_client, maximum_allowed_output_server);
-
-    let mut too_long_output = [0u8; 255 * 48 + 1];
-    assert_eq!(
-        client
-            .export_keying_material(&mut too_long_output, b"label", Some(b"context"),)
-            .err(),
-        Some(Error::General("exporting too much".into()))
-    );
-    assert_eq!(
-        server
-            .export_keying_material(&mut too_long_output, b"label", Some(b"context"),)
-            .err(),
-        Some(Error::General("exporting too much".into()))
-    );
-}
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
 
-fn find_suite(suite: CipherSuite) -> SupportedCipherSuite {
-    for scs in provider::ALL_CIPHER_SUITES
-        .iter()
-        .copied()
     {
-        if scs.suite() == suite {
-            return scs;
-        }
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert_eq!(wrlen, 154);
+        // CCS, finished, then two application datas
+        assert_eq!(pipe.writevs, vec![vec![6, 74, 42, 32]]);
     }
 
-    panic!("find_suite given unsupported suite");
+    assert!(!server.is_handshaking());
+    assert!(!client.is_handshaking());
+    check_read(&mut server.reader(), b"012345678901234567890123456789");
 }
 
-fn test_ciphersuites() -> Vec<(
-    &'static rustls::SupportedProtocolVersion,
-    KeyType,
-    CipherSuite,
-)> {
-    let mut v = vec![
-        (
-            &rustls::version::TLS13,
-            KeyType::Rsa2048,
-            CipherSuite::TLS13_AES_256_GCM_SHA384,
-        ),
-        (
-            &rustls::version::TLS13,
-            KeyType::Rsa2048,
-            CipherSuite::TLS13_AES_128_GCM_SHA256,
-        ),
-        #[cfg(feature = "tls12")]
-        (
-            &rustls::version::TLS12,
-            KeyType::EcdsaP384,
-            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
-        ),
-        #[cfg(feature = "tls12")]
-        (
-            &rustls::version::TLS12,
-            KeyType::EcdsaP384,
-            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
-        ),
-        #[cfg(feature = "tls12")]
-        (
-            &rustls::version::TLS12,
-            KeyType::Rsa2048,
-            CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
-        ),
-        #[cfg(feature = "tls12")]
-        (
-            &rustls::version::TLS12,
-            KeyType::Rsa2048,
-            CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
-        ),
-    ];
+#[test]
+fn vectored_write_with_slow_client() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
 
-    if !provider_is_fips() {
-        v.extend_from_slice(&[
-            (
-                &rustls::version::TLS13,
-                KeyType::Rsa2048,
-                CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
-            ),
-            #[cfg(feature = "tls12")]
-            (
-                &rustls::version::TLS12,
-                KeyType::EcdsaP256,
-                CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
-            ),
-            #[cfg(feature = "tls12")]
-            (
-                &rustls::version::TLS12,
-                KeyType::Rsa2048,
-                CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
-            ),
-        ]);
-    }
+    client.set_buffer_limit(Some(32));
 
-    v
-}
+    do_handshake(&mut client, &mut server);
+    server
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
 
-#[test]
-fn negotiated_ciphersuite_default() {
-    let expected_kx = match provider_is_fips() {
-        true => NamedGroup::secp256r1,
-        false => NamedGroup::X25519,
-    };
-    for kt in ALL_KEY_TYPES {
-        do_suite_and_kx_test(
-            make_client_config(*kt),
-            make_server_config(*kt),
-            find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384),
-            expected_kx,
-            ProtocolVersion::TLSv1_3,
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        pipe.short_writes = true;
+        let wrlen = server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap();
+        assert_eq!(42, wrlen);
+        assert_eq!(
+            pipe.writevs,
+            vec![vec![21], vec![10], vec![5], vec![3], vec![3]]
         );
     }
+    check_read(&mut client.reader(), b"01234567890123456789");
 }
 
-#[test]
-fn all_suites_covered() {
-    assert_eq!(
-        provider::DEFAULT_CIPHER_SUITES.len(),
-        test_ciphersuites().len()
-    );
+struct ServerStorage {
+    storage: Arc<dyn rustls::server::StoresServerSessions>,
+    put_count: AtomicUsize,
+    get_count: AtomicUsize,
+    take_count: AtomicUsize,
 }
 
-#[test]
-fn negotiated_ciphersuite_client() {
-    let expected_kx = match provider_is_fips() {
-        true => NamedGroup::secp256r1,
-        false => NamedGroup::X25519,
-    };
-    for (version, kt, suite) in test_ciphersuites() {
-        let scs = find_suite(suite);
-        let client_config = finish_client_config(
-            kt,
-            ClientConfig::builder_with_provider(
-                CryptoProvider {
-                    cipher_suites: vec![scs],
-                    ..provider::default_provider()
-                }
-                .into(),
-            )
-            .with_protocol_versions(&[version])
-            .unwrap(),
-        );
+impl ServerStorage {
+    fn new() -> Self {
+        Self {
+            storage: rustls::server::ServerSessionMemoryCache::new(1024),
+            put_count: AtomicUsize::new(0),
+            get_count: AtomicUsize::new(0),
+            take_count: AtomicUsize::new(0),
+        }
+    }
 
-        do_suite_and_kx_test(
-            client_config,
-            make_server_config(kt),
-            scs,
-            expected_kx,
-            version.version,
-        );
+    fn puts(&self) -> usize {
+        self.put_count.load(Ordering::SeqCst)
+    }
+    fn gets(&self) -> usize {
+        self.get_count.load(Ordering::SeqCst)
+    }
+    fn takes(&self) -> usize {
+        self.take_count.load(Ordering::SeqCst)
     }
 }
 
-#[test]
-fn negotiated_ciphersuite_server() {
-    let expected_kx = match provider_is_fips() {
-        true => NamedGroup::secp256r1,
-        false => NamedGroup::X25519,
-    };
-    for (version, kt, suite) in test_ciphersuites() {
-        let scs = find_suite(suite);
-        let server_config = finish_server_config(
-            kt,
-            ServerConfig::builder_with_provider(
-                CryptoProvider {
-                    cipher_suites: vec![scs],
-                    ..provider::default_provider()
-                }
-                .into(),
-            )
-            .with_protocol_versions(&[version])
-            .unwrap(),
-        );
+impl fmt::Debug for ServerStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "(put: {:?}, get: {:?}, take: {:?})",
+            self.put_count, self.get_count, self.take_count
+        )
+    }
+}
 
-        do_suite_and_kx_test(
-            make_client_config(kt),
-            server_config,
-            scs,
-            expected_kx,
-            version.version,
-        );
+impl rustls::server::StoresServerSessions for ServerStorage {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.put_count
+            .fetch_add(1, Ordering::SeqCst);
+        self.storage.put(key, value)
     }
-}
 
-#[test]
-fn negotiated_ciphersuite_server_ignoring_client_preference() {
-    let expected_kx = match provider_is_fips() {
-        true => NamedGroup::secp256r1,
-        false => NamedGroup::X25519,
-    };
-    for (version, kt, suite) in test_ciphersuites() {
-        let scs = find_suite(suite);
-        let scs_other = if scs.suite() == CipherSuite::TLS13_AES_256_GCM_SHA384 {
-            find_suite(CipherSuite::TLS13_AES_128_GCM_SHA256)
-        } else {
-            find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384)
-        };
-        let mut server_config = finish_server_config(
-            kt,
-            ServerConfig::builder_with_provider(
-                CryptoProvider {
-                    cipher_suites: vec![scs, scs_other],
-                    ..provider::default_provider()
-                }
-                .into(),
-            )
-            .with_protocol_versions(&[version])
-            .unwrap(),
-        );
-        server_config.ignore_client_order = true;
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get_count
+            .fetch_add(1, Ordering::SeqCst);
+        self.storage.get(key)
+    }
 
-        let client_config = finish_client_config(
-            kt,
-            ClientConfig::builder_with_provider(
-                CryptoProvider {
-                    cipher_suites: vec![scs_other, scs],
-                    ..provider::default_provider()
-                }
-                .into(),
-            )
-            .with_safe_default_protocol_versions()
-            .unwrap(),
-        );
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.take_count
+            .fetch_add(1, Ordering::SeqCst);
+        self.storage.take(key)
+    }
 
-        do_suite_and_kx_test(
-            client_config,
-            server_config,
-            scs,
-            expected_kx,
-            version.version,
-        );
+    fn can_cache(&self) -> bool {
+        true
     }
 }
 
-#[derive(Debug, PartialEq)]
-struct KeyLogItem {
-    label: String,
-    client_random: Vec<u8>,
-    secret: Vec<u8>,
+#[derive(Debug, Clone)]
+#[allow(dead_code)] // complete mock, but not 100% used in tests
+enum ClientStorageOp {
+    SetKxHint(ServerName<'static>, rustls::NamedGroup),
+    GetKxHint(ServerName<'static>, Option<rustls::NamedGroup>),
+    SetTls12Session(ServerName<'static>),
+    GetTls12Session(ServerName<'static>, bool),
+    RemoveTls12Session(ServerName<'static>),
+    InsertTls13Ticket(ServerName<'static>),
+    TakeTls13Ticket(ServerName<'static>, bool),
 }
 
-#[derive(Debug)]
-struct KeyLogToVec {
-    label: &'static str,
-    items: Mutex<Vec<KeyLogItem>>,
+struct ClientStorage {
+    storage: Arc<dyn rustls::client::ClientSessionStore>,
+    ops: Mutex<Vec<ClientStorageOp>>,
 }
 
-impl KeyLogToVec {
-    fn new(who: &'static str) -> Self {
+impl ClientStorage {
+    fn new() -> Self {
         Self {
-            label: who,
-            items: Mutex::new(vec![]),
+            storage: Arc::new(rustls::client::ClientSessionMemoryCache::new(1024)),
+            ops: Mutex::new(Vec::new()),
         }
     }
 
-    fn take(&self) -> Vec<KeyLogItem> {
-        std::mem::take(&mut self.items.lock().unwrap())
+    #[cfg(feature = "tls12")]
+    fn ops(&self) -> Vec<ClientStorageOp> {
+        self.ops.lock().unwrap().clone()
     }
-}
-
-impl KeyLog for KeyLogToVec {
-    fn log(&self, label: &str, client: &[u8], secret: &[u8]) {
-        let value = KeyLogItem {
-            label: label.into(),
-            client_random: client.into(),
-            secret: secret.into(),
-        };
 
-        println!("key log {:?}: {:?}", self.label, value);
+    #[cfg(feature = "tls12")]
+    fn ops_and_reset(&self) -> Vec<ClientStorageOp> {
+        std::mem::take(&mut self.ops.lock().unwrap())
+    }
+}
 
-        self.items.lock().unwrap().push(value);
+impl fmt::Debug for ClientStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(ops: {:?})", self.ops.lock().unwrap())
     }
 }
 
-#[cfg(feature = "tls12")]
-#[test]
-fn key_log_for_tls12() {
-    let client_key_log = Arc::new(KeyLogToVec::new("client"));
-    let server_key_log = Arc::new(KeyLogToVec::new("server"));
+impl rustls::client::ClientSessionStore for ClientStorage {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: rustls::NamedGroup) {
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::SetKxHint(server_name.clone(), group));
+        self.storage
+            .set_kx_hint(server_name, group)
+    }
 
-    let kt = KeyType::Rsa2048;
-    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS12]);
-    client_config.key_log = client_key_log.clone();
-    let client_config = Arc::new(client_config);
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<rustls::NamedGroup> {
+        let rc = self.storage.kx_hint(server_name);
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::GetKxHint(server_name.to_owned(), rc));
+        rc
+    }
 
-    let mut server_config = make_server_config(kt);
-    server_config.key_log = server_key_log.clone();
-    let server_config = Arc::new(server_config);
+    fn set_tls12_session(
+        &self,
+        server_name: ServerName<'static>,
+        value: rustls::client::Tls12ClientSessionValue,
+    ) {
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::SetTls12Session(server_name.clone()));
+        self.storage
+            .set_tls12_session(server_name, value)
+    }
 
-    // full handshake
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
+    fn tls12_session(
+        &self,
+        server_name: &ServerName<'_>,
+    ) -> Option<rustls::client::Tls12ClientSessionValue> {
+        let rc = self.storage.tls12_session(server_name);
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::GetTls12Session(
+                server_name.to_owned(),
+                rc.is_some(),
+            ));
+        rc
+    }
 
-    let client_full_log = client_key_log.take();
-    let server_full_log = server_key_log.take();
-    assert_eq!(client_full_log, server_full_log);
-    assert_eq!(1, client_full_log.len());
-    assert_eq!("CLIENT_RANDOM", client_full_log[0].label);
+    fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::RemoveTls12Session(server_name.clone()));
+        self.storage
+            .remove_tls12_session(server_name);
+    }
 
-    // resumed
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
+    fn insert_tls13_ticket(
+        &self,
+        server_name: ServerName<'static>,
+        value: rustls::client::Tls13ClientSessionValue,
+    ) {
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::InsertTls13Ticket(server_name.clone()));
+        self.storage
+            .insert_tls13_ticket(server_name, value);
+    }
 
-    let client_resume_log = client_key_log.take();
-    let server_resume_log = server_key_log.take();
-    assert_eq!(client_resume_log, server_resume_log);
-    assert_eq!(1, client_resume_log.len());
-    assert_eq!("CLIENT_RANDOM", client_resume_log[0].label);
-    assert_eq!(client_full_log[0].secret, client_resume_log[0].secret);
+    fn take_tls13_ticket(
+        &self,
+        server_name: &ServerName<'static>,
+    ) -> Option<rustls::client::Tls13ClientSessionValue> {
+        let rc = self
+            .storage
+            .take_tls13_ticket(server_name);
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::TakeTls13Ticket(
+                server_name.clone(),
+                rc.is_some(),
+            ));
+        rc
+    }
 }
 
 #[test]
-fn key_log_for_tls13() {
-    let client_key_log = Arc::new(KeyLogToVec::new("client"));
-    let server_key_log = Arc::new(KeyLogToVec::new("server"));
-
+fn tls13_stateful_resumption() {
     let kt = KeyType::Rsa2048;
-    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
-    client_config.key_log = client_key_log.clone();
+    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
     let client_config = Arc::new(client_config);
 
     let mut server_config = make_server_config(kt);
-    server_config.key_log = server_key_log.clone();
+    let storage = Arc::new(ServerStorage::new());
+    server_config.session_storage = storage.clone();
     let server_config = Arc::new(server_config);
 
-    // full handshake
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
-
-    let client_full_log = client_key_log.take();
-    let server_full_log = server_key_log.take();
-
-    assert_eq!(5, client_full_log.len());
-    assert_eq!("CLIENT_HANDSHAKE_TRAFFIC_SECRET", client_full_log[0].label);
-    assert_eq!("SERVER_HANDSHAKE_TRAFFIC_SECRET", client_full_log[1].label);
-    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_full_log[2].label);
-    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_full_log[3].label);
-    assert_eq!("EXPORTER_SECRET", client_full_log[4].label);
-
-    assert_eq!(client_full_log[0], server_full_log[0]);
-    assert_eq!(client_full_log[1], server_full_log[1]);
-    assert_eq!(client_full_log[2], server_full_log[2]);
-    assert_eq!(client_full_log[3], server_full_log[3]);
-    assert_eq!(client_full_log[4], server_full_log[4]);
-
-    // resumed
+    // full handshake
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
-
-    let client_resume_log = client_key_log.take();
-    let server_resume_log = server_key_log.take();
-
-    assert_eq!(5, client_resume_log.len());
-    assert_eq!(
-        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
-        client_resume_log[0].label
-    );
+    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(storage.puts(), 4);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
     assert_eq!(
-        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
-        client_resume_log[1].label
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
     );
-    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_resume_log[2].label);
-    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_resume_log[3].label);
-    assert_eq!("EXPORTER_SECRET", client_resume_log[4].label);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(client.resumption_kind(), None);
+    assert_eq!(server.resumption_kind(), None);
 
-    assert_eq!(6, server_resume_log.len());
-    assert_eq!("CLIENT_EARLY_TRAFFIC_SECRET", server_resume_log[0].label);
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
+    assert!(resume_c2s > full_c2s);
+    assert!(resume_s2c < full_s2c);
+    assert_eq!(storage.puts(), 8);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 1);
     assert_eq!(
-        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
-        server_resume_log[1].label
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
     );
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(client.resumption_kind(), Some(ResumptionKind::Ticket));
+    assert_eq!(server.resumption_kind(), Some(ResumptionKind::Ticket));
+
+    // resumed again
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(resume_s2c, resume2_s2c);
+    assert_eq!(resume_c2s, resume2_c2s);
+    assert_eq!(storage.puts(), 12);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 2);
     assert_eq!(
-        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
-        server_resume_log[2].label
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
     );
-    assert_eq!("CLIENT_TRAFFIC_SECRET_0", server_resume_log[3].label);
-    assert_eq!("SERVER_TRAFFIC_SECRET_0", server_resume_log[4].label);
-    assert_eq!("EXPORTER_SECRET", server_resume_log[5].label);
-
-    assert_eq!(client_resume_log[0], server_resume_log[1]);
-    assert_eq!(client_resume_log[1], server_resume_log[2]);
-    assert_eq!(client_resume_log[2], server_resume_log[3]);
-    assert_eq!(client_resume_log[3], server_resume_log[4]);
-    assert_eq!(client_resume_log[4], server_resume_log[5]);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(client.resumption_kind(), Some(ResumptionKind::Ticket));
+    assert_eq!(server.resumption_kind(), Some(ResumptionKind::Ticket));
 }
 
 #[test]
-fn vectored_write_for_server_appdata() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
-    do_handshake(&mut client, &mut server);
-
-    server
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    server
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        assert_eq!(84, wrlen);
-        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-    }
-    check_read(
-        &mut client.reader(),
-        b"0123456789012345678901234567890123456789",
-    );
-}
+fn tls13_stateless_resumption() {
+    let kt = KeyType::Rsa2048;
+    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    let client_config = Arc::new(client_config);
 
-#[test]
-fn vectored_write_for_client_appdata() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
-    do_handshake(&mut client, &mut server);
+    let mut server_config = make_server_config(kt);
+    server_config.ticketer = provider::Ticketer::new().unwrap();
+    let storage = Arc::new(ServerStorage::new());
+    server_config.session_storage = storage.clone();
+    let server_config = Arc::new(server_config);
 
-    client
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    client
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        assert_eq!(84, wrlen);
-        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-    }
-    check_read(
-        &mut server.reader(),
-        b"0123456789012345678901234567890123456789",
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
     );
-}
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(client.resumption_kind(), None);
+    assert_eq!(server.resumption_kind(), None);
 
-#[test]
-fn vectored_write_for_server_handshake_with_half_rtt_data() {
-    let mut server_config = make_server_config(KeyType::Rsa2048);
-    server_config.send_half_rtt_data = true;
-    let (mut client, mut server) = make_pair_for_configs(
-        make_client_config_with_auth(KeyType::Rsa2048),
-        server_config,
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
+    assert!(resume_c2s > full_c2s);
+    assert!(resume_s2c < full_s2c);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
     );
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(client.resumption_kind(), Some(ResumptionKind::Ticket));
+    assert_eq!(server.resumption_kind(), Some(ResumptionKind::Ticket));
 
-    server
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    server
-        .writer()
-        .write_all(b"0123456789")
-        .unwrap();
-
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        // don't assert exact sizes here, to avoid a brittle test
-        assert!(wrlen > 2500); // its pretty big (contains cert chain)
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert_eq!(pipe.writevs[0].len(), 8); // at least a server hello/ccs/cert/serverkx/0.5rtt data
-    }
-
-    client.process_new_packets().unwrap();
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        // 4 tickets
-        assert_eq!(wrlen, 103 * 4);
-        assert_eq!(pipe.writevs, vec![vec![103, 103, 103, 103]]);
-    }
-
-    assert!(!server.is_handshaking());
-    assert!(!client.is_handshaking());
-    check_read(&mut client.reader(), b"012345678901234567890123456789");
-}
-
-fn check_half_rtt_does_not_work(server_config: ServerConfig) {
-    let (mut client, mut server) = make_pair_for_configs(
-        make_client_config_with_auth(KeyType::Rsa2048),
-        server_config,
+    // resumed again
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(resume_s2c, resume2_s2c);
+    assert_eq!(resume_c2s, resume2_c2s);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
     );
-
-    server
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    server
-        .writer()
-        .write_all(b"0123456789")
-        .unwrap();
-
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        // don't assert exact sizes here, to avoid a brittle test
-        assert!(wrlen > 2500); // its pretty big (contains cert chain)
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert!(pipe.writevs[0].len() >= 6); // at least a server hello/ccs/cert/serverkx data
-    }
-
-    // client second flight
-    client.process_new_packets().unwrap();
-    transfer(&mut client, &mut server);
-
-    // when client auth is enabled, we don't sent 0.5-rtt data, as we'd be sending
-    // it to an unauthenticated peer. so it happens here, in the server's second
-    // flight (42 and 32 are lengths of appdata sent above).
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        assert_eq!(wrlen, 486);
-        assert_eq!(pipe.writevs, vec![vec![103, 103, 103, 103, 42, 32]]);
-    }
-
-    assert!(!server.is_handshaking());
-    assert!(!client.is_handshaking());
-    check_read(&mut client.reader(), b"012345678901234567890123456789");
-}
-
-#[test]
-fn vectored_write_for_server_handshake_no_half_rtt_with_client_auth() {
-    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa2048);
-    server_config.send_half_rtt_data = true; // ask even though it will be ignored
-    check_half_rtt_does_not_work(server_config);
-}
-
-#[test]
-fn vectored_write_for_server_handshake_no_half_rtt_by_default() {
-    let server_config = make_server_config(KeyType::Rsa2048);
-    assert!(!server_config.send_half_rtt_data);
-    check_half_rtt_does_not_work(server_config);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(client.resumption_kind(), Some(ResumptionKind::Ticket));
+    assert_eq!(server.resumption_kind(), Some(ResumptionKind::Ticket));
 }
 
+#[cfg(feature = "tls12")]
 #[test]
-fn vectored_write_for_client_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+fn tls12_session_id_resumption_without_session_tickets() {
+    let kt = KeyType::Rsa2048;
+    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS12]);
+    let client_config = Arc::new(client_config);
 
-    client
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    client
-        .writer()
-        .write_all(b"0123456789")
-        .unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        // don't assert exact sizes here, to avoid a brittle test
-        assert!(wrlen > 200); // just the client hello
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert!(pipe.writevs[0].len() == 1); // only a client hello
-    }
+    let storage = Arc::new(ServerStorage::new());
+    let server_config = make_server_config(kt)
+        .with_session_storage(storage.clone())
+        .with_session_tickets(provider::Ticketer::new().unwrap());
 
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+    // the client is issued a ticket
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(server_config.clone()));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
 
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        assert_eq!(wrlen, 154);
-        // CCS, finished, then two application datas
-        assert_eq!(pipe.writevs, vec![vec![6, 74, 42, 32]]);
-    }
+    // its ticket is ignored once tickets are disabled, but it gets a session ID
+    let server_config = Arc::new(server_config.without_session_tickets());
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(storage.puts(), 2);
 
-    assert!(!server.is_handshaking());
-    assert!(!client.is_handshaking());
-    check_read(&mut server.reader(), b"012345678901234567890123456789");
+    // which it resumes with
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(server.resumption_kind(), Some(ResumptionKind::SessionId));
 }
 
 #[test]
-fn vectored_write_with_slow_client() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+fn tls13_ticket_refresh_due() {
+    let kt = KeyType::Rsa2048;
+    let clock = ManualTime::new(UnixTime::now().as_secs());
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.time_provider = clock.clone();
+    client_config.resumption =
+        Resumption::default().ticket_refresh_window(Duration::from_secs(60 * 60));
+    let client_config = Arc::new(client_config);
 
-    client.set_buffer_limit(Some(32));
+    let mut server_config = make_server_config(kt);
+    server_config.send_tls13_tickets = 1;
+    server_config.time_provider = clock.clone();
+    let server_config = Arc::new(server_config);
 
+    // full handshake: no ticket offered
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
     do_handshake(&mut client, &mut server);
-    server
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        pipe.short_writes = true;
-        let wrlen = server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap();
-        assert_eq!(42, wrlen);
-        assert_eq!(
-            pipe.writevs,
-            vec![vec![21], vec![10], vec![5], vec![3], vec![3]]
-        );
-    }
-    check_read(&mut client.reader(), b"01234567890123456789");
-}
-
-struct ServerStorage {
-    storage: Arc<dyn rustls::server::StoresServerSessions>,
-    put_count: AtomicUsize,
-    get_count: AtomicUsize,
-    take_count: AtomicUsize,
-}
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    assert!(!client.ticket_refresh_due());
 
-impl ServerStorage {
-    fn new() -> Self {
-        Self {
-            storage: rustls::server::ServerSessionMemoryCache::new(1024),
-            put_count: AtomicUsize::new(0),
-            get_count: AtomicUsize::new(0),
-            take_count: AtomicUsize::new(0),
-        }
-    }
+    // ticket well within its lifetime
+    clock.advance(60 * 60);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(!client.ticket_refresh_due());
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
 
-    fn puts(&self) -> usize {
-        self.put_count.load(Ordering::SeqCst)
-    }
-    fn gets(&self) -> usize {
-        self.get_count.load(Ordering::SeqCst)
-    }
-    fn takes(&self) -> usize {
-        self.take_count.load(Ordering::SeqCst)
-    }
-}
+    // ticket close to expiry: refreshed by the server's new ticket
+    clock.advance(23 * 60 * 60 + 30 * 60);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(client.ticket_refresh_due());
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    assert!(!client.ticket_refresh_due());
 
-impl fmt::Debug for ServerStorage {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "(put: {:?}, get: {:?}, take: {:?})",
-            self.put_count, self.get_count, self.take_count
-        )
-    }
+    // ticket close to expiry, and the server does not replace it
+    clock.advance(23 * 60 * 60 + 30 * 60);
+    let mut server_config = make_server_config(kt);
+    server_config.send_tls13_tickets = 0;
+    server_config.time_provider = clock.clone();
+    let server_config = Arc::new(server_config);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(client.ticket_refresh_due());
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    assert!(client.ticket_refresh_due());
 }
 
-impl rustls::server::StoresServerSessions for ServerStorage {
-    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
-        self.put_count
-            .fetch_add(1, Ordering::SeqCst);
-        self.storage.put(key, value)
-    }
-
-    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.get_count
-            .fetch_add(1, Ordering::SeqCst);
-        self.storage.get(key)
-    }
+#[test]
+fn resumption_with_encrypted_session_caches() {
+    let kt = KeyType::Rsa2048;
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(kt, &[version]);
+        client_config.resumption = Resumption::store(Arc::new(
+            rustls::client::ClientSessionMemoryCache::new(32)
+                .with_encryption(provider::Ticketer::new().unwrap()),
+        ));
+        let client_config = Arc::new(client_config);
 
-    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.take_count
-            .fetch_add(1, Ordering::SeqCst);
-        self.storage.take(key)
-    }
+        let mut server_config = make_server_config(kt);
+        server_config.session_storage = rustls::server::ServerSessionMemoryCache::new_encrypted(
+            32,
+            provider::Ticketer::new().unwrap(),
+        );
+        let server_config = Arc::new(server_config);
 
-    fn can_cache(&self) -> bool {
-        true
+        for expected in [HandshakeKind::Full, HandshakeKind::Resumed] {
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &server_config);
+            do_handshake(&mut client, &mut server);
+            assert_eq!(client.handshake_kind(), Some(expected));
+            assert_eq!(server.handshake_kind(), Some(expected));
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-#[allow(dead_code)] // complete mock, but not 100% used in tests
-enum ClientStorageOp {
-    SetKxHint(ServerName<'static>, rustls::NamedGroup),
-    GetKxHint(ServerName<'static>, Option<rustls::NamedGroup>),
-    SetTls12Session(ServerName<'static>),
-    GetTls12Session(ServerName<'static>, bool),
-    RemoveTls12Session(ServerName<'static>),
-    InsertTls13Ticket(ServerName<'static>),
-    TakeTls13Ticket(ServerName<'static>, bool),
-}
-
-struct ClientStorage {
-    storage: Arc<dyn rustls::client::ClientSessionStore>,
-    ops: Mutex<Vec<ClientStorageOp>>,
-}
-
-impl ClientStorage {
-    fn new() -> Self {
-        Self {
-            storage: Arc::new(rustls::client::ClientSessionMemoryCache::new(1024)),
-            ops: Mutex::new(Vec::new()),
-        }
-    }
+#[test]
+fn client_resumption_fresh_first_connection() {
+    let kt = KeyType::Rsa2048;
+    let storage = Arc::new(rustls::client::ClientSessionMemoryCache::new(32));
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.resumption = Resumption::store(storage.clone()).fresh_first_connection();
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config(kt));
 
-    #[cfg(feature = "tls12")]
-    fn ops(&self) -> Vec<ClientStorageOp> {
-        self.ops.lock().unwrap().clone()
-    }
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(
+        client.resumption_decision(),
+        ResumptionDecision::FirstConnection
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
 
-    #[cfg(feature = "tls12")]
-    fn ops_and_reset(&self) -> Vec<ClientStorageOp> {
-        std::mem::take(&mut self.ops.lock().unwrap())
-    }
-}
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(client.resumption_decision(), ResumptionDecision::Offered);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
 
-impl fmt::Debug for ClientStorage {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "(ops: {:?})", self.ops.lock().unwrap())
-    }
+    // like a new process using the same stored tickets
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.resumption = Resumption::store(storage).fresh_first_connection();
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    assert_eq!(
+        client.resumption_decision(),
+        ResumptionDecision::FirstConnection
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
 }
 
-impl rustls::client::ClientSessionStore for ClientStorage {
-    fn set_kx_hint(&self, server_name: ServerName<'static>, group: rustls::NamedGroup) {
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::SetKxHint(server_name.clone(), group));
-        self.storage
-            .set_kx_hint(server_name, group)
-    }
-
-    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<rustls::NamedGroup> {
-        let rc = self.storage.kx_hint(server_name);
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::GetKxHint(server_name.to_owned(), rc));
-        rc
-    }
-
-    fn set_tls12_session(
-        &self,
-        server_name: ServerName<'static>,
-        value: rustls::client::Tls12ClientSessionValue,
-    ) {
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::SetTls12Session(server_name.clone()));
-        self.storage
-            .set_tls12_session(server_name, value)
-    }
+#[cfg(feature = "tls12")]
+#[test]
+fn client_resumption_single_use_tls12_sessions() {
+    let kt = KeyType::Rsa2048;
+    let storage = Arc::new(ClientStorage::new());
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS12]);
+    client_config.resumption = Resumption::store(storage.clone()).single_use_sessions();
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config(kt));
 
-    fn tls12_session(
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(client.resumption_decision(), ResumptionDecision::NoSession);
+    do_handshake(&mut client, &mut server);
+
+    let ops_before = storage.ops().len();
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(client.resumption_decision(), ResumptionDecision::Offered);
+    assert!(matches!(
+        storage.ops()[ops_before..],
+        [
+            ClientStorageOp::TakeTls13Ticket(_, false),
+            ClientStorageOp::GetTls12Session(_, true),
+            ClientStorageOp::RemoveTls12Session(_),
+            ..
+        ]
+    ));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+}
+
+#[derive(Debug)]
+struct TicketFilter {
+    max_lifetime: Duration,
+    seen: AtomicUsize,
+}
+
+impl rustls::client::FiltersTickets for TicketFilter {
+    fn accept_ticket(
         &self,
         server_name: &ServerName<'_>,
-    ) -> Option<rustls::client::Tls12ClientSessionValue> {
-        let rc = self.storage.tls12_session(server_name);
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::GetTls12Session(
-                server_name.to_owned(),
-                rc.is_some(),
-            ));
-        rc
+        ticket: &rustls::client::ReceivedTicket<'_>,
+    ) -> bool {
+        assert_eq!(server_name, &ServerName::try_from("localhost").unwrap());
+        assert!(ticket.ticket_len > 0);
+        assert_eq!(
+            ticket.nonce.is_some(),
+            ticket.version == ProtocolVersion::TLSv1_3
+        );
+        self.seen.fetch_add(1, Ordering::SeqCst);
+        ticket.lifetime <= self.max_lifetime
     }
+}
 
-    fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::RemoveTls12Session(server_name.clone()));
-        self.storage
-            .remove_tls12_session(server_name);
-    }
+#[test]
+fn client_resumption_ticket_filter() {
+    for version in rustls::ALL_VERSIONS {
+        for (max_lifetime, expected) in [
+            (Duration::from_secs(86_400 * 7), HandshakeKind::Resumed),
+            (Duration::from_secs(60), HandshakeKind::Full),
+        ] {
+            let kt = KeyType::Rsa2048;
+            let filter = Arc::new(TicketFilter {
+                max_lifetime,
+                seen: AtomicUsize::new(0),
+            });
+            let mut client_config = make_client_config_with_versions(kt, &[version]);
+            client_config.resumption = Resumption::default().ticket_filter(filter.clone());
+            let client_config = Arc::new(client_config);
 
-    fn insert_tls13_ticket(
-        &self,
-        server_name: ServerName<'static>,
-        value: rustls::client::Tls13ClientSessionValue,
-    ) {
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::InsertTls13Ticket(server_name.clone()));
-        self.storage
-            .insert_tls13_ticket(server_name, value);
-    }
+            let mut server_config = make_server_config(kt);
+            server_config.ticketer = provider::Ticketer::new().unwrap();
+            server_config.session_storage = Arc::new(rustls::server::NoServerSessionStorage {});
+            let server_config = Arc::new(server_config);
 
-    fn take_tls13_ticket(
-        &self,
-        server_name: &ServerName<'static>,
-    ) -> Option<rustls::client::Tls13ClientSessionValue> {
-        let rc = self
-            .storage
-            .take_tls13_ticket(server_name);
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::TakeTls13Ticket(
-                server_name.clone(),
-                rc.is_some(),
-            ));
-        rc
+            let log = Arc::new(EventLog::default());
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &server_config);
+            client.set_connection_observer(log.clone());
+            do_handshake(&mut client, &mut server);
+            assert!(filter.seen.load(Ordering::SeqCst) > 0);
+
+            // only stored tickets are reported
+            let reported = log
+                .take()
+                .contains(&"ticket_received".to_string());
+            assert_eq!(reported, expected == HandshakeKind::Resumed);
+
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &server_config);
+            do_handshake(&mut client, &mut server);
+            assert_eq!(client.handshake_kind(), Some(expected));
+        }
     }
 }
 
 #[test]
-fn tls13_stateful_resumption() {
+fn client_connection_without_resumption() {
     let kt = KeyType::Rsa2048;
-    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    let storage = Arc::new(ClientStorage::new());
+    let mut client_config = make_client_config(kt);
+    client_config.resumption = Resumption::store(storage.clone());
     let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config(kt));
 
-    let mut server_config = make_server_config(kt);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(!storage.ops_and_reset().is_empty());
+
+    let mut client = ClientConnection::new_with_options(
+        client_config.clone(),
+        server_name("localhost"),
+        rustls::client::ClientConnectionOptions::new().without_resumption(),
+    )
+    .unwrap();
+    let mut server = ServerConnection::new(server_config.clone()).unwrap();
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    assert!(storage.ops_and_reset().is_empty());
+
+    // the session from the first connection is still available
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+}
+
+#[test]
+fn server_connection_without_resumption() {
+    let kt = KeyType::Rsa2048;
+    let client_config = Arc::new(make_client_config(kt));
     let storage = Arc::new(ServerStorage::new());
+    let mut server_config = make_server_config(kt);
     server_config.session_storage = storage.clone();
     let server_config = Arc::new(server_config);
 
-    // full handshake
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(storage.puts(), 4);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
+    do_handshake(&mut client, &mut server);
+    let puts = storage.puts();
+    assert!(puts > 0);
+
+    let mut client =
+        ClientConnection::new(client_config.clone(), server_name("localhost")).unwrap();
+    let mut server = ServerConnection::new_without_resumption(server_config.clone()).unwrap();
+    do_handshake(&mut client, &mut server);
     assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
     assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(storage.puts(), puts);
+    assert_eq!(storage.takes(), 0);
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_session_id_resumption() {
+    let kt = KeyType::Rsa2048;
+    let client_config = Arc::new(make_client_config_with_versions(
+        kt,
+        &[&rustls::version::TLS12],
+    ));
+    let server_config = Arc::new(make_server_config(kt));
 
-    // resumed
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
-    assert!(resume_c2s > full_c2s);
-    assert!(resume_s2c < full_s2c);
-    assert_eq!(storage.puts(), 8);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 1);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(client.resumption_kind(), None);
+    assert_eq!(server.resumption_kind(), None);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
     assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
     assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(client.resumption_kind(), Some(ResumptionKind::SessionId));
+    assert_eq!(server.resumption_kind(), Some(ResumptionKind::SessionId));
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_ticket_resumption() {
+    let kt = KeyType::Rsa2048;
+    let client_config = Arc::new(make_client_config_with_versions(
+        kt,
+        &[&rustls::version::TLS12],
+    ));
+    let mut server_config = make_server_config(kt);
+    server_config.ticketer = provider::Ticketer::new().unwrap();
+    server_config.session_storage = Arc::new(rustls::server::NoServerSessionStorage {});
+    let server_config = Arc::new(server_config);
 
-    // resumed again
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(resume_s2c, resume2_s2c);
-    assert_eq!(resume_c2s, resume2_c2s);
-    assert_eq!(storage.puts(), 12);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 2);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
     assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
     assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert_eq!(client.resumption_kind(), Some(ResumptionKind::Ticket));
+    assert_eq!(server.resumption_kind(), Some(ResumptionKind::Ticket));
+}
+
+#[derive(Debug, Default)]
+struct TicketLog {
+    issued: Mutex<Vec<(usize, Option<Duration>, ProtocolVersion)>>,
+    redeemed: Mutex<Vec<(usize, Option<Duration>, ProtocolVersion)>>,
+}
+
+impl rustls::server::ObservesTickets for TicketLog {
+    fn ticket_issued(&self, ticket: &rustls::server::TicketInfo<'_>) {
+        assert!(ticket.stateless);
+        assert_eq!(ticket.server_name, Some("localhost"));
+        self.issued
+            .lock()
+            .unwrap()
+            .push((ticket.ticket_len, ticket.lifetime, ticket.version));
+    }
+
+    fn ticket_redeemed(&self, ticket: &rustls::server::TicketInfo<'_>) {
+        assert!(ticket.stateless);
+        self.redeemed
+            .lock()
+            .unwrap()
+            .push((ticket.ticket_len, ticket.lifetime, ticket.version));
+    }
 }
 
 #[test]
-fn tls13_stateless_resumption() {
-    let kt = KeyType::Rsa2048;
-    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
-    let client_config = Arc::new(client_config);
+fn server_ticket_observer() {
+    for version in rustls::ALL_VERSIONS {
+        let kt = KeyType::Rsa2048;
+        let client_config = Arc::new(make_client_config_with_versions(kt, &[version]));
+        let log = Arc::new(TicketLog::default());
+        let mut server_config = make_server_config(kt);
+        server_config.ticketer = provider::Ticketer::new().unwrap();
+        server_config.session_storage = Arc::new(rustls::server::NoServerSessionStorage {});
+        server_config.ticket_observer = Some(log.clone());
+        let server_config = Arc::new(server_config);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        let issued = log.issued.lock().unwrap().clone();
+        assert!(!issued.is_empty());
+        for (len, lifetime, ticket_version) in &issued {
+            assert!(*len > 0);
+            assert!(lifetime.is_some());
+            assert_eq!(*ticket_version, version.version);
+        }
+        assert!(log.redeemed.lock().unwrap().is_empty());
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+        let redeemed = log.redeemed.lock().unwrap().clone();
+        assert_eq!(redeemed.len(), 1);
+        let (len, lifetime, ticket_version) = redeemed[0];
+        assert!(issued
+            .iter()
+            .any(|(issued_len, _, _)| *issued_len == len));
+        assert_eq!(lifetime, None);
+        assert_eq!(ticket_version, version.version);
+    }
+}
 
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_session_id_resumption_respects_session_lifetime() {
+    let kt = KeyType::Rsa2048;
+    let client_config = Arc::new(make_client_config_with_versions(
+        kt,
+        &[&rustls::version::TLS12],
+    ));
     let mut server_config = make_server_config(kt);
-    server_config.ticketer = provider::Ticketer::new().unwrap();
-    let storage = Arc::new(ServerStorage::new());
-    server_config.session_storage = storage.clone();
+    server_config.session_lifetime = 0;
     let server_config = Arc::new(server_config);
 
-    // full handshake
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
+    do_handshake(&mut client, &mut server);
     assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
-    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
 
-    // resumed
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
-    assert!(resume_c2s > full_c2s);
-    assert!(resume_s2c < full_s2c);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(server.resumption_kind(), None);
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_client_reports_downgrade_sentinel() {
+    let kt = KeyType::Rsa2048;
+    let server_config = Arc::new(make_server_config(kt));
+
+    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS12]);
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.downgrade_sentinel(), Some(DowngradeSentinel::Tls12));
+
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(make_client_config(kt)), &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.downgrade_sentinel(), None);
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_client_strict_downgrade_protection() {
+    let kt = KeyType::Rsa2048;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS12]);
+    client_config.strict_downgrade_protection = true;
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
     assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::PeerMisbehaved(
+            PeerMisbehaved::UnexpectedDowngradeSentinel
+        )))
+    );
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn client_rejects_tls11_downgrade_sentinel() {
+    let kt = KeyType::Rsa2048;
+    let server_config = finish_server_config(
+        kt,
+        server_config_builder_with_versions(&[&rustls::version::TLS12]),
     );
-    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
-    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+    let (mut client, mut server) = make_pair_for_configs(make_client_config(kt), server_config);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    let (mut client, mut server): (rustls::Connection, rustls::Connection) =
+        (client.into(), server.into());
 
-    // resumed again
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(resume_s2c, resume2_s2c);
-    assert_eq!(resume_c2s, resume2_c2s);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
+    let insert_tls11_sentinel = |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ServerHello(_) = &parsed.payload {
+                // handshake header, then legacy_version, then random
+                let mut bytes = encoded.bytes().to_vec();
+                bytes[30..38].copy_from_slice(b"DOWNGRD\x00");
+                *encoded = Payload::new(bytes);
+            }
+        }
+        Altered::InPlace
+    };
+
+    transfer_altered(&mut server, insert_tls11_sentinel, &mut client);
     assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
+        client.process_new_packets(),
+        Err(Error::PeerMisbehaved(
+            PeerMisbehaved::AttemptedDowngradeToTls12WhenTls13IsSupported
+        ))
     );
-    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
-    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
 }
 
 #[test]
@@ -4111,6 +6867,64 @@ fn early_data_is_available_on_resumption() {
     assert_eq!(&received_early_data[..], b"hello");
 }
 
+#[derive(Debug, Default)]
+struct AdvancingTime(AtomicUsize);
+
+impl rustls::time_provider::TimeProvider for AdvancingTime {
+    fn current_time(&self) -> Option<UnixTime> {
+        let now = UnixTime::now().as_secs() + self.0.load(Ordering::SeqCst) as u64;
+        Some(UnixTime::since_unix_epoch(Duration::from_secs(now)))
+    }
+}
+
+#[test]
+fn server_ticket_options() {
+    let (client_config, server_config) = early_data_configs();
+    let mut server_config =
+        ServerConfig::clone(&server_config).with_ticket_max_early_data_size(100);
+    server_config
+        .dangerous()
+        .set_randomize_ticket_age_add(false);
+    let server_config = Arc::new(server_config);
+
+    // tickets allow less early data than the server accepts
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .bytes_left(),
+        100
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Resumed));
+
+    // the client discards tickets once their advertised lifetime passes
+    for (ticket_lifetime, expected) in [
+        (None, HandshakeKind::Resumed),
+        (Some(60), HandshakeKind::Full),
+    ] {
+        let time = Arc::new(AdvancingTime::default());
+        let mut client_config = make_client_config(KeyType::Rsa2048);
+        client_config.time_provider = time.clone();
+        let client_config = Arc::new(client_config);
+
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.ticket_lifetime = ticket_lifetime;
+        let server_config = Arc::new(server_config);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+
+        time.0.store(120, Ordering::SeqCst);
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(expected));
+    }
+}
+
 #[test]
 fn early_data_not_available_on_server_before_client_hello() {
     let mut server = ServerConnection::new(Arc::new(make_server_config(KeyType::Rsa2048))).unwrap();
@@ -4822,110 +7636,505 @@ mod test_quic {
         assert_eq!(payload.len(), buf.len() - header_len - tag_len);
     }
 
-    #[test]
-    fn test_quic_exporter() {
-        for &kt in ALL_KEY_TYPES {
-            let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
-            let server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
+    #[test]
+    fn test_quic_exporter() {
+        for &kt in ALL_KEY_TYPES {
+            let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+            let server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
+
+            do_exporter_test(client_config, server_config);
+        }
+    }
+
+    #[test]
+    fn test_fragmented_append() {
+        // Create a QUIC client connection.
+        let client_config =
+            make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+        let client_config = Arc::new(client_config);
+        let mut client = quic::ClientConnection::new(
+            Arc::clone(&client_config),
+            quic::Version::V1,
+            server_name("localhost"),
+            b"client params"[..].into(),
+        )
+        .unwrap();
+
+        // Construct a message that is too large to fit in a single QUIC packet.
+        // We want the partial pieces to be large enough to overflow the deframer's
+        // 4096 byte buffer if mishandled.
+        let mut out = vec![0; 4096];
+        let len_bytes = u32::to_be_bytes(9266_u32);
+        out[1..4].copy_from_slice(&len_bytes[1..]);
+
+        // Read the message - this will put us into a joining handshake message state, buffering
+        // 4096 bytes into the deframer buffer.
+        client.read_hs(&out).unwrap();
+
+        // Read the message again - once more it isn't a complete message, so we'll try to
+        // append another 4096 bytes into the deframer buffer.
+        //
+        // If the deframer mishandles writing into the used buffer space this will panic with
+        // an index out of range error:
+        //   range end index 8192 out of range for slice of length 4096
+        client.read_hs(&out).unwrap();
+    }
+} // mod test_quic
+
+#[test]
+fn test_client_does_not_offer_sha1() {
+    use rustls::internal::msgs::codec::Reader;
+    use rustls::internal::msgs::handshake::HandshakePayload;
+    use rustls::internal::msgs::message::{MessagePayload, OutboundOpaqueMessage};
+    use rustls::HandshakeType;
+
+    for kt in ALL_KEY_TYPES {
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions(*kt, &[version]);
+            let (mut client, _) = make_pair_for_configs(client_config, make_server_config(*kt));
+
+            assert!(client.wants_write());
+            let mut buf = [0u8; 262144];
+            let sz = client
+                .write_tls(&mut buf.as_mut())
+                .unwrap();
+            let msg = OutboundOpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
+            let msg = Message::try_from(msg.into_plain_message()).unwrap();
+            assert!(msg.is_handshake_type(HandshakeType::ClientHello));
+
+            let client_hello = match msg.payload {
+                MessagePayload::Handshake { parsed, .. } => match parsed.payload {
+                    HandshakePayload::ClientHello(ch) => ch,
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            };
+
+            let sigalgs = client_hello
+                .sigalgs_extension()
+                .unwrap();
+            assert!(
+                !sigalgs.contains(&SignatureScheme::RSA_PKCS1_SHA1),
+                "sha1 unexpectedly offered"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_client_config_keyshare() {
+    let kx_groups = vec![provider::kx_group::SECP384R1];
+    let client_config = make_client_config_with_kx_groups(KeyType::Rsa2048, kx_groups.clone());
+    let server_config = make_server_config_with_kx_groups(KeyType::Rsa2048, kx_groups);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake_until_error(&mut client, &mut server).unwrap();
+}
+
+#[test]
+fn test_client_config_keyshare_mismatch() {
+    let client_config =
+        make_client_config_with_kx_groups(KeyType::Rsa2048, vec![provider::kx_group::SECP384R1]);
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa2048, vec![provider::kx_group::X25519]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+}
+
+#[test]
+fn test_server_config_handle() {
+    use rustls::server::ServerConfigHandle;
+
+    let handle = ServerConfigHandle::new(Arc::new(make_server_config_with_mandatory_client_auth(
+        KeyType::Rsa2048,
+    )));
+    let connect = |client_config: ClientConfig| {
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+        let mut server = ServerConnection::new(handle.current()).unwrap();
+        do_handshake_until_error(&mut client, &mut server).map(|_| server)
+    };
+
+    // client auth is required, with no ALPN
+    assert!(connect(make_client_config(KeyType::Rsa2048)).is_err());
+    let server = connect(make_client_config_with_auth(KeyType::Rsa2048)).unwrap();
+    assert_eq!(server.alpn_protocol(), None);
+
+    let before = handle.current();
+    handle.update(|update| {
+        update
+            .set_client_auth(false)
+            .set_alpn_protocols(vec![b"h2".to_vec()]);
+    });
+    assert!(before.alpn_protocols.is_empty());
+
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+    let server = connect(client_config).unwrap();
+    assert_eq!(server.alpn_protocol(), Some(&b"h2"[..]));
+    assert_eq!(server.peer_certificates(), None);
+
+    // turning client auth back on restores the original verifier
+    handle.update(|update| {
+        update.set_client_auth(true);
+    });
+    assert!(connect(make_client_config(KeyType::Rsa2048)).is_err());
+    assert!(connect(make_client_config_with_auth(KeyType::Rsa2048)).is_ok());
+
+    // sessions made while client auth was off are not resumed once it is mandatory
+    for version in [&rustls::version::TLS12, &rustls::version::TLS13] {
+        handle.update(|update| {
+            update.set_client_auth(false);
+        });
+        let client_config = Arc::new(make_client_config_with_versions(
+            KeyType::Rsa2048,
+            &[version],
+        ));
+        let mut client =
+            ClientConnection::new(Arc::clone(&client_config), server_name("localhost")).unwrap();
+        let mut server = ServerConnection::new(handle.current()).unwrap();
+        do_handshake(&mut client, &mut server);
+
+        handle.update(|update| {
+            update.set_client_auth(true);
+        });
+        let mut client = ClientConnection::new(client_config, server_name("localhost")).unwrap();
+        let mut server = ServerConnection::new(handle.current()).unwrap();
+        assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+        assert_ne!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    }
+}
+
+#[test]
+fn test_transcript_feed() {
+    use rustls::transcript::danger::DeterministicRandom;
+    use rustls::transcript::{FeedError, RecordingReader, Transcript, TranscriptClock};
+
+    static RANDOM: DeterministicRandom = DeterministicRandom::new(0);
+
+    let client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS12]);
+    let mut server_config = finish_server_config(
+        KeyType::Rsa2048,
+        ServerConfig::builder_with_provider(
+            CryptoProvider {
+                secure_random: &RANDOM,
+                ..provider::default_provider()
+            }
+            .into(),
+        )
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap(),
+    );
+    let clock = TranscriptClock::new();
+    server_config.time_provider = Arc::new(clock.clone());
+    let server_config = Arc::new(server_config);
+
+    // record a failing handshake
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let mut reader = RecordingReader::new(&buf[..]);
+    server.read_tls(&mut reader).unwrap();
+    let err = server
+        .process_new_packets()
+        .unwrap_err();
+    let saved = reader.into_parts().1.encode();
+
+    // and reproduce it
+    let transcript = Transcript::read(&saved).unwrap();
+    assert_eq!(transcript.entries()[0].data, buf);
+    let mut server = ServerConnection::new(server_config).unwrap();
+    match transcript.feed(&mut server, &clock) {
+        Err(FeedError::Connection(replayed)) => assert_eq!(replayed, err),
+        other => panic!("unexpected result {other:?}"),
+    }
+}
+
+#[test]
+fn test_transcript_feed_is_deterministic() {
+    use rustls::transcript::danger::{DeterministicRandom, DeterministicX25519};
+    use rustls::transcript::{Transcript, TranscriptClock};
+
+    static KX: DeterministicX25519 = DeterministicX25519::new(0);
+
+    fn server_config(clock: &TranscriptClock) -> Arc<ServerConfig> {
+        // each connection needs a fresh generator, to make the same random values
+        let random: &'static DeterministicRandom = Box::leak(Box::new(DeterministicRandom::new(0)));
+        let mut config = finish_server_config(
+            KeyType::Ed25519,
+            ServerConfig::builder_with_provider(
+                CryptoProvider {
+                    secure_random: random,
+                    kx_groups: vec![&KX],
+                    ..provider::default_provider()
+                }
+                .into(),
+            )
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap(),
+        );
+        config.time_provider = Arc::new(clock.clone());
+        Arc::new(config)
+    }
+
+    // record a whole connection
+    let clock = TranscriptClock::new();
+    let mut client = ClientConnection::new(
+        Arc::new(make_client_config(KeyType::Ed25519)),
+        server_name("localhost"),
+    )
+    .unwrap();
+    let mut server = ServerConnection::new(server_config(&clock)).unwrap();
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let mut transcript = Transcript::new();
+    while client.wants_write() {
+        let mut buf = Vec::new();
+        client.write_tls(&mut buf).unwrap();
+        transcript.push(UnixTime::now(), &buf);
+        server.read_tls(&mut &buf[..]).unwrap();
+        server.process_new_packets().unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+    }
+    assert!(!server.is_handshaking());
+
+    // the new connection decrypts what the client sent to the original
+    let mut server = ServerConnection::new(server_config(&clock)).unwrap();
+    transcript
+        .feed(&mut server, &clock)
+        .unwrap();
+    assert!(!server.is_handshaking());
+    let mut received = [0u8; 5];
+    server
+        .reader()
+        .read_exact(&mut received)
+        .unwrap();
+    assert_eq!(&received, b"hello");
+}
+
+#[cfg(feature = "state_trace")]
+#[test]
+fn test_state_trace() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    do_handshake(&mut client, &mut server);
 
-            do_exporter_test(client_config, server_config);
-        }
-    }
+    let trace = client.state_trace();
+    let first = &trace.transitions()[0];
+    assert_eq!(
+        first.from,
+        "client::hs::ExpectServerHelloOrHelloRetryRequest"
+    );
+    assert_eq!(first.received, "ServerHello");
+    assert!(first.error.is_none());
+    assert!(trace
+        .transitions()
+        .iter()
+        .any(|transition| transition
+            .sent
+            .contains(&"Finished".to_string())));
+    assert!(trace
+        .to_dot()
+        .starts_with("digraph handshake {\n"));
+
+    // nothing after the handshake is recorded
+    let recorded = server.state_trace().transitions().len();
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    server.refresh_traffic_keys().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    assert_eq!(server.state_trace().transitions().len(), recorded);
+    assert!(!client
+        .state_trace()
+        .transitions()
+        .iter()
+        .any(|transition| transition.received == "KeyUpdate"));
 
-    #[test]
-    fn test_fragmented_append() {
-        // Create a QUIC client connection.
-        let client_config =
-            make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
-        let client_config = Arc::new(client_config);
-        let mut client = quic::ClientConnection::new(
-            Arc::clone(&client_config),
-            quic::Version::V1,
-            server_name("localhost"),
-            b"client params"[..].into(),
-        )
+    // a failed transition records the error
+    let client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS12]);
+    let server_config =
+        make_server_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS13]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    transfer(&mut client, &mut server);
+    assert!(server.process_new_packets().is_err());
+    let last = server
+        .state_trace()
+        .transitions()
+        .last()
         .unwrap();
+    assert_eq!(last.received, "ClientHello");
+    assert_eq!(last.to, None);
+    assert!(last.error.is_some());
+}
 
-        // Construct a message that is too large to fit in a single QUIC packet.
-        // We want the partial pieces to be large enough to overflow the deframer's
-        // 4096 byte buffer if mishandled.
-        let mut out = vec![0; 4096];
-        let len_bytes = u32::to_be_bytes(9266_u32);
-        out[1..4].copy_from_slice(&len_bytes[1..]);
+#[test]
+fn test_client_offers_hybrid_component_key_share() {
+    use rustls::crypto::{ActiveKeyExchange, SharedSecret, SupportedKxGroup};
 
-        // Read the message - this will put us into a joining handshake message state, buffering
-        // 4096 bytes into the deframer buffer.
-        client.read_hs(&out).unwrap();
+    const FAKE_HYBRID: NamedGroup = NamedGroup::Unknown(0xfe00);
 
-        // Read the message again - once more it isn't a complete message, so we'll try to
-        // append another 4096 bytes into the deframer buffer.
-        //
-        // If the deframer mishandles writing into the used buffer space this will panic with
-        // an index out of range error:
-        //   range end index 8192 out of range for slice of length 4096
-        client.read_hs(&out).unwrap();
+    // a "hybrid" group whose classical component is X25519, and which has no other
+    #[derive(Debug)]
+    struct FakeHybrid;
+
+    impl SupportedKxGroup for FakeHybrid {
+        fn start(&self) -> Result<Box<dyn ActiveKeyExchange>, Error> {
+            Ok(Box::new(FakeHybridKx(provider::kx_group::X25519.start()?)))
+        }
+
+        fn name(&self) -> NamedGroup {
+            FAKE_HYBRID
+        }
     }
-} // mod test_quic
 
-#[test]
-fn test_client_does_not_offer_sha1() {
-    use rustls::internal::msgs::codec::Reader;
-    use rustls::internal::msgs::handshake::HandshakePayload;
-    use rustls::internal::msgs::message::{MessagePayload, OutboundOpaqueMessage};
-    use rustls::HandshakeType;
+    struct FakeHybridKx(Box<dyn ActiveKeyExchange>);
 
-    for kt in ALL_KEY_TYPES {
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions(*kt, &[version]);
-            let (mut client, _) = make_pair_for_configs(client_config, make_server_config(*kt));
+    impl ActiveKeyExchange for FakeHybridKx {
+        fn complete(self: Box<Self>, peer_pub_key: &[u8]) -> Result<SharedSecret, Error> {
+            self.0.complete(peer_pub_key)
+        }
 
-            assert!(client.wants_write());
-            let mut buf = [0u8; 262144];
-            let sz = client
-                .write_tls(&mut buf.as_mut())
-                .unwrap();
-            let msg = OutboundOpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
-            let msg = Message::try_from(msg.into_plain_message()).unwrap();
-            assert!(msg.is_handshake_type(HandshakeType::ClientHello));
+        fn pub_key(&self) -> &[u8] {
+            self.0.pub_key()
+        }
 
-            let client_hello = match msg.payload {
-                MessagePayload::Handshake { parsed, .. } => match parsed.payload {
-                    HandshakePayload::ClientHello(ch) => ch,
-                    _ => unreachable!(),
-                },
-                _ => unreachable!(),
-            };
+        fn group(&self) -> NamedGroup {
+            FAKE_HYBRID
+        }
 
-            let sigalgs = client_hello
-                .sigalgs_extension()
-                .unwrap();
-            assert!(
-                !sigalgs.contains(&SignatureScheme::RSA_PKCS1_SHA1),
-                "sha1 unexpectedly offered"
-            );
+        fn hybrid_component(&self) -> Option<(NamedGroup, &[u8])> {
+            Some((self.0.group(), self.0.pub_key()))
+        }
+
+        fn complete_hybrid_component(
+            self: Box<Self>,
+            peer_pub_key: &[u8],
+        ) -> Result<SharedSecret, Error> {
+            self.0.complete(peer_pub_key)
         }
     }
-}
 
-#[test]
-fn test_client_config_keyshare() {
-    let kx_groups = vec![provider::kx_group::SECP384R1];
-    let client_config = make_client_config_with_kx_groups(KeyType::Rsa2048, kx_groups.clone());
-    let server_config = make_server_config_with_kx_groups(KeyType::Rsa2048, kx_groups);
+    static FAKE_HYBRID_GROUP: FakeHybrid = FakeHybrid;
+
+    // the server does not support the hybrid group, so chooses its component without a retry
+    let client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa2048,
+        vec![&FAKE_HYBRID_GROUP, provider::kx_group::X25519],
+    );
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa2048, vec![provider::kx_group::X25519]);
     let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
-    do_handshake_until_error(&mut client, &mut server).unwrap();
-}
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(
+        client
+            .negotiated_key_exchange_group()
+            .map(|group| group.name()),
+        Some(NamedGroup::X25519)
+    );
 
-#[test]
-fn test_client_config_keyshare_mismatch() {
+    // the component is not offered unless its group is supported
     let client_config =
-        make_client_config_with_kx_groups(KeyType::Rsa2048, vec![provider::kx_group::SECP384R1]);
+        make_client_config_with_kx_groups(KeyType::Rsa2048, vec![&FAKE_HYBRID_GROUP]);
     let server_config =
         make_server_config_with_kx_groups(KeyType::Rsa2048, vec![provider::kx_group::X25519]);
     let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
     assert!(do_handshake_until_error(&mut client, &mut server).is_err());
 }
 
+#[test]
+fn test_client_remembers_kx_groups() {
+    let server_config = Arc::new(make_server_config_with_kx_groups(
+        KeyType::Rsa2048,
+        vec![provider::kx_group::X25519],
+    ));
+
+    for remembers in [true, false] {
+        let mut client_config = make_client_config_with_kx_groups(
+            KeyType::Rsa2048,
+            vec![provider::kx_group::SECP384R1, provider::kx_group::X25519],
+        );
+        client_config.resumption = Resumption::kx_groups_only(8).remember_kx_groups(remembers);
+        let client_config = Arc::new(client_config);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            client.handshake_kind(),
+            Some(HandshakeKind::FullWithHelloRetryRequest)
+        );
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            client.handshake_kind(),
+            Some(match remembers {
+                true => HandshakeKind::Full,
+                false => HandshakeKind::FullWithHelloRetryRequest,
+            })
+        );
+    }
+}
+
+#[test]
+fn test_client_requires_same_resumption_parameters() {
+    let x25519_server = Arc::new(make_server_config_with_kx_groups(
+        KeyType::Rsa2048,
+        vec![provider::kx_group::X25519],
+    ));
+    // shares sessions with `x25519_server`, but prefers another group
+    let mut secp_server =
+        make_server_config_with_kx_groups(KeyType::Rsa2048, vec![provider::kx_group::SECP384R1]);
+    secp_server.session_storage = x25519_server.session_storage.clone();
+    let secp_server = Arc::new(secp_server);
+
+    for require in [false, true] {
+        let mut client_config = make_client_config_with_kx_groups(
+            KeyType::Rsa2048,
+            vec![provider::kx_group::X25519, provider::kx_group::SECP384R1],
+        );
+        if require {
+            client_config.resumption = client_config
+                .resumption
+                .require_same_parameters();
+        }
+        let client_config = Arc::new(client_config);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &x25519_server);
+        do_handshake(&mut client, &mut server);
+
+        // resuming with the same group is fine
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &x25519_server);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &secp_server);
+        let result = do_handshake_until_error(&mut client, &mut server);
+        match require {
+            false => {
+                result.unwrap();
+                assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+            }
+            true => assert_eq!(
+                result,
+                Err(ErrorFromPeer::Client(Error::PeerIncompatible(
+                    PeerIncompatible::ResumedWithDifferentParameters
+                )))
+            ),
+        }
+    }
+}
+
 #[cfg(feature = "tls12")]
 #[test]
 fn test_client_sends_helloretryrequest() {
@@ -5404,6 +8613,108 @@ fn test_server_mtu_reduction() {
     check_read(&mut client.reader(), &big_data);
 }
 
+#[test]
+fn test_max_fragment_length_negotiation() {
+    let server_config = Arc::new(make_server_config(KeyType::Rsa2048));
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.max_fragment_length = Some(MaxFragmentLength::Len512);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+
+        // record header, plus AEAD tag and explicit nonce/content type
+        let max_record = 512 + 5 + 24;
+        let big_data = [0u8; 4096];
+
+        server
+            .writer()
+            .write_all(&big_data)
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            server.write_tls(&mut pipe).unwrap();
+            assert!(pipe.writevs[0].len() >= 8);
+            assert!(pipe.writevs[0]
+                .iter()
+                .all(|x| *x <= max_record));
+        }
+        client.process_new_packets().unwrap();
+        check_read(&mut client.reader(), &big_data);
+
+        client
+            .writer()
+            .write_all(&big_data)
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            client.write_tls(&mut pipe).unwrap();
+            assert!(pipe.writevs[0].len() >= 8);
+            assert!(pipe.writevs[0]
+                .iter()
+                .all(|x| *x <= max_record));
+        }
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), &big_data);
+    }
+}
+
+#[test]
+fn test_max_fragment_length_not_requested() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    do_handshake(&mut client, &mut server);
+
+    server
+        .writer()
+        .write_all(&[0u8; 4096])
+        .unwrap();
+    let mut pipe = OtherSession::new(&mut client);
+    server.write_tls(&mut pipe).unwrap();
+    assert_eq!(pipe.writevs[0].len(), 1);
+}
+
+#[test]
+fn test_server_rejects_invalid_max_fragment_length() {
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.max_fragment_length = Some(MaxFragmentLength::Len512);
+    let (client, server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+    let (mut client, mut server) = (client.into(), server.into());
+
+    let use_invalid_max_fragment_length = |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                for ext in ch.extensions.iter_mut() {
+                    if let ClientExtension::MaxFragmentLength(mfl) = ext {
+                        *mfl = MaxFragmentLength::Unknown(5);
+                    }
+                }
+            }
+            *encoded = Payload::new(parsed.get_encoding());
+        }
+        Altered::InPlace
+    };
+
+    transfer_altered(&mut client, use_invalid_max_fragment_length, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::PeerMisbehaved(
+            PeerMisbehaved::OfferedInvalidMaxFragmentLength
+        ))
+    );
+}
+
+#[test]
+fn bad_client_max_fragment_length() {
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.max_fragment_length = Some(MaxFragmentLength::Unknown(5));
+    assert_eq!(
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).err(),
+        Some(Error::BadMaxFragmentSize)
+    );
+}
+
 fn check_client_max_fragment_size(size: usize) -> Option<Error> {
     let mut client_config = make_client_config(KeyType::Ed25519);
     client_config.max_fragment_size = Some(size);
@@ -5620,19 +8931,16 @@ fn test_client_rejects_illegal_tls13_ccs() {
 
 #[cfg(feature = "tls12")]
 #[test]
-fn test_client_rejects_no_extended_master_secret_extension_when_require_ems_or_fips() {
+fn test_client_rejects_no_extended_master_secret_extension_by_default() {
     let key_type = KeyType::Rsa2048;
-    let mut client_config = make_client_config(key_type);
-    if provider_is_fips() {
-        assert!(client_config.require_ems);
-    } else {
-        client_config.require_ems = true;
-    }
+    let client_config = make_client_config(key_type);
     let mut server_config = finish_server_config(
         key_type,
         server_config_builder_with_versions(&[&rustls::version::TLS12]),
     );
-    server_config.require_ems = false;
+    server_config
+        .dangerous()
+        .set_require_ems(false);
     let (client, server) = make_pair_for_configs(client_config, server_config);
     let (mut client, mut server) = (client.into(), server.into());
     transfer_altered(&mut client, remove_ems_request, &mut server);
@@ -5648,18 +8956,13 @@ fn test_client_rejects_no_extended_master_secret_extension_when_require_ems_or_f
 
 #[cfg(feature = "tls12")]
 #[test]
-fn test_server_rejects_no_extended_master_secret_extension_when_require_ems_or_fips() {
+fn test_server_rejects_no_extended_master_secret_extension_by_default() {
     let key_type = KeyType::Rsa2048;
     let client_config = make_client_config(key_type);
-    let mut server_config = finish_server_config(
+    let server_config = finish_server_config(
         key_type,
         server_config_builder_with_versions(&[&rustls::version::TLS12]),
     );
-    if provider_is_fips() {
-        assert!(server_config.require_ems);
-    } else {
-        server_config.require_ems = true;
-    }
     let (client, server) = make_pair_for_configs(client_config, server_config);
     let (mut client, mut server) = (client.into(), server.into());
     transfer_altered(&mut client, remove_ems_request, &mut server);
@@ -5671,6 +8974,40 @@ fn test_server_rejects_no_extended_master_secret_extension_when_require_ems_or_f
     );
 }
 
+#[cfg(feature = "tls12")]
+#[test]
+fn test_server_allows_no_extended_master_secret_extension_when_not_required() {
+    let key_type = KeyType::Rsa2048;
+    let client_config = make_client_config(key_type);
+    let mut server_config = finish_server_config(
+        key_type,
+        server_config_builder_with_versions(&[&rustls::version::TLS12]),
+    );
+    server_config
+        .dangerous()
+        .set_require_ems(false);
+    let (client, server) = make_pair_for_configs(client_config, server_config);
+    let (mut client, mut server): (rustls::Connection, rustls::Connection) =
+        (client.into(), server.into());
+    assert_eq!(server.extended_master_secret(), None);
+
+    transfer_altered(&mut client, remove_ems_request, &mut server);
+    server.process_new_packets().unwrap();
+    assert_eq!(server.extended_master_secret(), Some(false));
+}
+
+#[test]
+fn test_extended_master_secret_status() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        let server_config = make_server_config(KeyType::Rsa2048);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.extended_master_secret(), Some(true));
+        assert_eq!(server.extended_master_secret(), Some(true));
+    }
+}
+
 #[cfg(feature = "tls12")]
 fn remove_ems_request(msg: &mut Message) -> Altered {
     if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
@@ -5801,6 +9138,273 @@ fn test_client_with_custom_verifier_can_accept_ecdsa_sha1_signatures() {
     client.process_new_packets().unwrap();
 }
 
+#[test]
+fn test_client_sends_grease() {
+    use rustls::server::Acceptor;
+
+    fn is_grease(value: u16) -> bool {
+        let [hi, lo] = value.to_be_bytes();
+        hi == lo && lo & 0x0f == 0x0a
+    }
+
+    let mut client_config = make_client_config(KeyType::Ed25519);
+    client_config.enable_grease = true;
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = Acceptor::default();
+    acceptor
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    let accepted = acceptor.accept().unwrap().unwrap();
+    assert!(is_grease(u16::from(
+        accepted.client_hello().cipher_suites()[0]
+    )));
+
+    let mut server = accepted
+        .into_connection(Arc::new(make_server_config(KeyType::Ed25519)))
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+}
+
+#[test]
+fn test_client_rejects_grease_extension_echoed_by_server() {
+    /// Records the first GREASE extension type in the `ClientHello`.
+    #[derive(Debug)]
+    struct RecordsGrease(Arc<Mutex<Option<[u8; 2]>>>);
+
+    impl rustls::client::danger::MutatesHandshakeMessages for RecordsGrease {
+        fn mutate(&self, message: &mut Vec<u8>) {
+            if message[0] != u8::from(HandshakeType::ClientHello) {
+                return;
+            }
+            // skip the header, version, random and session id
+            let mut offset = 4 + 2 + 32;
+            offset += 1 + usize::from(message[offset]);
+            let cipher_suites_len = u16::from_be_bytes([message[offset], message[offset + 1]]);
+            offset += 2 + usize::from(cipher_suites_len);
+            offset += 1 + usize::from(message[offset]);
+
+            let mut extensions = &message[offset + 2..];
+            while !extensions.is_empty() {
+                let typ = [extensions[0], extensions[1]];
+                if typ[0] == typ[1] && typ[0] & 0x0f == 0x0a {
+                    *self.0.lock().unwrap() = Some(typ);
+                    return;
+                }
+                let len = u16::from_be_bytes([extensions[2], extensions[3]]);
+                extensions = &extensions[4 + usize::from(len)..];
+            }
+        }
+    }
+
+    /// Appends the recorded GREASE extension to the `ServerHello`.
+    #[derive(Debug)]
+    struct EchoesGrease(Arc<Mutex<Option<[u8; 2]>>>);
+
+    impl rustls::client::danger::MutatesHandshakeMessages for EchoesGrease {
+        fn mutate(&self, message: &mut Vec<u8>) {
+            if message[0] != u8::from(HandshakeType::ServerHello) {
+                return;
+            }
+            let typ = self.0.lock().unwrap().unwrap();
+            message.extend_from_slice(&[typ[0], typ[1], 0, 0]);
+
+            // fix up the extensions and message lengths
+            let session_id_len = usize::from(message[4 + 2 + 32]);
+            let extensions_len = 4 + 2 + 32 + 1 + session_id_len + 2 + 1;
+            let len = u16::from_be_bytes([message[extensions_len], message[extensions_len + 1]]);
+            message[extensions_len..extensions_len + 2].copy_from_slice(&(len + 4).to_be_bytes());
+            let len = (message.len() - 4) as u32;
+            message[1..4].copy_from_slice(&len.to_be_bytes()[1..]);
+        }
+    }
+
+    for version in rustls::ALL_VERSIONS {
+        let grease = Arc::new(Mutex::new(None));
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.enable_grease = true;
+        client_config
+            .dangerous()
+            .set_handshake_mutator(Arc::new(RecordsGrease(grease.clone())));
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config
+            .dangerous()
+            .set_handshake_mutator(Arc::new(EchoesGrease(grease)));
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::PeerMisbehaved(
+                PeerMisbehaved::UnsolicitedServerHelloExtension
+            ))),
+            "{version:?}"
+        );
+    }
+}
+
+#[test]
+fn test_client_grease_handshakes() {
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.enable_grease = true;
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    }
+
+    // and with a HelloRetryRequest
+    let mut client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa2048,
+        vec![provider::kx_group::SECP384R1, provider::kx_group::X25519],
+    );
+    client_config.enable_grease = true;
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa2048, vec![provider::kx_group::X25519]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client.handshake_kind(),
+        Some(HandshakeKind::FullWithHelloRetryRequest)
+    );
+}
+
+#[test]
+fn test_client_hello_profile() {
+    use rustls::client::ShapesClientHello;
+    use rustls::server::Acceptor;
+
+    #[derive(Debug)]
+    struct Profile;
+
+    impl ShapesClientHello for Profile {
+        fn extension_order(&self) -> &[u16] {
+            // signature_algorithms, server_name, supported_groups
+            &[13, 0, 10]
+        }
+
+        fn cipher_suites(&self, suites: &mut Vec<CipherSuite>) {
+            suites.insert(0, CipherSuite::TLS_RSA_WITH_AES_128_GCM_SHA256);
+        }
+
+        fn named_groups(&self, groups: &mut Vec<rustls::NamedGroup>) {
+            groups.push(rustls::NamedGroup::secp521r1);
+        }
+
+        fn signature_schemes(&self, schemes: &mut Vec<SignatureScheme>) {
+            schemes.reverse();
+        }
+
+        fn extra_extensions(&self) -> Vec<(u16, Vec<u8>)> {
+            // an extension type that is already sent is skipped
+            vec![(0xfe00, vec![1, 2, 3]), (0, vec![])]
+        }
+    }
+
+    /// Return the extension types of the `ClientHello` in `buf`, in order.
+    fn extension_types(buf: &[u8]) -> Vec<u16> {
+        let read_u16 = |at: usize| u16::from_be_bytes([buf[at], buf[at + 1]]);
+        // record header, handshake header, version and random
+        let mut at = 5 + 4 + 2 + 32;
+        at += 1 + buf[at] as usize;
+        at += 2 + read_u16(at) as usize;
+        at += 1 + buf[at] as usize;
+        let end = at + 2 + read_u16(at) as usize;
+        at += 2;
+
+        let mut types = vec![];
+        while at < end {
+            types.push(read_u16(at));
+            at += 4 + read_u16(at + 2) as usize;
+        }
+        types
+    }
+
+    let server_config = Arc::new(make_server_config(KeyType::Ed25519));
+    for _ in 0..4 {
+        let mut client_config = make_client_config(KeyType::Ed25519);
+        client_config.hello_profile = Some(Arc::new(Profile));
+        let verify_schemes = client_config
+            .crypto_provider()
+            .signature_verification_algorithms
+            .supported_schemes();
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+        let mut buf = Vec::new();
+        client.write_tls(&mut buf).unwrap();
+
+        let types = extension_types(&buf);
+        assert_eq!(&types[..3], &[13, 0, 10]);
+        assert_eq!(
+            types
+                .iter()
+                .filter(|typ| **typ == 0)
+                .count(),
+            1
+        );
+        assert!(types.contains(&0xfe00));
+
+        let mut acceptor = Acceptor::default();
+        acceptor
+            .read_tls(&mut buf.as_slice())
+            .unwrap();
+        let accepted = acceptor.accept().unwrap().unwrap();
+        let hello = accepted.client_hello();
+        assert_eq!(
+            hello.cipher_suites()[0],
+            CipherSuite::TLS_RSA_WITH_AES_128_GCM_SHA256
+        );
+        assert_eq!(hello.signature_schemes().first(), verify_schemes.last());
+
+        let mut server = accepted
+            .into_connection(Arc::clone(&server_config))
+            .unwrap();
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn test_client_hello_profile_keeps_key_share_group_advertised() {
+    use rustls::client::ShapesClientHello;
+
+    #[derive(Debug)]
+    struct Profile(usize);
+
+    impl ShapesClientHello for Profile {
+        fn named_groups(&self, groups: &mut Vec<rustls::NamedGroup>) {
+            groups.drain(..self.0.min(groups.len()));
+        }
+    }
+
+    let server_config = Arc::new(make_server_config(KeyType::Ed25519));
+    let provider_groups = provider::default_provider().kx_groups;
+    assert!(provider_groups.len() > 1);
+
+    // the key share is for the first group the profile leaves, or the first group
+    // (advertised again) if it leaves none
+    for (removed, expected) in [
+        (1, provider_groups[1].name()),
+        (usize::MAX, provider_groups[0].name()),
+    ] {
+        let mut client_config = make_client_config(KeyType::Ed25519);
+        client_config.hello_profile = Some(Arc::new(Profile(removed)));
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+        assert_eq!(
+            client
+                .negotiated_key_exchange_group()
+                .map(|group| group.name()),
+            Some(expected)
+        );
+    }
+}
+
 #[test]
 fn test_acceptor() {
     use rustls::server::Acceptor;
@@ -5882,6 +9486,94 @@ fn test_acceptor() {
     assert_eq!(alert_content, expected);
 }
 
+#[test]
+fn handshake_limiter_rejects_handshakes_over_limit() {
+    use rustls::server::{HandshakeLimitPolicy, HandshakeLimiter};
+
+    let limiter = HandshakeLimiter::new(1, HandshakeLimitPolicy::Reject);
+    let rejected = Some(Error::TooManyHandshakes);
+
+    let acceptor = limiter.acceptor().unwrap();
+    assert_eq!(limiter.in_flight(), 1);
+    assert_eq!(limiter.acceptor().err(), rejected);
+    drop(acceptor);
+    assert_eq!(limiter.in_flight(), 0);
+
+    // a connection gives back its place once its handshake completes
+    let client_config = Arc::new(make_client_config(KeyType::Ed25519));
+    let mut client = ClientConnection::new(client_config, server_name("localhost")).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = limiter.acceptor().unwrap();
+    acceptor
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    let mut server = acceptor
+        .accept()
+        .unwrap()
+        .unwrap()
+        .into_connection(Arc::new(make_server_config(KeyType::Ed25519)))
+        .unwrap();
+    assert_eq!(limiter.acceptor().err(), rejected);
+
+    do_handshake(&mut client, &mut server);
+    assert_eq!(limiter.in_flight(), 0);
+    limiter.acceptor().unwrap();
+}
+
+#[test]
+fn handshake_limiter_queues_handshakes_over_limit() {
+    use rustls::server::{HandshakeLimitPolicy, HandshakeLimiter};
+
+    let limiter = HandshakeLimiter::new(1, HandshakeLimitPolicy::Queue);
+    let acceptor = limiter.acceptor().unwrap();
+
+    let waiter = {
+        let limiter = limiter.clone();
+        std::thread::spawn(move || {
+            limiter.acceptor().unwrap();
+        })
+    };
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(!waiter.is_finished());
+
+    drop(acceptor);
+    waiter.join().unwrap();
+    assert_eq!(limiter.in_flight(), 0);
+}
+
+#[test]
+fn handshake_limiter_queues_handshakes_for_a_bounded_time() {
+    use rustls::server::{HandshakeLimitPolicy, HandshakeLimiter};
+
+    let limiter =
+        HandshakeLimiter::new(1, HandshakeLimitPolicy::QueueFor(Duration::from_millis(50)));
+    let acceptor = limiter.acceptor().unwrap();
+    assert_eq!(limiter.acceptor().err(), Some(Error::TooManyHandshakes));
+
+    let waiter = {
+        let limiter = limiter.clone();
+        std::thread::spawn(move || limiter.acceptor().is_ok())
+    };
+    drop(acceptor);
+    assert!(waiter.join().unwrap());
+    assert_eq!(limiter.in_flight(), 0);
+}
+
+#[test]
+fn handshake_limiter_try_acceptor_does_not_wait() {
+    use rustls::server::{HandshakeLimitPolicy, HandshakeLimiter};
+
+    let limiter = HandshakeLimiter::new(1, HandshakeLimitPolicy::Queue);
+    let acceptor = limiter.try_acceptor().unwrap();
+    assert_eq!(limiter.in_flight(), 1);
+    assert_eq!(limiter.try_acceptor().err(), Some(Error::TooManyHandshakes));
+
+    drop(acceptor);
+    limiter.try_acceptor().unwrap();
+}
+
 #[test]
 fn test_acceptor_rejected_handshake() {
     use rustls::server::Acceptor;
@@ -6477,8 +10169,35 @@ fn test_client_fips_service_indicator_includes_require_ems() {
 
     let mut client_config = make_client_config(KeyType::Rsa2048);
     assert!(client_config.fips());
-    client_config.require_ems = false;
+    client_config
+        .dangerous()
+        .set_require_ems(false);
+    assert!(!client_config.fips());
+}
+
+#[test]
+fn test_client_fips_service_indicator_includes_ech_resolver() {
+    #[derive(Debug)]
+    struct Resolver {
+        fips: bool,
+    }
+
+    impl ResolvesEchConfig for Resolver {
+        fn ech_config(&self, _server_name: &ServerName<'_>) -> Option<EchConfig> {
+            None
+        }
+
+        fn fips(&self) -> bool {
+            self.fips
+        }
+    }
+
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.ech_resolver = Some(Arc::new(Resolver { fips: false }));
     assert!(!client_config.fips());
+
+    client_config.ech_resolver = Some(Arc::new(Resolver { fips: true }));
+    assert_eq!(client_config.fips(), provider_is_fips());
 }
 
 #[test]
@@ -6489,7 +10208,9 @@ fn test_server_fips_service_indicator_includes_require_ems() {
 
     let mut server_config = make_server_config(KeyType::Rsa2048);
     assert!(server_config.fips());
-    server_config.require_ems = false;
+    server_config
+        .dangerous()
+        .set_require_ems(false);
     assert!(!server_config.fips());
 }
 
@@ -6538,15 +10259,196 @@ fn test_client_fips_service_indicator_includes_ech_hpke_suite() {
         let config = finish_client_config(KeyType::Rsa2048, config);
         assert_eq!(config.fips(), suite.fips());
 
-        // And a connection made from a client config should retain the fips status of the
-        // config w.r.t the HPKE suite.
-        let conn = ClientConnection::new(
-            config.into(),
-            ServerName::DnsName(DnsName::try_from("example.org").unwrap()),
-        )
-        .unwrap();
-        assert_eq!(conn.fips(), suite.fips());
-    }
+        // And a connection made from a client config should retain the fips status of the
+        // config w.r.t the HPKE suite.
+        let conn = ClientConnection::new(
+            config.into(),
+            ServerName::DnsName(DnsName::try_from("example.org").unwrap()),
+        )
+        .unwrap();
+        assert_eq!(conn.fips(), suite.fips());
+    }
+}
+
+#[cfg(feature = "aws_lc_rs")]
+#[test]
+fn test_client_ech_key_log() {
+    let suite = ALL_SUPPORTED_SUITES[0];
+    let (public_key, _) = suite.generate_key_pair().unwrap();
+    let suite_id = suite.suite();
+    let config = EchConfigPayload::V18(EchConfigContents {
+        key_config: HpkeKeyConfig {
+            config_id: 10,
+            kem_id: suite_id.kem,
+            public_key: PayloadU16(public_key.0.clone()),
+            symmetric_cipher_suites: vec![HpkeSymmetricCipherSuite {
+                kdf_id: suite_id.sym.kdf_id,
+                aead_id: suite_id.sym.aead_id,
+            }],
+        },
+        maximum_name_length: 0,
+        public_name: DnsName::try_from("example.com").unwrap(),
+        extensions: vec![],
+    });
+    let config_bytes = vec![config.clone()].get_encoding();
+
+    let key_log = Arc::new(KeyLogToVec::new("client"));
+    let mut client_config = finish_client_config(
+        KeyType::Rsa2048,
+        ClientConfig::builder_with_provider(provider::default_provider().into())
+            .with_ech(EchMode::Enable(
+                EchConfig::new(EchConfigListBytes::from(config_bytes), &[suite]).unwrap(),
+            ))
+            .unwrap(),
+    );
+    client_config.key_log = key_log.clone();
+    let mut client = ClientConnection::new(
+        Arc::new(client_config),
+        ServerName::DnsName(DnsName::try_from("example.org").unwrap()),
+    )
+    .unwrap();
+
+    // the outer hello's random identifies the ECH secrets
+    let mut hello = Vec::new();
+    client.write_tls(&mut hello).unwrap();
+    let outer_random = &hello[11..43];
+
+    let log = key_log.take();
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0].label, "ECH_SECRET");
+    assert_eq!(log[0].client_random, outer_random);
+    assert_eq!(log[1].label, "ECH_CONFIG");
+    assert_eq!(log[1].client_random, outer_random);
+    assert_eq!(log[1].secret, config.get_encoding());
+}
+
+#[cfg(feature = "aws_lc_rs")]
+#[test]
+fn test_client_ech_resolver_notified_of_rejection() {
+    #[derive(Debug)]
+    struct Resolver {
+        config: EchConfig,
+        resolved: Mutex<Vec<String>>,
+        rejected: Mutex<Vec<(String, Option<EchConfigListBytes<'static>>)>>,
+    }
+
+    impl ResolvesEchConfig for Resolver {
+        fn ech_config(&self, server_name: &ServerName<'_>) -> Option<EchConfig> {
+            self.resolved
+                .lock()
+                .unwrap()
+                .push(server_name.to_str().into_owned());
+            Some(self.config.clone())
+        }
+
+        fn ech_rejected(
+            &self,
+            server_name: &ServerName<'_>,
+            retry_configs: Option<EchConfigListBytes<'static>>,
+        ) {
+            self.rejected
+                .lock()
+                .unwrap()
+                .push((server_name.to_str().into_owned(), retry_configs));
+        }
+    }
+
+    let suite = ALL_SUPPORTED_SUITES[0];
+    let (public_key, _) = suite.generate_key_pair().unwrap();
+    let suite_id = suite.suite();
+    let config = EchConfigPayload::V18(EchConfigContents {
+        key_config: HpkeKeyConfig {
+            config_id: 10,
+            kem_id: suite_id.kem,
+            public_key: PayloadU16(public_key.0.clone()),
+            symmetric_cipher_suites: vec![HpkeSymmetricCipherSuite {
+                kdf_id: suite_id.sym.kdf_id,
+                aead_id: suite_id.sym.aead_id,
+            }],
+        },
+        maximum_name_length: 0,
+        public_name: DnsName::try_from("testserver.com").unwrap(),
+        extensions: vec![],
+    });
+    let mut config_bytes = Vec::new();
+    vec![config].encode(&mut config_bytes);
+
+    let resolver = Arc::new(Resolver {
+        config: EchConfig::new(EchConfigListBytes::from(config_bytes), &[suite]).unwrap(),
+        resolved: Mutex::default(),
+        rejected: Mutex::default(),
+    });
+
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.ech_resolver = Some(resolver.clone());
+    let server_config = make_server_config(KeyType::Rsa2048);
+
+    // rustls servers do not support ECH, so will not accept the offer.
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(client.ech_status(), EchStatus::Offered);
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Client(Error::PeerIncompatible(
+            PeerIncompatible::ServerRejectedEncryptedClientHello(None)
+        )))
+    );
+    assert_eq!(client.ech_status(), EchStatus::Rejected);
+
+    assert_eq!(*resolver.resolved.lock().unwrap(), vec!["localhost"]);
+    assert_eq!(
+        *resolver.rejected.lock().unwrap(),
+        vec![("localhost".to_string(), None)]
+    );
+}
+
+#[test]
+fn test_service_binding_selects_alpn() {
+    // "1 . alpn=h2,h3 no-default-alpn"
+    let binding =
+        ServiceBinding::from_rdata(b"\x00\x01\x00\x00\x01\x00\x06\x02h2\x02h3\x00\x02\x00\x00")
+            .unwrap();
+
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.alpn_protocols = vec![b"http/1.1".to_vec(), b"h2".to_vec()];
+    let client_config = binding
+        .apply_to(&client_config, b"http/1.1", &[])
+        .unwrap();
+    assert_eq!(client_config.alpn_protocols, vec![b"h2".to_vec()]);
+
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec(), b"h2".to_vec()];
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    assert_eq!(
+        binding
+            .apply_to(&client_config, b"http/1.1", &[])
+            .unwrap_err(),
+        Error::NoApplicationProtocol
+    );
+
+    // "0 svc.example."
+    let alias = ServiceBinding::from_rdata(b"\x00\x00\x03svc\x07example\x00").unwrap();
+    assert_eq!(
+        alias
+            .apply_to(&client_config, b"http/1.1", &[])
+            .unwrap_err(),
+        Error::InvalidServiceBinding(ServiceBindingError::AliasMode)
+    );
+
+    // "1 . ech=010203"
+    let ech = ServiceBinding::from_rdata(b"\x00\x01\x00\x00\x05\x00\x03\x01\x02\x03").unwrap();
+    let client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS12]);
+    assert_eq!(
+        ech.apply_to(&client_config, b"http/1.1", &[])
+            .unwrap_err(),
+        Error::InvalidServiceBinding(ServiceBindingError::EchRequiresTls13)
+    );
 }
 
 #[test]
@@ -6703,6 +10605,47 @@ fn test_close_notify_sent_prior_to_handshake_complete() {
     );
 }
 
+#[test]
+fn test_user_canceled_before_close_notify() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa2048));
+        do_handshake(&mut client, &mut server);
+
+        client.send_warning_alert(WarningAlert::UserCanceled);
+        client.send_close_notify();
+        transfer(&mut client, &mut server);
+        let io_state = server.process_new_packets().unwrap();
+        assert!(io_state.peer_has_closed());
+    }
+}
+
+#[test]
+fn test_user_canceled_sent_prior_to_handshake_complete() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    client.send_warning_alert(WarningAlert::UserCanceled);
+    client.send_close_notify();
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::AlertReceived(
+            AlertDescription::CloseNotify
+        )))
+    );
+}
+
+#[test]
+fn test_warning_alert_after_close_notify_ignored() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa2048);
+    do_handshake(&mut client, &mut server);
+    client.send_close_notify();
+    assert!(transfer(&mut client, &mut server) > 0);
+
+    // does nothing
+    client.send_warning_alert(WarningAlert::UserCanceled);
+    assert_eq!(transfer(&mut client, &mut server), 0);
+}
+
 #[test]
 fn test_subsequent_close_notify_ignored() {
     let (mut client, mut server) = make_pair(KeyType::Rsa2048);
@@ -6744,27 +10687,690 @@ fn test_read_tls_artificial_eof_after_close_notify() {
     );
 }
 
-#[test]
-fn test_pinned_ocsp_response_given_to_custom_server_cert_verifier() {
-    let ocsp_response = b"hello-ocsp-world!";
-    let kt = KeyType::EcdsaP256;
+#[test]
+fn test_client_connection_with_verifier_override() {
+    // the client does not trust the server's issuer
+    let client_config = Arc::new(make_client_config(KeyType::EcdsaP256));
+    let server_config = Arc::new(make_server_config(KeyType::Rsa2048));
+    let strict_fails = || {
+        let mut client =
+            ClientConnection::new(client_config.clone(), server_name("localhost")).unwrap();
+        let mut server = ServerConnection::new(server_config.clone()).unwrap();
+        assert!(matches!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::InvalidCertificate(_)))
+        ));
+    };
+    strict_fails();
+
+    let mut options = rustls::client::ClientConnectionOptions::new();
+    options
+        .dangerous()
+        .set_certificate_verifier(Arc::new(MockServerVerifier::accepts_anything()));
+    let mut client = ClientConnection::new_with_options(
+        client_config.clone(),
+        server_name("localhost"),
+        options,
+    )
+    .unwrap();
+    let mut server = ServerConnection::new(server_config.clone()).unwrap();
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    // neither the shared config, nor its sessions, were affected
+    strict_fails();
+}
+
+#[test]
+fn test_client_connection_with_kx_groups_override() {
+    let client_config = Arc::new(make_client_config_with_kx_groups(
+        KeyType::Rsa2048,
+        vec![provider::kx_group::X25519, provider::kx_group::SECP384R1],
+    ));
+    let server_config = Arc::new(make_server_config(KeyType::Rsa2048));
+
+    let mut client = ClientConnection::new_with_options(
+        client_config.clone(),
+        server_name("localhost"),
+        rustls::client::ClientConnectionOptions::new()
+            .with_kx_groups(&[NamedGroup::secp384r1, NamedGroup::secp521r1]),
+    )
+    .unwrap();
+    let mut server = ServerConnection::new(server_config.clone()).unwrap();
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client
+            .negotiated_key_exchange_group()
+            .unwrap()
+            .name(),
+        NamedGroup::secp384r1
+    );
+
+    // the shared config is unaffected
+    let (mut client, mut server) = make_pair_for_arc_configs(
+        &client_config,
+        &Arc::new(make_server_config(KeyType::Rsa2048)),
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client
+            .negotiated_key_exchange_group()
+            .unwrap()
+            .name(),
+        NamedGroup::X25519
+    );
+
+    assert_eq!(
+        ClientConnection::new_with_options(
+            client_config,
+            server_name("localhost"),
+            rustls::client::ClientConnectionOptions::new().with_kx_groups(&[NamedGroup::secp521r1]),
+        )
+        .err(),
+        Some(Error::ApiMisuse(ApiMisuse::NoKxGroupsSelected))
+    );
+}
+
+#[test]
+fn test_pinned_ocsp_response_given_to_custom_server_cert_verifier() {
+    let ocsp_response = b"hello-ocsp-world!";
+    let kt = KeyType::EcdsaP256;
+
+    for version in rustls::ALL_VERSIONS {
+        let server_config = server_config_builder()
+            .with_no_client_auth()
+            .with_single_cert_with_ocsp(kt.get_chain(), kt.get_key(), ocsp_response.to_vec())
+            .unwrap();
+
+        let client_config = client_config_builder_with_versions(&[version])
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(MockServerVerifier::expects_ocsp_response(
+                ocsp_response,
+            )))
+            .with_no_client_auth();
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn test_stapled_ocsp_for_intermediates() {
+    use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls::client::RevocationStatus;
+    use rustls::DigitallySignedStruct;
+
+    #[derive(Debug)]
+    struct ChecksStaples {
+        inner: Arc<dyn ServerCertVerifier>,
+        seen: Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl ServerCertVerifier for ChecksStaples {
+        fn verify_server_cert(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            intermediates: &[CertificateDer<'_>],
+            server_name: &ServerName<'_>,
+            ocsp_response: &[u8],
+            now: UnixTime,
+        ) -> Result<ServerCertVerified, Error> {
+            self.inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            self.inner
+                .verify_tls12_signature(message, cert, dss)
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            self.inner
+                .verify_tls13_signature(message, cert, dss)
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.inner.supported_verify_schemes()
+        }
+
+        fn check_stapled_ocsp(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            ocsp_responses: &[&[u8]],
+            _now: UnixTime,
+        ) -> Result<Vec<RevocationStatus>, Error> {
+            *self.seen.lock().unwrap() = ocsp_responses
+                .iter()
+                .map(|resp| resp.to_vec())
+                .collect();
+            if ocsp_responses.contains(&&b"revoked"[..]) {
+                return Err(Error::InvalidCertificate(CertificateError::Revoked));
+            }
+            Ok(ocsp_responses
+                .iter()
+                .map(|resp| match resp.is_empty() {
+                    true => RevocationStatus::Unknown,
+                    false => RevocationStatus::Good,
+                })
+                .collect())
+        }
+    }
+
+    let kt = KeyType::Rsa2048;
+    let chain = kt.get_chain();
+    assert_eq!(chain.len(), 3);
+    let signing_key: Arc<dyn sign::SigningKey> =
+        Arc::new(RsaSigningKey::new(&kt.get_key()).unwrap());
+
+    for version in [&rustls::version::TLS13, &rustls::version::TLS12] {
+        // only TLS1.3 can carry staples for intermediates
+        let (intermediate_seen, intermediate_status) = match version.version {
+            ProtocolVersion::TLSv1_3 => (&b"intermediate"[..], RevocationStatus::Good),
+            _ => (&b""[..], RevocationStatus::Unknown),
+        };
+        let expect_seen = vec![
+            b"end-entity".to_vec(),
+            intermediate_seen.to_vec(),
+            Vec::new(),
+        ];
+        let expect_status = [
+            RevocationStatus::Good,
+            intermediate_status,
+            RevocationStatus::Unknown,
+        ];
+
+        let mut certified_key = sign::CertifiedKey::new(chain.clone(), Arc::clone(&signing_key))
+            .with_intermediate_ocsp(vec![Some(b"intermediate".to_vec()), None]);
+        certified_key.ocsp = Some(b"end-entity".to_vec());
+        let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+        resolver
+            .add("localhost", certified_key)
+            .unwrap();
+        let mut server_config = make_server_config(kt);
+        server_config.cert_resolver = Arc::new(resolver);
+
+        let verifier = Arc::new(ChecksStaples {
+            inner: webpki_server_verifier_builder(get_client_root_store(kt))
+                .build()
+                .unwrap(),
+            seen: Mutex::new(Vec::new()),
+        });
+        let client_config = client_config_builder_with_versions(&[version])
+            .dangerous()
+            .with_custom_certificate_verifier(verifier.clone())
+            .with_no_client_auth();
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(client.peer_revocation_status(), &[]);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(*verifier.seen.lock().unwrap(), expect_seen);
+        assert_eq!(client.peer_revocation_status(), &expect_status);
+    }
+
+    // a verifier can reject the chain based on an intermediate's staple
+    let certified_key = sign::CertifiedKey::new(chain, signing_key)
+        .with_intermediate_ocsp(vec![Some(b"revoked".to_vec())]);
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    resolver
+        .add("localhost", certified_key)
+        .unwrap();
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let client_config = client_config_builder_with_versions(&[&rustls::version::TLS13])
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(ChecksStaples {
+            inner: webpki_server_verifier_builder(get_client_root_store(kt))
+                .build()
+                .unwrap(),
+            seen: Mutex::new(Vec::new()),
+        }))
+        .with_no_client_auth();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+            CertificateError::Revoked
+        )))
+    );
+}
+
+#[test]
+fn test_stale_ocsp_responses_are_not_stapled() {
+    use rustls::server::{OcspFreshness, StaleOcspPolicy};
+
+    let fresh = ocsp_response_with_next_update(b"99991231235959Z");
+    let stale = ocsp_response_with_next_update(b"20000101000000Z");
+    let one_day = Duration::from_secs(24 * 60 * 60);
+    let kt = KeyType::EcdsaP256;
+
+    for version in rustls::ALL_VERSIONS {
+        let handshake = |ocsp_response: &[u8], freshness, expect_stapled: &[u8]| {
+            let mut server_config = server_config_builder()
+                .with_no_client_auth()
+                .with_single_cert_with_ocsp(kt.get_chain(), kt.get_key(), ocsp_response.to_vec())
+                .unwrap();
+            server_config.ocsp_freshness = freshness;
+            let client_config = client_config_builder_with_versions(&[version])
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(
+                    MockServerVerifier::expects_ocsp_response(expect_stapled),
+                ))
+                .with_no_client_auth();
+            let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+            do_handshake_until_error(&mut client, &mut server)
+        };
+
+        // not checked by default
+        assert_eq!(handshake(&stale, None, &stale), Ok(()));
+
+        let freshness = OcspFreshness::new(one_day);
+        assert_eq!(handshake(&fresh, Some(freshness), &fresh), Ok(()));
+        assert_eq!(
+            handshake(&stale, Some(freshness), &[]),
+            Err(ErrorFromPeer::Server(Error::StaleOcspResponse))
+        );
+        assert!(handshake(b"junk", Some(freshness), &[]).is_err());
+
+        let freshness = freshness.with_policy(StaleOcspPolicy::OmitStaple);
+        assert_eq!(handshake(&fresh, Some(freshness), &fresh), Ok(()));
+        assert_eq!(handshake(&stale, Some(freshness), &[]), Ok(()));
+
+        // within the margin
+        let freshness =
+            OcspFreshness::new(one_day * 365 * 10_000).with_policy(StaleOcspPolicy::OmitStaple);
+        assert_eq!(handshake(&fresh, Some(freshness), &[]), Ok(()));
+    }
+}
+
+/// A minimal, unsigned `OCSPResponse` with the given `nextUpdate` GeneralizedTime.
+fn ocsp_response_with_next_update(next_update: &[u8]) -> Vec<u8> {
+    let time = |time: &[u8]| der(0x18, time);
+    let single = [
+        der(0x30, &[]),                // certID
+        vec![0x80, 0x00],              // certStatus: good
+        time(b"20000101000000Z"),      // thisUpdate
+        der(0xa0, &time(next_update)), // nextUpdate
+    ]
+    .concat();
+    let tbs = [
+        der(0xa2, &der(0x04, &[1; 20])), // responderID: byKey
+        time(b"20000101000000Z"),        // producedAt
+        der(0x30, &der(0x30, &single)),  // responses
+    ]
+    .concat();
+    let basic = [der(0x30, &tbs), der(0x30, &[]), der(0x03, &[0])].concat();
+    ocsp_response(&der(0x30, &basic))
+}
+
+#[test]
+fn test_default_verifier_checks_stapled_ocsp() {
+    use rustls::client::RevocationStatus;
+
+    let kt = KeyType::EcdsaP256;
+    let chain = kt.get_chain();
+    let issuer_key = provider::default_provider()
+        .key_provider
+        .load_private_key(kt.get_intermediate_key())
+        .unwrap();
+    let cert_id = ocsp_cert_id(&chain[0], &chain[1]);
+    let good = |this_update: &[u8], next_update: Option<&[u8]>| {
+        signed_ocsp_response(
+            &cert_id,
+            &*issuer_key,
+            &[0x80, 0x00],
+            this_update,
+            next_update,
+        )
+    };
+    let revoked = signed_ocsp_response(
+        &cert_id,
+        &*issuer_key,
+        &der(0xa1, &generalized_time()),
+        &generalized_time(),
+        Some(b"99991231235959Z"),
+    );
+    let mut forged = good(&generalized_time(), Some(b"99991231235959Z"));
+    *forged.last_mut().unwrap() ^= 1;
+    // the right serial number, but another issuer's name and key
+    let other_issuer = signed_ocsp_response(
+        &ocsp_cert_id(&chain[0], &chain[2]),
+        &*issuer_key,
+        &[0x80, 0x00],
+        &generalized_time(),
+        Some(b"99991231235959Z"),
+    );
+
+    for version in rustls::ALL_VERSIONS {
+        let handshake = |ocsp_response: &[u8]| {
+            let server_config = server_config_builder()
+                .with_no_client_auth()
+                .with_single_cert_with_ocsp(kt.get_chain(), kt.get_key(), ocsp_response.to_vec())
+                .unwrap();
+            let client_config = make_client_config_with_versions(kt, &[version]);
+            let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+            do_handshake_until_error(&mut client, &mut server)
+                .map(|()| client.peer_revocation_status().to_vec())
+        };
+
+        let good_statuses = Ok(vec![
+            RevocationStatus::Good,
+            RevocationStatus::Unknown,
+            RevocationStatus::Unknown,
+        ]);
+        assert_eq!(
+            handshake(&good(&generalized_time(), Some(b"99991231235959Z"))),
+            good_statuses
+        );
+        // without a nextUpdate time, a response is only current for a while
+        assert_eq!(
+            handshake(&good(
+                &generalized_time_ago(Duration::from_secs(3600)),
+                None
+            )),
+            good_statuses
+        );
+        assert_eq!(
+            handshake(&revoked),
+            Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                CertificateError::Revoked
+            )))
+        );
+
+        // responses which cannot be verified tell us nothing
+        for unverified in [
+            &forged[..],
+            &ocsp_response_with_next_update(b"99991231235959Z"),
+            &other_issuer,
+            &good(&generalized_time(), None),
+        ] {
+            assert_eq!(
+                handshake(unverified),
+                Ok(vec![RevocationStatus::Unknown; 3])
+            );
+        }
+    }
+}
+
+/// A SHA-256 `CertID` for the certificate with the serial number of `cert`, issued by `issuer`.
+fn ocsp_cert_id(cert: &CertificateDer<'_>, issuer: &CertificateDer<'_>) -> Vec<u8> {
+    // Certificate -> TBSCertificate -> [0] version, serialNumber
+    let (_, tbs, _) = read_der(cert);
+    let (_, tbs, _) = read_der(tbs);
+    let (_, _, tbs) = read_der(tbs);
+    let (_, serial, _) = read_der(tbs);
+
+    // ... signature, issuer, validity, subject, subjectPublicKeyInfo
+    let (_, tbs, _) = read_der(issuer);
+    let (_, tbs, _) = read_der(tbs);
+    let (_, _, tbs) = read_der(tbs);
+    let (_, _, tbs) = read_der(tbs);
+    let (_, _, tbs) = read_der(tbs);
+    let (_, _, tbs) = read_der(tbs);
+    let (_, _, tbs) = read_der(tbs);
+    let (_, _, spki) = read_der(tbs);
+    let subject = &tbs[..tbs.len() - spki.len()];
+    let (_, spki, _) = read_der(spki);
+    let (_, _, spki) = read_der(spki);
+    let (_, key, _) = read_der(spki);
+
+    let sha256 = cipher_suite::TLS13_AES_128_GCM_SHA256
+        .tls13()
+        .unwrap()
+        .common
+        .hash_provider;
+    [
+        // sha256
+        der(
+            0x30,
+            &der(
+                0x06,
+                &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01],
+            ),
+        ),
+        der(0x04, sha256.hash(subject).as_ref()), // issuerNameHash
+        der(0x04, sha256.hash(&key[1..]).as_ref()), // issuerKeyHash
+        der(0x02, serial),
+    ]
+    .concat()
+}
+
+/// An `OCSPResponse` with the given `CertID`, DER-encoded `certStatus`, and `thisUpdate`
+/// and `nextUpdate` times, signed by `issuer_key`.
+fn signed_ocsp_response(
+    cert_id: &[u8],
+    issuer_key: &dyn sign::SigningKey,
+    cert_status: &[u8],
+    this_update: &[u8],
+    next_update: Option<&[u8]>,
+) -> Vec<u8> {
+    let mut single = [
+        der(0x30, cert_id),
+        cert_status.to_vec(),
+        this_update.to_vec(),
+    ]
+    .concat();
+    if let Some(next_update) = next_update {
+        single.extend(der(0xa0, &der(0x18, next_update)));
+    }
+    let tbs = der(
+        0x30,
+        &[
+            der(0xa2, &der(0x04, &[1; 20])), // responderID: byKey
+            generalized_time(),              // producedAt
+            der(0x30, &der(0x30, &single)),
+        ]
+        .concat(),
+    );
+
+    let signer = issuer_key
+        .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+        .unwrap();
+    let mut signature = vec![0];
+    signature.extend(signer.sign(&tbs).unwrap());
+    let basic = der(
+        0x30,
+        &[
+            tbs,
+            // ecdsa-with-SHA256
+            der(
+                0x30,
+                &der(0x06, &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02]),
+            ),
+            der(0x03, &signature),
+        ]
+        .concat(),
+    );
+
+    ocsp_response(&basic)
+}
+
+fn generalized_time() -> Vec<u8> {
+    der(0x18, b"20000101000000Z")
+}
+
+/// The GeneralizedTime `ago` before now.
+fn generalized_time_ago(ago: Duration) -> Vec<u8> {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        - ago.as_secs();
+
+    // days to civil date, from <https://howardhinnant.github.io/date_algorithms.html>
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    let time = format!(
+        "{year:04}{month:02}{day:02}{:02}{:02}{:02}Z",
+        secs / 3_600,
+        secs / 60 % 60,
+        secs % 60
+    );
+    der(0x18, time.as_bytes())
+}
+
+/// Read one DER TLV from `input`, returning its tag, value and the rest of `input`.
+fn read_der(input: &[u8]) -> (u8, &[u8], &[u8]) {
+    let (len, header) = match input[1] {
+        len if len < 0x80 => (usize::from(len), 2),
+        0x81 => (usize::from(input[2]), 3),
+        0x82 => (usize::from(input[2]) << 8 | usize::from(input[3]), 4),
+        _ => panic!("unsupported DER length"),
+    };
+    let (value, rest) = input[header..].split_at(len);
+    (input[0], value, rest)
+}
+
+fn der(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match body.len() {
+        len if len < 0x80 => out.push(len as u8),
+        len if len < 0x100 => out.extend([0x81, len as u8]),
+        len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+/// Wrap a `BasicOCSPResponse` in a successful `OCSPResponse`.
+fn ocsp_response(basic: &[u8]) -> Vec<u8> {
+    let response_bytes = [
+        // id-pkix-ocsp-basic
+        der(
+            0x06,
+            &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01],
+        ),
+        der(0x04, basic),
+    ]
+    .concat();
+    der(
+        0x30,
+        &[
+            der(0x0a, &[0]), // responseStatus: successful
+            der(0xa0, &der(0x30, &response_bytes)),
+        ]
+        .concat(),
+    )
+}
+
+#[test]
+fn test_default_verifier_reports_unknown_revocation_status() {
+    let kt = KeyType::Rsa2048;
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            client.peer_revocation_status(),
+            &[rustls::client::RevocationStatus::Unknown; 3]
+        );
+    }
+}
+
+#[test]
+fn test_spki_pinning_verifier_reports_failures() {
+    use rustls::client::{PinFailureReport, ReportsPinFailures, SpkiPinningVerifier};
+
+    #[derive(Debug, Default)]
+    struct CollectsReports(Mutex<Vec<PinFailureReport>>);
+
+    impl ReportsPinFailures for CollectsReports {
+        fn pin_failure(&self, report: &PinFailureReport) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(report.clone());
+        }
+    }
 
-    for version in rustls::ALL_VERSIONS {
-        let server_config = server_config_builder()
-            .with_no_client_auth()
-            .with_single_cert_with_ocsp(kt.get_chain(), kt.get_key(), ocsp_response.to_vec())
+    let kt = KeyType::Rsa2048;
+    let chain = kt.get_chain();
+    let hash = cipher_suite::TLS13_AES_128_GCM_SHA256
+        .tls13()
+        .unwrap()
+        .common
+        .hash_provider;
+    let intermediate_spki = ParsedCertificate::try_from(&chain[1])
+        .unwrap()
+        .subject_public_key_info();
+    let intermediate_pin = hash
+        .hash(intermediate_spki.as_ref())
+        .as_ref()
+        .to_vec();
+    let wrong_pin = vec![0u8; 32];
+
+    let make_client = |pins: Vec<Vec<u8>>, report_only: bool| {
+        let reporter = Arc::new(CollectsReports::default());
+        let inner = webpki_server_verifier_builder(get_client_root_store(kt))
+            .build()
             .unwrap();
-
-        let client_config = client_config_builder_with_versions(&[version])
+        let mut verifier =
+            SpkiPinningVerifier::new(inner, hash, pins).with_reporter(reporter.clone());
+        if report_only {
+            verifier = verifier.report_only();
+        }
+        let client_config = client_config_builder()
             .dangerous()
-            .with_custom_certificate_verifier(Arc::new(MockServerVerifier::expects_ocsp_response(
-                ocsp_response,
-            )))
+            .with_custom_certificate_verifier(Arc::new(verifier))
             .with_no_client_auth();
+        (client_config, reporter)
+    };
 
-        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
-        do_handshake(&mut client, &mut server);
-    }
+    // matching pin
+    let (client_config, reporter) = make_client(vec![wrong_pin.clone(), intermediate_pin], false);
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+    do_handshake(&mut client, &mut server);
+    assert!(reporter.0.lock().unwrap().is_empty());
+
+    // no matching pin, enforced
+    let (client_config, reporter) = make_client(vec![wrong_pin.clone()], false);
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+            CertificateError::ApplicationVerificationFailure
+        )))
+    );
+    let reports = reporter.0.lock().unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].server_name, server_name("localhost"));
+    assert_eq!(reports[0].expected_pins, vec![wrong_pin.clone()]);
+    assert_eq!(reports[0].presented_chain, chain);
+    assert!(reports[0].enforced);
+    drop(reports);
+
+    // no matching pin, report-only
+    let (client_config, reporter) = make_client(vec![wrong_pin], true);
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+    do_handshake(&mut client, &mut server);
+    let reports = reporter.0.lock().unwrap();
+    assert_eq!(reports.len(), 1);
+    assert!(!reports[0].enforced);
 }
 
 #[cfg(feature = "zlib")]
@@ -6848,6 +11454,74 @@ impl rustls::compress::CertCompressor for FailingCompressor {
     }
 }
 
+#[test]
+fn test_cert_compression_stats() {
+    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa2048);
+    server_config.cert_compressors = vec![&IdentityCompressor];
+    server_config.cert_decompressors = vec![&IdentityDecompressor];
+    let mut client_config = make_client_config_with_auth(KeyType::Rsa2048);
+    client_config.cert_compressors = vec![&IdentityCompressor];
+    client_config.cert_decompressors = vec![&IdentityDecompressor];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    for (sender, receiver) in [
+        (
+            client.sent_cert_compression(),
+            server.received_cert_compression(),
+        ),
+        (
+            server.sent_cert_compression(),
+            client.received_cert_compression(),
+        ),
+    ] {
+        let (sent, received) = (sender.unwrap(), receiver.unwrap());
+        assert_eq!(
+            sent.algorithm,
+            rustls::CertificateCompressionAlgorithm::Zlib
+        );
+        assert_eq!(sent.algorithm, received.algorithm);
+        assert!(sent.uncompressed_len > 0);
+        assert_eq!(sent.uncompressed_len, received.uncompressed_len);
+        assert_eq!(sent.compressed_len, received.compressed_len);
+        assert!(sent.elapsed.is_some());
+    }
+
+    // nothing is compressed without a common algorithm
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_compressors = vec![&IdentityCompressor];
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.cert_decompressors = vec![];
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.sent_cert_compression(), None);
+    assert_eq!(client.received_cert_compression(), None);
+}
+
+#[derive(Debug)]
+struct IdentityDecompressor;
+
+impl rustls::compress::CertDecompressor for IdentityDecompressor {
+    fn decompress(
+        &self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), rustls::compress::DecompressionFailed> {
+        match input.len() == output.len() {
+            true => {
+                output.copy_from_slice(input);
+                Ok(())
+            }
+            false => Err(rustls::compress::DecompressionFailed),
+        }
+    }
+
+    fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
+        rustls::CertificateCompressionAlgorithm::Zlib
+    }
+}
+
 #[derive(Debug)]
 struct NeverDecompressor;
 
@@ -6908,6 +11582,158 @@ fn test_server_can_opt_out_of_compression_cache() {
     }
 }
 
+#[test]
+fn test_server_cert_compression_policy() {
+    static COMPRESSIONS: Mutex<Vec<rustls::compress::CompressionLevel>> = Mutex::new(Vec::new());
+
+    let policy = Arc::new(ByServerName::default());
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_compressors = vec![&RecordingCompressor];
+    server_config.cert_compression_policy = Some(policy.clone());
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+    let server_config = Arc::new(server_config);
+
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.resumption = Resumption::disabled();
+    client_config.cert_decompressors = vec![&IdentityDecompressor];
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+    let client_config = Arc::new(client_config);
+
+    // compression is refused for this name
+    let mut client = ClientConnection::new(
+        Arc::clone(&client_config),
+        server_name("second.testserver.com"),
+    )
+    .unwrap();
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    do_handshake(&mut client, &mut server);
+    assert!(COMPRESSIONS.lock().unwrap().is_empty());
+
+    // interactive compressions are not cached
+    for _ in 0..2 {
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+    }
+    assert_eq!(
+        *COMPRESSIONS.lock().unwrap(),
+        vec![
+            rustls::compress::CompressionLevel::Interactive,
+            rustls::compress::CompressionLevel::Interactive,
+        ]
+    );
+
+    let offers = policy.0.lock().unwrap();
+    assert_eq!(
+        *offers,
+        vec![
+            ("second.testserver.com".to_string(), b"h2".to_vec()),
+            ("localhost".to_string(), b"h2".to_vec()),
+            ("localhost".to_string(), b"h2".to_vec()),
+        ]
+    );
+
+    #[derive(Debug, Default)]
+    struct ByServerName(Mutex<Vec<(String, Vec<u8>)>>);
+
+    impl rustls::compress::ChoosesCertCompression for ByServerName {
+        fn choose_compression(
+            &self,
+            offer: &rustls::compress::CertCompressionOffer<'_>,
+        ) -> Option<rustls::compress::CertCompressionChoice> {
+            self.0.lock().unwrap().push((
+                offer
+                    .server_name
+                    .unwrap_or_default()
+                    .to_string(),
+                offer
+                    .alpn_protocol
+                    .unwrap_or_default()
+                    .to_vec(),
+            ));
+            match offer.server_name {
+                Some("second.testserver.com") => None,
+                _ => offer
+                    .default_choice()
+                    .map(|choice| rustls::compress::CertCompressionChoice {
+                        level: Some(rustls::compress::CompressionLevel::Interactive),
+                        ..choice
+                    }),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct RecordingCompressor;
+
+    impl rustls::compress::CertCompressor for RecordingCompressor {
+        fn compress(
+            &self,
+            input: Vec<u8>,
+            level: rustls::compress::CompressionLevel,
+        ) -> Result<Vec<u8>, rustls::compress::CompressionFailed> {
+            COMPRESSIONS.lock().unwrap().push(level);
+            Ok(input)
+        }
+
+        fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
+            rustls::CertificateCompressionAlgorithm::Zlib
+        }
+    }
+}
+
+#[test]
+fn test_client_caches_cert_decompression() {
+    static DECOMPRESS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_compressors = vec![&IdentityCompressor];
+    let server_config = Arc::new(server_config);
+
+    for (cache, expected_count) in [
+        (rustls::compress::DecompressionCache::default(), 1),
+        (rustls::compress::DecompressionCache::Disabled, 5),
+    ] {
+        DECOMPRESS_COUNT.store(0, Ordering::SeqCst);
+
+        let mut client_config = make_client_config(KeyType::Rsa2048);
+        client_config.cert_decompressors = vec![&CountingIdentityDecompressor];
+        client_config.cert_decompression_cache = Arc::new(cache);
+        client_config.resumption = Resumption::disabled();
+        let client_config = Arc::new(client_config);
+
+        for _ in 0..5 {
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &server_config);
+            do_handshake(&mut client, &mut server);
+            assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+        }
+
+        assert_eq!(DECOMPRESS_COUNT.load(Ordering::SeqCst), expected_count);
+    }
+
+    #[derive(Debug)]
+    struct CountingIdentityDecompressor;
+
+    impl rustls::compress::CertDecompressor for CountingIdentityDecompressor {
+        fn decompress(
+            &self,
+            input: &[u8],
+            output: &mut [u8],
+        ) -> Result<(), rustls::compress::DecompressionFailed> {
+            DECOMPRESS_COUNT.fetch_add(1, Ordering::SeqCst);
+            if input.len() != output.len() {
+                return Err(rustls::compress::DecompressionFailed);
+            }
+            output.copy_from_slice(input);
+            Ok(())
+        }
+
+        fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
+            rustls::CertificateCompressionAlgorithm::Zlib
+        }
+    }
+}
+
 #[test]
 fn test_cert_decompression_by_client_produces_invalid_cert_payload() {
     let mut server_config = make_server_config(KeyType::Rsa2048);
@@ -6950,6 +11776,77 @@ fn test_cert_decompression_by_server_produces_invalid_cert_payload() {
     );
 }
 
+#[test]
+fn test_cert_decompression_is_incremental() {
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_compressors = vec![&IdentityCompressor];
+    let server_config = Arc::new(server_config);
+
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.cert_decompressors = vec![&ChunkedDecompressor { trailer: b"" }];
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(client
+        .received_cert_compression()
+        .is_some());
+
+    // output beyond the declared length is refused
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.cert_decompressors = vec![&ChunkedDecompressor { trailer: b"!" }];
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::PeerMisbehaved(
+            PeerMisbehaved::InvalidCertCompression
+        )))
+    );
+
+    // and likewise for servers decompressing client certificates
+    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa2048);
+    server_config.cert_decompressors = vec![&ChunkedDecompressor { trailer: b"" }];
+    let mut client_config = make_client_config_with_auth(KeyType::Rsa2048);
+    client_config.cert_compressors = vec![&IdentityCompressor];
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(server
+        .received_cert_compression()
+        .is_some());
+}
+
+/// Decompresses like `IdentityDecompressor`, but only incrementally, then writes `trailer`.
+#[derive(Debug)]
+struct ChunkedDecompressor {
+    trailer: &'static [u8],
+}
+
+impl rustls::compress::CertDecompressor for ChunkedDecompressor {
+    fn decompress(
+        &self,
+        _input: &[u8],
+        _output: &mut [u8],
+    ) -> Result<(), rustls::compress::DecompressionFailed> {
+        panic!("ChunkedDecompressor::decompress should not be called");
+    }
+
+    fn decompress_incremental(
+        &self,
+        input: &[u8],
+        output: &mut rustls::compress::DecompressionOutput,
+    ) -> Result<(), rustls::compress::DecompressionFailed> {
+        for chunk in input.chunks(100) {
+            output.write(chunk)?;
+        }
+        assert_eq!(output.written_len(), output.declared_len());
+        output.write(self.trailer)
+    }
+
+    fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
+        rustls::CertificateCompressionAlgorithm::Zlib
+    }
+}
+
 #[test]
 fn test_cert_decompression_by_server_fails() {
     let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa2048);
@@ -6971,6 +11868,37 @@ fn test_cert_decompression_by_server_fails() {
     );
 }
 
+#[test]
+fn test_max_decompressed_cert_size() {
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_compressors = vec![&IdentityCompressor];
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.cert_decompressors = vec![&IdentityDecompressor];
+    client_config.max_decompressed_cert_size = Some(1000);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::InvalidMessage(
+            InvalidMessage::MessageTooLarge
+        )))
+    );
+
+    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa2048);
+    server_config.cert_decompressors = vec![&IdentityDecompressor];
+    server_config.max_decompressed_cert_size = Some(1000);
+    let mut client_config = make_client_config_with_auth(KeyType::Rsa2048);
+    client_config.cert_compressors = vec![&IdentityCompressor];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::InvalidMessage(
+            InvalidMessage::MessageTooLarge
+        )))
+    );
+}
+
 #[cfg(feature = "zlib")]
 #[test]
 fn test_cert_decompression_by_server_would_result_in_excessively_large_cert() {
@@ -7034,20 +11962,88 @@ impl rustls::compress::CertDecompressor for GarbageDecompressor {
     }
 }
 
-#[derive(Debug)]
-struct FailingDecompressor;
+#[derive(Debug)]
+struct FailingDecompressor;
+
+impl rustls::compress::CertDecompressor for FailingDecompressor {
+    fn decompress(
+        &self,
+        _input: &[u8],
+        _output: &mut [u8],
+    ) -> Result<(), rustls::compress::DecompressionFailed> {
+        Err(rustls::compress::DecompressionFailed)
+    }
+
+    fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
+        rustls::CertificateCompressionAlgorithm::Zlib
+    }
+}
+
+#[test]
+fn test_cert_compression_preference() {
+    use rustls::CertificateCompressionAlgorithm::{Brotli, Zlib};
+
+    static ZLIB: AnyIdentityCompression = AnyIdentityCompression(Zlib);
+    static BROTLI: AnyIdentityCompression = AnyIdentityCompression(Brotli);
+
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.cert_compressors = vec![&ZLIB, &BROTLI];
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.cert_decompressors = vec![&ZLIB, &BROTLI];
+    client_config.resumption = Resumption::disabled();
+
+    for (preference, expected) in [(&[][..], Zlib), (&[Brotli][..], Brotli)] {
+        let server_config = server_config
+            .clone()
+            .with_cert_compression_preference(preference);
+        let client_config = client_config
+            .clone()
+            .with_cert_compression_preference(preference);
+        assert_eq!(
+            server_config.cert_compressors[0].algorithm(),
+            client_config.cert_decompressors[0].algorithm(),
+        );
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            client
+                .received_cert_compression()
+                .map(|stats| stats.algorithm),
+            Some(expected)
+        );
+    }
+
+    /// Compresses and decompresses like `IdentityCompressor`, but for any algorithm.
+    #[derive(Debug)]
+    struct AnyIdentityCompression(rustls::CertificateCompressionAlgorithm);
+
+    impl rustls::compress::CertCompressor for AnyIdentityCompression {
+        fn compress(
+            &self,
+            input: Vec<u8>,
+            _level: rustls::compress::CompressionLevel,
+        ) -> Result<Vec<u8>, rustls::compress::CompressionFailed> {
+            Ok(input)
+        }
 
-impl rustls::compress::CertDecompressor for FailingDecompressor {
-    fn decompress(
-        &self,
-        _input: &[u8],
-        _output: &mut [u8],
-    ) -> Result<(), rustls::compress::DecompressionFailed> {
-        Err(rustls::compress::DecompressionFailed)
+        fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
+            self.0
+        }
     }
 
-    fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
-        rustls::CertificateCompressionAlgorithm::Zlib
+    impl rustls::compress::CertDecompressor for AnyIdentityCompression {
+        fn decompress(
+            &self,
+            input: &[u8],
+            output: &mut [u8],
+        ) -> Result<(), rustls::compress::DecompressionFailed> {
+            IdentityDecompressor.decompress(input, output)
+        }
+
+        fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
+            self.0
+        }
     }
 }
 
@@ -7169,6 +12165,55 @@ fn test_illegal_server_renegotiation_attempt_after_tls12_handshake() {
     );
 }
 
+#[test]
+fn test_renegotiation_allowance_not_restored_by_app_data() {
+    // aws-lc-rs' TLS1.2 sealing key requires nonces to increase from the first it
+    // sees, which `RawTls` sending a third record midway through the sequence breaks
+    if provider_is_aws_lc_rs() {
+        return;
+    }
+
+    let client_config =
+        make_client_config_with_versions(KeyType::Rsa2048, &[&rustls::version::TLS12]);
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.enable_secret_extraction = true;
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    let mut raw_server = RawTls::new_server(server);
+
+    let renegotiation_request = PlainMessage {
+        typ: ContentType::Handshake,
+        version: ProtocolVersion::TLSv1_2,
+        payload: Payload::new(
+            HandshakeMessagePayload {
+                typ: HandshakeType::HelloRequest,
+                payload: HandshakePayload::HelloRequest,
+            }
+            .get_encoding(),
+        ),
+    };
+    let app_data = PlainMessage {
+        typ: ContentType::ApplicationData,
+        version: ProtocolVersion::TLSv1_2,
+        payload: Payload::new(b"hello".to_vec()),
+    };
+
+    raw_server.encrypt_and_send(&renegotiation_request, &mut client);
+    client.process_new_packets().unwrap();
+    raw_server.encrypt_and_send(&app_data, &mut client);
+    client.process_new_packets().unwrap();
+
+    raw_server.encrypt_and_send(&renegotiation_request, &mut client);
+    assert_eq!(
+        client
+            .process_new_packets()
+            .unwrap_err(),
+        Error::PeerMisbehaved(PeerMisbehaved::TooManyRenegotiationRequests)
+    );
+}
+
 #[test]
 fn test_illegal_client_renegotiation_attempt_after_tls13_handshake() {
     let mut client_config =
@@ -7386,10 +12431,13 @@ fn tls12_connection_fails_after_key_reaches_confidentiality_limit() {
 
     for i in 0..CONFIDENTIALITY_LIMIT {
         let message = format!("{i:08}");
-        client
+        let written = client
             .writer()
-            .write_all(message.as_bytes())
-            .unwrap();
+            .write_all(message.as_bytes());
+        match i {
+            1023 => assert_eq!(written.unwrap_err().kind(), io::ErrorKind::BrokenPipe),
+            _ => written.unwrap(),
+        }
         let transferred = transfer(&mut client, &mut server);
         println!(
             "{}: {} -> {:?}",
@@ -7408,6 +12456,282 @@ fn tls12_connection_fails_after_key_reaches_confidentiality_limit() {
     }
 }
 
+#[test]
+fn tls13_keys_updated_at_configured_key_limit() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]);
+    client_config.key_limits = KeyLimits::default().max_records_per_key(16);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Ed25519));
+    do_handshake(&mut client, &mut server);
+
+    let mut key_updates = 0;
+    for i in 0..40 {
+        let message = format!("{i:08}");
+        client
+            .writer()
+            .write_all(message.as_bytes())
+            .unwrap();
+        transfer(&mut client, &mut server);
+        key_updates += server
+            .process_new_packets()
+            .unwrap()
+            .key_updates_received();
+
+        let mut buf = [0u8; 32];
+        let recvd = server.reader().read(&mut buf).unwrap();
+        assert_eq!(&buf[..recvd], message.as_bytes());
+    }
+    assert_eq!(key_updates, 2);
+}
+
+#[test]
+fn tls13_connection_closes_at_configured_key_limit() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]);
+    client_config.key_limits = KeyLimits::default()
+        .max_records_per_key(16)
+        .on_limit(KeyLimitAction::Close);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Ed25519));
+    do_handshake(&mut client, &mut server);
+
+    for i in 0..16 {
+        let message = format!("{i:08}");
+        client
+            .writer()
+            .write_all(message.as_bytes())
+            .unwrap();
+        transfer(&mut client, &mut server);
+        let io_state = server.process_new_packets().unwrap();
+        assert_eq!(io_state.key_updates_received(), 0);
+        assert!(!io_state.peer_has_closed());
+
+        let mut buf = [0u8; 32];
+        let recvd = server.reader().read(&mut buf).unwrap();
+        assert_eq!(&buf[..recvd], message.as_bytes());
+    }
+
+    // data beyond the limit is refused, rather than silently discarded
+    for _ in 0..2 {
+        let err = client
+            .writer()
+            .write(b"too late")
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    transfer(&mut client, &mut server);
+    let io_state = server.process_new_packets().unwrap();
+    assert!(io_state.peer_has_closed());
+    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
+}
+
+#[test]
+#[should_panic(expected = "key limit must be at least one record")]
+fn key_limit_of_zero_records_is_refused() {
+    let _ = KeyLimits::default().max_records_per_key(0);
+}
+
+#[test]
+fn tls13_client_limits_new_session_tickets() {
+    let client_config =
+        make_client_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]);
+    let server_config = Arc::new(make_server_config(KeyType::Ed25519));
+
+    // the server sends 4 tickets
+    let mut limited_config = client_config.clone();
+    limited_config.post_handshake_limits =
+        PostHandshakeLimits::default().max_new_session_tickets(4);
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(limited_config), &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let mut limited_config = client_config;
+    limited_config.post_handshake_limits =
+        PostHandshakeLimits::default().max_new_session_tickets(2);
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(limited_config), &server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::PeerMisbehaved(
+            PeerMisbehaved::TooManyNewSessionTickets
+        )))
+    );
+}
+
+#[test]
+fn tls13_server_limits_key_updates() {
+    let mut server_config = make_server_config(KeyType::Ed25519);
+    server_config.post_handshake_limits = PostHandshakeLimits::default().max_key_updates(2);
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]),
+        server_config,
+    );
+    do_handshake(&mut client, &mut server);
+
+    for _ in 0..2 {
+        client.refresh_traffic_keys().unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+    }
+
+    // application data from the peer restores the allowance
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    for _ in 0..2 {
+        client.refresh_traffic_keys().unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+    }
+
+    client.refresh_traffic_keys().unwrap();
+    transfer(&mut client, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::PeerMisbehaved(
+            PeerMisbehaved::TooManyKeyUpdateRequests
+        ))
+    );
+}
+
+#[test]
+fn tls13_server_key_update_limit_removed_dangerously() {
+    let mut server_config = make_server_config(KeyType::Ed25519);
+    server_config.post_handshake_limits = PostHandshakeLimits::default().max_key_updates(2);
+    server_config
+        .dangerous()
+        .set_unlimited_key_updates();
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]),
+        server_config,
+    );
+    do_handshake(&mut client, &mut server);
+
+    for _ in 0..8 {
+        client.refresh_traffic_keys().unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+    }
+}
+
+#[test]
+fn tls13_keys_updated_at_key_limit_many_times() {
+    // more automatic key updates than the peer's default `max_key_updates`
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]);
+    client_config.key_limits = KeyLimits::default().max_records_per_key(2);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Ed25519));
+    do_handshake(&mut client, &mut server);
+
+    let mut key_updates = 0;
+    for i in 0..100 {
+        let message = format!("{i:08}");
+        client
+            .writer()
+            .write_all(message.as_bytes())
+            .unwrap();
+        transfer(&mut client, &mut server);
+        key_updates += server
+            .process_new_packets()
+            .unwrap()
+            .key_updates_received();
+
+        let mut buf = [0u8; 32];
+        let recvd = server.reader().read(&mut buf).unwrap();
+        assert_eq!(&buf[..recvd], message.as_bytes());
+    }
+    assert!(key_updates > 32);
+}
+
+/// A "key server" that signs with a local key, after failing a given number of times.
+#[derive(Debug)]
+struct LocalKeyServer {
+    key: Arc<dyn sign::SigningKey>,
+    failures: AtomicUsize,
+}
+
+impl sign::RequestsSignatures for LocalKeyServer {
+    fn request_signature(
+        &self,
+        request: &sign::RemoteSignRequest<'_>,
+        _timeout: Duration,
+    ) -> Result<Vec<u8>, Error> {
+        if self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            return Err(Error::General("key server unavailable".into()));
+        }
+
+        assert!(!request.prehashed);
+        self.key
+            .choose_scheme(&[request.scheme])
+            .unwrap()
+            .sign(request.input)
+    }
+}
+
+#[test]
+fn remote_signing_key_completes_handshake() {
+    for kt in [KeyType::EcdsaP256, KeyType::Rsa2048] {
+        let local_key = provider::default_provider()
+            .key_provider
+            .load_private_key(kt.get_key())
+            .unwrap();
+        let schemes = [
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ]
+        .into_iter()
+        .filter(|scheme| {
+            local_key
+                .choose_scheme(&[*scheme])
+                .is_some()
+        })
+        .collect();
+        let broken: Arc<dyn sign::RequestsSignatures> = Arc::new(LocalKeyServer {
+            key: local_key.clone(),
+            failures: AtomicUsize::new(usize::MAX),
+        });
+        let working: Arc<dyn sign::RequestsSignatures> = Arc::new(LocalKeyServer {
+            key: local_key.clone(),
+            failures: AtomicUsize::new(0),
+        });
+
+        let remote_key = sign::RemoteSigningKey::new(
+            b"key-id".to_vec(),
+            local_key.algorithm(),
+            schemes,
+            vec![broken, working],
+        )
+        .with_public_key(
+            local_key
+                .public_key()
+                .unwrap()
+                .into_owned(),
+        );
+        let certified_key = sign::CertifiedKey::new(kt.get_chain(), Arc::new(remote_key));
+        certified_key.keys_match().unwrap();
+
+        let mut resolver = rustls::server::ResolvesServerCertUsingTrustAnchors::new();
+        resolver.set_default(certified_key);
+        let server_config = server_config_builder()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        let (mut client, mut server) = make_pair_for_configs(make_client_config(kt), server_config);
+        do_handshake(&mut client, &mut server);
+    }
+}
+
 #[test]
 fn test_keys_match_for_all_signing_key_types() {
     for kt in ALL_KEY_TYPES {
@@ -7422,3 +12746,91 @@ fn test_keys_match_for_all_signing_key_types() {
 }
 
 const CONFIDENTIALITY_LIMIT: u64 = 1024;
+
+fn check_client_max_handshake_size(size: usize) -> Option<Error> {
+    let mut client_config = make_client_config(KeyType::Ed25519);
+    client_config.max_handshake_size = Some(size);
+    ClientConnection::new(Arc::new(client_config), server_name("localhost")).err()
+}
+
+#[test]
+fn bad_client_max_handshake_sizes() {
+    assert_eq!(
+        check_client_max_handshake_size(0x3fff),
+        Some(Error::BadMaxHandshakeSize)
+    );
+    assert_eq!(check_client_max_handshake_size(0x4000), None);
+    assert_eq!(check_client_max_handshake_size(0xff_ffff), None);
+    assert_eq!(
+        check_client_max_handshake_size(0x100_0000),
+        Some(Error::BadMaxHandshakeSize)
+    );
+}
+
+#[test]
+fn max_handshake_size_limits_server_certificate_chain() {
+    fn server_config_with_chain_size(min_len: usize) -> Arc<ServerConfig> {
+        let kt = KeyType::Rsa2048;
+        let mut chain = kt.get_chain();
+        // a self-signed root can be repeated without putting the chain out of order
+        let root = chain.last().unwrap().clone();
+        while chain
+            .iter()
+            .map(|c| c.len())
+            .sum::<usize>()
+            < min_len
+        {
+            chain.push(root.clone());
+        }
+        Arc::new(
+            server_config_builder()
+                .with_no_client_auth()
+                .with_single_cert(chain, kt.get_key())
+                .unwrap(),
+        )
+    }
+
+    // `transfer()` buffers a whole flight, which is more than a client will accept
+    // before processing it.
+    fn handshake(
+        max_handshake_size: Option<usize>,
+        server_config: &Arc<ServerConfig>,
+    ) -> Result<(), Error> {
+        let mut client_config = make_client_config(KeyType::Rsa2048);
+        client_config.max_handshake_size = max_handshake_size;
+        // compression would shrink the certificate chain below the limits
+        client_config.cert_decompressors = vec![];
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), server_config);
+
+        while client.is_handshaking() {
+            transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+
+            let mut flight = Vec::new();
+            while server.wants_write() {
+                server.write_tls(&mut flight).unwrap();
+            }
+            let mut rd = &flight[..];
+            while !rd.is_empty() {
+                client.read_tls(&mut rd).unwrap();
+                client.process_new_packets()?;
+            }
+        }
+        Ok(())
+    }
+
+    let too_large = Err(Error::InvalidMessage(
+        InvalidMessage::HandshakePayloadTooLarge,
+    ));
+
+    // Just over the minimum limit, but within the default.
+    let server_config = server_config_with_chain_size(0x4000);
+    assert_eq!(handshake(None, &server_config), Ok(()));
+    assert_eq!(handshake(Some(0x4000), &server_config), too_large);
+
+    // Over the default limit.
+    let server_config = server_config_with_chain_size(0x10000);
+    assert_eq!(handshake(None, &server_config), too_large);
+    assert_eq!(handshake(Some(0x20000), &server_config), Ok(()));
+}
@@ -3,7 +3,9 @@
 
 use std::io;
 use std::ops::DerefMut;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use once_cell::sync::OnceCell;
 use pki_types::{
@@ -267,6 +269,111 @@ where
     total
 }
 
+/// Something that can go wrong with a single TLS record in transit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// The record is never delivered.
+    Drop,
+    /// The record is delivered twice in a row.
+    Duplicate,
+    /// The record is delivered after the one that follows it.
+    Delay,
+    /// The last byte of the record's payload is changed.
+    Corrupt,
+}
+
+/// Moves TLS records between connections, damaging chosen ones on the way.
+///
+/// Records are numbered from zero in the order they leave `left`, counting
+/// across all calls to [`FaultInjector::transfer`].
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    faults: Vec<(usize, Fault)>,
+    records: usize,
+    delayed: Option<Vec<u8>>,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply `fault` to the record numbered `record`.
+    pub fn with(mut self, record: usize, fault: Fault) -> Self {
+        self.faults.push((record, fault));
+        self
+    }
+
+    /// The number of records seen so far.
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Like [`transfer`], but applying the configured faults.
+    ///
+    /// Returns the number of bytes delivered to `right`.  A delayed
+    /// record that has no successor is delivered at the end.
+    pub fn transfer(
+        &mut self,
+        left: &mut impl DerefMut<Target = ConnectionCommon<impl SideData>>,
+        right: &mut impl DerefMut<Target = ConnectionCommon<impl SideData>>,
+    ) -> usize {
+        let mut buf = Vec::new();
+        while left.wants_write() {
+            left.write_tls(&mut buf).unwrap();
+        }
+
+        let mut out = Vec::new();
+        let mut rest = &buf[..];
+        while !rest.is_empty() {
+            let len = 5 + u16::from_be_bytes([rest[3], rest[4]]) as usize;
+            let (record, tail) = rest.split_at(len);
+            rest = tail;
+
+            let fault = self
+                .faults
+                .iter()
+                .find(|(n, _)| *n == self.records)
+                .map(|(_, fault)| *fault);
+            self.records += 1;
+
+            match fault {
+                None => out.extend_from_slice(record),
+                Some(Fault::Drop) => {}
+                Some(Fault::Duplicate) => {
+                    out.extend_from_slice(record);
+                    out.extend_from_slice(record);
+                }
+                Some(Fault::Delay) => {
+                    self.delayed = Some(record.to_vec());
+                    continue;
+                }
+                Some(Fault::Corrupt) => {
+                    let mut record = record.to_vec();
+                    *record.last_mut().unwrap() ^= 0x01;
+                    out.extend_from_slice(&record);
+                }
+            }
+
+            if let Some(delayed) = self.delayed.take() {
+                out.extend_from_slice(&delayed);
+            }
+        }
+
+        if let Some(delayed) = self.delayed.take() {
+            out.extend_from_slice(&delayed);
+        }
+
+        let mut offs = 0;
+        while offs < out.len() {
+            offs += right
+                .read_tls(&mut &out[offs..])
+                .unwrap();
+        }
+        offs
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum KeyType {
     Rsa2048,
@@ -317,6 +424,17 @@ impl KeyType {
         )
     }
 
+    pub fn get_intermediate_key(&self) -> PrivateKeyDer<'static> {
+        PrivateKeyDer::Pkcs8(
+            rustls_pemfile::pkcs8_private_keys(&mut io::BufReader::new(
+                self.bytes_for("inter.key"),
+            ))
+            .next()
+            .unwrap()
+            .unwrap(),
+        )
+    }
+
     pub fn get_client_chain(&self) -> Vec<CertificateDer<'static>> {
         rustls_pemfile::certs(&mut io::BufReader::new(self.bytes_for("client.fullchain")))
             .map(|result| result.unwrap())
@@ -842,6 +960,36 @@ fn exactly_one_provider() -> bool {
     ))
 }
 
+/// A `TimeProvider` whose time only changes when told to.
+#[derive(Debug)]
+pub struct ManualTime(AtomicU64);
+
+impl ManualTime {
+    pub fn new(secs: u64) -> Arc<Self> {
+        Arc::new(Self(AtomicU64::new(secs * 1000)))
+    }
+
+    pub fn advance(&self, secs: u64) {
+        self.advance_millis(secs * 1000);
+    }
+
+    pub fn advance_millis(&self, millis: u64) {
+        self.0
+            .fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl rustls::time_provider::TimeProvider for ManualTime {
+    fn current_time(&self) -> Option<UnixTime> {
+        self.current_time_precise()
+            .map(UnixTime::since_unix_epoch)
+    }
+
+    fn current_time_precise(&self) -> Option<Duration> {
+        Some(Duration::from_millis(self.0.load(Ordering::SeqCst)))
+    }
+}
+
 #[derive(Debug)]
 pub struct MockServerVerifier {
     cert_rejection_error: Option<Error>,
@@ -955,6 +1103,13 @@ impl MockServerVerifier {
             ..Default::default()
         }
     }
+
+    pub fn offers_signature_schemes(signature_schemes: Vec<SignatureScheme>) -> Self {
+        MockServerVerifier {
+            signature_schemes,
+            ..Default::default()
+        }
+    }
 }
 
 impl Default for MockServerVerifier {
@@ -2,15 +2,21 @@
 
 use std::num::NonZeroUsize;
 use std::sync::Arc;
+use std::time::Duration;
 
 use rustls::client::{ClientConnectionData, EarlyDataError, UnbufferedClientConnection};
-use rustls::server::{ServerConnectionData, UnbufferedServerConnection};
+use rustls::server::{
+    ServerConnectionData, UnbufferedAcceptStatus, UnbufferedAcceptor, UnbufferedServerConnection,
+};
 use rustls::unbuffered::{
     ConnectionState, EncodeError, EncryptError, InsufficientSizeError, UnbufferedConnectionCommon,
     UnbufferedStatus, WriteTraffic,
 };
-use rustls::version::TLS13;
-use rustls::{ClientConfig, Error, ServerConfig, SideData};
+use rustls::version::{TLS12, TLS13};
+use rustls::{
+    ClientConfig, ConnectionTrafficSecrets, Error, HandshakeKind, ServerConfig, SideData,
+    WarningAlert,
+};
 
 use super::*;
 
@@ -264,6 +270,58 @@ fn early_data() {
             .server_received_early_data
             .as_slice()
     );
+    assert!(outcome
+        .client
+        .unwrap()
+        .is_early_data_accepted());
+}
+
+#[test]
+fn early_data_rejected() {
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.max_early_data_size = 128;
+    let server_config = Arc::new(server_config);
+
+    let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[&TLS13]);
+    client_config.enable_early_data = true;
+    let client_config = Arc::new(client_config);
+
+    run(
+        client_config.clone(),
+        &mut NO_ACTIONS.clone(),
+        server_config.clone(),
+        &mut NO_ACTIONS.clone(),
+    );
+
+    // same session storage, but no longer accepting early data
+    let mut rejecting_config = ServerConfig::clone(&server_config);
+    rejecting_config.max_early_data_size = 0;
+
+    let mut client_actions = Actions {
+        early_data_to_send: Some(b"hello"),
+        app_data_to_send: Some(b"world"),
+        ..NO_ACTIONS
+    };
+
+    let outcome = run(
+        client_config,
+        &mut client_actions,
+        Arc::new(rejecting_config),
+        &mut NO_ACTIONS.clone(),
+    );
+
+    assert!(outcome
+        .server_received_early_data
+        .is_empty());
+    assert_eq!(
+        outcome
+            .server_received_app_data
+            .as_slice(),
+        [b"world"]
+    );
+    let client = outcome.client.unwrap();
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Resumed));
+    assert!(!client.is_early_data_accepted());
 }
 
 fn run(
@@ -271,17 +329,30 @@ fn run(
     client_actions: &mut Actions,
     server_config: Arc<ServerConfig>,
     server_actions: &mut Actions,
+) -> Outcome {
+    let client = UnbufferedClientConnection::new(client_config, server_name("localhost")).unwrap();
+    let server = UnbufferedServerConnection::new(server_config).unwrap();
+    run_connections(
+        client,
+        client_actions,
+        server,
+        server_actions,
+        BothBuffers::default(),
+    )
+}
+
+fn run_connections(
+    mut client: UnbufferedClientConnection,
+    client_actions: &mut Actions,
+    mut server: UnbufferedServerConnection,
+    server_actions: &mut Actions,
+    mut buffers: BothBuffers,
 ) -> Outcome {
     let mut outcome = Outcome::default();
     let mut count = 0;
     let mut client_handshake_done = false;
     let mut server_handshake_done = false;
 
-    let mut client =
-        UnbufferedClientConnection::new(client_config.clone(), server_name("localhost")).unwrap();
-    let mut server = UnbufferedServerConnection::new(server_config.clone()).unwrap();
-    let mut buffers = BothBuffers::default();
-
     while !(client_handshake_done
         && server_handshake_done
         && client_actions.finished()
@@ -524,6 +595,120 @@ fn queue_close_notify_is_idempotent() {
     assert_eq!(len_second.unwrap(), 0);
 }
 
+#[test]
+fn user_canceled_before_close_notify() {
+    let mut outcome = handshake(&rustls::version::TLS13);
+    let mut client = outcome.client.take().unwrap();
+    let mut server = outcome.server.take().unwrap();
+
+    let mut client_send_buf = [0u8; 128];
+    let len = write_traffic(
+        client.process_tls_records(&mut []),
+        |mut wt: WriteTraffic<_>| {
+            let len = wt
+                .queue_warning_alert(WarningAlert::UserCanceled, &mut client_send_buf)
+                .unwrap();
+            len + wt
+                .queue_close_notify(&mut client_send_buf[len..])
+                .unwrap()
+        },
+    );
+
+    match server.process_tls_records(&mut client_send_buf[..len]) {
+        UnbufferedStatus {
+            discard,
+            state: Ok(ConnectionState::Closed),
+        } => {
+            assert_eq!(discard, len);
+        }
+        st => {
+            panic!("unexpected server state {st:?} (wanted Closed)");
+        }
+    }
+}
+
+#[test]
+fn handshake_timeout() {
+    let clock = ManualTime::new(0);
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.time_provider = clock.clone();
+    server_config.handshake_timeout = Some(Duration::from_secs(10));
+    let mut server = UnbufferedServerConnection::new(Arc::new(server_config)).unwrap();
+
+    assert!(matches!(
+        server
+            .process_tls_records(&mut [])
+            .state,
+        Ok(ConnectionState::BlockedHandshake)
+    ));
+    clock.advance(11);
+    assert!(matches!(
+        server
+            .process_tls_records(&mut [])
+            .state,
+        Err(Error::HandshakeTimedOut)
+    ));
+}
+
+#[test]
+fn secret_extraction() {
+    fn explode_secrets(s: &ConnectionTrafficSecrets) -> (&[u8], &[u8]) {
+        match s {
+            ConnectionTrafficSecrets::Aes128Gcm { key, iv } => (key.as_ref(), iv.as_ref()),
+            ConnectionTrafficSecrets::Aes256Gcm { key, iv } => (key.as_ref(), iv.as_ref()),
+            ConnectionTrafficSecrets::Chacha20Poly1305 { key, iv } => (key.as_ref(), iv.as_ref()),
+            _ => panic!("unexpected secret type"),
+        }
+    }
+
+    fn assert_secrets_equal(
+        (l_seq, l_sec): (u64, ConnectionTrafficSecrets),
+        (r_seq, r_sec): (u64, ConnectionTrafficSecrets),
+    ) {
+        assert_eq!(l_seq, r_seq);
+        assert_eq!(explode_secrets(&l_sec), explode_secrets(&r_sec));
+    }
+
+    for version in [&TLS12, &TLS13] {
+        let mut server_config = make_server_config(KeyType::Rsa2048);
+        server_config.enable_secret_extraction = true;
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa2048, &[version]);
+        client_config.enable_secret_extraction = true;
+
+        let outcome = run(
+            Arc::new(client_config),
+            &mut NO_ACTIONS.clone(),
+            Arc::new(server_config),
+            &mut NO_ACTIONS.clone(),
+        );
+
+        let client_secrets = outcome
+            .client
+            .unwrap()
+            .dangerous_extract_secrets()
+            .unwrap();
+        let server_secrets = outcome
+            .server
+            .unwrap()
+            .dangerous_extract_secrets()
+            .unwrap();
+
+        assert_secrets_equal(client_secrets.tx, server_secrets.rx);
+        assert_secrets_equal(client_secrets.rx, server_secrets.tx);
+    }
+}
+
+#[test]
+fn secret_extraction_disabled() {
+    let (client, server) = make_connection_pair(&TLS13);
+    assert!(client
+        .dangerous_extract_secrets()
+        .is_err());
+    assert!(server
+        .dangerous_extract_secrets()
+        .is_err());
+}
+
 #[test]
 fn refresh_traffic_keys_on_tls12_connection() {
     let mut outcome = handshake(&rustls::version::TLS12);
@@ -1231,6 +1416,108 @@ fn server_receives_handshake_byte_by_byte() {
     assert_eq!(client_hello_buffer.len(), discard);
 }
 
+#[test]
+fn acceptor_selects_config_from_client_hello() {
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.alpn_protocols = vec![b"proto".to_vec()];
+    let mut client =
+        UnbufferedClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut buffers = BothBuffers::default();
+    let mut transcript = vec![];
+
+    let mut acceptor = UnbufferedAcceptor::default();
+    let accepted = loop {
+        match advance_client(
+            &mut client,
+            &mut buffers.client,
+            NO_ACTIONS,
+            &mut transcript,
+        ) {
+            State::EncodedTlsData => {}
+            State::TransmitTlsData { .. } => buffers.client_send(),
+            state => panic!("unexpected client state {state:?}"),
+        }
+
+        let UnbufferedAcceptStatus { discard, state } =
+            acceptor.accept(buffers.server.incoming.filled());
+        buffers.server.incoming.discard(discard);
+        if let Some(accepted) = state.unwrap() {
+            break accepted;
+        }
+    };
+    assert_eq!(buffers.server.incoming.filled().len(), 0);
+
+    let client_hello = accepted.client_hello();
+    assert_eq!(client_hello.server_name(), Some("localhost"));
+    assert_eq!(
+        client_hello
+            .alpn()
+            .unwrap()
+            .collect::<Vec<_>>(),
+        vec![b"proto"]
+    );
+
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.alpn_protocols = vec![b"proto".to_vec()];
+    let server = accepted
+        .into_connection(Arc::new(server_config))
+        .unwrap();
+
+    let outcome = run_connections(
+        client,
+        &mut NO_ACTIONS.clone(),
+        server,
+        &mut NO_ACTIONS.clone(),
+        buffers,
+    );
+    let (client, server) = (outcome.client.unwrap(), outcome.server.unwrap());
+    assert!(!client.is_handshaking());
+    assert!(!server.is_handshaking());
+    assert_eq!(client.alpn_protocol(), Some(&b"proto"[..]));
+    assert_eq!(server.alpn_protocol(), Some(&b"proto"[..]));
+}
+
+#[test]
+fn acceptor_returns_alert_for_rejected_client_hello() {
+    let mut client_config = make_client_config(KeyType::Rsa2048);
+    client_config.alpn_protocols = vec![b"proto".to_vec()];
+    let mut client =
+        UnbufferedClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+
+    let mut client_hello = vec![0u8; 1024];
+    match client
+        .process_tls_records(&mut [])
+        .state
+        .unwrap()
+    {
+        ConnectionState::EncodeTlsData(mut inner) => {
+            let wr = inner.encode(&mut client_hello).unwrap();
+            client_hello.truncate(wr);
+        }
+        _ => panic!("unexpected first client event"),
+    };
+
+    let mut acceptor = UnbufferedAcceptor::default();
+    let UnbufferedAcceptStatus { discard, state } = acceptor.accept(&mut client_hello);
+    assert_eq!(discard, client_hello.len());
+    let accepted = state.unwrap().unwrap();
+
+    let mut server_config = make_server_config(KeyType::Rsa2048);
+    server_config.alpn_protocols = vec![b"other".to_vec()];
+    let (err, alert) = match accepted.into_connection(Arc::new(server_config)) {
+        Ok(_) => panic!("unexpected success"),
+        Err(err) => err,
+    };
+    assert_eq!(err, Error::NoApplicationProtocol);
+    // In TLS1.3 the ALPN check follows the ServerHello, so the alert is encrypted
+    // after that.
+    assert!(alert.starts_with(&[0x16, 0x3, 0x3]));
+
+    let UnbufferedAcceptStatus { discard, state } = acceptor.accept(&mut []);
+    assert_eq!(discard, 0);
+    assert!(state.is_err());
+}
+
 #[test]
 fn server_receives_incorrect_first_handshake_message() {
     let (_, mut server) = make_connection_pair(&TLS13);